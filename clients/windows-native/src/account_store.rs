@@ -8,7 +8,10 @@ use argon2::{Argon2, PasswordHasher, PasswordVerifier, password_hash::{SaltStrin
 use rand::rngs::OsRng;
 use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// Shortest password an account accepts
+const MIN_PASSWORD_LEN: usize = 4;
 
 /// Account data stored on disk
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -30,7 +33,7 @@ pub struct Account {
 
 /// Get path to account.json
 fn get_account_path() -> Result<PathBuf> {
-    Ok(crate::request_store::get_data_dir()?.join("account.json"))
+    Ok(crate::paths::data_dir()?.join("account.json"))
 }
 
 /// Check if an account exists
@@ -38,25 +41,35 @@ pub fn account_exists() -> bool {
     get_account_path().map(|p| p.exists()).unwrap_or(false)
 }
 
-/// Load account from disk
-pub fn load_account() -> Result<Option<Account>> {
-    let path = get_account_path()?;
+fn load_account_from(path: &Path) -> Result<Option<Account>> {
     if !path.exists() {
         return Ok(None);
     }
-    let json = fs::read_to_string(&path).context("Failed to read account file")?;
+    let json = fs::read_to_string(path).context("Failed to read account file")?;
     let account: Account = serde_json::from_str(&json).context("Failed to parse account")?;
     Ok(Some(account))
 }
 
-/// Save account to disk
-pub fn save_account(account: &Account) -> Result<()> {
-    let path = get_account_path()?;
+/// Load account from disk
+pub fn load_account() -> Result<Option<Account>> {
+    load_account_from(&get_account_path()?)
+}
+
+/// Write the account next to `path` and rename it into place, so a failed
+/// write leaves the previous file intact
+fn save_account_to(path: &Path, account: &Account) -> Result<()> {
     let json = serde_json::to_string_pretty(account)?;
-    fs::write(&path, json).context("Failed to save account")?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, json).context("Failed to save account")?;
+    fs::rename(&tmp, path).context("Failed to replace account file")?;
     Ok(())
 }
 
+/// Save account to disk
+pub fn save_account(account: &Account) -> Result<()> {
+    save_account_to(&get_account_path()?, account)
+}
+
 /// Hash a password with Argon2
 pub fn hash_password(password: &str) -> Result<String> {
     let salt = SaltString::generate(&mut OsRng);
@@ -145,8 +158,8 @@ pub fn create_account(
     public_key: &str,
     fingerprint: &str,
 ) -> Result<Account> {
-    if password.len() < 4 {
-        bail!("Password must be at least 4 characters");
+    if password.len() < MIN_PASSWORD_LEN {
+        bail!("Password must be at least {} characters", MIN_PASSWORD_LEN);
     }
     
     let password_hash = hash_password(password)?;
@@ -167,6 +180,65 @@ pub fn create_account(
     Ok(account)
 }
 
+/// Swap the account's keypair after a key rotation (password re-encrypts the new key)
+pub fn replace_keypair(password: &str, secret_key: &str, public_key: &str, fingerprint: &str) -> Result<Account> {
+    let mut account = load_account()?.ok_or_else(|| anyhow::anyhow!("No account found"))?;
+    if !verify_password(password, &account.password_hash) {
+        bail!("Wrong password");
+    }
+
+    let (encrypted_secret_key, encryption_nonce, key_derivation_salt) =
+        encrypt_secret_key(secret_key, password)?;
+    account.encrypted_secret_key = encrypted_secret_key;
+    account.encryption_nonce = encryption_nonce;
+    account.key_derivation_salt = key_derivation_salt;
+    account.public_key = public_key.to_string();
+    account.fingerprint = fingerprint.to_string();
+
+    save_account(&account)?;
+    Ok(account)
+}
+
+fn change_password_at(path: &Path, old_password: &str, new_password: &str) -> Result<Account> {
+    let account = load_account_from(path)?.ok_or_else(|| anyhow::anyhow!("No account found"))?;
+    if !verify_password(old_password, &account.password_hash) {
+        bail!("Wrong password");
+    }
+    if new_password.len() < MIN_PASSWORD_LEN {
+        bail!("Password must be at least {} characters", MIN_PASSWORD_LEN);
+    }
+    let secret_key = decrypt_secret_key(
+        &account.encrypted_secret_key,
+        &account.encryption_nonce,
+        &account.key_derivation_salt,
+        old_password,
+    )?;
+
+    let (encrypted_secret_key, encryption_nonce, key_derivation_salt) =
+        encrypt_secret_key(&secret_key, new_password)?;
+    let updated = Account {
+        password_hash: hash_password(new_password)?,
+        encrypted_secret_key,
+        encryption_nonce,
+        key_derivation_salt,
+        ..account
+    };
+    // Make sure the new wrapping opens before it replaces the old one
+    decrypt_secret_key(&updated.encrypted_secret_key, &updated.encryption_nonce, &updated.key_derivation_salt, new_password)?;
+    save_account_to(path, &updated)?;
+    Ok(updated)
+}
+
+/// Change the account password, re-encrypting the secret key under the new one.
+///
+/// Chat history needs no re-encryption: its storage key is derived from the
+/// key fingerprint (see `encrypted_storage::derive_storage_key`), which stays
+/// the same. The account file is swapped in a single rename, so if anything
+/// fails the old password keeps working.
+pub fn change_password(old_password: &str, new_password: &str) -> Result<Account> {
+    change_password_at(&get_account_path()?, old_password, new_password)
+}
+
 /// Login with password and get decrypted secret key
 pub fn login(password: &str) -> Result<(Account, String)> {
     let account = load_account()?.ok_or_else(|| anyhow::anyhow!("No account found"))?;
@@ -186,3 +258,51 @@ pub fn login(password: &str) -> Result<(Account, String)> {
     
     Ok((account, secret_key))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encrypted_storage::{decrypt_data, derive_storage_key, encrypt_data};
+
+    #[test]
+    fn history_stays_readable_after_a_password_change() {
+        let dir = std::env::temp_dir().join(format!("cryptochat-account-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("account.json");
+        let (encrypted_secret_key, encryption_nonce, key_derivation_salt) =
+            encrypt_secret_key("SECRET KEY", "old pass").unwrap();
+        let account = Account {
+            username: "alice".to_string(),
+            password_hash: hash_password("old pass").unwrap(),
+            encrypted_secret_key,
+            public_key: "PUBLIC KEY".to_string(),
+            fingerprint: "ABCD1234".to_string(),
+            encryption_nonce,
+            key_derivation_salt,
+        };
+        save_account_to(&path, &account).unwrap();
+        let history = encrypt_data(&["hello"], &derive_storage_key(&account.fingerprint)).unwrap();
+
+        // A wrong current password changes nothing
+        assert!(change_password_at(&path, "wrong", "new pass").is_err());
+        assert!(change_password_at(&path, "old pass", "no").is_err());
+        assert_eq!(load_account_from(&path).unwrap().unwrap().password_hash, account.password_hash);
+
+        let changed = change_password_at(&path, "old pass", "new pass").unwrap();
+        let stored = load_account_from(&path).unwrap().unwrap();
+        assert_eq!(stored.fingerprint, account.fingerprint);
+        assert!(verify_password("new pass", &stored.password_hash));
+        assert!(!verify_password("old pass", &stored.password_hash));
+        let unwrap_with = |password: &str| {
+            decrypt_secret_key(&stored.encrypted_secret_key, &stored.encryption_nonce, &stored.key_derivation_salt, password)
+        };
+        assert_eq!(unwrap_with("new pass").unwrap(), "SECRET KEY");
+        assert!(unwrap_with("old pass").is_err());
+        assert!(!dir.join("account.json.tmp").exists());
+
+        let readable: Vec<String> = decrypt_data(&history, &derive_storage_key(&changed.fingerprint)).unwrap();
+        assert_eq!(readable, vec!["hello".to_string()]);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -4,7 +4,31 @@ use base64::Engine;
 use cryptochat_crypto_core::pgp::PgpKeyPair;
 use cryptochat_messaging::{DeviceId, onboarding::TrustRecord};
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+/// Lock for reading. A panic on another thread while it held the lock doesn't
+/// leave the keys half-written (every write is a single assignment), so the
+/// poisoned guard is recovered rather than spreading the panic.
+fn read_lock<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Lock for writing, recovering from poisoning like `read_lock`
+fn write_lock<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Parse a public key we already hold (a saved contact or group member).
+///
+/// Fresh keys go through `PgpKeyPair::from_public_key`, which refuses expired
+/// or revoked ones. A stored key may have lapsed since it was saved, and it
+/// still has to load so old messages verify and the user can be told, so the
+/// reason it's no longer valid comes back as a warning instead.
+pub fn stored_public_key(armored: &str) -> anyhow::Result<(PgpKeyPair, Option<String>)> {
+    let key = PgpKeyPair::parse_public_key(armored)?;
+    let warning = key.validate(std::time::SystemTime::now()).err().map(|e| e.to_string());
+    Ok((key, warning))
+}
 
 pub struct AppState {
     pub keypair: Arc<RwLock<Option<PgpKeyPair>>>,
@@ -26,41 +50,42 @@ impl AppState {
     }
 
     pub fn set_keypair(&self, keypair: PgpKeyPair) {
-        *self.keypair.write().unwrap() = Some(keypair);
+        *write_lock(&self.keypair) = Some(keypair);
     }
 
     pub fn set_recipient_keypair(&self, keypair: PgpKeyPair) {
-        *self.recipient_keypair.write().unwrap() = Some(keypair);
+        *write_lock(&self.recipient_keypair) = Some(keypair);
     }
 
     pub fn get_fingerprint(&self) -> Option<String> {
-        self.keypair
-            .read()
-            .unwrap()
+        read_lock(&self.keypair)
             .as_ref()
             .map(|kp| kp.fingerprint())
     }
 
     pub fn get_recipient_fingerprint(&self) -> Option<String> {
-        self.recipient_keypair
-            .read()
-            .unwrap()
+        read_lock(&self.recipient_keypair)
             .as_ref()
             .map(|kp| kp.fingerprint())
     }
 
     pub fn get_keypair(&self) -> Option<PgpKeyPair> {
-        self.keypair.read().unwrap().clone()
+        read_lock(&self.keypair).clone()
     }
 
     /// Get a clone of the recipient's keypair
     pub fn get_recipient_keypair(&self) -> anyhow::Result<Option<PgpKeyPair>> {
-        Ok(self.recipient_keypair.read().unwrap().clone())
+        Ok(read_lock(&self.recipient_keypair).clone())
+    }
+
+    /// The recipient's armored public key
+    pub fn get_recipient_public_key(&self) -> Option<String> {
+        read_lock(&self.recipient_keypair).as_ref().and_then(|kp| kp.export_public_key().ok())
     }
 
     pub fn encrypt_message(&self, plaintext: &str) -> anyhow::Result<String> {
-        let my_keypair = self.keypair.read().unwrap();
-        let recipient_keypair = self.recipient_keypair.read().unwrap();
+        let my_keypair = read_lock(&self.keypair);
+        let recipient_keypair = read_lock(&self.recipient_keypair);
 
         match (my_keypair.as_ref(), recipient_keypair.as_ref()) {
             (Some(my_key), Some(recipient_key)) => {
@@ -71,9 +96,23 @@ impl AppState {
         }
     }
 
+    /// Encrypt and sign for a recipient other than the current session's peer
+    pub fn encrypt_message_for(&self, plaintext: &str, recipient_public_key: &str) -> anyhow::Result<String> {
+        let my_keypair = read_lock(&self.keypair);
+        let Some(my_key) = my_keypair.as_ref() else {
+            anyhow::bail!("Own keypair not initialized");
+        };
+        let (recipient, warning) = stored_public_key(recipient_public_key)?;
+        if let Some(warning) = warning {
+            tracing::warn!(peer = crate::logging::short_fp(&recipient.fingerprint()), %warning, "encrypting to a key that is no longer valid");
+        }
+        let encrypted_bytes = my_key.encrypt_and_sign(recipient.cert(), plaintext.as_bytes())?;
+        Ok(base64::engine::general_purpose::STANDARD.encode(&encrypted_bytes))
+    }
+
     pub fn decrypt_message(&self, encrypted_base64: &str) -> anyhow::Result<String> {
-        let my_keypair = self.keypair.read().unwrap();
-        let recipient_keypair = self.recipient_keypair.read().unwrap();
+        let my_keypair = read_lock(&self.keypair);
+        let recipient_keypair = read_lock(&self.recipient_keypair);
 
         match (my_keypair.as_ref(), recipient_keypair.as_ref()) {
             (Some(my_key), Some(recipient_key)) => {
@@ -88,12 +127,13 @@ impl AppState {
     /// Decrypt a message using the sender's public key directly (for multi-chat support)
     /// This allows decrypting messages from any contact without requiring them to be "active"
     pub fn decrypt_message_with_sender_key(&self, encrypted_base64: &str, sender_public_key: &str) -> anyhow::Result<String> {
-        let my_keypair = self.keypair.read().unwrap();
+        let my_keypair = read_lock(&self.keypair);
         
         match my_keypair.as_ref() {
             Some(my_key) => {
                 // Parse the sender's public key to get their cert for signature verification
-                let sender_keypair = PgpKeyPair::from_public_key(sender_public_key)?;
+                // Messages signed before the key lapsed still verify
+                let (sender_keypair, _) = stored_public_key(sender_public_key)?;
                 let encrypted_bytes = base64::engine::general_purpose::STANDARD.decode(encrypted_base64)?;
                 let decrypted = my_key.decrypt_and_verify(sender_keypair.cert(), &encrypted_bytes)?;
                 Ok(String::from_utf8(decrypted)?)
@@ -102,9 +142,22 @@ impl AppState {
         }
     }
 
+    /// Try each of the sender's known public keys in turn, returning the index
+    /// of the one that verified along with the plaintext
+    pub fn decrypt_message_with_any_key(&self, encrypted_base64: &str, sender_public_keys: &[&str]) -> anyhow::Result<(usize, String)> {
+        let mut last_error = anyhow::anyhow!("No keys known for this sender");
+        for (index, key) in sender_public_keys.iter().enumerate() {
+            match self.decrypt_message_with_sender_key(encrypted_base64, key) {
+                Ok(plaintext) => return Ok((index, plaintext)),
+                Err(e) => last_error = e,
+            }
+        }
+        Err(last_error)
+    }
+
     /// Export keypair data for account migration (secret_key, public_key, fingerprint)
     pub fn get_keypair_for_export(&self) -> Option<(String, String, String)> {
-        let keypair_guard = self.keypair.read().unwrap();
+        let keypair_guard = read_lock(&self.keypair);
         if let Some(ref kp) = *keypair_guard {
             let secret_key = kp.export_secret_key().ok()?;
             let public_key = kp.export_public_key().ok()?;
@@ -116,7 +169,14 @@ impl AppState {
     }
 
     pub fn set_peer_address(&self, address: String) {
-        *self.peer_address.write().unwrap() = Some(address);
+        *write_lock(&self.peer_address) = Some(address);
+    }
+
+    /// Forget the current peer (their key and address) without touching our
+    /// own identity or trust records
+    pub fn end_session(&self) {
+        *write_lock(&self.recipient_keypair) = None;
+        *write_lock(&self.peer_address) = None;
     }
 }
 
@@ -126,3 +186,68 @@ impl Default for AppState {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn end_session_keeps_identity_and_trust() {
+        let state = AppState::new();
+        let me = PgpKeyPair::generate("me@example.com").unwrap();
+        let peer = PgpKeyPair::generate("peer@example.com").unwrap();
+        let my_fp = me.fingerprint();
+        state.set_keypair(me);
+        state.set_recipient_keypair(peer);
+        state.set_peer_address("127.0.0.1:62780".to_string());
+        state.trust_records.write().unwrap().insert(
+            "peer".to_string(),
+            TrustRecord::new_unverified(DeviceId::new(), "PEERFP".to_string()),
+        );
+
+        state.end_session();
+
+        assert_eq!(state.get_recipient_fingerprint(), None);
+        assert!(state.peer_address.read().unwrap().is_none());
+        assert_eq!(state.get_fingerprint(), Some(my_fp));
+        assert_eq!(state.trust_records.read().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn falls_back_to_a_later_sender_key() {
+        let state = AppState::new();
+        let me = PgpKeyPair::generate("me@example.com").unwrap();
+        let stale = PgpKeyPair::generate("alice-old@example.com").unwrap();
+        let alice = PgpKeyPair::generate("alice@example.com").unwrap();
+        let ciphertext = alice.encrypt_and_sign(me.cert(), b"hi").unwrap();
+        let encoded = base64::engine::general_purpose::STANDARD.encode(ciphertext);
+        state.set_keypair(me);
+
+        let stale_key = stale.export_public_key().unwrap();
+        let alice_key = alice.export_public_key().unwrap();
+        let (index, plaintext) = state.decrypt_message_with_any_key(&encoded, &[&stale_key, &alice_key]).unwrap();
+        assert_eq!((index, plaintext.as_str()), (1, "hi"));
+        assert!(state.decrypt_message_with_any_key(&encoded, &[&stale_key]).is_err());
+        assert!(state.decrypt_message_with_any_key(&encoded, &[]).is_err());
+    }
+
+    #[test]
+    fn poisoned_lock_is_recovered() {
+        let state = AppState::new();
+        let me = PgpKeyPair::generate("me@example.com").unwrap();
+        let my_fp = me.fingerprint();
+        state.set_keypair(me);
+
+        let keypair = state.keypair.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = keypair.write().unwrap();
+            panic!("poison the lock");
+        })
+        .join();
+        assert!(state.keypair.is_poisoned());
+
+        assert_eq!(state.get_fingerprint(), Some(my_fp));
+        state.set_peer_address("127.0.0.1:62780".to_string());
+        state.end_session();
+        assert!(state.encrypt_message("hi").is_err());
+    }
+}
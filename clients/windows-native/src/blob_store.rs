@@ -0,0 +1,107 @@
+//! Encrypted, content-addressed storage for attachments kept out of history
+//!
+//! Large payloads such as voice clips are written once under
+//! `blobs/<sha256>.blob` (AES-256-GCM with the fingerprint-derived storage
+//! key, IV prepended) and the conversation only stores the hash.
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, OsRng},
+    Aes256Gcm, Nonce,
+};
+use anyhow::{bail, Context, Result};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::encrypted_storage::derive_storage_key;
+
+const IV_LEN: usize = 12;
+
+/// Hashes are the only names that come back from history or the network
+fn is_blob_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn blob_path(dir: &Path, id: &str) -> Result<PathBuf> {
+    if !is_blob_id(id) {
+        bail!("Invalid blob id: {}", id);
+    }
+    Ok(dir.join(format!("{}.blob", id)))
+}
+
+fn put_in(dir: &Path, data: &[u8], key: &[u8; 32]) -> Result<String> {
+    let id = format!("{:x}", Sha256::digest(data));
+    let path = blob_path(dir, &id)?;
+    if path.exists() {
+        return Ok(id);
+    }
+
+    let mut iv = [0u8; IV_LEN];
+    OsRng.fill_bytes(&mut iv);
+    let cipher = Aes256Gcm::new_from_slice(key).context("Failed to create cipher")?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&iv), data)
+        .map_err(|_| anyhow::anyhow!("Blob encryption failed"))?;
+
+    fs::create_dir_all(dir)?;
+    let mut contents = iv.to_vec();
+    contents.extend_from_slice(&ciphertext);
+    fs::write(&path, contents).context("Failed to write blob")?;
+    Ok(id)
+}
+
+fn get_in(dir: &Path, id: &str, key: &[u8; 32]) -> Result<Vec<u8>> {
+    let contents = fs::read(blob_path(dir, id)?).context("Blob not found")?;
+    if contents.len() < IV_LEN {
+        bail!("Blob {} is truncated", id);
+    }
+    let (iv, ciphertext) = contents.split_at(IV_LEN);
+    let cipher = Aes256Gcm::new_from_slice(key).context("Failed to create cipher")?;
+    let data = cipher
+        .decrypt(Nonce::from_slice(iv), ciphertext)
+        .map_err(|_| anyhow::anyhow!("Blob {} failed to decrypt", id))?;
+    if format!("{:x}", Sha256::digest(&data)) != id {
+        bail!("Blob {} does not match its hash", id);
+    }
+    Ok(data)
+}
+
+fn blobs_dir() -> Result<PathBuf> {
+    Ok(crate::paths::data_dir()?.join("blobs"))
+}
+
+/// Store `data` for the account `fingerprint`, returning its id
+pub fn put(data: &[u8], fingerprint: &str) -> Result<String> {
+    put_in(&blobs_dir()?, data, &derive_storage_key(fingerprint))
+}
+
+/// Load a blob stored with `put`
+pub fn get(id: &str, fingerprint: &str) -> Result<Vec<u8>> {
+    get_in(&blobs_dir()?, id, &derive_storage_key(fingerprint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blobs_round_trip_and_reject_tampering() {
+        let dir = std::env::temp_dir().join(format!("cryptochat_blobs_{}", uuid::Uuid::new_v4()));
+        let key = [7u8; 32];
+
+        let id = put_in(&dir, b"voice clip", &key).unwrap();
+        assert_eq!(put_in(&dir, b"voice clip", &key).unwrap(), id);
+        assert_eq!(get_in(&dir, &id, &key).unwrap(), b"voice clip");
+        assert!(get_in(&dir, &id, &[8u8; 32]).is_err());
+        assert!(get_in(&dir, "../account", &key).is_err());
+
+        let path = blob_path(&dir, &id).unwrap();
+        let mut contents = fs::read(&path).unwrap();
+        *contents.last_mut().unwrap() ^= 1;
+        fs::write(&path, contents).unwrap();
+        assert!(get_in(&dir, &id, &key).is_err());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -6,6 +6,19 @@ use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
 
+/// Default accent color for "my" bubbles
+pub const DEFAULT_BUBBLE_COLOR: &str = "#7c3aed";
+
+/// Message text size bounds (points)
+pub const MIN_FONT_SIZE: u8 = 10;
+pub const MAX_FONT_SIZE: u8 = 28;
+const DEFAULT_FONT_SIZE: u8 = 14;
+
+/// Bubble corner radius bounds (pixels)
+pub const MIN_BUBBLE_RADIUS: f32 = 0.0;
+pub const MAX_BUBBLE_RADIUS: f32 = 24.0;
+const DEFAULT_BUBBLE_RADIUS: f32 = 18.0;
+
 /// Bubble style options
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub enum BubbleStyle {
@@ -20,7 +33,7 @@ pub enum BubbleStyle {
 impl Default for BubbleStyle {
     fn default() -> Self {
         BubbleStyle::Solid {
-            color: "#7c3aed".to_string(), // New accent purple
+            color: DEFAULT_BUBBLE_COLOR.to_string(), // New accent purple
         }
     }
 }
@@ -33,48 +46,61 @@ pub struct ColorPreferences {
     pub saturation: f32, // 0.0 - 1.0
     #[serde(default = "default_their_color")]
     pub their_bubble_color: String, // Hex string for incoming messages
+    #[serde(default = "default_font_size")]
+    pub font_size: u8, // Message text size
+    #[serde(default = "default_bubble_radius")]
+    pub bubble_radius: f32, // Bubble corner radius
 }
 
 fn default_their_color() -> String {
     "#222240".to_string() // New surface color
 }
 
+fn default_font_size() -> u8 {
+    DEFAULT_FONT_SIZE
+}
+
+fn default_bubble_radius() -> f32 {
+    DEFAULT_BUBBLE_RADIUS
+}
+
+/// Clamp a requested message text size to the supported range
+pub fn clamp_font_size(size: i32) -> u8 {
+    size.clamp(MIN_FONT_SIZE as i32, MAX_FONT_SIZE as i32) as u8
+}
+
+/// Clamp a requested bubble corner radius to the supported range
+pub fn clamp_bubble_radius(radius: f32) -> f32 {
+    if radius.is_nan() {
+        return DEFAULT_BUBBLE_RADIUS;
+    }
+    radius.clamp(MIN_BUBBLE_RADIUS, MAX_BUBBLE_RADIUS)
+}
+
 impl Default for ColorPreferences {
     fn default() -> Self {
         Self {
             bubble_style: BubbleStyle::Solid {
-                color: "#7c3aed".to_string(), // New accent purple
+                color: DEFAULT_BUBBLE_COLOR.to_string(), // New accent purple
             },
             hue: 270.0,      // Purple hue
             saturation: 0.8, // High saturation
             their_bubble_color: default_their_color(),
+            font_size: DEFAULT_FONT_SIZE,
+            bubble_radius: DEFAULT_BUBBLE_RADIUS,
         }
     }
 }
 
 /// Get path to colors.json
 fn get_colors_path() -> PathBuf {
-    let base = std::env::var("USERPROFILE").unwrap_or_else(|_| ".".to_string());
-    let instance_suffix = std::env::args()
-        .skip_while(|a| a != "--instance")
-        .nth(1)
-        .map(|i| format!("_{}", i))
-        .unwrap_or_default();
-    
-    PathBuf::from(format!("{}/.cryptochat{}/colors.json", base, instance_suffix))
+    crate::paths::data_dir().unwrap_or_else(|_| PathBuf::from(".")).join("colors.json")
 }
 
 /// Load color preferences from disk
 pub fn load_preferences() -> ColorPreferences {
-    let path = get_colors_path();
-    if path.exists() {
-        if let Ok(data) = fs::read_to_string(&path) {
-            if let Ok(prefs) = serde_json::from_str(&data) {
-                return prefs;
-            }
-        }
-    }
-    ColorPreferences::default()
+    crate::store_recovery::load_or_recover(&get_colors_path(), |json| Ok(serde_json::from_slice(json)?))
+        .unwrap_or_default()
 }
 
 /// Save color preferences to disk
@@ -87,6 +113,73 @@ pub fn save_preferences(prefs: &ColorPreferences) -> Result<(), std::io::Error>
     fs::write(path, json)
 }
 
+/// Errors from importing a shared theme
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThemeError {
+    /// The theme isn't valid JSON for `ColorPreferences`
+    Malformed(String),
+    /// A color field isn't a `#rrggbb` hex string
+    InvalidColor { field: &'static str, value: String },
+    /// A numeric field is out of range
+    OutOfRange { field: &'static str, value: f32 },
+}
+
+impl std::fmt::Display for ThemeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ThemeError::Malformed(e) => write!(f, "Malformed theme: {}", e),
+            ThemeError::InvalidColor { field, value } => write!(f, "Invalid color for {}: {:?}", field, value),
+            ThemeError::OutOfRange { field, value } => write!(f, "{} out of range: {}", field, value),
+        }
+    }
+}
+
+impl std::error::Error for ThemeError {}
+
+/// Serialize color preferences for sharing or backup
+pub fn export_theme(prefs: &ColorPreferences) -> String {
+    serde_json::to_string_pretty(prefs).unwrap_or_default()
+}
+
+/// Parse and validate a theme produced by `export_theme`
+pub fn import_theme(json: &str) -> Result<ColorPreferences, ThemeError> {
+    let mut prefs: ColorPreferences = serde_json::from_str(json)
+        .map_err(|e| ThemeError::Malformed(e.to_string()))?;
+    prefs.font_size = clamp_font_size(prefs.font_size as i32);
+    prefs.bubble_radius = clamp_bubble_radius(prefs.bubble_radius);
+
+    let check_color = |field: &'static str, value: &str| {
+        if value.starts_with('#') && hex_to_rgb(value).is_some() {
+            Ok(())
+        } else {
+            Err(ThemeError::InvalidColor { field, value: value.to_string() })
+        }
+    };
+
+    match &prefs.bubble_style {
+        BubbleStyle::Solid { color } => check_color("color", color)?,
+        BubbleStyle::Gradient { color1, color2 } => {
+            check_color("color1", color1)?;
+            check_color("color2", color2)?;
+        }
+        BubbleStyle::Rainbow { speed } => {
+            if !(speed.is_finite() && *speed > 0.0 && *speed <= 10.0) {
+                return Err(ThemeError::OutOfRange { field: "speed", value: *speed });
+            }
+        }
+    }
+    check_color("their_bubble_color", &prefs.their_bubble_color)?;
+
+    if !(0.0..=360.0).contains(&prefs.hue) {
+        return Err(ThemeError::OutOfRange { field: "hue", value: prefs.hue });
+    }
+    if !(0.0..=1.0).contains(&prefs.saturation) {
+        return Err(ThemeError::OutOfRange { field: "saturation", value: prefs.saturation });
+    }
+
+    Ok(prefs)
+}
+
 /// Convert HSL to hex color string
 pub fn hsl_to_hex(hue: f32, saturation: f32, lightness: f32) -> String {
     let c = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
@@ -128,8 +221,130 @@ pub fn hex_to_rgb(hex: &str) -> Option<(f32, f32, f32)> {
     Some((r, g, b))
 }
 
+/// Resolve the bubble color for a conversation: its own override if set and
+/// valid, then the global style's primary color, then the default accent
+pub fn resolve_bubble_color(conversation_color: Option<&str>, prefs: &ColorPreferences) -> String {
+    if let Some(color) = conversation_color.filter(|c| hex_to_rgb(c).is_some()) {
+        return color.to_string();
+    }
+    let global = match &prefs.bubble_style {
+        BubbleStyle::Solid { color } => Some(color.as_str()),
+        BubbleStyle::Gradient { color1, .. } => Some(color1.as_str()),
+        BubbleStyle::Rainbow { .. } => None,
+    };
+    match global.filter(|c| hex_to_rgb(c).is_some()) {
+        Some(color) => color.to_string(),
+        None => DEFAULT_BUBBLE_COLOR.to_string(),
+    }
+}
+
 /// Get rainbow color based on time offset (0.0 - 1.0 through spectrum)
 pub fn rainbow_color(offset: f32) -> String {
     let hue = (offset * 360.0) % 360.0;
     hsl_to_hex(hue, 0.8, 0.5)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn theme_round_trip() {
+        let prefs = ColorPreferences {
+            bubble_style: BubbleStyle::Gradient {
+                color1: "#ff0000".to_string(),
+                color2: "#0000ff".to_string(),
+            },
+            hue: 120.0,
+            saturation: 0.5,
+            their_bubble_color: "#123456".to_string(),
+            ..Default::default()
+        };
+        let imported = import_theme(&export_theme(&prefs)).unwrap();
+        assert_eq!(imported.bubble_style, prefs.bubble_style);
+        assert_eq!(imported.their_bubble_color, "#123456");
+        assert_eq!(imported.hue, 120.0);
+    }
+
+    #[test]
+    fn import_rejects_invalid_color() {
+        let mut prefs = ColorPreferences::default();
+        prefs.their_bubble_color = "#12345g".to_string();
+        assert_eq!(
+            import_theme(&export_theme(&prefs)).unwrap_err(),
+            ThemeError::InvalidColor { field: "their_bubble_color", value: "#12345g".to_string() }
+        );
+
+        let bad_solid = r##"{"bubble_style":{"Solid":{"color":"red"}},"hue":0.0,"saturation":0.5}"##;
+        assert!(matches!(import_theme(bad_solid), Err(ThemeError::InvalidColor { field: "color", .. })));
+    }
+
+    #[test]
+    fn import_rejects_malformed_json() {
+        assert!(matches!(import_theme("{not json"), Err(ThemeError::Malformed(_))));
+    }
+
+    #[test]
+    fn conversation_color_overrides_global() {
+        let prefs = ColorPreferences {
+            bubble_style: BubbleStyle::Solid { color: "#112233".to_string() },
+            ..Default::default()
+        };
+        assert_eq!(resolve_bubble_color(Some("#abcdef"), &prefs), "#abcdef");
+    }
+
+    #[test]
+    fn global_color_used_when_no_override() {
+        let prefs = ColorPreferences {
+            bubble_style: BubbleStyle::Solid { color: "#112233".to_string() },
+            ..Default::default()
+        };
+        assert_eq!(resolve_bubble_color(None, &prefs), "#112233");
+        // Invalid override falls through to global
+        assert_eq!(resolve_bubble_color(Some("nope"), &prefs), "#112233");
+    }
+
+    #[test]
+    fn default_color_used_when_nothing_set() {
+        let prefs = ColorPreferences {
+            bubble_style: BubbleStyle::Rainbow { speed: 1.0 },
+            ..Default::default()
+        };
+        assert_eq!(resolve_bubble_color(None, &prefs), "#7c3aed");
+
+        let broken = ColorPreferences {
+            bubble_style: BubbleStyle::Solid { color: "garbage".to_string() },
+            ..Default::default()
+        };
+        assert_eq!(resolve_bubble_color(None, &broken), "#7c3aed");
+    }
+
+    #[test]
+    fn font_size_and_radius_are_clamped() {
+        assert_eq!(clamp_font_size(2), MIN_FONT_SIZE);
+        assert_eq!(clamp_font_size(16), 16);
+        assert_eq!(clamp_font_size(300), MAX_FONT_SIZE);
+        assert_eq!(clamp_bubble_radius(-5.0), MIN_BUBBLE_RADIUS);
+        assert_eq!(clamp_bubble_radius(12.0), 12.0);
+        assert_eq!(clamp_bubble_radius(99.0), MAX_BUBBLE_RADIUS);
+        assert_eq!(clamp_bubble_radius(f32::NAN), DEFAULT_BUBBLE_RADIUS);
+    }
+
+    #[test]
+    fn new_fields_round_trip_and_default_for_old_files() {
+        let prefs = ColorPreferences {
+            font_size: 20,
+            bubble_radius: 6.0,
+            ..Default::default()
+        };
+        let json = serde_json::to_string(&prefs).unwrap();
+        let loaded: ColorPreferences = serde_json::from_str(&json).unwrap();
+        assert_eq!((loaded.font_size, loaded.bubble_radius), (20, 6.0));
+
+        // colors.json written before these settings existed
+        let old = r##"{"bubble_style":{"Solid":{"color":"#2c7be5"}},"hue":210.0,"saturation":0.8,"their_bubble_color":"#222240"}"##;
+        let loaded: ColorPreferences = serde_json::from_str(old).unwrap();
+        assert_eq!(loaded.font_size, DEFAULT_FONT_SIZE);
+        assert_eq!(loaded.bubble_radius, DEFAULT_BUBBLE_RADIUS);
+    }
+}
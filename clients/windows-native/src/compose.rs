@@ -0,0 +1,128 @@
+//! Length limit for outgoing text messages
+//!
+//! A draft longer than the limit isn't sent as one enormous bubble. If it
+//! fits in [`MAX_SPLIT_PARTS`] pieces it can go out as several messages,
+//! broken at whitespace where possible; anything longer is offered as a
+//! text file instead. The parts of a split draft go out one at a time, each
+//! after the previous send finished, so they arrive in order.
+
+/// Default longest message, in characters
+pub const DEFAULT_MAX_MESSAGE_CHARS: usize = 4000;
+/// Most messages an oversized draft is split into before a file is offered
+pub const MAX_SPLIT_PARTS: usize = 5;
+/// The counter appears once a draft reaches this share of the limit (percent)
+const COUNTER_THRESHOLD_PERCENT: usize = 80;
+
+/// Longest message, overridden by CRYPTOCHAT_MAX_MESSAGE_CHARS
+pub fn max_message_chars_from_env() -> usize {
+    std::env::var("CRYPTOCHAT_MAX_MESSAGE_CHARS")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|max| *max > 0)
+        .unwrap_or(DEFAULT_MAX_MESSAGE_CHARS)
+}
+
+/// What to do with a draft of a given length
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthCheck {
+    /// Within the limit: send as is
+    Fits,
+    /// Too long, but splits into this many messages
+    Split(usize),
+    /// Too long to split sensibly: send as a text file
+    SendAsFile,
+}
+
+/// Check a draft against `max_chars`
+pub fn check_length(text: &str, max_chars: usize) -> LengthCheck {
+    if text.chars().count() <= max_chars {
+        return LengthCheck::Fits;
+    }
+    // A draft needing more parts than allowed can stop counting early
+    match split_parts(text, max_chars).take(MAX_SPLIT_PARTS + 1).count() {
+        parts if parts <= MAX_SPLIT_PARTS => LengthCheck::Split(parts),
+        _ => LengthCheck::SendAsFile,
+    }
+}
+
+/// Cut `text` into messages of at most `max_chars` characters, preferring
+/// to break after whitespace in the second half of each piece
+pub fn split_message(text: &str, max_chars: usize) -> Vec<String> {
+    split_parts(text, max_chars).map(str::to_string).collect()
+}
+
+fn split_parts(text: &str, max_chars: usize) -> impl Iterator<Item = &str> {
+    let max_chars = max_chars.max(1);
+    let mut rest = text;
+    std::iter::from_fn(move || loop {
+        if rest.is_empty() {
+            return None;
+        }
+        let end = match rest.char_indices().nth(max_chars) {
+            // The remainder fits
+            None => rest.len(),
+            Some((limit, _)) => {
+                let window = &rest[..limit];
+                let half = window.char_indices().nth(max_chars / 2).map_or(0, |(i, _)| i);
+                window[half..]
+                    .rfind(char::is_whitespace)
+                    .map(|i| {
+                        let at = half + i;
+                        at + window[at..].chars().next().map_or(1, char::len_utf8)
+                    })
+                    .unwrap_or(limit)
+            }
+        };
+        let (part, remainder) = rest.split_at(end);
+        rest = remainder;
+        let part = part.trim();
+        if !part.is_empty() {
+            return Some(part);
+        }
+    })
+}
+
+/// Parts of a split direct message still to send
+#[derive(Debug)]
+pub struct PendingSplit {
+    /// Conversation the parts belong to
+    pub conversation_id: String,
+    /// Id of the part being sent; the next one waits for it
+    pub in_flight: String,
+    pub remaining: std::collections::VecDeque<String>,
+}
+
+/// "used/limit" once a draft is close to the limit
+pub fn counter(text: &str, max_chars: usize) -> Option<String> {
+    let used = text.chars().count();
+    (used * 100 >= max_chars * COUNTER_THRESHOLD_PERCENT).then(|| format!("{}/{}", used, max_chars))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_or_file_at_the_boundary() {
+        let max = 10;
+        assert_eq!(check_length(&"x".repeat(max), max), LengthCheck::Fits);
+        assert_eq!(check_length(&"x".repeat(max + 1), max), LengthCheck::Split(2));
+        assert_eq!(check_length(&"x".repeat(max * MAX_SPLIT_PARTS), max), LengthCheck::Split(MAX_SPLIT_PARTS));
+        assert_eq!(check_length(&"x".repeat(max * MAX_SPLIT_PARTS + 1), max), LengthCheck::SendAsFile);
+        // Characters, not bytes
+        assert_eq!(check_length(&"é".repeat(max), max), LengthCheck::Fits);
+    }
+
+    #[test]
+    fn splits_at_whitespace_within_the_limit() {
+        let parts = split_message("hello there general kenobi", 12);
+        assert_eq!(parts, vec!["hello there", "general", "kenobi"]);
+        assert!(parts.iter().all(|p| p.chars().count() <= 12));
+
+        // No whitespace to break at: hard cut on a character boundary
+        assert_eq!(split_message("ééééé", 2), vec!["éé", "éé", "é"]);
+
+        assert_eq!(counter(&"x".repeat(7), 10), None);
+        assert_eq!(counter(&"x".repeat(8), 10).as_deref(), Some("8/10"));
+    }
+}
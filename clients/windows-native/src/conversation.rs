@@ -1,12 +1,22 @@
 
+use cryptochat_messaging::settings_sync::ConversationSettings;
 use serde::{Serialize, Deserialize};
+use std::collections::{HashMap, VecDeque};
+use uuid::Uuid;
+
+/// Message ids each conversation remembers for spotting resent envelopes
+pub const MAX_RECENT_IDS: usize = 512;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
     pub sender_name: String,
     pub content: String,
     pub is_mine: bool,
+    /// Local "HH:MM" the message was sent/received, for display
     pub timestamp: String,
+    /// Unix millis the message was sent/received (0 = older history without it)
+    #[serde(default)]
+    pub sent_at_ms: i64,
     /// Optional image data for inline preview (stored in memory)
     pub image_data: Option<Vec<u8>>,
     /// Filename for images (used for save button)
@@ -15,6 +25,75 @@ pub struct ChatMessage {
     pub reactions: Vec<(String, String)>,
     /// Custom emotes used in this message (name -> hash)
     pub emotes: std::collections::HashMap<String, String>,
+    /// RFC3339 time after which this message is deleted (None = never)
+    #[serde(default)]
+    pub expires_at: Option<String>,
+    /// Id shared with the peer so acks can find our copy (outgoing only)
+    #[serde(default)]
+    pub message_id: Option<String>,
+    /// Delivery progress of an outgoing message
+    #[serde(default)]
+    pub status: DeliveryStatus,
+    /// Group members (fingerprints) that acknowledged an outgoing group message
+    #[serde(default)]
+    pub delivered_to: Vec<String>,
+    /// We forwarded this from another conversation
+    #[serde(default)]
+    pub forwarded: bool,
+    /// Recorded voice clip, its audio kept in the blob store
+    #[serde(default)]
+    pub voice: Option<crate::voice::VoiceClip>,
+    /// Stable id that reactions, acks and read positions refer to. It is the
+    /// sender's `message_id` when one came with the message; `message_id` is
+    /// only kept as well on our outgoing copies, for the outbox and acks.
+    /// History from before ids loads with none and gets one from
+    /// [`assign_legacy_ids`].
+    #[serde(default)]
+    pub id: Uuid,
+}
+
+/// How far an outgoing message has got. History from before this existed
+/// loads as `Sent`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DeliveryStatus {
+    /// Added locally, send still in flight
+    Pending,
+    /// Handed to the peer's socket
+    #[default]
+    Sent,
+    /// Peer acknowledged receiving it
+    Delivered,
+    /// Peer has read past it
+    Read,
+    /// Peer could not be reached
+    Failed,
+}
+
+impl DeliveryStatus {
+    /// Whether moving to `next` is progress. Statuses never go backwards, so
+    /// a late send result can't undo a read receipt; a failed send can only
+    /// be retried.
+    pub fn can_become(self, next: DeliveryStatus) -> bool {
+        use DeliveryStatus::*;
+        match (self, next) {
+            (Pending, next) => next != Pending,
+            (Failed, Pending) => true,
+            (Sent, Delivered | Read) => true,
+            (Delivered, Read) => true,
+            _ => false,
+        }
+    }
+
+    /// Bubble suffix shown next to the timestamp
+    pub fn indicator(self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => " [pending]",
+            DeliveryStatus::Sent => " [sent]",
+            DeliveryStatus::Delivered => " [delivered]",
+            DeliveryStatus::Read => " [read]",
+            DeliveryStatus::Failed => " [failed]",
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -24,11 +103,34 @@ pub struct Conversation {
     pub messages: Vec<ChatMessage>,
     pub unread_count: usize,
     pub last_activity: u64,
+    #[serde(default)]
     pub input_draft: String,
     #[serde(skip)]
     pub is_typing: bool,
+    /// Group members currently typing: fingerprint -> (name, last update ms)
+    #[serde(skip)]
+    pub typing_members: HashMap<String, (String, i64)>,
     pub last_read: Option<String>,
     pub peer_address: Option<String>,
+    /// Bubble color override for this chat (hex), None = use global preference
+    #[serde(default)]
+    pub bubble_color: Option<String>,
+    /// Disappearing message timer for this chat in seconds (None = off)
+    #[serde(default)]
+    pub disappearing_timer_secs: Option<u64>,
+    /// How far each peer has read (fingerprint -> `sent_at_ms` of our copy of
+    /// the newest message they've read): one entry for a direct chat, one per
+    /// member for a group. Times are ours, so they order our own history.
+    #[serde(default)]
+    pub read_through_ms: HashMap<String, i64>,
+    /// Mute/pin/archive flags, synced between devices; mute silences toasts
+    /// and sounds but unread counts still update
+    #[serde(flatten)]
+    pub settings: ConversationSettings,
+    /// Ids of the most recent messages added, oldest first, so a resent
+    /// envelope isn't shown twice even after its message expired
+    #[serde(default)]
+    pub recent_ids: VecDeque<Uuid>,
 }
 
 impl Conversation {
@@ -41,8 +143,1039 @@ impl Conversation {
             last_activity: 0,
             input_draft: String::new(),
             is_typing: false,
+            typing_members: HashMap::new(),
             last_read: None,
             peer_address,
+            bubble_color: None,
+            disappearing_timer_secs: None,
+            read_through_ms: HashMap::new(),
+            settings: ConversationSettings::default(),
+            recent_ids: VecDeque::new(),
+        }
+    }
+
+    /// Remember `id` as added to this conversation. Returns false if it
+    /// already was, i.e. the message is a duplicate.
+    pub fn note_message_id(&mut self, id: Uuid) -> bool {
+        if self.recent_ids.contains(&id) {
+            return false;
+        }
+        if self.recent_ids.len() >= MAX_RECENT_IDS {
+            self.recent_ids.pop_front();
+        }
+        self.recent_ids.push_back(id);
+        true
+    }
+}
+
+/// Move the composer text into the draft of the conversation being left and
+/// load the draft of the conversation being entered into `message_input`.
+pub fn switch_drafts(
+    conversations: &mut HashMap<String, Conversation>,
+    from: Option<&str>,
+    to: &str,
+    message_input: &mut String,
+) {
+    if let Some(conv) = from.and_then(|id| conversations.get_mut(id)) {
+        conv.input_draft = std::mem::take(message_input);
+    }
+    *message_input = conversations
+        .get(to)
+        .map(|conv| conv.input_draft.clone())
+        .unwrap_or_default();
+}
+
+/// Timer choices offered in the UI, in seconds (None = off)
+pub const DISAPPEARING_TIMER_OPTIONS: [Option<u64>; 5] = [
+    None,
+    Some(5 * 60),
+    Some(60 * 60),
+    Some(24 * 60 * 60),
+    Some(7 * 24 * 60 * 60),
+];
+
+/// Next timer in `DISAPPEARING_TIMER_OPTIONS` (wraps back to off)
+pub fn next_disappearing_timer(current: Option<u64>) -> Option<u64> {
+    let idx = DISAPPEARING_TIMER_OPTIONS.iter().position(|t| *t == current).unwrap_or(0);
+    DISAPPEARING_TIMER_OPTIONS[(idx + 1) % DISAPPEARING_TIMER_OPTIONS.len()]
+}
+
+/// Short label for a timer, e.g. "5m", "1h", "Off"
+pub fn disappearing_timer_label(timer_secs: Option<u64>) -> String {
+    match timer_secs {
+        None => "Off".to_string(),
+        Some(s) if s % 86_400 == 0 => format!("{}d", s / 86_400),
+        Some(s) if s % 3_600 == 0 => format!("{}h", s / 3_600),
+        Some(s) if s % 60 == 0 => format!("{}m", s / 60),
+        Some(s) => format!("{}s", s),
+    }
+}
+
+/// Set `expires_at` from the chat's timer unless the message already has one
+/// (e.g. stamped by the sender)
+pub fn stamp_expiry(msg: &mut ChatMessage, timer_secs: Option<u64>, now: chrono::DateTime<chrono::Utc>) {
+    if msg.expires_at.is_some() {
+        return;
+    }
+    if let Some(secs) = timer_secs {
+        let expiry = now + chrono::Duration::seconds(secs as i64);
+        msg.expires_at = Some(expiry.to_rfc3339());
+    }
+}
+
+/// Whether a message's expiry time has passed (unparseable times are kept)
+pub fn is_expired(msg: &ChatMessage, now: chrono::DateTime<chrono::Utc>) -> bool {
+    msg.expires_at
+        .as_deref()
+        .and_then(|t| chrono::DateTime::parse_from_rfc3339(t).ok())
+        .is_some_and(|expiry| expiry <= now)
+}
+
+/// Drop expired messages from every conversation, returning how many were
+/// removed. Unread messages that expire stop counting as unread.
+pub fn sweep_expired(conversations: &mut HashMap<String, Conversation>, now: chrono::DateTime<chrono::Utc>) -> usize {
+    let mut removed = 0;
+    for conv in conversations.values_mut() {
+        if let Some(first_unread) = first_unread_index(conv) {
+            let expired_unread = conv.messages[first_unread..]
+                .iter()
+                .filter(|msg| !msg.is_mine && is_expired(msg, now))
+                .count();
+            conv.unread_count -= expired_unread.min(conv.unread_count);
+        }
+        let before = conv.messages.len();
+        conv.messages.retain(|msg| !is_expired(msg, now));
+        removed += before - conv.messages.len();
+    }
+    removed
+}
+
+/// Id to report when we open a conversation: the newest message, since
+/// everything we have is read. Ids are shared with the peer, so it means the
+/// same message to them even when our histories differ.
+pub fn read_position(conv: &Conversation) -> Option<Uuid> {
+    conv.messages.last().map(|m| m.id)
+}
+
+/// When our copy of `last_read` arrived, for a peer who has read up to it.
+/// None if we don't have that message (it expired, or never reached us).
+pub fn read_through_ms(conv: &Conversation, last_read: Uuid) -> Option<i64> {
+    conv.messages.iter().find(|m| m.id == last_read).map(|m| m.sent_at_ms)
+}
+
+/// Index of the oldest unread message: the `unread_count` most recent
+/// messages from the other side. `None` when nothing is unread.
+pub fn first_unread_index(conv: &Conversation) -> Option<usize> {
+    if conv.unread_count == 0 {
+        return None;
+    }
+    conv.messages.iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, m)| !m.is_mine)
+        .take(conv.unread_count)
+        .last()
+        .map(|(i, _)| i)
+}
+
+/// Clear every unread badge. Returns the conversations that had any, so
+/// their peers can be told how far we've read.
+pub fn mark_all_read(conversations: &mut HashMap<String, Conversation>) -> Vec<String> {
+    conversations.values_mut()
+        .filter(|c| c.unread_count > 0)
+        .map(|c| {
+            c.unread_count = 0;
+            c.id.clone()
+        })
+        .collect()
+}
+
+/// Record that `reader` has read everything up to `through_ms` (see
+/// [`read_through_ms`]). Positions only move forward, so a late or replayed
+/// update can't un-read anything. Returns whether anything changed.
+pub fn record_read(conv: &mut Conversation, reader: &str, through_ms: i64) -> bool {
+    match conv.read_through_ms.get_mut(reader) {
+        Some(position) if through_ms <= *position => false,
+        Some(position) => {
+            *position = through_ms;
+            true
+        }
+        None => {
+            conv.read_through_ms.insert(reader.to_string(), through_ms);
+            true
+        }
+    }
+}
+
+/// Whether a peer read through `through_ms` has seen `msg`
+fn has_read(through_ms: i64, msg: &ChatMessage) -> bool {
+    msg.sent_at_ms <= through_ms
+}
+
+/// Text put on the clipboard by "Copy": the filename for image/file
+/// messages, otherwise the message text as written (newlines kept)
+pub fn copy_text(msg: &ChatMessage) -> String {
+    match &msg.image_filename {
+        Some(filename) => filename.clone(),
+        None => msg.content.clone(),
+    }
+}
+
+/// A group member's typing state expires if no update arrives for this long
+pub const TYPING_TIMEOUT_MS: i64 = 6_000;
+
+/// Record a typing update from a group member
+pub fn set_member_typing(conv: &mut Conversation, fingerprint: &str, name: &str, is_typing: bool, now_ms: i64) {
+    if is_typing {
+        conv.typing_members.insert(fingerprint.to_string(), (name.to_string(), now_ms));
+    } else {
+        conv.typing_members.remove(fingerprint);
+    }
+}
+
+/// Drop typing states that haven't been refreshed within `TYPING_TIMEOUT_MS`.
+/// Returns whether anything was cleared.
+pub fn clear_stale_typing(conversations: &mut HashMap<String, Conversation>, now_ms: i64) -> bool {
+    let mut cleared = false;
+    for conv in conversations.values_mut() {
+        let before = conv.typing_members.len();
+        conv.typing_members.retain(|_, (_, at)| now_ms - *at < TYPING_TIMEOUT_MS);
+        cleared |= conv.typing_members.len() != before;
+    }
+    cleared
+}
+
+/// Whether anyone is typing in `conv`
+pub fn someone_typing(conv: &Conversation) -> bool {
+    conv.is_typing || !conv.typing_members.is_empty()
+}
+
+/// "Alice is typing", "Alice and Bob are typing", "Alice, Bob and 2 others are typing"
+pub fn typing_label(names: &[&str]) -> Option<String> {
+    let mut names = names.to_vec();
+    names.sort_unstable();
+    let label = match names.as_slice() {
+        [] => return None,
+        [one] => format!("{} is typing", one),
+        [first, second] => format!("{} and {} are typing", first, second),
+        [first, second, third] => format!("{}, {} and {} are typing", first, second, third),
+        [first, second, rest @ ..] => format!("{}, {} and {} others are typing", first, second, rest.len()),
+    };
+    Some(label)
+}
+
+/// Id for a new message: the sender's shared `message_id` so both sides
+/// agree on it, or a fresh one when none was sent
+pub fn message_uuid(shared_id: Option<&str>) -> Uuid {
+    shared_id.and_then(|id| Uuid::parse_str(id).ok()).unwrap_or_else(Uuid::new_v4)
+}
+
+/// Give messages stored before ids existed one: their `message_id` if they
+/// have it, otherwise one derived from the message itself, so the same
+/// history gets the same ids on every load.
+pub fn assign_legacy_ids(conversations: &mut HashMap<String, Conversation>) {
+    use sha2::{Digest, Sha256};
+    for (conversation_id, conv) in conversations.iter_mut() {
+        let mut seen = std::collections::HashSet::new();
+        for msg in conv.messages.iter_mut().filter(|m| m.id.is_nil()) {
+            if let Some(id) = msg.message_id.as_deref().and_then(|id| Uuid::parse_str(id).ok()) {
+                msg.id = id;
+                continue;
+            }
+            // Identical messages in the same minute are told apart by order
+            for occurrence in 0u32.. {
+                let mut hash = Sha256::new();
+                for part in [conversation_id.as_str(), &msg.sender_name, &msg.timestamp, &msg.content] {
+                    hash.update((part.len() as u64).to_be_bytes());
+                    hash.update(part.as_bytes());
+                }
+                hash.update(msg.sent_at_ms.to_be_bytes());
+                hash.update(occurrence.to_be_bytes());
+                let digest = hash.finalize();
+                let id = Uuid::from_slice(&digest[..16]).expect("16 bytes make a UUID");
+                if seen.insert(id) {
+                    msg.id = id;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// The message a peer refers to. Matched by id when the peer sent one;
+/// peers too old to send ids fall back to the `HH:MM` timestamp, which can't
+/// tell apart messages from the same minute.
+fn find_message_mut<'a>(conv: &'a mut Conversation, msg_id: Option<Uuid>, msg_timestamp: &str) -> Option<&'a mut ChatMessage> {
+    let idx = match msg_id {
+        Some(id) => conv.messages.iter().position(|m| m.id == id)?,
+        None => conv.messages.iter().position(|m| m.timestamp == msg_timestamp)?,
+    };
+    conv.messages.get_mut(idx)
+}
+
+/// Toggle `sender_name`'s `emoji` on the message with `msg_id` (or, from a
+/// peer that sent no id, the one sent at `msg_timestamp`). Returns false if
+/// that message isn't in local history.
+pub fn toggle_reaction(conv: &mut Conversation, msg_id: Option<Uuid>, msg_timestamp: &str, emoji: &str, sender_name: &str) -> bool {
+    let Some(msg) = find_message_mut(conv, msg_id, msg_timestamp) else {
+        return false;
+    };
+    match msg.reactions.iter().position(|(e, s)| e == emoji && s == sender_name) {
+        Some(pos) => {
+            msg.reactions.remove(pos);
+        }
+        None => msg.reactions.push((emoji.to_string(), sender_name.to_string())),
+    }
+    true
+}
+
+/// Route a received reaction to the group chat when it carries a `group_id`,
+/// otherwise to the sender's direct chat, and toggle it there. Returns false
+/// when there is no such conversation or message.
+pub fn apply_reaction(
+    conversations: &mut HashMap<String, Conversation>,
+    group_id: Option<&str>,
+    sender_fingerprint: &str,
+    msg_id: Option<Uuid>,
+    msg_timestamp: &str,
+    emoji: &str,
+    sender_name: &str,
+) -> bool {
+    let conv_id = group_id.unwrap_or(sender_fingerprint);
+    conversations
+        .get_mut(conv_id)
+        .is_some_and(|conv| toggle_reaction(conv, msg_id, msg_timestamp, emoji, sender_name))
+}
+
+/// Reactions on a message grouped by emoji, with how many people chose each,
+/// in the order each emoji was first used
+pub fn reaction_counts(msg: &ChatMessage) -> Vec<(&str, usize)> {
+    let mut counts: Vec<(&str, usize)> = Vec::new();
+    for (emoji, _) in &msg.reactions {
+        match counts.iter_mut().find(|(e, _)| e == emoji) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((emoji.as_str(), 1)),
+        }
+    }
+    counts
+}
+
+/// Most names listed for one emoji before the rest become "+N more"
+pub const MAX_REACTORS_SHOWN: usize = 5;
+
+/// Who reacted with each emoji, in the order each emoji was first used.
+/// Someone listed twice for the same emoji appears once.
+pub fn reactors_by_emoji(msg: &ChatMessage) -> Vec<(&str, Vec<&str>)> {
+    let mut reactors: Vec<(&str, Vec<&str>)> = Vec::new();
+    for (emoji, sender) in &msg.reactions {
+        match reactors.iter_mut().find(|(e, _)| e == emoji) {
+            Some((_, names)) if names.contains(&sender.as_str()) => {}
+            Some((_, names)) => names.push(sender.as_str()),
+            None => reactors.push((emoji.as_str(), vec![sender.as_str()])),
+        }
+    }
+    reactors
+}
+
+/// One line per emoji naming who reacted, e.g. "👍 alice, bob +3 more"
+pub fn reactor_summary(msg: &ChatMessage) -> String {
+    reactors_by_emoji(msg)
+        .into_iter()
+        .map(|(emoji, names)| {
+            let shown = names[..names.len().min(MAX_REACTORS_SHOWN)].join(", ");
+            match names.len().saturating_sub(MAX_REACTORS_SHOWN) {
+                0 => format!("{} {}", emoji, shown),
+                more => format!("{} {} +{} more", emoji, shown, more),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Whether a new message in `conv` should raise a toast/sound. Muted chats
+/// stay quiet unless the message mentions us.
+pub fn should_notify(conv: Option<&Conversation>, mentioned: bool) -> bool {
+    mentioned || !conv.is_some_and(|c| c.settings.muted.value)
+}
+
+/// Calendar day (in `tz`) a message was sent on; None for history without a send time
+pub fn message_day<Tz: chrono::TimeZone>(msg: &ChatMessage, tz: &Tz) -> Option<chrono::NaiveDate> {
+    if msg.sent_at_ms <= 0 {
+        return None;
+    }
+    tz.timestamp_millis_opt(msg.sent_at_ms).single().map(|t| t.date_naive())
+}
+
+/// Where day headers go: the index of the first message of each new day, with that day
+pub fn day_breaks<Tz: chrono::TimeZone>(messages: &[ChatMessage], tz: &Tz) -> Vec<(usize, chrono::NaiveDate)> {
+    let mut breaks = Vec::new();
+    let mut current = None;
+    for (idx, msg) in messages.iter().enumerate() {
+        if let Some(day) = message_day(msg, tz) {
+            if current != Some(day) {
+                breaks.push((idx, day));
+                current = Some(day);
+            }
+        }
+    }
+    breaks
+}
+
+/// Header text for a day group: "Today", "Yesterday", the weekday within the
+/// last week, otherwise the full date
+pub fn day_header(day: chrono::NaiveDate, today: chrono::NaiveDate) -> String {
+    match (today - day).num_days() {
+        0 => "Today".to_string(),
+        1 => "Yesterday".to_string(),
+        2..=6 => day.format("%A").to_string(),
+        _ => day.format("%d %b %Y").to_string(),
+    }
+}
+
+/// Move our message `message_id` to `status` (if that's progress).
+/// Returns whether anything changed.
+pub fn update_delivery_status(conv: &mut Conversation, message_id: &str, status: DeliveryStatus) -> bool {
+    match conv.messages.iter_mut().find(|m| m.is_mine && m.message_id.as_deref() == Some(message_id)) {
+        Some(msg) if msg.status.can_become(status) => {
+            msg.status = status;
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Mark our messages up to `through_ms` as read
+pub fn mark_read_through(conv: &mut Conversation, through_ms: i64) -> bool {
+    let mut changed = false;
+    for msg in conv.messages.iter_mut().filter(|m| m.is_mine && has_read(through_ms, m)) {
+        if msg.status.can_become(DeliveryStatus::Read) {
+            msg.status = DeliveryStatus::Read;
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// Sends still pending when the app closed never got a result; they didn't go out
+pub fn fail_interrupted_sends(conversations: &mut HashMap<String, Conversation>) {
+    for msg in conversations.values_mut().flat_map(|c| c.messages.iter_mut()) {
+        if msg.status == DeliveryStatus::Pending {
+            msg.status = DeliveryStatus::Failed;
+        }
+    }
+}
+
+/// Fold `arrived`, what came in for a chat while its stored history was
+/// still loading, into the loaded `conv`. Returns how many messages were new.
+pub fn merge_arrived(conv: &mut Conversation, arrived: Conversation) -> usize {
+    let known: std::collections::HashSet<Uuid> = conv.messages.iter().map(|m| m.id).collect();
+    let mut added = 0;
+    for msg in arrived.messages.into_iter().filter(|m| !known.contains(&m.id)) {
+        conv.note_message_id(msg.id);
+        conv.messages.push(msg);
+        added += 1;
+    }
+    conv.unread_count += arrived.unread_count.min(added);
+    conv.last_activity = conv.last_activity.max(arrived.last_activity);
+    if arrived.peer_address.is_some() {
+        conv.peer_address = arrived.peer_address;
+    }
+    for (reader, through_ms) in arrived.read_through_ms {
+        let entry = conv.read_through_ms.entry(reader).or_insert(through_ms);
+        *entry = (*entry).max(through_ms);
+    }
+    added
+}
+
+/// Record that group `member` acknowledged our message `message_id`.
+/// Returns whether anything changed.
+pub fn record_member_delivery(conv: &mut Conversation, message_id: &str, member: &str) -> bool {
+    let Some(msg) = conv.messages.iter_mut().find(|m| m.is_mine && m.message_id.as_deref() == Some(message_id)) else {
+        return false;
+    };
+    if msg.delivered_to.iter().any(|m| m == member) {
+        return false;
+    }
+    msg.delivered_to.push(member.to_string());
+    if msg.status.can_become(DeliveryStatus::Delivered) {
+        msg.status = DeliveryStatus::Delivered;
+    }
+    true
+}
+
+/// Where one group member is with one of our messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberReceipt {
+    /// Not acknowledged yet (e.g. the member hasn't come online)
+    Pending,
+    Delivered,
+    Read,
+}
+
+/// Per-member state of `msg` for the given `members` (everyone in the group
+/// except us)
+pub fn member_receipts<'a>(conv: &Conversation, msg: &ChatMessage, members: &[&'a str]) -> Vec<(&'a str, MemberReceipt)> {
+    members.iter().map(|&member| {
+        let read = conv.read_through_ms.get(member).is_some_and(|&through_ms| has_read(through_ms, msg));
+        let receipt = if read {
+            MemberReceipt::Read
+        } else if msg.delivered_to.iter().any(|m| m == member) {
+            MemberReceipt::Delivered
+        } else {
+            MemberReceipt::Pending
+        };
+        (member, receipt)
+    }).collect()
+}
+
+/// Summary shown on our group bubbles: "seen by N/M", else "delivered to N/M".
+/// None until at least one member has received it.
+pub fn group_receipt_summary(receipts: &[(&str, MemberReceipt)]) -> Option<String> {
+    let total = receipts.len();
+    let seen = receipts.iter().filter(|(_, r)| *r == MemberReceipt::Read).count();
+    let delivered = receipts.iter().filter(|(_, r)| *r != MemberReceipt::Pending).count();
+    if seen > 0 {
+        Some(format!("seen by {}/{}", seen, total))
+    } else if delivered > 0 {
+        Some(format!("delivered to {}/{}", delivered, total))
+    } else {
+        None
+    }
+}
+
+/// How many peers have read `msg`
+pub fn read_by_count(conv: &Conversation, msg: &ChatMessage) -> usize {
+    conv.read_through_ms.values().filter(|&&through_ms| has_read(through_ms, msg)).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn two_conversations() -> HashMap<String, Conversation> {
+        let mut map = HashMap::new();
+        map.insert("alice".to_string(), Conversation::new("alice".to_string(), "Alice".to_string(), None));
+        map.insert("bob".to_string(), Conversation::new("bob".to_string(), "Bob".to_string(), None));
+        map
+    }
+
+    #[test]
+    fn draft_saved_on_switch_and_restored_on_return() {
+        let mut convs = two_conversations();
+        let mut input = "half-written".to_string();
+
+        switch_drafts(&mut convs, Some("alice"), "bob", &mut input);
+        assert_eq!(convs["alice"].input_draft, "half-written");
+        assert!(input.is_empty());
+
+        input.push_str("reply to bob");
+        switch_drafts(&mut convs, Some("bob"), "alice", &mut input);
+        assert_eq!(input, "half-written");
+        assert_eq!(convs["bob"].input_draft, "reply to bob");
+    }
+
+    #[test]
+    fn empty_draft_clears_previous_draft() {
+        let mut convs = two_conversations();
+        convs.get_mut("alice").unwrap().input_draft = "stale".to_string();
+        let mut input = String::new();
+
+        switch_drafts(&mut convs, Some("alice"), "bob", &mut input);
+        assert!(convs["alice"].input_draft.is_empty());
+
+        switch_drafts(&mut convs, Some("bob"), "alice", &mut input);
+        assert!(input.is_empty());
+    }
+
+    #[test]
+    fn switching_from_nothing_loads_target_draft() {
+        let mut convs = two_conversations();
+        convs.get_mut("bob").unwrap().input_draft = "saved".to_string();
+        let mut input = "ignored".to_string();
+
+        switch_drafts(&mut convs, None, "bob", &mut input);
+        assert_eq!(input, "saved");
+    }
+
+    fn message(content: &str, expires_at: Option<&str>) -> ChatMessage {
+        ChatMessage {
+            sender_name: "Alice".to_string(),
+            content: content.to_string(),
+            is_mine: false,
+            timestamp: "12:00".to_string(),
+            sent_at_ms: 0,
+            image_data: None,
+            image_filename: None,
+            reactions: Vec::new(),
+            emotes: HashMap::new(),
+            expires_at: expires_at.map(str::to_string),
+            message_id: None,
+            status: DeliveryStatus::Sent,
+            delivered_to: Vec::new(),
+            forwarded: false,
+            voice: None,
+            id: Uuid::new_v4(),
         }
     }
+
+    fn at(rfc3339: &str) -> chrono::DateTime<chrono::Utc> {
+        chrono::DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&chrono::Utc)
+    }
+
+    #[test]
+    fn stamps_expiry_from_timer() {
+        let now = at("2024-01-01T12:00:00Z");
+        let mut msg = message("hi", None);
+        stamp_expiry(&mut msg, Some(3600), now);
+        assert_eq!(at(msg.expires_at.as_deref().unwrap()), at("2024-01-01T13:00:00Z"));
+
+        let mut plain = message("hi", None);
+        stamp_expiry(&mut plain, None, now);
+        assert!(plain.expires_at.is_none());
+    }
+
+    #[test]
+    fn sender_expiry_is_not_overwritten() {
+        let mut msg = message("hi", Some("2024-01-01T12:05:00Z"));
+        stamp_expiry(&mut msg, Some(3600), at("2024-01-01T12:00:00Z"));
+        assert_eq!(msg.expires_at.as_deref(), Some("2024-01-01T12:05:00Z"));
+    }
+
+    #[test]
+    fn sweep_removes_only_expired_messages() {
+        let mut convs = two_conversations();
+        let alice = convs.get_mut("alice").unwrap();
+        alice.messages.push(message("gone", Some("2024-01-01T11:59:00Z")));
+        alice.messages.push(message("kept", Some("2024-01-01T12:01:00Z")));
+        alice.messages.push(message("forever", None));
+        convs.get_mut("bob").unwrap().messages.push(message("also gone", Some("2024-01-01T12:00:00Z")));
+
+        let removed = sweep_expired(&mut convs, at("2024-01-01T12:00:00Z"));
+        assert_eq!(removed, 2);
+        let left: Vec<&str> = convs["alice"].messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(left, vec!["kept", "forever"]);
+        assert!(convs["bob"].messages.is_empty());
+
+        // Nothing left to expire
+        assert_eq!(sweep_expired(&mut convs, at("2024-01-01T12:00:30Z")), 0);
+    }
+
+    #[test]
+    fn duplicate_ids_are_dropped_but_same_content_is_not() {
+        let mut conv = Conversation::new("alice".to_string(), "Alice".to_string(), None);
+        let original = message("see you at 8", None);
+        let resent = original.clone();
+        let new_same_text = message("see you at 8", None);
+
+        assert!(conv.note_message_id(original.id));
+        assert!(!conv.note_message_id(resent.id));
+        assert!(conv.note_message_id(new_same_text.id));
+
+        // Only the most recent ids are kept
+        for _ in 0..MAX_RECENT_IDS {
+            conv.note_message_id(Uuid::new_v4());
+        }
+        assert_eq!(conv.recent_ids.len(), MAX_RECENT_IDS);
+        assert!(conv.note_message_id(original.id));
+    }
+
+    #[test]
+    fn expired_unread_messages_stop_counting() {
+        let mut convs = two_conversations();
+        let alice = convs.get_mut("alice").unwrap();
+        alice.messages.push(message("read long ago", Some("2024-01-01T11:00:00Z")));
+        alice.messages.push(message("unread, gone", Some("2024-01-01T11:59:00Z")));
+        alice.messages.push(message("unread, kept", None));
+        alice.unread_count = 2;
+
+        assert_eq!(sweep_expired(&mut convs, at("2024-01-01T12:00:00Z")), 2);
+        let alice = &convs["alice"];
+        assert_eq!(alice.unread_count, 1);
+        assert_eq!(first_unread_index(alice), Some(0));
+        assert_eq!(alice.messages[0].content, "unread, kept");
+    }
+
+    #[test]
+    fn timer_options_cycle_back_to_off() {
+        let mut timer = None;
+        for _ in 0..DISAPPEARING_TIMER_OPTIONS.len() {
+            timer = next_disappearing_timer(timer);
+        }
+        assert_eq!(timer, None);
+        assert_eq!(disappearing_timer_label(Some(3600)), "1h");
+        assert_eq!(disappearing_timer_label(Some(300)), "5m");
+    }
+
+    #[test]
+    fn read_position_only_moves_forward() {
+        let mut convs = two_conversations();
+        let alice = convs.get_mut("alice").unwrap();
+        for i in 0..3 {
+            alice.messages.push(ChatMessage { sent_at_ms: (i + 1) * 1_000, ..message(&format!("m{}", i), None) });
+        }
+        assert_eq!(read_position(alice), Some(alice.messages[2].id));
+
+        let through_m1 = read_through_ms(alice, alice.messages[1].id).unwrap();
+        assert!(record_read(alice, "alice-fp", through_m1));
+        let through_m0 = read_through_ms(alice, alice.messages[0].id).unwrap();
+        assert!(!record_read(alice, "alice-fp", through_m0));
+        assert_eq!(alice.read_through_ms["alice-fp"], 2_000);
+        assert_eq!(read_by_count(alice, &alice.messages[1]), 1);
+        assert_eq!(read_by_count(alice, &alice.messages[2]), 0);
+
+        // Our history losing a message doesn't shift what they've read
+        alice.messages.remove(0);
+        assert_eq!(read_by_count(alice, &alice.messages[0]), 1);
+        assert_eq!(read_by_count(alice, &alice.messages[1]), 0);
+        // Nor does them reporting a message we don't have
+        assert_eq!(read_through_ms(alice, Uuid::new_v4()), None);
+    }
+
+    #[test]
+    fn first_unread_skips_our_own_replies() {
+        let mut conv = Conversation::new("alice".to_string(), "Alice".to_string(), None);
+        assert_eq!(first_unread_index(&conv), None);
+        conv.messages.push(message("read earlier", None));
+        conv.messages.push(message("new 1", None));
+        conv.messages.push(outgoing("mine"));
+        conv.messages.push(message("new 2", None));
+
+        conv.unread_count = 2;
+        assert_eq!(first_unread_index(&conv), Some(1));
+        conv.unread_count = 1;
+        assert_eq!(first_unread_index(&conv), Some(3));
+        // More unread than we have (messages expired since): start at the top
+        conv.unread_count = 10;
+        assert_eq!(first_unread_index(&conv), Some(0));
+    }
+
+    #[test]
+    fn mark_all_read_clears_every_badge() {
+        let mut convs = two_conversations();
+        convs.insert("carol".to_string(), Conversation::new("carol".to_string(), "Carol".to_string(), None));
+        convs.get_mut("alice").unwrap().unread_count = 3;
+        convs.get_mut("carol").unwrap().unread_count = 1;
+
+        let mut cleared = mark_all_read(&mut convs);
+        cleared.sort();
+        assert_eq!(cleared, vec!["alice".to_string(), "carol".to_string()]);
+        assert!(convs.values().all(|c| c.unread_count == 0));
+        assert!(mark_all_read(&mut convs).is_empty());
+    }
+
+    #[test]
+    fn group_read_positions_aggregate_per_member() {
+        let mut group = Conversation::new("group-1".to_string(), "Group".to_string(), None);
+        for i in 0..4 {
+            group.messages.push(ChatMessage { sent_at_ms: (i + 1) * 1_000, ..message(&format!("m{}", i), None) });
+        }
+        record_read(&mut group, "alice", 4_000);
+        record_read(&mut group, "bob", 2_000);
+        record_read(&mut group, "carol", 1_000);
+
+        assert_eq!(read_by_count(&group, &group.messages[0]), 3);
+        assert_eq!(read_by_count(&group, &group.messages[1]), 2);
+        assert_eq!(read_by_count(&group, &group.messages[3]), 1);
+    }
+
+    #[test]
+    fn draft_survives_serialization() {
+        let mut conv = Conversation::new("alice".to_string(), "Alice".to_string(), None);
+        conv.input_draft = "persist me".to_string();
+        let json = serde_json::to_string(&conv).unwrap();
+        let restored: Conversation = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.input_draft, "persist me");
+    }
+
+    fn outgoing(id: &str) -> ChatMessage {
+        ChatMessage {
+            is_mine: true,
+            message_id: Some(id.to_string()),
+            status: DeliveryStatus::Pending,
+            ..message(id, None)
+        }
+    }
+
+    #[test]
+    fn delivery_status_follows_send_ack_and_read_events() {
+        let mut conv = Conversation::new("bob".to_string(), "Bob".to_string(), None);
+        conv.messages.push(ChatMessage { sent_at_ms: 1_000, ..outgoing("m1") });
+        conv.messages.push(ChatMessage { sent_at_ms: 2_000, ..message("reply", None) });
+        conv.messages.push(ChatMessage { sent_at_ms: 3_000, ..outgoing("m2") });
+        conv.messages.push(ChatMessage { sent_at_ms: 4_000, ..outgoing("m3") });
+
+        // Send result, then the peer's delivery ack
+        assert!(update_delivery_status(&mut conv, "m1", DeliveryStatus::Sent));
+        assert!(update_delivery_status(&mut conv, "m1", DeliveryStatus::Delivered));
+        assert!(update_delivery_status(&mut conv, "m2", DeliveryStatus::Failed));
+        assert!(update_delivery_status(&mut conv, "m3", DeliveryStatus::Delivered));
+
+        // Peer opens the chat having read up to m2: only our delivered message
+        // before that becomes read
+        assert!(mark_read_through(&mut conv, 3_000));
+        assert_eq!(conv.messages[0].status, DeliveryStatus::Read);
+        assert_eq!(conv.messages[1].status, DeliveryStatus::Sent);
+        assert_eq!(conv.messages[2].status, DeliveryStatus::Failed);
+        assert_eq!(conv.messages[3].status, DeliveryStatus::Delivered);
+
+        // A late ack can't move a read message backwards
+        assert!(!update_delivery_status(&mut conv, "m1", DeliveryStatus::Delivered));
+        assert!(!update_delivery_status(&mut conv, "unknown", DeliveryStatus::Sent));
+    }
+
+    #[test]
+    fn pending_sends_fail_after_restart() {
+        let mut convs = HashMap::new();
+        let mut conv = Conversation::new("bob".to_string(), "Bob".to_string(), None);
+        conv.messages.push(outgoing("m1"));
+        convs.insert("bob".to_string(), conv);
+
+        let json = serde_json::to_string(&convs).unwrap();
+        let mut restored: HashMap<String, Conversation> = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored["bob"].messages[0].status, DeliveryStatus::Pending);
+
+        fail_interrupted_sends(&mut restored);
+        assert_eq!(restored["bob"].messages[0].status, DeliveryStatus::Failed);
+    }
+
+    #[test]
+    fn muted_chats_only_notify_on_mentions() {
+        let mut conv = Conversation::new("group".to_string(), "Group".to_string(), None);
+        assert!(should_notify(Some(&conv), false));
+
+        conv.settings.muted.set(true, 1);
+        assert!(!should_notify(Some(&conv), false));
+        assert!(should_notify(Some(&conv), true));
+
+        // First message from a new chat: nothing muted yet
+        assert!(should_notify(None, false));
+    }
+
+    #[test]
+    fn group_receipts_track_each_member() {
+        let mut conv = Conversation::new("group-1".to_string(), "Group".to_string(), None);
+        conv.messages.push(ChatMessage { sent_at_ms: 1_000, ..outgoing("m1") });
+        conv.messages.push(ChatMessage { sent_at_ms: 2_000, ..outgoing("m2") });
+        let members = ["FP_ALICE", "FP_BOB", "FP_CAROL"];
+
+        assert!(record_member_delivery(&mut conv, "m1", "FP_ALICE"));
+        assert!(!record_member_delivery(&mut conv, "m1", "FP_ALICE"));
+        assert!(record_member_delivery(&mut conv, "m1", "FP_BOB"));
+        assert!(!record_member_delivery(&mut conv, "unknown", "FP_BOB"));
+        assert_eq!(conv.messages[0].status, DeliveryStatus::Delivered);
+        // Bob read the first message only
+        record_read(&mut conv, "FP_BOB", 1_000);
+
+        assert_eq!(
+            member_receipts(&conv, &conv.messages[0], &members),
+            [("FP_ALICE", MemberReceipt::Delivered), ("FP_BOB", MemberReceipt::Read), ("FP_CAROL", MemberReceipt::Pending)]
+        );
+        // Carol never came online: she stays pending on everything
+        assert_eq!(member_receipts(&conv, &conv.messages[1], &members).iter().filter(|(_, r)| *r == MemberReceipt::Pending).count(), 3);
+    }
+
+    #[test]
+    fn group_receipt_summary_prefers_seen_count() {
+        use MemberReceipt::*;
+        assert_eq!(group_receipt_summary(&[("a", Read), ("b", Delivered), ("c", Pending)]).as_deref(), Some("seen by 1/3"));
+        assert_eq!(group_receipt_summary(&[("a", Delivered), ("b", Delivered), ("c", Pending)]).as_deref(), Some("delivered to 2/3"));
+        assert_eq!(group_receipt_summary(&[("a", Pending), ("b", Pending)]), None);
+        assert_eq!(group_receipt_summary(&[]), None);
+    }
+
+    #[test]
+    fn group_typing_label_names_members() {
+        let mut conv = Conversation::new("group-1".to_string(), "Group".to_string(), None);
+        set_member_typing(&mut conv, "FP_BOB", "Bob", true, 1_000);
+        set_member_typing(&mut conv, "FP_ALICE", "Alice", true, 2_000);
+        let names: Vec<&str> = conv.typing_members.values().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(typing_label(&names).as_deref(), Some("Alice and Bob are typing"));
+
+        set_member_typing(&mut conv, "FP_BOB", "Bob", false, 2_500);
+        let names: Vec<&str> = conv.typing_members.values().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(typing_label(&names).as_deref(), Some("Alice is typing"));
+
+        assert_eq!(typing_label(&["Dan", "Carol", "Alice", "Bob"]).as_deref(), Some("Alice, Bob and 2 others are typing"));
+        assert_eq!(typing_label(&["Carol", "Alice", "Bob"]).as_deref(), Some("Alice, Bob and Carol are typing"));
+        assert_eq!(typing_label(&[]), None);
+    }
+
+    #[test]
+    fn stale_group_typing_times_out() {
+        let mut convs = HashMap::new();
+        let mut conv = Conversation::new("group-1".to_string(), "Group".to_string(), None);
+        set_member_typing(&mut conv, "FP_ALICE", "Alice", true, 0);
+        set_member_typing(&mut conv, "FP_BOB", "Bob", true, 4_000);
+        convs.insert("group-1".to_string(), conv);
+
+        assert!(!clear_stale_typing(&mut convs, TYPING_TIMEOUT_MS - 1));
+        assert!(clear_stale_typing(&mut convs, TYPING_TIMEOUT_MS));
+        assert_eq!(convs["group-1"].typing_members.keys().collect::<Vec<_>>(), ["FP_BOB"]);
+    }
+
+    #[test]
+    fn group_reactions_route_to_group_and_toggle() {
+        let mut convs = HashMap::new();
+        let mut group = Conversation::new("group-1".to_string(), "Group".to_string(), None);
+        group.messages.push(message("hello all", None));
+        let stamp = group.messages[0].timestamp.clone();
+        convs.insert("group-1".to_string(), group);
+        convs.insert("FP_ALICE".to_string(), Conversation::new("FP_ALICE".to_string(), "alice".to_string(), None));
+
+        assert!(apply_reaction(&mut convs, Some("group-1"), "FP_ALICE", None, &stamp, "👍", "alice"));
+        assert!(apply_reaction(&mut convs, Some("group-1"), "FP_BOB", None, &stamp, "👍", "bob"));
+        assert!(apply_reaction(&mut convs, Some("group-1"), "FP_BOB", None, &stamp, "🔥", "bob"));
+        assert_eq!(reaction_counts(&convs["group-1"].messages[0]), [("👍", 2), ("🔥", 1)]);
+
+        // Reacting again toggles it off
+        assert!(apply_reaction(&mut convs, Some("group-1"), "FP_ALICE", None, &stamp, "👍", "alice"));
+        assert_eq!(reaction_counts(&convs["group-1"].messages[0]), [("👍", 1), ("🔥", 1)]);
+
+        // Unknown message or group: ignored
+        assert!(!apply_reaction(&mut convs, Some("group-1"), "FP_ALICE", None, "never", "👍", "alice"));
+        assert!(!apply_reaction(&mut convs, Some("group-2"), "FP_ALICE", None, &stamp, "👍", "alice"));
+        assert!(!apply_reaction(&mut convs, None, "FP_ALICE", None, &stamp, "👍", "alice"));
+    }
+
+    #[test]
+    fn reactor_summary_groups_names_by_emoji() {
+        let mut msg = message("party", None);
+        for (emoji, sender) in [("👍", "alice"), ("🔥", "bob"), ("👍", "bob"), ("👍", "alice")] {
+            msg.reactions.push((emoji.to_string(), sender.to_string()));
+        }
+        assert_eq!(reactors_by_emoji(&msg), [("👍", vec!["alice", "bob"]), ("🔥", vec!["bob"])]);
+        assert_eq!(reactor_summary(&msg), "👍 alice, bob\n🔥 bob");
+
+        let mut crowded = message("popular", None);
+        for i in 0..MAX_REACTORS_SHOWN + 3 {
+            crowded.reactions.push(("❤️".to_string(), format!("user{}", i)));
+        }
+        assert_eq!(reactor_summary(&crowded), "❤️ user0, user1, user2, user3, user4 +3 more");
+        assert_eq!(reactor_summary(&message("quiet", None)), "");
+    }
+
+    #[test]
+    fn reactions_find_same_minute_messages_by_id() {
+        let mut conv = Conversation::new("FP_BOB".to_string(), "bob".to_string(), None);
+        let shared = "6f1c5a3e-2b7d-4c1e-9a0f-3d2e1b4c5a6f";
+        let mut second = message("second", None);
+        second.id = message_uuid(Some(shared));
+        conv.messages.push(message("first", None));
+        conv.messages.push(second);
+        let stamp = conv.messages[0].timestamp.clone();
+        assert_eq!(stamp, conv.messages[1].timestamp);
+
+        assert!(toggle_reaction(&mut conv, Some(Uuid::parse_str(shared).unwrap()), &stamp, "👍", "bob"));
+        assert!(conv.messages[0].reactions.is_empty());
+        assert_eq!(conv.messages[1].reactions, [("👍".to_string(), "bob".to_string())]);
+
+        // An old peer without ids still lands on the first match
+        assert!(toggle_reaction(&mut conv, None, &stamp, "🔥", "bob"));
+        assert_eq!(conv.messages[0].reactions.len(), 1);
+
+        // An id we don't have matches nothing, whatever the timestamp
+        assert!(!toggle_reaction(&mut conv, Some(Uuid::new_v4()), &stamp, "😮", "bob"));
+        assert!(message_uuid(Some("not-a-uuid")) != message_uuid(Some("not-a-uuid")));
+    }
+
+    #[test]
+    fn legacy_history_gets_the_same_ids_on_every_load() {
+        let mut conv = Conversation::new("FP_BOB".to_string(), "bob".to_string(), None);
+        let shared = "6f1c5a3e-2b7d-4c1e-9a0f-3d2e1b4c5a6f";
+        let mut ours = message("hi", None);
+        ours.message_id = Some(shared.to_string());
+        // Two identical messages from the same minute
+        conv.messages.extend([ours, message("hi", None), message("hi", None)]);
+        let mut stored = serde_json::to_value(&conv).unwrap();
+        for msg in stored["messages"].as_array_mut().unwrap() {
+            msg.as_object_mut().unwrap().remove("id");
+        }
+
+        let load = || {
+            let conv: Conversation = serde_json::from_value(stored.clone()).unwrap();
+            let mut convs = HashMap::from([("FP_BOB".to_string(), conv)]);
+            assign_legacy_ids(&mut convs);
+            convs.remove("FP_BOB").unwrap().messages.into_iter().map(|m| m.id).collect::<Vec<_>>()
+        };
+        let ids = load();
+        assert_eq!(ids, load());
+        assert_eq!(ids[0], Uuid::parse_str(shared).unwrap());
+        assert_ne!(ids[1], ids[2]);
+        assert!(ids.iter().all(|id| !id.is_nil()));
+    }
+
+    fn sent_at(content: &str, rfc3339: &str) -> ChatMessage {
+        ChatMessage {
+            sent_at_ms: at(rfc3339).timestamp_millis(),
+            ..message(content, None)
+        }
+    }
+
+    #[test]
+    fn day_breaks_start_each_new_day() {
+        let messages = vec![
+            message("old history", None),
+            sent_at("a", "2024-03-01T09:00:00Z"),
+            sent_at("b", "2024-03-01T23:59:00Z"),
+            sent_at("c", "2024-03-02T00:01:00Z"),
+            sent_at("d", "2024-03-05T12:00:00Z"),
+        ];
+        let breaks: Vec<(usize, String)> = day_breaks(&messages, &chrono::Utc)
+            .into_iter()
+            .map(|(idx, day)| (idx, day.to_string()))
+            .collect();
+        assert_eq!(breaks, vec![
+            (1, "2024-03-01".to_string()),
+            (3, "2024-03-02".to_string()),
+            (4, "2024-03-05".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn day_header_is_relative_to_today() {
+        let today = chrono::NaiveDate::from_ymd_opt(2024, 3, 10).unwrap();
+        let day = |d| chrono::NaiveDate::from_ymd_opt(2024, 3, d).unwrap();
+        assert_eq!(day_header(day(10), today), "Today");
+        assert_eq!(day_header(day(9), today), "Yesterday");
+        assert_eq!(day_header(day(5), today), "Tuesday");
+        assert_eq!(day_header(day(1), today), "01 Mar 2024");
+    }
+
+    #[test]
+    fn copy_text_uses_filename_for_images_and_keeps_newlines() {
+        let text = message("first line\nsecond line", None);
+        assert_eq!(copy_text(&text), "first line\nsecond line");
+
+        let image = ChatMessage {
+            content: "[Image: cat.png]".to_string(),
+            image_data: Some(vec![1, 2, 3]),
+            image_filename: Some("cat.png".to_string()),
+            ..message("", None)
+        };
+        assert_eq!(copy_text(&image), "cat.png");
+    }
+
+    #[test]
+    fn messages_that_arrived_while_loading_go_on_top_of_the_history() {
+        let mut loaded = Conversation::new("bob".to_string(), "Bob".to_string(), None);
+        let old = message("old", None);
+        loaded.messages.push(old.clone());
+        loaded.last_activity = 10;
+
+        let mut arrived = Conversation::new("bob".to_string(), "Bob".to_string(), Some("10.0.0.2:9000".to_string()));
+        // A resend of something already in the history, and a new message
+        arrived.messages.push(old);
+        arrived.messages.push(message("new", None));
+        arrived.unread_count = 2;
+        arrived.last_activity = 20;
+        arrived.read_through_ms.insert("bob".to_string(), 5);
+
+        assert_eq!(merge_arrived(&mut loaded, arrived), 1);
+        let contents: Vec<&str> = loaded.messages.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, vec!["old", "new"]);
+        assert_eq!(loaded.unread_count, 1);
+        assert_eq!(loaded.last_activity, 20);
+        assert_eq!(loaded.peer_address.as_deref(), Some("10.0.0.2:9000"));
+        assert_eq!(loaded.read_through_ms.get("bob"), Some(&5));
+    }
 }
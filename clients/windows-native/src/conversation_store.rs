@@ -1,41 +1,649 @@
-use crate::conversation::Conversation;
+use crate::conversation::{ChatMessage, Conversation};
 use crate::encrypted_storage::{derive_storage_key, encrypt_data, decrypt_data, EncryptedStore};
-use crate::request_store::get_data_dir;
+use crate::keystore;
+use crate::paths::data_dir;
 use anyhow::{Context, Result};
-use std::collections::HashMap;
+use cryptochat_crypto_core::pgp::PgpKeyPair;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroize;
 
 fn get_conversations_path(fingerprint: &str) -> Result<PathBuf> {
     // Encrypted file extension .enc, specific to this user fingerprint
-    Ok(get_data_dir()?.join(format!("conversations_{}.enc", fingerprint)))
+    Ok(data_dir()?.join(format!("conversations_{}.enc", fingerprint)))
 }
 
-/// Save all conversations to encrypted disk storage
-pub fn save_conversations(conversations: &HashMap<String, Conversation>, fingerprint: &str) -> Result<()> {
-    let key = derive_storage_key(fingerprint);
-    let encrypted = encrypt_data(conversations, &key)?;
-    
-    let path = get_conversations_path(fingerprint)?;
+fn get_index_path(fingerprint: &str) -> Result<PathBuf> {
+    Ok(data_dir()?.join(format!("conversation_index_{}.enc", fingerprint)))
+}
+
+/// Longest last-message preview kept in the index, in characters
+const PREVIEW_CHARS: usize = 60;
+
+/// What the sidebar shows for one conversation. Kept in a small index file
+/// next to the history so the chat list can be drawn and sorted without
+/// deserializing every message.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversationSummary {
+    pub id: String,
+    pub name: String,
+    pub last_message_preview: String,
+    pub last_activity: u64,
+    pub unread_count: usize,
+    pub muted: bool,
+    pub pinned: bool,
+    pub archived: bool,
+}
+
+/// Summarize a conversation for the index
+pub fn summarize(conv: &Conversation) -> ConversationSummary {
+    let preview = conv.messages.last().map(preview_text).unwrap_or_default();
+    ConversationSummary {
+        id: conv.id.clone(),
+        name: conv.name.clone(),
+        last_message_preview: truncate_chars(&preview, PREVIEW_CHARS),
+        last_activity: conv.last_activity,
+        unread_count: conv.unread_count,
+        muted: conv.settings.muted.value,
+        pinned: conv.settings.pinned.value,
+        archived: conv.settings.archived.value,
+    }
+}
+
+fn preview_text(msg: &ChatMessage) -> String {
+    if msg.voice.is_some() {
+        return "[Voice message]".to_string();
+    }
+    let text = transcript_content(msg);
+    text.lines().next().unwrap_or_default().to_string()
+}
+
+fn truncate_chars(text: &str, max: usize) -> String {
+    match text.char_indices().nth(max) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// Index entries for every conversation
+pub fn build_index(conversations: &HashMap<String, Conversation>) -> HashMap<String, ConversationSummary> {
+    conversations.iter().map(|(id, conv)| (id.clone(), summarize(conv))).collect()
+}
+
+/// Index entries in sidebar order: pinned first, then most recent activity.
+/// Archived chats are left out unless `show_archived` is set.
+pub fn sidebar_order(index: &HashMap<String, ConversationSummary>, show_archived: bool) -> Vec<&ConversationSummary> {
+    let mut entries: Vec<&ConversationSummary> = index.values().filter(|c| show_archived || !c.archived).collect();
+    entries.sort_by(|a, b| b.pinned.cmp(&a.pinned).then(b.last_activity.cmp(&a.last_activity)));
+    entries
+}
+
+fn save_index_to(path: &Path, index: &HashMap<String, ConversationSummary>, key: &[u8; 32]) -> Result<()> {
+    let encrypted = encrypt_data(index, key)?;
+    fs::write(path, serde_json::to_vec(&encrypted)?).context("Failed to write conversation index")?;
+    Ok(())
+}
+
+fn load_index_from(path: &Path, key: &[u8; 32]) -> Result<HashMap<String, ConversationSummary>> {
+    let json = fs::read(path).context("Failed to read conversation index")?;
+    let encrypted: EncryptedStore = serde_json::from_slice(&json).context("Failed to parse encrypted store")?;
+    decrypt_data(&encrypted, key)
+}
+
+/// Load the sidebar index. It is rebuilt from the history on every save, so
+/// a missing or unreadable index is an error for the caller to rebuild from,
+/// not something to recover.
+pub fn load_index(fingerprint: &str) -> Result<HashMap<String, ConversationSummary>> {
+    load_index_from(&get_index_path(fingerprint)?, &derive_storage_key(fingerprint))
+}
+
+/// Saved conversations plus a per-conversation hash chain over their messages
+#[derive(Serialize, Deserialize)]
+struct ChainedConversations<C> {
+    conversations: C,
+    chains: HashMap<String, ChainHead>,
+    /// Bumped on every save. The keystore keeps the latest value, so an
+    /// older copy of the file put back in place is caught.
+    #[serde(default)]
+    generation: u64,
+    /// MAC over the generation and every chain head. Files saved before the
+    /// history MAC key existed have none; their chains use the storage key.
+    #[serde(default)]
+    seal: Option<[u8; 32]>,
+}
+
+/// Files written before the integrity chain were a bare map
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredConversations {
+    Chained(ChainedConversations<HashMap<String, Conversation>>),
+    Legacy(HashMap<String, Conversation>),
+}
+
+/// End of a conversation's chain: how many messages it covers and the final MAC
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ChainHead {
+    count: usize,
+    head: [u8; 32],
+}
+
+/// History on disk no longer matches what the app last saved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IntegrityError {
+    /// A message was edited, inserted or reordered, or the conversation was
+    /// added outside the app
+    Modified { conversation_id: String },
+    /// Messages were removed from the end of the conversation
+    Truncated { conversation_id: String, expected: usize, found: usize },
+    /// A whole conversation was removed
+    Missing { conversation_id: String },
+    /// The file as a whole was altered, or saved under another account
+    Tampered,
+    /// An older copy of the history was put back in place of the latest save
+    RolledBack { expected: u64, found: u64 },
+}
+
+impl std::fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IntegrityError::Modified { conversation_id } => {
+                write!(f, "Conversation {} was modified outside CryptoChat", conversation_id)
+            }
+            IntegrityError::Truncated { conversation_id, expected, found } => write!(
+                f,
+                "Conversation {} was truncated: expected {} messages, found {}",
+                conversation_id, expected, found
+            ),
+            IntegrityError::Missing { conversation_id } => {
+                write!(f, "Conversation {} was removed outside CryptoChat", conversation_id)
+            }
+            IntegrityError::Tampered => write!(f, "The history file was modified outside CryptoChat"),
+            IntegrityError::RolledBack { expected, found } => write!(
+                f,
+                "The history was replaced with an older copy: expected save {}, found save {}",
+                expected, found
+            ),
+        }
+    }
+}
+
+impl std::error::Error for IntegrityError {}
+
+fn mac(key: &[u8], parts: &[&[u8]]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// Key for the history MACs. Derived from the account's secret key, so unlike
+/// the storage key it can't be worked out from the public fingerprint.
+pub fn history_mac_key(keypair: &PgpKeyPair) -> Result<[u8; 32]> {
+    let mut secret = keypair.export_secret_key()?;
+    let key = mac(secret.as_bytes(), &[b"cryptochat-history-mac-v1"]);
+    secret.zeroize();
+    Ok(key)
+}
+
+/// Chain the messages of one conversation: each link is an HMAC over the
+/// previous link and the next message, starting from the conversation id.
+/// Messages go through `serde_json::Value` first so map fields hash in a
+/// stable (sorted) order.
+fn chain_head(conv_id: &str, messages: &[ChatMessage], key: &[u8; 32]) -> Result<ChainHead> {
+    let mut head = mac(key, &[b"cryptochat-history-v1", conv_id.as_bytes()]);
+    for msg in messages {
+        let value = serde_json::to_value(msg).context("Failed to serialize message")?;
+        head = mac(key, &[&head, &serde_json::to_vec(&value)?]);
+    }
+    Ok(ChainHead { count: messages.len(), head })
+}
+
+/// MAC tying the save generation to the full set of chain heads
+fn seal(generation: u64, chains: &HashMap<String, ChainHead>, mac_key: &[u8; 32]) -> Result<[u8; 32]> {
+    let sorted: BTreeMap<&String, &ChainHead> = chains.iter().collect();
+    let encoded = serde_json::to_vec(&(generation, sorted))?;
+    Ok(mac(mac_key, &[b"cryptochat-history-seal-v1", &encoded]))
+}
+
+/// Check every conversation against its saved chain head
+fn verify_chains(
+    conversations: &HashMap<String, Conversation>,
+    chains: &HashMap<String, ChainHead>,
+    key: &[u8; 32],
+) -> Result<()> {
+    for (id, conv) in conversations {
+        let Some(saved) = chains.get(id) else {
+            return Err(IntegrityError::Modified { conversation_id: id.clone() }.into());
+        };
+        if conv.messages.len() < saved.count {
+            return Err(IntegrityError::Truncated {
+                conversation_id: id.clone(),
+                expected: saved.count,
+                found: conv.messages.len(),
+            }
+            .into());
+        }
+        if chain_head(id, &conv.messages, key)? != *saved {
+            return Err(IntegrityError::Modified { conversation_id: id.clone() }.into());
+        }
+    }
+    if let Some(id) = chains.keys().find(|id| !conversations.contains_key(*id)) {
+        return Err(IntegrityError::Missing { conversation_id: id.clone() }.into());
+    }
+    Ok(())
+}
+
+fn save_conversations_to(
+    path: &Path,
+    conversations: &HashMap<String, Conversation>,
+    key: &[u8; 32],
+    mac_key: &[u8; 32],
+    generation: u64,
+) -> Result<()> {
+    let chains = conversations
+        .iter()
+        .map(|(id, conv)| Ok((id.clone(), chain_head(id, &conv.messages, mac_key)?)))
+        .collect::<Result<HashMap<_, _>>>()?;
+    let seal = Some(seal(generation, &chains, mac_key)?);
+    let stored = ChainedConversations { conversations, chains, generation, seal };
+    let encrypted = encrypt_data(&stored, key)?;
+
     let json = serde_json::to_vec(&encrypted)?;
-    fs::write(&path, json).context("Failed to write conversations file")?;
-    
+    fs::write(path, json).context("Failed to write conversations file")?;
+
     Ok(())
 }
 
-/// Load conversations from encrypted disk storage
-pub fn load_conversations(fingerprint: &str) -> Result<HashMap<String, Conversation>> {
-    let path = get_conversations_path(fingerprint)?;
-    
+/// Load and check a history file. `min_generation` is the last save
+/// generation recorded outside the file; anything older is a rollback.
+fn load_conversations_from(
+    path: &Path,
+    key: &[u8; 32],
+    mac_key: &[u8; 32],
+    min_generation: u64,
+) -> Result<HashMap<String, Conversation>> {
+    let rolled_back = |found| IntegrityError::RolledBack { expected: min_generation, found };
     if !path.exists() {
+        if min_generation > 0 {
+            return Err(rolled_back(0).into());
+        }
         return Ok(HashMap::new());
     }
-    
-    let json = fs::read(&path).context("Failed to read conversations file")?;
+
+    let json = fs::read(path).context("Failed to read conversations file")?;
     let encrypted: EncryptedStore = serde_json::from_slice(&json).context("Failed to parse encrypted store")?;
-    
-    let key = derive_storage_key(fingerprint);
-    let conversations: HashMap<String, Conversation> = decrypt_data(&encrypted, &key)?;
-    
-    Ok(conversations)
+
+    match decrypt_data(&encrypted, key)? {
+        StoredConversations::Chained(stored) => {
+            match stored.seal {
+                Some(saved) => {
+                    verify_chains(&stored.conversations, &stored.chains, mac_key)?;
+                    if saved != seal(stored.generation, &stored.chains, mac_key)? {
+                        return Err(IntegrityError::Tampered.into());
+                    }
+                    if stored.generation < min_generation {
+                        return Err(rolled_back(stored.generation).into());
+                    }
+                }
+                // Chained under the storage key; sealed the next time it is saved
+                None if min_generation == 0 => verify_chains(&stored.conversations, &stored.chains, key)?,
+                None => return Err(rolled_back(0).into()),
+            }
+            Ok(stored.conversations)
+        }
+        // Gains a chain the next time it is saved
+        StoredConversations::Legacy(conversations) if min_generation == 0 => Ok(conversations),
+        StoredConversations::Legacy(_) => Err(rolled_back(0).into()),
+    }
+}
+
+/// Save all conversations to encrypted disk storage, along with their
+/// sidebar index. Returns the index that was written.
+pub fn save_conversations(
+    conversations: &HashMap<String, Conversation>,
+    keypair: &PgpKeyPair,
+) -> Result<HashMap<String, ConversationSummary>> {
+    let fingerprint = keypair.fingerprint();
+    let key = derive_storage_key(&fingerprint);
+    // Recorded after the write: a crash in between leaves the file ahead of
+    // the keystore, which still loads
+    let generation = keystore::load_history_generation(&fingerprint)? + 1;
+    let path = get_conversations_path(&fingerprint)?;
+    save_conversations_to(&path, conversations, &key, &history_mac_key(keypair)?, generation)?;
+    keystore::save_history_generation(&fingerprint, generation)?;
+    let index = build_index(conversations);
+    save_index_to(&get_index_path(&fingerprint)?, &index, &key)?;
+    Ok(index)
+}
+
+/// Load conversations from encrypted disk storage.
+///
+/// Fails with an [`IntegrityError`] if the history was edited, truncated or
+/// swapped for an older copy since the app last saved it. A file that can't
+/// be decrypted or parsed at all is moved aside and an empty history returned.
+pub fn load_conversations(keypair: &PgpKeyPair) -> Result<HashMap<String, Conversation>> {
+    let fingerprint = keypair.fingerprint();
+    let key = derive_storage_key(&fingerprint);
+    let path = get_conversations_path(&fingerprint)?;
+    let min_generation = keystore::load_history_generation(&fingerprint)?;
+    match load_conversations_from(&path, &key, &history_mac_key(keypair)?, min_generation) {
+        Ok(mut conversations) => {
+            crate::conversation::assign_legacy_ids(&mut conversations);
+            Ok(conversations)
+        }
+        // Tampering is reported to the user; see `quarantine_conversations`
+        Err(e) if e.downcast_ref::<IntegrityError>().is_none() && path.exists() => {
+            let backup = crate::store_recovery::set_aside(&path)?;
+            tracing::warn!(backup = %backup.display(), error = %e, "conversations file was corrupt; starting fresh");
+            Ok(HashMap::new())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Move a history file that failed its integrity check aside and start an
+/// empty one, so neither the next save nor the next start trips over it.
+/// Returns where it was moved, if there was a file to move.
+pub fn quarantine_conversations(keypair: &PgpKeyPair) -> Result<Option<PathBuf>> {
+    let path = get_conversations_path(&keypair.fingerprint())?;
+    let backup = path.with_extension(format!("tampered-{}.enc", chrono::Local::now().format("%Y%m%d-%H%M%S")));
+    let moved = path.exists();
+    if moved {
+        fs::rename(&path, &backup).context("Failed to move conversations file aside")?;
+    }
+    save_conversations(&HashMap::new(), keypair)?;
+    Ok(moved.then_some(backup))
+}
+
+/// Transcript formats for exporting a conversation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    PlainText,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::PlainText => "txt",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+/// Render a conversation as a transcript. Image bytes are never included;
+/// attachments are referenced by filename.
+pub fn export_transcript(conv: &Conversation, format: ExportFormat) -> String {
+    match format {
+        ExportFormat::PlainText => {
+            let mut out = format!("Conversation: {}\n\n", conv.name);
+            for msg in &conv.messages {
+                out.push_str(&format!("[{}] {}: {}\n", msg.timestamp, msg.sender_name, transcript_content(msg)));
+            }
+            out
+        }
+        ExportFormat::Json => {
+            let messages: Vec<serde_json::Value> = conv.messages.iter().map(|msg| {
+                serde_json::json!({
+                    "timestamp": msg.timestamp,
+                    "sender": msg.sender_name,
+                    "is_mine": msg.is_mine,
+                    "content": msg.content,
+                    "attachment": msg.image_filename,
+                })
+            }).collect();
+            let doc = serde_json::json!({
+                "conversation": conv.name,
+                "id": conv.id,
+                "messages": messages,
+            });
+            serde_json::to_string_pretty(&doc).unwrap_or_default()
+        }
+    }
+}
+
+fn transcript_content(msg: &ChatMessage) -> String {
+    match &msg.image_filename {
+        Some(filename) => format!("[Attachment: {}]", filename),
+        None => msg.content.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversation::DeliveryStatus;
+
+    fn sample_conversation() -> Conversation {
+        let mut conv = Conversation::new("fp1".to_string(), "Alice".to_string(), None);
+        conv.messages.push(ChatMessage {
+            sender_name: "Alice".to_string(),
+            content: "hello there".to_string(),
+            is_mine: false,
+            timestamp: "09:15".to_string(),
+            sent_at_ms: 0,
+            image_data: None,
+            image_filename: None,
+            reactions: Vec::new(),
+            emotes: HashMap::new(),
+            expires_at: None,
+            message_id: None,
+            status: DeliveryStatus::Sent,
+            delivered_to: Vec::new(),
+            forwarded: false,
+            voice: None,
+            id: uuid::Uuid::new_v4(),
+        });
+        conv.messages.push(ChatMessage {
+            sender_name: "Me".to_string(),
+            content: "[Image: cat.png]".to_string(),
+            is_mine: true,
+            timestamp: "09:16".to_string(),
+            sent_at_ms: 0,
+            image_data: Some(vec![0x89, b'P', b'N', b'G', 1, 2, 3]),
+            image_filename: Some("cat.png".to_string()),
+            reactions: Vec::new(),
+            emotes: HashMap::new(),
+            expires_at: None,
+            message_id: None,
+            status: DeliveryStatus::Sent,
+            delivered_to: Vec::new(),
+            forwarded: false,
+            voice: None,
+            id: uuid::Uuid::new_v4(),
+        });
+        conv
+    }
+
+    #[test]
+    fn plaintext_transcript_has_timestamps_and_senders() {
+        let out = export_transcript(&sample_conversation(), ExportFormat::PlainText);
+        assert_eq!(
+            out,
+            "Conversation: Alice\n\n[09:15] Alice: hello there\n[09:16] Me: [Attachment: cat.png]\n"
+        );
+    }
+
+    #[test]
+    fn json_transcript_references_images_by_filename() {
+        let out = export_transcript(&sample_conversation(), ExportFormat::Json);
+        let doc: serde_json::Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(doc["conversation"], "Alice");
+        let messages = doc["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0]["sender"], "Alice");
+        assert_eq!(messages[0]["timestamp"], "09:15");
+        assert!(messages[0]["attachment"].is_null());
+        assert_eq!(messages[1]["attachment"], "cat.png");
+        assert!(!out.contains("image_data"));
+    }
+
+    const MAC_KEY: [u8; 32] = [7; 32];
+
+    fn temp_history_path() -> PathBuf {
+        std::env::temp_dir().join(format!("cryptochat-history-{}.enc", uuid::Uuid::new_v4()))
+    }
+
+    fn sample_history() -> HashMap<String, Conversation> {
+        let conv = sample_conversation();
+        HashMap::from([(conv.id.clone(), conv)])
+    }
+
+    #[test]
+    fn saved_history_loads_cleanly() {
+        let path = temp_history_path();
+        let key = derive_storage_key("fp-me");
+        save_conversations_to(&path, &sample_history(), &key, &MAC_KEY, 1).unwrap();
+
+        let loaded = load_conversations_from(&path, &key, &MAC_KEY, 1).unwrap();
+        assert_eq!(loaded["fp1"].messages.len(), 2);
+        assert_eq!(loaded["fp1"].messages[0].content, "hello there");
+        let _ = fs::remove_file(&path);
+    }
+
+    fn message(sender: &str, content: &str) -> ChatMessage {
+        let mut msg = sample_conversation().messages.remove(0);
+        msg.sender_name = sender.to_string();
+        msg.content = content.to_string();
+        msg
+    }
+
+    #[test]
+    fn index_matches_history_after_appends() {
+        let path = temp_history_path();
+        let index_path = path.with_extension("index.enc");
+        let key = derive_storage_key("fp-me");
+        let generation = std::cell::Cell::new(0);
+        let save = |history: &HashMap<String, Conversation>| {
+            generation.set(generation.get() + 1);
+            save_conversations_to(&path, history, &key, &MAC_KEY, generation.get()).unwrap();
+            save_index_to(&index_path, &build_index(history), &key).unwrap();
+        };
+
+        let mut history = sample_history();
+        let mut bob = Conversation::new("fp2".to_string(), "Bob".to_string(), None);
+        bob.messages.push(message("Bob", "first"));
+        bob.last_activity = 100;
+        history.insert(bob.id.clone(), bob);
+        save(&history);
+
+        let bob = history.get_mut("fp2").unwrap();
+        bob.messages.push(message("Bob", &format!("{}\nsecond line", "x".repeat(80))));
+        bob.unread_count = 2;
+        bob.last_activity = 200;
+        save(&history);
+
+        let index = load_index_from(&index_path, &key).unwrap();
+        assert_eq!(index, build_index(&load_conversations_from(&path, &key, &MAC_KEY, 2).unwrap()));
+        assert_eq!(index["fp2"].unread_count, 2);
+        assert_eq!(index["fp2"].last_message_preview, format!("{}…", "x".repeat(PREVIEW_CHARS)));
+        assert_eq!(index["fp1"].last_message_preview, "[Attachment: cat.png]");
+        let order: Vec<&str> = sidebar_order(&index, false).iter().map(|c| c.id.as_str()).collect();
+        assert_eq!(order, ["fp2", "fp1"]);
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&index_path);
+    }
+
+    #[test]
+    fn pinned_chats_lead_and_archived_are_hidden() {
+        let mut convs = HashMap::new();
+        for (id, activity) in [("old", 1), ("new", 3), ("pinned", 0), ("archived", 5)] {
+            let mut conv = Conversation::new(id.to_string(), id.to_string(), None);
+            conv.last_activity = activity;
+            convs.insert(id.to_string(), conv);
+        }
+        convs.get_mut("pinned").unwrap().settings.pinned.set(true, 1);
+        convs.get_mut("archived").unwrap().settings.archived.set(true, 1);
+
+        let index = build_index(&convs);
+        let ids = |show| sidebar_order(&index, show).iter().map(|c| c.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids(false), ["pinned", "new", "old"]);
+        assert_eq!(ids(true), ["pinned", "archived", "new", "old"]);
+    }
+
+    #[test]
+    fn edited_or_truncated_history_is_detected() {
+        let path = temp_history_path();
+        let key = derive_storage_key("fp-me");
+        save_conversations_to(&path, &sample_history(), &key, &MAC_KEY, 1).unwrap();
+
+        // Rewrite the file the way an outside tool would: decrypt, edit, re-encrypt
+        let rewrite = |edit: &dyn Fn(&mut Conversation)| {
+            let encrypted: EncryptedStore = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+            let mut stored: serde_json::Value = decrypt_data(&encrypted, &key).unwrap();
+            let mut conv: Conversation = serde_json::from_value(stored["conversations"]["fp1"].take()).unwrap();
+            edit(&mut conv);
+            stored["conversations"]["fp1"] = serde_json::to_value(&conv).unwrap();
+            fs::write(&path, serde_json::to_vec(&encrypt_data(&stored, &key).unwrap()).unwrap()).unwrap();
+        };
+
+        rewrite(&|conv| conv.messages[0].content = "send me your password".to_string());
+        let err = load_conversations_from(&path, &key, &MAC_KEY, 1).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<IntegrityError>(),
+            Some(&IntegrityError::Modified { conversation_id: "fp1".to_string() })
+        );
+
+        save_conversations_to(&path, &sample_history(), &key, &MAC_KEY, 1).unwrap();
+        rewrite(&|conv| {
+            conv.messages.pop();
+        });
+        let err = load_conversations_from(&path, &key, &MAC_KEY, 1).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<IntegrityError>(),
+            Some(&IntegrityError::Truncated { conversation_id: "fp1".to_string(), expected: 2, found: 1 })
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn history_resealed_with_the_public_storage_key_is_rejected() {
+        let path = temp_history_path();
+        let key = derive_storage_key("fp-me");
+        save_conversations_to(&path, &sample_history(), &key, &MAC_KEY, 1).unwrap();
+
+        // Anyone who knows the fingerprint can derive the storage key, so an
+        // edit re-chained and re-sealed with it must still fail
+        let mut history = sample_history();
+        history.get_mut("fp1").unwrap().messages[0].content = "send me your password".to_string();
+        save_conversations_to(&path, &history, &key, &key, 1).unwrap();
+        let err = load_conversations_from(&path, &key, &MAC_KEY, 1).unwrap_err();
+        assert!(err.downcast_ref::<IntegrityError>().is_some());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn an_older_copy_of_the_history_is_caught() {
+        let path = temp_history_path();
+        let key = derive_storage_key("fp-me");
+        save_conversations_to(&path, &sample_history(), &key, &MAC_KEY, 1).unwrap();
+        let old_copy = fs::read(&path).unwrap();
+
+        let mut history = sample_history();
+        history.get_mut("fp1").unwrap().messages.push(message("Alice", "newer"));
+        save_conversations_to(&path, &history, &key, &MAC_KEY, 2).unwrap();
+        assert_eq!(load_conversations_from(&path, &key, &MAC_KEY, 2).unwrap()["fp1"].messages.len(), 3);
+
+        // Every message and chain in the old copy is genuine; only the
+        // generation recorded outside the file gives it away
+        fs::write(&path, &old_copy).unwrap();
+        let err = load_conversations_from(&path, &key, &MAC_KEY, 2).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<IntegrityError>(),
+            Some(&IntegrityError::RolledBack { expected: 2, found: 1 })
+        );
+
+        fs::remove_file(&path).unwrap();
+        let err = load_conversations_from(&path, &key, &MAC_KEY, 2).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<IntegrityError>(),
+            Some(&IntegrityError::RolledBack { expected: 2, found: 0 })
+        );
+    }
 }
@@ -0,0 +1,343 @@
+//! Whole-account export and import for moving to a new machine
+//!
+//! The archive bundles the key material and every per-account file in the data
+//! directory (conversations, contacts, groups, emote library) and encrypts the
+//! lot with a passphrase-derived key, the same way `account_store` protects the
+//! secret key. Stored files are copied byte for byte; the ones already
+//! encrypted under the fingerprint-derived storage key stay readable because
+//! the fingerprint travels with them.
+//!
+//! An import never destroys data: every file it would overwrite is first
+//! copied under `backups/`, and an archive from an account other than this
+//! install's is restored under `accounts/<fingerprint>/` instead of over it.
+
+use anyhow::{bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use zeroize::Zeroize;
+
+use crate::account_store::{decrypt_secret_key, encrypt_secret_key};
+use crate::keystore::{self, StoredKey};
+
+const ARCHIVE_FORMAT: &str = "cryptochat-archive-v1";
+
+/// What a file in the archive belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Category {
+    Keys,
+    Conversations,
+    Contacts,
+    Groups,
+    Emotes,
+}
+
+/// Passphrase-encrypted archive as written to disk
+#[derive(Serialize, Deserialize)]
+struct SealedArchive {
+    format: String,
+    ciphertext: String,
+    nonce: String,
+    salt: String,
+}
+
+/// Key pair as held in the keystore
+#[derive(Serialize, Deserialize, Zeroize)]
+#[zeroize(drop)]
+struct ArchivedKey {
+    secret_key_armored: String,
+    public_key_armored: String,
+    fingerprint: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ArchivedFile {
+    category: Category,
+    /// Base64 file contents
+    data: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Archive {
+    fingerprint: String,
+    key: Option<ArchivedKey>,
+    /// Path relative to the data directory -> contents
+    files: BTreeMap<String, ArchivedFile>,
+}
+
+/// What `import_all` restored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportSummary {
+    pub fingerprint: String,
+    pub files: BTreeMap<Category, usize>,
+    /// Copies of the files the import replaced, if it replaced any
+    pub backup: Option<PathBuf>,
+    /// Set when the archive is another account's: its files went here
+    /// rather than over ours, and its key was not installed
+    pub set_aside: Option<PathBuf>,
+}
+
+/// Files that make up an account, relative to the data directory
+fn account_files(dir: &Path, fingerprint: &str) -> Vec<(Category, String)> {
+    let mut files = vec![
+        (Category::Keys, "account.json".to_string()),
+        (Category::Conversations, format!("conversations_{}.enc", fingerprint)),
+        (Category::Conversations, format!("conversation_index_{}.enc", fingerprint)),
+        (Category::Conversations, "chat_history.enc".to_string()),
+        (Category::Contacts, "contacts.json".to_string()),
+        (Category::Contacts, "simple_contacts.json".to_string()),
+        (Category::Groups, "groups.enc".to_string()),
+        (Category::Groups, format!("group_invites_{}.json", fingerprint)),
+        (Category::Emotes, "emotes/emotes.json".to_string()),
+    ];
+    if let Ok(entries) = fs::read_dir(dir.join("emotes").join("library")) {
+        for entry in entries.flatten().filter(|e| e.path().is_file()) {
+            files.push((Category::Emotes, format!("emotes/library/{}", entry.file_name().to_string_lossy())));
+        }
+    }
+    // Voice clips referenced from conversation history
+    if let Ok(entries) = fs::read_dir(dir.join("blobs")) {
+        for entry in entries.flatten().filter(|e| e.path().is_file()) {
+            files.push((Category::Conversations, format!("blobs/{}", entry.file_name().to_string_lossy())));
+        }
+    }
+    files
+}
+
+/// Only plain relative paths may come out of an archive
+fn is_safe_relative(path: &str) -> bool {
+    let path = Path::new(path);
+    path.components().next().is_some() && path.components().all(|c| matches!(c, Component::Normal(_)))
+}
+
+fn export_from(dir: &Path, fingerprint: &str, key: Option<ArchivedKey>, password: &str) -> Result<Vec<u8>> {
+    let mut files = BTreeMap::new();
+    for (category, relative) in account_files(dir, fingerprint) {
+        let path = dir.join(&relative);
+        if path.exists() {
+            let data = fs::read(&path).with_context(|| format!("Failed to read {}", relative))?;
+            files.insert(relative, ArchivedFile { category, data: STANDARD.encode(data) });
+        }
+    }
+
+    let archive = Archive { fingerprint: fingerprint.to_string(), key, files };
+    let mut json = serde_json::to_string(&archive)?;
+    let sealed = encrypt_secret_key(&json, password);
+    json.zeroize();
+    let (ciphertext, nonce, salt) = sealed?;
+
+    let sealed = SealedArchive { format: ARCHIVE_FORMAT.to_string(), ciphertext, nonce, salt };
+    Ok(serde_json::to_vec(&sealed)?)
+}
+
+/// Restore an archive into `dir`, backing up whatever it replaces. An
+/// archive from an account other than `existing_fingerprint` is restored
+/// under `accounts/<fingerprint>/` and its key is not returned.
+fn import_into(
+    dir: &Path,
+    bytes: &[u8],
+    password: &str,
+    existing_fingerprint: Option<&str>,
+    now_ms: i64,
+) -> Result<(ImportSummary, Option<ArchivedKey>)> {
+    let sealed: SealedArchive = serde_json::from_slice(bytes).context("Not a CryptoChat archive")?;
+    if sealed.format != ARCHIVE_FORMAT {
+        bail!("Unsupported archive format: {}", sealed.format);
+    }
+    let mut json = decrypt_secret_key(&sealed.ciphertext, &sealed.nonce, &sealed.salt, password)?;
+    let archive: Result<Archive, _> = serde_json::from_str(&json);
+    json.zeroize();
+    let mut archive = archive.context("Archive contents are malformed")?;
+
+    if archive.fingerprint.is_empty() || !archive.fingerprint.chars().all(|c| c.is_ascii_alphanumeric()) {
+        bail!("Archive has an invalid fingerprint");
+    }
+    if let Some(bad) = archive.files.keys().find(|p| !is_safe_relative(p)) {
+        bail!("Archive contains an invalid path: {}", bad);
+    }
+
+    let set_aside = existing_fingerprint
+        .filter(|existing| *existing != archive.fingerprint)
+        .map(|_| dir.join("accounts").join(&archive.fingerprint));
+    let target = set_aside.as_deref().unwrap_or(dir);
+    let backup = dir.join("backups").join(format!("import_{}_{}", archive.fingerprint, now_ms));
+
+    let mut counts = BTreeMap::new();
+    let mut backed_up = false;
+    for (relative, file) in &archive.files {
+        let data = STANDARD.decode(&file.data).with_context(|| format!("Corrupt data for {}", relative))?;
+        let path = target.join(relative);
+        if path.exists() {
+            let copy = backup.join(relative);
+            if let Some(parent) = copy.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&path, &copy).with_context(|| format!("Failed to back up {}", relative))?;
+            backed_up = true;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, data).with_context(|| format!("Failed to restore {}", relative))?;
+        *counts.entry(file.category).or_insert(0) += 1;
+    }
+
+    let key = if set_aside.is_none() { archive.key.take() } else { None };
+    let summary = ImportSummary {
+        fingerprint: archive.fingerprint.clone(),
+        files: counts,
+        backup: backed_up.then_some(backup),
+        set_aside,
+    };
+    Ok((summary, key))
+}
+
+/// Bundle this account's keys and data into a passphrase-encrypted archive
+pub fn export_all(password: &str) -> Result<Vec<u8>> {
+    if password.is_empty() {
+        bail!("Choose a passphrase for the archive");
+    }
+    let stored = keystore::load_keypair()?.context("No account to export")?;
+    let key = ArchivedKey {
+        secret_key_armored: stored.secret_key_armored.clone(),
+        public_key_armored: stored.public_key_armored.clone(),
+        fingerprint: stored.fingerprint.clone(),
+    };
+    export_from(&crate::paths::data_dir()?, &stored.fingerprint, Some(key), password)
+}
+
+/// Restore an archive made by `export_all`. The caller reloads what it
+/// holds in memory afterwards, or its next save undoes the import.
+pub fn import_all(bytes: &[u8], password: &str) -> Result<ImportSummary> {
+    let existing = keystore::load_keypair()?.map(|k| k.fingerprint.clone());
+    let (summary, key) = import_into(&crate::paths::data_dir()?, bytes, password, existing.as_deref(), crate::now_ms())?;
+    if let Some(key) = key {
+        keystore::save_keypair(&StoredKey::new(
+            key.secret_key_armored.clone(),
+            key.public_key_armored.clone(),
+            key.fingerprint.clone(),
+        ))?;
+    }
+    if summary.set_aside.is_none() && summary.files.contains_key(&Category::Conversations) {
+        // The restored history is older than the last save recorded for
+        // it, which would otherwise read as a rollback
+        keystore::save_history_generation(&summary.fingerprint, 0)?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cryptochat_archive_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn test_key() -> ArchivedKey {
+        ArchivedKey {
+            secret_key_armored: "SECRET".to_string(),
+            public_key_armored: "PUBLIC".to_string(),
+            fingerprint: "FPME".to_string(),
+        }
+    }
+
+    #[test]
+    fn archive_round_trip_restores_every_category() {
+        let source = temp_dir();
+        let sample: [(&str, &[u8]); 6] = [
+            ("account.json", b"{\"username\":\"me\"}"),
+            ("conversations_FPME.enc", b"conversations"),
+            ("simple_contacts.json", b"[]"),
+            ("groups.enc", b"groups"),
+            ("emotes/emotes.json", b"{}"),
+            ("emotes/library/abcd.png", &[0x89, b'P', b'N', b'G']),
+        ];
+        fs::create_dir_all(source.join("emotes/library")).unwrap();
+        for (path, data) in sample {
+            fs::write(source.join(path), data).unwrap();
+        }
+        // Another account's history stays behind
+        fs::write(source.join("conversations_FPOTHER.enc"), b"not mine").unwrap();
+
+        let bytes = export_from(&source, "FPME", Some(test_key()), "hunter2").unwrap();
+        assert!(import_into(&temp_dir(), &bytes, "wrong", None, 0).is_err());
+
+        let target = temp_dir();
+        let (summary, key) = import_into(&target, &bytes, "hunter2", None, 0).unwrap();
+        assert_eq!(summary.backup, None);
+        assert_eq!(summary.set_aside, None);
+        assert_eq!(summary.fingerprint, "FPME");
+        assert_eq!(key.map(|k| k.secret_key_armored.clone()), Some("SECRET".to_string()));
+        assert_eq!(
+            summary.files,
+            BTreeMap::from([
+                (Category::Keys, 1),
+                (Category::Conversations, 1),
+                (Category::Contacts, 1),
+                (Category::Groups, 1),
+                (Category::Emotes, 2),
+            ])
+        );
+        for (path, data) in sample {
+            assert_eq!(fs::read(target.join(path)).unwrap(), data, "{}", path);
+        }
+        assert!(!target.join("conversations_FPOTHER.enc").exists());
+
+        let _ = fs::remove_dir_all(&source);
+        let _ = fs::remove_dir_all(&target);
+    }
+
+    #[test]
+    fn another_accounts_archive_is_set_aside_instead_of_overwriting_ours() {
+        let source = temp_dir();
+        fs::write(source.join("account.json"), b"{\"username\":\"me\"}").unwrap();
+        let bytes = export_from(&source, "FPME", Some(test_key()), "pw").unwrap();
+
+        let target = temp_dir();
+        fs::write(target.join("account.json"), b"someone else").unwrap();
+        let (summary, key) = import_into(&target, &bytes, "pw", Some("FPSOMEONEELSE"), 0).unwrap();
+        assert!(key.is_none());
+        assert_eq!(summary.set_aside, Some(target.join("accounts").join("FPME")));
+        assert_eq!(summary.backup, None);
+        assert_eq!(fs::read(target.join("account.json")).unwrap(), b"someone else");
+        assert_eq!(fs::read(target.join("accounts/FPME/account.json")).unwrap(), b"{\"username\":\"me\"}");
+
+        let _ = fs::remove_dir_all(&source);
+        let _ = fs::remove_dir_all(&target);
+    }
+
+    #[test]
+    fn import_over_our_own_account_backs_up_what_it_replaces() {
+        let source = temp_dir();
+        fs::write(source.join("account.json"), b"archived").unwrap();
+        let bytes = export_from(&source, "FPME", Some(test_key()), "pw").unwrap();
+
+        let target = temp_dir();
+        fs::write(target.join("account.json"), b"newer").unwrap();
+        let (summary, key) = import_into(&target, &bytes, "pw", Some("FPME"), 42).unwrap();
+        assert!(key.is_some());
+        assert_eq!(summary.set_aside, None);
+        let backup = target.join("backups").join("import_FPME_42");
+        assert_eq!(summary.backup.as_deref(), Some(backup.as_path()));
+        assert_eq!(fs::read(backup.join("account.json")).unwrap(), b"newer");
+        assert_eq!(fs::read(target.join("account.json")).unwrap(), b"archived");
+
+        let _ = fs::remove_dir_all(&source);
+        let _ = fs::remove_dir_all(&target);
+    }
+
+    #[test]
+    fn archive_paths_must_stay_inside_data_dir() {
+        assert!(is_safe_relative("emotes/library/abcd.png"));
+        assert!(!is_safe_relative("../account.json"));
+        assert!(!is_safe_relative("/etc/passwd"));
+        assert!(!is_safe_relative(""));
+    }
+}
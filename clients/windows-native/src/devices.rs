@@ -0,0 +1,126 @@
+//! This install's device identity and the registry of devices on the account
+//!
+//! Each install keeps a random seed in the data directory and derives its
+//! device id from it, so the id survives restarts. Devices are recorded in the
+//! node's storage under the account fingerprint; revoking one there makes the
+//! node refuse envelopes it sends.
+//!
+//! Every install also has a signing key of its own, and registers that rather
+//! than the account key: the node only believes an envelope's device id when
+//! the envelope is signed by the key registered for it, and the account key
+//! is shared by every device, revoked ones included.
+
+use anyhow::{Context, Result};
+use cryptochat_crypto_core::pgp::PgpKeyPair;
+use cryptochat_crypto_core::{device_id_from_seed, generate_device_id};
+use cryptochat_node::storage::{DeviceRecord, NodeStorage};
+use rand::rngs::OsRng;
+use std::fs;
+use std::path::Path;
+
+use crate::keystore;
+
+const SEED_FILE: &str = "device_seed";
+const KEY_FILE: &str = "device_key.asc";
+const REGISTRY_DIR: &str = "node";
+
+/// Devices on the account and which one is this install
+#[derive(Debug, Clone)]
+pub struct DeviceList {
+    pub this_device: String,
+    pub devices: Vec<DeviceRecord>,
+}
+
+/// Stable device id for the install whose data lives in `dir`, creating the
+/// seed on first use
+fn local_device_id_in(dir: &Path) -> Result<String> {
+    let path = dir.join(SEED_FILE);
+    if let Ok(seed) = fs::read(&path) {
+        if !seed.is_empty() {
+            return Ok(device_id_from_seed(&seed));
+        }
+    }
+    let mut seed = [0u8; 32];
+    rand::RngCore::fill_bytes(&mut OsRng, &mut seed);
+    fs::write(&path, seed).context("Failed to save device seed")?;
+    Ok(device_id_from_seed(&seed))
+}
+
+/// This install's own signing key, created on first use
+fn local_device_key_in(dir: &Path, device_id: &str) -> Result<PgpKeyPair> {
+    let path = dir.join(KEY_FILE);
+    if let Ok(armored) = fs::read_to_string(&path) {
+        if let Ok(key) = PgpKeyPair::from_secret_key(&armored) {
+            return Ok(key);
+        }
+    }
+    let key = PgpKeyPair::generate(&format!("device {}", device_id))?;
+    fs::write(&path, key.export_secret_key()?).context("Failed to save device key")?;
+    Ok(key)
+}
+
+fn open_registry(dir: &Path) -> Result<NodeStorage> {
+    NodeStorage::open(dir.join(REGISTRY_DIR))
+}
+
+/// This install's device id
+pub fn local_device_id() -> Result<String> {
+    local_device_id_in(&crate::paths::data_dir()?)
+}
+
+/// Register this install under the current account and list every device
+pub fn refresh() -> Result<DeviceList> {
+    let dir = crate::paths::data_dir()?;
+    let stored = keystore::load_keypair()?.context("Not logged in")?;
+    let this_device = local_device_id_in(&dir).unwrap_or_else(|_| generate_device_id());
+
+    let device_key = local_device_key_in(&dir, &this_device)?;
+
+    let registry = open_registry(&dir)?;
+    registry.register_device(&stored.fingerprint, &this_device, &device_key.export_public_key()?)?;
+    let devices = registry.list_devices(&stored.fingerprint)?;
+    Ok(DeviceList { this_device, devices })
+}
+
+/// Revoke another device on this account, returning the updated list
+pub fn revoke(device_id: &str) -> Result<DeviceList> {
+    let dir = crate::paths::data_dir()?;
+    let stored = keystore::load_keypair()?.context("Not logged in")?;
+    let this_device = local_device_id_in(&dir)?;
+    if device_id == this_device {
+        anyhow::bail!("Can't revoke the device you're using");
+    }
+
+    let registry = open_registry(&dir)?;
+    registry.revoke_device(&stored.fingerprint, device_id)?;
+    let devices = registry.list_devices(&stored.fingerprint)?;
+    Ok(DeviceList { this_device, devices })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn device_id_survives_restarts() {
+        let dir = std::env::temp_dir().join(format!("cryptochat_devices_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let first = local_device_id_in(&dir).unwrap();
+        assert_eq!(local_device_id_in(&dir).unwrap(), first);
+        assert!(uuid::Uuid::parse_str(&first).is_ok());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn device_key_survives_restarts() {
+        let dir = std::env::temp_dir().join(format!("cryptochat_devices_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let first = local_device_key_in(&dir, "laptop").unwrap();
+        assert_eq!(local_device_key_in(&dir, "laptop").unwrap().fingerprint(), first.fingerprint());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,273 @@
+//! End-to-end tests: two clients in one process talking over real sockets
+//!
+//! Each `Peer` is what a running instance has underneath the UI: its own
+//! `AppState` holding a generated key and a `network` listener feeding an
+//! event channel. The tests drive the two the way `main.rs` does, exchanging
+//! keys with `AcceptedResponse` and then sending envelopes to each other's
+//! listening port, so they cover serialization, framing, the listener and
+//! the PGP layer together.
+
+use crate::app::AppState;
+use crate::network::{MessageEnvelope, NetworkEvent, NetworkHandle};
+use cryptochat_crypto_core::pgp::PgpKeyPair;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// How long to wait for an envelope to come through
+const EVENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+struct Peer {
+    name: &'static str,
+    state: AppState,
+    network: NetworkHandle,
+    events: mpsc::UnboundedReceiver<NetworkEvent>,
+}
+
+impl Peer {
+    fn start(name: &'static str) -> Self {
+        let state = AppState::new();
+        state.set_keypair(PgpKeyPair::generate(&format!("{}@example.com", name)).unwrap());
+        let (sender, events) = mpsc::unbounded_channel();
+        let network = NetworkHandle::start_with_sender(sender, None).unwrap();
+        Self { name, state, network, events }
+    }
+
+    fn address(&self) -> String {
+        format!("127.0.0.1:{}", self.network.port())
+    }
+
+    fn fingerprint(&self) -> String {
+        self.state.get_fingerprint().unwrap()
+    }
+
+    fn key_share(&self) -> MessageEnvelope {
+        let keypair = self.state.get_keypair().unwrap();
+        MessageEnvelope::accepted_response(&keypair, self.network.port(), self.name, 0).unwrap()
+    }
+
+    fn send(&self, to: &Peer, envelope: MessageEnvelope) {
+        NetworkHandle::send_message(&to.address(), envelope).unwrap();
+    }
+
+    /// The next event off the listener, failing the test if none arrives
+    fn next_event(&mut self) -> NetworkEvent {
+        let deadline = Instant::now() + EVENT_TIMEOUT;
+        loop {
+            match self.events.try_recv() {
+                Ok(NetworkEvent::Error(e)) => panic!("{} got a network error: {}", self.name, e),
+                Ok(event) => return event,
+                Err(_) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(10)),
+                Err(e) => panic!("{} got no event: {}", self.name, e),
+            }
+        }
+    }
+
+    /// Adopt the key from an incoming key share, as accepting a request does
+    fn accept_key_share(&mut self) -> String {
+        match self.next_event() {
+            NetworkEvent::RequestReceived { sender_public_key, sender_address, .. } => {
+                self.state.set_recipient_keypair(PgpKeyPair::from_public_key(&sender_public_key).unwrap());
+                self.state.set_peer_address(sender_address.clone());
+                sender_address
+            }
+            other => panic!("expected a key share, got {:?}", other),
+        }
+    }
+}
+
+/// Two peers that have exchanged keys
+fn connected_pair() -> (Peer, Peer) {
+    let mut alice = Peer::start("alice");
+    let mut bob = Peer::start("bob");
+
+    alice.send(&bob, alice.key_share());
+    assert_eq!(bob.accept_key_share(), alice.address());
+    bob.send(&alice, bob.key_share());
+    assert_eq!(alice.accept_key_share(), bob.address());
+
+    assert_eq!(alice.state.get_recipient_fingerprint(), Some(bob.fingerprint()));
+    assert_eq!(bob.state.get_recipient_fingerprint(), Some(alice.fingerprint()));
+    (alice, bob)
+}
+
+#[test]
+fn message_is_delivered_decrypted_and_acknowledged() {
+    let (mut alice, mut bob) = connected_pair();
+
+    let encrypted = alice.state.encrypt_message("hello bob").unwrap();
+    assert!(!encrypted.contains("hello"));
+    alice.send(&bob, MessageEnvelope::RegularMessage {
+        encrypted_payload: encrypted,
+        sender_name: Some("alice".to_string()),
+        sender_fingerprint: alice.fingerprint(),
+        sender_listening_port: alice.network.port(),
+        message_id: Some("m1".to_string()),
+    });
+
+    let message_id = match bob.next_event() {
+        NetworkEvent::MessageReceived { encrypted_payload, sender_fingerprint, sender_address, message_id, .. } => {
+            assert_eq!(sender_fingerprint, alice.fingerprint());
+            assert_eq!(sender_address, alice.address());
+            assert_eq!(bob.state.decrypt_message(&encrypted_payload).unwrap(), "hello bob");
+            message_id.unwrap()
+        }
+        other => panic!("expected a message, got {:?}", other),
+    };
+
+    // Delivery and read receipts back to the sender
+    bob.send(&alice, MessageEnvelope::DeliveryAck {
+        message_id,
+        sender_fingerprint: bob.fingerprint(),
+        group_id: None,
+    });
+    match alice.next_event() {
+        NetworkEvent::DeliveryAckReceived { message_id, sender_fingerprint, group_id } => {
+            assert_eq!((message_id.as_str(), group_id), ("m1", None));
+            assert_eq!(sender_fingerprint, bob.fingerprint());
+        }
+        other => panic!("expected a delivery ack, got {:?}", other),
+    }
+
+    bob.send(&alice, MessageEnvelope::ReadReceipt {
+        last_read_timestamp: "12:00".to_string(),
+        sender_fingerprint: bob.fingerprint(),
+        sender_listening_port: bob.network.port(),
+    });
+    match alice.next_event() {
+        NetworkEvent::ReadReceiptReceived { last_read_timestamp, sender_address, .. } => {
+            assert_eq!(last_read_timestamp, "12:00");
+            assert_eq!(sender_address, bob.address());
+        }
+        other => panic!("expected a read receipt, got {:?}", other),
+    }
+}
+
+#[test]
+fn typing_starts_and_stops() {
+    let (mut alice, bob) = connected_pair();
+
+    for is_typing in [true, false] {
+        bob.send(&alice, MessageEnvelope::TypingIndicator {
+            is_typing,
+            sender_fingerprint: bob.fingerprint(),
+            sender_listening_port: bob.network.port(),
+            group_id: None,
+        });
+        match alice.next_event() {
+            NetworkEvent::TypingUpdate { is_typing: got, sender_fingerprint, group_id, .. } => {
+                assert_eq!(got, is_typing);
+                assert_eq!(sender_fingerprint, bob.fingerprint());
+                assert_eq!(group_id, None);
+            }
+            other => panic!("expected a typing update, got {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn first_message_racing_the_key_share_still_decrypts() {
+    let alice = Peer::start("alice");
+    let mut bob = Peer::start("bob");
+    // Alice already has Bob's key (from a QR code, say) and writes straight
+    // away, so her key share and first message are in flight together
+    alice.state.set_recipient_keypair(PgpKeyPair::from_public_key(
+        &bob.state.get_keypair().unwrap().export_public_key().unwrap(),
+    ).unwrap());
+    alice.send(&bob, alice.key_share());
+    alice.send(&bob, MessageEnvelope::RegularMessage {
+        encrypted_payload: alice.state.encrypt_message("are you there?").unwrap(),
+        sender_name: Some("alice".to_string()),
+        sender_fingerprint: alice.fingerprint(),
+        sender_listening_port: alice.network.port(),
+        message_id: None,
+    });
+
+    // Each envelope comes in on its own connection, so either may land first
+    let (mut sender_key, mut payload) = (None, None);
+    for _ in 0..2 {
+        match bob.next_event() {
+            NetworkEvent::RequestReceived { sender_public_key, .. } => sender_key = Some(sender_public_key),
+            NetworkEvent::MessageReceived { encrypted_payload, .. } => payload = Some(encrypted_payload),
+            other => panic!("unexpected event {:?}", other),
+        }
+    }
+    let (sender_key, payload) = (sender_key.unwrap(), payload.unwrap());
+
+    // No session with Alice yet, but her key from the share is enough
+    assert!(bob.state.decrypt_message(&payload).is_err());
+    assert_eq!(bob.state.decrypt_message_with_sender_key(&payload, &sender_key).unwrap(), "are you there?");
+}
+
+#[test]
+fn disconnect_keeps_contacts_and_history_and_the_session_can_resume() {
+    let (alice, mut bob) = connected_pair();
+    let bob_fp = bob.fingerprint();
+    let contacts = vec![crate::request_store::SimpleContact {
+        name: "bob".to_string(),
+        fingerprint: bob_fp.clone(),
+        public_key: bob.state.get_keypair().unwrap().export_public_key().unwrap(),
+        address: bob.address(),
+        revoked: false,
+        alias: None,
+        address_updated_ms: 0,
+    }];
+    let mut conversations = std::collections::HashMap::from([(
+        bob_fp.clone(),
+        crate::conversation::Conversation::new(bob_fp.clone(), "bob".to_string(), Some(bob.address())),
+    )]);
+    let history = crate::conversation::ChatMessage {
+        sender_name: "bob".to_string(),
+        content: "see you".to_string(),
+        is_mine: false,
+        timestamp: "09:00".to_string(),
+        sent_at_ms: 1,
+        image_data: None,
+        image_filename: None,
+        reactions: Vec::new(),
+        emotes: Default::default(),
+        expires_at: None,
+        message_id: None,
+        status: crate::conversation::DeliveryStatus::Sent,
+        delivered_to: Vec::new(),
+        forwarded: false,
+        voice: None,
+        id: uuid::Uuid::new_v4(),
+    };
+    conversations.get_mut(&bob_fp).unwrap().messages.push(history.clone());
+    let mut active = Some(bob_fp.clone());
+    let mut input = "half-written".to_string();
+
+    alice.send(&bob, MessageEnvelope::SessionEnd { sender_fingerprint: alice.fingerprint() });
+    crate::end_session(&alice.state, &mut conversations, &mut active, &mut input);
+
+    match bob.next_event() {
+        NetworkEvent::SessionEnded { sender_fingerprint } => assert_eq!(sender_fingerprint, alice.fingerprint()),
+        other => panic!("expected the session to end, got {:?}", other),
+    }
+    assert_eq!(alice.state.get_recipient_fingerprint(), None);
+    assert!(alice.state.encrypt_message("still there?").is_err());
+    assert_eq!(active, None);
+    assert!(input.is_empty());
+    let chat = &conversations[&bob_fp];
+    assert_eq!(chat.messages.len(), 1);
+    assert_eq!(chat.messages[0].id, history.id);
+    assert_eq!(chat.input_draft, "half-written");
+
+    // Opening the kept contact again reconnects
+    let contact = contacts.iter().find(|c| c.fingerprint == bob_fp).unwrap();
+    alice.state.set_recipient_keypair(PgpKeyPair::from_public_key(&contact.public_key).unwrap());
+    alice.state.set_peer_address(contact.address.clone());
+    alice.send(&bob, MessageEnvelope::RegularMessage {
+        encrypted_payload: alice.state.encrypt_message("back again").unwrap(),
+        sender_name: Some("alice".to_string()),
+        sender_fingerprint: alice.fingerprint(),
+        sender_listening_port: alice.network.port(),
+        message_id: None,
+    });
+    match bob.next_event() {
+        NetworkEvent::MessageReceived { encrypted_payload, .. } => {
+            assert_eq!(bob.state.decrypt_message(&encrypted_payload).unwrap(), "back again");
+        }
+        other => panic!("expected a message, got {:?}", other),
+    }
+}
@@ -2,10 +2,19 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
 use sha2::{Sha256, Digest};
 use anyhow::Result;
 
+/// Shortest delay between animation frames (caps playback at 20 fps)
+pub const MIN_FRAME_DELAY_MS: u32 = 50;
+/// Maximum number of frames decoded per animated emote
+const MAX_ANIMATION_FRAMES: usize = 120;
+/// Maximum decoded RGBA bytes kept in memory per animated emote
+const MAX_ANIMATION_BYTES: usize = 8 * 1024 * 1024;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Emote {
     pub name: String,
@@ -13,21 +22,103 @@ pub struct Emote {
     pub extension: String,
 }
 
+/// Largest file accepted as an emote
+pub const MAX_EMOTE_FILE_BYTES: u64 = 5 * 1024 * 1024;
+/// Emotes larger than this (in either dimension) are downscaled on import
+pub const MAX_EMOTE_DIMENSION: u32 = 128;
+
+/// Current emote pack format version
+const EMOTE_PACK_VERSION: u32 = 1;
+
+/// Default cap on the size of the received-emote cache directory
+pub const DEFAULT_MAX_CACHE_BYTES: u64 = 50 * 1024 * 1024;
+
+/// How long to wait before asking a peer for the same missing emote again
+const EMOTE_REQUEST_RETRY: Duration = Duration::from_secs(30);
+
+/// Shareable bundle of emotes
+#[derive(Serialize, Deserialize)]
+struct EmotePack {
+    version: u32,
+    emotes: Vec<PackedEmote>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PackedEmote {
+    name: String,
+    extension: String,
+    /// Base64 encoded image data
+    data: String,
+}
+
+/// A single decoded GIF frame
+#[derive(Clone, Debug)]
+pub struct GifFrame {
+    pub width: u32,
+    pub height: u32,
+    /// RGBA8 pixel data
+    pub rgba: Vec<u8>,
+    /// How long this frame is shown (already clamped to MIN_FRAME_DELAY_MS)
+    pub delay_ms: u32,
+}
+
+/// Decoded animation ready for rendering
+#[derive(Clone, Debug)]
+pub struct AnimatedEmote {
+    frames: Vec<iced::widget::image::Handle>,
+    delays_ms: Vec<u32>,
+    total_ms: u64,
+}
+
+impl AnimatedEmote {
+    fn from_frames(frames: Vec<GifFrame>) -> Self {
+        let delays_ms: Vec<u32> = frames.iter().map(|f| f.delay_ms).collect();
+        let total_ms = delays_ms.iter().map(|d| *d as u64).sum();
+        let frames = frames
+            .into_iter()
+            .map(|f| iced::widget::image::Handle::from_pixels(f.width, f.height, f.rgba))
+            .collect();
+        Self { frames, delays_ms, total_ms }
+    }
+
+    /// Frame to display after `elapsed_ms` of playback (loops forever)
+    pub fn frame_at(&self, elapsed_ms: u64) -> iced::widget::image::Handle {
+        let idx = frame_index_at(&self.delays_ms, self.total_ms, elapsed_ms);
+        self.frames[idx].clone()
+    }
+}
+
 #[derive(Clone)]
 pub struct EmoteManager {
     /// User's personal library (name -> Emote)
     pub library: Arc<RwLock<HashMap<String, Emote>>>,
     /// Cache index (hash -> full path)
     pub cache: Arc<RwLock<HashMap<String, PathBuf>>>,
+    /// Decoded animations (hash -> animation, None = static or undecodable)
+    animations: Arc<RwLock<HashMap<String, Option<Arc<AnimatedEmote>>>>>,
+    /// Last access tick for cached emotes (hash -> tick), used for LRU eviction
+    last_access: Arc<RwLock<HashMap<String, u64>>>,
+    /// Monotonic counter handing out access ticks
+    access_clock: Arc<AtomicU64>,
+    /// Maximum total size of the cache directory in bytes
+    max_cache_bytes: Arc<AtomicU64>,
     base_path: PathBuf,
 }
 
 impl EmoteManager {
     pub fn new() -> Self {
-        let base = get_data_dir();
+        Self::with_base_path(get_data_dir())
+    }
+
+    /// Create a manager rooted at a specific directory
+    pub fn with_base_path(base: PathBuf) -> Self {
         let manager = Self {
             library: Arc::new(RwLock::new(HashMap::new())),
             cache: Arc::new(RwLock::new(HashMap::new())),
+            animations: Arc::new(RwLock::new(HashMap::new())),
+            last_access: Arc::new(RwLock::new(HashMap::new())),
+            access_clock: Arc::new(AtomicU64::new(0)),
+            max_cache_bytes: Arc::new(AtomicU64::new(DEFAULT_MAX_CACHE_BYTES)),
             base_path: base.clone(),
         };
         
@@ -70,34 +161,124 @@ impl EmoteManager {
         // We assume filename is {hash}.{ext}
         let cache_dir = self.base_path.join("cache");
         if let Ok(entries) = fs::read_dir(cache_dir) {
-            if let Ok(mut guard) = self.cache.write() {
-                for entry in entries.flatten() {
+            // Seed LRU order from file modification times
+            let mut found: Vec<(std::time::SystemTime, String, PathBuf)> = entries
+                .flatten()
+                .filter_map(|entry| {
                     let path = entry.path();
-                    if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
-                        // Validate stem is hex hash? For now just assume
-                        guard.insert(stem.to_string(), path);
-                    }
+                    let stem = path.file_stem()?.to_str()?.to_string();
+                    let modified = entry.metadata().and_then(|m| m.modified()).unwrap_or(std::time::UNIX_EPOCH);
+                    Some((modified, stem, path))
+                })
+                .collect();
+            found.sort();
+
+            if let Ok(mut guard) = self.cache.write() {
+                for (_, stem, path) in found {
+                    // Validate stem is hex hash? For now just assume
+                    self.touch(&stem);
+                    guard.insert(stem, path);
                 }
             }
         }
     }
 
+    /// Set the maximum size of the received-emote cache
+    pub fn set_max_cache_bytes(&self, max_bytes: u64) {
+        self.max_cache_bytes.store(max_bytes, Ordering::Relaxed);
+    }
+
+    /// Record an access to a cached emote
+    fn touch(&self, hash: &str) {
+        let tick = self.access_clock.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut guard) = self.last_access.write() {
+            guard.insert(hash.to_string(), tick);
+        }
+    }
+
+    /// Evict least-recently-used cached emotes until the cache fits under the
+    /// cap. Emotes in the user's own library and `keep` are never evicted.
+    fn enforce_cache_limit(&self, keep: &str) -> Result<()> {
+        let max_bytes = self.max_cache_bytes.load(Ordering::Relaxed);
+        let owned: std::collections::HashSet<String> = {
+            let guard = self.library.read().map_err(|_| anyhow::anyhow!("Lock poisoned"))?;
+            guard.values().map(|e| e.hash.clone()).collect()
+        };
+
+        let mut entries: Vec<(u64, String, PathBuf, u64)> = {
+            let cache = self.cache.read().map_err(|_| anyhow::anyhow!("Lock poisoned"))?;
+            let access = self.last_access.read().map_err(|_| anyhow::anyhow!("Lock poisoned"))?;
+            cache
+                .iter()
+                .map(|(hash, path)| {
+                    let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+                    let tick = access.get(hash).copied().unwrap_or(0);
+                    (tick, hash.clone(), path.clone(), size)
+                })
+                .collect()
+        };
+
+        let mut total: u64 = entries.iter().map(|(_, _, _, size)| size).sum();
+        if total <= max_bytes {
+            return Ok(());
+        }
+
+        entries.sort();
+        for (_, hash, path, size) in entries {
+            if total <= max_bytes {
+                break;
+            }
+            if hash == keep || owned.contains(&hash) {
+                continue;
+            }
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+            total = total.saturating_sub(size);
+            if let Ok(mut guard) = self.cache.write() {
+                guard.remove(&hash);
+            }
+            if let Ok(mut guard) = self.last_access.write() {
+                guard.remove(&hash);
+            }
+            if let Ok(mut guard) = self.animations.write() {
+                guard.remove(&hash);
+            }
+        }
+        Ok(())
+    }
+
     /// Import a file into the local library
     pub fn import_emote(&self, source_path: &Path, name: String) -> Result<Emote> {
+        let size = fs::metadata(source_path)?.len();
+        if size > MAX_EMOTE_FILE_BYTES {
+            anyhow::bail!(
+                "Emote file is too large ({} KB, max {} KB)",
+                size / 1024,
+                MAX_EMOTE_FILE_BYTES / 1024
+            );
+        }
         let bytes = fs::read(source_path)?;
-        let mut hasher = Sha256::new();
-        hasher.update(&bytes);
-        let hash = format!("{:x}", hasher.finalize());
-        
         let ext = source_path.extension()
             .and_then(|e| e.to_str())
             .unwrap_or("png")
             .to_lowercase();
+        self.add_to_library(&bytes, &ext, name)
+    }
+
+    /// Validate image bytes and store them in the library under `name`
+    fn add_to_library(&self, bytes: &[u8], ext: &str, name: String) -> Result<Emote> {
+        let (bytes, ext) = prepare_emote_image(bytes, ext)?;
+        let bytes = bytes.as_slice();
+
+        let mut hasher = Sha256::new();
+        hasher.update(bytes);
+        let hash = format!("{:x}", hasher.finalize());
             
         let dest_filename = format!("{}.{}", hash, ext);
         let dest_path = self.base_path.join("library").join(&dest_filename);
         
-        fs::copy(source_path, &dest_path)?;
+        fs::write(&dest_path, bytes)?;
         
         let emote = Emote {
             name: name.clone(),
@@ -119,12 +300,50 @@ impl EmoteManager {
         Ok(emote)
     }
 
-    /// Save a received emote data to cache
+    /// Validate an image file and store it in the cache without adding it to
+    /// the library (used for group avatars). Returns its hash.
+    pub fn store_image(&self, source_path: &Path) -> Result<String> {
+        let bytes = fs::read(source_path)?;
+        let ext = source_path.extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("png")
+            .to_lowercase();
+        let (bytes, _) = prepare_emote_image(&bytes, &ext)?;
+        let mut hasher = Sha256::new();
+        hasher.update(&bytes);
+        let hash = format!("{:x}", hasher.finalize());
+        self.save_to_cache(&hash, &bytes)?;
+        Ok(hash)
+    }
+
+    /// Cache emote data a peer sent in reply to our request. The hash names
+    /// the cache file, so it must be a SHA-256 in hex and match the data.
+    pub fn save_received(&self, hash: &str, data_bytes: &[u8]) -> Result<PathBuf> {
+        if hash.len() != 64 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            anyhow::bail!("Emote hash is not a SHA-256");
+        }
+        if data_bytes.len() as u64 > MAX_EMOTE_FILE_BYTES {
+            anyhow::bail!("Emote is too large");
+        }
+        let actual = format!("{:x}", Sha256::digest(data_bytes));
+        if actual != hash {
+            anyhow::bail!("Emote data doesn't match its hash");
+        }
+        self.save_to_cache(hash, data_bytes)
+    }
+
+    /// Save emote data to cache under `hash`
     pub fn save_to_cache(&self, hash: &str, data_bytes: &[u8]) -> Result<PathBuf> {
         // Detect extension from partial bytes or just assume png/jpg?
         // Usually we might want metadata or detect header.
         // For MVP, lets assume png or detect magic bytes.
-        let ext = if data_bytes.starts_with(&[0xFF, 0xD8, 0xFF]) { "jpg" } else { "png" };
+        let ext = if is_gif(data_bytes) {
+            "gif"
+        } else if data_bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            "jpg"
+        } else {
+            "png"
+        };
         
         let filename = format!("{}.{}", hash, ext);
         let path = self.base_path.join("cache").join(filename);
@@ -135,6 +354,8 @@ impl EmoteManager {
             let mut guard = self.cache.write().map_err(|_| anyhow::anyhow!("Lock poisoned"))?;
             guard.insert(hash.to_string(), path.clone());
         }
+        self.touch(hash);
+        self.enforce_cache_limit(hash)?;
         
         Ok(path)
     }
@@ -156,7 +377,10 @@ impl EmoteManager {
         {
             let guard = self.cache.read().ok()?;
             if let Some(path) = guard.get(hash) {
-                if path.exists() { return Some(path.clone()); }
+                if path.exists() {
+                    self.touch(hash);
+                    return Some(path.clone());
+                }
             }
         }
         
@@ -167,15 +391,695 @@ impl EmoteManager {
         let guard = self.library.read().ok()?;
         guard.get(name).cloned()
     }
+
+    /// Hashes referenced by a message that we have no local file for
+    pub fn missing_hashes(&self, emotes: &HashMap<String, String>) -> Vec<String> {
+        let mut missing: Vec<String> = emotes
+            .values()
+            .filter(|hash| self.get_emote_path(hash).is_none())
+            .cloned()
+            .collect();
+        missing.sort();
+        missing.dedup();
+        missing
+    }
+
+    /// List library emotes as (name, hash), sorted by name
+    pub fn list_emotes(&self) -> Vec<(String, String)> {
+        let mut emotes: Vec<(String, String)> = match self.library.read() {
+            Ok(guard) => guard.values().map(|e| (e.name.clone(), e.hash.clone())).collect(),
+            Err(_) => Vec::new(),
+        };
+        emotes.sort();
+        emotes
+    }
+
+    /// Remove an emote from the library. The backing file is only deleted
+    /// once no other name refers to the same image.
+    pub fn remove_emote(&self, name: &str) -> Result<bool> {
+        let removed = {
+            let mut guard = self.library.write().map_err(|_| anyhow::anyhow!("Lock poisoned"))?;
+            match guard.remove(name) {
+                Some(emote) => {
+                    let still_used = guard.values().any(|e| e.hash == emote.hash);
+                    Some((emote, still_used))
+                }
+                None => None,
+            }
+        };
+
+        let Some((emote, still_used)) = removed else {
+            return Ok(false);
+        };
+
+        if !still_used {
+            let path = self.base_path.join("library").join(format!("{}.{}", emote.hash, emote.extension));
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+            if let Ok(mut guard) = self.animations.write() {
+                guard.remove(&emote.hash);
+            }
+        }
+
+        self.save_library()?;
+        Ok(true)
+    }
+
+    /// Bundle the named library emotes into a single pack file
+    pub fn export_pack(&self, names: &[String]) -> Result<Vec<u8>> {
+        use base64::Engine;
+
+        let mut emotes = Vec::new();
+        for name in names {
+            let emote = self.get_emote_by_name(name)
+                .ok_or_else(|| anyhow::anyhow!("Unknown emote: {}", name))?;
+            let path = self.base_path.join("library").join(format!("{}.{}", emote.hash, emote.extension));
+            let bytes = fs::read(&path)?;
+            emotes.push(PackedEmote {
+                name: emote.name,
+                extension: emote.extension,
+                data: base64::engine::general_purpose::STANDARD.encode(bytes),
+            });
+        }
+
+        let pack = EmotePack { version: EMOTE_PACK_VERSION, emotes };
+        Ok(serde_json::to_vec_pretty(&pack)?)
+    }
+
+    /// Add every emote in a pack to the library. Emotes that are already
+    /// present under the same name are skipped; a different emote with a
+    /// taken name is stored as `name_2`, `name_3`, ... Returns the names
+    /// that were added.
+    pub fn import_pack(&self, bytes: &[u8]) -> Result<Vec<String>> {
+        use base64::Engine;
+
+        let pack: EmotePack = serde_json::from_slice(bytes)?;
+        if pack.version > EMOTE_PACK_VERSION {
+            anyhow::bail!("Unsupported emote pack version {}", pack.version);
+        }
+
+        let mut added = Vec::new();
+        for packed in pack.emotes {
+            let data = base64::engine::general_purpose::STANDARD.decode(&packed.data)?;
+            let mut hasher = Sha256::new();
+            hasher.update(&data);
+            let hash = format!("{:x}", hasher.finalize());
+
+            let name = {
+                let guard = self.library.read().map_err(|_| anyhow::anyhow!("Lock poisoned"))?;
+                let mut candidate = packed.name.clone();
+                let mut n = 2;
+                let mut already_present = false;
+                while let Some(existing) = guard.get(&candidate) {
+                    if existing.hash == hash {
+                        already_present = true;
+                        break;
+                    }
+                    candidate = format!("{}_{}", packed.name, n);
+                    n += 1;
+                }
+                if already_present {
+                    continue;
+                }
+                candidate
+            };
+
+            let ext = packed.extension.to_lowercase();
+            if !ext.chars().all(|c| c.is_ascii_alphanumeric()) {
+                anyhow::bail!("Invalid emote extension: {}", packed.extension);
+            }
+            self.add_to_library(&data, &ext, name.clone())?;
+            added.push(name);
+        }
+        Ok(added)
+    }
+
+    /// Delete files in the library directory that no emote refers to.
+    /// Returns the number of files removed.
+    pub fn cleanup_orphaned_files(&self) -> Result<usize> {
+        let referenced: std::collections::HashSet<String> = {
+            let guard = self.library.read().map_err(|_| anyhow::anyhow!("Lock poisoned"))?;
+            guard.values().map(|e| format!("{}.{}", e.hash, e.extension)).collect()
+        };
+
+        let mut removed = 0;
+        for entry in fs::read_dir(self.base_path.join("library"))?.flatten() {
+            let path = entry.path();
+            let Some(filename) = path.file_name().and_then(|f| f.to_str()) else { continue };
+            if path.is_file() && !referenced.contains(filename) {
+                fs::remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Get the decoded animation for an emote, decoding it on first use.
+    /// Returns None for static images or GIFs that fail to decode, in which
+    /// case the caller should render the file as a static image.
+    pub fn get_animation(&self, hash: &str) -> Option<Arc<AnimatedEmote>> {
+        if let Ok(guard) = self.animations.read() {
+            if let Some(cached) = guard.get(hash) {
+                return cached.clone();
+            }
+        }
+
+        let path = self.get_emote_path(hash)?;
+        let decoded = fs::read(&path)
+            .ok()
+            .filter(|bytes| is_gif(bytes))
+            .and_then(|bytes| decode_gif_frames(&bytes).ok())
+            .filter(|frames| frames.len() > 1)
+            .map(|frames| Arc::new(AnimatedEmote::from_frames(frames)));
+
+        if let Ok(mut guard) = self.animations.write() {
+            guard.insert(hash.to_string(), decoded.clone());
+        }
+        decoded
+    }
+
+    /// Check if any of the given hashes refer to an animated emote
+    pub fn has_animated(&self, hashes: &[&String]) -> bool {
+        hashes.iter().any(|hash| self.get_animation(hash).is_some())
+    }
+}
+
+/// Tracks outgoing emote requests so the same hash isn't requested repeatedly
+/// while a reply is in flight
+#[derive(Debug, Default)]
+pub struct EmoteRequestTracker {
+    requested: HashMap<String, Instant>,
+}
+
+impl EmoteRequestTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Filter `hashes` down to the ones that should be requested now and
+    /// record them as pending
+    pub fn take_new(&mut self, hashes: Vec<String>, now: Instant) -> Vec<String> {
+        hashes
+            .into_iter()
+            .filter(|hash| {
+                let due = self
+                    .requested
+                    .get(hash)
+                    .map_or(true, |at| now.duration_since(*at) >= EMOTE_REQUEST_RETRY);
+                if due {
+                    self.requested.insert(hash.clone(), now);
+                }
+                due
+            })
+            .collect()
+    }
+
+    /// Whether we asked for `hash` and are still waiting for it
+    pub fn is_pending(&self, hash: &str) -> bool {
+        self.requested.contains_key(hash)
+    }
+
+    /// Forget a pending request once its data has arrived
+    pub fn mark_received(&mut self, hash: &str) {
+        self.requested.remove(hash);
+    }
+}
+
+/// Check an emote image and shrink it to fit within MAX_EMOTE_DIMENSION,
+/// preserving aspect ratio. Returns the bytes to store and their extension.
+pub fn prepare_emote_image(bytes: &[u8], ext: &str) -> Result<(Vec<u8>, String)> {
+    if bytes.len() as u64 > MAX_EMOTE_FILE_BYTES {
+        anyhow::bail!(
+            "Emote file is too large ({} KB, max {} KB)",
+            bytes.len() / 1024,
+            MAX_EMOTE_FILE_BYTES / 1024
+        );
+    }
+
+    let format = image::guess_format(bytes)
+        .map_err(|_| anyhow::anyhow!("Not a supported image file"))?;
+
+    if format == image::ImageFormat::Gif {
+        return Ok((downscale_gif(bytes)?, "gif".to_string()));
+    }
+
+    let img = image::load_from_memory_with_format(bytes, format)
+        .map_err(|e| anyhow::anyhow!("Could not read image: {}", e))?;
+    if img.width() <= MAX_EMOTE_DIMENSION && img.height() <= MAX_EMOTE_DIMENSION {
+        let ext = if ext.is_empty() { "png" } else { ext };
+        return Ok((bytes.to_vec(), ext.to_string()));
+    }
+
+    let resized = img.resize(MAX_EMOTE_DIMENSION, MAX_EMOTE_DIMENSION, image::imageops::FilterType::Lanczos3);
+    let mut out = std::io::Cursor::new(Vec::new());
+    resized.write_to(&mut out, image::ImageFormat::Png)?;
+    Ok((out.into_inner(), "png".to_string()))
+}
+
+/// Shrink an oversized GIF, keeping frame timing. Frames are read with
+/// [`decode_gif_frames`], so the stored emote keeps only the first
+/// `MAX_ANIMATION_FRAMES` frames (fewer if they pass `MAX_ANIMATION_BYTES`
+/// decoded) and plays no faster than [`MIN_FRAME_DELAY_MS`] per frame, the
+/// same as it would be shown anyway.
+fn downscale_gif(bytes: &[u8]) -> Result<Vec<u8>> {
+    let frames = decode_gif_frames(bytes)?;
+    let (width, height) = (frames[0].width, frames[0].height);
+    if width <= MAX_EMOTE_DIMENSION && height <= MAX_EMOTE_DIMENSION {
+        return Ok(bytes.to_vec());
+    }
+
+    let scale = MAX_EMOTE_DIMENSION as f32 / width.max(height) as f32;
+    let new_width = ((width as f32 * scale).round() as u32).max(1);
+    let new_height = ((height as f32 * scale).round() as u32).max(1);
+
+    let mut out = Vec::new();
+    {
+        let mut encoder = image::codecs::gif::GifEncoder::new(&mut out);
+        encoder.set_repeat(image::codecs::gif::Repeat::Infinite)?;
+        for frame in frames {
+            let buffer = image::RgbaImage::from_raw(frame.width, frame.height, frame.rgba)
+                .ok_or_else(|| anyhow::anyhow!("Corrupt GIF frame"))?;
+            let resized = image::imageops::resize(&buffer, new_width, new_height, image::imageops::FilterType::Triangle);
+            let delay = image::Delay::from_numer_denom_ms(frame.delay_ms, 1);
+            encoder.encode_frame(image::Frame::from_parts(resized, 0, 0, delay))?;
+        }
+    }
+    Ok(out)
+}
+
+/// Check GIF magic bytes
+pub fn is_gif(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a")
+}
+
+/// Decode GIF frames, clamping frame delays and stopping once the frame or
+/// memory budget is reached.
+pub fn decode_gif_frames(bytes: &[u8]) -> Result<Vec<GifFrame>> {
+    use image::AnimationDecoder;
+
+    let decoder = image::codecs::gif::GifDecoder::new(std::io::Cursor::new(bytes))?;
+    let mut frames = Vec::new();
+    let mut used_bytes = 0usize;
+
+    for frame in decoder.into_frames() {
+        let frame = frame?;
+        let (numer, denom) = frame.delay().numer_denom_ms();
+        let delay_ms = if denom == 0 { 0 } else { numer / denom };
+        let buffer = frame.into_buffer();
+
+        used_bytes += buffer.as_raw().len();
+        if frames.len() >= MAX_ANIMATION_FRAMES || used_bytes > MAX_ANIMATION_BYTES {
+            break;
+        }
+
+        frames.push(GifFrame {
+            width: buffer.width(),
+            height: buffer.height(),
+            rgba: buffer.into_raw(),
+            delay_ms: delay_ms.max(MIN_FRAME_DELAY_MS),
+        });
+    }
+
+    if frames.is_empty() {
+        anyhow::bail!("GIF contains no decodable frames");
+    }
+    Ok(frames)
+}
+
+/// Index of the frame visible after `elapsed_ms`, looping the animation
+fn frame_index_at(delays_ms: &[u32], total_ms: u64, elapsed_ms: u64) -> usize {
+    if total_ms == 0 {
+        return 0;
+    }
+    let mut t = elapsed_ms % total_ms;
+    for (idx, delay) in delays_ms.iter().enumerate() {
+        if t < *delay as u64 {
+            return idx;
+        }
+        t -= *delay as u64;
+    }
+    delays_ms.len().saturating_sub(1)
 }
 
 fn get_data_dir() -> PathBuf {
-    let base = std::env::var("USERPROFILE").unwrap_or_else(|_| ".".to_string());
-    let instance_suffix = std::env::args()
-        .skip_while(|a| a != "--instance")
-        .nth(1)
-        .map(|i| format!("_{}", i))
-        .unwrap_or_default();
-    
-    PathBuf::from(format!("{}/.cryptochat{}/emotes", base, instance_suffix))
+    crate::paths::data_dir().unwrap_or_else(|_| PathBuf::from(".")).join("emotes")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{codecs::gif::GifEncoder, Delay, Frame, Rgba, RgbaImage};
+
+    fn temp_manager() -> (EmoteManager, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("cryptochat_emotes_{}", uuid::Uuid::new_v4()));
+        (EmoteManager::with_base_path(dir.clone()), dir)
+    }
+
+    /// Tiny distinct PNG for library tests
+    fn png(shade: u8) -> Vec<u8> {
+        sample_png_colored(2, 2, shade)
+    }
+
+    fn sample_png_colored(width: u32, height: u32, shade: u8) -> Vec<u8> {
+        let img = RgbaImage::from_pixel(width, height, Rgba([shade, 128, 255, 255]));
+        let mut out = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut out, image::ImageFormat::Png).unwrap();
+        out.into_inner()
+    }
+
+    fn write_source(dir: &Path, name: &str, bytes: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn sample_gif(frame_count: usize, delay_ms: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            for i in 0..frame_count {
+                let shade = (i * 60) as u8;
+                let img = RgbaImage::from_pixel(4, 4, Rgba([shade, 0, 0, 255]));
+                let frame = Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(delay_ms, 1));
+                encoder.encode_frame(frame).unwrap();
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn detects_gif_magic() {
+        assert!(is_gif(&sample_gif(1, 100)));
+        assert!(is_gif(b"GIF87a..."));
+        assert!(!is_gif(&[0x89, b'P', b'N', b'G']));
+        assert!(!is_gif(&[0xFF, 0xD8, 0xFF]));
+    }
+
+    #[test]
+    fn extracts_frames_from_sample() {
+        let frames = decode_gif_frames(&sample_gif(3, 100)).unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!((frames[0].width, frames[0].height), (4, 4));
+        assert_eq!(frames[0].rgba.len(), 4 * 4 * 4);
+        assert_eq!(frames[1].delay_ms, 100);
+    }
+
+    #[test]
+    fn clamps_fast_frame_delays() {
+        let frames = decode_gif_frames(&sample_gif(2, 10)).unwrap();
+        assert!(frames.iter().all(|f| f.delay_ms == MIN_FRAME_DELAY_MS));
+    }
+
+    #[test]
+    fn invalid_gif_fails_to_decode() {
+        assert!(decode_gif_frames(b"GIF89a garbage").is_err());
+    }
+
+    #[test]
+    fn frame_index_loops() {
+        let delays = [100, 200, 100];
+        assert_eq!(frame_index_at(&delays, 400, 0), 0);
+        assert_eq!(frame_index_at(&delays, 400, 150), 1);
+        assert_eq!(frame_index_at(&delays, 400, 350), 2);
+        assert_eq!(frame_index_at(&delays, 400, 450), 0);
+    }
+
+    #[test]
+    fn list_and_remove_emotes() {
+        let (manager, dir) = temp_manager();
+        let a = write_source(&dir, "a.png", &png(1));
+        let b = write_source(&dir, "b.png", &png(2));
+        let pog = manager.import_emote(&a, "pog".to_string()).unwrap();
+        let kek = manager.import_emote(&b, "kek".to_string()).unwrap();
+
+        assert_eq!(
+            manager.list_emotes(),
+            vec![("kek".to_string(), kek.hash.clone()), ("pog".to_string(), pog.hash.clone())]
+        );
+
+        assert!(manager.remove_emote("pog").unwrap());
+        assert!(!manager.remove_emote("pog").unwrap());
+        assert_eq!(manager.list_emotes(), vec![("kek".to_string(), kek.hash.clone())]);
+        assert!(manager.get_emote_path(&pog.hash).is_none());
+        assert!(!dir.join("library").join(format!("{}.png", pog.hash)).exists());
+
+        // Removal is persisted
+        let reloaded = EmoteManager::with_base_path(dir.clone());
+        assert_eq!(reloaded.list_emotes().len(), 1);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn shared_file_kept_until_last_name_removed() {
+        let (manager, dir) = temp_manager();
+        let src = write_source(&dir, "same.png", &png(3));
+        let first = manager.import_emote(&src, "one".to_string()).unwrap();
+        manager.import_emote(&src, "two".to_string()).unwrap();
+        let file = dir.join("library").join(format!("{}.png", first.hash));
+
+        manager.remove_emote("one").unwrap();
+        assert!(file.exists());
+        assert!(manager.get_emote_path(&first.hash).is_some());
+
+        manager.remove_emote("two").unwrap();
+        assert!(!file.exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn cleanup_removes_orphaned_files() {
+        let (manager, dir) = temp_manager();
+        let src = write_source(&dir, "keep.png", &png(4));
+        let kept = manager.import_emote(&src, "keep".to_string()).unwrap();
+        fs::write(dir.join("library").join("deadbeef.png"), b"orphan").unwrap();
+
+        assert_eq!(manager.cleanup_orphaned_files().unwrap(), 1);
+        assert!(!dir.join("library").join("deadbeef.png").exists());
+        assert!(dir.join("library").join(format!("{}.png", kept.hash)).exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn detects_missing_hashes() {
+        let (manager, dir) = temp_manager();
+        let src = write_source(&dir, "have.png", &png(5));
+        let have = manager.import_emote(&src, "have".to_string()).unwrap();
+
+        let mut emotes = HashMap::new();
+        emotes.insert("have".to_string(), have.hash.clone());
+        emotes.insert("gone".to_string(), "abc123".to_string());
+        emotes.insert("gone_alias".to_string(), "abc123".to_string());
+        assert_eq!(manager.missing_hashes(&emotes), vec!["abc123".to_string()]);
+
+        manager.save_to_cache("abc123", b"remote emote").unwrap();
+        assert!(manager.missing_hashes(&emotes).is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn received_emotes_must_match_their_hash() {
+        let (manager, dir) = temp_manager();
+        let data = b"remote emote";
+        let hash = format!("{:x}", Sha256::digest(data));
+
+        assert!(manager.save_received("../../escape", data).is_err());
+        assert!(manager.save_received(&"0".repeat(64), data).is_err());
+        assert!(manager.save_received(&hash.to_uppercase(), data).is_err());
+        assert!(manager.get_emote_path(&hash).is_none());
+
+        manager.save_received(&hash, data).unwrap();
+        assert!(manager.get_emote_path(&hash).is_some());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn request_tracker_dedups_until_retry() {
+        let mut tracker = EmoteRequestTracker::new();
+        let start = Instant::now();
+        let hashes = vec!["a".to_string(), "b".to_string()];
+
+        assert_eq!(tracker.take_new(hashes.clone(), start), hashes);
+        assert!(tracker.take_new(hashes.clone(), start + Duration::from_secs(1)).is_empty());
+        assert_eq!(tracker.take_new(vec!["a".to_string()], start + EMOTE_REQUEST_RETRY), vec!["a".to_string()]);
+
+        assert!(tracker.is_pending("b"));
+        assert!(!tracker.is_pending("c"));
+        tracker.mark_received("b");
+        assert!(!tracker.is_pending("b"));
+        assert_eq!(tracker.take_new(vec!["b".to_string()], start + Duration::from_secs(2)), vec!["b".to_string()]);
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let (manager, dir) = temp_manager();
+        manager.set_max_cache_bytes(25);
+
+        manager.save_to_cache("aaaa", &[1u8; 10]).unwrap();
+        manager.save_to_cache("bbbb", &[2u8; 10]).unwrap();
+        // Touch "aaaa" so "bbbb" becomes the oldest
+        assert!(manager.get_emote_path("aaaa").is_some());
+        manager.save_to_cache("cccc", &[3u8; 10]).unwrap();
+
+        assert!(manager.get_emote_path("aaaa").is_some());
+        assert!(manager.get_emote_path("bbbb").is_none());
+        assert!(manager.get_emote_path("cccc").is_some());
+        assert!(!dir.join("cache").join("bbbb.png").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn cache_never_evicts_owned_emotes() {
+        let (manager, dir) = temp_manager();
+        manager.set_max_cache_bytes(15);
+        let src = write_source(&dir, "mine.png", &png(6));
+        let mine = manager.import_emote(&src, "mine".to_string()).unwrap();
+
+        // Same image also arrived from a peer before we imported it
+        manager.save_to_cache(&mine.hash, &[9u8; 10]).unwrap();
+        manager.save_to_cache("dddd", &[4u8; 10]).unwrap();
+
+        assert!(dir.join("cache").join(format!("{}.png", mine.hash)).exists());
+        assert!(manager.get_emote_path(&mine.hash).is_some());
+        // Newly inserted entry is kept even though the cap is exceeded
+        assert!(manager.get_emote_path("dddd").is_some());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn pack_round_trip() {
+        let (source, source_dir) = temp_manager();
+        let a = write_source(&source_dir, "a.png", &png(7));
+        let b = write_source(&source_dir, "b.png", &png(8));
+        let pog = source.import_emote(&a, "pog".to_string()).unwrap();
+        source.import_emote(&b, "kek".to_string()).unwrap();
+
+        let pack = source.export_pack(&["pog".to_string(), "kek".to_string()]).unwrap();
+
+        let (target, target_dir) = temp_manager();
+        let mut added = target.import_pack(&pack).unwrap();
+        added.sort();
+        assert_eq!(added, vec!["kek".to_string(), "pog".to_string()]);
+        assert_eq!(target.list_emotes(), source.list_emotes());
+        let path = target.get_emote_path(&pog.hash).unwrap();
+        assert_eq!(fs::read(path).unwrap(), png(7));
+
+        let _ = fs::remove_dir_all(&source_dir);
+        let _ = fs::remove_dir_all(&target_dir);
+    }
+
+    #[test]
+    fn pack_import_handles_collisions() {
+        let (source, source_dir) = temp_manager();
+        let src = write_source(&source_dir, "pog.png", &png(9));
+        source.import_emote(&src, "pog".to_string()).unwrap();
+        let same = write_source(&source_dir, "same.png", &png(10));
+        source.import_emote(&same, "same".to_string()).unwrap();
+        let pack = source.export_pack(&["pog".to_string(), "same".to_string()]).unwrap();
+
+        let (target, target_dir) = temp_manager();
+        let mine = write_source(&target_dir, "pog.png", &png(11));
+        target.import_emote(&mine, "pog".to_string()).unwrap();
+        let same = write_source(&target_dir, "same.png", &png(10));
+        target.import_emote(&same, "same".to_string()).unwrap();
+
+        // Identical emote is skipped, different emote with the same name is suffixed
+        assert_eq!(target.import_pack(&pack).unwrap(), vec!["pog_2".to_string()]);
+        assert_eq!(target.list_emotes().len(), 3);
+        // Importing again adds nothing
+        assert!(target.import_pack(&pack).unwrap().is_empty());
+
+        let _ = fs::remove_dir_all(&source_dir);
+        let _ = fs::remove_dir_all(&target_dir);
+    }
+
+    #[test]
+    fn export_unknown_emote_fails() {
+        let (manager, dir) = temp_manager();
+        assert!(manager.export_pack(&["nope".to_string()]).is_err());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn oversized_image_is_downscaled_preserving_aspect() {
+        let (bytes, ext) = prepare_emote_image(&sample_png_colored(512, 256, 0), "png").unwrap();
+        assert_eq!(ext, "png");
+        let img = image::load_from_memory(&bytes).unwrap();
+        assert_eq!((img.width(), img.height()), (128, 64));
+    }
+
+    #[test]
+    fn small_image_is_stored_unchanged() {
+        let original = sample_png_colored(32, 32, 0);
+        let (bytes, _) = prepare_emote_image(&original, "png").unwrap();
+        assert_eq!(bytes, original);
+    }
+
+    #[test]
+    fn oversized_gif_keeps_all_frames() {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            for _ in 0..2 {
+                let img = RgbaImage::from_pixel(256, 256, Rgba([255, 0, 0, 255]));
+                encoder.encode_frame(Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(100, 1))).unwrap();
+            }
+        }
+        let (scaled, ext) = prepare_emote_image(&bytes, "gif").unwrap();
+        assert_eq!(ext, "gif");
+        let frames = decode_gif_frames(&scaled).unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].width, frames[0].height), (128, 128));
+    }
+
+    fn square_gif(side: u32, frame_count: usize, delay_ms: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        {
+            let mut encoder = GifEncoder::new(&mut bytes);
+            for i in 0..frame_count {
+                let img = RgbaImage::from_pixel(side, side, Rgba([(i % 256) as u8, 0, 0, 255]));
+                encoder.encode_frame(Frame::from_parts(img, 0, 0, Delay::from_numer_denom_ms(delay_ms, 1))).unwrap();
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn downscaled_gif_is_cut_at_the_decode_caps() {
+        // Frame cap: small enough frames that the byte budget isn't reached
+        let long = square_gif(130, MAX_ANIMATION_FRAMES + 5, 10);
+        let frames = decode_gif_frames(&downscale_gif(&long).unwrap()).unwrap();
+        assert_eq!(frames.len(), MAX_ANIMATION_FRAMES);
+        assert!(frames.iter().all(|f| f.delay_ms == MIN_FRAME_DELAY_MS));
+
+        // Byte cap: 256x256 RGBA frames fill the budget after 32
+        let large = square_gif(256, 40, 100);
+        let per_frame = 256 * 256 * 4;
+        let frames = decode_gif_frames(&downscale_gif(&large).unwrap()).unwrap();
+        assert_eq!(frames.len(), MAX_ANIMATION_BYTES / per_frame);
+        assert_eq!((frames[0].width, frames[0].height), (128, 128));
+        assert!(frames.iter().all(|f| f.delay_ms == 100));
+    }
+
+    #[test]
+    fn rejects_too_large_and_non_image_files() {
+        let (manager, dir) = temp_manager();
+        let big = write_source(&dir, "big.png", &vec![0u8; MAX_EMOTE_FILE_BYTES as usize + 1]);
+        let err = manager.import_emote(&big, "big".to_string()).unwrap_err();
+        assert!(err.to_string().contains("too large"));
+
+        let text = write_source(&dir, "notes.png", b"definitely not an image");
+        let err = manager.import_emote(&text, "notes".to_string()).unwrap_err();
+        assert!(err.to_string().contains("Not a supported image"));
+        assert!(manager.list_emotes().is_empty());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
 }
@@ -9,14 +9,14 @@ use aes_gcm::{
     aead::{Aead, KeyInit, OsRng},
     Aes256Gcm, Nonce,
 };
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use pbkdf2::pbkdf2_hmac;
 use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use serde::de::DeserializeOwned;
 use sha2::Sha256;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Number of PBKDF2 iterations for key derivation
 const PBKDF2_ITERATIONS: u32 = 100_000;
@@ -108,48 +108,67 @@ pub fn decrypt_data<T: DeserializeOwned>(store: &EncryptedStore, storage_key: &[
 
 /// Get the path to the encrypted chat history file
 fn get_encrypted_history_path() -> Result<PathBuf> {
-    let home = std::env::var("USERPROFILE")
-        .or_else(|_| std::env::var("HOME"))
-        .context("Could not find home directory")?;
-    
-    let dir_name = match crate::get_instance_id() {
-        Some(id) => format!(".cryptochat_{}", id),
-        None => ".cryptochat".to_string(),
+    Ok(crate::paths::data_dir()?.join("chat_history.enc"))
+}
+
+/// On-disk schema version of the encrypted chat history.
+///
+/// - 0: bare message list (plaintext `chat_history.json`, or encrypted
+///   without a version field)
+/// - 1: `{ version, messages }`, encrypted
+pub const HISTORY_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+struct VersionedHistory<'a> {
+    version: u32,
+    messages: &'a [StoredMessage],
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AnyHistory {
+    Versioned { version: u32, messages: Vec<StoredMessage> },
+    Unversioned(Vec<StoredMessage>),
+}
+
+/// Write messages to `path` in the current versioned format
+pub fn write_history_file(path: &Path, messages: &[StoredMessage], storage_key: &[u8; 32]) -> Result<()> {
+    let history = VersionedHistory { version: HISTORY_VERSION, messages };
+    let encrypted = encrypt_data(&history, storage_key)?;
+    fs::write(path, serde_json::to_vec(&encrypted)?).context("Failed to write chat history")?;
+    Ok(())
+}
+
+/// Read an encrypted history file, returning its schema version and messages
+/// (`None` if there is no file)
+pub fn read_history_file(path: &Path, storage_key: &[u8; 32]) -> Result<Option<(u32, Vec<StoredMessage>)>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let json = fs::read(path)?;
+    let encrypted: EncryptedStore = serde_json::from_slice(&json)?;
+    let (version, messages) = match decrypt_data(&encrypted, storage_key)? {
+        AnyHistory::Versioned { version, messages } => (version, messages),
+        AnyHistory::Unversioned(messages) => (0, messages),
     };
-    
-    let data_dir = PathBuf::from(home).join(dir_name);
-    if !data_dir.exists() {
-        fs::create_dir_all(&data_dir)?;
+    if version > HISTORY_VERSION {
+        bail!("Chat history was written by a newer version of CryptoChat (schema {})", version);
     }
-    
-    Ok(data_dir.join("chat_history.enc"))
+    Ok(Some((version, messages)))
 }
 
 /// Save messages to encrypted file
 pub fn save_encrypted_history(messages: &[StoredMessage], fingerprint: &str) -> Result<()> {
     let storage_key = derive_storage_key(fingerprint);
-    let encrypted = encrypt_data(messages, &storage_key)?;
-    
-    let path = get_encrypted_history_path()?;
-    let json = serde_json::to_vec(&encrypted)?;
-    fs::write(&path, json)?;
-    
-    Ok(())
+    write_history_file(&get_encrypted_history_path()?, messages, &storage_key)
 }
 
 /// Load messages from encrypted file
 pub fn load_encrypted_history(fingerprint: &str) -> Result<Vec<StoredMessage>> {
-    let path = get_encrypted_history_path()?;
-    
-    if !path.exists() {
-        return Ok(Vec::new());
-    }
-    
-    let json = fs::read(&path)?;
-    let encrypted: EncryptedStore = serde_json::from_slice(&json)?;
-    
     let storage_key = derive_storage_key(fingerprint);
-    decrypt_data(&encrypted, &storage_key)
+    let history = read_history_file(&get_encrypted_history_path()?, &storage_key)?;
+    Ok(history.map(|(_, messages)| messages).unwrap_or_default())
 }
 
 /// Remove expired messages (for disappearing message feature)
@@ -172,6 +191,17 @@ pub fn cleanup_expired_messages(messages: &mut Vec<StoredMessage>) -> usize {
     initial_count - messages.len()
 }
 
+/// Remove expired messages from the encrypted history file, rewriting it only
+/// when something was dropped
+pub fn purge_expired_history(fingerprint: &str) -> Result<usize> {
+    let mut messages = load_encrypted_history(fingerprint)?;
+    let removed = cleanup_expired_messages(&mut messages);
+    if removed > 0 {
+        save_encrypted_history(&messages, fingerprint)?;
+    }
+    Ok(removed)
+}
+
 /// Check if there's an existing unencrypted history file to migrate
 pub fn has_unencrypted_history() -> bool {
     if let Ok(path) = get_encrypted_history_path() {
@@ -0,0 +1,85 @@
+//! Native open/save file dialogs
+//!
+//! Thin wrapper over `rfd` so the rest of the client deals only in
+//! `Option<PathBuf>`: `None` means the user cancelled. These block until the
+//! dialog closes, so call them from `spawn_blocking`.
+
+use rfd::FileDialog;
+use std::path::PathBuf;
+
+/// Dialog filter: display name and extensions (without the dot)
+pub type Filter<'a> = (&'a str, &'a [&'a str]);
+
+pub const ALL_FILES: Filter<'static> = ("All Files", &["*"]);
+pub const IMAGES: Filter<'static> = ("Images", &["png", "jpg", "jpeg", "gif"]);
+pub const JSON: Filter<'static> = ("JSON", &["json"]);
+
+/// Everything a dialog is shown with, kept apart from `rfd` so what each
+/// helper asks for can be checked without a window
+#[derive(Debug, PartialEq, Eq)]
+struct DialogOptions<'a> {
+    title: &'a str,
+    filters: &'a [Filter<'a>],
+    /// Pre-filled name (save dialogs only)
+    file_name: Option<&'a str>,
+}
+
+impl DialogOptions<'_> {
+    fn dialog(&self) -> FileDialog {
+        let dialog = self.filters.iter().fold(FileDialog::new().set_title(self.title), |dialog, (name, extensions)| {
+            dialog.add_filter(*name, extensions)
+        });
+        match self.file_name {
+            Some(name) => dialog.set_file_name(name),
+            None => dialog,
+        }
+    }
+}
+
+fn pick_options<'a>(title: &'a str, filters: &'a [Filter<'a>]) -> DialogOptions<'a> {
+    DialogOptions { title, filters, file_name: None }
+}
+
+fn save_options<'a>(title: &'a str, filters: &'a [Filter<'a>], default_name: &'a str) -> DialogOptions<'a> {
+    DialogOptions { title, filters, file_name: Some(default_name).filter(|name| !name.is_empty()) }
+}
+
+/// Show a dialog with `show`; kept separate so the cancel path can be exercised
+/// without a window
+fn run(dialog: FileDialog, show: impl FnOnce(FileDialog) -> Option<PathBuf>) -> Option<PathBuf> {
+    show(dialog)
+}
+
+/// Ask the user for an existing file
+pub fn pick_file(title: &str, filters: &[Filter]) -> Option<PathBuf> {
+    run(pick_options(title, filters).dialog(), FileDialog::pick_file)
+}
+
+/// Ask the user where to save, pre-filling `default_name`
+pub fn save_file(title: &str, filters: &[Filter], default_name: &str) -> Option<PathBuf> {
+    run(save_options(title, filters, default_name).dialog(), FileDialog::save_file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn helpers_ask_for_their_filters_and_name() {
+        let pick = pick_options("Select file", &[IMAGES, ALL_FILES]);
+        assert_eq!(pick.filters, [("Images", &["png", "jpg", "jpeg", "gif"][..]), ("All Files", &["*"][..])]);
+        assert_eq!(pick.file_name, None);
+
+        let save = save_options("Export Contacts", &[JSON], "contacts.json");
+        assert_eq!(save.filters, [JSON]);
+        assert_eq!(save.file_name, Some("contacts.json"));
+        // No name to suggest leaves the field empty rather than set to ""
+        assert_eq!(save_options("Export", &[JSON], "").file_name, None);
+    }
+
+    #[test]
+    fn cancelled_dialog_returns_none() {
+        let picked = run(save_options("Export", &[JSON], "contacts.json").dialog(), |_| None);
+        assert_eq!(picked, None);
+    }
+}
@@ -0,0 +1,72 @@
+//! Finding a font that can draw emoji
+//!
+//! The emoji font is read from the system at startup rather than compiled
+//! in, so the app builds anywhere and still starts on a machine without it.
+//! The first known emoji font that exists is loaded; with none of them the
+//! default font is used and emoji may show as boxes.
+
+use iced::Font;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Family name of the emoji font that was loaded, if any
+static EMOJI_FAMILY: OnceLock<Option<&'static str>> = OnceLock::new();
+
+/// Emoji fonts to try, most preferred first, as (family name, file)
+fn system_candidates() -> Vec<(&'static str, PathBuf)> {
+    let windows = std::env::var_os("WINDIR").map(PathBuf::from).unwrap_or_else(|| PathBuf::from(r"C:\Windows"));
+    vec![
+        ("Segoe UI Emoji", windows.join("Fonts").join("seguiemj.ttf")),
+        ("Noto Color Emoji", PathBuf::from("/usr/share/fonts/truetype/noto/NotoColorEmoji.ttf")),
+        ("Noto Color Emoji", PathBuf::from("/usr/share/fonts/noto/NotoColorEmoji.ttf")),
+        ("Apple Color Emoji", PathBuf::from("/System/Library/Fonts/Apple Color Emoji.ttc")),
+    ]
+}
+
+/// The first candidate `read` can load, with its bytes
+fn resolve(
+    candidates: &[(&'static str, PathBuf)],
+    read: impl Fn(&Path) -> Option<Vec<u8>>,
+) -> Option<(&'static str, Vec<u8>)> {
+    candidates.iter().find_map(|(family, path)| read(path).map(|bytes| (*family, bytes)))
+}
+
+/// Look for an emoji font on this system and remember which one was found.
+/// Returns its bytes for iced to load; call once, before the app starts.
+pub fn load_emoji_font() -> Option<Vec<u8>> {
+    let found = resolve(&system_candidates(), |path| std::fs::read(path).ok());
+    match &found {
+        Some((family, _)) => tracing::info!(family, "loaded emoji font"),
+        None => tracing::warn!("no emoji font found; using the default font"),
+    }
+    EMOJI_FAMILY.set(found.as_ref().map(|(family, _)| *family)).ok();
+    found.map(|(_, bytes)| bytes)
+}
+
+/// Font for text containing emoji
+pub fn emoji_font() -> Font {
+    match EMOJI_FAMILY.get().copied().flatten() {
+        Some(family) => Font::with_name(family),
+        None => Font::DEFAULT,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn falls_back_when_the_primary_font_is_missing() {
+        let candidates = vec![
+            ("Segoe UI Emoji", PathBuf::from("missing/seguiemj.ttf")),
+            ("Noto Color Emoji", PathBuf::from("present/NotoColorEmoji.ttf")),
+        ];
+        let read = |path: &Path| path.starts_with("present").then(|| b"font".to_vec());
+        assert_eq!(resolve(&candidates, read), Some(("Noto Color Emoji", b"font".to_vec())));
+
+        let primary_only = |path: &Path| path.starts_with("missing").then(|| b"segoe".to_vec());
+        assert_eq!(resolve(&candidates, primary_only), Some(("Segoe UI Emoji", b"segoe".to_vec())));
+
+        assert_eq!(resolve(&candidates, |_| None), None);
+    }
+}
@@ -0,0 +1,165 @@
+//! Forwarding a message into another conversation
+//!
+//! The forwarded copy is built from our decrypted local message and encrypted
+//! afresh for the new recipient; the original ciphertext, which only we can
+//! read, is never relayed.
+
+use anyhow::{bail, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use cryptochat_crypto_core::pgp::PgpKeyPair;
+
+use crate::conversation::{ChatMessage, DeliveryStatus};
+use crate::network::MessageEnvelope;
+use crate::EmotePayload;
+
+/// Our outgoing copy of `original`, marked as forwarded
+pub fn forwarded_copy(original: &ChatMessage, my_name: &str, timestamp: String, now_ms: i64) -> ChatMessage {
+    let id = uuid::Uuid::new_v4();
+    ChatMessage {
+        sender_name: my_name.to_string(),
+        content: original.content.clone(),
+        is_mine: true,
+        timestamp,
+        sent_at_ms: now_ms,
+        image_data: original.image_data.clone(),
+        image_filename: original.image_filename.clone(),
+        reactions: Vec::new(),
+        emotes: original.emotes.clone(),
+        // The forwarded copy follows the target chat's timer, not the original's
+        expires_at: None,
+        message_id: Some(id.to_string()),
+        status: DeliveryStatus::Pending,
+        delivered_to: Vec::new(),
+        forwarded: true,
+        voice: original.voice.clone(),
+        id,
+    }
+}
+
+/// Text as it goes over the wire: plain, or with its emote map attached
+pub fn wire_text(msg: &ChatMessage) -> String {
+    if msg.emotes.is_empty() {
+        return msg.content.clone();
+    }
+    let payload = EmotePayload { content: msg.content.clone(), emotes: msg.emotes.clone() };
+    serde_json::to_string(&payload).unwrap_or_else(|_| msg.content.clone())
+}
+
+/// Envelope delivering `msg` to a direct contact, encrypted for their key
+pub fn direct_envelope(
+    msg: &ChatMessage,
+    my_key: &PgpKeyPair,
+    recipient_public_key: &str,
+    sender_name: &str,
+    sender_fingerprint: &str,
+    listening_port: u16,
+) -> Result<MessageEnvelope> {
+    let recipient = PgpKeyPair::from_public_key(recipient_public_key)?;
+    if let Some(data) = &msg.image_data {
+        let encrypted = PgpKeyPair::encrypt(recipient.cert(), data)?;
+        return Ok(MessageEnvelope::FileMessage {
+            filename: msg.image_filename.clone().unwrap_or_else(|| "image.png".to_string()),
+            encrypted_data: STANDARD.encode(encrypted),
+            sender_name: Some(sender_name.to_string()),
+            sender_fingerprint: sender_fingerprint.to_string(),
+            sender_listening_port: listening_port,
+            message_id: msg.message_id.clone(),
+        });
+    }
+    if msg.voice.is_some() {
+        bail!("Voice messages can't be forwarded yet");
+    }
+    if msg.image_filename.is_some() {
+        bail!("Only the file name was kept, so this file can't be forwarded");
+    }
+    let encrypted = my_key.encrypt_and_sign(recipient.cert(), wire_text(msg).as_bytes())?;
+    Ok(MessageEnvelope::RegularMessage {
+        encrypted_payload: STANDARD.encode(encrypted),
+        sender_name: Some(sender_name.to_string()),
+        sender_fingerprint: sender_fingerprint.to_string(),
+        sender_listening_port: listening_port,
+        message_id: msg.message_id.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn received(content: &str) -> ChatMessage {
+        ChatMessage {
+            sender_name: "Bob".to_string(),
+            content: content.to_string(),
+            is_mine: false,
+            timestamp: "09:00".to_string(),
+            sent_at_ms: 1,
+            image_data: None,
+            image_filename: None,
+            reactions: vec![("👍".to_string(), "Alice".to_string())],
+            emotes: HashMap::from([("wave".to_string(), "abcd".to_string())]),
+            expires_at: Some("2030-01-01T00:00:00Z".to_string()),
+            message_id: None,
+            status: DeliveryStatus::Sent,
+            delivered_to: Vec::new(),
+            forwarded: false,
+            voice: None,
+            id: uuid::Uuid::new_v4(),
+        }
+    }
+
+    #[test]
+    fn forwarded_copy_is_ours_and_marked() {
+        let copy = forwarded_copy(&received("hello :wave:"), "Alice", "10:30".to_string(), 42);
+        assert!(copy.forwarded && copy.is_mine);
+        assert_eq!(copy.sender_name, "Alice");
+        assert_eq!(copy.content, "hello :wave:");
+        assert_eq!(copy.emotes.get("wave").map(String::as_str), Some("abcd"));
+        assert!(copy.reactions.is_empty());
+        assert_eq!(copy.expires_at, None);
+        assert_eq!(copy.status, DeliveryStatus::Pending);
+        assert!(copy.message_id.is_some());
+    }
+
+    #[test]
+    fn forward_is_encrypted_for_the_new_recipient() {
+        let me = PgpKeyPair::generate("me@example.com").unwrap();
+        let carol = PgpKeyPair::generate("carol@example.com").unwrap();
+        let bob = PgpKeyPair::generate("bob@example.com").unwrap();
+        let copy = forwarded_copy(&received("secret plan"), "me", "10:30".to_string(), 42);
+
+        let envelope = direct_envelope(&copy, &me, &carol.export_public_key().unwrap(), "me", "FP_ME", 9000).unwrap();
+        let MessageEnvelope::RegularMessage { encrypted_payload, message_id, .. } = envelope else {
+            panic!("expected a text message");
+        };
+        assert_eq!(message_id, copy.message_id);
+        let ciphertext = STANDARD.decode(encrypted_payload).unwrap();
+        let plaintext = carol.decrypt_and_verify(me.cert(), &ciphertext).unwrap();
+        assert_eq!(EmotePayload::parse(&String::from_utf8(plaintext).unwrap()).content, "secret plan");
+        // The original recipient can't read the forwarded copy
+        assert!(bob.decrypt_and_verify(me.cert(), &ciphertext).is_err());
+    }
+
+    #[test]
+    fn forwarded_images_go_as_files() {
+        let me = PgpKeyPair::generate("me@example.com").unwrap();
+        let carol = PgpKeyPair::generate("carol@example.com").unwrap();
+        let image = ChatMessage {
+            image_data: Some(vec![1, 2, 3]),
+            image_filename: Some("cat.png".to_string()),
+            ..received("[Image: cat.png]")
+        };
+        let copy = forwarded_copy(&image, "me", "10:30".to_string(), 42);
+
+        let envelope = direct_envelope(&copy, &me, &carol.export_public_key().unwrap(), "me", "FP_ME", 9000).unwrap();
+        let MessageEnvelope::FileMessage { filename, encrypted_data, .. } = envelope else {
+            panic!("expected a file message");
+        };
+        assert_eq!(filename, "cat.png");
+        assert_eq!(carol.decrypt(&STANDARD.decode(encrypted_data).unwrap()).unwrap(), vec![1, 2, 3]);
+
+        let file_only = ChatMessage { image_filename: Some("report.pdf".to_string()), ..received("[File: report.pdf]") };
+        assert!(direct_envelope(&file_only, &me, &carol.export_public_key().unwrap(), "me", "FP_ME", 9000).is_err());
+    }
+}
@@ -0,0 +1,167 @@
+//! Signatures on group control messages
+//!
+//! Leaves, key rotations and name/admin updates change the group for
+//! everyone who receives them, so the sender has to prove who they are
+//! rather than just name themselves in the envelope. The sender signs the
+//! action, the group id and the action's fields with their identity key,
+//! and receivers check it against the public key the group already has on
+//! file for that member. A key carried in the message itself is never
+//! trusted.
+
+use cryptochat_crypto_core::pgp::PgpKeyPair;
+
+use crate::group_store::Group;
+
+/// Group control messages that carry a signature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupAction {
+    /// A member leaving (signed by that member)
+    Leave,
+    /// A new key after a member left (signed by the rotating admin)
+    KeyRotation,
+    /// A new name, avatar or admin list (signed by the admin making it)
+    MetadataUpdate,
+}
+
+/// What a group control message signs
+///
+/// Encoded as JSON so no field can bleed into the next one.
+pub fn statement(action: GroupAction, group_id: &str, signer: &str, fields: &[&str]) -> Vec<u8> {
+    let action = format!("{:?}", action);
+    serde_json::to_vec(&("cryptochat-group-v1", action, group_id, signer, fields))
+        .unwrap_or_default()
+}
+
+/// The signed fields of a name/avatar/admins update, in order
+pub fn metadata_fields(
+    name: &str,
+    avatar_hash: Option<&str>,
+    updated_at_ms: i64,
+    admins: &[String],
+    owner: &str,
+    roles_version: u64,
+) -> Vec<String> {
+    vec![
+        name.to_string(),
+        avatar_hash.unwrap_or_default().to_string(),
+        updated_at_ms.to_string(),
+        admins.join(","),
+        owner.to_string(),
+        roles_version.to_string(),
+    ]
+}
+
+/// Sign `action` on `group_id` as `keypair`
+pub fn sign(keypair: &PgpKeyPair, action: GroupAction, group_id: &str, fields: &[&str]) -> anyhow::Result<String> {
+    Ok(keypair.sign_detached(&statement(action, group_id, &keypair.fingerprint(), fields))?)
+}
+
+/// Whether `signature` is `signer`'s, checked against the key `group` has on
+/// file for them. Non-members are always refused.
+pub fn verify(group: &Group, signer: &str, action: GroupAction, fields: &[&str], signature: &str) -> bool {
+    if signature.is_empty() {
+        return false;
+    }
+    let Some(member) = group.members.iter().find(|m| m.fingerprint == signer) else {
+        return false;
+    };
+    PgpKeyPair::parse_public_key(&member.public_key)
+        .ok()
+        .filter(|key| key.fingerprint() == signer)
+        .is_some_and(|key| {
+            let statement = statement(action, &group.id, signer, fields);
+            PgpKeyPair::verify_detached(key.cert(), &statement, signature).is_ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::group_store::{GroupMember, GroupSettings, InvitePermission};
+
+    fn member(keypair: &PgpKeyPair) -> GroupMember {
+        GroupMember {
+            fingerprint: keypair.fingerprint(),
+            username: "member".to_string(),
+            public_key: keypair.export_public_key().unwrap(),
+            address: String::new(),
+            joined_at: String::new(),
+        }
+    }
+
+    /// A group of `members` with `admin` as its owner and only admin
+    fn group(admin: &PgpKeyPair, members: &[&PgpKeyPair]) -> Group {
+        Group {
+            id: "g1".to_string(),
+            name: "Friends".to_string(),
+            created_at: String::new(),
+            creator_fingerprint: admin.fingerprint(),
+            members: members.iter().map(|keypair| member(keypair)).collect(),
+            admins: vec![admin.fingerprint()],
+            settings: GroupSettings {
+                invite_permission: InvitePermission::AdminsOnly,
+                max_members: None,
+                disappearing_timer_secs: None,
+            },
+            symmetric_key: vec![1; 32],
+            avatar_hash: None,
+            metadata_updated_ms: 0,
+            metadata_updated_by: String::new(),
+            membership_epoch: 0,
+            join_requests: Vec::new(),
+            roles_version: 0,
+            roles_updated_by: String::new(),
+        }
+    }
+
+    #[test]
+    fn only_the_members_own_key_signs_for_them() {
+        let alice = PgpKeyPair::generate("alice").unwrap();
+        let mallory = PgpKeyPair::generate("mallory").unwrap();
+        let outsider = PgpKeyPair::generate("outsider").unwrap();
+        let group = group(&alice, &[&alice, &mallory]);
+        let alice_fp = alice.fingerprint();
+
+        let signature = sign(&alice, GroupAction::Leave, &group.id, &[&alice_fp]).unwrap();
+        assert!(verify(&group, &alice_fp, GroupAction::Leave, &[&alice_fp], &signature));
+
+        // Another action, other fields, another group or an unsigned message
+        assert!(!verify(&group, &alice_fp, GroupAction::KeyRotation, &[&alice_fp], &signature));
+        assert!(!verify(&group, &alice_fp, GroupAction::Leave, &["someone"], &signature));
+        let mut other = group.clone();
+        other.id = "other".to_string();
+        assert!(!verify(&other, &alice_fp, GroupAction::Leave, &[&alice_fp], &signature));
+        assert!(!verify(&group, &alice_fp, GroupAction::Leave, &[&alice_fp], ""));
+
+        // Mallory can't leave on Alice's behalf, and outsiders can't sign at all
+        let forged = sign(&mallory, GroupAction::Leave, &group.id, &[&alice_fp]).unwrap();
+        assert!(!verify(&group, &alice_fp, GroupAction::Leave, &[&alice_fp], &forged));
+        let outsider_fp = outsider.fingerprint();
+        let signature = sign(&outsider, GroupAction::Leave, &group.id, &[&outsider_fp]).unwrap();
+        assert!(!verify(&group, &outsider_fp, GroupAction::Leave, &[&outsider_fp], &signature));
+    }
+
+    #[test]
+    fn metadata_updates_are_signed_over_every_field() {
+        let alice = PgpKeyPair::generate("alice").unwrap();
+        let mallory = PgpKeyPair::generate("mallory").unwrap();
+        let group = group(&alice, &[&alice, &mallory]);
+        let (alice_fp, mallory_fp) = (alice.fingerprint(), mallory.fingerprint());
+        let admins = vec![alice_fp.clone()];
+        let fields = metadata_fields("Book Club", Some("abc"), 100, &admins, &alice_fp, 1);
+        let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+
+        let signature = sign(&alice, GroupAction::MetadataUpdate, &group.id, &fields).unwrap();
+        assert!(verify(&group, &alice_fp, GroupAction::MetadataUpdate, &fields, &signature));
+
+        // Alice's signature doesn't carry over to a different admin list
+        let widened = vec![alice_fp.clone(), mallory_fp.clone()];
+        let tampered = metadata_fields("Book Club", Some("abc"), 100, &widened, &alice_fp, 1);
+        let tampered: Vec<&str> = tampered.iter().map(String::as_str).collect();
+        assert!(!verify(&group, &alice_fp, GroupAction::MetadataUpdate, &tampered, &signature));
+
+        // Mallory naming Alice as the sender doesn't make it Alice's update
+        let forged = sign(&mallory, GroupAction::MetadataUpdate, &group.id, &fields).unwrap();
+        assert!(!verify(&group, &alice_fp, GroupAction::MetadataUpdate, &fields, &forged));
+    }
+}
@@ -0,0 +1,232 @@
+//! Group message encryption bound to the membership epoch
+//!
+//! Messages are sealed with the group's symmetric key (AES-256-GCM), and the
+//! group id and membership epoch go into the associated data. A ciphertext
+//! from an earlier membership is refused once anyone has joined or left, even
+//! by a member who still holds the old key, so a captured message can't be
+//! replayed into the group after the change.
+//!
+//! Admins are the authority on the epoch: members only adopt the epoch from
+//! an admin's member sync or the invite they joined with. A member who sees
+//! messages from a later epoch (or has no key yet) asks an admin to resync
+//! rather than trusting the sender. Once a group has its key nothing is sent
+//! or accepted in the clear.
+
+use aes_gcm::{
+    aead::{Aead, KeyInit, OsRng, Payload},
+    Aes256Gcm, Nonce,
+};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::group_store::Group;
+
+/// An encrypted group message as it goes over the wire
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GroupCiphertext {
+    /// Membership epoch the sender encrypted under
+    pub epoch: u64,
+    pub nonce: Vec<u8>,
+    pub ciphertext: Vec<u8>,
+}
+
+/// Why a group message couldn't be opened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupCryptoError {
+    /// Sent under a different membership than ours
+    EpochMismatch { message: u64, current: u64 },
+    InvalidKey,
+    /// We don't have the group key yet
+    NoKey,
+    /// Plain text in a group that has a key
+    Unsealed,
+    /// Wrong key, tampered with, or a forged epoch
+    Decrypt,
+}
+
+impl fmt::Display for GroupCryptoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GroupCryptoError::EpochMismatch { message, current } => write!(
+                f,
+                "Message is from membership epoch {} but the group is at {}",
+                message, current
+            ),
+            GroupCryptoError::InvalidKey => write!(f, "Group key is not a valid AES-256 key"),
+            GroupCryptoError::NoKey => write!(f, "Waiting for the group key from an admin"),
+            GroupCryptoError::Unsealed => write!(f, "Group message was not encrypted"),
+            GroupCryptoError::Decrypt => write!(f, "Group message could not be decrypted"),
+        }
+    }
+}
+
+impl std::error::Error for GroupCryptoError {}
+
+impl GroupCryptoError {
+    /// Whether an admin's member sync could fix this: we have no key yet, or
+    /// the message comes from an epoch we haven't caught up to
+    pub fn needs_resync(&self) -> bool {
+        match self {
+            GroupCryptoError::NoKey => true,
+            GroupCryptoError::EpochMismatch { message, current } => message > current,
+            _ => false,
+        }
+    }
+}
+
+/// Associated data tying a ciphertext to one group and membership epoch
+fn associated_data(group_id: &str, epoch: u64) -> Vec<u8> {
+    let mut aad = b"cryptochat-group-v1:".to_vec();
+    aad.extend_from_slice(group_id.as_bytes());
+    aad.push(0);
+    aad.extend_from_slice(&epoch.to_be_bytes());
+    aad
+}
+
+fn cipher(group: &Group) -> Result<Aes256Gcm, GroupCryptoError> {
+    Aes256Gcm::new_from_slice(&group.symmetric_key).map_err(|_| GroupCryptoError::InvalidKey)
+}
+
+/// Encrypt `plaintext` for the group's current membership
+pub fn encrypt(group: &Group, plaintext: &[u8]) -> Result<GroupCiphertext, GroupCryptoError> {
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+    let aad = associated_data(&group.id, group.membership_epoch);
+    let ciphertext = cipher(group)?
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad: &aad })
+        .map_err(|_| GroupCryptoError::Decrypt)?;
+    Ok(GroupCiphertext { epoch: group.membership_epoch, nonce: nonce.to_vec(), ciphertext })
+}
+
+/// Decrypt a group message, refusing anything not sent under the group's
+/// current membership epoch
+pub fn decrypt(group: &Group, message: &GroupCiphertext) -> Result<Vec<u8>, GroupCryptoError> {
+    if message.epoch != group.membership_epoch {
+        return Err(GroupCryptoError::EpochMismatch { message: message.epoch, current: group.membership_epoch });
+    }
+    if message.nonce.len() != 12 {
+        return Err(GroupCryptoError::Decrypt);
+    }
+    // Authenticated against our epoch, so relabelling an old message fails here
+    let aad = associated_data(&group.id, group.membership_epoch);
+    cipher(group)?
+        .decrypt(Nonce::from_slice(&message.nonce), Payload { msg: &message.ciphertext, aad: &aad })
+        .map_err(|_| GroupCryptoError::Decrypt)
+}
+
+/// Whether the group holds a real key, not the all-zero placeholder a group
+/// joined by invite code has until the key arrives
+pub fn has_key(group: &Group) -> bool {
+    group.symmetric_key.iter().any(|&b| b != 0)
+}
+
+/// `encrypted_content` for a group message. Fails rather than falling back
+/// to plain text when we don't have the group key yet.
+pub fn seal_content(group: &Group, text: &str) -> Result<String, GroupCryptoError> {
+    if !has_key(group) {
+        return Err(GroupCryptoError::NoKey);
+    }
+    let sealed = encrypt(group, text.as_bytes())?;
+    serde_json::to_string(&sealed).map_err(|_| GroupCryptoError::Decrypt)
+}
+
+/// Text of a received `encrypted_content`. Plain text is refused.
+pub fn open_content(group: &Group, content: &str) -> Result<String, GroupCryptoError> {
+    let Ok(sealed) = serde_json::from_str::<GroupCiphertext>(content) else {
+        return Err(GroupCryptoError::Unsealed);
+    };
+    if !has_key(group) {
+        return Err(GroupCryptoError::NoKey);
+    }
+    let plaintext = decrypt(group, &sealed)?;
+    String::from_utf8(plaintext).map_err(|_| GroupCryptoError::Decrypt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::group_store::{add_member, GroupMember, GroupSettings, InvitePermission};
+
+    fn member(fingerprint: &str) -> GroupMember {
+        GroupMember {
+            fingerprint: fingerprint.to_string(),
+            username: fingerprint.to_lowercase(),
+            public_key: String::new(),
+            address: String::new(),
+            joined_at: String::new(),
+        }
+    }
+
+    fn group() -> Group {
+        Group {
+            id: "g1".to_string(),
+            name: "Friends".to_string(),
+            created_at: String::new(),
+            creator_fingerprint: "FP_A".to_string(),
+            members: vec![member("FP_A"), member("FP_B")],
+            admins: vec!["FP_A".to_string()],
+            settings: GroupSettings {
+                invite_permission: InvitePermission::AdminsOnly,
+                max_members: None,
+                disappearing_timer_secs: None,
+            },
+            symmetric_key: vec![7; 32],
+            avatar_hash: None,
+            metadata_updated_ms: 0,
+            metadata_updated_by: String::new(),
+            membership_epoch: 4,
+            join_requests: Vec::new(),
+            roles_version: 0,
+            roles_updated_by: String::new(),
+        }
+    }
+
+    #[test]
+    fn same_epoch_decrypts() {
+        let group = group();
+        let sealed = encrypt(&group, b"hello group").unwrap();
+        assert_eq!(sealed.epoch, 4);
+        assert_eq!(decrypt(&group, &sealed).unwrap(), b"hello group");
+
+        let content = seal_content(&group, "hello group").unwrap();
+        assert!(!content.contains("hello"));
+        assert_eq!(open_content(&group, &content).unwrap(), "hello group");
+    }
+
+    #[test]
+    fn nothing_goes_in_the_clear() {
+        let group = group();
+        assert_eq!(open_content(&group, "plain"), Err(GroupCryptoError::Unsealed));
+
+        // Joined by invite code and still waiting for the key
+        let mut waiting = group.clone();
+        waiting.symmetric_key = vec![0; 32];
+        assert_eq!(seal_content(&waiting, "hi"), Err(GroupCryptoError::NoKey));
+        let content = seal_content(&group, "hi").unwrap();
+        let err = open_content(&waiting, &content).unwrap_err();
+        assert_eq!(err, GroupCryptoError::NoKey);
+        assert!(err.needs_resync());
+    }
+
+    #[test]
+    fn messages_from_a_prior_membership_are_rejected() {
+        let mut group = group();
+        let sealed = encrypt(&group, b"before carol joined").unwrap();
+
+        // Same key, but membership changed since
+        add_member(&mut group, member("FP_C"));
+        assert_eq!(
+            decrypt(&group, &sealed),
+            Err(GroupCryptoError::EpochMismatch { message: 4, current: 5 })
+        );
+
+        // Relabelling the old message with the new epoch doesn't get it through
+        let relabelled = GroupCiphertext { epoch: 5, ..sealed };
+        assert_eq!(decrypt(&group, &relabelled), Err(GroupCryptoError::Decrypt));
+
+        // Only a message from ahead of us means we missed an admin's sync
+        assert!(!GroupCryptoError::EpochMismatch { message: 4, current: 5 }.needs_resync());
+        assert!(GroupCryptoError::EpochMismatch { message: 6, current: 5 }.needs_resync());
+    }
+}
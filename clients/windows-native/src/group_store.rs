@@ -39,6 +39,29 @@ pub struct Group {
     pub settings: GroupSettings,
     /// Shared symmetric key (AES-256) for this group
     pub symmetric_key: Vec<u8>,
+    /// Emote-cache hash of the group avatar image
+    #[serde(default)]
+    pub avatar_hash: Option<String>,
+    /// When name/avatar were last changed (ms since epoch), for last-writer-wins
+    #[serde(default)]
+    pub metadata_updated_ms: i64,
+    /// Fingerprint of the admin who made the last name/avatar change
+    #[serde(default)]
+    pub metadata_updated_by: String,
+    /// Bumped on every member add/remove; group ciphertext is bound to it
+    #[serde(default)]
+    pub membership_epoch: u64,
+    /// Peers who announced a join and are waiting for an admin to let them
+    /// in. Nobody gets the group key until then.
+    #[serde(default)]
+    pub join_requests: Vec<GroupMember>,
+    /// Bumped on every admin or owner change, separately from the name/avatar
+    /// stamp so a rename can't undo a role change or the other way round
+    #[serde(default)]
+    pub roles_version: u64,
+    /// Admin who made the last role change (breaks ties between versions)
+    #[serde(default)]
+    pub roles_updated_by: String,
 }
 
 /// Helper struct for serialization to encrypted storage
@@ -48,16 +71,15 @@ struct GroupListWrapper {
 }
 
 fn get_groups_path() -> Result<PathBuf> {
-    Ok(crate::request_store::get_data_dir()?.join("groups.enc"))
+    Ok(crate::paths::data_dir()?.join("groups.enc"))
 }
 
 /// Load all groups from encrypted storage
 pub fn load_groups(fingerprint: &str) -> Result<Vec<Group>> {
-    let path = get_groups_path()?;
-    if !path.exists() {
-        return Ok(Vec::new());
-    }
+    crate::store_recovery::load_or_recover(&get_groups_path()?, |json| decrypt_groups(json, fingerprint))
+}
 
+fn decrypt_groups(json: &[u8], fingerprint: &str) -> Result<Vec<Group>> {
     // Reuse encrypted_storage module but we need to repurpose it for generic data
     // Since encrypted_storage handles Vec<StoredMessage>, we might need to extend it 
     // or just use the raw encrypt/decrypt functions generic over content.
@@ -74,8 +96,7 @@ pub fn load_groups(fingerprint: &str) -> Result<Vec<Group>> {
     use crate::encrypted_storage::{derive_storage_key, EncryptedStore};
     use aes_gcm::{aead::{Aead, KeyInit}, Aes256Gcm, Nonce};
     
-    let json = fs::read(&path)?;
-    let store: EncryptedStore = serde_json::from_slice(&json)?;
+    let store: EncryptedStore = serde_json::from_slice(json)?;
     let key = derive_storage_key(fingerprint);
     
     let cipher = Aes256Gcm::new_from_slice(&key).context("Failed to create cipher")?;
@@ -141,6 +162,13 @@ pub fn create_group(
             disappearing_timer_secs: None,
         },
         symmetric_key: key.to_vec(),
+        avatar_hash: None,
+        metadata_updated_ms: 0,
+        metadata_updated_by: String::new(),
+        membership_epoch: 0,
+        join_requests: Vec::new(),
+        roles_version: 0,
+        roles_updated_by: String::new(),
     };
     
     // Load existing, add new, save
@@ -157,3 +185,759 @@ pub fn delete_group(group_id: &str, fingerprint: &str) -> Result<()> {
     save_groups(&groups, fingerprint)?;
     Ok(())
 }
+
+/// Leave a group: remove it from local storage and return it so the caller
+/// can notify the remaining members
+pub fn leave_group(group_id: &str, fingerprint: &str) -> Result<Option<Group>> {
+    let mut groups = load_groups(fingerprint)?;
+    let Some(idx) = groups.iter().position(|g| g.id == group_id) else {
+        return Ok(None);
+    };
+    let group = groups.remove(idx);
+    save_groups(&groups, fingerprint)?;
+    Ok(Some(group))
+}
+
+/// Drop a departed member from a group. If they were the last admin, the
+/// longest-standing remaining member is promoted so the group keeps an admin.
+/// Returns the fingerprint of the promoted member, if any.
+pub fn apply_member_leave(group: &mut Group, fingerprint: &str) -> Option<String> {
+    let before = group.members.len();
+    group.members.retain(|m| m.fingerprint != fingerprint);
+    group.admins.retain(|a| a != fingerprint);
+    if group.members.len() != before {
+        group.membership_epoch += 1;
+    }
+
+    if !group.admins.is_empty() {
+        return None;
+    }
+    // Deterministic choice so every member promotes the same person
+    let successor = group.members.iter()
+        .min_by(|a, b| a.joined_at.cmp(&b.joined_at).then_with(|| a.fingerprint.cmp(&b.fingerprint)))?
+        .fingerprint
+        .clone();
+    group.admins.push(successor.clone());
+    Some(successor)
+}
+
+/// Add a member who joined, starting a new membership epoch. Returns false
+/// if they were already in the group.
+pub fn add_member(group: &mut Group, member: GroupMember) -> bool {
+    if group.members.iter().any(|m| m.fingerprint == member.fingerprint) {
+        return false;
+    }
+    group.members.push(member);
+    group.membership_epoch += 1;
+    true
+}
+
+/// Queue a join announcement for an admin to approve. Returns false if the
+/// peer is already a member or already waiting.
+pub fn queue_join_request(group: &mut Group, member: GroupMember) -> bool {
+    let known = group.members.iter().chain(&group.join_requests).any(|m| m.fingerprint == member.fingerprint);
+    if known || member.fingerprint.is_empty() {
+        return false;
+    }
+    group.join_requests.push(member);
+    true
+}
+
+/// Let a waiting peer in, as the admin `by`. Returns the new member so the
+/// caller can send them the group key.
+pub fn approve_join(group: &mut Group, by: &str, fingerprint: &str) -> Result<GroupMember> {
+    require_admin(group, by)?;
+    let idx = group.join_requests.iter().position(|m| m.fingerprint == fingerprint)
+        .context("No pending join request")?;
+    let member = group.join_requests.remove(idx);
+    add_member(group, member.clone());
+    Ok(member)
+}
+
+/// Turn a waiting peer away, as the admin `by`
+pub fn deny_join(group: &mut Group, by: &str, fingerprint: &str) -> Result<()> {
+    require_admin(group, by)?;
+    let before = group.join_requests.len();
+    group.join_requests.retain(|m| m.fingerprint != fingerprint);
+    if group.join_requests.len() == before {
+        anyhow::bail!("No pending join request");
+    }
+    Ok(())
+}
+
+/// Merge an admin's view of the group, sent when they approve a join or
+/// answer a resync. This catches us up rather than changing membership, so
+/// instead of bumping the epoch we adopt theirs when it is ahead. Syncs
+/// from non-admins are ignored. Returns true if anything changed.
+pub fn apply_member_sync(group: &mut Group, sender_fingerprint: &str, members: Vec<GroupMember>, membership_epoch: u64) -> bool {
+    if !can_edit_metadata(group, sender_fingerprint) {
+        return false;
+    }
+    let mut updated = false;
+    for member in members {
+        if !group.members.iter().any(|m| m.fingerprint == member.fingerprint) {
+            group.members.push(member);
+            updated = true;
+        }
+    }
+    if membership_epoch > group.membership_epoch {
+        group.membership_epoch = membership_epoch;
+        updated = true;
+    }
+    updated
+}
+
+/// Point every reference to `old_fingerprint` (member entry, admin list,
+/// creator) at the rotated key. Returns true if any group changed.
+pub fn apply_identity_rotation(groups: &mut [Group], old_fingerprint: &str, new_fingerprint: &str, new_public_key: &str) -> bool {
+    let mut changed = false;
+    for group in groups.iter_mut() {
+        for member in group.members.iter_mut().filter(|m| m.fingerprint == old_fingerprint) {
+            member.fingerprint = new_fingerprint.to_string();
+            member.public_key = new_public_key.to_string();
+            changed = true;
+        }
+        for admin in group.admins.iter_mut().filter(|a| *a == old_fingerprint) {
+            *admin = new_fingerprint.to_string();
+            changed = true;
+        }
+        if group.creator_fingerprint == old_fingerprint {
+            group.creator_fingerprint = new_fingerprint.to_string();
+            changed = true;
+        }
+    }
+    changed
+}
+
+/// The member responsible for rotating the key after a membership change:
+/// the admin with the lowest fingerprint, so exactly one member does it
+pub fn key_rotator(group: &Group) -> Option<&str> {
+    group.admins.iter()
+        .filter(|a| group.members.iter().any(|m| &m.fingerprint == *a))
+        .min()
+        .map(|a| a.as_str())
+}
+
+/// Replace the group's symmetric key with a fresh random one
+pub fn rotate_key(group: &mut Group) -> Vec<u8> {
+    use rand::RngCore;
+
+    let mut key = [0u8; 32];
+    aes_gcm::aead::OsRng.fill_bytes(&mut key);
+    group.symmetric_key = key.to_vec();
+    group.symmetric_key.clone()
+}
+
+/// Check whether a member may change the group's name/avatar
+pub fn can_edit_metadata(group: &Group, fingerprint: &str) -> bool {
+    group.admins.iter().any(|a| a == fingerprint)
+}
+
+/// Rename a group (and optionally change its avatar) as `fingerprint`.
+/// Only admins may do this.
+pub fn rename_group(
+    group: &mut Group,
+    fingerprint: &str,
+    name: &str,
+    avatar_hash: Option<String>,
+    now_ms: i64,
+) -> Result<()> {
+    if !can_edit_metadata(group, fingerprint) {
+        anyhow::bail!("Only group admins can change the group name or avatar");
+    }
+    let name = name.trim();
+    if name.is_empty() {
+        anyhow::bail!("Group name cannot be empty");
+    }
+    group.name = name.to_string();
+    group.avatar_hash = avatar_hash;
+    stamp_metadata(group, fingerprint, now_ms);
+    Ok(())
+}
+
+/// Record a local metadata change for last-writer-wins ordering
+fn stamp_metadata(group: &mut Group, fingerprint: &str, now_ms: i64) {
+    // Never move our clock backwards relative to an update we already applied
+    group.metadata_updated_ms = now_ms.max(group.metadata_updated_ms + 1);
+    group.metadata_updated_by = fingerprint.to_string();
+}
+
+fn require_admin(group: &Group, fingerprint: &str) -> Result<()> {
+    if !can_edit_metadata(group, fingerprint) {
+        anyhow::bail!("Only group admins can do that");
+    }
+    Ok(())
+}
+
+fn require_member(group: &Group, fingerprint: &str) -> Result<()> {
+    if !group.members.iter().any(|m| m.fingerprint == fingerprint) {
+        anyhow::bail!("Not a member of this group");
+    }
+    Ok(())
+}
+
+/// Make a member an admin, as the admin `by`. Travels to other members
+/// with the group metadata, under its own roles version.
+pub fn add_admin(group: &mut Group, by: &str, fingerprint: &str) -> Result<()> {
+    require_admin(group, by)?;
+    require_member(group, fingerprint)?;
+    if can_edit_metadata(group, fingerprint) {
+        anyhow::bail!("Already an admin");
+    }
+    group.admins.push(fingerprint.to_string());
+    stamp_roles(group, by);
+    Ok(())
+}
+
+/// Take admin rights away, as the admin `by`. The last admin can't be
+/// removed, and the owner only after handing ownership to someone else.
+pub fn remove_admin(group: &mut Group, by: &str, fingerprint: &str) -> Result<()> {
+    require_admin(group, by)?;
+    if !can_edit_metadata(group, fingerprint) {
+        anyhow::bail!("Not an admin");
+    }
+    if group.admins.len() == 1 {
+        anyhow::bail!("A group needs at least one admin");
+    }
+    if group.creator_fingerprint == fingerprint {
+        anyhow::bail!("Transfer ownership before removing the owner as admin");
+    }
+    group.admins.retain(|a| a != fingerprint);
+    stamp_roles(group, by);
+    Ok(())
+}
+
+/// Hand group ownership to another member. Only the current owner `by`
+/// can; the new owner becomes an admin if they weren't and the previous
+/// owner stays one.
+pub fn transfer_ownership(group: &mut Group, by: &str, new_owner: &str) -> Result<()> {
+    if group.creator_fingerprint != by {
+        anyhow::bail!("Only the group owner can hand over ownership");
+    }
+    require_member(group, new_owner)?;
+    if group.creator_fingerprint == new_owner {
+        anyhow::bail!("Already the owner");
+    }
+    if !can_edit_metadata(group, new_owner) {
+        group.admins.push(new_owner.to_string());
+    }
+    group.creator_fingerprint = new_owner.to_string();
+    stamp_roles(group, by);
+    Ok(())
+}
+
+/// Record a local role change, newer than any we've applied
+fn stamp_roles(group: &mut Group, by: &str) {
+    group.roles_version += 1;
+    group.roles_updated_by = by.to_string();
+}
+
+/// Apply a name/avatar update received from another member. Updates from
+/// non-admins are ignored; concurrent updates resolve last-writer-wins by
+/// timestamp, with the sender fingerprint breaking ties. Returns true if the
+/// update was applied.
+pub fn apply_metadata_update(
+    group: &mut Group,
+    sender_fingerprint: &str,
+    name: &str,
+    avatar_hash: Option<String>,
+    updated_at_ms: i64,
+) -> bool {
+    if !can_edit_metadata(group, sender_fingerprint) || name.trim().is_empty() {
+        return false;
+    }
+    let newer = (updated_at_ms, sender_fingerprint) > (group.metadata_updated_ms, group.metadata_updated_by.as_str());
+    if !newer {
+        return false;
+    }
+    group.name = name.trim().to_string();
+    group.avatar_hash = avatar_hash;
+    group.metadata_updated_ms = updated_at_ms;
+    group.metadata_updated_by = sender_fingerprint.to_string();
+    true
+}
+
+/// Adopt the admin list and owner carried by a metadata update from the
+/// admin `sender_fingerprint`. Role changes have their own version, newest
+/// wins with the sender breaking ties. The result must hold together: at
+/// least one admin, every admin a member, and the owner one of them. Only
+/// the owner can hand over ownership or step down as admin. Older clients
+/// send no admin list, so an empty one leaves ours alone. Returns true if
+/// the roles were applied.
+pub fn apply_admin_roles(
+    group: &mut Group,
+    sender_fingerprint: &str,
+    admins: &[String],
+    owner: &str,
+    roles_version: u64,
+) -> bool {
+    if admins.is_empty() || !can_edit_metadata(group, sender_fingerprint) {
+        return false;
+    }
+    let newer = (roles_version, sender_fingerprint) > (group.roles_version, group.roles_updated_by.as_str());
+    if !newer {
+        return false;
+    }
+    let all_members = admins.iter().all(|a| group.members.iter().any(|m| &m.fingerprint == a));
+    if !all_members || !admins.iter().any(|a| a == owner) {
+        return false;
+    }
+    let current_owner = group.creator_fingerprint.as_str();
+    let owner_touched = owner != current_owner || !admins.iter().any(|a| a == current_owner);
+    if owner_touched && sender_fingerprint != current_owner {
+        return false;
+    }
+    group.admins = admins.to_vec();
+    group.creator_fingerprint = owner.to_string();
+    group.roles_version = roles_version;
+    group.roles_updated_by = sender_fingerprint.to_string();
+    true
+}
+
+// ============ Pending Invites ============
+
+/// A group invite received over the network, waiting for accept/decline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingGroupInvite {
+    pub group_id: String,
+    pub group_name: String,
+    pub creator_name: String,
+    /// AES key encrypted with our public key
+    pub encrypted_symmetric_key: String,
+    pub members: Vec<(String, String)>, // (name, fingerprint)
+    pub settings: GroupSettings,
+    pub received_at: String, // ISO8601
+    /// The inviting admin's membership epoch
+    #[serde(default)]
+    pub membership_epoch: u64,
+}
+
+/// Invites are kept per account: they carry a group key encrypted to one identity
+fn get_pending_invites_path(fingerprint: &str) -> Result<PathBuf> {
+    Ok(crate::paths::data_dir()?.join(format!("group_invites_{}.json", fingerprint)))
+}
+
+/// Load the pending group invites of the account with `fingerprint`
+pub fn load_pending_invites(fingerprint: &str) -> Result<Vec<PendingGroupInvite>> {
+    crate::store_recovery::load_or_recover(&get_pending_invites_path(fingerprint)?, |json| Ok(serde_json::from_slice(json)?))
+}
+
+/// Save the pending group invites of the account with `fingerprint`
+pub fn save_pending_invites(invites: &[PendingGroupInvite], fingerprint: &str) -> Result<()> {
+    let path = get_pending_invites_path(fingerprint)?;
+    let json = serde_json::to_string_pretty(invites)?;
+    fs::write(&path, json).context("Failed to save group invites")?;
+    Ok(())
+}
+
+/// Queue an invite unless we already have one for that group or are a member.
+/// Returns true if it was added.
+pub fn add_pending_invite(
+    pending: &mut Vec<PendingGroupInvite>,
+    groups: &[Group],
+    invite: PendingGroupInvite,
+) -> bool {
+    let known = pending.iter().any(|i| i.group_id == invite.group_id)
+        || groups.iter().any(|g| g.id == invite.group_id);
+    if known {
+        return false;
+    }
+    pending.push(invite);
+    true
+}
+
+/// Take an invite out of the pending list (used for both accept and decline)
+pub fn take_pending_invite(pending: &mut Vec<PendingGroupInvite>, group_id: &str) -> Option<PendingGroupInvite> {
+    let idx = pending.iter().position(|i| i.group_id == group_id)?;
+    Some(pending.remove(idx))
+}
+
+/// Build the local group for an accepted invite. `known_members` supplies
+/// address/public key for members we already know; `me` is always included.
+pub fn group_from_invite(
+    invite: &PendingGroupInvite,
+    known_members: &[GroupMember],
+    me: GroupMember,
+    symmetric_key: Vec<u8>,
+) -> Group {
+    let now = chrono::Utc::now().to_rfc3339();
+    let mut members: Vec<GroupMember> = invite.members.iter()
+        .filter(|(_, fp)| *fp != me.fingerprint)
+        .map(|(name, fp)| {
+            known_members.iter()
+                .find(|m| &m.fingerprint == fp)
+                .cloned()
+                .unwrap_or_else(|| GroupMember {
+                    fingerprint: fp.clone(),
+                    username: name.clone(),
+                    public_key: String::new(),
+                    address: String::new(), // Filled in by GroupMemberSync
+                    joined_at: now.clone(),
+                })
+        })
+        .collect();
+    members.push(me);
+
+    let creator_fingerprint = invite.members.iter()
+        .find(|(name, _)| *name == invite.creator_name)
+        .map(|(_, fp)| fp.clone())
+        .unwrap_or_else(|| invite.creator_name.clone());
+
+    Group {
+        id: invite.group_id.clone(),
+        name: invite.group_name.clone(),
+        created_at: now,
+        creator_fingerprint: creator_fingerprint.clone(),
+        members,
+        admins: vec![creator_fingerprint],
+        settings: invite.settings.clone(),
+        symmetric_key,
+        avatar_hash: None,
+        metadata_updated_ms: 0,
+        metadata_updated_by: String::new(),
+        membership_epoch: invite.membership_epoch,
+        join_requests: Vec::new(),
+        roles_version: 0,
+        roles_updated_by: String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(name: &str, fp: &str, address: &str) -> GroupMember {
+        GroupMember {
+            fingerprint: fp.to_string(),
+            username: name.to_string(),
+            public_key: format!("key-{}", fp),
+            address: address.to_string(),
+            joined_at: "2024-01-01T00:00:00Z".to_string(),
+        }
+    }
+
+    fn invite(group_id: &str) -> PendingGroupInvite {
+        PendingGroupInvite {
+            group_id: group_id.to_string(),
+            group_name: "Friends".to_string(),
+            creator_name: "alice".to_string(),
+            encrypted_symmetric_key: String::new(),
+            members: vec![
+                ("alice".to_string(), "FP_ALICE".to_string()),
+                ("bob".to_string(), "FP_BOB".to_string()),
+                ("me".to_string(), "FP_ME".to_string()),
+            ],
+            settings: GroupSettings {
+                invite_permission: InvitePermission::AdminsOnly,
+                max_members: None,
+                disappearing_timer_secs: Some(60),
+            },
+            received_at: "2024-01-01T00:00:00Z".to_string(),
+            membership_epoch: 3,
+        }
+    }
+
+    fn group_with(members: Vec<GroupMember>, admins: &[&str]) -> Group {
+        Group {
+            id: "g1".to_string(),
+            name: "Friends".to_string(),
+            created_at: "2024-01-01T00:00:00Z".to_string(),
+            creator_fingerprint: admins.first().unwrap_or(&"").to_string(),
+            members,
+            admins: admins.iter().map(|a| a.to_string()).collect(),
+            settings: GroupSettings {
+                invite_permission: InvitePermission::AdminsOnly,
+                max_members: None,
+                disappearing_timer_secs: None,
+            },
+            symmetric_key: vec![1; 32],
+            avatar_hash: None,
+            metadata_updated_ms: 0,
+            metadata_updated_by: String::new(),
+            membership_epoch: 0,
+            join_requests: Vec::new(),
+            roles_version: 0,
+            roles_updated_by: String::new(),
+        }
+    }
+
+    #[test]
+    fn member_leave_drops_member() {
+        let mut group = group_with(
+            vec![member("alice", "FP_A", "a:1"), member("bob", "FP_B", "b:1"), member("carol", "FP_C", "c:1")],
+            &["FP_A"],
+        );
+        assert_eq!(apply_member_leave(&mut group, "FP_B"), None);
+        let remaining: Vec<&str> = group.members.iter().map(|m| m.fingerprint.as_str()).collect();
+        assert_eq!(remaining, vec!["FP_A", "FP_C"]);
+        assert_eq!(group.admins, vec!["FP_A".to_string()]);
+
+        // Applying the same leave twice is harmless
+        assert_eq!(apply_member_leave(&mut group, "FP_B"), None);
+        assert_eq!(group.members.len(), 2);
+    }
+
+    #[test]
+    fn admin_leave_hands_off_to_longest_member() {
+        let mut bob = member("bob", "FP_B", "b:1");
+        bob.joined_at = "2024-02-01T00:00:00Z".to_string();
+        let mut carol = member("carol", "FP_C", "c:1");
+        carol.joined_at = "2024-01-15T00:00:00Z".to_string();
+        let mut group = group_with(vec![member("alice", "FP_A", "a:1"), bob, carol], &["FP_A"]);
+
+        assert_eq!(apply_member_leave(&mut group, "FP_A"), Some("FP_C".to_string()));
+        assert_eq!(group.admins, vec!["FP_C".to_string()]);
+        assert_eq!(key_rotator(&group), Some("FP_C"));
+    }
+
+    #[test]
+    fn last_member_leaving_leaves_no_admin() {
+        let mut group = group_with(vec![member("alice", "FP_A", "a:1")], &["FP_A"]);
+        assert_eq!(apply_member_leave(&mut group, "FP_A"), None);
+        assert!(group.members.is_empty());
+        assert!(key_rotator(&group).is_none());
+    }
+
+    #[test]
+    fn membership_changes_advance_the_epoch() {
+        let mut group = group_with(vec![member("alice", "FP_A", "a:1")], &["FP_A"]);
+        assert!(add_member(&mut group, member("bob", "FP_B", "b:1")));
+        assert!(!add_member(&mut group, member("bob", "FP_B", "b:1")));
+        assert_eq!(group.membership_epoch, 1);
+
+        apply_member_leave(&mut group, "FP_B");
+        apply_member_leave(&mut group, "FP_B");
+        assert_eq!(group.membership_epoch, 2);
+
+        // Catching up from a sync takes the sender's epoch without counting the merge
+        let mut joiner = group_with(vec![member("carol", "FP_C", "c:1")], &["FP_A"]);
+        assert!(apply_member_sync(&mut joiner, "FP_A", vec![member("alice", "FP_A", "a:1")], 3));
+        assert_eq!(joiner.membership_epoch, 3);
+        assert!(!apply_member_sync(&mut joiner, "FP_A", vec![member("alice", "FP_A", "a:1")], 2));
+        assert_eq!(joiner.membership_epoch, 3);
+        // Only an admin sets the epoch or adds members
+        assert!(!apply_member_sync(&mut joiner, "FP_C", vec![member("eve", "FP_E", "e:1")], 9));
+        assert_eq!(joiner.membership_epoch, 3);
+        assert!(!joiner.members.iter().any(|m| m.fingerprint == "FP_E"));
+    }
+
+    #[test]
+    fn rotate_key_replaces_key() {
+        let mut group = group_with(vec![member("alice", "FP_A", "a:1")], &["FP_A"]);
+        let key = rotate_key(&mut group);
+        assert_eq!(key.len(), 32);
+        assert_ne!(key, vec![1; 32]);
+        assert_eq!(group.symmetric_key, key);
+    }
+
+    #[test]
+    fn only_admins_can_rename() {
+        let mut group = group_with(vec![member("alice", "FP_A", "a:1"), member("bob", "FP_B", "b:1")], &["FP_A"]);
+        assert!(rename_group(&mut group, "FP_B", "Hijacked", None, 100).is_err());
+        assert_eq!(group.name, "Friends");
+        assert!(rename_group(&mut group, "FP_A", "   ", None, 100).is_err());
+
+        rename_group(&mut group, "FP_A", " Book Club ", Some("abc".to_string()), 100).unwrap();
+        assert_eq!(group.name, "Book Club");
+        assert_eq!(group.avatar_hash.as_deref(), Some("abc"));
+        assert_eq!(group.metadata_updated_ms, 100);
+    }
+
+    #[test]
+    fn rename_propagates_to_members() {
+        let members = vec![member("alice", "FP_A", "a:1"), member("bob", "FP_B", "b:1")];
+        let mut admin_copy = group_with(members.clone(), &["FP_A"]);
+        let mut member_copy = group_with(members, &["FP_A"]);
+
+        rename_group(&mut admin_copy, "FP_A", "Book Club", Some("abc".to_string()), 100).unwrap();
+        assert!(apply_metadata_update(
+            &mut member_copy,
+            "FP_A",
+            &admin_copy.name,
+            admin_copy.avatar_hash.clone(),
+            admin_copy.metadata_updated_ms,
+        ));
+        assert_eq!(member_copy.name, "Book Club");
+        assert_eq!(member_copy.avatar_hash.as_deref(), Some("abc"));
+
+        // Replaying the same update is a no-op
+        assert!(!apply_metadata_update(&mut member_copy, "FP_A", "Book Club", None, 100));
+        // Non-admin updates are ignored
+        assert!(!apply_metadata_update(&mut member_copy, "FP_B", "Hijacked", None, 200));
+        assert_eq!(member_copy.name, "Book Club");
+    }
+
+    #[test]
+    fn concurrent_renames_last_writer_wins() {
+        let members = vec![member("alice", "FP_A", "a:1"), member("bob", "FP_B", "b:1")];
+        let mut group = group_with(members, &["FP_A", "FP_B"]);
+
+        assert!(apply_metadata_update(&mut group, "FP_A", "Later", None, 200));
+        // Older update arriving late loses
+        assert!(!apply_metadata_update(&mut group, "FP_B", "Earlier", None, 150));
+        assert_eq!(group.name, "Later");
+        // Same timestamp: higher fingerprint wins on every member
+        assert!(apply_metadata_update(&mut group, "FP_B", "Tie", None, 200));
+        assert!(!apply_metadata_update(&mut group, "FP_A", "Later", None, 200));
+        assert_eq!(group.name, "Tie");
+    }
+
+    #[test]
+    fn joins_wait_for_an_admin() {
+        let mut group = group_with(vec![member("alice", "FP_A", "a:1"), member("bob", "FP_B", "b:1")], &["FP_A"]);
+
+        assert!(queue_join_request(&mut group, member("eve", "FP_E", "e:1")));
+        assert!(!queue_join_request(&mut group, member("eve", "FP_E", "e:2")));
+        assert!(!queue_join_request(&mut group, member("bob", "FP_B", "b:2")));
+        // Waiting peers aren't members yet
+        assert_eq!(group.members.len(), 2);
+        assert_eq!(group.membership_epoch, 0);
+
+        assert!(approve_join(&mut group, "FP_B", "FP_E").is_err());
+        assert!(approve_join(&mut group, "FP_A", "FP_STRANGER").is_err());
+        let joined = approve_join(&mut group, "FP_A", "FP_E").unwrap();
+        assert_eq!(joined.fingerprint, "FP_E");
+        assert!(group.members.iter().any(|m| m.fingerprint == "FP_E"));
+        assert!(group.join_requests.is_empty());
+        assert_eq!(group.membership_epoch, 1);
+
+        assert!(queue_join_request(&mut group, member("mallory", "FP_M", "m:1")));
+        assert!(deny_join(&mut group, "FP_B", "FP_M").is_err());
+        deny_join(&mut group, "FP_A", "FP_M").unwrap();
+        assert!(group.join_requests.is_empty());
+        assert!(!group.members.iter().any(|m| m.fingerprint == "FP_M"));
+    }
+
+    #[test]
+    fn admins_are_added_and_removed_by_admins_only() {
+        let members = vec![member("alice", "FP_A", "a:1"), member("bob", "FP_B", "b:1"), member("carol", "FP_C", "c:1")];
+        let mut group = group_with(members, &["FP_A"]);
+
+        assert!(add_admin(&mut group, "FP_B", "FP_C").is_err());
+        assert!(add_admin(&mut group, "FP_A", "FP_STRANGER").is_err());
+        add_admin(&mut group, "FP_A", "FP_B").unwrap();
+        assert_eq!(group.admins, vec!["FP_A".to_string(), "FP_B".to_string()]);
+        assert!(add_admin(&mut group, "FP_A", "FP_B").is_err());
+        assert_eq!((group.roles_version, group.roles_updated_by.as_str()), (1, "FP_A"));
+        // Roles don't touch the name/avatar stamp
+        assert_eq!(group.metadata_updated_ms, 0);
+
+        assert!(remove_admin(&mut group, "FP_C", "FP_B").is_err());
+        // The owner keeps admin rights until ownership moves
+        assert!(remove_admin(&mut group, "FP_B", "FP_A").is_err());
+        remove_admin(&mut group, "FP_A", "FP_B").unwrap();
+        assert_eq!(group.admins, vec!["FP_A".to_string()]);
+        assert!(remove_admin(&mut group, "FP_A", "FP_C").is_err());
+    }
+
+    #[test]
+    fn last_admin_cannot_be_removed() {
+        let members = vec![member("alice", "FP_A", "a:1"), member("bob", "FP_B", "b:1")];
+        let mut group = group_with(members, &["FP_A"]);
+        // Even with no owner to protect, the only admin stays
+        group.creator_fingerprint = "FP_GONE".to_string();
+
+        let err = remove_admin(&mut group, "FP_A", "FP_A").unwrap_err();
+        assert!(err.to_string().contains("at least one admin"));
+        assert_eq!(group.admins, vec!["FP_A".to_string()]);
+        assert_eq!(group.roles_version, 0);
+    }
+
+    #[test]
+    fn ownership_transfer_propagates_to_members() {
+        let members = vec![member("alice", "FP_A", "a:1"), member("bob", "FP_B", "b:1")];
+        let mut owner_copy = group_with(members.clone(), &["FP_A"]);
+        let mut member_copy = group_with(members, &["FP_A"]);
+
+        assert!(transfer_ownership(&mut owner_copy, "FP_B", "FP_B").is_err());
+        assert!(transfer_ownership(&mut owner_copy, "FP_A", "FP_STRANGER").is_err());
+        transfer_ownership(&mut owner_copy, "FP_A", "FP_B").unwrap();
+        assert_eq!(owner_copy.creator_fingerprint, "FP_B");
+        assert_eq!(owner_copy.admins, vec!["FP_A".to_string(), "FP_B".to_string()]);
+        // Only the owner hands over ownership, even among admins
+        assert!(transfer_ownership(&mut owner_copy, "FP_A", "FP_A").is_err());
+
+        // The former owner can now step down
+        remove_admin(&mut owner_copy, "FP_A", "FP_A").unwrap();
+        assert_eq!(owner_copy.admins, vec!["FP_B".to_string()]);
+
+        // Members adopt the roles from the metadata update; FP_A was the owner when they sent it
+        assert!(apply_admin_roles(&mut member_copy, "FP_A", &owner_copy.admins, &owner_copy.creator_fingerprint, owner_copy.roles_version));
+        assert_eq!(member_copy.admins, vec!["FP_B".to_string()]);
+        assert_eq!(member_copy.creator_fingerprint, "FP_B");
+        assert_eq!(member_copy.name, "Friends");
+        // An update from an older client carries no roles
+        assert!(!apply_admin_roles(&mut member_copy, "FP_B", &[], "", 9));
+        assert_eq!(member_copy.admins, vec!["FP_B".to_string()]);
+    }
+
+    #[test]
+    fn received_roles_must_hold_together() {
+        let members = vec![member("alice", "FP_A", "a:1"), member("bob", "FP_B", "b:1"), member("carol", "FP_C", "c:1")];
+        let mut group = group_with(members, &["FP_A", "FP_B"]);
+        let roles = |admins: &[&str]| admins.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+
+        // Non-admins, strangers as admins, an owner who isn't an admin, or no admins at all
+        assert!(!apply_admin_roles(&mut group, "FP_C", &roles(&["FP_C"]), "FP_A", 1));
+        assert!(!apply_admin_roles(&mut group, "FP_B", &roles(&["FP_A", "FP_X"]), "FP_A", 1));
+        assert!(!apply_admin_roles(&mut group, "FP_B", &roles(&["FP_B"]), "FP_A", 1));
+        assert!(!apply_admin_roles(&mut group, "FP_B", &[], "FP_A", 1));
+        // Another admin can't take ownership or demote the owner
+        assert!(!apply_admin_roles(&mut group, "FP_B", &roles(&["FP_A", "FP_B"]), "FP_B", 1));
+        assert!(!apply_admin_roles(&mut group, "FP_B", &roles(&["FP_B"]), "FP_B", 1));
+        assert_eq!(group.admins, roles(&["FP_A", "FP_B"]));
+        assert_eq!(group.roles_version, 0);
+
+        // But can promote a member
+        assert!(apply_admin_roles(&mut group, "FP_B", &roles(&["FP_A", "FP_B", "FP_C"]), "FP_A", 1));
+        // A stale version is ignored, a renamed group keeps its roles
+        assert!(!apply_admin_roles(&mut group, "FP_A", &roles(&["FP_A"]), "FP_A", 0));
+        assert!(apply_metadata_update(&mut group, "FP_A", "Renamed", None, 500));
+        assert_eq!(group.admins, roles(&["FP_A", "FP_B", "FP_C"]));
+        // The owner can step down after handing over
+        assert!(apply_admin_roles(&mut group, "FP_A", &roles(&["FP_C"]), "FP_C", 2));
+        assert_eq!((group.creator_fingerprint.as_str(), group.admins.clone()), ("FP_C", roles(&["FP_C"])));
+    }
+
+    #[test]
+    fn duplicate_invites_are_ignored() {
+        let mut pending = Vec::new();
+        assert!(add_pending_invite(&mut pending, &[], invite("g1")));
+        assert!(!add_pending_invite(&mut pending, &[], invite("g1")));
+
+        let existing = group_from_invite(&invite("g2"), &[], member("me", "FP_ME", "127.0.0.1:1"), vec![0; 32]);
+        assert!(!add_pending_invite(&mut pending, &[existing], invite("g2")));
+        assert_eq!(pending.len(), 1);
+    }
+
+    #[test]
+    fn accepting_invite_creates_group() {
+        let mut pending = vec![invite("g1")];
+        let accepted = take_pending_invite(&mut pending, "g1").unwrap();
+        assert!(pending.is_empty());
+
+        let known = vec![member("alice", "FP_ALICE", "10.0.0.2:62780")];
+        let me = member("me", "FP_ME", "127.0.0.1:62781");
+        let group = group_from_invite(&accepted, &known, me, vec![7; 32]);
+
+        assert_eq!(group.id, "g1");
+        assert_eq!(group.name, "Friends");
+        assert_eq!(group.members.len(), 3);
+        assert_eq!(group.admins, vec!["FP_ALICE".to_string()]);
+        assert_eq!(group.settings.disappearing_timer_secs, Some(60));
+        let alice = group.members.iter().find(|m| m.fingerprint == "FP_ALICE").unwrap();
+        assert_eq!(alice.address, "10.0.0.2:62780");
+        let bob = group.members.iter().find(|m| m.fingerprint == "FP_BOB").unwrap();
+        assert!(bob.address.is_empty());
+        assert_eq!(group.members.iter().filter(|m| m.fingerprint == "FP_ME").count(), 1);
+    }
+
+    #[test]
+    fn declining_invite_only_removes_it() {
+        let mut pending = vec![invite("g1"), invite("g2")];
+        assert!(take_pending_invite(&mut pending, "g1").is_some());
+        assert!(take_pending_invite(&mut pending, "missing").is_none());
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].group_id, "g2");
+    }
+}
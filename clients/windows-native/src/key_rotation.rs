@@ -0,0 +1,101 @@
+//! Identity key rotation
+//!
+//! When a key has to be replaced, the owner signs a rotation statement with the
+//! *old* key vouching for the new public key. Contacts who still trust the old
+//! key can check that signature and move the contact over to the new key
+//! instead of treating it as a stranger.
+
+use anyhow::{bail, Context, Result};
+
+use cryptochat_crypto_core::pgp::PgpKeyPair;
+
+const ROTATION_VERSION: u32 = 1;
+
+/// Old-key-signed statement that `new_public_key` replaces `old_fingerprint`
+#[derive(Debug, Clone)]
+pub struct RotationStatement {
+    pub old_fingerprint: String,
+    /// ASCII-armored public key of the replacement key
+    pub new_public_key: String,
+    /// Armored detached signature by the old key over the canonical message
+    pub signature: String,
+}
+
+/// Canonical bytes that get signed: version||old fingerprint||new public key
+fn canonical_message(old_fingerprint: &str, new_public_key: &str) -> String {
+    format!("cryptochat-key-rotation:{}:{}:{}", ROTATION_VERSION, old_fingerprint, new_public_key)
+}
+
+/// Sign a statement with `old_key` announcing `new_key` as its replacement
+pub fn sign_rotation(old_key: &PgpKeyPair, new_key: &PgpKeyPair) -> Result<RotationStatement> {
+    let old_fingerprint = old_key.fingerprint();
+    if new_key.fingerprint() == old_fingerprint {
+        bail!("New key is the same as the old key");
+    }
+    let new_public_key = new_key.export_public_key()
+        .context("Failed to export new public key")?;
+    let signature = old_key
+        .sign_detached(canonical_message(&old_fingerprint, &new_public_key).as_bytes())
+        .context("Failed to sign rotation statement")?;
+
+    Ok(RotationStatement { old_fingerprint, new_public_key, signature })
+}
+
+/// Check a rotation statement against the old key we already trust.
+///
+/// Returns the new key once the old-key signature is valid and the new key is
+/// usable (not expired, revoked or missing an encryption subkey).
+pub fn verify_rotation(trusted_old_public_key: &str, statement: &RotationStatement) -> Result<PgpKeyPair> {
+    let old_key = PgpKeyPair::parse_public_key(trusted_old_public_key)
+        .context("Stored key for this contact is unreadable")?;
+    if old_key.fingerprint() != statement.old_fingerprint {
+        bail!("Rotation statement is for a different key");
+    }
+
+    let message = canonical_message(&statement.old_fingerprint, &statement.new_public_key);
+    PgpKeyPair::verify_detached(old_key.cert(), message.as_bytes(), &statement.signature)
+        .context("SECURITY WARNING: rotation statement was not signed by the old key")?;
+
+    let new_key = PgpKeyPair::from_public_key(&statement.new_public_key)
+        .context("Replacement key is not usable")?;
+    if new_key.fingerprint() == statement.old_fingerprint {
+        bail!("Replacement key is the same as the old key");
+    }
+    Ok(new_key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_statement_verifies_against_old_key() {
+        let old_key = PgpKeyPair::generate("old@example.com").unwrap();
+        let new_key = PgpKeyPair::generate("new@example.com").unwrap();
+        let old_public = old_key.export_public_key().unwrap();
+
+        let statement = sign_rotation(&old_key, &new_key).unwrap();
+        assert_eq!(statement.old_fingerprint, old_key.fingerprint());
+
+        let verified = verify_rotation(&old_public, &statement).unwrap();
+        assert_eq!(verified.fingerprint(), new_key.fingerprint());
+    }
+
+    #[test]
+    fn rotation_rejects_swapped_key_or_foreign_signer() {
+        let old_key = PgpKeyPair::generate("old@example.com").unwrap();
+        let new_key = PgpKeyPair::generate("new@example.com").unwrap();
+        let attacker = PgpKeyPair::generate("mallory@example.com").unwrap();
+        let old_public = old_key.export_public_key().unwrap();
+
+        // Valid signature, but the announced key was swapped afterwards
+        let mut swapped = sign_rotation(&old_key, &new_key).unwrap();
+        swapped.new_public_key = attacker.export_public_key().unwrap();
+        assert!(verify_rotation(&old_public, &swapped).is_err());
+
+        // Attacker signs with their own key while claiming the old fingerprint
+        let mut forged = sign_rotation(&attacker, &new_key).unwrap();
+        forged.old_fingerprint = old_key.fingerprint();
+        assert!(verify_rotation(&old_public, &forged).is_err());
+    }
+}
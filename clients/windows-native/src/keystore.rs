@@ -129,7 +129,7 @@ pub fn load_keypair() -> Result<Option<StoredKey>> {
         - metadata.created_timestamp_ms;
     let age_days = age_ms / (1000 * 60 * 60 * 24);
     if age_days > 90 {
-        eprintln!("Warning: Stored key is {} days old. Consider key rotation.", age_days);
+        tracing::warn!(age_days, "stored key is due for rotation");
     }
 
     Ok(Some(stored_key))
@@ -140,9 +140,43 @@ pub fn delete_keypair() -> Result<()> {
     delete_credential(&get_credential_target("CryptoChat_SecretKey"))?;
     delete_credential(&get_credential_target("CryptoChat_PublicKey"))?;
     delete_credential(&get_credential_target("CryptoChat_Metadata"))?;
+    // Older installs never stored a revocation certificate
+    let _ = delete_credential(&get_credential_target("CryptoChat_Revocation"));
     Ok(())
 }
 
+/// Store the revocation certificate made when the key was generated
+pub fn save_revocation_certificate(armored: &str) -> Result<()> {
+    write_credential(&get_credential_target("CryptoChat_Revocation"), armored.as_bytes())
+}
+
+/// Load the stored revocation certificate, if one was saved
+pub fn load_revocation_certificate() -> Result<Option<String>> {
+    match read_credential(&get_credential_target("CryptoChat_Revocation"))? {
+        Some(data) => Ok(Some(String::from_utf8(data)?)),
+        None => Ok(None),
+    }
+}
+
+/// Record the generation of the last chat history save. Kept here rather
+/// than next to the history so putting back an older history file shows up.
+pub fn save_history_generation(fingerprint: &str, generation: u64) -> Result<()> {
+    let target = get_credential_target(&format!("CryptoChat_HistoryGeneration_{}", fingerprint));
+    write_credential(&target, &generation.to_be_bytes())
+}
+
+/// Generation of the last chat history save (0 if none was recorded)
+pub fn load_history_generation(fingerprint: &str) -> Result<u64> {
+    let target = get_credential_target(&format!("CryptoChat_HistoryGeneration_{}", fingerprint));
+    match read_credential(&target)? {
+        Some(data) => {
+            let bytes: [u8; 8] = data.as_slice().try_into().context("Corrupt history generation")?;
+            Ok(u64::from_be_bytes(bytes))
+        }
+        None => Ok(0),
+    }
+}
+
 // Low-level Windows Credential Manager wrappers
 
 fn write_credential(target_name: &str, data: &[u8]) -> Result<()> {
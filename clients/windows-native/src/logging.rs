@@ -0,0 +1,68 @@
+//! Diagnostic logging to a file in the data directory
+//!
+//! A release build has no console, so events go to `logs/cryptochat.log`.
+//! The level is taken from `CRYPTOCHAT_LOG` (an `EnvFilter` directive such as
+//! `debug` or `cryptochat=trace`) and defaults to `info`. Never log keys or
+//! message content; identify peers with [`short_fp`].
+
+use anyhow::{Context, Result};
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use tracing_subscriber::EnvFilter;
+
+const LOG_ENV: &str = "CRYPTOCHAT_LOG";
+const DEFAULT_LEVEL: &str = "info";
+
+/// Log file in use once `init` has run
+static LOG_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Start logging to `<data dir>/logs/cryptochat.log`. Safe to call more than
+/// once; later calls keep the first file. Returns the log file path.
+pub fn init() -> Result<PathBuf> {
+    init_in(&crate::paths::data_dir()?.join("logs"))
+}
+
+fn init_in(dir: &Path) -> Result<PathBuf> {
+    if let Some(path) = LOG_FILE.get() {
+        return Ok(path.clone());
+    }
+    fs::create_dir_all(dir).context("Failed to create log directory")?;
+    let path = dir.join("cryptochat.log");
+    let file = OpenOptions::new().create(true).append(true).open(&path).context("Failed to open log file")?;
+
+    let filter = EnvFilter::try_from_env(LOG_ENV).unwrap_or_else(|_| EnvFilter::new(DEFAULT_LEVEL));
+    // Fails only if another subscriber won the race; that one keeps logging
+    let _ = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(Mutex::new(file))
+        .with_ansi(false)
+        .with_target(false)
+        .try_init();
+
+    Ok(LOG_FILE.get_or_init(|| path).clone())
+}
+
+/// First 8 characters of a fingerprint: enough to tell peers apart in a log
+pub fn short_fp(fingerprint: &str) -> &str {
+    fingerprint.get(..8).unwrap_or(fingerprint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn init_is_idempotent() {
+        let dir = std::env::temp_dir().join(format!("cryptochat_logs_{}", uuid::Uuid::new_v4()));
+        let first = init_in(&dir).unwrap();
+        tracing::info!(peer = short_fp("ABCDEF0123456789"), "logging started");
+
+        let other = std::env::temp_dir().join(format!("cryptochat_logs_{}", uuid::Uuid::new_v4()));
+        assert_eq!(init_in(&other).unwrap(), first);
+        assert!(!other.exists());
+        assert_eq!(short_fp("ABC"), "ABC");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -13,20 +13,51 @@ mod theme;
 mod emote_manager;
 mod conversation;
 mod conversation_store;
+mod mentions;
+mod peer_address;
+mod key_rotation;
+mod file_dialog;
+mod notifications;
+mod data_export;
+mod devices;
+mod forward;
+mod blob_store;
+mod voice;
+mod rate_limit;
+mod logging;
+mod paths;
+mod store_recovery;
+mod sender_keys;
+mod group_crypto;
+mod group_auth;
+mod search;
+mod outbox;
+mod relay;
+mod request_replay;
+mod settings_sync;
+mod fonts;
+mod compose;
+mod shortcuts;
+mod switcher;
+#[cfg(test)]
+mod e2e_tests;
 
-use conversation::{ChatMessage, Conversation};
+use conversation::{ChatMessage, Conversation, DeliveryStatus};
+use notifications::{play_notification_sound, show_notification};
 
-use iced::widget::{button, column, container, row, text, text_input, scrollable, Space, mouse_area};
-use iced::{Application, Command, Element, Font, Length, Settings, Subscription, Theme, Color};
+use iced::widget::{button, column, container, row, text, text_input, scrollable, tooltip, Space, mouse_area};
+use iced::{Application, Command, Element, Length, Settings, Subscription, Theme, Color};
 use std::sync::{Arc, OnceLock, Mutex};
 use tokio::sync::mpsc;
 
+const SEARCH_INPUT_ID: &str = "global-search";
+const SWITCHER_INPUT_ID: &str = "quick-switcher";
+/// Least time between resync requests for one group
+const GROUP_RESYNC_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
 static INSTANCE_ID: OnceLock<Option<u32>> = OnceLock::new();
 static NETWORK_RECEIVER: OnceLock<Mutex<Option<mpsc::UnboundedReceiver<network::NetworkEvent>>>> = OnceLock::new();
 
-/// Font for emoji rendering (Segoe UI Emoji loaded in Settings)
-const EMOJI_FONT: Font = Font::with_name("Segoe UI Emoji");
-
 /// Emoji list for :emoji: autocomplete (name, emoji)
 const EMOJI_LIST: &[(&str, &str)] = &[
     ("smile", "😀"), ("grin", "😁"), ("joy", "😂"), ("wink", "😉"),
@@ -37,6 +68,51 @@ const EMOJI_LIST: &[(&str, &str)] = &[
     ("check", "✅"), ("x", "❌"), ("100", "💯"), ("pray", "🙏"),
 ];
 
+/// Load the chat history for `keypair`'s account. Altered history isn't
+/// loaded; it's moved aside and the returned warning tells the user why.
+fn load_history(
+    keypair: &cryptochat_crypto_core::pgp::PgpKeyPair,
+) -> (std::collections::HashMap<String, Conversation>, Option<String>) {
+    if let Err(e) = request_store::migrate_chat_history(&keypair.fingerprint()) {
+        tracing::error!(error = %e, "chat history migration failed");
+    }
+    let mut warning = None;
+    let mut conversations = match conversation_store::load_conversations(keypair) {
+        Ok(conversations) => conversations,
+        Err(e) => {
+            if let Some(integrity) = e.downcast_ref::<conversation_store::IntegrityError>() {
+                let kept = conversation_store::quarantine_conversations(keypair)
+                    .ok()
+                    .flatten()
+                    .map(|path| format!(" (kept a copy at {})", path.display()))
+                    .unwrap_or_default();
+                warning = Some(format!("⚠ Chat history failed its integrity check: {}{}", integrity, kept));
+            }
+            std::collections::HashMap::new()
+        }
+    };
+    conversation::fail_interrupted_sends(&mut conversations);
+    (conversations, warning)
+}
+
+/// Forget the current peer's key and address and close their chat, keeping
+/// the unsent text as its draft. Contacts and history are left alone, so the
+/// session can be picked up again from the contact.
+fn end_session(
+    app_state: &app::AppState,
+    conversations: &mut std::collections::HashMap<String, Conversation>,
+    active_conversation_id: &mut Option<String>,
+    message_input: &mut String,
+) {
+    if let Some(conv) = active_conversation_id.as_ref().and_then(|id| conversations.get_mut(id)) {
+        conv.input_draft = std::mem::take(message_input);
+    }
+    if *active_conversation_id == app_state.get_recipient_fingerprint() {
+        *active_conversation_id = None;
+    }
+    app_state.end_session();
+}
+
 pub fn get_instance_id() -> Option<u32> {
     INSTANCE_ID.get().copied().flatten()
 }
@@ -63,8 +139,17 @@ pub struct CryptoChat {
     // UI State
     scroll_id: scrollable::Id,
     message_input: String,
+    /// Longest text message before splitting or sending as a file (characters)
+    max_message_chars: usize,
+    /// Rest of a split message, sent as each part's send completes
+    pending_split: Option<compose::PendingSplit>,
     // chat_messages: Vec<ChatMessage>, 
     conversations: std::collections::HashMap<String, Conversation>,
+    /// Sidebar summaries of `conversations`, refreshed on every save
+    conversation_index: std::collections::HashMap<String, conversation_store::ConversationSummary>,
+    /// The stored history has been read into `conversations`. Until then the
+    /// sidebar runs off the saved index and nothing is written back.
+    history_loaded: bool,
     active_conversation_id: Option<String>,
     
     status: String,
@@ -78,22 +163,60 @@ pub struct CryptoChat {
     peer_is_typing: bool,
     /// Animation phase for typing dots (0, 1, 2 for ".", "..", "...")
     typing_dots_phase: u8,
-    /// Last read timestamp from peer (for ✓✓)
-    peer_last_read: Option<String>,
     /// Show emoji picker panel
     show_emoji_picker: bool,
+    /// Include archived chats in the sidebar
+    show_archived: bool,
+    /// Search across all conversations, shown in place of the chat
+    show_search: bool,
+    search_query: String,
+    /// Quick-jump palette over chats and contacts, shown in place of the chat
+    show_switcher: bool,
+    switcher_query: String,
+    search_include_archived: bool,
+    /// (conversation id, message index), newest first
+    search_hits: Vec<(String, usize)>,
+    search_index: search::SearchIndex,
+    /// The search index changed since it was last written to disk
+    search_index_unsaved: bool,
+    /// Logged direct messages that never went out, offered for resending
+    unsent: Vec<outbox::OutboxEntry>,
+    /// Show emote library panel
+    show_emote_library: bool,
+    /// Show the per-conversation bubble color picker
+    show_conversation_color_picker: bool,
     /// Emoji suggestions for :emoji: autocomplete
     emoji_suggestions: Vec<(&'static str, &'static str)>,
     /// Dark mode enabled (false = light mode)
     dark_mode: bool,
     /// Which message index has reaction picker open (None = closed)
     reaction_picker_for_msg: Option<usize>,
+    /// Which message index has the forward target picker open (None = closed)
+    forward_picker_for_msg: Option<usize>,
+    /// Microphone capture while a voice message is being recorded
+    recorder: Option<voice::Recorder>,
     /// Pending connection requests awaiting user approval
     pending_requests: Vec<PendingRequest>,
+    /// Oldest message that was unread when a chat was opened (conversation id, index)
+    first_unread: Option<(String, usize)>,
+    /// Nonces of recently handled connection requests
+    request_replay: request_replay::ReplayGuard,
+    /// Requests we sent, to tell answers we asked for from unsolicited ones
+    awaited_answers: request_store::AwaitedAnswers,
+    /// Group invites waiting for accept/decline
+    pending_group_invites: Vec<group_store::PendingGroupInvite>,
+    /// Group being renamed (group_id, name input)
+    group_rename: Option<(String, String)>,
+    /// Contact whose alias is being edited (fingerprint, alias input)
+    contact_alias_edit: Option<(String, String)>,
+    /// Address input when resending our key to a peer we have no address for
+    resend_key_address: Option<String>,
     /// List of groups the user is in
     groups: Vec<group_store::Group>,
     /// Group pending deletion (for confirmation dialog)
     pending_group_delete: Option<String>,
+    /// Our key-share QR code while it is shown in-app
+    qr_display: Option<iced::widget::image::Handle>,
     /// Group invite input for joining groups
     group_invite_input: String,
     /// Currently selected group for messaging (None = direct chat)
@@ -104,6 +227,16 @@ pub struct CryptoChat {
     confirm_password_input: String,
     /// Login error message
     login_error: Option<String>,
+    /// Password confirming a key rotation or password change (settings)
+    rotation_password: String,
+    /// Revoking our key is waiting for confirmation
+    confirm_revoke: bool,
+    /// Replacement account password (settings)
+    new_password: String,
+    /// Passphrase for exporting/importing the full data archive (settings)
+    backup_password: String,
+    /// Devices registered on this account (settings), loaded when settings open
+    device_list: Option<devices::DeviceList>,
     
     // Color settings
     /// Show settings modal
@@ -112,8 +245,14 @@ pub struct CryptoChat {
     settings_tab: u8,
     /// Color preferences
     color_prefs: color_store::ColorPreferences,
+    /// Whether toasts and the notification sound are on
+    notification_settings: notifications::NotificationSettings,
+    /// Whether saved contacts' requests skip the prompt
+    request_settings: request_store::RequestSettings,
     /// Rainbow animation offset (0.0 - 1.0)
     rainbow_offset: f32,
+    /// Playback clock for animated emotes (ms)
+    emote_clock_ms: u64,
     /// Gradient color 1 (for editing)
     gradient_color1: String,
     /// Gradient color 2 (for editing)
@@ -121,6 +260,12 @@ pub struct CryptoChat {
     
     // Custom Emotes
     emote_manager: emote_manager::EmoteManager,
+    /// Emote hashes we've asked peers for
+    emote_requests: emote_manager::EmoteRequestTracker,
+    /// Ids of relayed messages handled since the last relay poll
+    relay_ack: Vec<u64>,
+    /// When we last asked an admin to resync each group (by group id)
+    group_resyncs: std::collections::HashMap<String, std::time::Instant>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -140,6 +285,17 @@ pub struct PendingRequest {
     pub sender_address: String,
     pub sender_name: Option<String>,
     pub timestamp: String,
+    /// When this request is dropped if left unanswered (ms since epoch)
+    pub expires_at_ms: i64,
+    /// Which prompt to show and what accepting does
+    pub route: request_store::RequestRoute,
+}
+
+fn now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -148,6 +304,17 @@ pub struct EmotePayload {
     pub emotes: std::collections::HashMap<String, String>,
 }
 
+impl EmotePayload {
+    /// Split a received plaintext into content and emotes. Messages without
+    /// emotes are sent as plain text.
+    pub fn parse(raw: &str) -> Self {
+        serde_json::from_str(raw).unwrap_or_else(|_| Self {
+            content: raw.to_string(),
+            emotes: std::collections::HashMap::new(),
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Message {
     GenerateKeys,
@@ -155,27 +322,62 @@ pub enum Message {
     UsernameChanged(String),
     CopyKeyShare,
     ShowQR,
+    /// Close the in-app QR code view
+    HideQR,
     CopyQR,
     ScanQR,
     ScanQRResult(Result<ImportResult, String>),
     KeyShareInputChanged(String),
     ImportKeyShare,
     KeyShareImported(Result<ImportResult, String>),
+    /// Check that the pasted key share's address (or the current peer) accepts connections
+    TestConnection,
+    ConnectionTested(String, network::ConnectionDiagnosis),
     MessageInputChanged(String),
+    /// Send an oversized draft as several messages
+    SendSplitMessage,
+    /// Send an oversized draft as a text file
+    SendDraftAsFile,
     SendMessage,
     MessageSent(Result<(), String>),
+    /// Outcome of sending a direct text message (conversation id, message id)
+    DirectMessageSent(String, String, Result<(), String>),
     NetworkStarted(Result<u16, String>),
     NetworkEvent(network::NetworkEvent),
     PollNetwork,
+    /// Check our mailbox on the relay node
+    PollRelay,
+    RelayFetched(Result<Vec<cryptochat_node::relay::RelayedMessage>, String>),
     ClearHistory,
     SelectContact(usize),
     PickFile,
     /// Result contains (filename, raw_file_data) for successful sends
-    FileSent(Result<(String, Vec<u8>), String>),
+    /// (filename, contents, message id) of a file we sent
+    FileSent(Result<(String, Vec<u8>, String), String>),
+    /// Start recording a voice message, or stop and send it
+    ToggleVoiceRecording,
+    /// Play a voice message (message index)
+    PlayVoice(usize),
     
     // Emote Upload
     UploadEmote,
     EmoteFileSelected(Option<std::path::PathBuf>),
+    /// Show/hide the emote library panel
+    ToggleEmoteLibrary,
+    /// Remove an emote from the library (name)
+    RemoveEmote(String),
+    /// Export the whole emote library as a pack file
+    ExportEmotePack,
+    EmotePackSavePathSelected(Option<std::path::PathBuf>),
+    /// Import an emote pack file
+    ImportEmotePack,
+    EmotePackFileSelected(Option<std::path::PathBuf>),
+    /// Save the contact list to a shareable file
+    ExportContacts,
+    ContactsSavePathSelected(Option<std::path::PathBuf>),
+    /// Merge contacts from an exported file
+    ImportContacts,
+    ContactsFileSelected(Option<std::path::PathBuf>),
     
     ToggleEmojiPicker,
     InsertEmoji(String),
@@ -191,6 +393,8 @@ pub enum Message {
     AcceptRequest(usize),
     /// Decline a pending connection request (index in pending_requests)
     DeclineRequest(usize),
+    /// Decline a pending request and drop the sender's future ones
+    BlockRequest(usize),
     /// Add current peer to saved contacts
     AddToContacts,
     /// Create a new group chat
@@ -199,6 +403,35 @@ pub enum Message {
     SelectGroup(String),
     /// Select a conversation (fingerprint)
     SelectConversation(String),
+    /// Mute/unmute notifications for a chat or group (conversation id)
+    ToggleMute(String),
+    /// Pin/unpin a chat to the top of the list (conversation id)
+    TogglePin(String),
+    /// Archive/unarchive a chat (conversation id)
+    ToggleArchive(String),
+    /// Show or hide archived chats in the sidebar
+    ToggleShowArchived,
+    /// Open or close the search across all conversations
+    ToggleSearch,
+    SearchQueryChanged(String),
+    SearchIncludeArchivedToggled(bool),
+    /// Jump to a search result (conversation id, message index)
+    OpenSearchHit(String, usize),
+    /// Open or close the quick-jump palette
+    ToggleSwitcher,
+    SwitcherQueryChanged(String),
+    /// Open the best match for the palette query (Enter)
+    SwitcherSubmit,
+    /// Open a chosen palette entry
+    SwitcherPick(switcher::Target),
+    /// Clear the unread count of every chat
+    MarkAllRead,
+    /// Scroll the open chat to the oldest message that was unread when it was opened
+    JumpToFirstUnread,
+    /// Send the messages in `unsent` again
+    ResendUnsent,
+    /// Give up on the messages in `unsent`
+    DiscardUnsent,
     /// Copy group invite key to clipboard
     CopyGroupKey(String),
     /// Request to delete a group (shows confirmation)
@@ -207,10 +440,37 @@ pub enum Message {
     ConfirmDeleteGroup(String),
     /// Cancel group deletion
     CancelDeleteGroup,
+    /// Leave a group and tell the other members
+    LeaveGroup(String),
+    /// Open the rename editor for a group (group_id)
+    StartGroupRename(String),
+    GroupRenameInputChanged(String),
+    SubmitGroupRename,
+    CancelGroupRename,
+    /// Grant or take away a member's admin rights (group_id, fingerprint, admin)
+    SetGroupAdmin(String, String, bool),
+    /// Let a peer waiting to join a group in (group id, fingerprint)
+    ApproveGroupJoin(String, String),
+    /// Turn a peer waiting to join a group away (group id, fingerprint)
+    DenyGroupJoin(String, String),
+    /// Hand group ownership to a member (group_id, fingerprint)
+    TransferGroupOwnership(String, String),
+    /// Open the alias editor for a contact (fingerprint)
+    StartContactAlias(String),
+    ContactAliasInputChanged(String),
+    SubmitContactAlias,
+    CancelContactAlias,
+    /// Pick a new avatar image for a group (group_id)
+    PickGroupAvatar(String),
+    GroupAvatarSelected(String, Option<std::path::PathBuf>),
     /// Group invite input changed
     GroupInviteInputChanged(String),
     /// Join a group from invite JSON
     JoinGroup,
+    /// Accept a pending group invite (group_id)
+    AcceptGroupInvite(String),
+    /// Decline a pending group invite (group_id)
+    DeclineGroupInvite(String),
     /// Password input changed
     PasswordInputChanged(String),
     /// Confirm password input changed
@@ -225,6 +485,8 @@ pub enum Message {
     // Settings/Color customization
     /// Toggle settings modal
     ToggleSettings,
+    /// A keyboard shortcut was pressed outside any text field
+    Shortcut(shortcuts::Shortcut),
     /// Switch settings tab (0=Solid, 1=Gradient, 2=Rainbow)
     SetSettingsTab(u8),
     /// Hue slider changed (0-360)
@@ -239,12 +501,81 @@ pub enum Message {
     SetRainbowSpeed(f32),
     /// Set "their" bubble color
     SetTheirBubbleColor(String),
+    /// Export color preferences to a theme file
+    ExportTheme,
+    ThemeSavePathSelected(Option<std::path::PathBuf>),
+    /// Import color preferences from a theme file
+    ImportTheme,
+    ThemeFileSelected(Option<std::path::PathBuf>),
+    /// Set message text size (clamped)
+    SetFontSize(i32),
+    /// Set bubble corner radius (clamped)
+    SetBubbleRadius(f32),
+    /// Save the active conversation's transcript to Downloads
+    ExportConversation(conversation_store::ExportFormat),
+    /// Show/hide the bubble color picker for the active conversation
+    ToggleConversationColorPicker,
+    /// Set the active conversation's bubble color (None = use global)
+    SetConversationBubbleColor(Option<String>),
     /// Save color preferences
     SaveColorPrefs,
     /// Tick for rainbow animation
     RainbowTick,
     /// Tick for typing dots animation
     TypingDotsTick,
+    /// Periodic cleanup of expired message requests and disappearing messages
+    PurgeExpired,
+    /// Frequent sweep while the open chat has disappearing messages, so they
+    /// vanish on time rather than at the next purge
+    ExpiryTick,
+    /// Step the active chat's disappearing-message timer to the next option
+    CycleDisappearingTimer,
+    /// Ask to confirm revoking our key
+    RevokeMyKey,
+    /// Back out of revoking our key
+    CancelRevokeMyKey,
+    /// Replace our key with a new one, then publish the old key's revocation
+    /// certificate to all contacts
+    ConfirmRevokeMyKey,
+    /// The revoked key was replaced in the background; carries the old key's
+    /// revocation certificate to publish
+    RevokedKeyReplaced(Result<(RotatedKey, String), String>),
+    /// Turn toast notifications on/off
+    SetNotificationsEnabled(bool),
+    /// Turn the notification sound on/off
+    SetNotificationSound(bool),
+    /// Accept requests from saved contacts without prompting
+    SetAutoAcceptContacts(bool),
+    /// Let blocked senders' requests through again
+    UnblockAll,
+    /// Password field for confirming a key rotation
+    RotationPasswordChanged(String),
+    /// Replace our key with a new one and tell contacts (signed by the old key)
+    RotateMyKey,
+    /// Key rotation finished in the background
+    KeyRotated(Result<RotatedKey, String>),
+    /// New password field in settings
+    NewPasswordChanged(String),
+    /// Re-encrypt our secret key under the new password
+    ChangePassword,
+    /// Passphrase field for the full data archive
+    BackupPasswordChanged(String),
+    /// Export keys, conversations, contacts, groups and emotes to one archive
+    ExportAllData,
+    /// Restore an archive made by `ExportAllData`
+    ImportAllData,
+    /// Archive written (None if the save dialog was cancelled)
+    AllDataExported(Result<Option<String>, String>),
+    /// Archive restored (None if the file picker was cancelled)
+    AllDataImported(Result<Option<data_export::ImportSummary>, String>),
+    /// The stored history, read in the background after startup
+    HistoryLoaded(Result<(std::collections::HashMap<String, Conversation>, Option<String>), String>),
+    /// Device registry loaded or changed
+    DevicesLoaded(Result<devices::DeviceList, String>),
+    /// Revoke a lost device (device id)
+    RevokeDevice(String),
+    /// Tick for animated emote playback
+    EmoteAnimationTick,
     
     // Reactions
     /// Show reaction picker for a message (message index)
@@ -253,6 +584,25 @@ pub enum Message {
     AddReaction(usize, String),
     /// Hide reaction picker
     HideReactionPicker,
+    /// Copy a message's text (or image filename) to the clipboard (message index)
+    CopyMessageText(usize),
+    /// Open the conversation picker to forward a message (message index)
+    ForwardMessage(usize),
+    /// Forward the picked message to a contact or group (fingerprint or group id)
+    ForwardTo(String),
+    /// Close the forward picker
+    CancelForward,
+    /// Copy a contact's full fingerprint to the clipboard
+    CopyFingerprint(String),
+    /// End the session with the current peer, keeping the contact and history
+    Disconnect,
+    /// Send our key and current port to the selected peer again
+    ResendKey,
+    ResendKeyAddressChanged(String),
+    SubmitResendKeyAddress,
+    CancelResendKey,
+    /// Result of a key resend (peer address)
+    KeyResent(String, Result<(), String>),
 }
 
 #[derive(Debug, Clone)]
@@ -260,6 +610,16 @@ pub struct KeyGenResult {
     pub fingerprint: String,
 }
 
+/// Outcome of rotating our own key: the new identity plus the old-key-signed
+/// statement to send to contacts
+#[derive(Debug, Clone)]
+pub struct RotatedKey {
+    pub old_fingerprint: String,
+    pub new_fingerprint: String,
+    pub new_public_key: String,
+    pub signature: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct ImportResult {
     pub fingerprint: String,
@@ -276,6 +636,11 @@ impl Application for CryptoChat {
     fn new(_flags: ()) -> (Self, Command<Message>) {
         let app_state = Arc::new(app::AppState::new());
         
+        network::set_size_limits(network::SizeLimits::from_env());
+
+        // Drop message requests nobody answered in time
+        let _ = request_store::purge_expired_requests();
+        
         // Check if account exists (needs login)
         let has_account = account_store::account_exists();
         
@@ -300,11 +665,46 @@ impl Application for CryptoChat {
         let saved_username = request_store::load_username().ok().flatten();
         let default_username = saved_username.unwrap_or_else(|| format!("User{}", get_instance_id().unwrap_or(1)));
         
-        let init_command = if has_keys {
-            Command::perform(async { start_network_async().await }, Message::NetworkStarted)
-        } else {
-            Command::none()
-        };
+        // The history is checked with a key derived from the secret key, so it
+        // isn't loaded without one
+        let history_keypair = keystore::load_keypair()
+            .ok()
+            .flatten()
+            .and_then(|key| cryptochat_crypto_core::pgp::PgpKeyPair::from_secret_key(&key.secret_key_armored).ok());
+        let stored_fingerprint = keystore::load_keypair().ok().flatten().map(|key| key.fingerprint);
+        // Only the sidebar index is read up front; the histories follow in the
+        // background and replace it once they are in
+        let conversation_index = stored_fingerprint
+            .as_deref()
+            .and_then(|fp| conversation_store::load_index(fp).ok())
+            .unwrap_or_default();
+        let conversations = std::collections::HashMap::new();
+        let history_loaded = history_keypair.is_none();
+        let search_index = search::SearchIndex::build(&conversations);
+        // Sends the last session started but never confirmed
+        let unsent = stored_fingerprint
+            .as_deref()
+            .map(|fp| outbox::pending(fp).unwrap_or_else(|e| {
+                tracing::warn!(error = %e, "could not read the outbox");
+                Vec::new()
+            }))
+            .unwrap_or_default();
+
+        let mut init_commands = Vec::new();
+        if has_keys {
+            init_commands.push(Command::perform(async { start_network_async().await }, Message::NetworkStarted));
+        }
+        if let Some(keypair) = history_keypair {
+            init_commands.push(Command::perform(
+                async move {
+                    tokio::task::spawn_blocking(move || load_history(&keypair))
+                        .await
+                        .map_err(|e| e.to_string())
+                },
+                Message::HistoryLoaded,
+            ));
+        }
+        let init_command = Command::batch(init_commands);
         
         (
             Self {
@@ -318,11 +718,11 @@ impl Application for CryptoChat {
                 peer_address: None,
                 scroll_id: scrollable::Id::unique(),
                 message_input: String::new(),
-                conversations: if let Ok(Some(key)) = keystore::load_keypair() {
-                     conversation_store::load_conversations(&key.fingerprint).unwrap_or_default()
-                } else {
-                     std::collections::HashMap::new()
-                },
+                max_message_chars: compose::max_message_chars_from_env(),
+                pending_split: None,
+                conversations,
+                conversation_index,
+                history_loaded,
                 active_conversation_id: None,
                 status: if has_keys { "Set username, then share your key".to_string() } else { "Generate keys".to_string() },
                 generating_keys: false,
@@ -331,23 +731,55 @@ impl Application for CryptoChat {
                 unread_count: 0,
                 peer_is_typing: false,
                 typing_dots_phase: 0,
-                peer_last_read: None,
                 show_emoji_picker: false,
+                show_archived: false,
+                show_search: false,
+                search_query: String::new(),
+                show_switcher: false,
+                switcher_query: String::new(),
+                search_include_archived: false,
+                search_hits: Vec::new(),
+                search_index,
+                search_index_unsaved: false,
+                unsent,
+                show_emote_library: false,
+                show_conversation_color_picker: false,
                 emoji_suggestions: Vec::new(),
                 dark_mode: true,  // Default to dark mode
                 reaction_picker_for_msg: None,
+                forward_picker_for_msg: None,
+                recorder: None,
                 pending_requests: Vec::new(),
+                first_unread: None,
+                request_replay: request_replay::ReplayGuard::new(),
+                awaited_answers: request_store::AwaitedAnswers::new(),
+                pending_group_invites: Vec::new(),
+                group_rename: None,
+                contact_alias_edit: None,
+                resend_key_address: None,
                 groups: Vec::new(), // Will be loaded when fingerprint available
                 pending_group_delete: None,
+                qr_display: None,
                 group_invite_input: String::new(),
                 selected_group_id: None,
                 password_input: String::new(),
                 confirm_password_input: String::new(),
                 login_error: None,
+                rotation_password: String::new(),
+                confirm_revoke: false,
+                new_password: String::new(),
+                backup_password: String::new(),
+                device_list: None,
                 
                 // Color settings - load from disk and apply to theme
                 show_settings: false,
                 settings_tab: 0,
+                notification_settings: {
+                    let settings = notifications::load_settings();
+                    notifications::apply_settings(&settings);
+                    settings
+                },
+                request_settings: request_store::load_request_settings(),
                 color_prefs: {
                     let prefs = color_store::load_preferences();
                     // Set initial bubble color in theme
@@ -370,10 +802,14 @@ impl Application for CryptoChat {
                     prefs
                 },
                 rainbow_offset: 0.0,
+                emote_clock_ms: 0,
                 gradient_color1: "#ff0000".to_string(),
                 gradient_color2: "#0000ff".to_string(),
                 
                 emote_manager: emote_manager::EmoteManager::new(),
+                emote_requests: emote_manager::EmoteRequestTracker::new(),
+                relay_ack: Vec::new(),
+                group_resyncs: std::collections::HashMap::new(),
             },
             init_command,
         )
@@ -425,6 +861,38 @@ impl Application for CryptoChat {
                     Ok(port) => {
                         self.listening_port = Some(port);
                         self.status = "Ready - Copy & share your key!".to_string();
+                        self.publish_settings();
+                        
+                        let previous_port = request_store::load_listening_port().ok().flatten();
+                        if previous_port != Some(port) {
+                            let _ = request_store::save_listening_port(port);
+                        }
+                        // Contacts still have our old port stored; tell them where we are
+                        // now, signed so they know it's us
+                        let moved = previous_port.filter(|p| *p != port).and_then(|_| self.app_state.get_keypair());
+                        if let Some(keypair) = moved {
+                            let notice = match request_store::AddressNotice::sign(&keypair, port, now_ms()) {
+                                Ok(notice) => notice,
+                                Err(e) => {
+                                    tracing::error!(error = %e, "could not sign address update");
+                                    return Command::none();
+                                }
+                            };
+                            let addresses: Vec<String> = self.contacts.iter()
+                                .filter(|c| !c.address.is_empty())
+                                .map(|c| c.address.clone())
+                                .collect();
+                            let envelope = network::MessageEnvelope::AddressUpdate {
+                                fingerprint: keypair.fingerprint(),
+                                new_port: port,
+                                sent_ms: notice.sent_ms,
+                                signature: notice.signature,
+                            };
+                            let _ = std::thread::spawn(move || {
+                                let _ = network::NetworkHandle::send_to_group(&addresses, envelope);
+                            });
+                            self.status = format!("Listening on new port {} - contacts notified", port);
+                        }
                     }
                     Err(e) => self.status = format!("Network error: {}", e),
                 }
@@ -442,7 +910,7 @@ impl Application for CryptoChat {
                     (Some((_, public_key, _)), Some(port)) => {
                         let key_share = network::KeyShareData {
                             public_key,
-                            address: format!("127.0.0.1:{}", port),
+                            address: network::advertised_address(port),
                             username: Some(self.my_username.clone()),
                         };
                         match serde_json::to_string(&key_share) {
@@ -476,6 +944,30 @@ impl Application for CryptoChat {
                     Message::KeyShareImported,
                 )
             }
+            Message::TestConnection => {
+                // Before importing, test the address in the pasted key share
+                let pasted = serde_json::from_str::<network::KeyShareData>(self.key_share_input.trim()).ok().map(|share| share.address);
+                let Some(address) = pasted.or_else(|| self.peer_address.clone()) else {
+                    self.status = "Paste a key share or connect to a peer first".to_string();
+                    return Command::none();
+                };
+                self.status = format!("Testing connection to {}...", address);
+                Command::perform(
+                    async move {
+                        let target = address.clone();
+                        let diagnosis = tokio::task::spawn_blocking(move || network::test_connection(&target, network::CONNECT_TEST_TIMEOUT))
+                            .await
+                            .unwrap_or_else(|e| network::ConnectionDiagnosis::Other(e.to_string()));
+                        (address, diagnosis)
+                    },
+                    |(address, diagnosis)| Message::ConnectionTested(address, diagnosis),
+                )
+            }
+            Message::ConnectionTested(address, diagnosis) => {
+                tracing::info!(peer = %address, result = %diagnosis, "connection test");
+                self.status = format!("{}: {}", address, diagnosis);
+                Command::none()
+            }
             Message::KeyShareImported(result) => {
                 match result {
                     Ok(res) => {
@@ -494,25 +986,26 @@ impl Application for CryptoChat {
                             let contact = request_store::SimpleContact {
                                 name: peer_name.clone(),
                                 fingerprint: res.fingerprint.clone(),
-                                public_key: self.app_state.recipient_keypair.read().unwrap()
-                                    .as_ref().map(|k| k.export_public_key().unwrap_or_default()).unwrap_or_default(),
+                                public_key: self.app_state.get_recipient_public_key().unwrap_or_default(),
                                 address: res.address.clone(),
+                                revoked: false,
+                                alias: None,
+                                address_updated_ms: 0,
                             };
                             let _ = request_store::upsert_simple_contact(&contact);
                             self.contacts = request_store::load_simple_contacts().unwrap_or_default();
                         }
                         
+                        tracing::info!(peer = logging::short_fp(&res.fingerprint), new_contact = !already_saved, "imported key share");
                         self.status = format!("Connected to {}!", peer_name);
                         
-                        // Send OUR public key to the peer so they can encrypt messages to us
-                        // Skip if we're already connected (prevents race conditions)
-                        if let (Ok(Some(our_key)), Some(port)) = (keystore::load_keypair(), self.listening_port) {
-                            let envelope = network::MessageEnvelope::AcceptedResponse {
-                                sender_fingerprint: our_key.fingerprint.clone(),
-                                sender_public_key: our_key.public_key_armored.clone(),
-                                sender_listening_port: port,
-                                sender_name: Some(self.my_username.clone()),
-                            };
+                        // Send OUR public key to the peer so they can encrypt messages to us,
+                        // as a signed request they can accept or decline
+                        let request = self.app_state.get_keypair().zip(self.listening_port).and_then(|(keypair, port)| {
+                            network::MessageEnvelope::request(&keypair, port, &self.my_username, cryptochat_messaging::requests::RequestKind::Chat, now_ms()).ok()
+                        });
+                        if let Some(envelope) = request {
+                            self.awaited_answers.record(&res.fingerprint, now_ms());
                             let peer_addr = res.address.clone();
                             return Command::perform(
                                 async move {
@@ -529,8 +1022,17 @@ impl Application for CryptoChat {
             }
             Message::MessageInputChanged(value) => {
                 let was_empty = self.message_input.is_empty();
+                let was_over = self.message_input.chars().count() > self.max_message_chars;
                 self.message_input = value.clone();
                 let is_empty = self.message_input.is_empty();
+                if !was_over && self.message_input.chars().count() > self.max_message_chars {
+                    self.status = format!("Over the {} character limit: split the message or send it as a file", self.max_message_chars);
+                }
+                
+                // Keep the active conversation's draft in sync (persisted on next save)
+                if let Some(conv) = self.get_active_conversation_mut() {
+                    conv.input_draft = value.clone();
+                }
                 
                 // Discord-style :emoji: autocomplete
                 self.emoji_suggestions.clear();
@@ -548,7 +1050,29 @@ impl Application for CryptoChat {
                 }
                 
                 // Send typing indicator when user starts/stops typing
-                if self.recipient_key_imported {
+                if let Some(group_id) = self.selected_group_id.clone() {
+                    let is_typing = !is_empty;
+                    let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
+                    let others: Vec<String> = self.groups.iter()
+                        .find(|g| g.id == group_id)
+                        .map(|g| g.members.iter()
+                            .filter(|m| m.fingerprint != my_fp && !m.address.is_empty())
+                            .map(|m| m.address.clone())
+                            .collect())
+                        .unwrap_or_default();
+                    if (was_empty != is_empty || is_typing) && !others.is_empty() {
+                        let port = self.listening_port.unwrap_or(network::DEFAULT_PORT);
+                        let envelope = network::MessageEnvelope::TypingIndicator {
+                            is_typing,
+                            sender_fingerprint: my_fp,
+                            sender_listening_port: port,
+                            group_id: Some(group_id),
+                        };
+                        let _ = std::thread::spawn(move || {
+                            let _ = network::NetworkHandle::send_to_group(&others, envelope);
+                        });
+                    }
+                } else if self.recipient_key_imported {
                     if let Some(addr) = &self.peer_address {
                         let is_typing = !is_empty;
                         if was_empty != is_empty || is_typing {
@@ -558,6 +1082,7 @@ impl Application for CryptoChat {
                                 is_typing,
                                 sender_fingerprint: my_fp,
                                 sender_listening_port: port,
+                                group_id: None,
                             };
                             let addr = addr.clone();
                             let _ = std::thread::spawn(move || {
@@ -572,139 +1097,185 @@ impl Application for CryptoChat {
                 if self.message_input.trim().is_empty() {
                     return Command::none();
                 }
-                self.unread_count = 0; // Clear unread when user is active
-                let content = self.message_input.clone();
-                self.message_input.clear();
-                
-                // Emote parsing
-                let mut emotes = std::collections::HashMap::new();
-                if let Ok(lib) = self.emote_manager.library.read() {
-                   for (name, emote) in lib.iter() {
-                       let pattern = format!(":{}:", name);
-                       if content.contains(&pattern) {
-                           emotes.insert(name.clone(), emote.hash.clone());
-                       }
-                   }
+                // Oversized drafts go out through the split / send-as-file buttons
+                let used = self.message_input.chars().count();
+                match compose::check_length(&self.message_input, self.max_message_chars) {
+                    compose::LengthCheck::Fits => {}
+                    compose::LengthCheck::Split(parts) => {
+                        self.status = format!("Message is too long ({}/{}): split it into {} messages or send it as a file", used, self.max_message_chars, parts);
+                        return Command::none();
+                    }
+                    compose::LengthCheck::SendAsFile if self.selected_group_id.is_some() => {
+                        self.status = format!(
+                            "Message is too long ({}/{}): groups can't receive files, so shorten it to about {} characters to split it",
+                            used, self.max_message_chars, self.max_message_chars * compose::MAX_SPLIT_PARTS
+                        );
+                        return Command::none();
+                    }
+                    compose::LengthCheck::SendAsFile => {
+                        self.status = format!("Message is too long ({}/{}): send it as a file", used, self.max_message_chars);
+                        return Command::none();
+                    }
                 }
-                
-                let network_payload = if !emotes.is_empty() {
-                    let payload = EmotePayload {
-                        content: content.clone(),
-                        emotes: emotes.clone(),
-                    };
-                    serde_json::to_string(&payload).unwrap_or(content.clone())
-                } else {
-                    content.clone()
-                };
-
-                let new_msg = ChatMessage {
-                    sender_name: self.my_username.clone(),
-                    content: content.clone(),
-                    is_mine: true,
-                    timestamp: chrono_time(),
-                    image_data: None,
-                    image_filename: None,
-                    reactions: Vec::new(),
-                    emotes: emotes,
+                let content = self.take_draft();
+                self.send_text(content)
+            }
+            Message::SendSplitMessage => {
+                if self.pending_split.is_some() {
+                    self.status = "Still sending the previous split message".to_string();
+                    return Command::none();
+                }
+                let content = self.take_draft();
+                let mut parts: std::collections::VecDeque<String> =
+                    compose::split_message(&content, self.max_message_chars).into();
+                let count = parts.len();
+                if count > 1 {
+                    self.status = format!("Sending as {} messages", count);
+                }
+                if self.selected_group_id.is_some() || !self.recipient_key_imported {
+                    // Group sends finish before send_text returns, so these already go in order
+                    let commands: Vec<_> = parts.into_iter().map(|part| self.send_text(part)).collect();
+                    return Command::batch(commands);
+                }
+                let Some(first) = parts.pop_front() else {
+                    return Command::none();
                 };
-                // save_message_to_history(&new_msg); // TODO: Refactor persistence
-                
-                // Route to group or direct peer
-                // Route to group or direct peer
-                let group_id_opt = self.selected_group_id.clone();
-                if let Some(ref group_id) = group_id_opt {
-                    // Add to group conversation
-                    self.add_message(group_id.clone(), "Group".to_string(), new_msg.clone(), None);
-
-                    // Group message sending...
-                    if let Some(group) = self.groups.iter().find(|g| &g.id == group_id) {
-                        let member_addresses: Vec<String> = group.members.iter()
-                            .filter(|m| m.fingerprint != self.app_state.get_fingerprint().unwrap_or_default())
-                            .map(|m| m.address.clone())
-                            .collect();
-                        
-                        if member_addresses.is_empty() {
-                            self.status = "No other members in group yet".to_string();
-                            return Command::none();
-                        }
-                        
-                        let username = self.my_username.clone();
-                        let group_id_clone = group_id.clone();
-                        let fingerprint = self.app_state.get_fingerprint().unwrap_or_default();
-                        let envelope = network::MessageEnvelope::GroupMessage {
-                            group_id: group_id_clone,
-                            sender_fingerprint: fingerprint,
-                            sender_name: username,
-                            encrypted_content: network_payload, 
-                            timestamp: chrono_time(),
-                            expires_at: None,
-                        };
-                        
-                        let (sent, failures) = network::NetworkHandle::send_to_group(&member_addresses, envelope);
-                        if failures.is_empty() {
-                            self.status = format!("Sent to {} members", sent);
-                        } else {
-                            self.status = format!("Sent to {}/{} members", sent, member_addresses.len());
+                // Direct sends run in the background: each part waits for the
+                // previous one so the peer gets them in order
+                let id = uuid::Uuid::new_v4();
+                if !parts.is_empty() {
+                    self.pending_split = Some(compose::PendingSplit {
+                        conversation_id: self.app_state.get_recipient_fingerprint().unwrap_or_default(),
+                        in_flight: id.to_string(),
+                        remaining: parts,
+                    });
+                }
+                self.send_text_with_id(first, id)
+            }
+            Message::SendDraftAsFile => {
+                if !self.recipient_key_imported || self.selected_group_id.is_some() {
+                    self.status = "Files can only be sent in direct chats".to_string();
+                    return Command::none();
+                }
+                let data = self.take_draft().into_bytes();
+                let app_state = self.app_state.clone();
+                let peer_addr = self.peer_address.clone();
+                let sender_name = self.my_username.clone();
+                let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
+                let port = self.listening_port.unwrap_or(network::DEFAULT_PORT);
+                Command::perform(
+                    async move {
+                        tokio::task::spawn_blocking(move || {
+                            send_file_data(app_state, peer_addr, "message.txt".to_string(), data, sender_name, my_fp, port)
+                        }).await.map_err(|e| e.to_string())?
+                    },
+                    |r| Message::FileSent(r),
+                )
+            }
+            Message::MessageSent(result) => {
+                if let Err(e) = result {
+                    self.status = format!("Send failed: {}", e);
+                }
+                Command::none()
+            }
+            Message::DirectMessageSent(conv_id, message_id, result) => {
+                let delivery = if result.is_ok() { DeliveryStatus::Sent } else { DeliveryStatus::Failed };
+                if let Some(conv) = self.conversations.get_mut(&conv_id) {
+                    if conversation::update_delivery_status(conv, &message_id, delivery) {
+                        self.save_conversations();
+                    }
+                }
+                if let Some(fp) = self.app_state.get_fingerprint() {
+                    if result.is_ok() {
+                        if let Err(e) = outbox::clear(&message_id, &fp) {
+                            tracing::warn!(error = %e, "could not clear sent message from the outbox");
                         }
-
-                        return self.snap_to_bottom(); // Snap after sending to group
+                        self.unsent.retain(|e| e.message_id != message_id);
                     } else {
-                        self.status = "Group not found".to_string();
-                        Command::none()
+                        self.refresh_unsent(&fp);
                     }
-                } else if self.recipient_key_imported {
-                    // Direct peer message
-                    let app_state = self.app_state.clone();
-                    let peer_addr = self.peer_address.clone().unwrap();
-                    let username = self.my_username.clone();
-                    // Get fingerprint for adding to local convo
-                    if let Some(fp) = self.app_state.get_recipient_fingerprint() {
-                        self.add_message(fp, self.peer_username.clone().unwrap_or("Peer".to_string()), new_msg.clone(), Some(peer_addr.clone()));
+                }
+                let next_part = self.send_next_split_part(&conv_id, &message_id, result.is_ok());
+                Command::batch(vec![next_part, self.update(Message::MessageSent(result))])
+            }
+            Message::ResendUnsent => {
+                let Some(my_fp) = self.app_state.get_fingerprint() else {
+                    self.status = "Log in to resend messages".to_string();
+                    return Command::none();
+                };
+                let port = self.listening_port.unwrap_or(network::DEFAULT_PORT);
+                let mut sends = Vec::new();
+                let mut no_key = Vec::new();
+                for entry in std::mem::take(&mut self.unsent) {
+                    // Encrypt for the chat's saved contact, not whoever is open now
+                    let Some(key) = self.contacts.iter().find(|c| c.fingerprint == entry.conversation_id && !c.revoked).map(|c| c.public_key.clone()) else {
+                        no_key.push(entry);
+                        continue;
+                    };
+                    if let Some(conv) = self.conversations.get_mut(&entry.conversation_id) {
+                        conversation::update_delivery_status(conv, &entry.message_id, DeliveryStatus::Pending);
                     }
-                    
-                    let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
-                    // Launch async task to send
                     let app_state = self.app_state.clone();
-                    let port = self.listening_port.unwrap_or(network::DEFAULT_PORT);
-                    
-                    return Command::batch(vec![
-                        Command::perform(
-                            async move {
-                                 send_message_async(app_state, peer_addr, network_payload, username, my_fp, port).await
-                            },
-                            Message::MessageSent,
-                        ),
-                        self.snap_to_bottom()
-                    ]);
-                } else {
-                    self.status = "Import a key or select a group first".to_string();
-                    Command::none()
+                    let username = self.my_username.clone();
+                    let my_fp = my_fp.clone();
+                    let (conv_id, message_id) = (entry.conversation_id.clone(), entry.message_id.clone());
+                    sends.push(Command::perform(
+                        async move { resend_message_async(app_state, key, entry, username, my_fp, port).await },
+                        move |result| Message::DirectMessageSent(conv_id, message_id, result),
+                    ));
                 }
+                self.status = if no_key.is_empty() {
+                    format!("Resending {} message(s)...", sends.len())
+                } else {
+                    format!("Resending {} message(s); {} have no saved contact to encrypt for", sends.len(), no_key.len())
+                };
+                self.unsent = no_key;
+                self.save_conversations();
+                Command::batch(sends)
             }
-            Message::MessageSent(result) => {
-                if let Err(e) = result {
-                    self.status = format!("Send failed: {}", e);
+            Message::DiscardUnsent => {
+                if let Some(fp) = self.app_state.get_fingerprint() {
+                    for entry in std::mem::take(&mut self.unsent) {
+                        if let Err(e) = outbox::clear(&entry.message_id, &fp) {
+                            tracing::warn!(error = %e, "could not clear discarded message from the outbox");
+                        }
+                    }
                 }
+                self.unsent.clear();
                 Command::none()
             }
             Message::NetworkEvent(event) => {
                 match event {
-                    network::NetworkEvent::MessageReceived { encrypted_payload, sender_name, sender_fingerprint, sender_address } => {
-                        // First, try to find sender's public key from contacts for decryption
-                        let sender_key = self.contacts.iter()
-                            .find(|c| c.fingerprint == sender_fingerprint)
-                            .map(|c| c.public_key.clone());
+                    network::NetworkEvent::MessageReceived { encrypted_payload, sender_name, sender_fingerprint, sender_address, message_id } => {
+                        // Every key we hold for the sender's fingerprint, saved contact first
+                        let active_peer = self.app_state.get_recipient_keypair().ok().flatten()
+                            .and_then(|k| Some((k.fingerprint(), k.export_public_key().ok()?)));
+                        let candidates = sender_keys::candidate_keys(
+                            &sender_fingerprint,
+                            &self.contacts,
+                            active_peer.as_ref().map(|(fp, key)| (fp.as_str(), key.as_str())),
+                            &self.groups,
+                        );
                         
-                        // Try decryption: use sender key from contacts, or fall back to active recipient
-                        let decrypt_result = if let Some(ref key) = sender_key {
-                            self.app_state.decrypt_message_with_sender_key(&encrypted_payload, key)
-                        } else {
+                        let decrypt_result = if candidates.is_empty() {
                             // Fall back to current recipient (might fail if not active)
                             self.app_state.decrypt_message(&encrypted_payload)
+                        } else {
+                            let keys: Vec<&str> = candidates.iter().map(|c| c.public_key.as_str()).collect();
+                            self.app_state.decrypt_message_with_any_key(&encrypted_payload, &keys).map(|(index, plaintext)| {
+                                if index > 0 {
+                                    let source = &candidates[index].source;
+                                    tracing::info!(peer = logging::short_fp(&sender_fingerprint), %source, "decrypted with fallback key");
+                                    self.status = format!("Decrypted with {}", source);
+                                }
+                                plaintext
+                            })
                         };
                         
                         match decrypt_result {
                             Ok(plaintext) => {
+                                let EmotePayload { content: plaintext, emotes } = EmotePayload::parse(&plaintext);
+                                self.request_missing_emotes(&emotes, &sender_address);
                                 let name = sender_name.unwrap_or_else(|| 
                                     // Try to find name in contacts if sender_name is missing
                                     self.contacts.iter()
@@ -723,17 +1294,40 @@ impl Application for CryptoChat {
                                     content: plaintext.clone(),
                                     is_mine: false,
                                     timestamp: chrono_time(),
+                                    sent_at_ms: now_ms(),
                                     image_data: None,
                                     image_filename: None,
                                     reactions: Vec::new(),
-                                    emotes: std::collections::HashMap::new(),
+                                    emotes,
+                                    expires_at: None,
+                                    message_id: None,
+                                    status: DeliveryStatus::Sent,
+                                    delivered_to: Vec::new(),
+                                    forwarded: false,
+                                    voice: None,
+                                    id: conversation::message_uuid(message_id.as_deref()),
                                 };
                                 // save_message_to_history(&new_msg); // TODO: Refactor persistence
-                                self.add_message(sender_fingerprint.clone(), name.clone(), new_msg, Some(sender_address.clone()));
+                                let added = self.add_message(sender_fingerprint.clone(), name.clone(), new_msg, Some(sender_address.clone()));
+                                
+                                // Let the sender know it arrived, duplicate or not
+                                if let Some(message_id) = message_id {
+                                    let envelope = network::MessageEnvelope::DeliveryAck {
+                                        message_id,
+                                        sender_fingerprint: self.app_state.get_fingerprint().unwrap_or_default(),
+                                        group_id: None,
+                                    };
+                                    let addr = sender_address.clone();
+                                    let _ = std::thread::spawn(move || {
+                                        let _ = network::NetworkHandle::send_message(&addr, envelope);
+                                    });
+                                }
                                 
                                 // Show notification and play sound
-                                show_notification(&format!("Message from {}", name), &plaintext);
-                                play_notification_sound();
+                                if added && conversation::should_notify(self.conversations.get(&sender_fingerprint), false) {
+                                    show_notification(&format!("Message from {}", name), &plaintext);
+                                    play_notification_sound();
+                                }
                                 
                                 // Reset typing indicator for this user
                                 if let Some(conv) = self.conversations.get_mut(&sender_fingerprint) {
@@ -766,12 +1360,8 @@ impl Application for CryptoChat {
                                 self.peer_username = Some(name.clone());
                                 if let Ok(Some(r)) = self.app_state.get_recipient_keypair() {
                                     let fp = r.fingerprint();
-                                    // Update in-memory contacts
-                                    for c in self.contacts.iter_mut() {
-                                        if c.fingerprint == fp && c.name != name {
-                                            c.name = name.clone();
-                                        }
-                                    }
+                                    // Update in-memory contacts; a local alias still takes precedence
+                                    request_store::apply_synced_name(&mut self.contacts, &fp, &name);
                                     // Update on disk
                                     let _ = request_store::update_contact_name(&fp, &name);
                                 }
@@ -785,14 +1375,64 @@ impl Application for CryptoChat {
                                 Command::none()
                             },
                             Err(e) => {
+                                tracing::warn!(peer = logging::short_fp(&sender_fingerprint), error = %e, "failed to decrypt message");
                                 self.status = format!("Decrypt error: {}", e);
                                 Command::none()
                             }
                         }
                     }
-                    network::NetworkEvent::RequestReceived { sender_fingerprint, sender_public_key, sender_address, sender_name } => {
+                    network::NetworkEvent::RequestReceived { sender_fingerprint, sender_public_key, sender_address, sender_name, kind, stamp, is_request } => {
+                        let checked = if is_request {
+                            self.request_replay.check_request(&stamp, &sender_fingerprint, &sender_public_key, kind, now_ms())
+                        } else {
+                            self.request_replay.check_response(&stamp, &sender_fingerprint, &sender_public_key, now_ms())
+                        };
+                        if let Err(reason) = checked {
+                            tracing::warn!(peer = logging::short_fp(&sender_fingerprint), %reason, "dropping connection request");
+                            return Command::none();
+                        }
+
+                        // The key must be the sender's own. Stamped requests were checked
+                        // above; legacy ones could pair a contact's fingerprint with another
+                        // key, which a re-link would then save over theirs
+                        if !request_store::key_belongs_to(&sender_fingerprint, &sender_public_key) {
+                            tracing::warn!(peer = logging::short_fp(&sender_fingerprint), "dropping connection request with someone else's key");
+                            return Command::none();
+                        }
+
                         // Add to pending requests instead of auto-connecting
                         let name = sender_name.clone().unwrap_or_else(|| sender_fingerprint[..8].to_string());
+
+                        // A saved contact resending the key we already trust (after a
+                        // restart or port change): follow them to the new address and
+                        // answer so their side connects too. Their key is public, so only
+                        // a stamp it signed shows the contact really moved; unsigned ones
+                        // from older clients are prompted like anyone else's. With
+                        // auto-accept off only answers to our own requests are followed
+                        let route = request_store::route_request(&self.contacts, kind, &sender_fingerprint, &sender_public_key);
+                        let signed = !stamp.is_legacy();
+                        let asked = !is_request && self.awaited_answers.take(&sender_fingerprint, &sender_address, now_ms());
+                        let handling = request_store::request_handling(route, &self.request_settings, &sender_fingerprint, signed, asked);
+                        if handling == request_store::RequestHandling::Drop {
+                            tracing::debug!(peer = logging::short_fp(&sender_fingerprint), "dropping request from blocked sender");
+                            return Command::none();
+                        }
+                        if handling == request_store::RequestHandling::Follow {
+                            if request_store::apply_address_update(&mut self.contacts, &sender_fingerprint, &sender_address) {
+                                let _ = request_store::save_simple_contacts(&self.contacts);
+                            }
+                            if let Some(conv) = self.conversations.get_mut(&sender_fingerprint) {
+                                conv.peer_address = Some(sender_address.clone());
+                            }
+                            if self.app_state.get_recipient_fingerprint().as_deref() == Some(sender_fingerprint.as_str()) {
+                                self.peer_address = Some(sender_address.clone());
+                                self.app_state.set_peer_address(sender_address.clone());
+                            }
+                            tracing::info!(peer = logging::short_fp(&sender_fingerprint), "known contact resent key");
+                            let reply = if is_request { self.resend_key_to(sender_address, false) } else { Command::none() };
+                            self.status = format!("{} reconnected", name);
+                            return reply;
+                        }
                         
                         // Check if we already have this request pending
                         let already_pending = self.pending_requests.iter().any(|r| r.sender_fingerprint == sender_fingerprint);
@@ -803,15 +1443,31 @@ impl Application for CryptoChat {
                                 sender_address,
                                 sender_name,
                                 timestamp: chrono_time(),
+                                expires_at_ms: now_ms() + cryptochat_messaging::requests::DEFAULT_REQUEST_TTL_MS,
+                                route,
                             };
                             self.pending_requests.push(pending);
-                            show_notification("Connection Request", &format!("{} wants to chat", name));
+                            let (what, accept) = route.prompt();
+                            show_notification("Connection Request", &format!("{} {}", name, what));
                             play_notification_sound();
-                            self.status = format!("Request from: {} (Accept/Decline)", name);
+                            self.status = format!("Request from: {} ({}/Decline)", name, accept);
+                        }
+                        Command::none()
+                    }
+                    network::NetworkEvent::TypingUpdate { is_typing, sender_fingerprint, group_id: Some(group_id), .. } => {
+                        let Some(member_name) = self.groups.iter()
+                            .find(|g| g.id == group_id)
+                            .and_then(|g| g.members.iter().find(|m| m.fingerprint == sender_fingerprint))
+                            .map(|m| m.username.clone())
+                        else {
+                            return Command::none();
+                        };
+                        if let Some(conv) = self.conversations.get_mut(&group_id) {
+                            conversation::set_member_typing(conv, &sender_fingerprint, &member_name, is_typing, now_ms());
                         }
                         Command::none()
                     }
-                    network::NetworkEvent::TypingUpdate { is_typing, sender_fingerprint, sender_address } => {
+                    network::NetworkEvent::TypingUpdate { is_typing, sender_fingerprint, sender_address, group_id: None } => {
                         if let Some(conv) = self.conversations.get_mut(&sender_fingerprint) {
                             conv.is_typing = is_typing;
                             conv.peer_address = Some(sender_address);
@@ -822,10 +1478,42 @@ impl Application for CryptoChat {
                         if let Some(conv) = self.conversations.get_mut(&sender_fingerprint) {
                             conv.last_read = Some(last_read_timestamp);
                             conv.peer_address = Some(sender_address);
+                            // Sent while they had our chat open: everything so far is read
+                            if conversation::mark_read_through(conv, now_ms()) {
+                                self.save_conversations();
+                            }
+                        }
+                        Command::none()
+                    }
+                    network::NetworkEvent::SessionEnded { sender_fingerprint } => {
+                        if let Some(conv) = self.conversations.get_mut(&sender_fingerprint) {
+                            conv.is_typing = false;
+                            if self.active_conversation_id.as_ref() == Some(&sender_fingerprint) {
+                                self.status = format!("{} ended the session", conv.name);
+                            }
+                        }
+                        Command::none()
+                    }
+                    network::NetworkEvent::DeliveryAckReceived { message_id, sender_fingerprint, group_id: Some(group_id) } => {
+                        let is_member = self.groups.iter()
+                            .find(|g| g.id == group_id)
+                            .is_some_and(|g| g.members.iter().any(|m| m.fingerprint == sender_fingerprint));
+                        if let Some(conv) = self.conversations.get_mut(&group_id).filter(|_| is_member) {
+                            if conversation::record_member_delivery(conv, &message_id, &sender_fingerprint) {
+                                self.save_conversations();
+                            }
+                        }
+                        Command::none()
+                    }
+                    network::NetworkEvent::DeliveryAckReceived { message_id, sender_fingerprint, group_id: None } => {
+                        if let Some(conv) = self.conversations.get_mut(&sender_fingerprint) {
+                            if conversation::update_delivery_status(conv, &message_id, DeliveryStatus::Delivered) {
+                                self.save_conversations();
+                            }
                         }
                         Command::none()
                     }
-                    network::NetworkEvent::FileReceived { filename, encrypted_data, sender_name, sender_fingerprint, sender_address } => {
+                    network::NetworkEvent::FileReceived { filename, encrypted_data, sender_name, sender_fingerprint, sender_address, message_id } => {
                         // Decrypt and save file
                         use base64::Engine;
                         if let Ok(Some(stored_key)) = keystore::load_keypair() {
@@ -846,18 +1534,31 @@ impl Application for CryptoChat {
                                             content: if is_image { format!("[Image: {}]", filename) } else { format!("[File: {}]", filename) },
                                             is_mine: false,
                                             timestamp: chrono_time(),
+                                            sent_at_ms: now_ms(),
                                             image_data: if is_image { Some(decrypted.clone()) } else { None },
                                             image_filename: Some(filename.clone()),
                                             reactions: Vec::new(),
                                             emotes: std::collections::HashMap::new(),
+                                            expires_at: None,
+                                            message_id: None,
+                                            status: DeliveryStatus::Sent,
+                                            delivered_to: Vec::new(),
+                                            forwarded: false,
+                                            voice: None,
+                                            id: conversation::message_uuid(message_id.as_deref()),
                                         };
                                         
                                         // Don't save to history if it's an image (too large)
                                         // if !is_image { save_message_to_history(&new_msg); } // TODO: Refactor persistence
-                                        self.add_message(sender_fingerprint.clone(), name.clone(), new_msg, Some(sender_address.clone()));
+                                        // A resent file is dropped like a resent message
+                                        if !self.add_message(sender_fingerprint.clone(), name.clone(), new_msg, Some(sender_address.clone())) {
+                                            return Command::none();
+                                        }
                                         
-                                        show_notification(&format!("File from {}", name), &format!("Received: {}", filename));
-                                        play_notification_sound();
+                                        if conversation::should_notify(self.conversations.get(&sender_fingerprint), false) {
+                                            show_notification(&format!("File from {}", name), &format!("Received: {}", filename));
+                                            play_notification_sound();
+                                        }
                                         // unread handled by add_message
                                         self.status = format!("Received: {}", filename);
                                     }
@@ -866,6 +1567,70 @@ impl Application for CryptoChat {
                     }
                         Command::none()
                     }
+                    network::NetworkEvent::VoiceReceived { encrypted_audio, duration_ms, sender_name, sender_fingerprint, sender_address, message_id } => {
+                        use base64::Engine;
+                        let wav = keystore::load_keypair().ok().flatten()
+                            .and_then(|stored| cryptochat_crypto_core::pgp::PgpKeyPair::from_secret_key(&stored.secret_key_armored).ok())
+                            .zip(base64::engine::general_purpose::STANDARD.decode(&encrypted_audio).ok())
+                            .and_then(|(keypair, data)| keypair.decrypt(&data).ok());
+                        let Some(wav) = wav else {
+                            tracing::warn!(peer = logging::short_fp(&sender_fingerprint), "failed to decrypt voice message");
+                            self.status = "Couldn't decrypt a voice message".to_string();
+                            return Command::none();
+                        };
+                        let duration_ms = match voice::checked_duration_ms(&wav, duration_ms) {
+                            Ok(ms) => ms,
+                            Err(e) => {
+                                self.status = e.to_string();
+                                return Command::none();
+                            }
+                        };
+                        let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
+                        let blob = match blob_store::put(&wav, &my_fp) {
+                            Ok(blob) => blob,
+                            Err(e) => {
+                                self.status = format!("Couldn't save voice message: {}", e);
+                                return Command::none();
+                            }
+                        };
+                        let name = sender_name.unwrap_or_else(|| self.peer_username.clone().unwrap_or_else(|| "Peer".to_string()));
+                        let new_msg = ChatMessage {
+                            sender_name: name.clone(),
+                            content: format!("[Voice message {}]", voice::format_duration(duration_ms)),
+                            is_mine: false,
+                            timestamp: chrono_time(),
+                            sent_at_ms: now_ms(),
+                            image_data: None,
+                            image_filename: None,
+                            reactions: Vec::new(),
+                            emotes: std::collections::HashMap::new(),
+                            expires_at: None,
+                            message_id: None,
+                            status: DeliveryStatus::Sent,
+                            delivered_to: Vec::new(),
+                            forwarded: false,
+                            voice: Some(voice::VoiceClip { blob, duration_ms }),
+                            id: conversation::message_uuid(message_id.as_deref()),
+                        };
+                        let added = self.add_message(sender_fingerprint.clone(), name.clone(), new_msg, Some(sender_address.clone()));
+
+                        if let Some(message_id) = message_id {
+                            let envelope = network::MessageEnvelope::DeliveryAck {
+                                message_id,
+                                sender_fingerprint: my_fp,
+                                group_id: None,
+                            };
+                            let addr = sender_address.clone();
+                            let _ = std::thread::spawn(move || {
+                                let _ = network::NetworkHandle::send_message(&addr, envelope);
+                            });
+                        }
+                        if added && conversation::should_notify(self.conversations.get(&sender_fingerprint), false) {
+                            show_notification(&format!("Voice message from {}", name), &voice::format_duration(duration_ms));
+                            play_notification_sound();
+                        }
+                        Command::none()
+                    }
                     network::NetworkEvent::ContactRemovalReceived { fingerprint } => {
                         // Peer removed us as a contact, remove them too
                         if let Some(idx) = self.contacts.iter().position(|c| c.fingerprint == fingerprint) {
@@ -882,74 +1647,469 @@ impl Application for CryptoChat {
                         }
                         Command::none()
                     }
-                    network::NetworkEvent::GroupInviteReceived {  group_name, .. } => {
-                        // TODO: Implement pending group invites
-                        self.status = format!("Received invite to group: {}", group_name);
-                        show_notification("New Group Invite", &format!("Invited to {}", group_name));
-                        // Later: Add to pending_groups list
+                    network::NetworkEvent::GroupInviteReceived { group_id, group_name, creator_name, encrypted_symmetric_key, members, settings, membership_epoch } => {
+                        let invite = group_store::PendingGroupInvite {
+                            group_id,
+                            group_name: group_name.clone(),
+                            creator_name: creator_name.clone(),
+                            encrypted_symmetric_key,
+                            members,
+                            settings,
+                            received_at: chrono::Utc::now().to_rfc3339(),
+                            membership_epoch,
+                        };
+                        if group_store::add_pending_invite(&mut self.pending_group_invites, &self.groups, invite) {
+                            let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
+                            let _ = group_store::save_pending_invites(&self.pending_group_invites, &my_fp);
+                            self.status = format!("{} invited you to '{}' (Accept/Decline)", creator_name, group_name);
+                            show_notification("New Group Invite", &format!("Invited to {}", group_name));
+                            play_notification_sound();
+                        }
                         Command::none()
                     }
-                    network::NetworkEvent::GroupMessageReceived { group_id,  sender_name, encrypted_content, timestamp, .. } => {
+                    network::NetworkEvent::GroupMessageReceived { group_id, sender_fingerprint, sender_name, encrypted_content, timestamp, expires_at, message_id } => {
                         // Add received group message to chat
-                        // Decrypt group message (TODO: Implement Group Encryption)
+                        let Some(group) = self.groups.iter().find(|g| g.id == group_id) else {
+                            return Command::none();
+                        };
+                        let content = match group_crypto::open_content(group, &encrypted_content) {
+                            Ok(content) => content,
+                            Err(e) => {
+                                // Includes replays from before the last membership change
+                                tracing::warn!(group = %group_id, peer = logging::short_fp(&sender_fingerprint), error = %e, "rejected group message");
+                                self.status = format!("Couldn't read a message in '{}': {}", group.name, e);
+                                if e.needs_resync() {
+                                    self.request_group_resync(&group_id);
+                                }
+                                return Command::none();
+                            }
+                        };
+                        let payload = EmotePayload::parse(&content);
+                        let id = conversation::message_uuid(message_id.as_deref());
+                        let sender_address = self.groups.iter()
+                            .find(|g| g.id == group_id)
+                            .and_then(|g| g.members.iter().find(|m| m.fingerprint == sender_fingerprint))
+                            .map(|m| m.address.clone());
+                        if let Some(addr) = sender_address {
+                            self.request_missing_emotes(&payload.emotes, &addr);
+                            // Let the sender know it reached us
+                            if let Some(message_id) = message_id {
+                                let envelope = network::MessageEnvelope::DeliveryAck {
+                                    message_id,
+                                    sender_fingerprint: self.app_state.get_fingerprint().unwrap_or_default(),
+                                    group_id: Some(group_id.clone()),
+                                };
+                                let _ = std::thread::spawn(move || {
+                                    let _ = network::NetworkHandle::send_message(&addr, envelope);
+                                });
+                            }
+                        }
+                        let group_name = self.groups.iter()
+                            .find(|g| g.id == group_id)
+                            .map(|g| g.name.clone())
+                            .unwrap_or_else(|| "Group".to_string());
+                        let mentioned = mentions::mentions_user(&payload.content, &self.group_roster(&group_id), &self.my_username);
+                        let preview = payload.content.clone();
                         let new_msg = ChatMessage {
                             sender_name: sender_name.clone(),
-                            content: encrypted_content, 
+                            content: payload.content, 
                             is_mine: false,
                             timestamp,
+                            sent_at_ms: now_ms(),
                             image_data: None,
                             image_filename: None,
                             reactions: Vec::new(),
-                            emotes: std::collections::HashMap::new(),
+                            emotes: payload.emotes,
+                            expires_at,
+                            message_id: None,
+                            status: DeliveryStatus::Sent,
+                            delivered_to: Vec::new(),
+                            forwarded: false,
+                            voice: None,
+                            id,
                         };
                         // self.chat_messages.push(new_msg);
-                        self.add_message(group_id.clone(), "Group".to_string(), new_msg, None);
+                        let added = self.add_message(group_id.clone(), "Group".to_string(), new_msg, None);
+                        if let Some(conv) = self.conversations.get_mut(&group_id) {
+                            conversation::set_member_typing(conv, &sender_fingerprint, &sender_name, false, now_ms());
+                        }
                         
-                        show_notification(&format!("{} ({})", sender_name, "Group"), "New group message");
-                        play_notification_sound();
+                        if added && conversation::should_notify(self.conversations.get(&group_id), mentioned) {
+                            if mentioned {
+                                show_notification(&format!("You were mentioned in {}", group_name), &format!("{}: {}", sender_name, preview));
+                            } else {
+                                show_notification(&format!("{} ({})", sender_name, "Group"), "New group message");
+                            }
+                            play_notification_sound();
+                        }
                          // Unread handled in add_message
                         Command::none()
                     }
                     
-                    network::NetworkEvent::GroupJoinReceived { group_id, new_member, sender_address } => {
-                        // A new member joined - add them to our local group
-                        if let Some(group) = self.groups.iter_mut().find(|g| g.id == group_id) {
-                            // Check if member already exists
-                            if !group.members.iter().any(|m| m.fingerprint == new_member.fingerprint) {
-                                let new_name = new_member.username.clone();
-                                group.members.push(new_member);
-                                
-                                // Capture data before releasing mutable borrow
-                                let member_count = group.members.len();
-                                let members_clone = group.members.clone();
-                                let gid = group_id.clone();
-                                
-                                // Save updated group and send sync response
-                                if let Ok(Some(stored_key)) = keystore::load_keypair() {
-                                    let all_groups: Vec<_> = self.groups.iter().cloned().collect();
-                                    let _ = group_store::save_groups(&all_groups, &stored_key.fingerprint);
-                                    
-                                    // Send our full member list back to the new joiner
-                                    let sync_response = network::MessageEnvelope::GroupMemberSync {
-                                        group_id: gid,
-                                        members: members_clone,
-                                    };
-                                    let _ = network::NetworkHandle::send_message(&sender_address, sync_response);
-                                    
-                                    self.status = format!("{} joined the group ({} members)", new_name, member_count);
+                    network::NetworkEvent::KeyRevocationReceived { fingerprint, revocation_certificate } => {
+                        let Some(contact) = self.contacts.iter_mut().find(|c| c.fingerprint == fingerprint) else {
+                            return Command::none();
+                        };
+                        let applied = cryptochat_crypto_core::pgp::PgpKeyPair::parse_public_key(&contact.public_key)
+                            .and_then(|mut key| {
+                                key.apply_revocation(&revocation_certificate)?;
+                                key.export_public_key()
+                            });
+                        match applied {
+                            Ok(revoked_key) => {
+                                contact.public_key = revoked_key;
+                                contact.revoked = true;
+                                let name = contact.name.clone();
+                                let _ = request_store::save_simple_contacts(&self.contacts);
+                                tracing::warn!(peer = logging::short_fp(&fingerprint), "contact revoked their key");
+                                show_notification("Key Revoked", &format!("{} revoked their key. Don't trust new messages from it.", name));
+                                self.status = format!("{} revoked their key", name);
+                            }
+                            Err(e) => {
+                                tracing::warn!(peer = logging::short_fp(&fingerprint), error = %e, "ignored invalid revocation");
+                                self.status = format!("Ignored invalid revocation: {}", e);
+                            }
+                        }
+                        Command::none()
+                    }
+                    
+                    network::NetworkEvent::KeyRotationReceived { old_fingerprint, new_public_key, signature } => {
+                        // Only a key we still trust can vouch for its replacement
+                        let Some(contact) = self.contacts.iter_mut().find(|c| c.fingerprint == old_fingerprint && !c.revoked) else {
+                            return Command::none();
+                        };
+                        let statement = key_rotation::RotationStatement { old_fingerprint: old_fingerprint.clone(), new_public_key, signature };
+                        let new_key = match key_rotation::verify_rotation(&contact.public_key, &statement) {
+                            Ok(key) => key,
+                            Err(e) => {
+                                tracing::warn!(peer = logging::short_fp(&old_fingerprint), error = %e, "ignored key rotation");
+                                self.status = format!("Ignored key rotation: {}", e);
+                                return Command::none();
+                            }
+                        };
+                        let new_fingerprint = new_key.fingerprint();
+                        contact.fingerprint = new_fingerprint.clone();
+                        contact.public_key = statement.new_public_key.clone();
+                        let name = contact.name.clone();
+                        let _ = request_store::save_simple_contacts(&self.contacts);
+
+                        if let Some(mut conv) = self.conversations.remove(&old_fingerprint) {
+                            conv.id = new_fingerprint.clone();
+                            if let Some(pos) = conv.read_through_ms.remove(&old_fingerprint) {
+                                conv.read_through_ms.insert(new_fingerprint.clone(), pos);
+                            }
+                            self.conversations.insert(new_fingerprint.clone(), conv);
+                            self.save_conversations();
+                        }
+                        if self.active_conversation_id.as_deref() == Some(old_fingerprint.as_str()) {
+                            self.active_conversation_id = Some(new_fingerprint.clone());
+                            self.app_state.set_recipient_keypair(new_key);
+                        }
+                        if group_store::apply_identity_rotation(&mut self.groups, &old_fingerprint, &new_fingerprint, &statement.new_public_key) {
+                            if let Some(my_fp) = self.app_state.get_fingerprint() {
+                                let _ = group_store::save_groups(&self.groups, &my_fp);
+                            }
+                        }
+
+                        tracing::info!(old = logging::short_fp(&old_fingerprint), new = logging::short_fp(&new_fingerprint), "contact rotated key");
+                        show_notification("Key Changed", &format!("{} rotated to a new key (verified with their old key)", name));
+                        self.status = format!("{} rotated their key", name);
+                        Command::none()
+                    }
+                    
+                    network::NetworkEvent::DisappearingTimerChanged { timer_secs, sender_fingerprint, sender_address } => {
+                        if let Some(conv) = self.conversations.get_mut(&sender_fingerprint) {
+                            conv.disappearing_timer_secs = timer_secs;
+                            conv.peer_address = Some(sender_address);
+                            let name = conv.name.clone();
+                            self.save_conversations();
+                            self.status = format!("{} set disappearing messages: {}", name, conversation::disappearing_timer_label(timer_secs));
+                        }
+                        Command::none()
+                    }
+                    
+                    network::NetworkEvent::AddressUpdateReceived { fingerprint, new_address, notice } => {
+                        if !request_store::accept_address_notice(&mut self.contacts, &fingerprint, &notice, now_ms()) {
+                            tracing::warn!(peer = logging::short_fp(&fingerprint), "ignoring address update not signed by the contact");
+                            return Command::none();
+                        }
+                        request_store::apply_address_update(&mut self.contacts, &fingerprint, &new_address);
+                        let _ = request_store::save_simple_contacts(&self.contacts);
+                        if let Some(conv) = self.conversations.get_mut(&fingerprint) {
+                            conv.peer_address = Some(new_address.clone());
+                            self.save_conversations();
+                        }
+                        if self.active_conversation_id.as_deref() == Some(fingerprint.as_str()) {
+                            self.peer_address = Some(new_address.clone());
+                            self.app_state.set_peer_address(new_address);
+                        }
+                        Command::none()
+                    }
+                    
+                    network::NetworkEvent::SettingsSyncReceived { sealed, sender_fingerprint } => {
+                        let Some(keypair) = self.app_state.get_keypair().filter(|k| k.fingerprint() == sender_fingerprint) else {
+                            return Command::none();
+                        };
+                        let merged = devices::local_device_id()
+                            .and_then(|id| settings_sync::device_id(&id))
+                            .and_then(|this_device| settings_sync::open_and_merge(sealed, &this_device, &keypair, &mut self.conversations));
+                        match merged {
+                            Ok(changed) if !changed.is_empty() => {
+                                tracing::info!(chats = changed.len(), "merged settings from another device");
+                                self.save_conversations();
+                            }
+                            Ok(_) => {}
+                            Err(e) => tracing::warn!(error = %e, "dropping unreadable settings sync"),
+                        }
+                        Command::none()
+                    }
+                    network::NetworkEvent::ConversationReadReceived { conversation_id, last_read_id, sender_fingerprint, sender_address } => {
+                        let conv_id = match self.groups.iter().find(|g| g.id == conversation_id) {
+                            Some(group) if group.members.iter().any(|m| m.fingerprint == sender_fingerprint) => conversation_id,
+                            Some(_) => return Command::none(),
+                            None => sender_fingerprint.clone(),
+                        };
+                        if let Some(conv) = self.conversations.get_mut(&conv_id) {
+                            if conv_id == sender_fingerprint {
+                                conv.peer_address = Some(sender_address);
+                            }
+                            // Older clients only send a count, which our history can't be matched against
+                            let Some(through_ms) = last_read_id.and_then(|id| conversation::read_through_ms(conv, id)) else {
+                                return Command::none();
+                            };
+                            let mut changed = conversation::record_read(conv, &sender_fingerprint, through_ms);
+                            if conv_id == sender_fingerprint {
+                                changed |= conversation::mark_read_through(conv, through_ms);
+                            }
+                            if changed {
+                                self.save_conversations();
+                            }
+                        }
+                        Command::none()
+                    }
+                    
+                    network::NetworkEvent::GroupSettingsReceived { group_id, new_settings, sender_fingerprint } => {
+                        let Ok(Some(stored_key)) = keystore::load_keypair() else {
+                            return Command::none();
+                        };
+                        if let Some(group) = self.groups.iter_mut().find(|g| g.id == group_id) {
+                            if group_store::can_edit_metadata(group, &sender_fingerprint) {
+                                group.settings = new_settings;
+                                let status = format!("{}: disappearing messages {}", group.name,
+                                    conversation::disappearing_timer_label(group.settings.disappearing_timer_secs));
+                                let all_groups: Vec<_> = self.groups.iter().cloned().collect();
+                                let _ = group_store::save_groups(&all_groups, &stored_key.fingerprint);
+                                self.status = status;
+                            }
+                        }
+                        Command::none()
+                    }
+                    
+                    network::NetworkEvent::GroupMetadataReceived { group_id, name, avatar_hash, updated_at_ms, sender_fingerprint, admins, owner, roles_version, signature } => {
+                        let Ok(Some(stored_key)) = keystore::load_keypair() else {
+                            return Command::none();
+                        };
+                        let Some(group) = self.groups.iter_mut().find(|g| g.id == group_id) else {
+                            return Command::none();
+                        };
+                        // The admin check below is only as good as the sender being who they say
+                        let fields = group_auth::metadata_fields(&name, avatar_hash.as_deref(), updated_at_ms, &admins, &owner, roles_version);
+                        let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+                        if !group_auth::verify(group, &sender_fingerprint, group_auth::GroupAction::MetadataUpdate, &fields, &signature) {
+                            tracing::warn!(group = %group_id, peer = logging::short_fp(&sender_fingerprint), "ignored unsigned group metadata update");
+                            return Command::none();
+                        }
+                        let previous_name = group.name.clone();
+                        // Name/avatar and roles are versioned apart; the sender's admin
+                        // rights are checked against the roles from before this update
+                        let renamed = group_store::apply_metadata_update(group, &sender_fingerprint, &name, avatar_hash.clone(), updated_at_ms);
+                        let roles_changed = group_store::apply_admin_roles(group, &sender_fingerprint, &admins, &owner, roles_version);
+                        if renamed || roles_changed {
+                            let sender_address = group.members.iter()
+                                .find(|m| m.fingerprint == sender_fingerprint)
+                                .map(|m| m.address.clone());
+                            let all_groups: Vec<_> = self.groups.iter().cloned().collect();
+                            let _ = group_store::save_groups(&all_groups, &stored_key.fingerprint);
+                            
+                            // Fetch the new avatar like any other missing emote
+                            if let (true, Some(hash), Some(addr)) = (renamed, avatar_hash, sender_address) {
+                                let mut wanted = std::collections::HashMap::new();
+                                wanted.insert("avatar".to_string(), hash);
+                                self.request_missing_emotes(&wanted, &addr);
+                            }
+                            if group.name != previous_name {
+                                self.status = format!("Group renamed to '{}'", group.name);
+                            } else if roles_changed {
+                                self.status = format!("Admins of '{}' changed", group.name);
+                            }
+                        }
+                        Command::none()
+                    }
+                    
+                    network::NetworkEvent::GroupLeaveReceived { group_id, fingerprint, signature } => {
+                        let Ok(Some(stored_key)) = keystore::load_keypair() else {
+                            return Command::none();
+                        };
+                        let Some(group) = self.groups.iter_mut().find(|g| g.id == group_id) else {
+                            return Command::none();
+                        };
+                        // Only the member themselves can say they left
+                        if !group_auth::verify(group, &fingerprint, group_auth::GroupAction::Leave, &[&fingerprint], &signature) {
+                            tracing::warn!(group = %group_id, peer = logging::short_fp(&fingerprint), "ignored unsigned group leave");
+                            return Command::none();
+                        }
+                        let left_name = group.members.iter()
+                            .find(|m| m.fingerprint == fingerprint)
+                            .map(|m| m.username.clone())
+                            .unwrap_or_else(|| "A member".to_string());
+                        let promoted = group_store::apply_member_leave(group, &fingerprint);
+                        
+                        // One admin rotates the key so the departed member can't read new messages
+                        if group_store::key_rotator(group) == Some(stored_key.fingerprint.as_str()) {
+                            use base64::Engine;
+                            let keypair = self.app_state.get_keypair();
+                            let new_key = group_store::rotate_key(group);
+                            for member in group.members.iter().filter(|m| m.fingerprint != stored_key.fingerprint && !m.address.is_empty()) {
+                                let encrypted = app::stored_public_key(&member.public_key)
+                                    .and_then(|(recipient, warning)| {
+                                        if let Some(warning) = warning {
+                                            tracing::warn!(group = %group_id, peer = logging::short_fp(&member.fingerprint), %warning, "member key is no longer valid");
+                                        }
+                                        Ok(cryptochat_crypto_core::pgp::PgpKeyPair::encrypt(recipient.cert(), &new_key)?)
+                                    })
+                                    .map(|bytes| base64::engine::general_purpose::STANDARD.encode(bytes))
+                                    .ok();
+                                let fields = [fingerprint.as_str(), encrypted.as_deref().unwrap_or_default()];
+                                let signature = keypair.as_ref()
+                                    .and_then(|keypair| group_auth::sign(keypair, group_auth::GroupAction::KeyRotation, &group_id, &fields).ok())
+                                    .unwrap_or_default();
+                                let envelope = network::MessageEnvelope::MemberRemoved {
+                                    group_id: group_id.clone(),
+                                    removed_fingerprint: fingerprint.clone(),
+                                    new_encrypted_key: encrypted,
+                                    sender_fingerprint: stored_key.fingerprint.clone(),
+                                    signature,
+                                };
+                                let _ = network::NetworkHandle::send_message(&member.address, envelope);
+                            }
+                        }
+                        
+                        let group_name = group.name.clone();
+                        let all_groups: Vec<_> = self.groups.iter().cloned().collect();
+                        let _ = group_store::save_groups(&all_groups, &stored_key.fingerprint);
+                        
+                        self.status = match promoted {
+                            Some(fp) if fp == stored_key.fingerprint => format!("{} left '{}' - you are now admin", left_name, group_name),
+                            _ => format!("{} left '{}'", left_name, group_name),
+                        };
+                        Command::none()
+                    }
+                    
+                    network::NetworkEvent::GroupKeyRotated { group_id, removed_fingerprint, new_encrypted_key, sender_fingerprint, signature } => {
+                        let Ok(Some(stored_key)) = keystore::load_keypair() else {
+                            return Command::none();
+                        };
+                        if let Some(group) = self.groups.iter_mut().find(|g| g.id == group_id) {
+                            // Must come from an admin, counting the successor promoted if
+                            // the leave itself hasn't reached us yet
+                            let fields = [removed_fingerprint.as_str(), new_encrypted_key.as_deref().unwrap_or_default()];
+                            let mut after = group.clone();
+                            group_store::apply_member_leave(&mut after, &removed_fingerprint);
+                            if !group_store::can_edit_metadata(&after, &sender_fingerprint)
+                                || !group_auth::verify(group, &sender_fingerprint, group_auth::GroupAction::KeyRotation, &fields, &signature)
+                            {
+                                tracing::warn!(group = %group_id, peer = logging::short_fp(&sender_fingerprint), "ignored key rotation not signed by an admin");
+                                return Command::none();
+                            }
+                            *group = after;
+                            if let Some(encoded) = new_encrypted_key {
+                                use base64::Engine;
+                                let new_key = cryptochat_crypto_core::pgp::PgpKeyPair::from_secret_key(&stored_key.secret_key_armored)
+                                    .ok()
+                                    .and_then(|keypair| {
+                                        let encrypted = base64::engine::general_purpose::STANDARD.decode(&encoded).ok()?;
+                                        keypair.decrypt(&encrypted).ok()
+                                    });
+                                if let Some(key) = new_key {
+                                    group.symmetric_key = key;
                                 }
                             }
+                            let all_groups: Vec<_> = self.groups.iter().cloned().collect();
+                            let _ = group_store::save_groups(&all_groups, &stored_key.fingerprint);
+                        }
+                        Command::none()
+                    }
+                    
+                    network::NetworkEvent::GroupJoinReceived { group_id, new_member } => {
+                        // Only admins can let someone in; everyone else hears about the
+                        // new member from the approving admin's member sync
+                        let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
+                        if let Some(group) = self.groups.iter_mut().find(|g| g.id == group_id) {
+                            let new_name = new_member.username.clone();
+                            if group_store::can_edit_metadata(group, &my_fp) && group_store::queue_join_request(group, new_member) {
+                                let group_name = group.name.clone();
+                                let all_groups: Vec<_> = self.groups.iter().cloned().collect();
+                                let _ = group_store::save_groups(&all_groups, &my_fp);
+                                self.status = format!("{} asks to join '{}' (approve with ✎)", new_name, group_name);
+                                show_notification("Join Request", &format!("{} asks to join {}", new_name, group_name));
+                            }
+                        }
+                        Command::none()
+                    }
+                    
+                    network::NetworkEvent::GroupResyncRequested { group_id, fingerprint } => {
+                        // Answer only members, at the address and key we have for them
+                        let Ok(Some(stored_key)) = keystore::load_keypair() else {
+                            return Command::none();
+                        };
+                        let Some(group) = self.groups.iter().find(|g| g.id == group_id) else {
+                            return Command::none();
+                        };
+                        if !group_store::can_edit_metadata(group, &stored_key.fingerprint) {
+                            return Command::none();
                         }
+                        let Some(member) = group.members.iter().find(|m| m.fingerprint == fingerprint && !m.address.is_empty()) else {
+                            return Command::none();
+                        };
+                        use base64::Engine;
+                        let encrypted_key = app::stored_public_key(&member.public_key)
+                            .and_then(|(recipient, warning)| {
+                                if let Some(warning) = warning {
+                                    tracing::warn!(group = %group_id, peer = logging::short_fp(&member.fingerprint), %warning, "member key is no longer valid");
+                                }
+                                Ok(cryptochat_crypto_core::pgp::PgpKeyPair::encrypt(recipient.cert(), &group.symmetric_key)?)
+                            })
+                            .map(|bytes| base64::engine::general_purpose::STANDARD.encode(bytes))
+                            .ok();
+                        let sync = network::MessageEnvelope::GroupMemberSync {
+                            group_id: group.id.clone(),
+                            sender_fingerprint: stored_key.fingerprint.clone(),
+                            members: group.members.clone(),
+                            membership_epoch: group.membership_epoch,
+                            encrypted_key,
+                        };
+                        let _ = network::NetworkHandle::send_message(&member.address, sync);
                         Command::none()
                     }
                     
-                    network::NetworkEvent::GroupMemberSyncReceived { group_id, members } => {
-                        // Received member list from another member - merge it with ours
+                    network::NetworkEvent::GroupMemberSyncReceived { group_id, sender_fingerprint, members, membership_epoch, encrypted_key } => {
+                        // An admin's member list - merge it with ours
                         if let Some(group) = self.groups.iter_mut().find(|g| g.id == group_id) {
-                            let mut updated = false;
-                            for member in members {
-                                if !group.members.iter().any(|m| m.fingerprint == member.fingerprint) {
-                                    group.members.push(member);
+                            if !group_store::can_edit_metadata(group, &sender_fingerprint) {
+                                return Command::none();
+                            }
+                            let mut updated = group_store::apply_member_sync(group, &sender_fingerprint, members, membership_epoch);
+                            // Joined by invite code, or asked for a resync: take the key the admin sent
+                            if encrypted_key.is_some() {
+                                use base64::Engine;
+                                let key = encrypted_key
+                                    .and_then(|encoded| base64::engine::general_purpose::STANDARD.decode(encoded).ok())
+                                    .zip(keystore::load_keypair().ok().flatten())
+                                    .and_then(|(encrypted, stored)| {
+                                        cryptochat_crypto_core::pgp::PgpKeyPair::from_secret_key(&stored.secret_key_armored).ok()?.decrypt(&encrypted).ok()
+                                    });
+                                if let Some(key) = key.filter(|k| k.len() == 32 && *k != group.symmetric_key) {
+                                    group.symmetric_key = key;
                                     updated = true;
                                 }
                             }
@@ -967,19 +2127,21 @@ impl Application for CryptoChat {
                         Command::none()
                     }
                     
-                    network::NetworkEvent::ReactionReceived { msg_timestamp, emoji, sender_name, sender_fingerprint, sender_address } => {
-                        // Find message by timestamp and update reaction (toggle)
-                        // Try to find conversation (DM for now - active refactor limitation)
-                        if let Some(conv) = self.conversations.get_mut(&sender_fingerprint) {
-                            conv.peer_address = Some(sender_address);
-                            if let Some(msg) = conv.messages.iter_mut().find(|m| m.timestamp == msg_timestamp) {
-                                // Check if sender already reacted with this emoji (toggle off)
-                                if let Some(pos) = msg.reactions.iter().position(|(e, s)| e == &emoji && s == &sender_name) {
-                                    msg.reactions.remove(pos);
-                                } else {
-                                    msg.reactions.push((emoji, sender_name));
-                                }
+                    network::NetworkEvent::ReactionReceived { msg_id, msg_timestamp, emoji, sender_name, sender_fingerprint, sender_address, group_id } => {
+                        // Group reactions only count from members of that group
+                        if let Some(group_id) = &group_id {
+                            let is_member = self.groups.iter()
+                                .find(|g| &g.id == group_id)
+                                .is_some_and(|g| g.members.iter().any(|m| m.fingerprint == sender_fingerprint));
+                            if !is_member {
+                                return Command::none();
                             }
+                        } else if let Some(conv) = self.conversations.get_mut(&sender_fingerprint) {
+                            conv.peer_address = Some(sender_address);
+                        }
+                        // Messages we no longer have (cleared or expired) just drop the reaction
+                        if conversation::apply_reaction(&mut self.conversations, group_id.as_deref(), &sender_fingerprint, msg_id, &msg_timestamp, &emoji, &sender_name) {
+                            self.save_conversations();
                         }
                         Command::none()
                     }
@@ -1000,8 +2162,15 @@ impl Application for CryptoChat {
                     
                     network::NetworkEvent::EmoteDataReceived { hash, data } => {
                         use base64::Engine;
+                         // Only take emotes we asked for, so a peer can't fill the cache
+                         if !self.emote_requests.is_pending(&hash) {
+                             return Command::none();
+                         }
                          if let Ok(bytes) = base64::engine::general_purpose::STANDARD.decode(&data) {
-                             let _ = self.emote_manager.save_to_cache(&hash, &bytes);
+                             // Bubbles referencing this hash pick it up on the next redraw
+                             if self.emote_manager.save_received(&hash, &bytes).is_ok() {
+                                 self.emote_requests.mark_received(&hash);
+                             }
                          }
                         Command::none()
                     }
@@ -1013,38 +2182,66 @@ impl Application for CryptoChat {
                 }
             }
             Message::PollNetwork => {
-                if let Some(receiver_mutex) = NETWORK_RECEIVER.get() {
-                    if let Ok(mut guard) = receiver_mutex.lock() {
-                        if let Some(ref mut receiver) = *guard {
-                            if let Ok(event) = receiver.try_recv() {
-                                return Command::perform(async move { event }, Message::NetworkEvent);
-                            }
+                let events = NETWORK_RECEIVER
+                    .get()
+                    .and_then(|receiver_mutex| receiver_mutex.lock().ok())
+                    .and_then(|mut guard| guard.as_mut().map(|receiver| network::drain_events(receiver, network::MAX_EVENTS_PER_POLL)))
+                    .unwrap_or_default();
+                // Handled here, in arrival order, so a message and the receipt
+                // right behind it land in the same tick
+                let commands: Vec<_> = events.into_iter().map(|event| self.update(Message::NetworkEvent(event))).collect();
+                Command::batch(commands)
+            }
+            Message::PollRelay => {
+                let (Some(url), Some(keypair)) = (relay::relay_url(), self.app_state.get_keypair()) else {
+                    return Command::none();
+                };
+                Command::perform(fetch_relay_async(url, keypair, self.relay_ack.clone()), Message::RelayFetched)
+            }
+            Message::RelayFetched(Ok(relayed)) => {
+                // The relay deleted what we acknowledged; these go out with the next poll
+                self.relay_ack = relayed.iter().map(|item| item.id).collect();
+                // Relayed envelopes are handled exactly as if they'd arrived directly
+                let commands: Vec<_> = relayed
+                    .into_iter()
+                    .filter_map(|item| match serde_json::from_str::<network::MessageEnvelope>(&item.payload) {
+                        Ok(envelope) => network::envelope_event(envelope, &item.sender_address),
+                        Err(e) => {
+                            tracing::warn!(error = %e, sender = %item.sender_fingerprint, "dropping unreadable relayed message");
+                            None
                         }
-                    }
-                }
+                    })
+                    .map(|event| self.update(Message::NetworkEvent(event)))
+                    .collect();
+                Command::batch(commands)
+            }
+            Message::RelayFetched(Err(e)) => {
+                tracing::debug!(error = %e, "relay poll failed");
                 Command::none()
             }
             Message::ShowQR => {
-                // Generate and show QR code (save to temp file and open)
+                // Generate QR code and show it in a modal
                 if let Some(keypair) = self.app_state.get_keypair() {
                     if let Ok(payload) = qr_exchange::QrPayload::create_and_sign(&keypair) {
                         if let Ok(img) = qr_exchange::generate_qr_image(&payload) {
-                            let path = format!("{}/.cryptochat{}/qr_code.png", 
-                                std::env::var("USERPROFILE").unwrap_or_default(),
-                                get_instance_id().map(|i| format!("_{}", i)).unwrap_or_default());
-                            if qr_exchange::save_qr_to_file(&img, &path).is_ok() {
-                                let _ = std::process::Command::new("cmd").args(["/C", "start", &path]).spawn();
-                                self.status = "QR code opened!".to_string();
-                            }
+                            let (width, height) = img.dimensions();
+                            let rgba = image::DynamicImage::ImageLuma8(img).to_rgba8().into_raw();
+                            self.qr_display = Some(iced::widget::image::Handle::from_pixels(width, height, rgba));
+                            self.status = "QR code valid for 5 minutes".to_string();
                         }
                     }
                 }
                 Command::none()
             }
+            Message::HideQR => {
+                self.qr_display = None;
+                Command::none()
+            }
             Message::CopyQR => {
-                // Generate QR and copy to clipboard
+                // Generate QR and copy to clipboard. It leaves the app, so it's
+                // signed with an armored signature any OpenPGP tool can check
                 if let Some(keypair) = self.app_state.get_keypair() {
-                    if let Ok(payload) = qr_exchange::QrPayload::create_and_sign(&keypair) {
+                    if let Ok(payload) = qr_exchange::QrPayload::create_and_sign_armored(&keypair) {
                         if let Ok(img) = qr_exchange::generate_qr_image(&payload) {
                             if copy_image_to_clipboard(&img).is_ok() {
                                 self.status = "QR copied to clipboard! Paste in other instance".to_string();
@@ -1072,16 +2269,15 @@ impl Application for CryptoChat {
                         self.peer_username = res.username.clone();
                         self.app_state.set_peer_address(res.address.clone());
                         let name = res.username.as_deref().unwrap_or("Peer");
+                        tracing::info!(peer = logging::short_fp(&res.fingerprint), "imported key from QR");
                         self.status = format!("Imported from QR: {}! Sending our key...", name);
                         
-                        // Send OUR public key to the peer
-                        if let (Ok(Some(our_key)), Some(port)) = (keystore::load_keypair(), self.listening_port) {
-                            let envelope = network::MessageEnvelope::AcceptedResponse {
-                                sender_fingerprint: our_key.fingerprint.clone(),
-                                sender_public_key: our_key.public_key_armored.clone(),
-                                sender_listening_port: port,
-                                sender_name: Some(self.my_username.clone()),
-                            };
+                        // Send OUR public key to the peer, as a signed request
+                        let request = self.app_state.get_keypair().zip(self.listening_port).and_then(|(keypair, port)| {
+                            network::MessageEnvelope::request(&keypair, port, &self.my_username, cryptochat_messaging::requests::RequestKind::Chat, now_ms()).ok()
+                        });
+                        if let Some(envelope) = request {
+                            self.awaited_answers.record(&res.fingerprint, now_ms());
                             let peer_addr = res.address.clone();
                             return Command::perform(
                                 async move {
@@ -1127,24 +2323,149 @@ impl Application for CryptoChat {
                      // Trigger SelectConversation
                      // recursive update call or duplication? Duplication is safer for borrow checker.
                      // Logic of SelectConversation:
+                     conversation::switch_drafts(&mut self.conversations, self.active_conversation_id.as_deref(), &fp, &mut self.message_input);
                      self.active_conversation_id = Some(fp.clone());
+                     self.save_conversations();
                      
                      // Load contact details
-                        if let Ok(keypair) = cryptochat_crypto_core::pgp::PgpKeyPair::from_public_key(&contact.public_key) {
+                        let mut key_warning = None;
+                        if let Ok((keypair, warning)) = app::stored_public_key(&contact.public_key) {
                             self.app_state.set_recipient_keypair(keypair);
+                            key_warning = warning;
                         }
                         self.app_state.set_peer_address(contact.address.clone());
                         self.peer_address = Some(contact.address.clone());
                         self.peer_username = Some(contact.name.clone());
                         self.recipient_key_imported = true;
-                        self.status = format!("Chatting with {}", contact.name);
+                        self.status = match key_warning {
+                            Some(warning) => format!("Chatting with {} (warning: {})", contact.name, warning),
+                            None => format!("Chatting with {}", contact.name),
+                        };
                         self.selected_group_id = None; 
                         
                         return self.snap_to_bottom();
                 }
                 Command::none()
             }
+            Message::ToggleMute(id) => {
+                // Groups have no conversation until their first message
+                let name = self.groups.iter().find(|g| g.id == id).map(|g| g.name.clone()).unwrap_or_else(|| "Group".to_string());
+                let conv = self.conversations.entry(id.clone())
+                    .or_insert_with(|| Conversation::new(id.clone(), name, None));
+                let muted = !conv.settings.muted.value;
+                conv.settings.muted.set(muted, now_ms());
+                self.status = format!("{} {}", conv.name, if muted { "muted" } else { "unmuted" });
+                self.save_conversations();
+                self.publish_settings();
+                Command::none()
+            }
+            Message::TogglePin(id) => {
+                if let Some(conv) = self.conversations.get_mut(&id) {
+                    let pinned = !conv.settings.pinned.value;
+                    conv.settings.pinned.set(pinned, now_ms());
+                    self.status = format!("{} {}", conv.name, if pinned { "pinned" } else { "unpinned" });
+                    self.save_conversations();
+                    self.publish_settings();
+                }
+                Command::none()
+            }
+            Message::ToggleArchive(id) => {
+                if let Some(conv) = self.conversations.get_mut(&id) {
+                    let archived = !conv.settings.archived.value;
+                    conv.settings.archived.set(archived, now_ms());
+                    self.status = format!("{} {}", conv.name, if archived { "archived" } else { "moved back to chats" });
+                    self.save_conversations();
+                    self.publish_settings();
+                }
+                Command::none()
+            }
+            Message::ToggleShowArchived => {
+                self.show_archived = !self.show_archived;
+                Command::none()
+            }
+            Message::ToggleSearch => {
+                self.show_search = !self.show_search;
+                if self.show_search {
+                    self.show_switcher = false;
+                    self.refresh_search_hits();
+                    return text_input::focus(text_input::Id::new(SEARCH_INPUT_ID));
+                }
+                Command::none()
+            }
+            Message::SearchQueryChanged(query) => {
+                self.search_query = query;
+                self.refresh_search_hits();
+                Command::none()
+            }
+            Message::SearchIncludeArchivedToggled(include) => {
+                self.search_include_archived = include;
+                self.refresh_search_hits();
+                Command::none()
+            }
+            Message::ToggleSwitcher => {
+                self.show_switcher = !self.show_switcher;
+                self.switcher_query.clear();
+                if self.show_switcher {
+                    self.show_search = false;
+                    return text_input::focus(text_input::Id::new(SWITCHER_INPUT_ID));
+                }
+                Command::none()
+            }
+            Message::SwitcherQueryChanged(query) => {
+                self.switcher_query = query;
+                Command::none()
+            }
+            Message::SwitcherSubmit => {
+                let candidates = self.switcher_candidates();
+                let best = switcher::rank(&self.switcher_query, &candidates).first().map(|c| c.target.clone());
+                match best {
+                    Some(target) => self.update(Message::SwitcherPick(target)),
+                    None => {
+                        self.status = format!("Nothing matches '{}'", self.switcher_query.trim());
+                        Command::none()
+                    }
+                }
+            }
+            Message::SwitcherPick(target) => {
+                self.show_switcher = false;
+                self.switcher_query.clear();
+                match target {
+                    switcher::Target::Conversation(id) => self.update(Message::SelectConversation(id)),
+                    switcher::Target::Group(id) => self.update(Message::SelectGroup(id)),
+                    switcher::Target::Contact(index) => self.update(Message::SelectContact(index)),
+                }
+            }
+            Message::OpenSearchHit(conv_id, msg_index) => {
+                self.show_search = false;
+                let opened = self.update(Message::SelectConversation(conv_id.clone()));
+                Command::batch([opened, self.snap_to_message(&conv_id, msg_index)])
+            }
+            Message::MarkAllRead => {
+                let cleared = conversation::mark_all_read(&mut self.conversations);
+                if cleared.is_empty() {
+                    return Command::none();
+                }
+                self.save_conversations();
+                for conv_id in &cleared {
+                    self.send_conversation_read(conv_id);
+                }
+                self.status = format!("Marked {} chats read", cleared.len());
+                Command::none()
+            }
+            Message::JumpToFirstUnread => {
+                match self.first_unread.take() {
+                    Some((conv_id, index)) if self.active_conversation_id.as_ref() == Some(&conv_id) => {
+                        self.snap_to_message(&conv_id, index)
+                    }
+                    _ => Command::none(),
+                }
+            }
             Message::SelectConversation(id) => {
+                if self.conversations.contains_key(&id) {
+                    // Stash the current draft and restore the one for this chat
+                    conversation::switch_drafts(&mut self.conversations, self.active_conversation_id.as_deref(), &id, &mut self.message_input);
+                    self.save_conversations();
+                }
                 if let Some(conv) = self.conversations.get(&id) {
                      self.active_conversation_id = Some(id.clone());
                      self.peer_username = Some(conv.name.clone());
@@ -1152,10 +2473,12 @@ impl Application for CryptoChat {
                      self.selected_group_id = None; 
                      
                      // Try to match with contact to load key
+                     let mut key_warning = None;
                      if let Some(contact) = self.contacts.iter().find(|c| c.fingerprint == id) {
-                         if let Ok(keypair) = cryptochat_crypto_core::pgp::PgpKeyPair::from_public_key(&contact.public_key) {
+                         if let Ok((keypair, warning)) = app::stored_public_key(&contact.public_key) {
                              self.app_state.set_recipient_keypair(keypair);
                              self.recipient_key_imported = true;
+                             key_warning = warning;
                          }
                          if let Some(ref addr) = conv.peer_address {
                              self.app_state.set_peer_address(addr.clone());
@@ -1174,7 +2497,12 @@ impl Application for CryptoChat {
                          }
                      }
                      
-                     self.status = format!("Chatting with {}", conv.name);
+                     self.status = match key_warning {
+                         Some(warning) => format!("Chatting with {} (warning: {})", conv.name, warning),
+                         None => format!("Chatting with {}", conv.name),
+                     };
+                     self.first_unread = conversation::first_unread_index(conv).map(|index| (id.clone(), index));
+                     self.mark_conversation_read(&id);
                      
                      return self.snap_to_bottom();
                 }
@@ -1200,13 +2528,97 @@ impl Application for CryptoChat {
                     |r| Message::FileSent(r),
                 );
             }
+            Message::ToggleVoiceRecording => {
+                let Some(recorder) = self.recorder.take() else {
+                    if !self.recipient_key_imported || self.selected_group_id.is_some() {
+                        self.status = "Voice messages can only be sent in direct chats".to_string();
+                        return Command::none();
+                    }
+                    match voice::Recorder::start() {
+                        Ok(recorder) => {
+                            self.recorder = Some(recorder);
+                            self.status = "Recording... press ⏹ to send".to_string();
+                        }
+                        Err(e) => self.status = format!("Recording failed: {}", e),
+                    }
+                    return Command::none();
+                };
+                let wav = match recorder.stop() {
+                    Ok(wav) => wav,
+                    Err(e) => {
+                        self.status = format!("Recording failed: {}", e);
+                        return Command::none();
+                    }
+                };
+                let (Some(conv_id), Some(peer_addr)) = (self.app_state.get_recipient_fingerprint(), self.peer_address.clone()) else {
+                    self.status = "Connect to a peer first".to_string();
+                    return Command::none();
+                };
+                let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
+                let duration_ms = voice::wav_duration_ms(&wav).unwrap_or(0);
+                let blob = match blob_store::put(&wav, &my_fp) {
+                    Ok(blob) => blob,
+                    Err(e) => {
+                        self.status = format!("Couldn't save recording: {}", e);
+                        return Command::none();
+                    }
+                };
+                let message_id = uuid::Uuid::new_v4().to_string();
+                let new_msg = ChatMessage {
+                    sender_name: self.my_username.clone(),
+                    content: format!("[Voice message {}]", voice::format_duration(duration_ms)),
+                    is_mine: true,
+                    timestamp: chrono_time(),
+                    sent_at_ms: now_ms(),
+                    image_data: None,
+                    image_filename: None,
+                    reactions: Vec::new(),
+                    emotes: std::collections::HashMap::new(),
+                    expires_at: None,
+                    message_id: Some(message_id.clone()),
+                    status: DeliveryStatus::Pending,
+                    delivered_to: Vec::new(),
+                    forwarded: false,
+                    voice: Some(voice::VoiceClip { blob, duration_ms }),
+                    id: conversation::message_uuid(Some(&message_id)),
+                };
+                self.add_message(conv_id.clone(), self.peer_username.clone().unwrap_or("Peer".to_string()), new_msg, Some(peer_addr.clone()));
+                self.status = "Voice message sent".to_string();
+
+                let app_state = self.app_state.clone();
+                let sender_name = self.my_username.clone();
+                let port = self.listening_port.unwrap_or(network::DEFAULT_PORT);
+                let id = message_id.clone();
+                return Command::batch(vec![
+                    Command::perform(
+                        async move {
+                            tokio::task::spawn_blocking(move || {
+                                send_voice(app_state, peer_addr, wav, duration_ms, sender_name, my_fp, port, id)
+                            }).await.map_err(|e| e.to_string())?
+                        },
+                        move |result| Message::DirectMessageSent(conv_id, message_id, result),
+                    ),
+                    self.snap_to_bottom(),
+                ]);
+            }
+            Message::PlayVoice(msg_idx) => {
+                let Some(clip) = self.get_active_messages().get(msg_idx).and_then(|m| m.voice.clone()) else {
+                    return Command::none();
+                };
+                let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
+                match blob_store::get(&clip.blob, &my_fp) {
+                    Ok(wav) => voice::play(wav),
+                    Err(e) => self.status = format!("Can't play voice message: {}", e),
+                }
+                Command::none()
+            }
             Message::UploadEmote => {
                  self.status = "Opening file picker...".to_string();
                  return Command::perform(
                     async {
                         tokio::task::spawn_blocking(|| {
-                            pick_emote_file()
-                        }).await.map_err(|e| e.to_string())?
+                            file_dialog::pick_file("Select Emote Image", &[file_dialog::IMAGES, file_dialog::ALL_FILES])
+                        }).await.map_err(|e| e.to_string())
                     },
                     |res| match res {
                         Ok(Some(path)) => Message::EmoteFileSelected(Some(path)),
@@ -1230,7 +2642,7 @@ impl Application for CryptoChat {
             Message::FileSent(result) => {
                 if let Err(e) = result {
                     self.status = format!("File send failed: {}", e);
-                } else if let Ok((filename, raw_data)) = result {
+                } else if let Ok((filename, raw_data, message_id)) = result {
                      if let Some(fp) = self.app_state.get_recipient_fingerprint() {
                         let is_image = filename.to_lowercase().ends_with(".png") || filename.to_lowercase().ends_with(".jpg");
                          
@@ -1239,10 +2651,18 @@ impl Application for CryptoChat {
                             content: if is_image { format!("[Image: {}]", filename) } else { format!("[File: {}]", filename) },
                             is_mine: true,
                             timestamp: chrono_time(),
+                            sent_at_ms: now_ms(),
                             image_data: if is_image { Some(raw_data.clone()) } else { None },
                             image_filename: Some(filename),
                             reactions: Vec::new(),
                             emotes: std::collections::HashMap::new(),
+                            expires_at: None,
+                            id: conversation::message_uuid(Some(&message_id)),
+                            message_id: Some(message_id),
+                            status: DeliveryStatus::Sent,
+                            delivered_to: Vec::new(),
+                            forwarded: false,
+                            voice: None,
                         };
                         // self.chat_messages.push(new_msg);
                         self.add_message(fp, self.peer_username.clone().unwrap(), new_msg, None);
@@ -1250,43 +2670,155 @@ impl Application for CryptoChat {
                 }
                 Command::none()
             }
-            Message::ToggleEmojiPicker => {
-                self.show_emoji_picker = !self.show_emoji_picker;
+            Message::ToggleEmoteLibrary => {
+                self.show_emote_library = !self.show_emote_library;
                 Command::none()
             }
-            Message::InsertEmoji(emoji) => {
-                self.message_input.push_str(&emoji);
-                self.show_emoji_picker = false;
+            Message::RemoveEmote(name) => {
+                match self.emote_manager.remove_emote(&name) {
+                    Ok(true) => self.status = format!("Emote removed: :{}:", name),
+                    Ok(false) => self.status = format!("Emote not found: :{}:", name),
+                    Err(e) => self.status = format!("Remove failed: {}", e),
+                }
                 Command::none()
             }
-            Message::SelectEmojiSuggestion(_name, emoji) => {
-                // Replace :text with emoji
-                if let Some(colon_pos) = self.message_input.rfind(':') {
-                    self.message_input.truncate(colon_pos);
-                    self.message_input.push_str(&emoji);
+            Message::ExportEmotePack => {
+                return Command::perform(
+                    async {
+                        tokio::task::spawn_blocking(|| {
+                            file_dialog::save_file("Export Emote Pack", &[file_dialog::JSON], "emotes.json")
+                        }).await.map_err(|e| e.to_string())
+                    },
+                    |res| Message::EmotePackSavePathSelected(res.ok().flatten()),
+                );
+            }
+            Message::EmotePackSavePathSelected(opt_path) => {
+                if let Some(path) = opt_path {
+                    let names: Vec<String> = self.emote_manager.list_emotes().into_iter().map(|(name, _)| name).collect();
+                    match self.emote_manager.export_pack(&names).and_then(|bytes| Ok(std::fs::write(&path, bytes)?)) {
+                        Ok(()) => self.status = format!("Exported {} emotes", names.len()),
+                        Err(e) => self.status = format!("Export failed: {}", e),
+                    }
                 }
-                self.emoji_suggestions.clear();
                 Command::none()
             }
-            Message::RemoveContact(index) => {
-                if let Some(contact) = self.contacts.get(index).cloned() {
-                    // Send removal notification to peer
-                    let envelope = network::MessageEnvelope::ContactRemoved {
-                        fingerprint: contact.fingerprint.clone(),
-                    };
-                    let peer_addr = contact.address.clone();
-                    let _ = std::thread::spawn(move || {
-                        let _ = network::NetworkHandle::send_message(&peer_addr, envelope);
-                    });
-                    
-                    // Remove locally
-                    self.contacts.remove(index);
-                    let _ = request_store::save_simple_contacts(&self.contacts);
-                    self.status = format!("Removed {} (synced)", contact.name);
-                    
-                    // If this was the current peer, disconnect
-                    if self.peer_address.as_ref() == Some(&contact.address) {
-                        self.recipient_key_imported = false;
+            Message::ImportEmotePack => {
+                return Command::perform(
+                    async {
+                        tokio::task::spawn_blocking(|| {
+                            file_dialog::pick_file("Import Emote Pack", &[file_dialog::JSON, file_dialog::ALL_FILES])
+                        }).await.map_err(|e| e.to_string())
+                    },
+                    |res| Message::EmotePackFileSelected(res.ok().flatten()),
+                );
+            }
+            Message::EmotePackFileSelected(opt_path) => {
+                if let Some(path) = opt_path {
+                    let result = std::fs::read(&path)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|bytes| self.emote_manager.import_pack(&bytes));
+                    match result {
+                        Ok(added) => self.status = format!("Imported {} emotes", added.len()),
+                        Err(e) => self.status = format!("Import failed: {}", e),
+                    }
+                }
+                Command::none()
+            }
+            Message::ExportContacts => {
+                return Command::perform(
+                    async {
+                        tokio::task::spawn_blocking(|| {
+                            file_dialog::save_file("Export Contacts", &[file_dialog::JSON], "contacts.json")
+                        }).await.map_err(|e| e.to_string())
+                    },
+                    |res| Message::ContactsSavePathSelected(res.ok().flatten()),
+                );
+            }
+            Message::ContactsSavePathSelected(opt_path) => {
+                if let Some(path) = opt_path {
+                    match request_store::export_contacts(&self.contacts).and_then(|bytes| Ok(std::fs::write(&path, bytes)?)) {
+                        Ok(()) => self.status = format!("Exported {} contacts", self.contacts.len()),
+                        Err(e) => self.status = format!("Export failed: {}", e),
+                    }
+                }
+                Command::none()
+            }
+            Message::ImportContacts => {
+                return Command::perform(
+                    async {
+                        tokio::task::spawn_blocking(|| {
+                            file_dialog::pick_file("Import Contacts", &[file_dialog::JSON, file_dialog::ALL_FILES])
+                        }).await.map_err(|e| e.to_string())
+                    },
+                    |res| Message::ContactsFileSelected(res.ok().flatten()),
+                );
+            }
+            Message::ContactsFileSelected(opt_path) => {
+                if let Some(path) = opt_path {
+                    let result = std::fs::read(&path)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|bytes| request_store::import_contacts(&bytes, &mut self.contacts));
+                    match result {
+                        Ok(summary) => {
+                            if let Err(e) = request_store::save_simple_contacts(&self.contacts) {
+                                self.status = format!("Import failed: {}", e);
+                                return Command::none();
+                            }
+                            let mut status = format!("Imported {} contacts ({} already known)", summary.added, summary.unchanged);
+                            if !summary.conflicts.is_empty() {
+                                let names: Vec<&str> = summary.conflicts.iter().map(|c| c.name.as_str()).collect();
+                                status.push_str(&format!("; kept current address for {}", names.join(", ")));
+                            }
+                            if summary.rejected > 0 {
+                                status.push_str(&format!("; skipped {} with mismatched keys", summary.rejected));
+                            }
+                            if !summary.invalid_keys.is_empty() {
+                                status.push_str(&format!("; ⚠ keys expired or revoked for {}", summary.invalid_keys.join(", ")));
+                            }
+                            self.status = status;
+                        }
+                        Err(e) => self.status = format!("Import failed: {}", e),
+                    }
+                }
+                Command::none()
+            }
+            Message::ToggleEmojiPicker => {
+                self.show_emoji_picker = !self.show_emoji_picker;
+                Command::none()
+            }
+            Message::InsertEmoji(emoji) => {
+                self.message_input.push_str(&emoji);
+                self.show_emoji_picker = false;
+                Command::none()
+            }
+            Message::SelectEmojiSuggestion(_name, emoji) => {
+                // Replace :text with emoji
+                if let Some(colon_pos) = self.message_input.rfind(':') {
+                    self.message_input.truncate(colon_pos);
+                    self.message_input.push_str(&emoji);
+                }
+                self.emoji_suggestions.clear();
+                Command::none()
+            }
+            Message::RemoveContact(index) => {
+                if let Some(contact) = self.contacts.get(index).cloned() {
+                    // Send removal notification to peer
+                    let envelope = network::MessageEnvelope::ContactRemoved {
+                        fingerprint: contact.fingerprint.clone(),
+                    };
+                    let peer_addr = contact.address.clone();
+                    let _ = std::thread::spawn(move || {
+                        let _ = network::NetworkHandle::send_message(&peer_addr, envelope);
+                    });
+                    
+                    // Remove locally
+                    self.contacts.remove(index);
+                    let _ = request_store::save_simple_contacts(&self.contacts);
+                    self.status = format!("Removed {} (synced)", contact.name);
+                    
+                    // If this was the current peer, disconnect
+                    if self.peer_address.as_ref() == Some(&contact.address) {
+                        self.recipient_key_imported = false;
                         self.peer_address = None;
                         self.peer_username = None;
                     }
@@ -1296,9 +2828,9 @@ impl Application for CryptoChat {
             Message::SaveImage(index) => {
                 if let Some(msg) = self.get_active_messages().get(index) {
                     if let (Some(data), Some(filename)) = (&msg.image_data, &msg.image_filename) {
-                        let downloads_dir = format!("{}\\Downloads", std::env::var("USERPROFILE").unwrap_or_else(|_| "C:\\Users\\Public".to_string()));
+                        let downloads_dir = paths::downloads_dir();
                         let _ = std::fs::create_dir_all(&downloads_dir);
-                        let save_path = format!("{}\\{}", downloads_dir, filename);
+                        let save_path = downloads_dir.join(filename).display().to_string();
                         match std::fs::write(&save_path, data) {
                             Ok(_) => {
                                 self.status = format!("SAVED: {}", filename);
@@ -1326,17 +2858,32 @@ impl Application for CryptoChat {
                         self.peer_address = Some(req.sender_address);
                         self.peer_username = req.sender_name;
                         self.recipient_key_imported = true;
-                        self.status = format!("Connected: {}", name);
-                        self.view = View::Chat;
+                        tracing::info!(peer = logging::short_fp(&req.sender_fingerprint), route = ?req.route, "accepted connection request");
+                        match req.route {
+                            request_store::RequestRoute::RelinkKey => {
+                                if let Some(contact) = self.contacts.iter_mut().find(|c| c.fingerprint == req.sender_fingerprint) {
+                                    contact.public_key = req.sender_public_key.clone();
+                                }
+                                let _ = request_store::save_simple_contacts(&self.contacts);
+                                self.status = format!("Re-linked {}'s key", name);
+                                self.view = View::Chat;
+                            }
+                            // The invite itself follows once they can reach us
+                            request_store::RequestRoute::AcceptGroup => {
+                                self.status = format!("Connected to {}; waiting for their group invite", name);
+                            }
+                            request_store::RequestRoute::AcceptChat | request_store::RequestRoute::Reconnect => {
+                                self.status = format!("Connected: {}", name);
+                                self.view = View::Chat;
+                            }
+                        }
                         
                         // Send AcceptedResponse back to requester so they establish connection too
-                        if let (Ok(Some(our_key)), Some(port)) = (keystore::load_keypair(), self.listening_port) {
-                            let envelope = network::MessageEnvelope::AcceptedResponse {
-                                sender_fingerprint: our_key.fingerprint.clone(),
-                                sender_public_key: our_key.public_key_armored.clone(),
-                                sender_listening_port: port,
-                                sender_name: Some(self.my_username.clone()),
-                            };
+                        let our_keypair = self.app_state.get_keypair();
+                        let envelope = our_keypair.zip(self.listening_port).and_then(|(keypair, port)| {
+                            network::MessageEnvelope::accepted_response(&keypair, port, &self.my_username, now_ms()).ok()
+                        });
+                        if let Some(envelope) = envelope {
                             return Command::perform(
                                 async move {
                                     network::NetworkHandle::send_message(&peer_addr, envelope)
@@ -1346,7 +2893,7 @@ impl Application for CryptoChat {
                             );
                         }
                     } else {
-                        self.status = format!("Failed to import key from {}", name);
+                        self.status = format!("Failed to import key from {} (invalid, expired or revoked)", name);
                     }
                 }
                 Command::none()
@@ -1359,6 +2906,17 @@ impl Application for CryptoChat {
                 }
                 Command::none()
             }
+            Message::BlockRequest(idx) => {
+                if idx < self.pending_requests.len() {
+                    let req = self.pending_requests.remove(idx);
+                    let name = req.sender_name.unwrap_or_else(|| req.sender_fingerprint[..8].to_string());
+                    if self.request_settings.block(&req.sender_fingerprint) {
+                        let _ = request_store::save_request_settings(&self.request_settings);
+                    }
+                    self.status = format!("Blocked {}", name);
+                }
+                Command::none()
+            }
             Message::AddToContacts => {
                 // Add current peer to contacts
                 if self.recipient_key_imported {
@@ -1377,6 +2935,9 @@ impl Application for CryptoChat {
                                 fingerprint: fingerprint.clone(),
                                 public_key: recipient.export_public_key().unwrap_or_default(),
                                 address,
+                                revoked: false,
+                                alias: None,
+                                address_updated_ms: 0,
                             };
                             if let Ok(()) = request_store::upsert_simple_contact(&contact) {
                                 self.contacts.push(contact);
@@ -1395,7 +2956,7 @@ impl Application for CryptoChat {
                         fingerprint: stored_key.fingerprint.clone(),
                         username: self.my_username.clone(),
                         public_key: stored_key.public_key_armored.clone(),
-                        address: format!("127.0.0.1:{}", self.listening_port.unwrap_or(62780)),
+                        address: network::advertised_address(self.listening_port.unwrap_or(network::DEFAULT_PORT)),
                         joined_at: chrono::Utc::now().to_rfc3339(),
                     };
                     
@@ -1420,8 +2981,9 @@ impl Application for CryptoChat {
             Message::SelectGroup(group_id) => {
                 // Switch to group chat mode
                 if let Some(group) = self.groups.iter().find(|g| g.id == group_id) {
-                    self.selected_group_id = Some(group_id);
                     self.status = format!("Chatting in: {}", group.name);
+                    self.mark_conversation_read(&group_id);
+                    self.selected_group_id = Some(group_id);
                 } else {
                     self.status = "Group not found".to_string();
                 }
@@ -1446,6 +3008,9 @@ impl Application for CryptoChat {
                         "group_name": group.name,
                         "creator": self.my_username,
                         "members": members_data,
+                        "admins": group.admins,
+                        "owner": group.creator_fingerprint,
+                        "membership_epoch": group.membership_epoch,
                     });
                     if let Ok(invite_str) = serde_json::to_string(&invite) {
                         let _ = copy_to_clipboard(&invite_str);
@@ -1480,6 +3045,200 @@ impl Application for CryptoChat {
                 self.pending_group_delete = None;
                 Command::none()
             }
+            Message::StartGroupRename(group_id) => {
+                let current = self.groups.iter().find(|g| g.id == group_id).map(|g| g.name.clone()).unwrap_or_default();
+                self.group_rename = Some((group_id, current));
+                Command::none()
+            }
+            Message::GroupRenameInputChanged(input) => {
+                if let Some((_, name)) = self.group_rename.as_mut() {
+                    *name = input;
+                }
+                Command::none()
+            }
+            Message::CancelGroupRename => {
+                self.group_rename = None;
+                Command::none()
+            }
+            Message::SetGroupAdmin(group_id, fingerprint, admin) => {
+                let changed = self.change_group(&group_id, |group, me, _| {
+                    if admin {
+                        group_store::add_admin(group, me, &fingerprint)
+                    } else {
+                        group_store::remove_admin(group, me, &fingerprint)
+                    }
+                });
+                if let Some(group_name) = changed {
+                    self.status = format!("Updated admins of '{}'", group_name);
+                }
+                Command::none()
+            }
+            Message::ApproveGroupJoin(group_id, fingerprint) => {
+                let Ok(Some(stored_key)) = keystore::load_keypair() else {
+                    return Command::none();
+                };
+                let Some(group) = self.groups.iter_mut().find(|g| g.id == group_id) else {
+                    return Command::none();
+                };
+                let new_member = match group_store::approve_join(group, &stored_key.fingerprint, &fingerprint) {
+                    Ok(member) => member,
+                    Err(e) => {
+                        self.status = e.to_string();
+                        return Command::none();
+                    }
+                };
+                
+                use base64::Engine;
+                let encrypted_key = app::stored_public_key(&new_member.public_key)
+                    .and_then(|(recipient, warning)| {
+                        if let Some(warning) = warning {
+                            tracing::warn!(group = %group.id, peer = logging::short_fp(&new_member.fingerprint), %warning, "member key is no longer valid");
+                        }
+                        Ok(cryptochat_crypto_core::pgp::PgpKeyPair::encrypt(recipient.cert(), &group.symmetric_key)?)
+                    })
+                    .map(|bytes| base64::engine::general_purpose::STANDARD.encode(bytes))
+                    .ok();
+                // The key goes to the new member only; the others just learn the member list
+                let sync = |encrypted_key| network::MessageEnvelope::GroupMemberSync {
+                    group_id: group.id.clone(),
+                    sender_fingerprint: stored_key.fingerprint.clone(),
+                    members: group.members.clone(),
+                    membership_epoch: group.membership_epoch,
+                    encrypted_key,
+                };
+                // Someone who joined by invite code may not know us yet: introduce
+                // ourselves as the admin letting them in
+                let known = self.contacts.iter().any(|c| c.fingerprint == new_member.fingerprint);
+                if let (false, Some(keypair), Some(port)) = (known, self.app_state.get_keypair(), self.listening_port) {
+                    let kind = cryptochat_messaging::requests::RequestKind::GroupInvite;
+                    if let Ok(request) = network::MessageEnvelope::request(&keypair, port, &self.my_username, kind, now_ms()) {
+                        self.awaited_answers.record(&new_member.fingerprint, now_ms());
+                        let _ = network::NetworkHandle::send_message(&new_member.address, request);
+                    }
+                }
+                let _ = network::NetworkHandle::send_message(&new_member.address, sync(encrypted_key));
+                let others: Vec<String> = group.members.iter()
+                    .filter(|m| m.fingerprint != stored_key.fingerprint && m.fingerprint != new_member.fingerprint && !m.address.is_empty())
+                    .map(|m| m.address.clone())
+                    .collect();
+                let _ = network::NetworkHandle::send_to_group(&others, sync(None));
+                
+                self.status = format!("{} joined '{}' ({} members)", new_member.username, group.name, group.members.len());
+                let all_groups: Vec<_> = self.groups.iter().cloned().collect();
+                let _ = group_store::save_groups(&all_groups, &stored_key.fingerprint);
+                Command::none()
+            }
+            Message::DenyGroupJoin(group_id, fingerprint) => {
+                let Ok(Some(stored_key)) = keystore::load_keypair() else {
+                    return Command::none();
+                };
+                let Some(group) = self.groups.iter_mut().find(|g| g.id == group_id) else {
+                    return Command::none();
+                };
+                match group_store::deny_join(group, &stored_key.fingerprint, &fingerprint) {
+                    Ok(()) => {
+                        let all_groups: Vec<_> = self.groups.iter().cloned().collect();
+                        let _ = group_store::save_groups(&all_groups, &stored_key.fingerprint);
+                    }
+                    Err(e) => self.status = e.to_string(),
+                }
+                Command::none()
+            }
+            Message::TransferGroupOwnership(group_id, fingerprint) => {
+                let changed = self.change_group(&group_id, |group, me, _| group_store::transfer_ownership(group, me, &fingerprint));
+                if let Some(group_name) = changed {
+                    self.status = format!("Transferred ownership of '{}'", group_name);
+                }
+                Command::none()
+            }
+            Message::SubmitGroupRename => {
+                if let Some((group_id, name)) = self.group_rename.take() {
+                    let avatar = self.groups.iter().find(|g| g.id == group_id).and_then(|g| g.avatar_hash.clone());
+                    self.update_group_metadata(&group_id, &name, avatar);
+                }
+                Command::none()
+            }
+            Message::StartContactAlias(fingerprint) => {
+                let current = self.contacts.iter()
+                    .find(|c| c.fingerprint == fingerprint)
+                    .and_then(|c| c.alias.clone())
+                    .unwrap_or_default();
+                self.contact_alias_edit = Some((fingerprint, current));
+                Command::none()
+            }
+            Message::ContactAliasInputChanged(input) => {
+                if let Some((_, alias)) = self.contact_alias_edit.as_mut() {
+                    *alias = input;
+                }
+                Command::none()
+            }
+            Message::CancelContactAlias => {
+                self.contact_alias_edit = None;
+                Command::none()
+            }
+            Message::SubmitContactAlias => {
+                if let Some((fingerprint, alias)) = self.contact_alias_edit.take() {
+                    if request_store::set_contact_alias(&mut self.contacts, &fingerprint, &alias) {
+                        if let Err(e) = request_store::save_simple_contacts(&self.contacts) {
+                            self.status = format!("Failed to save alias: {}", e);
+                        }
+                    }
+                }
+                Command::none()
+            }
+            Message::PickGroupAvatar(group_id) => {
+                return Command::perform(
+                    async {
+                        tokio::task::spawn_blocking(|| {
+                            file_dialog::pick_file("Select Group Avatar", &[file_dialog::IMAGES, file_dialog::ALL_FILES])
+                        }).await.map_err(|e| e.to_string())
+                    },
+                    move |res| Message::GroupAvatarSelected(group_id.clone(), res.ok().flatten()),
+                );
+            }
+            Message::GroupAvatarSelected(group_id, opt_path) => {
+                if let Some(path) = opt_path {
+                    match self.emote_manager.store_image(&path) {
+                        Ok(hash) => {
+                            let name = self.groups.iter().find(|g| g.id == group_id).map(|g| g.name.clone()).unwrap_or_default();
+                            self.update_group_metadata(&group_id, &name, Some(hash));
+                        }
+                        Err(e) => self.status = format!("Avatar failed: {}", e),
+                    }
+                }
+                Command::none()
+            }
+            Message::LeaveGroup(group_id) => {
+                if let Ok(Some(stored_key)) = keystore::load_keypair() {
+                    match group_store::leave_group(&group_id, &stored_key.fingerprint) {
+                        Ok(Some(group)) => {
+                            let others: Vec<String> = group.members.iter()
+                                .filter(|m| m.fingerprint != stored_key.fingerprint && !m.address.is_empty())
+                                .map(|m| m.address.clone())
+                                .collect();
+                            let fp = stored_key.fingerprint.clone();
+                            let signature = self.app_state.get_keypair()
+                                .and_then(|keypair| group_auth::sign(&keypair, group_auth::GroupAction::Leave, &group_id, &[&fp]).ok())
+                                .unwrap_or_default();
+                            let leave = network::MessageEnvelope::GroupLeave {
+                                group_id: group_id.clone(),
+                                fingerprint: fp,
+                                signature,
+                            };
+                            let _ = network::NetworkHandle::send_to_group(&others, leave);
+                            
+                            self.groups.retain(|g| g.id != group_id);
+                            if self.selected_group_id.as_deref() == Some(group_id.as_str()) {
+                                self.selected_group_id = None;
+                            }
+                            self.status = format!("Left group: {}", group.name);
+                        }
+                        Ok(None) => {}
+                        Err(e) => self.status = format!("Leave failed: {}", e),
+                    }
+                }
+                Command::none()
+            }
             Message::GroupInviteInputChanged(input) => {
                 self.group_invite_input = input;
                 Command::none()
@@ -1504,7 +3263,11 @@ impl Application for CryptoChat {
                                         fingerprint: m.get("fingerprint").and_then(|v| v.as_str()).unwrap_or("").to_string(),
                                         username: m.get("username").and_then(|v| v.as_str()).unwrap_or("Unknown").to_string(),
                                         public_key: m.get("public_key").and_then(|v| v.as_str()).unwrap_or("").to_string(),
-                                        address: m.get("address").and_then(|v| v.as_str()).unwrap_or("127.0.0.1:62780").to_string(),
+                                        address: m.get("address")
+                                            .and_then(|v| v.as_str())
+                                            .and_then(|a| a.parse::<peer_address::PeerAddress>().ok())
+                                            .map(|a| a.to_string())
+                                            .unwrap_or_else(|| "127.0.0.1:62780".to_string()),
                                         joined_at: chrono::Utc::now().to_rfc3339(),
                                     };
                                     members.push(member);
@@ -1518,60 +3281,44 @@ impl Application for CryptoChat {
                                     fingerprint: my_fp,
                                     username: self.my_username.clone(),
                                     public_key: stored_key.public_key_armored.clone(),
-                                    address: format!("127.0.0.1:{}", self.listening_port.unwrap_or(62780)),
+                                    address: network::advertised_address(self.listening_port.unwrap_or(network::DEFAULT_PORT)),
                                     joined_at: chrono::Utc::now().to_rfc3339(),
                                 };
                                 members.push(me);
                             }
                             
-                            let member_count = members.len();
+                            // Admins and epoch as the inviting admin sees them; older invites
+                            // only name the creator
+                            let admins: Vec<String> = json_val.get("admins")
+                                .and_then(|v| serde_json::from_value(v.clone()).ok())
+                                .filter(|admins: &Vec<String>| !admins.is_empty())
+                                .unwrap_or_else(|| vec![creator.clone()]);
+                            let owner = json_val.get("owner").and_then(|v| v.as_str()).map(str::to_string).unwrap_or(creator);
+                            let membership_epoch = json_val.get("membership_epoch").and_then(|v| v.as_u64()).unwrap_or(0);
+                            
                             let group = group_store::Group {
                                 id: group_id,
                                 name: group_name.clone(),
                                 created_at: chrono::Utc::now().to_rfc3339(),
-                                creator_fingerprint: creator.clone(),
+                                creator_fingerprint: owner,
                                 members,
-                                admins: vec![creator],
+                                admins,
                                 settings: group_store::GroupSettings {
                                     invite_permission: group_store::InvitePermission::AdminsOnly,
                                     max_members: None,
                                     disappearing_timer_secs: None,
                                 },
                                 symmetric_key: vec![0u8; 32], // Placeholder - real key comes from network
+                                avatar_hash: None,
+                                metadata_updated_ms: 0,
+                                metadata_updated_by: String::new(),
+                                membership_epoch,
+                                join_requests: Vec::new(),
+                                roles_version: 0,
+                                roles_updated_by: String::new(),
                             };
                             
-                            // Save to storage
-                            let mut groups = group_store::load_groups(&stored_key.fingerprint).unwrap_or_default();
-                            groups.push(group.clone());
-                            if let Err(e) = group_store::save_groups(&groups, &stored_key.fingerprint) {
-                                self.status = format!("Failed to save group: {}", e);
-                            } else {
-                                // Broadcast join announcement to all OTHER members
-                                let my_member_info = group.members.iter()
-                                    .find(|m| m.fingerprint == stored_key.fingerprint)
-                                    .cloned();
-                                
-                                if let Some(me) = my_member_info {
-                                    let other_members: Vec<String> = group.members.iter()
-                                        .filter(|m| m.fingerprint != stored_key.fingerprint)
-                                        .map(|m| m.address.clone())
-                                        .collect();
-                                    
-                                    if !other_members.is_empty() {
-                                        let announcement = network::MessageEnvelope::GroupJoinAnnouncement {
-                                            group_id: group.id.clone(),
-                                            new_member: me,
-                                        };
-                                        let (sent, _) = network::NetworkHandle::send_to_group(&other_members, announcement);
-                                        self.status = format!("Joined '{}' - syncing with {} members", group_name, sent);
-                                    } else {
-                                        self.status = format!("Joined '{}'", group_name);
-                                    }
-                                }
-                                
-                                // Auto-select the group so chat is immediately enabled
-                                self.selected_group_id = Some(group.id.clone());
-                                self.groups.push(group);
+                            if self.join_group(group, &stored_key.fingerprint) {
                                 self.group_invite_input.clear();
                             }
                         }
@@ -1583,6 +3330,60 @@ impl Application for CryptoChat {
                 }
                 Command::none()
             }
+            Message::AcceptGroupInvite(group_id) => {
+                // The invite stays queued until the group is saved, so a failed accept can be retried
+                let Some(invite) = self.pending_group_invites.iter().find(|i| i.group_id == group_id).cloned() else {
+                    return Command::none();
+                };
+                let Ok(Some(stored_key)) = keystore::load_keypair() else {
+                    self.status = "Log in to accept group invites".to_string();
+                    return Command::none();
+                };
+                {
+                    // Decrypt the group key sent with the invite
+                    use base64::Engine;
+                    let symmetric_key = cryptochat_crypto_core::pgp::PgpKeyPair::from_secret_key(&stored_key.secret_key_armored)
+                        .ok()
+                        .and_then(|keypair| {
+                            let encrypted = base64::engine::general_purpose::STANDARD.decode(&invite.encrypted_symmetric_key).ok()?;
+                            keypair.decrypt(&encrypted).ok()
+                        })
+                        .filter(|key| key.len() == 32);
+                    let Some(symmetric_key) = symmetric_key else {
+                        self.status = format!("Couldn't decrypt the key for '{}'; ask for a new invite", invite.group_name);
+                        return Command::none();
+                    };
+                    
+                    let known_members: Vec<group_store::GroupMember> = self.contacts.iter().map(|c| group_store::GroupMember {
+                        fingerprint: c.fingerprint.clone(),
+                        username: c.name.clone(),
+                        public_key: c.public_key.clone(),
+                        address: c.address.clone(),
+                        joined_at: chrono::Utc::now().to_rfc3339(),
+                    }).collect();
+                    let me = group_store::GroupMember {
+                        fingerprint: stored_key.fingerprint.clone(),
+                        username: self.my_username.clone(),
+                        public_key: stored_key.public_key_armored.clone(),
+                        address: network::advertised_address(self.listening_port.unwrap_or(network::DEFAULT_PORT)),
+                        joined_at: chrono::Utc::now().to_rfc3339(),
+                    };
+                    let group = group_store::group_from_invite(&invite, &known_members, me, symmetric_key);
+                    if self.join_group(group, &stored_key.fingerprint) {
+                        group_store::take_pending_invite(&mut self.pending_group_invites, &group_id);
+                        let _ = group_store::save_pending_invites(&self.pending_group_invites, &stored_key.fingerprint);
+                    }
+                }
+                Command::none()
+            }
+            Message::DeclineGroupInvite(group_id) => {
+                if let Some(invite) = group_store::take_pending_invite(&mut self.pending_group_invites, &group_id) {
+                    let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
+                    let _ = group_store::save_pending_invites(&self.pending_group_invites, &my_fp);
+                    self.status = format!("Declined invite to '{}'", invite.group_name);
+                }
+                Command::none()
+            }
             Message::PasswordInputChanged(password) => {
                 self.password_input = password;
                 self.login_error = None; // Clear error on input
@@ -1607,6 +3408,7 @@ impl Application for CryptoChat {
                                 self.status = format!("Welcome back, {}!", account.username);
                                 // Load groups
                                 self.groups = group_store::load_groups(&account.fingerprint).unwrap_or_default();
+                                self.pending_group_invites = group_store::load_pending_invites(&account.fingerprint).unwrap_or_default();
                                 return Command::perform(async { start_network_async().await }, Message::NetworkStarted);
                             }
                             Err(e) => {
@@ -1685,10 +3487,49 @@ impl Application for CryptoChat {
             }
             
             // Color settings handlers
+            Message::Shortcut(shortcut) => {
+                let message = match shortcut {
+                    shortcuts::Shortcut::Switcher => Some(Message::ToggleSwitcher),
+                    shortcuts::Shortcut::Settings => Some(Message::ToggleSettings),
+                    shortcuts::Shortcut::ToggleTheme => Some(Message::ToggleTheme),
+                    shortcuts::Shortcut::Close => self.close_message(),
+                };
+                match message {
+                    Some(message) => self.update(message),
+                    None => Command::none(),
+                }
+            }
             Message::ToggleSettings => {
                 self.show_settings = !self.show_settings;
+                if !self.show_settings {
+                    return Command::none();
+                }
+                Command::perform(
+                    async {
+                        tokio::task::spawn_blocking(|| devices::refresh().map_err(|e| e.to_string()))
+                            .await
+                            .map_err(|e| e.to_string())?
+                    },
+                    Message::DevicesLoaded,
+                )
+            }
+            Message::DevicesLoaded(result) => {
+                match result {
+                    Ok(list) => self.device_list = Some(list),
+                    Err(e) => self.status = format!("Couldn't load devices: {}", e),
+                }
                 Command::none()
             }
+            Message::RevokeDevice(device_id) => {
+                Command::perform(
+                    async move {
+                        tokio::task::spawn_blocking(move || devices::revoke(&device_id).map_err(|e| e.to_string()))
+                            .await
+                            .map_err(|e| e.to_string())?
+                    },
+                    Message::DevicesLoaded,
+                )
+            }
             Message::SetSettingsTab(tab) => {
                 self.settings_tab = tab;
                 // Update bubble style based on tab
@@ -1779,6 +3620,93 @@ impl Application for CryptoChat {
                 self.status = "Color preferences saved!".to_string();
                 Command::none()
             }
+            Message::ExportTheme => {
+                return Command::perform(
+                    async {
+                        tokio::task::spawn_blocking(|| {
+                            file_dialog::save_file("Export Theme", &[file_dialog::JSON], "cryptochat-theme.json")
+                        }).await.map_err(|e| e.to_string())
+                    },
+                    |res| Message::ThemeSavePathSelected(res.ok().flatten()),
+                );
+            }
+            Message::ThemeSavePathSelected(opt_path) => {
+                if let Some(path) = opt_path {
+                    match std::fs::write(&path, color_store::export_theme(&self.color_prefs)) {
+                        Ok(()) => self.status = "Theme exported".to_string(),
+                        Err(e) => self.status = format!("Export failed: {}", e),
+                    }
+                }
+                Command::none()
+            }
+            Message::ImportTheme => {
+                return Command::perform(
+                    async {
+                        tokio::task::spawn_blocking(|| {
+                            file_dialog::pick_file("Import Theme", &[file_dialog::JSON, file_dialog::ALL_FILES])
+                        }).await.map_err(|e| e.to_string())
+                    },
+                    |res| Message::ThemeFileSelected(res.ok().flatten()),
+                );
+            }
+            Message::ThemeFileSelected(opt_path) => {
+                if let Some(path) = opt_path {
+                    let result = std::fs::read_to_string(&path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|json| color_store::import_theme(&json).map_err(|e| e.to_string()));
+                    match result {
+                        Ok(prefs) => {
+                            self.color_prefs = prefs;
+                            // Apply and persist like a manual save
+                            let cmd = self.update(Message::SaveColorPrefs);
+                            self.status = "Theme imported".to_string();
+                            return cmd;
+                        }
+                        Err(e) => self.status = format!("Import failed: {}", e),
+                    }
+                }
+                Command::none()
+            }
+            Message::SetFontSize(size) => {
+                self.color_prefs.font_size = color_store::clamp_font_size(size);
+                Command::none()
+            }
+            Message::SetBubbleRadius(radius) => {
+                self.color_prefs.bubble_radius = color_store::clamp_bubble_radius(radius);
+                Command::none()
+            }
+            Message::ExportConversation(format) => {
+                let Some(conv) = self.active_conversation_id.as_ref().and_then(|id| self.conversations.get(id)) else {
+                    return Command::none();
+                };
+                let transcript = conversation_store::export_transcript(conv, format);
+                let safe_name: String = conv.name.chars()
+                    .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+                    .collect();
+                let downloads_dir = paths::downloads_dir();
+                let _ = std::fs::create_dir_all(&downloads_dir);
+                let save_path = downloads_dir.join(format!("{}_transcript.{}", safe_name, format.extension())).display().to_string();
+                match std::fs::write(&save_path, transcript) {
+                    Ok(_) => {
+                        self.status = format!("Exported to {}", save_path);
+                        show_notification("Conversation Exported", &format!("Saved to: {}", save_path));
+                    }
+                    Err(e) => self.status = format!("Export failed: {}", e),
+                }
+                Command::none()
+            }
+            Message::ToggleConversationColorPicker => {
+                self.show_conversation_color_picker = !self.show_conversation_color_picker;
+                Command::none()
+            }
+            Message::SetConversationBubbleColor(color) => {
+                if let Some(conv) = self.get_active_conversation_mut() {
+                    conv.bubble_color = color;
+                    self.save_conversations();
+                }
+                self.show_conversation_color_picker = false;
+                Command::none()
+            }
             Message::RainbowTick => {
                 // Update rainbow offset for animation
                 if let color_store::BubbleStyle::Rainbow { speed } = &self.color_prefs.bubble_style {
@@ -1795,51 +3723,559 @@ impl Application for CryptoChat {
             Message::TypingDotsTick => {
                 // Cycle typing dots animation phase: 0 -> 1 -> 2 -> 0
                 self.typing_dots_phase = (self.typing_dots_phase + 1) % 3;
+                // Members who went quiet without a "stopped typing" update
+                conversation::clear_stale_typing(&mut self.conversations, now_ms());
                 Command::none()
             }
-            Message::ShowReactionPicker(msg_idx) => {
-                self.reaction_picker_for_msg = Some(msg_idx);
+            Message::PurgeExpired => {
+                let _ = request_store::purge_expired_requests();
+                let now = now_ms();
+                self.pending_requests.retain(|r| r.expires_at_ms > now);
+                
+                // Disappearing messages
+                self.sweep_expired_messages();
+                if let Some(fp) = self.app_state.get_fingerprint() {
+                    let _ = encrypted_storage::purge_expired_history(&fp);
+                }
                 Command::none()
             }
-            Message::HideReactionPicker => {
-                self.reaction_picker_for_msg = None;
+            Message::ExpiryTick => {
+                self.sweep_expired_messages();
                 Command::none()
             }
-            Message::AddReaction(msg_idx, emoji) => {
-                let my_username_clone = self.my_username.clone();
-                if let Some(conv) = self.get_active_conversation_mut() {
-                   if let Some(msg) = conv.messages.get_mut(msg_idx) {
-                    let msg_timestamp = msg.timestamp.clone();
-                    
-                    // Check if user already reacted with this emoji (toggle off)
-                    if let Some(pos) = msg.reactions.iter().position(|(e, sender)| e == &emoji && sender == &my_username_clone) {
-                        msg.reactions.remove(pos);
-                    } else {
-                        msg.reactions.push((emoji.clone(), my_username_clone.clone()));
+            Message::RevokeMyKey => {
+                self.confirm_revoke = true;
+                self.status = "Revoking can't be undone: your key is replaced with a new one you'll need to share again".to_string();
+                Command::none()
+            }
+            Message::CancelRevokeMyKey => {
+                self.confirm_revoke = false;
+                self.status = "Key not revoked".to_string();
+                Command::none()
+            }
+            Message::ConfirmRevokeMyKey => {
+                let (Some(old_key), Ok(Some(stored_key))) = (self.app_state.get_keypair(), keystore::load_keypair()) else {
+                    self.status = "No key to revoke".to_string();
+                    return Command::none();
+                };
+                if self.rotation_password.is_empty() {
+                    self.status = "Enter your password; revoking replaces your key".to_string();
+                    return Command::none();
+                }
+                // Taken now: replacing the key stores the new key's certificate.
+                // Keys made before revocation support have no stored certificate
+                let revocation = match keystore::load_revocation_certificate() {
+                    Ok(Some(cert)) => Ok(cert),
+                    _ => cryptochat_crypto_core::pgp::PgpKeyPair::from_secret_key(&stored_key.secret_key_armored)
+                        .and_then(|k| k.generate_revocation())
+                        .map_err(|e| e.to_string()),
+                };
+                let revocation = match revocation {
+                    Ok(revocation) => revocation,
+                    Err(e) => {
+                        self.status = format!("Revocation failed: {}", e);
+                        return Command::none();
                     }
-                    
-                    // Send reaction to peer
-                    if let Some(ref addr) = self.peer_address {
-                        let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
-                        let port = self.listening_port.unwrap_or(network::DEFAULT_PORT);
-                        let envelope = network::MessageEnvelope::Reaction {
-                            msg_timestamp,
-                            emoji,
-                            sender_name: my_username_clone,
-                            sender_fingerprint: my_fp,
+                };
+                self.confirm_revoke = false;
+                let password = std::mem::take(&mut self.rotation_password);
+                self.status = "Replacing revoked key...".to_string();
+                // The revocation only goes out once we've stopped using the key
+                Command::perform(
+                    rotate_key_async(self.app_state.clone(), old_key, password),
+                    move |result| Message::RevokedKeyReplaced(result.map(|rotated| (rotated, revocation))),
+                )
+            }
+            Message::RevokedKeyReplaced(result) => {
+                match result {
+                    Ok((rotated, revocation_certificate)) => {
+                        self.adopt_rotated_key(&rotated);
+                        let addresses: Vec<String> = self.contacts.iter()
+                            .filter(|c| !c.address.is_empty())
+                            .map(|c| c.address.clone())
+                            .collect();
+                        // No signed rotation notice: whoever has the old key could
+                        // sign one too, so contacts have to be given the new key again
+                        let envelope = network::MessageEnvelope::KeyRevoked {
+                            fingerprint: rotated.old_fingerprint.clone(),
+                            revocation_certificate,
+                        };
+                        let (sent, _) = network::NetworkHandle::send_to_group(&addresses, envelope);
+                        self.status = format!(
+                            "Old key revoked and sent to {}/{} contacts. Now using {} - share your new key with your contacts",
+                            sent, addresses.len(), &rotated.new_fingerprint[..8.min(rotated.new_fingerprint.len())]
+                        );
+                        self.show_settings = false;
+                    }
+                    Err(e) => self.status = format!("Revocation failed, key still in use: {}", e),
+                }
+                Command::none()
+            }
+            Message::SetNotificationsEnabled(enabled) => {
+                self.notification_settings.enabled = enabled;
+                notifications::apply_settings(&self.notification_settings);
+                let _ = notifications::save_settings(&self.notification_settings);
+                Command::none()
+            }
+            Message::SetNotificationSound(sound) => {
+                self.notification_settings.sound = sound;
+                notifications::apply_settings(&self.notification_settings);
+                let _ = notifications::save_settings(&self.notification_settings);
+                Command::none()
+            }
+            Message::SetAutoAcceptContacts(enabled) => {
+                self.request_settings.auto_accept_contacts = enabled;
+                let _ = request_store::save_request_settings(&self.request_settings);
+                Command::none()
+            }
+            Message::UnblockAll => {
+                self.request_settings.blocked_fingerprints.clear();
+                let _ = request_store::save_request_settings(&self.request_settings);
+                self.status = "Unblocked everyone".to_string();
+                Command::none()
+            }
+            Message::RotationPasswordChanged(password) => {
+                self.rotation_password = password;
+                Command::none()
+            }
+            Message::RotateMyKey => {
+                let Some(old_key) = self.app_state.get_keypair() else {
+                    self.status = "No key to rotate".to_string();
+                    return Command::none();
+                };
+                if self.rotation_password.is_empty() {
+                    self.status = "Enter your password to rotate your key".to_string();
+                    return Command::none();
+                }
+                let password = std::mem::take(&mut self.rotation_password);
+                self.status = "Generating new key...".to_string();
+                Command::perform(rotate_key_async(self.app_state.clone(), old_key, password), Message::KeyRotated)
+            }
+            Message::KeyRotated(result) => {
+                match result {
+                    Ok(rotated) => {
+                        self.adopt_rotated_key(&rotated);
+
+                        let addresses: Vec<String> = self.contacts.iter()
+                            .filter(|c| !c.address.is_empty() && !c.revoked)
+                            .map(|c| c.address.clone())
+                            .collect();
+                        let envelope = network::MessageEnvelope::KeyRotation {
+                            old_fingerprint: rotated.old_fingerprint,
+                            new_public_key: rotated.new_public_key,
+                            signature: rotated.signature,
+                        };
+                        let (sent, _) = network::NetworkHandle::send_to_group(&addresses, envelope);
+                        self.status = format!("Key rotated to {} - sent to {}/{} contacts", &rotated.new_fingerprint[..8.min(rotated.new_fingerprint.len())], sent, addresses.len());
+                        self.show_settings = false;
+                    }
+                    Err(e) => self.status = format!("Key rotation failed: {}", e),
+                }
+                Command::none()
+            }
+            Message::NewPasswordChanged(password) => {
+                self.new_password = password;
+                Command::none()
+            }
+            Message::ChangePassword => {
+                if self.rotation_password.is_empty() || self.new_password.is_empty() {
+                    self.status = "Enter your current and new password".to_string();
+                    return Command::none();
+                }
+                match account_store::change_password(&self.rotation_password, &self.new_password) {
+                    Ok(_) => {
+                        self.rotation_password.clear();
+                        self.new_password.clear();
+                        self.status = "Password changed".to_string();
+                    }
+                    Err(e) => self.status = format!("Password change failed: {}", e),
+                }
+                Command::none()
+            }
+            Message::BackupPasswordChanged(password) => {
+                self.backup_password = password;
+                Command::none()
+            }
+            Message::ExportAllData => {
+                if self.backup_password.is_empty() {
+                    self.status = "Enter a passphrase for the archive".to_string();
+                    return Command::none();
+                }
+                let password = std::mem::take(&mut self.backup_password);
+                self.status = "Exporting all data...".to_string();
+                Command::perform(
+                    async move {
+                        tokio::task::spawn_blocking(move || -> Result<Option<String>, String> {
+                            let bytes = data_export::export_all(&password).map_err(|e| e.to_string())?;
+                            let Some(path) = file_dialog::save_file("Export All Data", &[file_dialog::ALL_FILES], "cryptochat-backup.ccarchive") else {
+                                return Ok(None);
+                            };
+                            std::fs::write(&path, bytes).map_err(|e| e.to_string())?;
+                            Ok(Some(path.display().to_string()))
+                        }).await.map_err(|e| e.to_string())?
+                    },
+                    Message::AllDataExported,
+                )
+            }
+            Message::AllDataExported(result) => {
+                self.status = match result {
+                    Ok(Some(path)) => format!("All data exported to {}", path),
+                    Ok(None) => "Export cancelled".to_string(),
+                    Err(e) => format!("Export failed: {}", e),
+                };
+                Command::none()
+            }
+            Message::ImportAllData => {
+                if self.backup_password.is_empty() {
+                    self.status = "Enter the archive's passphrase".to_string();
+                    return Command::none();
+                }
+                let password = std::mem::take(&mut self.backup_password);
+                Command::perform(
+                    async move {
+                        tokio::task::spawn_blocking(move || -> Result<Option<data_export::ImportSummary>, String> {
+                            let Some(path) = file_dialog::pick_file("Import All Data", &[file_dialog::ALL_FILES]) else {
+                                return Ok(None);
+                            };
+                            let bytes = std::fs::read(&path).map_err(|e| e.to_string())?;
+                            data_export::import_all(&bytes, &password).map(Some).map_err(|e| e.to_string())
+                        }).await.map_err(|e| e.to_string())?
+                    },
+                    Message::AllDataImported,
+                )
+            }
+            Message::HistoryLoaded(Ok((mut loaded, warning))) => {
+                // Messages that came in while the history was being read
+                let mut merged = false;
+                for (id, arrived) in std::mem::take(&mut self.conversations) {
+                    match loaded.get_mut(&id) {
+                        Some(conv) => merged |= conversation::merge_arrived(conv, arrived) > 0,
+                        None => {
+                            loaded.insert(id, arrived);
+                            merged = true;
+                        }
+                    }
+                }
+                self.conversations = loaded;
+                self.history_loaded = true;
+                if let Some(fp) = keystore::load_keypair().ok().flatten().map(|key| key.fingerprint) {
+                    // Caught up with the loaded history; written out with the next save
+                    (self.search_index, self.search_index_unsaved) = search::load_index(&fp, &self.conversations);
+                }
+                if merged {
+                    self.save_conversations();
+                } else {
+                    self.conversation_index = conversation_store::build_index(&self.conversations);
+                }
+                if let Some(warning) = warning {
+                    self.status = warning;
+                }
+                Command::none()
+            }
+            Message::HistoryLoaded(Err(e)) => {
+                // Nothing is saved over a history we never read
+                tracing::error!(error = %e, "could not load chat history");
+                self.status = "⚠ Could not load chat history; restart to try again".to_string();
+                Command::none()
+            }
+            Message::AllDataImported(result) => {
+                self.status = match result {
+                    Ok(Some(summary)) => {
+                        let restored: usize = summary.files.values().sum();
+                        let account = &summary.fingerprint[..8.min(summary.fingerprint.len())];
+                        match &summary.set_aside {
+                            Some(path) => format!(
+                                "That archive is account {}'s; restored {} files to {} without touching this account",
+                                account, restored, path.display()
+                            ),
+                            None => {
+                                let backup = summary.backup
+                                    .map(|path| format!(" (replaced files backed up to {})", path.display()))
+                                    .unwrap_or_default();
+                                self.reload_account_data()
+                                    .unwrap_or_else(|| format!("Restored {} files for {}{}", restored, account, backup))
+                            }
+                        }
+                    }
+                    Ok(None) => "Import cancelled".to_string(),
+                    Err(e) => format!("Import failed: {}", e),
+                };
+                Command::none()
+            }
+            Message::CycleDisappearingTimer => {
+                let Some(conv_id) = self.selected_group_id.clone().or_else(|| self.active_conversation_id.clone()) else {
+                    return Command::none();
+                };
+                let Ok(Some(stored_key)) = keystore::load_keypair() else {
+                    return Command::none();
+                };
+                let timer = conversation::next_disappearing_timer(self.disappearing_timer_for(&conv_id));
+                
+                if let Some(group) = self.groups.iter_mut().find(|g| g.id == conv_id) {
+                    if !group_store::can_edit_metadata(group, &stored_key.fingerprint) {
+                        self.status = "Only admins can change the timer".to_string();
+                        return Command::none();
+                    }
+                    group.settings.disappearing_timer_secs = timer;
+                    let others: Vec<String> = group.members.iter()
+                        .filter(|m| m.fingerprint != stored_key.fingerprint && !m.address.is_empty())
+                        .map(|m| m.address.clone())
+                        .collect();
+                    let envelope = network::MessageEnvelope::SettingsChanged {
+                        group_id: group.id.clone(),
+                        new_settings: group.settings.clone(),
+                        sender_fingerprint: stored_key.fingerprint.clone(),
+                    };
+                    let _ = network::NetworkHandle::send_to_group(&others, envelope);
+                    let all_groups: Vec<_> = self.groups.iter().cloned().collect();
+                    let _ = group_store::save_groups(&all_groups, &stored_key.fingerprint);
+                } else if let Some(conv) = self.conversations.get_mut(&conv_id) {
+                    conv.disappearing_timer_secs = timer;
+                    if let (Some(peer_addr), Some(port)) = (conv.peer_address.clone(), self.listening_port) {
+                        let envelope = network::MessageEnvelope::DisappearingTimer {
+                            timer_secs: timer,
+                            sender_fingerprint: stored_key.fingerprint.clone(),
                             sender_listening_port: port,
                         };
-                        let addr = addr.clone();
                         let _ = std::thread::spawn(move || {
-                            let _ = network::NetworkHandle::send_message(&addr, envelope);
+                            let _ = network::NetworkHandle::send_message(&peer_addr, envelope);
                         });
                     }
+                    self.save_conversations();
+                }
+                self.status = format!("Disappearing messages: {}", conversation::disappearing_timer_label(timer));
+                Command::none()
+            }
+            Message::EmoteAnimationTick => {
+                self.emote_clock_ms = self.emote_clock_ms.wrapping_add(emote_manager::MIN_FRAME_DELAY_MS as u64);
+                Command::none()
+            }
+            Message::ShowReactionPicker(msg_idx) => {
+                self.reaction_picker_for_msg = Some(msg_idx);
+                Command::none()
+            }
+            Message::HideReactionPicker => {
+                self.reaction_picker_for_msg = None;
+                Command::none()
+            }
+            Message::CopyMessageText(msg_idx) => {
+                if let Some(msg) = self.get_active_messages().get(msg_idx) {
+                    let copied = conversation::copy_text(msg);
+                    self.status = match copy_to_clipboard(&copied) {
+                        Ok(()) => "Message copied".to_string(),
+                        Err(e) => format!("Copy failed: {}", e),
+                    };
+                }
+                self.reaction_picker_for_msg = None;
+                Command::none()
+            }
+            Message::Disconnect => {
+                if let (Some(addr), true) = (self.peer_address.clone(), self.recipient_key_imported) {
+                    let envelope = network::MessageEnvelope::SessionEnd {
+                        sender_fingerprint: self.app_state.get_fingerprint().unwrap_or_default(),
+                    };
+                    let _ = std::thread::spawn(move || {
+                        let _ = network::NetworkHandle::send_message(&addr, envelope);
+                    });
+                }
+                end_session(&self.app_state, &mut self.conversations, &mut self.active_conversation_id, &mut self.message_input);
+                self.save_conversations();
+                self.recipient_key_imported = false;
+                self.peer_address = None;
+                self.peer_username = None;
+                self.status = "Disconnected".to_string();
+                Command::none()
+            }
+            Message::ResendKey => {
+                let conv_id = self.active_conversation_id.clone().unwrap_or_default();
+                let known = self.conversations.get(&conv_id).and_then(|c| c.peer_address.clone())
+                    .or_else(|| self.contacts.iter().find(|c| c.fingerprint == conv_id).map(|c| c.address.clone()))
+                    .or_else(|| self.peer_address.clone())
+                    .filter(|addr| !addr.is_empty());
+                match known {
+                    Some(addr) => self.resend_key_to(addr, true),
+                    None => {
+                        self.resend_key_address = Some(String::new());
+                        self.status = "Enter the peer's address to resend your key".to_string();
+                        Command::none()
+                    }
+                }
+            }
+            Message::ResendKeyAddressChanged(input) => {
+                if let Some(addr) = self.resend_key_address.as_mut() {
+                    *addr = input;
+                }
+                Command::none()
+            }
+            Message::CancelResendKey => {
+                self.resend_key_address = None;
+                Command::none()
+            }
+            Message::SubmitResendKeyAddress => {
+                let Some(input) = self.resend_key_address.clone() else {
+                    return Command::none();
+                };
+                match input.trim().parse::<peer_address::PeerAddress>() {
+                    Ok(addr) => {
+                        self.resend_key_address = None;
+                        self.resend_key_to(addr.to_string(), true)
+                    }
+                    Err(e) => {
+                        self.status = format!("Invalid address: {}", e);
+                        Command::none()
                     }
                 }
+            }
+            Message::KeyResent(addr, result) => {
+                self.status = match result {
+                    Ok(()) => format!("Key resent to {}", addr),
+                    Err(e) => {
+                        tracing::warn!(error = %e, "key resend failed");
+                        format!("Couldn't resend key to {}: {}", addr, e)
+                    }
+                };
+                Command::none()
+            }
+            Message::CopyFingerprint(fingerprint) => {
+                self.status = match copy_to_clipboard(&fingerprint) {
+                    Ok(()) => "Fingerprint copied".to_string(),
+                    Err(e) => format!("Copy failed: {}", e),
+                };
+                Command::none()
+            }
+            Message::AddReaction(msg_idx, emoji) => {
+                let my_username_clone = self.my_username.clone();
+                let group_id = self.selected_group_id.clone();
+                let Some((msg_id, msg_timestamp)) = self.get_active_conversation_mut().and_then(|conv| {
+                    let msg = conv.messages.get(msg_idx)?;
+                    let (msg_id, msg_timestamp) = (msg.id, msg.timestamp.clone());
+                    conversation::toggle_reaction(conv, Some(msg_id), &msg_timestamp, &emoji, &my_username_clone);
+                    Some((msg_id, msg_timestamp))
+                }) else {
+                    self.reaction_picker_for_msg = None;
+                    return Command::none();
+                };
+                self.save_conversations();
+
+                // Send reaction to the peer, or every other member of the group
+                let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
+                let addresses: Vec<String> = match group_id.as_ref().and_then(|id| self.groups.iter().find(|g| &g.id == id)) {
+                    Some(group) => group.members.iter()
+                        .filter(|m| m.fingerprint != my_fp && !m.address.is_empty())
+                        .map(|m| m.address.clone())
+                        .collect(),
+                    None => self.peer_address.clone().into_iter().collect(),
+                };
+                if !addresses.is_empty() {
+                    let port = self.listening_port.unwrap_or(network::DEFAULT_PORT);
+                    let envelope = network::MessageEnvelope::Reaction {
+                        msg_id: Some(msg_id),
+                        msg_timestamp,
+                        emoji,
+                        sender_name: my_username_clone,
+                        sender_fingerprint: my_fp,
+                        sender_listening_port: port,
+                        group_id,
+                    };
+                    let _ = std::thread::spawn(move || {
+                        let _ = network::NetworkHandle::send_to_group(&addresses, envelope);
+                    });
+                }
 
                 self.reaction_picker_for_msg = None;
                 Command::none()
             }
+            Message::ForwardMessage(msg_idx) => {
+                self.reaction_picker_for_msg = None;
+                self.forward_picker_for_msg = Some(msg_idx);
+                Command::none()
+            }
+            Message::CancelForward => {
+                self.forward_picker_for_msg = None;
+                Command::none()
+            }
+            Message::ForwardTo(target) => {
+                let Some(msg_idx) = self.forward_picker_for_msg.take() else {
+                    return Command::none();
+                };
+                let Some(original) = self.get_active_messages().get(msg_idx).cloned() else {
+                    return Command::none();
+                };
+                let mut copy = forward::forwarded_copy(&original, &self.my_username, chrono_time(), now_ms());
+                let message_id = copy.message_id.clone().unwrap_or_default();
+                let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
+                let port = self.listening_port.unwrap_or(network::DEFAULT_PORT);
+
+                if let Some(group) = self.groups.iter().find(|g| g.id == target) {
+                    if copy.image_filename.is_some() || copy.voice.is_some() {
+                        self.status = "Images, files and voice messages can't be forwarded to groups yet".to_string();
+                        return Command::none();
+                    }
+                    let group_name = group.name.clone();
+                    let others: Vec<String> = group.members.iter()
+                        .filter(|m| m.fingerprint != my_fp && !m.address.is_empty())
+                        .map(|m| m.address.clone())
+                        .collect();
+                    let encrypted_content = match group_crypto::seal_content(group, &forward::wire_text(&copy)) {
+                        Ok(content) => content,
+                        Err(e) => {
+                            self.status = e.to_string();
+                            return Command::none();
+                        }
+                    };
+                    conversation::stamp_expiry(&mut copy, self.disappearing_timer_for(&target), chrono::Utc::now());
+                    let envelope = network::MessageEnvelope::GroupMessage {
+                        group_id: target.clone(),
+                        sender_fingerprint: my_fp,
+                        sender_name: self.my_username.clone(),
+                        encrypted_content,
+                        timestamp: copy.timestamp.clone(),
+                        expires_at: copy.expires_at.clone(),
+                        message_id: Some(message_id),
+                    };
+                    let (sent, _) = network::NetworkHandle::send_to_group(&others, envelope);
+                    copy.status = if sent > 0 { DeliveryStatus::Sent } else { DeliveryStatus::Failed };
+                    self.add_message(target, "Group".to_string(), copy, None);
+                    self.save_conversations();
+                    self.status = format!("Forwarded to {}", group_name);
+                    return Command::none();
+                }
+
+                let Some(contact) = self.contacts.iter().find(|c| c.fingerprint == target).cloned() else {
+                    self.status = "Contact not found".to_string();
+                    return Command::none();
+                };
+                let Some(my_key) = self.app_state.get_keypair() else {
+                    self.status = "Log in to forward messages".to_string();
+                    return Command::none();
+                };
+                // Encrypted for the new recipient from our plaintext copy
+                let envelope = match forward::direct_envelope(&copy, &my_key, &contact.public_key, &self.my_username, &my_fp, port) {
+                    Ok(envelope) => envelope,
+                    Err(e) => {
+                        self.status = format!("Forward failed: {}", e);
+                        return Command::none();
+                    }
+                };
+                self.add_message(contact.fingerprint.clone(), contact.name.clone(), copy, Some(contact.address.clone()));
+                self.status = format!("Forwarded to {}", contact.name);
+                let conv_id = contact.fingerprint.clone();
+                let addr = contact.address.clone();
+                let my_address = network::advertised_address(port);
+                let recipient_fp = conv_id.clone();
+                Command::perform(
+                    async move {
+                        tokio::task::spawn_blocking(move || {
+                            relay::send_with_fallback(&addr, &recipient_fp, envelope, &my_fp, &my_address)
+                                .map(|_| ())
+                                .map_err(|e| e.to_string())
+                        })
+                        .await
+                        .map_err(|e| e.to_string())?
+                    },
+                    move |result| Message::DirectMessageSent(conv_id, message_id, result),
+                )
+            }
         }
     }
 
@@ -1848,11 +4284,18 @@ impl Application for CryptoChat {
             View::Login => self.view_login(),
             View::Onboarding => self.view_onboarding(),
             View::Chat => {
+                let main_panel = if self.show_switcher {
+                    self.view_switcher()
+                } else if self.show_search {
+                    self.view_search()
+                } else {
+                    self.view_chat()
+                };
                 row![
                     container(self.view_sidebar())
                         .width(Length::Fixed(260.0))
                         .height(Length::Fill),
-                    container(self.view_chat())
+                    container(main_panel)
                         .width(Length::Fill)
                         .height(Length::Fill)
                 ].into()
@@ -1882,11 +4325,12 @@ impl Application for CryptoChat {
         // Network polling
         let network_sub = iced::time::every(std::time::Duration::from_millis(100)).map(|_| Message::PollNetwork);
         
-        // Typing dots animation (when active conversation peer is typing)
+        // Typing dots animation (when active conversation peer is typing), which
+        // also expires group members' typing state
         let is_active_typing = self.active_conversation_id.as_ref()
             .and_then(|id| self.conversations.get(id))
-            .map(|conv| conv.is_typing)
-            .unwrap_or(false);
+            .is_some_and(|conv| conv.is_typing)
+            || self.conversations.values().any(|c| !c.typing_members.is_empty());
         let typing_sub = if is_active_typing {
             Some(iced::time::every(std::time::Duration::from_millis(400)).map(|_| Message::TypingDotsTick))
         } else {
@@ -1902,23 +4346,42 @@ impl Application for CryptoChat {
             None
         };
         
+        // Animated emote playback (only while an animated emote is on screen)
+        let has_animated_emotes = self.get_active_messages().iter().any(|msg| {
+            let hashes: Vec<&String> = msg.emotes.values().collect();
+            !hashes.is_empty() && self.emote_manager.has_animated(&hashes)
+        });
+        let emote_sub = if has_animated_emotes {
+            Some(iced::time::every(std::time::Duration::from_millis(emote_manager::MIN_FRAME_DELAY_MS as u64))
+                .map(|_| Message::EmoteAnimationTick))
+        } else {
+            None
+        };
+        
         // Combine all active subscriptions
-        match (typing_sub, rainbow_sub) {
-            (Some(t), Some(r)) => Subscription::batch([network_sub, t, r]),
-            (Some(t), None) => Subscription::batch([network_sub, t]),
-            (None, Some(r)) => Subscription::batch([network_sub, r]),
-            (None, None) => network_sub,
-        }
+        // Expired request and disappearing message cleanup
+        let purge_sub = iced::time::every(std::time::Duration::from_secs(60)).map(|_| Message::PurgeExpired);
+        let expiry_sub = self.get_active_messages().iter().any(|msg| msg.expires_at.is_some())
+            .then(|| iced::time::every(std::time::Duration::from_secs(1)).map(|_| Message::ExpiryTick));
+        
+        // Mailbox polling, only when a relay node is configured
+        let relay_sub = relay::relay_url().map(|_| iced::time::every(relay::POLL_INTERVAL).map(|_| Message::PollRelay));
+        
+        let shortcut_sub = iced::keyboard::on_key_press(|key, modifiers| shortcuts::shortcut_for(key, modifiers).map(Message::Shortcut));
+        
+        let mut subs = vec![network_sub, purge_sub, shortcut_sub];
+        subs.extend(relay_sub);
+        subs.extend(typing_sub);
+        subs.extend(rainbow_sub);
+        subs.extend(emote_sub);
+        subs.extend(expiry_sub);
+        Subscription::batch(subs)
     }
 }
 
 fn chrono_time() -> String {
-    // Simple time format
-    let now = std::time::SystemTime::now();
-    let secs = now.duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
-    let hours = (secs / 3600) % 24;
-    let mins = (secs / 60) % 60;
-    format!("{:02}:{:02}", hours, mins)
+    // Local wall-clock time; the date comes from the day headers
+    chrono::Local::now().format("%H:%M").to_string()
 }
 
 fn copy_to_clipboard(text: &str) -> Result<(), String> {
@@ -1935,40 +4398,38 @@ fn pick_and_send_file(
     sender_name: String,
     sender_fingerprint: String,
     listening_port: u16,
-) -> Result<(String, Vec<u8>), String> {
+) -> Result<(String, Vec<u8>, String), String> {
     let peer_addr = peer_addr.ok_or("No peer connected")?;
     
-    // Use PowerShell to open file picker
-    let ps_cmd = r#"
-Add-Type -AssemblyName System.Windows.Forms
-$dialog = New-Object System.Windows.Forms.OpenFileDialog
-$dialog.Title = 'Select file to send'
-$dialog.Filter = 'All Files (*.*)|*.*'
-if ($dialog.ShowDialog() -eq 'OK') { $dialog.FileName } else { '' }
-"#;
-    
-    let output = std::process::Command::new("powershell")
-        .args(["-WindowStyle", "Hidden", "-Command", ps_cmd])
-        .output()
-        .map_err(|e| format!("File picker failed: {}", e))?;
-    
-    let file_path = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    if file_path.is_empty() {
-        return Err("No file selected".into());
-    }
+    let file_path = file_dialog::pick_file("Select file to send", &[file_dialog::ALL_FILES])
+        .ok_or("No file selected")?;
     
     // Read file
     let file_data = std::fs::read(&file_path)
         .map_err(|e| format!("Read failed: {}", e))?;
     
-    let filename = std::path::Path::new(&file_path)
+    let filename = file_path
         .file_name()
         .map(|n| n.to_string_lossy().to_string())
         .unwrap_or_else(|| "file".to_string());
     
+    send_file_data(app_state, Some(peer_addr), filename, file_data, sender_name, sender_fingerprint, listening_port)
+}
+
+/// Send `file_data` as an encrypted file named `filename`
+fn send_file_data(
+    app_state: Arc<app::AppState>,
+    peer_addr: Option<String>,
+    filename: String,
+    file_data: Vec<u8>,
+    sender_name: String,
+    sender_fingerprint: String,
+    listening_port: u16,
+) -> Result<(String, Vec<u8>, String), String> {
+    let peer_addr = peer_addr.ok_or("No peer connected")?;
+    
     // Encrypt with recipient's public key
-    let recipient_key = app_state.recipient_keypair.read().unwrap();
-    let recipient = recipient_key.as_ref().ok_or("No recipient key")?;
+    let recipient = app_state.get_recipient_keypair().ok().flatten().ok_or("No recipient key")?;
     let encrypted = cryptochat_crypto_core::pgp::PgpKeyPair::encrypt(recipient.cert(), &file_data)
         .map_err(|e| format!("Encrypt failed: {}", e))?;
     
@@ -1977,85 +4438,55 @@ if ($dialog.ShowDialog() -eq 'OK') { $dialog.FileName } else { '' }
     let encoded = base64::engine::general_purpose::STANDARD.encode(&encrypted);
     
     // Send file message
+    let message_id = uuid::Uuid::new_v4().to_string();
     let envelope = network::MessageEnvelope::FileMessage {
         filename: filename.clone(),
         encrypted_data: encoded,
         sender_name: Some(sender_name),
         sender_fingerprint,
         sender_listening_port: listening_port,
+        message_id: Some(message_id.clone()),
     };
     
     network::NetworkHandle::send_message(&peer_addr, envelope)
         .map_err(|e| format!("Send failed: {}", e))?;
     
-    // Return filename and raw data for sender's display
-    Ok((filename, file_data))
+    // Return filename, raw data and id for sender's display
+    Ok((filename, file_data, message_id))
 }
 
-fn pick_emote_file() -> Result<Option<std::path::PathBuf>, String> {
-    let ps_cmd = r#"
-Add-Type -AssemblyName System.Windows.Forms
-$dialog = New-Object System.Windows.Forms.OpenFileDialog
-$dialog.Title = 'Select Emote Image'
-$dialog.Filter = 'Images (*.png;*.jpg;*.jpeg;*.gif)|*.png;*.jpg;*.jpeg;*.gif|All Files (*.*)|*.*'
-if ($dialog.ShowDialog() -eq 'OK') { $dialog.FileName } else { '' }
-"#;
-    
-    let output = std::process::Command::new("powershell")
-        .args(["-WindowStyle", "Hidden", "-Sta", "-Command", ps_cmd])
-        .output()
-        .map_err(|e| format!("File picker failed: {}", e))?;
-    
-    let file_path = String::from_utf8_lossy(&output.stdout).trim().to_string();
-    if file_path.is_empty() {
-        return Ok(None);
-    }
-    Ok(Some(std::path::PathBuf::from(file_path)))
-}
-
-/// Show Windows toast notification
-fn show_notification(title: &str, message: &str) {
-    let ps_cmd = format!(
-        r#"
-[Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime] | Out-Null
-[Windows.Data.Xml.Dom.XmlDocument, Windows.Data.Xml.Dom.XmlDocument, ContentType = WindowsRuntime] | Out-Null
-$template = @"
-<toast>
-    <visual>
-        <binding template="ToastText02">
-            <text id="1">{}</text>
-            <text id="2">{}</text>
-        </binding>
-    </visual>
-    <audio src="ms-winsoundevent:Notification.IM"/>
-</toast>
-"@
-$xml = New-Object Windows.Data.Xml.Dom.XmlDocument
-$xml.LoadXml($template)
-$toast = [Windows.UI.Notifications.ToastNotification]::new($xml)
-[Windows.UI.Notifications.ToastNotificationManager]::CreateToastNotifier("CryptoChat").Show($toast)
-"#,
-        title.replace("\"", "'"),
-        message.replace("\"", "'").replace("\n", " ")
-    );
-    
-    let _ = std::process::Command::new("powershell")
-        .args(["-WindowStyle", "Hidden", "-Command", &ps_cmd])
-        .spawn();
-}
+/// Send a recorded voice clip, encrypted for the current recipient
+#[allow(clippy::too_many_arguments)]
+fn send_voice(
+    app_state: Arc<app::AppState>,
+    peer_addr: String,
+    wav: Vec<u8>,
+    duration_ms: u64,
+    sender_name: String,
+    sender_fingerprint: String,
+    listening_port: u16,
+    message_id: String,
+) -> Result<(), String> {
+    let recipient = app_state.get_recipient_keypair().ok().flatten().ok_or("No recipient key")?;
+    let encrypted = cryptochat_crypto_core::pgp::PgpKeyPair::encrypt(recipient.cert(), &wav)
+        .map_err(|e| format!("Encrypt failed: {}", e))?;
 
-/// Play notification sound using Windows IM notification
-fn play_notification_sound() {
-    // Use Windows Media Player to play system notification sound
-    let _ = std::process::Command::new("powershell")
-        .args(["-WindowStyle", "Hidden", "-Command", 
-            r#"(New-Object Media.SoundPlayer "C:\Windows\Media\Windows Notify Email.wav").PlaySync()"#])
-        .spawn();
+    use base64::Engine;
+    let envelope = network::MessageEnvelope::VoiceMessage {
+        encrypted_audio: base64::engine::general_purpose::STANDARD.encode(&encrypted),
+        duration_ms,
+        sender_name: Some(sender_name),
+        sender_fingerprint,
+        sender_listening_port: listening_port,
+        message_id: Some(message_id),
+    };
+    network::NetworkHandle::send_message(&peer_addr, envelope)
+        .map_err(|e| format!("Send failed: {}", e))
 }
 
 fn copy_image_to_clipboard(img: &image::ImageBuffer<image::Luma<u8>, Vec<u8>>) -> Result<(), String> {
     // Save to temp file and use Windows to copy (simplest cross-platform approach)
-    let path = format!("{}/.cryptochat_qr_temp.png", std::env::var("USERPROFILE").unwrap_or_default());
+    let path = paths::temp_file("cryptochat_qr_temp.png").display().to_string();
     img.save(&path).map_err(|e| format!("Save failed: {}", e))?;
     
     // Use PowerShell to copy image to clipboard
@@ -2074,24 +4505,12 @@ fn copy_image_to_clipboard(img: &image::ImageBuffer<image::Luma<u8>, Vec<u8>>) -
 
 async fn scan_qr_from_clipboard_async(app_state: Arc<app::AppState>) -> Result<ImportResult, String> {
     tokio::task::spawn_blocking(move || {
-        // Save clipboard image to temp file using PowerShell
-        let temp_path = format!("{}/.cryptochat_qr_scan.png", std::env::var("USERPROFILE").unwrap_or_default());
-        let ps_cmd = format!(
-            "Add-Type -AssemblyName System.Windows.Forms; $img = [System.Windows.Forms.Clipboard]::GetImage(); if ($img) {{ $img.Save('{}') }} else {{ exit 1 }}",
-            temp_path.replace("/", "\\")
-        );
+        let mut clipboard = arboard::Clipboard::new().map_err(|e| format!("Clipboard init: {}", e))?;
+        let data = clipboard.get_image().map_err(|_| "No image in clipboard".to_string())?;
+        let rgba = image::RgbaImage::from_raw(data.width as u32, data.height as u32, data.bytes.into_owned())
+            .ok_or("Clipboard image is malformed")?;
         
-        let result = std::process::Command::new("powershell")
-            .args(["-Command", &ps_cmd])
-            .output()
-            .map_err(|e| format!("PowerShell failed: {}", e))?;
-        
-        if !result.status.success() {
-            return Err("No image in clipboard".to_string());
-        }
-        
-        // Scan QR from file
-        let payload = qr_exchange::scan_qr_from_file(&temp_path)
+        let payload = qr_exchange::scan_qr_from_image(image::DynamicImage::ImageRgba8(rgba).to_luma8())
             .map_err(|e| format!("QR scan failed: {}", e))?;
         
         // Import the key
@@ -2100,9 +4519,6 @@ async fn scan_qr_from_clipboard_async(app_state: Arc<app::AppState>) -> Result<I
         let fingerprint = keypair.fingerprint();
         app_state.set_recipient_keypair(keypair);
         
-        // Clean up
-        let _ = std::fs::remove_file(&temp_path);
-        
         // For QR, address is embedded in the port from our listening port
         // The QR payload doesn't include address, so we need to get it from clipboard text or manual entry
         // For now, use localhost with a placeholder
@@ -2135,10 +4551,18 @@ fn load_chat_history_sync() -> Vec<ChatMessage> {
             content: m.content,
             is_mine: m.is_mine,
             timestamp: m.timestamp,
+            sent_at_ms: 0,
             image_data: None,  // Images not stored in history
             image_filename: None,
             reactions: Vec::new(),
             emotes: m.emotes,
+            expires_at: m.expires_at,
+            message_id: None,
+            status: DeliveryStatus::Sent,
+            delivered_to: Vec::new(),
+            forwarded: false,
+            voice: None,
+            id: uuid::Uuid::new_v4(),
         })
         .collect()
 }
@@ -2149,7 +4573,7 @@ fn save_message_to_history(msg: &ChatMessage) {
         content: msg.content.clone(),
         is_mine: msg.is_mine,
         timestamp: msg.timestamp.clone(),
-        expires_at: None, // TODO: Add disappearing timer support
+        expires_at: msg.expires_at.clone(),
         emotes: msg.emotes.clone(),
     };
     
@@ -2164,7 +4588,8 @@ fn save_message_to_history(msg: &ChatMessage) {
 async fn start_network_async() -> Result<u16, String> {
     let (sender, receiver) = mpsc::unbounded_channel();
     let _ = NETWORK_RECEIVER.set(Mutex::new(Some(receiver)));
-    let handle = network::NetworkHandle::start_with_sender(sender).map_err(|e| format!("{}", e))?;
+    let preferred_port = request_store::load_listening_port().ok().flatten();
+    let handle = network::NetworkHandle::start_with_sender(sender, preferred_port).map_err(|e| format!("{}", e))?;
     Ok(handle.port())
 }
 
@@ -2174,12 +4599,42 @@ async fn generate_keys_async() -> Result<KeyGenResult, String> {
         let fingerprint = keypair.fingerprint();
         let public_key = keypair.export_public_key().map_err(|e| format!("{}", e))?;
         let secret_key = keypair.export_secret_key().map_err(|e| format!("{}", e))?;
+        let revocation = keypair.generate_revocation().map_err(|e| format!("{}", e))?;
         let stored = keystore::StoredKey { fingerprint: fingerprint.clone(), public_key_armored: public_key, secret_key_armored: secret_key };
         keystore::save_keypair(&stored).map_err(|e| format!("{}", e))?;
+        keystore::save_revocation_certificate(&revocation).map_err(|e| format!("{}", e))?;
         Ok(KeyGenResult { fingerprint })
     }).await.map_err(|e| format!("{}", e))?
 }
 
+/// Generate a replacement key, vouch for it with the current one and make it
+/// our identity (account, keystore and in-memory keypair)
+async fn rotate_key_async(app_state: Arc<app::AppState>, old_key: cryptochat_crypto_core::pgp::PgpKeyPair, password: String) -> Result<RotatedKey, String> {
+    tokio::task::spawn_blocking(move || {
+        let new_key = cryptochat_crypto_core::pgp::PgpKeyPair::generate("CryptoChat User").map_err(|e| format!("{}", e))?;
+        let statement = key_rotation::sign_rotation(&old_key, &new_key).map_err(|e| format!("{}", e))?;
+
+        let fingerprint = new_key.fingerprint();
+        let secret_key = new_key.export_secret_key().map_err(|e| format!("{}", e))?;
+        let revocation = new_key.generate_revocation().map_err(|e| format!("{}", e))?;
+        if account_store::account_exists() {
+            account_store::replace_keypair(&password, &secret_key, &statement.new_public_key, &fingerprint)
+                .map_err(|e| format!("{}", e))?;
+        }
+        let stored = keystore::StoredKey::new(secret_key, statement.new_public_key.clone(), fingerprint.clone());
+        keystore::save_keypair(&stored).map_err(|e| format!("{}", e))?;
+        keystore::save_revocation_certificate(&revocation).map_err(|e| format!("{}", e))?;
+        app_state.set_keypair(new_key);
+
+        Ok(RotatedKey {
+            old_fingerprint: statement.old_fingerprint,
+            new_fingerprint: fingerprint,
+            new_public_key: statement.new_public_key,
+            signature: statement.signature,
+        })
+    }).await.map_err(|e| format!("{}", e))?
+}
+
 async fn import_key_share_async(app_state: Arc<app::AppState>, input: String) -> Result<ImportResult, String> {
     tokio::task::spawn_blocking(move || {
         // First, check if this is a group invite (has "type": "group_invite")
@@ -2193,61 +4648,751 @@ async fn import_key_share_async(app_state: Arc<app::AppState>, input: String) ->
         
         // Standard key share import
         let key_share: network::KeyShareData = serde_json::from_str(&input).map_err(|e| format!("Invalid JSON: {}", e))?;
+        let address: peer_address::PeerAddress = key_share.address.parse().map_err(|e| format!("Invalid address: {}", e))?;
         let keypair = cryptochat_crypto_core::pgp::PgpKeyPair::from_public_key(&key_share.public_key).map_err(|e| format!("Invalid key: {}", e))?;
         let fingerprint = keypair.fingerprint();
         app_state.set_recipient_keypair(keypair);
-        app_state.set_peer_address(key_share.address.clone());
-        Ok(ImportResult { fingerprint, address: key_share.address, username: key_share.username })
+        app_state.set_peer_address(address.to_string());
+        Ok(ImportResult { fingerprint, address: address.to_string(), username: key_share.username })
+    }).await.map_err(|e| format!("{}", e))?
+}
+
+/// Send a logged message again, encrypted for `recipient_public_key`
+async fn resend_message_async(app_state: Arc<app::AppState>, recipient_public_key: String, entry: outbox::OutboxEntry, username: String, sender_fingerprint: String, sender_listening_port: u16) -> Result<(), String> {
+    tokio::task::spawn_blocking(move || {
+        let encrypted = app_state.encrypt_message_for(&entry.payload, &recipient_public_key).map_err(|e| format!("{}", e))?;
+        let envelope = network::MessageEnvelope::RegularMessage {
+            encrypted_payload: encrypted,
+            sender_name: Some(username),
+            sender_fingerprint: sender_fingerprint.clone(),
+            sender_listening_port,
+            message_id: Some(entry.message_id),
+        };
+        let my_address = network::advertised_address(sender_listening_port);
+        relay::send_with_fallback(&entry.peer_address, &entry.conversation_id, envelope, &sender_fingerprint, &my_address)
+            .map(|_| ())
+            .map_err(|e| format!("{}", e))
     }).await.map_err(|e| format!("{}", e))?
 }
 
-async fn send_message_async(app_state: Arc<app::AppState>, peer_address: String, content: String, username: String, sender_fingerprint: String, sender_listening_port: u16) -> Result<(), String> {
+async fn send_message_async(app_state: Arc<app::AppState>, peer_address: String, recipient_fingerprint: String, content: String, username: String, sender_fingerprint: String, sender_listening_port: u16, message_id: String) -> Result<(), String> {
     tokio::task::spawn_blocking(move || {
         let encrypted = app_state.encrypt_message(&content).map_err(|e| format!("{}", e))?;
         let envelope = network::MessageEnvelope::RegularMessage { 
             encrypted_payload: encrypted, 
             sender_name: Some(username), 
-            sender_fingerprint,
+            sender_fingerprint: sender_fingerprint.clone(),
             sender_listening_port,
+            message_id: Some(message_id),
         };
-        network::NetworkHandle::send_message(&peer_address, envelope).map_err(|e| format!("{}", e))
+        let my_address = network::advertised_address(sender_listening_port);
+        relay::send_with_fallback(&peer_address, &recipient_fingerprint, envelope, &sender_fingerprint, &my_address)
+            .map(|_| ())
+            .map_err(|e| format!("{}", e))
     }).await.map_err(|e| format!("{}", e))?
 }
 
+/// Check our relay mailbox, acknowledging what the last check returned
+async fn fetch_relay_async(relay_url: String, keypair: cryptochat_crypto_core::pgp::PgpKeyPair, ack: Vec<u64>) -> Result<Vec<cryptochat_node::relay::RelayedMessage>, String> {
+    tokio::task::spawn_blocking(move || relay::fetch(&relay_url, &keypair, &ack).map_err(|e| format!("{}", e)))
+        .await
+        .map_err(|e| format!("{}", e))?
+}
+
 impl CryptoChat {
+    /// Everything the quick-jump palette can open: chats in sidebar order
+    /// (archived included), then groups and contacts with no chat yet
+    fn switcher_candidates(&self) -> Vec<switcher::Candidate> {
+        let group_name = |id: &str| self.groups.iter().find(|g| g.id == id).map(|g| g.name.clone());
+        let chats = conversation_store::sidebar_order(&self.conversation_index, true)
+            .into_iter()
+            .map(|c| switcher::Candidate {
+                label: group_name(&c.id).unwrap_or_else(|| request_store::conversation_label(&self.contacts, &c.id, &c.name).to_string()),
+                target: switcher::Target::Conversation(c.id.clone()),
+            });
+        let groups = self.groups.iter()
+            .filter(|g| !self.conversation_index.contains_key(&g.id))
+            .map(|g| switcher::Candidate { label: g.name.clone(), target: switcher::Target::Group(g.id.clone()) });
+        let contacts = self.contacts.iter().enumerate()
+            .filter(|(_, c)| !self.conversation_index.contains_key(&c.fingerprint))
+            .map(|(i, c)| switcher::Candidate { label: c.display_name().to_string(), target: switcher::Target::Contact(i) });
+        chats.chain(groups).chain(contacts).collect()
+    }
+
+    /// What Esc closes: the topmost open panel, editor or picker
+    fn close_message(&self) -> Option<Message> {
+        if self.show_settings {
+            Some(Message::ToggleSettings)
+        } else if self.show_switcher {
+            Some(Message::ToggleSwitcher)
+        } else if self.show_search {
+            Some(Message::ToggleSearch)
+        } else if self.forward_picker_for_msg.is_some() {
+            Some(Message::CancelForward)
+        } else if self.reaction_picker_for_msg.is_some() {
+            Some(Message::HideReactionPicker)
+        } else if self.show_emoji_picker {
+            Some(Message::ToggleEmojiPicker)
+        } else if self.show_emote_library {
+            Some(Message::ToggleEmoteLibrary)
+        } else if self.group_rename.is_some() {
+            Some(Message::CancelGroupRename)
+        } else if self.contact_alias_edit.is_some() {
+            Some(Message::CancelContactAlias)
+        } else if self.pending_group_delete.is_some() {
+            Some(Message::CancelDeleteGroup)
+        } else {
+            None
+        }
+    }
+
+    /// Empty the input (and the active conversation's saved draft),
+    /// returning what was typed
+    fn take_draft(&mut self) -> String {
+        self.unread_count = 0; // Clear unread when user is active
+        let content = std::mem::take(&mut self.message_input);
+        if let Some(conv) = self.get_active_conversation_mut() {
+            conv.input_draft.clear();
+        }
+        content
+    }
+
+    /// Once the send of split part `message_id` finished, send the next one.
+    /// If it failed, or the chat has changed since, the unsent parts go back
+    /// into that chat's draft instead.
+    fn send_next_split_part(&mut self, conv_id: &str, message_id: &str, sent: bool) -> Command<Message> {
+        if !self.pending_split.as_ref().is_some_and(|s| s.conversation_id == conv_id && s.in_flight == message_id) {
+            return Command::none();
+        }
+        let Some(mut split) = self.pending_split.take() else {
+            return Command::none();
+        };
+        let same_chat = self.selected_group_id.is_none()
+            && self.recipient_key_imported
+            && self.app_state.get_recipient_fingerprint().as_deref() == Some(conv_id);
+        if !sent || !same_chat {
+            let rest = Vec::from(split.remaining).join("\n");
+            let restore = |draft: &mut String| {
+                *draft = if draft.is_empty() { rest.clone() } else { format!("{}\n{}", rest, draft) };
+            };
+            if self.active_conversation_id.as_deref() == Some(conv_id) {
+                restore(&mut self.message_input);
+            } else if let Some(conv) = self.conversations.get_mut(conv_id) {
+                restore(&mut conv.input_draft);
+            }
+            self.status = "Stopped sending the split message; the unsent parts are back in the draft".to_string();
+            return Command::none();
+        }
+        let Some(part) = split.remaining.pop_front() else {
+            return Command::none();
+        };
+        let id = uuid::Uuid::new_v4();
+        if !split.remaining.is_empty() {
+            split.in_flight = id.to_string();
+            self.pending_split = Some(split);
+        }
+        self.send_text_with_id(part, id)
+    }
+
+    /// Send one text message to the selected group or the current peer
+    fn send_text(&mut self, content: String) -> Command<Message> {
+        self.send_text_with_id(content, uuid::Uuid::new_v4())
+    }
+
+    /// [`Self::send_text`] under a given message id
+    fn send_text_with_id(&mut self, content: String, id: uuid::Uuid) -> Command<Message> {
+        // Emote parsing
+        let mut emotes = std::collections::HashMap::new();
+        if let Ok(lib) = self.emote_manager.library.read() {
+           for (name, emote) in lib.iter() {
+               let pattern = format!(":{}:", name);
+               if content.contains(&pattern) {
+                   emotes.insert(name.clone(), emote.hash.clone());
+               }
+           }
+        }
+        
+        let network_payload = if !emotes.is_empty() {
+            let payload = EmotePayload {
+                content: content.clone(),
+                emotes: emotes.clone(),
+            };
+            serde_json::to_string(&payload).unwrap_or(content.clone())
+        } else {
+            content.clone()
+        };
+
+        let mut new_msg = ChatMessage {
+            sender_name: self.my_username.clone(),
+            content: content.clone(),
+            is_mine: true,
+            timestamp: chrono_time(),
+            sent_at_ms: now_ms(),
+            image_data: None,
+            image_filename: None,
+            reactions: Vec::new(),
+            emotes: emotes,
+            expires_at: None,
+            message_id: Some(id.to_string()),
+            status: DeliveryStatus::Pending,
+            delivered_to: Vec::new(),
+            forwarded: false,
+            voice: None,
+            id,
+        };
+        let message_id = new_msg.message_id.clone().unwrap_or_default();
+        // save_message_to_history(&new_msg); // TODO: Refactor persistence
+        
+        // Route to group or direct peer
+        // Route to group or direct peer
+        let group_id_opt = self.selected_group_id.clone();
+        if let Some(ref group_id) = group_id_opt {
+            if self.groups.iter().find(|g| &g.id == group_id).is_some_and(|g| !group_crypto::has_key(g)) {
+                // Nothing goes out in the clear; keep the text for when the key arrives
+                self.message_input = content;
+                self.status = group_crypto::GroupCryptoError::NoKey.to_string();
+                self.request_group_resync(group_id);
+                return Command::none();
+            }
+            // Stamp now so members get the same expiry we store
+            conversation::stamp_expiry(&mut new_msg, self.disappearing_timer_for(group_id), chrono::Utc::now());
+            // Add to group conversation
+            self.add_message(group_id.clone(), "Group".to_string(), new_msg.clone(), None);
+
+            // Group message sending...
+            if let Some(group) = self.groups.iter().find(|g| &g.id == group_id) {
+                let member_addresses: Vec<String> = group.members.iter()
+                    .filter(|m| m.fingerprint != self.app_state.get_fingerprint().unwrap_or_default())
+                    .map(|m| m.address.clone())
+                    .collect();
+                
+                if member_addresses.is_empty() {
+                    self.status = "No other members in group yet".to_string();
+                    return Command::none();
+                }
+                
+                let username = self.my_username.clone();
+                let group_id_clone = group_id.clone();
+                let fingerprint = self.app_state.get_fingerprint().unwrap_or_default();
+                let encrypted_content = match group_crypto::seal_content(group, &network_payload) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        self.status = e.to_string();
+                        return Command::none();
+                    }
+                };
+                let envelope = network::MessageEnvelope::GroupMessage {
+                    group_id: group_id_clone,
+                    sender_fingerprint: fingerprint,
+                    sender_name: username,
+                    encrypted_content,
+                    timestamp: chrono_time(),
+                    expires_at: new_msg.expires_at.clone(),
+                    message_id: Some(message_id.clone()),
+                };
+                
+                let (sent, failures) = network::NetworkHandle::send_to_group(&member_addresses, envelope);
+                if failures.is_empty() {
+                    self.status = format!("Sent to {} members", sent);
+                } else {
+                    self.status = format!("Sent to {}/{} members", sent, member_addresses.len());
+                }
+                let delivery = if sent > 0 { DeliveryStatus::Sent } else { DeliveryStatus::Failed };
+                if let Some(conv) = self.conversations.get_mut(group_id) {
+                    conversation::update_delivery_status(conv, &message_id, delivery);
+                }
+                self.save_conversations();
+
+                self.snap_to_bottom() // Snap after sending to group
+            } else {
+                self.status = "Group not found".to_string();
+                Command::none()
+            }
+        } else if self.recipient_key_imported {
+            // Direct peer message
+            let peer_addr = self.peer_address.clone().unwrap();
+            let username = self.my_username.clone();
+            // Get fingerprint for adding to local convo
+            let conv_id = self.app_state.get_recipient_fingerprint().unwrap_or_default();
+            if !conv_id.is_empty() {
+                self.add_message(conv_id.clone(), self.peer_username.clone().unwrap_or("Peer".to_string()), new_msg.clone(), Some(peer_addr.clone()));
+            }
+            
+            let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
+            // Logged before it leaves so a crash or an offline peer doesn't lose it
+            let entry = outbox::OutboxEntry {
+                message_id: message_id.clone(),
+                conversation_id: conv_id.clone(),
+                peer_address: peer_addr.clone(),
+                payload: network_payload.clone(),
+                queued_ms: now_ms(),
+            };
+            if let Err(e) = outbox::append(&entry, &my_fp) {
+                tracing::warn!(error = %e, "could not log outgoing message");
+            }
+            // Launch async task to send
+            let app_state = self.app_state.clone();
+            let port = self.listening_port.unwrap_or(network::DEFAULT_PORT);
+            let conv_fp = conv_id.clone();
+            
+            Command::batch(vec![
+                Command::perform(
+                    async move {
+                         let result = send_message_async(app_state, peer_addr, conv_fp, network_payload, username, my_fp, port, message_id.clone()).await;
+                         (message_id, result)
+                    },
+                    move |(message_id, result)| Message::DirectMessageSent(conv_id, message_id, result),
+                ),
+                self.snap_to_bottom()
+            ])
+        } else {
+            self.status = "Import a key or select a group first".to_string();
+            Command::none()
+        }
+    }
+
+    /// Change a group's name/avatar locally and push the change to members
+    fn update_group_metadata(&mut self, group_id: &str, name: &str, avatar_hash: Option<String>) {
+        let renamed = self.change_group(group_id, |group, me, now| group_store::rename_group(group, me, name, avatar_hash, now));
+        if let Some(group_name) = renamed {
+            self.status = format!("Updated group '{}'", group_name);
+        }
+    }
+
+    /// Make an admin-only change to a group as ourselves, then push its name,
+    /// avatar and admins to the other members. Returns the group's name, or
+    /// None (with the reason in the status line) if the change was refused
+    /// or couldn't be signed.
+    fn change_group(
+        &mut self,
+        group_id: &str,
+        change: impl FnOnce(&mut group_store::Group, &str, i64) -> anyhow::Result<()>,
+    ) -> Option<String> {
+        let Ok(Some(stored_key)) = keystore::load_keypair() else {
+            return None;
+        };
+        let keypair = self.app_state.get_keypair();
+        let index = self.groups.iter().position(|g| g.id == group_id)?;
+        // Work on a copy so nothing changes locally unless the update can be signed and sent
+        let mut group = self.groups[index].clone();
+        if let Err(e) = change(&mut group, &stored_key.fingerprint, now_ms()) {
+            self.status = e.to_string();
+            return None;
+        }
+        
+        let others: Vec<String> = group.members.iter()
+            .filter(|m| m.fingerprint != stored_key.fingerprint && !m.address.is_empty())
+            .map(|m| m.address.clone())
+            .collect();
+        let fields = group_auth::metadata_fields(
+            &group.name,
+            group.avatar_hash.as_deref(),
+            group.metadata_updated_ms,
+            &group.admins,
+            &group.creator_fingerprint,
+            group.roles_version,
+        );
+        let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+        let signed = keypair
+            .ok_or_else(|| anyhow::anyhow!("no identity key loaded"))
+            .and_then(|keypair| group_auth::sign(&keypair, group_auth::GroupAction::MetadataUpdate, &group.id, &fields));
+        let signature = match signed {
+            Ok(signature) => signature,
+            Err(e) => {
+                self.status = format!("Couldn't sign the group update: {}", e);
+                return None;
+            }
+        };
+        let update = network::MessageEnvelope::GroupMetadataUpdate {
+            group_id: group.id.clone(),
+            name: group.name.clone(),
+            avatar_hash: group.avatar_hash.clone(),
+            updated_at_ms: group.metadata_updated_ms,
+            sender_fingerprint: stored_key.fingerprint.clone(),
+            admins: group.admins.clone(),
+            owner: group.creator_fingerprint.clone(),
+            roles_version: group.roles_version,
+            signature,
+        };
+        let group_name = group.name.clone();
+        self.groups[index] = group;
+        let _ = network::NetworkHandle::send_to_group(&others, update);
+        
+        let all_groups: Vec<_> = self.groups.iter().cloned().collect();
+        let _ = group_store::save_groups(&all_groups, &stored_key.fingerprint);
+        Some(group_name)
+    }
+    
+    /// Ask the group's admins for their member list, epoch and key, at most
+    /// once per [`GROUP_RESYNC_INTERVAL`]
+    fn request_group_resync(&mut self, group_id: &str) {
+        let now = std::time::Instant::now();
+        if self.group_resyncs.get(group_id).is_some_and(|at| now.duration_since(*at) < GROUP_RESYNC_INTERVAL) {
+            return;
+        }
+        let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
+        let Some(group) = self.groups.iter().find(|g| g.id == group_id) else {
+            return;
+        };
+        let admins: Vec<String> = group.members.iter()
+            .filter(|m| m.fingerprint != my_fp && !m.address.is_empty() && group_store::can_edit_metadata(group, &m.fingerprint))
+            .map(|m| m.address.clone())
+            .collect();
+        if admins.is_empty() {
+            return;
+        }
+        self.group_resyncs.insert(group_id.to_string(), now);
+        let request = network::MessageEnvelope::GroupResyncRequest {
+            group_id: group_id.to_string(),
+            fingerprint: my_fp,
+        };
+        let _ = network::NetworkHandle::send_to_group(&admins, request);
+    }
+    
+    /// Save a newly joined group, announce ourselves to its other members and
+    /// select it. Returns false if the group couldn't be saved.
+    fn join_group(&mut self, group: group_store::Group, my_fingerprint: &str) -> bool {
+        let group_name = group.name.clone();
+        
+        // Save to storage
+        let mut groups = group_store::load_groups(my_fingerprint).unwrap_or_default();
+        groups.push(group.clone());
+        if let Err(e) = group_store::save_groups(&groups, my_fingerprint) {
+            self.status = format!("Failed to save group: {}", e);
+            return false;
+        }
+        
+        // Broadcast join announcement to all OTHER members
+        let my_member_info = group.members.iter()
+            .find(|m| m.fingerprint == my_fingerprint)
+            .cloned();
+        
+        if let Some(me) = my_member_info {
+            let other_members: Vec<String> = group.members.iter()
+                .filter(|m| m.fingerprint != my_fingerprint && !m.address.is_empty())
+                .map(|m| m.address.clone())
+                .collect();
+            
+            if !other_members.is_empty() {
+                let announcement = network::MessageEnvelope::GroupJoinAnnouncement {
+                    group_id: group.id.clone(),
+                    new_member: me,
+                };
+                let (sent, _) = network::NetworkHandle::send_to_group(&other_members, announcement);
+                self.status = format!("Joined '{}' - syncing with {} members", group_name, sent);
+            } else {
+                self.status = format!("Joined '{}'", group_name);
+            }
+        }
+        
+        // Auto-select the group so chat is immediately enabled
+        self.selected_group_id = Some(group.id.clone());
+        self.groups.push(group);
+        true
+    }
+    
+    /// Usernames that can be @mentioned in a group (members plus ourselves)
+    fn group_roster(&self, group_id: &str) -> Vec<String> {
+        let mut roster: Vec<String> = self.groups.iter()
+            .find(|g| g.id == group_id)
+            .map(|g| g.members.iter().map(|m| m.username.clone()).collect())
+            .unwrap_or_default();
+        if !roster.contains(&self.my_username) {
+            roster.push(self.my_username.clone());
+        }
+        roster
+    }
+    
+    /// Ask the sender for any emotes in a received message we don't have yet
+    fn request_missing_emotes(&mut self, emotes: &std::collections::HashMap<String, String>, peer_address: &str) {
+        let missing = self.emote_manager.missing_hashes(emotes);
+        let to_request = self.emote_requests.take_new(missing, std::time::Instant::now());
+        if to_request.is_empty() {
+            return;
+        }
+        
+        let port = self.listening_port.unwrap_or(network::DEFAULT_PORT);
+        let addr = peer_address.to_string();
+        let _ = std::thread::spawn(move || {
+            for hash in to_request {
+                let envelope = network::MessageEnvelope::EmoteRequest { hash, sender_listening_port: Some(port) };
+                let _ = network::NetworkHandle::send_message(&addr, envelope);
+            }
+        });
+    }
+    
+    /// Drop disappearing messages whose time is up and save. Anything on
+    /// screen that refers to a message by position is let go, since the
+    /// positions may have shifted.
+    fn sweep_expired_messages(&mut self) {
+        if conversation::sweep_expired(&mut self.conversations, chrono::Utc::now()) == 0 {
+            return;
+        }
+        self.save_conversations();
+        self.reaction_picker_for_msg = None;
+        self.forward_picker_for_msg = None;
+        self.first_unread = None;
+        if self.show_search {
+            self.refresh_search_hits();
+        }
+    }
+
     fn get_active_messages(&self) -> &[ChatMessage] {
         if let Some(id) = &self.active_conversation_id {
             if let Some(conv) = self.conversations.get(id) {
                 return &conv.messages;
             }
         }
-        &[]
+        &[]
+    }
+    
+    fn get_active_conversation_mut(&mut self) -> Option<&mut Conversation> {
+        if let Some(id) = self.active_conversation_id.as_ref() {
+            return self.conversations.get_mut(id);
+        }
+        None
+    }
+    
+    /// Clear the unread badge for a chat and tell the peer (or every group
+    /// member) how far we've read
+    fn mark_conversation_read(&mut self, conv_id: &str) {
+        let Some(conv) = self.conversations.get_mut(conv_id) else {
+            return;
+        };
+        conv.unread_count = 0;
+        self.save_conversations();
+        self.send_conversation_read(conv_id);
+    }
+
+    /// Post our chat settings to the account's other devices, through our
+    /// own relay mailbox. Nothing to do without a relay.
+    fn publish_settings(&self) {
+        let (Some(relay_url), Some(keypair), Some(port)) = (relay::relay_url(), self.app_state.get_keypair(), self.listening_port) else {
+            return;
+        };
+        let sealed = devices::local_device_id()
+            .and_then(|id| settings_sync::device_id(&id))
+            .and_then(|device_id| settings_sync::seal(&self.conversations, device_id, &keypair));
+        let sealed = match sealed {
+            Ok(sealed) => sealed,
+            Err(e) => {
+                tracing::warn!(error = %e, "could not seal settings for other devices");
+                return;
+            }
+        };
+        let fingerprint = keypair.fingerprint();
+        let envelope = network::MessageEnvelope::SettingsSync { sealed, sender_fingerprint: fingerprint.clone() };
+        let my_address = network::advertised_address(port);
+        let _ = std::thread::spawn(move || {
+            if let Err(e) = relay::submit(&relay_url, &fingerprint, &envelope, &fingerprint, &my_address) {
+                tracing::debug!(error = %e, "settings not posted to relay");
+            }
+        });
+    }
+
+    /// Tell the peer (or every group member) how far we've read a chat
+    fn send_conversation_read(&self, conv_id: &str) {
+        let Some(conv) = self.conversations.get(conv_id) else {
+            return;
+        };
+        let last_read_id = conversation::read_position(conv);
+        let up_to_seq = conv.messages.len() as u64;
+        let peer_address = conv.peer_address.clone();
+        let (Some(my_fp), Some(port)) = (self.app_state.get_fingerprint(), self.listening_port) else {
+            return;
+        };
+        let addresses: Vec<String> = match self.groups.iter().find(|g| g.id == conv_id) {
+            Some(group) => group.members.iter()
+                .filter(|m| m.fingerprint != my_fp && !m.address.is_empty())
+                .map(|m| m.address.clone())
+                .collect(),
+            None => peer_address.into_iter().collect(),
+        };
+        if addresses.is_empty() {
+            return;
+        }
+        let envelope = network::MessageEnvelope::ConversationRead {
+            conversation_id: conv_id.to_string(),
+            up_to_seq,
+            last_read_id,
+            sender_fingerprint: my_fp,
+            sender_listening_port: port,
+        };
+        let _ = std::thread::spawn(move || {
+            let _ = network::NetworkHandle::send_to_group(&addresses, envelope);
+        });
+    }
+
+    fn snap_to_bottom(&self) -> Command<Message> {
+        scrollable::snap_to(self.scroll_id.clone(), scrollable::RelativeOffset::END)
+    }
+
+    /// Scroll to the message at `msg_index` in `conv_id`. Day headers make
+    /// this approximate, but it lands on the message's neighbourhood.
+    fn snap_to_message(&self, conv_id: &str, msg_index: usize) -> Command<Message> {
+        let Some(len) = self.conversations.get(conv_id).map(|c| c.messages.len()) else {
+            return Command::none();
+        };
+        let y = if len > 1 { msg_index.min(len - 1) as f32 / (len - 1) as f32 } else { 1.0 };
+        scrollable::snap_to(self.scroll_id.clone(), scrollable::RelativeOffset { x: 0.0, y })
+    }
+
+    /// Reload the logged messages that failed to send. Ones whose send is
+    /// still in flight are left out so they aren't offered twice.
+    fn refresh_unsent(&mut self, fingerprint: &str) {
+        match outbox::pending(fingerprint) {
+            Ok(entries) => {
+                self.unsent = entries.into_iter().filter(|e| {
+                    let status = self.conversations.get(&e.conversation_id)
+                        .and_then(|c| c.messages.iter().find(|m| m.message_id.as_deref() == Some(e.message_id.as_str())))
+                        .map(|m| m.status);
+                    status != Some(DeliveryStatus::Pending)
+                }).collect();
+            }
+            Err(e) => tracing::warn!(error = %e, "could not read the outbox"),
+        }
+    }
+
+    /// Run the current query through the index, catching it up with the
+    /// history first
+    fn refresh_search_hits(&mut self) {
+        self.search_index_unsaved |= self.search_index.sync(&self.conversations);
+        self.search_hits = self.search_index.search(&self.conversations, &self.search_query, self.search_include_archived);
+    }
+
+    /// Move our own data over to a key we just rotated to
+    fn adopt_rotated_key(&mut self, rotated: &RotatedKey) {
+        // Our data files are keyed by fingerprint; re-save them under the new one
+        self.save_conversations();
+        group_store::apply_identity_rotation(&mut self.groups, &rotated.old_fingerprint, &rotated.new_fingerprint, &rotated.new_public_key);
+        let _ = group_store::save_groups(&self.groups, &rotated.new_fingerprint);
+    }
+
+    /// Re-read everything an import may have replaced, so the next save
+    /// writes the restored data rather than what was in memory before it.
+    /// Returns a warning if the restored history failed its integrity check.
+    fn reload_account_data(&mut self) -> Option<String> {
+        let keypair = keystore::load_keypair()
+            .ok()
+            .flatten()
+            .and_then(|key| cryptochat_crypto_core::pgp::PgpKeyPair::from_secret_key(&key.secret_key_armored).ok())?;
+        let fingerprint = keypair.fingerprint();
+        let (conversations, warning) = load_history(&keypair);
+        self.conversations = conversations;
+        self.history_loaded = true;
+        self.conversation_index = conversation_store::build_index(&self.conversations);
+        (self.search_index, self.search_index_unsaved) = search::load_index(&fingerprint, &self.conversations);
+        self.active_conversation_id = None;
+        self.contacts = request_store::load_simple_contacts().unwrap_or_default();
+        // Groups are otherwise loaded at login
+        if self.app_state.get_keypair().is_some() {
+            self.groups = group_store::load_groups(&fingerprint).unwrap_or_default();
+            self.pending_group_invites = group_store::load_pending_invites(&fingerprint).unwrap_or_default();
+        } else if self.view == View::Onboarding && account_store::account_exists() {
+            // A fresh install now has an account to log in to
+            self.view = View::Login;
+        }
+        warning
     }
-    
-    fn get_active_conversation_mut(&mut self) -> Option<&mut Conversation> {
-        if let Some(id) = self.active_conversation_id.as_ref() {
-            return self.conversations.get_mut(id);
+
+    fn save_conversations(&mut self) {
+        if !self.history_loaded {
+            // Saving now would replace the stored history with what arrived
+            // since startup; it's merged in and saved once loaded
+            self.conversation_index.extend(conversation_store::build_index(&self.conversations));
+            return;
         }
-        None
+        self.search_index_unsaved |= self.search_index.sync(&self.conversations);
+        if let Some(keypair) = self.app_state.get_keypair() {
+            if self.search_index_unsaved {
+                match search::save_index(&self.search_index, &keypair.fingerprint()) {
+                    Ok(()) => self.search_index_unsaved = false,
+                    Err(e) => tracing::error!(error = %e, "failed to save search index"),
+                }
+            }
+            match conversation_store::save_conversations(&self.conversations, &keypair) {
+                Ok(index) => {
+                    self.conversation_index = index;
+                    return;
+                }
+                Err(e) => tracing::error!(error = %e, "failed to save conversations"),
+            }
+        }
+        self.conversation_index = conversation_store::build_index(&self.conversations);
     }
-    
-    fn snap_to_bottom(&self) -> Command<Message> {
-        scrollable::snap_to(self.scroll_id.clone(), scrollable::RelativeOffset::END)
+
+    /// Disappearing timer for a group (from its settings) or a direct chat
+    fn disappearing_timer_for(&self, conversation_id: &str) -> Option<u64> {
+        match self.groups.iter().find(|g| g.id == conversation_id) {
+            Some(group) => group.settings.disappearing_timer_secs,
+            None => self.conversations.get(conversation_id).and_then(|c| c.disappearing_timer_secs),
+        }
     }
 
-    fn save_conversations(&self) {
-        if let Some(fp) = self.app_state.get_fingerprint() {
-            if let Err(e) = conversation_store::save_conversations(&self.conversations, &fp) {
-                eprintln!("Failed to save conversations: {}", e);
+    /// Send our key and current listening port to `addr` so the peer can
+    /// reach us again after a restart, port change or lost key exchange.
+    /// Sent as a signed key re-exchange request when `as_request`, or as the
+    /// answer to one (which must not be a request, or two peers would keep
+    /// answering each other)
+    fn resend_key_to(&mut self, addr: String, as_request: bool) -> Command<Message> {
+        let Some(port) = self.listening_port else {
+            self.status = "Not listening yet; try again in a moment".to_string();
+            return Command::none();
+        };
+        let Some(keypair) = self.app_state.get_keypair() else {
+            self.status = "Log in to resend your key".to_string();
+            return Command::none();
+        };
+        let envelope = if as_request {
+            let kind = cryptochat_messaging::requests::RequestKind::KeyReexchange;
+            match network::MessageEnvelope::request(&keypair, port, &self.my_username, kind, now_ms()) {
+                Ok(envelope) => {
+                    self.awaited_answers.record(&addr, now_ms());
+                    envelope
+                }
+                Err(e) => {
+                    self.status = format!("Couldn't sign the key request: {}", e);
+                    return Command::none();
+                }
             }
-        }
+        } else {
+            match network::MessageEnvelope::accepted_response(&keypair, port, &self.my_username, now_ms()) {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    self.status = format!("Couldn't sign the key response: {}", e);
+                    return Command::none();
+                }
+            }
+        };
+        self.status = format!("Resending key to {}...", addr);
+        Command::perform(
+            async move {
+                let result = tokio::task::spawn_blocking({
+                    let addr = addr.clone();
+                    move || network::NetworkHandle::send_message(&addr, envelope).map_err(|e| e.to_string())
+                })
+                .await
+                .map_err(|e| e.to_string())
+                .and_then(|r| r);
+                (addr, result)
+            },
+            |(addr, result)| Message::KeyResent(addr, result),
+        )
     }
 
-    fn add_message(&mut self, fingerprint: String, name: String, msg: ChatMessage, peer_address: Option<String>) {
+    /// Add a message to a conversation, creating it if needed. A message
+    /// whose id was already added (a resent envelope) is dropped and `false`
+    /// returned; callers still acknowledge it so the sender stops resending.
+    fn add_message(&mut self, fingerprint: String, name: String, mut msg: ChatMessage, peer_address: Option<String>) -> bool {
+        conversation::stamp_expiry(&mut msg, self.disappearing_timer_for(&fingerprint), chrono::Utc::now());
         let active_id = self.active_conversation_id.clone();
         let conv = self.conversations.entry(fingerprint.clone()).or_insert_with(|| {
              Conversation::new(fingerprint.clone(), name, peer_address.clone())
         });
+        if !conv.note_message_id(msg.id) {
+            tracing::debug!(conversation = %logging::short_fp(&fingerprint), "dropped duplicate message");
+            return false;
+        }
         
         // Update peer address if provided
         if let Some(addr) = peer_address {
@@ -2265,6 +5410,7 @@ impl CryptoChat {
         }
         
         self.save_conversations();
+        true
     }
 
     fn view_login(&self) -> Element<Message> {
@@ -2316,9 +5462,9 @@ impl CryptoChat {
         
         let content = column![
             Space::with_height(60),
-            text("🔐").size(48).font(EMOJI_FONT),
-            title.font(EMOJI_FONT),
-            subtitle.font(EMOJI_FONT),
+            text("🔐").size(48).font(fonts::emoji_font()),
+            title.font(fonts::emoji_font()),
+            subtitle.font(fonts::emoji_font()),
             Space::with_height(20),
             username_input,
             password_input,
@@ -2349,8 +5495,8 @@ impl CryptoChat {
         };
         column![
             Space::with_height(Length::FillPortion(1)),
-            text("CryptoChat").size(48).font(EMOJI_FONT),
-            text("Secure P2P Messaging").size(20).font(EMOJI_FONT),
+            text("CryptoChat").size(48).font(fonts::emoji_font()),
+            text("Secure P2P Messaging").size(20).font(fonts::emoji_font()),
             Space::with_height(40),
             generate_btn,
             Space::with_height(20),
@@ -2378,13 +5524,17 @@ impl CryptoChat {
 
         let import_section = if self.recipient_key_imported {
             let peer_name = self.peer_username.as_deref().unwrap_or("Connected");
-            column![text(format!("Connected to: {}", peer_name)).size(11)]
+            column![
+                text(format!("Connected to: {}", peer_name)).size(11),
+                button(text("Test Connection").size(10)).padding([4, 8]).on_press(Message::TestConnection),
+            ].spacing(4)
         } else {
             column![
                 text("Paste peer's key:").size(11),
                 text_input("{...}", &self.key_share_input).on_input(Message::KeyShareInputChanged).padding(6).size(10),
                 row![
                     button(text("Import JSON").size(10)).padding([4, 8]).on_press(Message::ImportKeyShare),
+                    button(text("Test").size(10)).padding([4, 8]).on_press(Message::TestConnection),
                     scan_qr_btn,
                 ].spacing(4),
             ].spacing(4)
@@ -2394,12 +5544,16 @@ impl CryptoChat {
         let theme_btn = button(text(theme_label).size(10)).padding([4, 8]).on_press(Message::ToggleTheme);
         let settings_btn = button(text("⚙ Colors").size(10)).padding([4, 8]).on_press(Message::ToggleSettings);
         let clear_btn = button(text("Clear History").size(10)).padding([4, 8]).on_press(Message::ClearHistory);
+        let search_btn = button(text("🔍 Search").font(fonts::emoji_font()).size(10)).padding([4, 8]).on_press(Message::ToggleSearch);
+        let any_unread = self.conversation_index.values().any(|c| c.unread_count > 0);
+        let mark_all_read_btn = button(text("Mark all read").size(10)).padding([4, 8])
+            .on_press_maybe(any_unread.then_some(Message::MarkAllRead));
 
         // --- 2. Conversations (Active Chats) ---
-        let mut convs: Vec<&Conversation> = self.conversations.values().collect();
-        convs.sort_by(|a, b| b.last_activity.cmp(&a.last_activity));
-        
-        let chats_list: Element<Message> = if convs.is_empty() {
+        let convs = conversation_store::sidebar_order(&self.conversation_index, self.show_archived);
+        let archived_count = self.conversation_index.values().filter(|c| c.archived).count();
+
+        let chats_list: Element<Message> = if convs.is_empty() && archived_count == 0 {
             container(text("No active chats").size(12).style(iced::theme::Text::Color(theme::colors::TEXT_MUTED))).padding(10).into()
         } else {
             column(
@@ -2407,13 +5561,15 @@ impl CryptoChat {
                     let is_active = self.active_conversation_id.as_ref() == Some(&c.id);
                     
                     // Build display text with typing indicator and unread count
-                    let typing_dot = if c.is_typing { " ●" } else { "" };
+                    let typing = self.conversations.get(&c.id).is_some_and(conversation::someone_typing);
+                    let typing_dot = if typing { " ●" } else { "" };
                     let unread_badge = if c.unread_count > 0 { 
                         format!(" ({})", c.unread_count) 
                     } else { 
                         String::new() 
                     };
-                    let display_name = format!("{}{}{}", c.name, typing_dot, unread_badge);
+                    let label = request_store::conversation_label(&self.contacts, &c.id, &c.name);
+                    let display_name = format!("{}{}{}", label, typing_dot, unread_badge);
                     
                     // Use styled container for active/inactive states
                     let item_style: fn(&Theme) -> container::Appearance = if is_active {
@@ -2422,17 +5578,31 @@ impl CryptoChat {
                         |_| theme::conversation_item()
                     };
                     
-                    button(
-                        container(text(display_name).size(12))
-                            .padding([8, 12])
-                            .width(Length::Fill)
-                            .style(item_style)
-                    )
-                    .width(Length::Fill)
-                    .padding(0)
-                    .on_press(Message::SelectConversation(c.id.clone()))
-                    .into()
-                }).collect::<Vec<_>>()
+                    let mute_label = if c.muted { "🔕" } else { "🔔" };
+                    let pin_label = if c.pinned { "📍" } else { "📌" };
+                    let archive_label = if c.archived { "↩" } else { "🗄" };
+                    row![
+                        button(
+                            container(text(display_name).size(12))
+                                .padding([8, 12])
+                                .width(Length::Fill)
+                                .style(item_style)
+                        )
+                        .width(Length::Fill)
+                        .padding(0)
+                        .on_press(Message::SelectConversation(c.id.clone())),
+                        button(text(pin_label).font(fonts::emoji_font()).size(10)).padding([6, 6]).on_press(Message::TogglePin(c.id.clone())),
+                        button(text(mute_label).font(fonts::emoji_font()).size(10)).padding([6, 6]).on_press(Message::ToggleMute(c.id.clone())),
+                        button(text(archive_label).font(fonts::emoji_font()).size(10)).padding([6, 6]).on_press(Message::ToggleArchive(c.id.clone())),
+                    ].spacing(2).align_items(iced::Alignment::Center).into()
+                }).chain((archived_count > 0).then(|| {
+                    let label = if self.show_archived {
+                        "Hide archived".to_string()
+                    } else {
+                        format!("Archived ({})", archived_count)
+                    };
+                    button(text(label).size(10)).padding([4, 8]).on_press(Message::ToggleShowArchived).into()
+                })).collect::<Vec<_>>()
             ).spacing(4).into()
         };
 
@@ -2442,11 +5612,13 @@ impl CryptoChat {
         } else {
             let pending_rows: Vec<Element<Message>> = self.pending_requests.iter().enumerate().map(|(i, req)| {
                 let name = req.sender_name.clone().unwrap_or_else(|| req.sender_fingerprint[..8].to_string());
+                let (what, accept) = req.route.prompt();
                 column![
-                    text(format!("{} wants to chat", name)).size(10),
+                    text(format!("{} {}", name, what)).size(10),
                     row![
-                        button(text("Accept").size(9)).padding([3, 6]).on_press(Message::AcceptRequest(i)),
+                        button(text(accept).size(9)).padding([3, 6]).on_press(Message::AcceptRequest(i)),
                         button(text("Decline").size(9)).padding([3, 6]).on_press(Message::DeclineRequest(i)),
+                        button(text("Block").size(9)).padding([3, 6]).on_press(Message::BlockRequest(i)),
                     ].spacing(4),
                 ].spacing(2).into()
             }).collect();
@@ -2456,21 +5628,62 @@ impl CryptoChat {
             ].spacing(4).into()
         };
 
+        // Group invites
+        let invites_section: Element<Message> = if self.pending_group_invites.is_empty() {
+            Space::with_height(0).into()
+        } else {
+            let invite_rows: Vec<Element<Message>> = self.pending_group_invites.iter().map(|inv| {
+                column![
+                    text(format!("{} invited you to '{}'", inv.creator_name, inv.group_name)).size(10),
+                    row![
+                        button(text("Accept").size(9)).padding([3, 6]).on_press(Message::AcceptGroupInvite(inv.group_id.clone())),
+                        button(text("Decline").size(9)).padding([3, 6]).on_press(Message::DeclineGroupInvite(inv.group_id.clone())),
+                    ].spacing(4),
+                ].spacing(2).into()
+            }).collect();
+            column![
+                text("Group Invites:").size(10),
+                column(invite_rows).spacing(4),
+            ].spacing(4).into()
+        };
+
         // --- 4. Contacts ---
         let contacts_section: Element<Message> = if self.contacts.is_empty() {
             text("No saved contacts").size(9).style(iced::theme::Text::Color(iced::Color::from_rgb(0.6,0.6,0.6))).into()
         } else {
             let contact_rows: Vec<Element<Message>> = self.contacts.iter().enumerate().map(|(i, c)| {
                 row![
-                    button(text(&c.name).size(10))
+                    button(text(c.display_name()).size(10))
                         .padding([4, 8])
                         .on_press(Message::SelectContact(i)),
+                    button(text("✎").size(9))
+                        .padding([4, 5])
+                        .on_press(Message::StartContactAlias(c.fingerprint.clone())),
                     button(text("X").size(9))
                         .padding([4, 6])
                         .on_press(Message::RemoveContact(i)),
                 ].spacing(4).into()
             }).collect();
-            column(contact_rows).spacing(2).into()
+            let contacts_list: Element<Message> = column(contact_rows).spacing(2).into();
+
+            // Alias editor; the synced name stays visible for reference
+            if let Some((ref fingerprint, ref alias)) = self.contact_alias_edit {
+                let synced = self.contacts.iter().find(|c| &c.fingerprint == fingerprint).map(|c| c.name.as_str()).unwrap_or("");
+                column![
+                    contacts_list,
+                    text(format!("Alias for {}", synced)).size(9),
+                    text_input("Alias (blank to clear)", alias)
+                        .on_input(Message::ContactAliasInputChanged)
+                        .on_submit(Message::SubmitContactAlias)
+                        .padding(4).size(9),
+                    row![
+                        button(text("Save").size(9)).padding([3, 8]).on_press(Message::SubmitContactAlias),
+                        button(text("Cancel").size(9)).padding([3, 8]).on_press(Message::CancelContactAlias),
+                    ].spacing(4),
+                ].spacing(4).into()
+            } else {
+                contacts_list
+            }
         };
 
         // --- 5. Groups ---
@@ -2490,15 +5703,50 @@ impl CryptoChat {
             } else {
                  column(
                     self.groups.iter().map(|g| {
+                        let avatar: Element<Message> = match g.avatar_hash.as_ref().and_then(|h| self.emote_manager.get_emote_path(h)) {
+                            Some(path) => iced::widget::Image::new(path)
+                                .width(Length::Fixed(18.0))
+                                .height(Length::Fixed(18.0))
+                                .into(),
+                            None => Space::with_width(0).into(),
+                        };
                         row![
+                            avatar,
                             button(text(&g.name).size(10)).padding([4, 8]).on_press(Message::SelectGroup(g.id.clone())),
+                            button(text(if self.conversations.get(&g.id).is_some_and(|c| c.settings.muted.value) { "🔕" } else { "🔔" }).font(fonts::emoji_font()).size(9))
+                                .padding([3, 5]).on_press(Message::ToggleMute(g.id.clone())),
+                            // Pending join requests show as a count on the editor button
+                            button(text(match g.join_requests.len() {
+                                0 => "✎".to_string(),
+                                n => format!("✎ {}", n),
+                            }).size(9)).padding([3, 5]).on_press(Message::StartGroupRename(g.id.clone())),
                             button(text("📋").size(9)).padding([3, 5]).on_press(Message::CopyGroupKey(g.id.clone())),
+                            button(text("Leave").size(9)).padding([3, 5]).on_press(Message::LeaveGroup(g.id.clone())),
                             button(text("X").size(9)).padding([3, 5]).on_press(Message::RequestDeleteGroup(g.id.clone())),
                         ].spacing(2).into()
                     }).collect::<Vec<_>>()
                 ).spacing(2).into()
             };
             
+            // Group rename editor
+            let groups_list: Element<Message> = if let Some((ref group_id, ref name)) = self.group_rename {
+                column![
+                    groups_list,
+                    text_input("Group name", name)
+                        .on_input(Message::GroupRenameInputChanged)
+                        .on_submit(Message::SubmitGroupRename)
+                        .padding(4).size(9),
+                    row![
+                        button(text("Save").size(9)).padding([3, 8]).on_press(Message::SubmitGroupRename),
+                        button(text("Avatar").size(9)).padding([3, 8]).on_press(Message::PickGroupAvatar(group_id.clone())),
+                        button(text("Cancel").size(9)).padding([3, 8]).on_press(Message::CancelGroupRename),
+                    ].spacing(4),
+                    self.view_group_admins(group_id),
+                ].spacing(4).into()
+            } else {
+                groups_list
+            };
+            
             // Group Join Section
             let join_section: Element<Message> = if self.group_invite_input.is_empty() {
                 column![
@@ -2553,7 +5801,7 @@ impl CryptoChat {
         // Create styled section headers with cyan accent color and emoji support
         let section_header = |title: &str| -> Element<Message> {
             container(
-                text(title).size(11).font(EMOJI_FONT).style(iced::theme::Text::Color(theme::colors::ACCENT_SECONDARY))
+                text(title).size(11).font(fonts::emoji_font()).style(iced::theme::Text::Color(theme::colors::ACCENT_SECONDARY))
             ).padding([8, 0, 4, 0]).into()
         };
         
@@ -2592,16 +5840,22 @@ impl CryptoChat {
              
              // Chats section
              section_header("CHATS"),
+             row![search_btn, mark_all_read_btn].spacing(4),
              chats_list,
              Space::with_height(6),
              
              pending_section,
+             invites_section,
              
              divider(),
              
              // Contacts section
              section_header("CONTACTS"),
              contacts_section,
+             row![
+                 button(text("Export").size(9)).padding([3, 8]).on_press(Message::ExportContacts),
+                 button(text("Import").size(9)).padding([3, 8]).on_press(Message::ImportContacts),
+             ].spacing(4),
              Space::with_height(6),
 
              // Groups section  
@@ -2619,13 +5873,95 @@ impl CryptoChat {
         scrollable(content).into()
     }
 
+    /// Results of searching every conversation; picking one opens the chat at that message
+    fn view_search(&self) -> Element<Message> {
+        let header = row![
+            text_input("Search all chats", &self.search_query)
+                .id(text_input::Id::new(SEARCH_INPUT_ID))
+                .on_input(Message::SearchQueryChanged)
+                .padding(8).size(14)
+                .width(Length::Fill),
+            iced::widget::checkbox("Include archived", self.search_include_archived)
+                .on_toggle(Message::SearchIncludeArchivedToggled)
+                .size(14).text_size(12),
+            button(text("Close").size(12)).padding([6, 10]).on_press(Message::ToggleSearch),
+        ].spacing(8).align_items(iced::Alignment::Center);
+
+        let results: Element<Message> = if self.search_query.trim().is_empty() {
+            text("Type to search messages in every chat").size(12).style(iced::theme::Text::Color(theme::colors::TEXT_MUTED)).into()
+        } else if self.search_hits.is_empty() {
+            text("No messages found").size(12).style(iced::theme::Text::Color(theme::colors::TEXT_MUTED)).into()
+        } else {
+            let rows: Vec<Element<Message>> = self.search_hits.iter().filter_map(|(conv_id, idx)| {
+                let conv = self.conversations.get(conv_id)?;
+                let msg = conv.messages.get(*idx)?;
+                let label = request_store::conversation_label(&self.contacts, conv_id, &conv.name);
+                Some(button(
+                    column![
+                        text(format!("{} · {} · {}", label, msg.sender_name, msg.timestamp)).size(10).style(iced::theme::Text::Color(theme::colors::TEXT_MUTED)),
+                        text(search::snippet(msg)).size(12),
+                    ].spacing(2)
+                )
+                .width(Length::Fill)
+                .padding([6, 10])
+                .on_press(Message::OpenSearchHit(conv_id.clone(), *idx))
+                .into())
+            }).collect();
+            scrollable(column(rows).spacing(4)).height(Length::Fill).into()
+        };
+
+        let count = if self.search_hits.len() >= search::MAX_HITS {
+            format!("Showing the newest {} matches", search::MAX_HITS)
+        } else {
+            format!("{} matches", self.search_hits.len())
+        };
+        column![
+            header,
+            text(count).size(10).style(iced::theme::Text::Color(theme::colors::TEXT_MUTED)),
+            results,
+        ].spacing(8).padding(16).into()
+    }
+
+    fn view_switcher(&self) -> Element<Message> {
+        let header = row![
+            text_input("Jump to a chat or contact", &self.switcher_query)
+                .id(text_input::Id::new(SWITCHER_INPUT_ID))
+                .on_input(Message::SwitcherQueryChanged)
+                .on_submit(Message::SwitcherSubmit)
+                .padding(8).size(14)
+                .width(Length::Fill),
+            button(text("Close").size(12)).padding([6, 10]).on_press(Message::ToggleSwitcher),
+        ].spacing(8).align_items(iced::Alignment::Center);
+
+        let candidates = self.switcher_candidates();
+        let matches = switcher::rank(&self.switcher_query, &candidates);
+        let results: Element<Message> = if candidates.is_empty() {
+            text("No chats or contacts yet").size(12).style(iced::theme::Text::Color(theme::colors::TEXT_MUTED)).into()
+        } else if matches.is_empty() {
+            text(format!("Nothing matches '{}'", self.switcher_query.trim())).size(12).style(iced::theme::Text::Color(theme::colors::TEXT_MUTED)).into()
+        } else {
+            let rows: Vec<Element<Message>> = matches.iter().enumerate().map(|(i, c)| {
+                // Enter opens the first entry
+                let label = if i == 0 { format!("{}  ⏎", c.label) } else { c.label.clone() };
+                button(text(label).size(13))
+                    .width(Length::Fill)
+                    .padding([6, 10])
+                    .on_press(Message::SwitcherPick(c.target.clone()))
+                    .into()
+            }).collect();
+            column(rows).spacing(4).into()
+        };
+
+        column![header, results].spacing(8).padding(16).into()
+    }
+
     fn view_chat(&self) -> Element<Message> {
         // Chat bubbles
         let messages_view: Element<Message> = if self.get_active_messages().is_empty() {
             // Modern empty state with visual hierarchy
             container(
                 column![
-                    text("💬").size(48).font(EMOJI_FONT),
+                    text("💬").size(48).font(fonts::emoji_font()),
                     Space::with_height(16),
                     text("Start Chatting!").size(20).style(iced::theme::Text::Color(theme::colors::TEXT_PRIMARY)),
                     Space::with_height(12),
@@ -2638,9 +5974,22 @@ impl CryptoChat {
                 ].spacing(4).align_items(iced::Alignment::Center)
             ).width(Length::Fill).height(Length::Fill).center_x().center_y().into()
         } else {
-            let bubbles: Vec<Element<Message>> = self.get_active_messages().iter().enumerate().map(|(idx, msg)| {
-                self.render_bubble(msg, idx)
-            }).collect();
+            let messages = self.get_active_messages();
+            let today = chrono::Local::now().date_naive();
+            let mut breaks = conversation::day_breaks(&messages, &chrono::Local).into_iter().peekable();
+            let mut bubbles: Vec<Element<Message>> = Vec::with_capacity(messages.len());
+            for (idx, msg) in messages.iter().enumerate() {
+                if let Some((_, day)) = breaks.next_if(|(start, _)| *start == idx) {
+                    bubbles.push(
+                        container(text(conversation::day_header(day, today)).size(11).style(iced::theme::Text::Color(theme::colors::TEXT_MUTED)))
+                            .width(Length::Fill)
+                            .center_x()
+                            .padding([4, 0])
+                            .into()
+                    );
+                }
+                bubbles.push(self.render_bubble(msg, idx));
+            }
             scrollable(iced::widget::Column::with_children(bubbles).spacing(8).padding(16))
                 .id(self.scroll_id.clone())
                 .width(Length::Fill)
@@ -2651,18 +6000,23 @@ impl CryptoChat {
         // Typing indicator with animated dots - check active conversation's typing state
         let active_typing = self.active_conversation_id.as_ref()
             .and_then(|id| self.conversations.get(id))
-            .map(|conv| (conv.is_typing, conv.name.clone()))
-            .unwrap_or((false, String::new()));
+            .and_then(|conv| {
+                if conv.is_typing {
+                    conversation::typing_label(&[conv.name.as_str()])
+                } else {
+                    let names: Vec<&str> = conv.typing_members.values().map(|(name, _)| name.as_str()).collect();
+                    conversation::typing_label(&names)
+                }
+            });
         
-        let typing_indicator: Element<Message> = if active_typing.0 {
-            let name = &active_typing.1;
+        let typing_indicator: Element<Message> = if let Some(label) = active_typing {
             let dots = match self.typing_dots_phase {
                 0 => "●  ",
                 1 => "● ●",
                 _ => "●●●",
             };
-            let typing_text = format!("  {} {}  {}", dots, name, "is typing");
-            container(text(typing_text).size(11).style(iced::theme::Text::Color(Color::from_rgb(0.6, 0.6, 0.65))).font(EMOJI_FONT))
+            let typing_text = format!("  {} {}", dots, label);
+            container(text(typing_text).size(11).style(iced::theme::Text::Color(Color::from_rgb(0.6, 0.6, 0.65))).font(fonts::emoji_font()))
                 .padding([4, 8])
                 .into()
         } else {
@@ -2674,9 +6028,13 @@ impl CryptoChat {
         let input_area: Element<Message> = if can_send {
             // Action buttons row
             let action_bar: iced::widget::Row<'_, Message> = row![
-                button(text("📎").font(EMOJI_FONT).size(16)).padding([8, 12]).on_press(Message::PickFile),
-                button(text("✨").font(EMOJI_FONT).size(16)).padding([8, 12]).on_press(Message::UploadEmote),
-                button(text("😊 Emoji").font(EMOJI_FONT).size(12)).padding([6, 10]).on_press(Message::ToggleEmojiPicker),
+                button(text("📎").font(fonts::emoji_font()).size(16)).padding([8, 12]).on_press(Message::PickFile),
+                button(text(if self.recorder.is_some() { "⏹" } else { "🎤" }).font(fonts::emoji_font()).size(16))
+                    .padding([8, 12])
+                    .on_press(Message::ToggleVoiceRecording),
+                button(text("✨").font(fonts::emoji_font()).size(16)).padding([8, 12]).on_press(Message::UploadEmote),
+                button(text("Emotes").size(12)).padding([6, 10]).on_press(Message::ToggleEmoteLibrary),
+                button(text("😊 Emoji").font(fonts::emoji_font()).size(12)).padding([6, 10]).on_press(Message::ToggleEmojiPicker),
             ].spacing(6);
             
             // Message input with Send button
@@ -2684,10 +6042,42 @@ impl CryptoChat {
                 text_input("Type a message...", &self.message_input)
                     .on_input(Message::MessageInputChanged)
                     .on_submit(Message::SendMessage)
-                    .padding(12).size(14),
+                    .padding(12).size(self.color_prefs.font_size as f32),
                 button(text("Send ▸").size(13)).padding([10, 20]).on_press(Message::SendMessage),
             ].spacing(8);
             
+            // Character counter near the limit, and what to do once past it
+            let max_chars = self.max_message_chars;
+            let length_row: Element<Message> = match compose::counter(&self.message_input, max_chars) {
+                None => Space::with_height(0).into(),
+                Some(count) => {
+                    let over = compose::check_length(&self.message_input, max_chars);
+                    let color = if over == compose::LengthCheck::Fits { Color::from_rgb(0.6, 0.6, 0.65) } else { Color::from_rgb(0.9, 0.4, 0.4) };
+                    let mut length_row = row![text(count).size(10).style(iced::theme::Text::Color(color))]
+                        .spacing(6)
+                        .align_items(iced::Alignment::Center);
+                    if let compose::LengthCheck::Split(parts) = over {
+                        length_row = length_row.push(
+                            button(text(format!("Split into {} messages", parts)).size(10)).padding([3, 8]).on_press(Message::SendSplitMessage),
+                        );
+                    }
+                    if over != compose::LengthCheck::Fits && self.selected_group_id.is_none() {
+                        length_row = length_row.push(
+                            button(text("Send as file").size(10)).padding([3, 8]).on_press(Message::SendDraftAsFile),
+                        );
+                    }
+                    if over == compose::LengthCheck::SendAsFile && self.selected_group_id.is_some() {
+                        // Groups can't receive files, so there's nothing to offer
+                        length_row = length_row.push(
+                            text(format!("Too long to split; groups can't receive files (about {} characters fit)", max_chars * compose::MAX_SPLIT_PARTS))
+                                .size(10)
+                                .style(iced::theme::Text::Color(color)),
+                        );
+                    }
+                    length_row.into()
+                }
+            };
+            
             // Wrap in styled container
             let input_container_style: fn(&Theme) -> container::Appearance = |_| {
                 container::Appearance {
@@ -2703,7 +6093,7 @@ impl CryptoChat {
             };
             
             container(
-                column![action_bar, message_row].spacing(8).padding([10, 14])
+                column![action_bar, message_row, length_row].spacing(8).padding([10, 14])
             ).width(Length::Fill).style(input_container_style).into()
         } else {
             Space::with_height(0).into()
@@ -2714,7 +6104,7 @@ impl CryptoChat {
             let emojis = ["😀", "😂", "😢", "😎", "🤔", "❤️", "👍", "👎", 
                           "🔥", "⭐", "🎉", "👋", "✅", "❌", "💯", "🙏"];
             let emoji_buttons: Vec<Element<Message>> = emojis.iter().map(|e| {
-                button(text(*e).size(20).font(EMOJI_FONT))
+                button(text(*e).size(20).font(fonts::emoji_font()))
                     .padding([6, 10])
                     .on_press(Message::InsertEmoji(e.to_string()))
                     .into()
@@ -2726,12 +6116,48 @@ impl CryptoChat {
             Space::with_height(0).into()
         };
         
+        // Emote library
+        let emote_library_panel: Element<Message> = if self.show_emote_library {
+            let emotes = self.emote_manager.list_emotes();
+            let items: Vec<Element<Message>> = if emotes.is_empty() {
+                vec![text("No emotes yet - use ✨ to import one").size(11).font(fonts::emoji_font()).into()]
+            } else {
+                emotes.into_iter().map(|(name, hash)| {
+                    let preview: Element<Message> = match self.emote_manager.get_emote_path(&hash) {
+                        Some(path) => iced::widget::Image::new(path)
+                            .width(Length::Fixed(24.0))
+                            .height(Length::Fixed(24.0))
+                            .into(),
+                        None => Space::with_width(24).into(),
+                    };
+                    row![
+                        preview,
+                        text(format!(":{}:", name)).size(12),
+                        Space::with_width(Length::Fill),
+                        button(text("Delete").size(10)).padding([4, 8]).on_press(Message::RemoveEmote(name)),
+                    ].spacing(8).align_items(iced::Alignment::Center).into()
+                }).collect()
+            };
+            let pack_buttons = row![
+                button(text("Import pack").size(10)).padding([4, 8]).on_press(Message::ImportEmotePack),
+                button(text("Export pack").size(10)).padding([4, 8]).on_press(Message::ExportEmotePack),
+            ].spacing(6);
+            container(
+                column![
+                    pack_buttons,
+                    scrollable(column(items).spacing(4)),
+                ].spacing(6).padding(8)
+            ).max_height(240).into()
+        } else {
+            Space::with_height(0).into()
+        };
+        
         // Suggestions
         let emoji_suggestions_panel: Element<Message> = if !self.emoji_suggestions.is_empty() {
             let suggestion_items: Vec<Element<Message>> = self.emoji_suggestions.iter().map(|(name, emoji)| {
                 button(
                     row![
-                        text(*emoji).size(16).font(EMOJI_FONT),
+                        text(*emoji).size(16).font(fonts::emoji_font()),
                         text(format!(":{name}:")).size(12),
                     ].spacing(8)
                 )
@@ -2756,23 +6182,187 @@ impl CryptoChat {
             Space::with_width(0).into()
         };
         
+        let timer_btn: Element<Message> = match self.selected_group_id.as_ref().or(self.active_conversation_id.as_ref()) {
+            Some(conv_id) => {
+                let label = conversation::disappearing_timer_label(self.disappearing_timer_for(conv_id));
+                button(text(format!("Timer: {}", label)).size(10)).padding([4, 8])
+                    .on_press(Message::CycleDisappearingTimer)
+                    .into()
+            }
+            None => Space::with_width(0).into(),
+        };
+        
+        let color_btn: Element<Message> = if self.active_conversation_id.is_some() {
+            row![
+                button(text("🎨").font(fonts::emoji_font()).size(12)).padding([4, 8]).on_press(Message::ToggleConversationColorPicker),
+                button(text("Export").size(10)).padding([4, 8])
+                    .on_press(Message::ExportConversation(conversation_store::ExportFormat::PlainText)),
+                button(text("JSON").size(10)).padding([4, 8])
+                    .on_press(Message::ExportConversation(conversation_store::ExportFormat::Json)),
+            ].spacing(4).into()
+        } else {
+            Space::with_width(0).into()
+        };
+        
+        // Full fingerprint of the contact we're talking to, for out-of-band checks
+        let fingerprint_btn: Element<Message> = if let Some(contact) = self.active_conversation_id.as_ref()
+            .and_then(|id| self.contacts.iter().find(|c| &c.fingerprint == id))
+        {
+            button(text("Copy fingerprint").size(10)).padding([4, 8])
+                .on_press(Message::CopyFingerprint(contact.fingerprint.clone()))
+                .into()
+        } else {
+            Space::with_width(0).into()
+        };
+        
+        // Warn when the contact we're talking to has revoked their key
+        let revoked_warning: Element<Message> = match self.active_conversation_id.as_ref()
+            .and_then(|id| self.contacts.iter().find(|c| &c.fingerprint == id && c.revoked))
+        {
+            Some(contact) => text(format!("⚠ {} revoked this key", contact.name)).size(11)
+                .style(iced::theme::Text::Color(Color::from_rgb(0.9, 0.3, 0.3)))
+                .into(),
+            None => Space::with_width(0).into(),
+        };
+        
+        let disconnect_btn: Element<Message> = if self.recipient_key_imported {
+            button(text("Disconnect").size(10)).padding([4, 8]).on_press(Message::Disconnect).into()
+        } else {
+            Space::with_width(0).into()
+        };
+
+        // Recover a broken session by sending our key again
+        let is_direct_chat = self.active_conversation_id.as_ref()
+            .is_some_and(|id| self.contacts.iter().any(|c| &c.fingerprint == id));
+        let resend_key_btn: Element<Message> = if is_direct_chat || self.recipient_key_imported {
+            button(text("Resend key").size(10)).padding([4, 8]).on_press(Message::ResendKey).into()
+        } else {
+            Space::with_width(0).into()
+        };
+        let unread_here = self.first_unread.as_ref()
+            .is_some_and(|(conv_id, _)| self.active_conversation_id.as_ref() == Some(conv_id));
+        let jump_unread_btn: Element<Message> = if unread_here {
+            button(text("Jump to first unread").size(10)).padding([4, 8]).on_press(Message::JumpToFirstUnread).into()
+        } else {
+            Space::with_width(0).into()
+        };
+        let resend_key_prompt: Element<Message> = match &self.resend_key_address {
+            Some(addr) => row![
+                text_input("Peer address (host:port)", addr)
+                    .on_input(Message::ResendKeyAddressChanged)
+                    .on_submit(Message::SubmitResendKeyAddress)
+                    .padding(4).size(10)
+                    .width(Length::Fixed(180.0)),
+                button(text("Send").size(10)).padding([4, 8]).on_press(Message::SubmitResendKeyAddress),
+                button(text("Cancel").size(10)).padding([4, 8]).on_press(Message::CancelResendKey),
+            ].spacing(4).into(),
+            None => Space::with_width(0).into(),
+        };
+        
         let header_content = row![
             text("Chat").size(18), 
             Space::with_width(8),
             add_contact_btn,
+            disconnect_btn,
+            resend_key_btn,
+            resend_key_prompt,
+            color_btn,
+            fingerprint_btn,
+            timer_btn,
+            jump_unread_btn,
+            revoked_warning,
             Space::with_width(Length::Fill), 
             text(&self.status).size(10)
-        ].padding(10);
+        ].spacing(4).padding(10);
+        
+        // Per-conversation bubble color picker
+        let conversation_color_picker: Element<Message> = if self.show_conversation_color_picker {
+            let swatches = [
+                ("🔴", "#d11a1e"), ("🟠", "#ff9500"), ("🟢", "#32b432"),
+                ("🔵", "#2c7be5"), ("🟣", "#9b59b6"), ("⚫", "#000000"),
+            ];
+            let mut picker = row![text("This chat:").size(11)].spacing(6).align_items(iced::Alignment::Center);
+            for (emoji, hex) in swatches {
+                picker = picker.push(
+                    button(text(emoji).font(fonts::emoji_font())).padding(4)
+                        .on_press(Message::SetConversationBubbleColor(Some(hex.to_string())))
+                );
+            }
+            picker = picker.push(
+                button(text("Use default").size(10)).padding([4, 8]).on_press(Message::SetConversationBubbleColor(None))
+            );
+            container(picker).padding([0, 10]).into()
+        } else {
+            Space::with_height(0).into()
+        };
         
+        // Messages from a crash or a failed send waiting to go out
+        let unsent_banner: Element<Message> = if self.unsent.is_empty() {
+            Space::with_height(0).into()
+        } else {
+            container(
+                row![
+                    text(format!("{} message(s) weren't sent", self.unsent.len())).size(11),
+                    Space::with_width(Length::Fill),
+                    button(text("Resend").size(10)).padding([4, 8]).on_press(Message::ResendUnsent),
+                    button(text("Discard").size(10)).padding([4, 8]).on_press(Message::DiscardUnsent),
+                ].spacing(6).align_items(iced::Alignment::Center)
+            ).padding([0, 10]).into()
+        };
+
         let chat_view = column![
             container(header_content),
+            unsent_banner,
+            conversation_color_picker,
             messages_view,
             typing_indicator,
             emoji_picker,
+            emote_library_panel,
             emoji_suggestions_panel,
             input_area,
         ].width(Length::Fill).height(Length::Fill);
         
+        // Styled modal with dark background
+        let modal_style: fn(&Theme) -> container::Appearance = |_| {
+            container::Appearance {
+                background: Some(iced::Background::Color(Color::from_rgb(0.08, 0.08, 0.14))),
+                text_color: Some(Color::WHITE),
+                border: iced::Border {
+                    color: Color::from_rgba(0.4, 0.3, 0.9, 0.4),
+                    width: 2.0,
+                    radius: 12.0.into(),
+                },
+                shadow: iced::Shadow {
+                    color: Color::from_rgba(0.486, 0.227, 0.929, 0.3),
+                    offset: iced::Vector { x: 0.0, y: 4.0 },
+                    blur_radius: 20.0,
+                },
+            }
+        };
+        
+        // QR code modal
+        if let Some(ref qr) = self.qr_display {
+            let modal = column![
+                text("Scan to add me").size(16),
+                iced::widget::Image::new(qr.clone())
+                    .width(Length::Fixed(320.0))
+                    .height(Length::Fixed(320.0)),
+                text("Valid for 5 minutes").size(11).style(iced::theme::Text::Color(theme::colors::TEXT_SECONDARY)),
+                row![
+                    button(text("Copy")).padding([8, 20]).on_press(Message::CopyQR),
+                    Space::with_width(Length::Fill),
+                    button(text("Close")).padding([8, 20]).on_press(Message::HideQR),
+                ].width(Length::Fixed(320.0)),
+            ].spacing(8).padding(24).align_items(iced::Alignment::Center);
+            
+            return container(container(modal).style(modal_style))
+                .width(Length::Fill)
+                .height(Length::Fill)
+                .center_x()
+                .center_y()
+                .into();
+        }
+        
         // Settings modal
         if self.show_settings {
              let tab_buttons = row![
@@ -2794,14 +6384,14 @@ impl CryptoChat {
                         text(format!("Current: {:.0}°", self.color_prefs.hue)).size(10),
                         text("Select a preset:").size(10),
                         row![
-                            button(text("🔵 Blue").size(12).font(EMOJI_FONT)).padding(4).on_press(Message::SetHue(210.0)),
-                            button(text("🟢 Green").size(12).font(EMOJI_FONT)).padding(4).on_press(Message::SetHue(120.0)),
-                            button(text("🟣 Purple").size(12).font(EMOJI_FONT)).padding(4).on_press(Message::SetHue(280.0)),
+                            button(text("🔵 Blue").size(12).font(fonts::emoji_font())).padding(4).on_press(Message::SetHue(210.0)),
+                            button(text("🟢 Green").size(12).font(fonts::emoji_font())).padding(4).on_press(Message::SetHue(120.0)),
+                            button(text("🟣 Purple").size(12).font(fonts::emoji_font())).padding(4).on_press(Message::SetHue(280.0)),
                         ].spacing(4),
                         row![
-                            button(text("🟠 Orange").size(12).font(EMOJI_FONT)).padding(4).on_press(Message::SetHue(30.0)),
-                            button(text("🔴 Red").size(12).font(EMOJI_FONT)).padding(4).on_press(Message::SetHue(0.0)),
-                            button(text("🩷 Pink").size(12).font(EMOJI_FONT)).padding(4).on_press(Message::SetHue(330.0)),
+                            button(text("🟠 Orange").size(12).font(fonts::emoji_font())).padding(4).on_press(Message::SetHue(30.0)),
+                            button(text("🔴 Red").size(12).font(fonts::emoji_font())).padding(4).on_press(Message::SetHue(0.0)),
+                            button(text("🩷 Pink").size(12).font(fonts::emoji_font())).padding(4).on_press(Message::SetHue(330.0)),
                         ].spacing(4),
                     ].spacing(8).into()
                 }
@@ -2809,25 +6399,25 @@ impl CryptoChat {
                     column![
                         text("Color 1 (start):").size(11),
                         row![
-                            button(text("🔴").font(EMOJI_FONT)).padding(4).on_press(Message::SetGradientColor1("#ff0000".to_string())),
-                            button(text("🟠").font(EMOJI_FONT)).padding(4).on_press(Message::SetGradientColor1("#ff9500".to_string())),
-                            button(text("🟢").font(EMOJI_FONT)).padding(4).on_press(Message::SetGradientColor1("#32b432".to_string())),
-                            button(text("🔵").font(EMOJI_FONT)).padding(4).on_press(Message::SetGradientColor1("#0a84ff".to_string())),
-                            button(text("🟣").font(EMOJI_FONT)).padding(4).on_press(Message::SetGradientColor1("#9b59b6".to_string())),
+                            button(text("🔴").font(fonts::emoji_font())).padding(4).on_press(Message::SetGradientColor1("#ff0000".to_string())),
+                            button(text("🟠").font(fonts::emoji_font())).padding(4).on_press(Message::SetGradientColor1("#ff9500".to_string())),
+                            button(text("🟢").font(fonts::emoji_font())).padding(4).on_press(Message::SetGradientColor1("#32b432".to_string())),
+                            button(text("🔵").font(fonts::emoji_font())).padding(4).on_press(Message::SetGradientColor1("#0a84ff".to_string())),
+                            button(text("🟣").font(fonts::emoji_font())).padding(4).on_press(Message::SetGradientColor1("#9b59b6".to_string())),
                         ].spacing(4),
                         text("Color 2 (end):").size(11),
                         row![
-                            button(text("🔴").font(EMOJI_FONT)).padding(4).on_press(Message::SetGradientColor2("#ff0000".to_string())),
-                            button(text("🟠").font(EMOJI_FONT)).padding(4).on_press(Message::SetGradientColor2("#ff9500".to_string())),
-                            button(text("🟢").font(EMOJI_FONT)).padding(4).on_press(Message::SetGradientColor2("#32b432".to_string())),
-                            button(text("🔵").font(EMOJI_FONT)).padding(4).on_press(Message::SetGradientColor2("#0a84ff".to_string())),
-                            button(text("🟣").font(EMOJI_FONT)).padding(4).on_press(Message::SetGradientColor2("#9b59b6".to_string())),
+                            button(text("🔴").font(fonts::emoji_font())).padding(4).on_press(Message::SetGradientColor2("#ff0000".to_string())),
+                            button(text("🟠").font(fonts::emoji_font())).padding(4).on_press(Message::SetGradientColor2("#ff9500".to_string())),
+                            button(text("🟢").font(fonts::emoji_font())).padding(4).on_press(Message::SetGradientColor2("#32b432".to_string())),
+                            button(text("🔵").font(fonts::emoji_font())).padding(4).on_press(Message::SetGradientColor2("#0a84ff".to_string())),
+                            button(text("🟣").font(fonts::emoji_font())).padding(4).on_press(Message::SetGradientColor2("#9b59b6".to_string())),
                         ].spacing(4),
                     ].spacing(8).into()
                 }
                 _ => {
                     column![
-                        text("🌈 Rainbow Mode").size(14).font(EMOJI_FONT),
+                        text("🌈 Rainbow Mode").size(14).font(fonts::emoji_font()),
                         text("Bubbles cycle through colors!").size(11),
                         row![
                             button(text("Slow")).padding([4, 8]).on_press(Message::SetRainbowSpeed(0.5)),
@@ -2838,24 +6428,6 @@ impl CryptoChat {
                 }
             };
             
-            // Styled modal with dark background
-            let modal_style: fn(&Theme) -> container::Appearance = |_| {
-                container::Appearance {
-                    background: Some(iced::Background::Color(Color::from_rgb(0.08, 0.08, 0.14))),
-                    text_color: Some(Color::WHITE),
-                    border: iced::Border {
-                        color: Color::from_rgba(0.4, 0.3, 0.9, 0.4),
-                        width: 2.0,
-                        radius: 12.0.into(),
-                    },
-                    shadow: iced::Shadow {
-                        color: Color::from_rgba(0.486, 0.227, 0.929, 0.3),
-                        offset: iced::Vector { x: 0.0, y: 4.0 },
-                        blur_radius: 20.0,
-                    },
-                }
-            };
-            
             let modal_header = row![
                 text("Color Settings").size(18).style(iced::theme::Text::Color(theme::colors::TEXT_PRIMARY)),
                 Space::with_width(Length::Fill),
@@ -2870,16 +6442,84 @@ impl CryptoChat {
                 text(&preview_text).size(12).style(iced::theme::Text::Color(theme::colors::TEXT_SECONDARY)),
                 tab_content,
                 Space::with_height(12),
+                text("Text & Bubbles:").size(12).style(iced::theme::Text::Color(theme::colors::ACCENT_SECONDARY)),
+                row![
+                    text(format!("Text size: {}", self.color_prefs.font_size)).size(11).width(Length::Fixed(110.0)),
+                    button(text("-")).padding([2, 10]).on_press(Message::SetFontSize(self.color_prefs.font_size as i32 - 1)),
+                    button(text("+")).padding([2, 10]).on_press(Message::SetFontSize(self.color_prefs.font_size as i32 + 1)),
+                ].spacing(6).align_items(iced::Alignment::Center),
+                row![
+                    text(format!("Corner radius: {:.0}", self.color_prefs.bubble_radius)).size(11).width(Length::Fixed(110.0)),
+                    button(text("-")).padding([2, 10]).on_press(Message::SetBubbleRadius(self.color_prefs.bubble_radius - 2.0)),
+                    button(text("+")).padding([2, 10]).on_press(Message::SetBubbleRadius(self.color_prefs.bubble_radius + 2.0)),
+                ].spacing(6).align_items(iced::Alignment::Center),
                 text("Incoming Bubble Color:").size(12).style(iced::theme::Text::Color(theme::colors::ACCENT_SECONDARY)),
                 row![
                     button(text("Default")).padding([4, 8]).on_press(Message::SetTheirBubbleColor("#2a2a2e".to_string())),
-                    button(text("🔴").font(EMOJI_FONT)).padding(4).on_press(Message::SetTheirBubbleColor("#d11a1e".to_string())),
-                    button(text("🔵").font(EMOJI_FONT)).padding(4).on_press(Message::SetTheirBubbleColor("#2c7be5".to_string())),
-                    button(text("🟢").font(EMOJI_FONT)).padding(4).on_press(Message::SetTheirBubbleColor("#32b432".to_string())),
-                    button(text("🟣").font(EMOJI_FONT)).padding(4).on_press(Message::SetTheirBubbleColor("#9b59b6".to_string())),
-                    button(text("⚫").font(EMOJI_FONT)).padding(4).on_press(Message::SetTheirBubbleColor("#000000".to_string())),
+                    button(text("🔴").font(fonts::emoji_font())).padding(4).on_press(Message::SetTheirBubbleColor("#d11a1e".to_string())),
+                    button(text("🔵").font(fonts::emoji_font())).padding(4).on_press(Message::SetTheirBubbleColor("#2c7be5".to_string())),
+                    button(text("🟢").font(fonts::emoji_font())).padding(4).on_press(Message::SetTheirBubbleColor("#32b432".to_string())),
+                    button(text("🟣").font(fonts::emoji_font())).padding(4).on_press(Message::SetTheirBubbleColor("#9b59b6".to_string())),
+                    button(text("⚫").font(fonts::emoji_font())).padding(4).on_press(Message::SetTheirBubbleColor("#000000".to_string())),
                 ].spacing(8),
                 Space::with_height(16),
+                row![
+                    iced::widget::checkbox("Notifications", self.notification_settings.enabled)
+                        .on_toggle(Message::SetNotificationsEnabled)
+                        .size(14).text_size(12),
+                    iced::widget::checkbox("Sound", self.notification_settings.sound)
+                        .on_toggle(Message::SetNotificationSound)
+                        .size(14).text_size(12),
+                    iced::widget::checkbox("Auto-accept contacts", self.request_settings.auto_accept_contacts)
+                        .on_toggle(Message::SetAutoAcceptContacts)
+                        .size(14).text_size(12),
+                    button(text(format!("Unblock all ({})", self.request_settings.blocked_fingerprints.len())).size(11))
+                        .padding([4, 8])
+                        .on_press_maybe((!self.request_settings.blocked_fingerprints.is_empty()).then_some(Message::UnblockAll)),
+                ].spacing(16),
+                row![
+                    button(text("Import theme").size(11)).padding([4, 8]).on_press(Message::ImportTheme),
+                    button(text("Export theme").size(11)).padding([4, 8]).on_press(Message::ExportTheme),
+                ].spacing(8),
+                row![
+                    text_input("Password", &self.rotation_password)
+                        .on_input(Message::RotationPasswordChanged)
+                        .secure(true)
+                        .padding(4).size(11)
+                        .width(Length::Fixed(140.0)),
+                    button(text("Rotate my key").size(11)).padding([4, 8]).on_press(Message::RotateMyKey),
+                    button(text("Revoke my key (compromised)").size(11)).padding([4, 8])
+                        .on_press_maybe((!self.confirm_revoke).then_some(Message::RevokeMyKey)),
+                ].spacing(8).align_items(iced::Alignment::Center),
+                if self.confirm_revoke {
+                    row![
+                        text("Revoke and replace your key? Enter your password above.").size(11)
+                            .style(iced::theme::Text::Color(theme::colors::TEXT_SECONDARY)),
+                        button(text("Confirm revoke").size(11)).padding([4, 8]).on_press(Message::ConfirmRevokeMyKey),
+                        button(text("Cancel").size(11)).padding([4, 8]).on_press(Message::CancelRevokeMyKey),
+                    ].spacing(8).align_items(iced::Alignment::Center)
+                } else {
+                    row![]
+                },
+                row![
+                    text_input("New password", &self.new_password)
+                        .on_input(Message::NewPasswordChanged)
+                        .secure(true)
+                        .padding(4).size(11)
+                        .width(Length::Fixed(140.0)),
+                    button(text("Change password").size(11)).padding([4, 8]).on_press(Message::ChangePassword),
+                ].spacing(8).align_items(iced::Alignment::Center),
+                row![
+                    text_input("Archive passphrase", &self.backup_password)
+                        .on_input(Message::BackupPasswordChanged)
+                        .secure(true)
+                        .padding(4).size(11)
+                        .width(Length::Fixed(140.0)),
+                    button(text("Export all data").size(11)).padding([4, 8]).on_press(Message::ExportAllData),
+                    button(text("Import all data").size(11)).padding([4, 8]).on_press(Message::ImportAllData),
+                ].spacing(8).align_items(iced::Alignment::Center),
+                text("Devices:").size(12).style(iced::theme::Text::Color(theme::colors::ACCENT_SECONDARY)),
+                self.view_devices(),
                 row![
                     button(text("Cancel")).padding([8, 20]).on_press(Message::ToggleSettings),
                     Space::with_width(Length::Fill),
@@ -2903,30 +6543,132 @@ impl CryptoChat {
         }
     }
     
+    /// Devices on this account with a revoke button for each other active one
+    fn view_devices(&self) -> Element<Message> {
+        let Some(list) = &self.device_list else {
+            return text("Loading...").size(11).into();
+        };
+        let format_ms = |ms: i64| {
+            chrono::DateTime::from_timestamp_millis(ms)
+                .map(|t| t.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default()
+        };
+        column(
+            list.devices.iter().map(|d| {
+                let name = if d.device_id == list.this_device {
+                    "This device".to_string()
+                } else {
+                    d.device_id[..8.min(d.device_id.len())].to_string()
+                };
+                let details = match d.revoked_ms {
+                    Some(ms) => format!("revoked {}", format_ms(ms)),
+                    None => format!("added {}, last seen {}", format_ms(d.first_seen_ms), format_ms(d.last_seen_ms)),
+                };
+                let action: Element<Message> = if d.is_revoked() || d.device_id == list.this_device {
+                    Space::with_width(0).into()
+                } else {
+                    button(text("Revoke").size(10)).padding([3, 6]).on_press(Message::RevokeDevice(d.device_id.clone())).into()
+                };
+                row![
+                    text(name).size(11).width(Length::Fixed(90.0)),
+                    text(details).size(10).style(iced::theme::Text::Color(theme::colors::TEXT_MUTED)).width(Length::Fill),
+                    action,
+                ].spacing(6).align_items(iced::Alignment::Center).into()
+            }).collect::<Vec<_>>()
+        ).spacing(4).into()
+    }
+
+    /// Members of a group with their role, plus admin controls for admins
+    fn view_group_admins(&self, group_id: &str) -> Element<Message> {
+        let Some(group) = self.groups.iter().find(|g| g.id == group_id) else {
+            return Space::with_height(0).into();
+        };
+        let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
+        let i_am_admin = group_store::can_edit_metadata(group, &my_fp);
+        let requests = group.join_requests.iter().filter(|_| i_am_admin).map(|m| {
+            row![
+                text(format!("{} wants to join", m.username)).size(9).width(Length::Fill),
+                button(text("Approve").size(8)).padding([2, 5])
+                    .on_press(Message::ApproveGroupJoin(group.id.clone(), m.fingerprint.clone())),
+                button(text("Deny").size(8)).padding([2, 5])
+                    .on_press(Message::DenyGroupJoin(group.id.clone(), m.fingerprint.clone())),
+            ].spacing(2).align_items(iced::Alignment::Center).into()
+        });
+        column(
+            requests.chain(group.members.iter().map(|m| {
+                let is_admin = group_store::can_edit_metadata(group, &m.fingerprint);
+                let role = if group.creator_fingerprint == m.fingerprint {
+                    "owner"
+                } else if is_admin {
+                    "admin"
+                } else {
+                    "member"
+                };
+                let mut entry = row![text(format!("{} ({})", m.username, role)).size(9).width(Length::Fill)]
+                    .spacing(2)
+                    .align_items(iced::Alignment::Center);
+                if i_am_admin {
+                    let toggle = if is_admin { "Demote" } else { "Make admin" };
+                    entry = entry.push(
+                        button(text(toggle).size(8)).padding([2, 5])
+                            .on_press(Message::SetGroupAdmin(group.id.clone(), m.fingerprint.clone(), !is_admin)),
+                    );
+                    if group.creator_fingerprint == my_fp && m.fingerprint != my_fp {
+                        entry = entry.push(
+                            button(text("Make owner").size(8)).padding([2, 5])
+                                .on_press(Message::TransferGroupOwnership(group.id.clone(), m.fingerprint.clone())),
+                        );
+                    }
+                }
+                entry.into()
+            })).collect::<Vec<_>>()
+        ).spacing(2).into()
+    }
+
     fn render_bubble(&self, msg: &ChatMessage, msg_index: usize) -> Element<Message> {
         let name_label = if msg.is_mine {
             format!("{} (You)", msg.sender_name)
         } else {
             msg.sender_name.clone()
         };
+        let name_label = if msg.forwarded { format!("{} · Forwarded", name_label) } else { name_label };
         
         // Add read receipt indicators for sent messages
         let status_indicator = if msg.is_mine {
-            // Compare timestamps to determine if message was read
-            let is_read = self.peer_last_read.as_ref()
-                .map(|lr| lr >= &msg.timestamp)
-                .unwrap_or(false);
-            if is_read {
-                " [read]" // Double check = read
-            } else {
-                " [sent]"  // Single check = sent
+            let active_conv = self.active_conversation_id.as_ref().and_then(|id| self.conversations.get(id));
+            let group = self.selected_group_id.as_ref().and_then(|id| self.groups.iter().find(|g| &g.id == id));
+            let summary = active_conv.zip(group).and_then(|(conv, group)| {
+                let my_fp = self.app_state.get_fingerprint().unwrap_or_default();
+                let members: Vec<&str> = group.members.iter()
+                    .map(|m| m.fingerprint.as_str())
+                    .filter(|fp| *fp != my_fp)
+                    .collect();
+                conversation::group_receipt_summary(&conversation::member_receipts(conv, msg, &members))
+            });
+            let read_by = active_conv.map(|c| conversation::read_by_count(c, msg)).unwrap_or(0);
+            match summary {
+                Some(summary) => format!(" [{}]", summary),
+                // Group we no longer have the member list for
+                None if self.selected_group_id.is_some() && read_by > 0 => format!(" [read by {}]", read_by),
+                None => msg.status.indicator().to_string(),
             }
         } else {
-            ""
+            String::new()
         };
         
         // Build content based on whether this is an image message
-        let bubble_content: Element<Message> = if let Some(ref image_bytes) = msg.image_data {
+        let bubble_content: Element<Message> = if let Some(clip) = &msg.voice {
+            column![
+                text(&name_label).size(11),
+                button(text(format!("▶ {}", voice::format_duration(clip.duration_ms))).font(fonts::emoji_font()).size(13))
+                    .padding([6, 12])
+                    .on_press(Message::PlayVoice(msg_index)),
+                row![
+                    text(&msg.timestamp).size(9),
+                    text(status_indicator).size(9),
+                ].spacing(4),
+            ].spacing(3).into()
+        } else if let Some(ref image_bytes) = msg.image_data {
             // Render inline image with Save button
             let img_handle = iced::widget::image::Handle::from_memory(image_bytes.clone());
             let img_widget = iced::widget::Image::new(img_handle)
@@ -2944,10 +6686,31 @@ impl CryptoChat {
                 ].spacing(4),
             ].spacing(3).into()
         } else {
-            // Regular text message - use EMOJI_FONT for emoji support
+            // Regular text message - use fonts::emoji_font() for emoji support
+            let font_size = self.color_prefs.font_size as f32;
+            let mentions_found = self.selected_group_id.as_ref()
+                .map(|gid| mentions::extract_mentions(&msg.content, &self.group_roster(gid)))
+                .unwrap_or_default();
+            let content_text: Element<Message> = if mentions_found.is_empty() {
+                text(&msg.content).size(font_size).font(fonts::emoji_font()).into()
+            } else {
+                // Highlight @mentions
+                let segments: Vec<Element<Message>> = mentions::split_mentions(&msg.content, &mentions_found)
+                    .into_iter()
+                    .map(|(segment, is_mention)| {
+                        let t = text(segment).size(font_size).font(fonts::emoji_font());
+                        if is_mention {
+                            t.style(iced::theme::Text::Color(theme::colors::ACCENT_SECONDARY)).into()
+                        } else {
+                            t.into()
+                        }
+                    })
+                    .collect();
+                row(segments).into()
+            };
             let mut content_col = column![
                 text(&name_label).size(11),
-                text(&msg.content).size(14).font(EMOJI_FONT),
+                content_text,
             ];
             
             // Render custom emotes below
@@ -2963,7 +6726,13 @@ impl CryptoChat {
                 
                 let mut emotes_row = row![].spacing(6);
                 for (_, hash) in ordered_emotes {
-                    if let Some(path) = self.emote_manager.get_emote_path(hash) {
+                    if let Some(animation) = self.emote_manager.get_animation(hash) {
+                        emotes_row = emotes_row.push(
+                             iced::widget::Image::new(animation.frame_at(self.emote_clock_ms))
+                                 .width(Length::Fixed(32.0))
+                                 .height(Length::Fixed(32.0))
+                        );
+                    } else if let Some(path) = self.emote_manager.get_emote_path(hash) {
                         emotes_row = emotes_row.push(
                              iced::widget::Image::new(path)
                                  .width(Length::Fixed(32.0))
@@ -2986,37 +6755,63 @@ impl CryptoChat {
         // Uses modern bubble styles with glow effects
         // For gradient mode: alternate between color1 (even) and color2 (odd)
         let is_gradient = matches!(&self.color_prefs.bubble_style, color_store::BubbleStyle::Gradient { .. });
-        let bubble_style: fn(&Theme) -> container::Appearance = if msg.is_mine {
-            if is_gradient && msg_index % 2 == 1 {
-                |_| theme::my_bubble_gradient2()
-            } else {
-                |_| theme::modern_bubble_mine() // Enhanced with glow shadow
-            }
-        } else {
-            |_| theme::modern_bubble_theirs() // Glass effect with border
-        };
+        let conversation_color = self.active_conversation_id.as_ref()
+            .and_then(|id| self.conversations.get(id))
+            .and_then(|conv| conv.bubble_color.as_deref())
+            .filter(|c| color_store::hex_to_rgb(c).is_some());
         
         let bubble = container(bubble_content)
             .padding([10, 16])
-            .max_width(500) // Max width for responsive layout
-            .style(bubble_style);
+            .max_width(500); // Max width for responsive layout
+        let radius = self.color_prefs.bubble_radius;
+        let bubble = match conversation_color {
+            // Per-conversation override wins over the global style
+            Some(color) if msg.is_mine => {
+                let resolved = color_store::resolve_bubble_color(Some(color), &self.color_prefs);
+                let (r, g, b) = color_store::hex_to_rgb(&resolved).unwrap_or((0.49, 0.23, 0.93));
+                bubble.style(move |_: &Theme| {
+                    let mut appearance = theme::modern_bubble_color(r, g, b);
+                    appearance.border.radius = radius.into();
+                    appearance
+                })
+            }
+            _ => {
+                let bubble_style: fn() -> container::Appearance = if msg.is_mine {
+                    if is_gradient && msg_index % 2 == 1 {
+                        theme::my_bubble_gradient2
+                    } else {
+                        theme::modern_bubble_mine // Enhanced with glow shadow
+                    }
+                } else {
+                    theme::modern_bubble_theirs // Glass effect with border
+                };
+                bubble.style(move |_: &Theme| {
+                    let mut appearance = bubble_style();
+                    appearance.border.radius = radius.into();
+                    appearance
+                })
+            }
+        };
         
         // Build reactions display row (if any reactions exist)
         // Discord-style: group same emojis and show count as pills
         let reactions_display: Element<Message> = if !msg.reactions.is_empty() {
             // Group reactions by emoji and count
-            let mut emoji_counts: std::collections::HashMap<&str, usize> = std::collections::HashMap::new();
-            for (emoji, _) in &msg.reactions {
-                *emoji_counts.entry(emoji.as_str()).or_insert(0) += 1;
-            }
+            let emoji_counts = conversation::reaction_counts(msg);
+            
+            // Hovering any pill lists who reacted, grouped by emoji
+            let reactors = conversation::reactor_summary(msg);
             
             // Create pill buttons for each emoji+count
             let pills: Vec<Element<Message>> = emoji_counts.iter().map(|(emoji, count)| {
                 // Always show count like Discord (e.g. "❤️ 1")
                 let label = format!("{} {}", emoji, count);
                 
-                container(text(label).size(12).font(EMOJI_FONT))
+                let pill = container(text(label).size(12).font(fonts::emoji_font()))
                     .padding([4, 8]) // Slightly more padding
+                    .style(theme::reaction_pill);
+                tooltip(pill, text(&reactors).size(11).font(fonts::emoji_font()), tooltip::Position::Top)
+                    .padding(6)
                     .style(theme::reaction_pill)
                     .into()
             }).collect();
@@ -3029,22 +6824,52 @@ impl CryptoChat {
         // Reaction picker (if open for this message)
         let picker: Element<Message> = if self.reaction_picker_for_msg == Some(msg_index) {
             let emojis = ["❤️", "👍", "😂", "😮", "😢", "🔥"];
-            let buttons: Vec<Element<Message>> = emojis.iter().map(|e| {
-                button(text(*e).font(EMOJI_FONT).size(18))
+            let mut buttons: Vec<Element<Message>> = emojis.iter().map(|e| {
+                button(text(*e).font(fonts::emoji_font()).size(18))
                     .padding([4, 8])
                     .on_press(Message::AddReaction(msg_index, e.to_string()))
                     .into()
             }).collect();
+            buttons.push(
+                button(text("Copy").size(11))
+                    .padding([6, 8])
+                    .on_press(Message::CopyMessageText(msg_index))
+                    .into()
+            );
+            buttons.push(
+                button(text("Forward").size(11))
+                    .padding([6, 8])
+                    .on_press(Message::ForwardMessage(msg_index))
+                    .into()
+            );
             row(buttons).spacing(4).into()
         } else {
             Space::with_height(0).into()
         };
+
+        // Forward target picker: every contact and group except this chat
+        let forward_picker: Element<Message> = if self.forward_picker_for_msg == Some(msg_index) {
+            let active = self.active_conversation_id.as_deref();
+            let targets = self.contacts.iter()
+                .map(|c| (c.fingerprint.clone(), c.name.clone()))
+                .chain(self.groups.iter().map(|g| (g.id.clone(), format!("# {}", g.name))))
+                .filter(|(id, _)| Some(id.as_str()) != active);
+            let mut buttons: Vec<Element<Message>> = vec![text("Forward to:").size(11).into()];
+            buttons.extend(targets.map(|(id, name)| {
+                button(text(name).size(11)).padding([4, 8]).on_press(Message::ForwardTo(id)).into()
+            }));
+            buttons.push(button(text("Cancel").size(11)).padding([4, 8]).on_press(Message::CancelForward).into());
+            row(buttons).spacing(4).align_items(iced::Alignment::Center).into()
+        } else {
+            Space::with_height(0).into()
+        };
         
-        // Combine bubble + reactions + picker
+        // Combine bubble + reactions + pickers
         let bubble_with_reactions = column![
             bubble,
             reactions_display,
             picker,
+            forward_picker,
         ].spacing(2);
         
         // Wrap with mouse_area for right-click reaction picker
@@ -3081,17 +6906,20 @@ fn main() -> iced::Result {
         }
     }
     INSTANCE_ID.set(instance_id).ok();
+    // Best-effort: the app works without a log file
+    let _ = logging::init();
+    tracing::info!(version = env!("CARGO_PKG_VERSION"), instance = ?instance_id, "starting");
     
-    // Load Segoe UI Emoji font for emoji support
-    let emoji_font_bytes: &'static [u8] = include_bytes!("C:/Windows/Fonts/seguiemj.ttf");
-    
+    // Found at runtime; without one emoji fall back to the default font
+    let emoji_font = fonts::load_emoji_font();
+
     CryptoChat::run(Settings {
         window: iced::window::Settings {
             size: iced::Size::new(900.0, 650.0),
             min_size: Some(iced::Size::new(700.0, 450.0)),
             ..Default::default()
         },
-        fonts: vec![std::borrow::Cow::Borrowed(emoji_font_bytes)],
+        fonts: emoji_font.into_iter().map(std::borrow::Cow::Owned).collect(),
         ..Default::default()
     })
 }
@@ -0,0 +1,125 @@
+//! Mentions - @username detection for group chats
+//!
+//! Matches `@name` tokens against a group's member roster. Names may contain
+//! spaces, so the longest matching member name wins.
+
+/// A mention found in a message
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mention {
+    /// Byte offset of the '@'
+    pub start: usize,
+    /// Byte offset just past the name
+    pub end: usize,
+    /// Roster name that was matched (roster spelling)
+    pub username: String,
+}
+
+/// Find all `@member` mentions in `text` for the given roster
+pub fn extract_mentions(text: &str, roster: &[String]) -> Vec<Mention> {
+    let mut mentions = Vec::new();
+    let mut search_from = 0;
+
+    while let Some(offset) = text[search_from..].find('@') {
+        let at = search_from + offset;
+        let after = at + 1;
+        search_from = after;
+
+        // '@' must start a word (avoid matching emails like a@b)
+        let at_word_start = text[..at].chars().next_back().map_or(true, |c| c.is_whitespace());
+        if !at_word_start {
+            continue;
+        }
+
+        let rest = &text[after..];
+        let best = roster
+            .iter()
+            .filter(|name| !name.is_empty())
+            .filter(|name| {
+                rest.get(..name.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(name))
+                    && rest[name.len()..].chars().next().map_or(true, |c| !c.is_alphanumeric() && c != '_')
+            })
+            .max_by_key(|name| name.len());
+
+        if let Some(name) = best {
+            let end = after + name.len();
+            mentions.push(Mention { start: at, end, username: name.clone() });
+            search_from = end;
+        }
+    }
+
+    mentions
+}
+
+/// Check whether `username` is mentioned in `text`
+pub fn mentions_user(text: &str, roster: &[String], username: &str) -> bool {
+    extract_mentions(text, roster)
+        .iter()
+        .any(|m| m.username.eq_ignore_ascii_case(username))
+}
+
+/// Split `text` into (segment, is_mention) pieces for rendering
+pub fn split_mentions<'a>(text: &'a str, mentions: &[Mention]) -> Vec<(&'a str, bool)> {
+    let mut segments = Vec::new();
+    let mut pos = 0;
+    for mention in mentions {
+        if mention.start > pos {
+            segments.push((&text[pos..mention.start], false));
+        }
+        segments.push((&text[mention.start..mention.end], true));
+        pos = mention.end;
+    }
+    if pos < text.len() {
+        segments.push((&text[pos..], false));
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roster() -> Vec<String> {
+        vec!["alice".to_string(), "Bob".to_string(), "Bob Smith".to_string(), "carol_99".to_string()]
+    }
+
+    #[test]
+    fn finds_simple_mentions() {
+        let mentions = extract_mentions("hey @alice and @carol_99!", &roster());
+        let names: Vec<&str> = mentions.iter().map(|m| m.username.as_str()).collect();
+        assert_eq!(names, vec!["alice", "carol_99"]);
+        assert_eq!((mentions[0].start, mentions[0].end), (4, 10));
+    }
+
+    #[test]
+    fn prefers_longest_name_with_spaces() {
+        let mentions = extract_mentions("@Bob Smith can you check?", &roster());
+        assert_eq!(mentions.len(), 1);
+        assert_eq!(mentions[0].username, "Bob Smith");
+
+        let mentions = extract_mentions("@bob said so", &roster());
+        assert_eq!(mentions[0].username, "Bob");
+    }
+
+    #[test]
+    fn ignores_unknown_names_and_partial_words() {
+        assert!(extract_mentions("@dave hi", &roster()).is_empty());
+        assert!(extract_mentions("@alicexyz", &roster()).is_empty());
+        assert!(extract_mentions("mail me at me@alice", &roster()).is_empty());
+    }
+
+    #[test]
+    fn detects_local_user_mention() {
+        assert!(mentions_user("ping @ALICE", &roster(), "alice"));
+        assert!(!mentions_user("ping @Bob", &roster(), "alice"));
+    }
+
+    #[test]
+    fn splits_segments_for_highlighting() {
+        let text = "hi @alice, see @Bob";
+        let mentions = extract_mentions(text, &roster());
+        assert_eq!(
+            split_mentions(text, &mentions),
+            vec![("hi ", false), ("@alice", true), (", see ", false), ("@Bob", true)]
+        );
+    }
+}
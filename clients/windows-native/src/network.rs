@@ -1,15 +1,271 @@
 //! P2P networking with usernames and channel-based message delivery
 
-use anyhow::Result;
-use std::net::{TcpListener, TcpStream};
+use anyhow::{bail, Result};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use std::ops::RangeInclusive;
 use std::io::{Read, Write};
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex, OnceLock, PoisonError};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use serde::{Serialize, Deserialize};
 use tokio::sync::mpsc;
+use crate::rate_limit::{Admission, InboundLimiter};
 
 pub const DEFAULT_PORT: u16 = 62780;
 
+/// Default cap on a serialized envelope (bytes)
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+/// Default cap for envelopes that carry file or emote data
+pub const DEFAULT_MAX_FILE_BYTES: usize = 64 * 1024 * 1024;
+
+static MAX_MESSAGE_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_MESSAGE_BYTES);
+static MAX_FILE_BYTES: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_FILE_BYTES);
+
+/// Size caps enforced on both sending and receiving
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeLimits {
+    pub max_message_bytes: usize,
+    /// Applies to `FileMessage`, `VoiceMessage` and `EmoteData` instead of `max_message_bytes`
+    pub max_file_bytes: usize,
+}
+
+impl Default for SizeLimits {
+    fn default() -> Self {
+        Self { max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES, max_file_bytes: DEFAULT_MAX_FILE_BYTES }
+    }
+}
+
+impl SizeLimits {
+    /// Defaults, overridden by CRYPTOCHAT_MAX_MESSAGE_BYTES / CRYPTOCHAT_MAX_FILE_BYTES
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().and_then(|v| v.parse().ok());
+        let defaults = Self::default();
+        Self {
+            max_message_bytes: var("CRYPTOCHAT_MAX_MESSAGE_BYTES").unwrap_or(defaults.max_message_bytes),
+            max_file_bytes: var("CRYPTOCHAT_MAX_FILE_BYTES").unwrap_or(defaults.max_file_bytes),
+        }
+    }
+
+    /// Largest frame we'll read before knowing what it contains
+    fn max_frame_bytes(&self) -> usize {
+        self.max_message_bytes.max(self.max_file_bytes)
+    }
+
+    fn limit_for(&self, envelope: &MessageEnvelope) -> usize {
+        match envelope {
+            MessageEnvelope::FileMessage { .. }
+            | MessageEnvelope::VoiceMessage { .. }
+            | MessageEnvelope::EmoteData { .. } => self.max_file_bytes,
+            _ => self.max_message_bytes,
+        }
+    }
+
+    /// Check a serialized envelope of `len` bytes against its limit
+    pub fn check(&self, envelope: &MessageEnvelope, len: usize) -> Result<()> {
+        let limit = self.limit_for(envelope);
+        if len > limit {
+            bail!("Message too large: {} bytes (limit {} bytes)", len, limit);
+        }
+        Ok(())
+    }
+}
+
+/// Apply new size limits to all later sends and receives
+pub fn set_size_limits(limits: SizeLimits) {
+    MAX_MESSAGE_BYTES.store(limits.max_message_bytes, Ordering::Relaxed);
+    MAX_FILE_BYTES.store(limits.max_file_bytes, Ordering::Relaxed);
+}
+
+pub fn size_limits() -> SizeLimits {
+    SizeLimits {
+        max_message_bytes: MAX_MESSAGE_BYTES.load(Ordering::Relaxed),
+        max_file_bytes: MAX_FILE_BYTES.load(Ordering::Relaxed),
+    }
+}
+
+/// Ports tried by default after the preferred one
+pub const DEFAULT_PORT_RANGE: RangeInclusive<u16> = DEFAULT_PORT..=DEFAULT_PORT + 19;
+
+/// Where we listen for peers and what address we hand out for it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListenConfig {
+    /// Interface to bind: 127.0.0.1 keeps us to this machine, 0.0.0.0 opens us to the LAN
+    pub bind_address: IpAddr,
+    /// Ports we may listen on, tried in order
+    pub port_range: RangeInclusive<u16>,
+    /// Host put in key shares, for when peers reach us through NAT or DNS
+    pub advertised_host: Option<String>,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        Self { bind_address: IpAddr::V4(Ipv4Addr::LOCALHOST), port_range: DEFAULT_PORT_RANGE, advertised_host: None }
+    }
+}
+
+impl ListenConfig {
+    /// Defaults, overridden by CRYPTOCHAT_BIND_ADDRESS, CRYPTOCHAT_PORT_RANGE
+    /// (`62780-62799` or a single port) and CRYPTOCHAT_ADVERTISE_HOST
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok().filter(|v| !v.trim().is_empty());
+        let defaults = Self::default();
+        Self {
+            bind_address: var("CRYPTOCHAT_BIND_ADDRESS").and_then(|v| v.trim().parse().ok()).unwrap_or(defaults.bind_address),
+            port_range: var("CRYPTOCHAT_PORT_RANGE").and_then(|v| parse_port_range(&v)).unwrap_or(defaults.port_range),
+            advertised_host: var("CRYPTOCHAT_ADVERTISE_HOST").map(|v| v.trim().to_string()),
+        }
+    }
+
+    /// Ports to try: the one from last run if it's in range, then the range in order
+    fn candidate_ports(&self, preferred: Option<u16>) -> Vec<u16> {
+        let preferred = preferred.filter(|p| self.port_range.contains(p));
+        preferred.into_iter().chain(self.port_range.clone().filter(|p| Some(*p) != preferred)).collect()
+    }
+
+    /// Bind the first free candidate port
+    fn bind(&self, preferred: Option<u16>) -> Result<TcpListener> {
+        self.candidate_ports(preferred)
+            .into_iter()
+            .find_map(|port| TcpListener::bind((self.bind_address, port)).ok())
+            .ok_or_else(|| anyhow::anyhow!(
+                "No free port on {} between {} and {}",
+                self.bind_address, self.port_range.start(), self.port_range.end()
+            ))
+    }
+
+    /// Address other machines should use to reach us on `port`
+    pub fn advertised_address(&self, port: u16) -> String {
+        let host = match &self.advertised_host {
+            Some(host) => host.clone(),
+            // Every interface: hand out the one that routes to the LAN
+            None if self.bind_address.is_unspecified() => lan_address().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)).to_string(),
+            None => self.bind_address.to_string(),
+        };
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+            _ => format!("{}:{}", host, port),
+        }
+    }
+}
+
+/// "62780-62799" or "62780"
+fn parse_port_range(value: &str) -> Option<RangeInclusive<u16>> {
+    let (start, end) = match value.split_once('-') {
+        Some((start, end)) => (start.trim().parse().ok()?, end.trim().parse().ok()?),
+        None => {
+            let port = value.trim().parse().ok()?;
+            (port, port)
+        }
+    };
+    (start > 0 && start <= end).then_some(start..=end)
+}
+
+/// Local address of the interface that routes outward. Connecting a UDP
+/// socket only picks a route; nothing is sent.
+fn lan_address() -> Option<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 9)).ok()?;
+    Some(socket.local_addr().ok()?.ip()).filter(|ip| !ip.is_unspecified())
+}
+
+static LISTEN_CONFIG: OnceLock<ListenConfig> = OnceLock::new();
+
+/// Listen settings for this run, read from the environment on first use
+pub fn listen_config() -> &'static ListenConfig {
+    LISTEN_CONFIG.get_or_init(ListenConfig::from_env)
+}
+
+/// Address to put in key shares and group member entries for `port`
+pub fn advertised_address(port: u16) -> String {
+    listen_config().advertised_address(port)
+}
+
+/// Most network events handled per UI poll, so a flood can't freeze the UI
+pub const MAX_EVENTS_PER_POLL: usize = 64;
+
+/// Take up to `max` waiting events, in the order they were received
+pub fn drain_events<T>(receiver: &mut mpsc::UnboundedReceiver<T>, max: usize) -> Vec<T> {
+    let mut events = Vec::new();
+    while events.len() < max {
+        match receiver.try_recv() {
+            Ok(event) => events.push(event),
+            Err(_) => break,
+        }
+    }
+    events
+}
+
+/// How long a connection test waits for the peer to accept
+pub const CONNECT_TEST_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Result of checking whether a peer accepts connections
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionDiagnosis {
+    Reachable { latency_ms: u64 },
+    InvalidAddress(String),
+    /// The hostname didn't resolve
+    DnsFailed(String),
+    Refused,
+    TimedOut,
+    Unreachable,
+    Other(String),
+}
+
+impl std::fmt::Display for ConnectionDiagnosis {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConnectionDiagnosis::Reachable { latency_ms } => write!(f, "Peer reachable ({} ms)", latency_ms),
+            ConnectionDiagnosis::InvalidAddress(e) => write!(f, "Invalid address: {}", e),
+            ConnectionDiagnosis::DnsFailed(host) => write!(f, "Could not resolve {}; check the hostname", host),
+            ConnectionDiagnosis::Refused => write!(f, "Connection refused: nothing is listening there (app closed or wrong port?)"),
+            ConnectionDiagnosis::TimedOut => write!(f, "Timed out: the peer may be offline or behind a firewall/NAT"),
+            ConnectionDiagnosis::Unreachable => write!(f, "No route to the peer's network from here"),
+            ConnectionDiagnosis::Other(e) => write!(f, "Connection failed: {}", e),
+        }
+    }
+}
+
+impl ConnectionDiagnosis {
+    /// Diagnosis for a failed connect
+    fn from_io(err: &std::io::Error) -> Self {
+        use std::io::ErrorKind;
+        match err.kind() {
+            ErrorKind::ConnectionRefused => ConnectionDiagnosis::Refused,
+            // Some platforms report an expired connect timeout as WouldBlock
+            ErrorKind::TimedOut | ErrorKind::WouldBlock => ConnectionDiagnosis::TimedOut,
+            ErrorKind::HostUnreachable | ErrorKind::NetworkUnreachable | ErrorKind::AddrNotAvailable => {
+                ConnectionDiagnosis::Unreachable
+            }
+            _ => ConnectionDiagnosis::Other(err.to_string()),
+        }
+    }
+}
+
+/// Try a TCP connect to `peer_address` and report how it went. The
+/// connection is closed without sending anything, which the listener
+/// treats as a probe rather than a broken message.
+pub fn test_connection(peer_address: &str, timeout: Duration) -> ConnectionDiagnosis {
+    let address: crate::peer_address::PeerAddress = match peer_address.parse() {
+        Ok(address) => address,
+        Err(e) => return ConnectionDiagnosis::InvalidAddress(e.to_string()),
+    };
+    let targets: Vec<SocketAddr> = match address.to_string().to_socket_addrs() {
+        Ok(targets) => targets.collect(),
+        Err(_) => return ConnectionDiagnosis::DnsFailed(address.host_display()),
+    };
+    let mut diagnosis = ConnectionDiagnosis::DnsFailed(address.host_display());
+    for target in targets {
+        let started = Instant::now();
+        match TcpStream::connect_timeout(&target, timeout) {
+            Ok(_) => {
+                return ConnectionDiagnosis::Reachable { latency_ms: started.elapsed().as_millis() as u64 };
+            }
+            Err(e) => diagnosis = ConnectionDiagnosis::from_io(&e),
+        }
+    }
+    diagnosis
+}
+
 /// Events sent from network to UI
 #[derive(Debug, Clone)]
 pub enum NetworkEvent {
@@ -18,17 +274,34 @@ pub enum NetworkEvent {
         sender_name: Option<String>,
         sender_fingerprint: String,
         sender_address: String,
+        message_id: Option<String>,
+    },
+    /// Peer ended the session (contact and history stay)
+    SessionEnded {
+        sender_fingerprint: String,
+    },
+    /// Peer confirmed it received our message `message_id`
+    DeliveryAckReceived {
+        message_id: String,
+        sender_fingerprint: String,
+        group_id: Option<String>,
     },
     RequestReceived {
         sender_fingerprint: String,
         sender_public_key: String,
         sender_address: String,
         sender_name: Option<String>,
+        kind: cryptochat_messaging::requests::RequestKind,
+        /// Signed stamp; empty from clients that predate them
+        stamp: crate::request_replay::RequestStamp,
+        /// A `Request`, rather than the answer to one we sent
+        is_request: bool,
     },
     TypingUpdate {
         is_typing: bool,
         sender_fingerprint: String,
         sender_address: String,
+        group_id: Option<String>,
     },
     ReadReceiptReceived {
         last_read_timestamp: String,
@@ -41,10 +314,56 @@ pub enum NetworkEvent {
         sender_name: Option<String>,
         sender_fingerprint: String,
         sender_address: String,
+        message_id: Option<String>,
+    },
+    VoiceReceived {
+        encrypted_audio: String,
+        duration_ms: u64,
+        sender_name: Option<String>,
+        sender_fingerprint: String,
+        sender_address: String,
+        message_id: Option<String>,
     },
     ContactRemovalReceived {
         fingerprint: String,
     },
+    /// A contact revoked their key
+    KeyRevocationReceived {
+        fingerprint: String,
+        revocation_certificate: String,
+    },
+    /// Contact replaced their key; signature is by the old key
+    KeyRotationReceived {
+        old_fingerprint: String,
+        new_public_key: String,
+        signature: String,
+    },
+    /// Peer changed the disappearing-message timer for our chat
+    DisappearingTimerChanged {
+        timer_secs: Option<u64>,
+        sender_fingerprint: String,
+        sender_address: String,
+    },
+    /// A contact says it now listens on a different port; only followed if
+    /// `notice` is signed by the key we hold for them
+    AddressUpdateReceived {
+        fingerprint: String,
+        new_address: String,
+        notice: crate::request_store::AddressNotice,
+    },
+    /// Chat settings from another of our devices, still sealed
+    SettingsSyncReceived {
+        sealed: cryptochat_messaging::EncryptedEnvelope,
+        sender_fingerprint: String,
+    },
+    /// Peer opened a chat and has read up to the message `last_read_id`
+    ConversationReadReceived {
+        conversation_id: String,
+        /// None from older clients, which only sent a message count
+        last_read_id: Option<uuid::Uuid>,
+        sender_fingerprint: String,
+        sender_address: String,
+    },
     
     // Group Events
     GroupInviteReceived {
@@ -54,6 +373,7 @@ pub enum NetworkEvent {
         encrypted_symmetric_key: String,
         members: Vec<(String, String)>,
         settings: crate::group_store::GroupSettings,
+        membership_epoch: u64,
     },
     
     GroupMessageReceived {
@@ -63,29 +383,79 @@ pub enum NetworkEvent {
         encrypted_content: String,
         timestamp: String,
         expires_at: Option<String>,
+        message_id: Option<String>,
     },
     
-    /// A new member announced they joined a group
+    /// A peer announced they joined a group; admins queue it for approval
     GroupJoinReceived {
         group_id: String,
         new_member: crate::group_store::GroupMember,
-        /// Address to send sync response back to
-        sender_address: String,
     },
     
-    /// Received member list from another member (in response to our join)
+    /// An admin changed a group's settings
+    GroupSettingsReceived {
+        group_id: String,
+        new_settings: crate::group_store::GroupSettings,
+        sender_fingerprint: String,
+    },
+    
+    /// A group's name/avatar changed
+    GroupMetadataReceived {
+        group_id: String,
+        name: String,
+        avatar_hash: Option<String>,
+        updated_at_ms: i64,
+        sender_fingerprint: String,
+        admins: Vec<String>,
+        owner: String,
+        roles_version: u64,
+        /// The sending admin's signature (see `group_auth`)
+        signature: String,
+    },
+    
+    /// A member left a group
+    GroupLeaveReceived {
+        group_id: String,
+        fingerprint: String,
+        /// The leaving member's signature (see `group_auth`)
+        signature: String,
+    },
+    
+    /// A member was removed and the group key rotated
+    GroupKeyRotated {
+        group_id: String,
+        removed_fingerprint: String,
+        /// New key encrypted for us (base64)
+        new_encrypted_key: Option<String>,
+        /// Admin who rotated the key, and their signature
+        sender_fingerprint: String,
+        signature: String,
+    },
+    
+    /// A member asked us, as an admin, to resync them
+    GroupResyncRequested {
+        group_id: String,
+        fingerprint: String,
+    },
+    
+    /// Received an admin's member list (on our join, or after a resync)
     GroupMemberSyncReceived {
         group_id: String,
+        sender_fingerprint: String,
         members: Vec<crate::group_store::GroupMember>,
+        membership_epoch: u64,
+        encrypted_key: Option<String>,
     },
     
     /// Received reaction from peer
     ReactionReceived {
+        msg_id: Option<uuid::Uuid>,
         msg_timestamp: String,
         emoji: String,
         sender_name: String,
         sender_fingerprint: String,
         sender_address: String,
+        group_id: Option<String>,
     },
 
     /// Received emote request
@@ -112,24 +482,64 @@ pub enum MessageEnvelope {
         sender_listening_port: u16,
         first_message: String,
         sender_name: Option<String>,
+        /// What the request is for (older clients only ever ask to chat)
+        #[serde(default)]
+        kind: cryptochat_messaging::requests::RequestKind,
+        /// Random per request, so a repeat of this envelope can be spotted
+        /// (empty from older clients)
+        #[serde(default)]
+        nonce: String,
+        /// When the request was sent (ms since epoch)
+        #[serde(default)]
+        sent_ms: i64,
+        /// Sender's signature over the nonce and time (empty from older clients)
+        #[serde(default)]
+        signature: String,
     },
     AcceptedResponse {
         sender_fingerprint: String,
         sender_public_key: String,
         sender_listening_port: u16,
         sender_name: Option<String>,
+        /// Random per-response value (empty from older clients)
+        #[serde(default)]
+        nonce: String,
+        /// When the response was sent (ms since epoch)
+        #[serde(default)]
+        sent_ms: i64,
+        /// Sender's signature over the nonce and time (empty from older clients)
+        #[serde(default)]
+        signature: String,
     },
     RegularMessage {
         encrypted_payload: String,
         sender_name: Option<String>,
         sender_fingerprint: String,
         sender_listening_port: u16,
+        /// Echoed back in a DeliveryAck (absent from older clients)
+        #[serde(default)]
+        message_id: Option<String>,
+    },
+    /// Sender closed the session with us; they stay a contact
+    SessionEnd {
+        sender_fingerprint: String,
+    },
+    /// Receipt that a RegularMessage arrived and decrypted
+    DeliveryAck {
+        message_id: String,
+        sender_fingerprint: String,
+        /// Set when acknowledging a group message
+        #[serde(default)]
+        group_id: Option<String>,
     },
     /// Typing indicator (true = started typing, false = stopped)
     TypingIndicator {
         is_typing: bool,
         sender_fingerprint: String,
         sender_listening_port: u16,
+        /// Set when typing in a group chat
+        #[serde(default)]
+        group_id: Option<String>,
     },
     /// Read receipt for message acknowledgment  
     ReadReceipt {
@@ -141,6 +551,9 @@ pub enum MessageEnvelope {
     /// Request a custom emote image by hash
     EmoteRequest {
         hash: String,
+        /// Port to send the EmoteData reply to (older clients omit it)
+        #[serde(default)]
+        sender_listening_port: Option<u16>,
     },
     /// Transfer custom emote image data
     EmoteData {
@@ -156,12 +569,77 @@ pub enum MessageEnvelope {
         sender_name: Option<String>,
         sender_fingerprint: String,
         sender_listening_port: u16,
+        /// Id the message is stored under on both sides
+        #[serde(default)]
+        message_id: Option<String>,
+    },
+    /// Recorded voice clip
+    VoiceMessage {
+        /// Base64-encoded encrypted WAV audio
+        encrypted_audio: String,
+        /// Length as recorded; the receiver checks it against the audio
+        duration_ms: u64,
+        sender_name: Option<String>,
+        sender_fingerprint: String,
+        sender_listening_port: u16,
+        #[serde(default)]
+        message_id: Option<String>,
     },
     /// Contact removal notification
     ContactRemoved {
         /// Fingerprint of the contact being removed
         fingerprint: String,
     },
+    /// Owner published a revocation certificate for their key
+    KeyRevoked {
+        fingerprint: String,
+        /// ASCII-armored revocation signature
+        revocation_certificate: String,
+    },
+    /// Owner replaced their key with `new_public_key`, vouched for by a
+    /// signature from the old key
+    KeyRotation {
+        old_fingerprint: String,
+        /// ASCII-armored public key of the replacement key
+        new_public_key: String,
+        /// Armored detached signature by the old key
+        signature: String,
+    },
+    /// Disappearing-message timer change for a direct chat (None = off)
+    DisappearingTimer {
+        timer_secs: Option<u64>,
+        sender_fingerprint: String,
+        sender_listening_port: u16,
+    },
+    /// Sent to contacts when our listening port changed since last run
+    AddressUpdate {
+        fingerprint: String,
+        new_port: u16,
+        #[serde(default)]
+        sent_ms: i64,
+        /// Signature over [`crate::request_store::address_statement`]
+        #[serde(default)]
+        signature: String,
+    },
+    /// Mute/pin/archive settings sealed for the sender's other devices (see
+    /// `settings_sync`), posted to their own relay mailbox
+    SettingsSync {
+        sealed: cryptochat_messaging::EncryptedEnvelope,
+        sender_fingerprint: String,
+    },
+    /// Sent when a chat is opened: the sender has read everything up to the
+    /// message `last_read_id`. `conversation_id` is the group id for groups;
+    /// direct chats are filed under the sender's fingerprint.
+    ConversationRead {
+        conversation_id: String,
+        /// How many messages the sender has, for older clients. Histories
+        /// differ between peers, so it isn't used for anything else.
+        up_to_seq: u64,
+        #[serde(default)]
+        last_read_id: Option<uuid::Uuid>,
+        sender_fingerprint: String,
+        sender_listening_port: u16,
+    },
     
     // Group Chat Messages
     
@@ -173,6 +651,9 @@ pub enum MessageEnvelope {
         encrypted_symmetric_key: String,
         members: Vec<(String, String)>, // (name, fingerprint)
         settings: crate::group_store::GroupSettings,
+        /// Inviting admin's membership epoch
+        #[serde(default)]
+        membership_epoch: u64,
     },
     
     GroupInviteResponse {
@@ -193,6 +674,9 @@ pub enum MessageEnvelope {
         timestamp: String,
         /// Optional expiration for disappearing messages
         expires_at: Option<String>,
+        /// Echoed back in a DeliveryAck (absent from older clients)
+        #[serde(default)]
+        message_id: Option<String>,
     },
     
     MemberAdded {
@@ -209,6 +693,12 @@ pub enum MessageEnvelope {
         removed_fingerprint: String,
         /// New symmetric key (encrypted for the recipient)
         new_encrypted_key: Option<String>, 
+        /// Admin who rotated the key (older clients omit it and are ignored)
+        #[serde(default)]
+        sender_fingerprint: String,
+        /// Signature over the removal and new key (see `group_auth`)
+        #[serde(default)]
+        signature: String,
     },
     
     AdminPromoted {
@@ -219,6 +709,40 @@ pub enum MessageEnvelope {
     SettingsChanged {
         group_id: String,
         new_settings: crate::group_store::GroupSettings,
+        /// Admin who made the change (older clients omit it)
+        #[serde(default)]
+        sender_fingerprint: String,
+    },
+    
+    /// Group name/avatar changed by an admin
+    GroupMetadataUpdate {
+        group_id: String,
+        name: String,
+        /// Emote-cache hash of the avatar image (fetched via EmoteRequest)
+        avatar_hash: Option<String>,
+        updated_at_ms: i64,
+        sender_fingerprint: String,
+        /// Admin fingerprints after the change (older clients omit it)
+        #[serde(default)]
+        admins: Vec<String>,
+        /// Owner after the change (older clients omit it)
+        #[serde(default)]
+        owner: String,
+        /// Version of the admin list and owner, apart from the name/avatar stamp
+        #[serde(default)]
+        roles_version: u64,
+        /// The sending admin's signature over all of the above (see `group_auth`)
+        #[serde(default)]
+        signature: String,
+    },
+    
+    /// Sent by a member to everyone else when they leave a group
+    GroupLeave {
+        group_id: String,
+        fingerprint: String,
+        /// The leaving member's signature (see `group_auth`)
+        #[serde(default)]
+        signature: String,
     },
     
     /// Sent by a new joiner to all existing members to announce they joined
@@ -227,16 +751,37 @@ pub enum MessageEnvelope {
         new_member: crate::group_store::GroupMember,
     },
     
-    /// Response to join announcement - contains sender's current member list
+    /// An admin's view of the group, sent on approving a join or answering a
+    /// resync request; only admins' syncs are applied
     GroupMemberSync {
         group_id: String,
+        /// The admin sending it (absent from older clients, whose syncs are ignored)
+        #[serde(default)]
+        sender_fingerprint: String,
         /// Full member list from sender's perspective
         members: Vec<crate::group_store::GroupMember>,
+        /// Sender's membership epoch, adopted by the joiner
+        #[serde(default)]
+        membership_epoch: u64,
+        /// Group key encrypted for the joiner (base64), who has none yet if
+        /// they joined by invite code
+        #[serde(default)]
+        encrypted_key: Option<String>,
+    },
+    
+    /// A member without the key, or behind on the membership epoch, asks an
+    /// admin for a fresh GroupMemberSync
+    GroupResyncRequest {
+        group_id: String,
+        fingerprint: String,
     },
     
     /// Emoji reaction to a message
     Reaction {
-        /// Timestamp of the message being reacted to
+        /// Id of the message being reacted to (absent from older clients)
+        #[serde(default)]
+        msg_id: Option<uuid::Uuid>,
+        /// Timestamp of the message being reacted to, for older clients
         msg_timestamp: String,
         /// The emoji reaction
         emoji: String,
@@ -244,6 +789,9 @@ pub enum MessageEnvelope {
         sender_name: String,
         sender_fingerprint: String,
         sender_listening_port: u16,
+        /// Set when reacting to a group message
+        #[serde(default)]
+        group_id: Option<String>,
     },
 }
 
@@ -255,29 +803,90 @@ pub struct KeyShareData {
     pub username: Option<String>,
 }
 
+impl MessageEnvelope {
+    /// Our key and where to reach us, sent when accepting a connection or to
+    /// re-establish a session after a restart or port change
+    /// A connection request of `kind`, stamped and signed with `keypair`
+    pub fn request(
+        keypair: &cryptochat_crypto_core::pgp::PgpKeyPair,
+        listening_port: u16,
+        name: &str,
+        kind: cryptochat_messaging::requests::RequestKind,
+        now_ms: i64,
+    ) -> Result<Self> {
+        let stamp = crate::request_replay::RequestStamp::sign(keypair, kind, now_ms)?;
+        Ok(MessageEnvelope::Request {
+            sender_fingerprint: keypair.fingerprint(),
+            sender_public_key: keypair.export_public_key()?,
+            sender_device_id: String::new(),
+            sender_listening_port: listening_port,
+            first_message: String::new(),
+            sender_name: Some(name.to_string()),
+            kind,
+            nonce: stamp.nonce,
+            sent_ms: stamp.sent_ms,
+            signature: stamp.signature,
+        })
+    }
+
+    pub fn accepted_response(
+        keypair: &cryptochat_crypto_core::pgp::PgpKeyPair,
+        listening_port: u16,
+        name: &str,
+        now_ms: i64,
+    ) -> Result<Self> {
+        let stamp = crate::request_replay::RequestStamp::sign_response(keypair, now_ms)?;
+        Ok(MessageEnvelope::AcceptedResponse {
+            sender_fingerprint: keypair.fingerprint(),
+            sender_public_key: keypair.export_public_key()?,
+            sender_listening_port: listening_port,
+            sender_name: Some(name.to_string()),
+            nonce: stamp.nonce,
+            sent_ms: stamp.sent_ms,
+            signature: stamp.signature,
+        })
+    }
+}
+
 pub struct NetworkHandle {
     listener_port: u16,
     running: Arc<AtomicBool>,
 }
 
 impl NetworkHandle {
-    pub fn start_with_sender(sender: mpsc::UnboundedSender<NetworkEvent>) -> Result<Self> {
-        let listener = match TcpListener::bind(format!("127.0.0.1:{}", DEFAULT_PORT)) {
-            Ok(l) => l,
-            Err(_) => TcpListener::bind("127.0.0.1:0")?,
-        };
+    /// Start listening on the configured interface, trying `preferred_port`
+    /// (the port from last run) first, then the rest of the configured range
+    pub fn start_with_sender(sender: mpsc::UnboundedSender<NetworkEvent>, preferred_port: Option<u16>) -> Result<Self> {
+        let listener = listen_config().bind(preferred_port)?;
         let listener_port = listener.local_addr()?.port();
         let running = Arc::new(AtomicBool::new(true));
         let running_clone = running.clone();
 
+        let limiter = Arc::new(Mutex::new(InboundLimiter::default()));
+
         std::thread::spawn(move || {
             while running_clone.load(Ordering::Relaxed) {
                 match listener.accept() {
                     Ok((mut stream, addr)) => {
-                        let sender = sender.clone();
                         let peer_addr = addr.to_string();
+                        let host = crate::peer_address::connection_host(&peer_addr);
+                        // Decide before reading anything so a flood costs us as little as possible
+                        let admission = limiter.lock().unwrap_or_else(PoisonError::into_inner).admit(&host, Instant::now());
+                        match admission {
+                            Admission::Accept => {}
+                            Admission::Throttle => {
+                                let _ = sender.send(NetworkEvent::Error(format!(
+                                    "Too many connections from {}; dropping them for now", host
+                                )));
+                                continue;
+                            }
+                            Admission::Drop => continue,
+                        }
+
+                        let sender = sender.clone();
+                        let limiter = limiter.clone();
                         std::thread::spawn(move || {
-                            if let Err(e) = handle_connection(&mut stream, &sender, &peer_addr) {
+                            if let Err(e) = handle_connection(&mut stream, &sender, &peer_addr, &limiter) {
                                 let _ = sender.send(NetworkEvent::Error(format!("{}: {}", addr, e)));
                             }
                         });
@@ -292,14 +901,16 @@ impl NetworkHandle {
 
     pub fn start() -> Result<Self> {
         let (s, _) = mpsc::unbounded_channel();
-        Self::start_with_sender(s)
+        Self::start_with_sender(s, None)
     }
 
     pub fn port(&self) -> u16 { self.listener_port }
 
     pub fn send_message(peer_address: &str, envelope: MessageEnvelope) -> Result<()> {
-        let mut stream = TcpStream::connect(peer_address)?;
+        let address: crate::peer_address::PeerAddress = peer_address.parse()?;
         let json = serde_json::to_vec(&envelope)?;
+        size_limits().check(&envelope, json.len())?;
+        let mut stream = TcpStream::connect(address.to_string())?;
         stream.write_all(&(json.len() as u32).to_be_bytes())?;
         stream.write_all(&json)?;
         stream.flush()?;
@@ -325,135 +936,443 @@ impl NetworkHandle {
     pub fn stop(&self) { self.running.store(false, Ordering::Relaxed); }
 }
 
-fn handle_connection(stream: &mut TcpStream, sender: &mpsc::UnboundedSender<NetworkEvent>, peer_addr: &str) -> Result<()> {
+fn handle_connection(
+    stream: &mut TcpStream,
+    sender: &mpsc::UnboundedSender<NetworkEvent>,
+    peer_addr: &str,
+    limiter: &Mutex<InboundLimiter>,
+) -> Result<()> {
     // Extract IP for use in sender_address fields
-    let ip = peer_addr.split(':').next().unwrap_or("127.0.0.1").to_string();
+    let ip = crate::peer_address::connection_host(peer_addr);
 
     let mut len_bytes = [0u8; 4];
-    stream.read_exact(&mut len_bytes)?;
+    match stream.read_exact(&mut len_bytes) {
+        // Closed before sending anything: a connection test, not a message
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+        result => result?,
+    }
     let len = u32::from_be_bytes(len_bytes) as usize;
+    // Refuse oversized frames before allocating a buffer for them
+    let limits = size_limits();
+    if len > limits.max_frame_bytes() {
+        bail!("Dropped oversized message: {} bytes (limit {} bytes)", len, limits.max_frame_bytes());
+    }
     let mut buffer = vec![0u8; len];
     stream.read_exact(&mut buffer)?;
     let envelope: MessageEnvelope = serde_json::from_slice(&buffer)?;
+    limits.check(&envelope, len).map_err(|e| anyhow::anyhow!("Dropped incoming message: {}", e))?;
+
+    if let MessageEnvelope::TypingIndicator { is_typing, .. } = &envelope {
+        if !limiter.lock().unwrap_or_else(PoisonError::into_inner).forward_typing(&ip, *is_typing, Instant::now()) {
+            return Ok(());
+        }
+    }
+    if let Some(event) = envelope_event(envelope, peer_addr) {
+        let _ = sender.send(event);
+    }
+    Ok(())
+}
+
+/// The UI event for an envelope that arrived from `peer_addr`, either on a
+/// direct connection or (for relayed messages) the sender's stated address
+pub fn envelope_event(envelope: MessageEnvelope, peer_addr: &str) -> Option<NetworkEvent> {
+    // Extract IP for use in sender_address fields
+    let ip = crate::peer_address::connection_host(peer_addr);
 
     match envelope {
-        MessageEnvelope::Request { sender_fingerprint, sender_public_key, sender_listening_port, sender_name, .. } => {
-            let ip = peer_addr.split(':').next().unwrap_or("127.0.0.1");
-            let _ = sender.send(NetworkEvent::RequestReceived {
+        MessageEnvelope::Request { sender_fingerprint, sender_public_key, sender_listening_port, sender_name, kind, nonce, sent_ms, signature, .. } => {
+            Some(NetworkEvent::RequestReceived {
                 sender_fingerprint,
                 sender_public_key,
                 sender_address: format!("{}:{}", ip, sender_listening_port),
                 sender_name,
-            });
+                kind,
+                stamp: crate::request_replay::RequestStamp { nonce, sent_ms, signature },
+                is_request: true,
+            })
         }
-        MessageEnvelope::AcceptedResponse { sender_fingerprint, sender_public_key, sender_listening_port, sender_name } => {
-            let ip = peer_addr.split(':').next().unwrap_or("127.0.0.1");
-            let _ = sender.send(NetworkEvent::RequestReceived {
+        MessageEnvelope::AcceptedResponse { sender_fingerprint, sender_public_key, sender_listening_port, sender_name, nonce, sent_ms, signature } => {
+            Some(NetworkEvent::RequestReceived {
                 sender_fingerprint,
                 sender_public_key,
                 sender_address: format!("{}:{}", ip, sender_listening_port),
                 sender_name,
-            });
+                kind: cryptochat_messaging::requests::RequestKind::Chat,
+                stamp: crate::request_replay::RequestStamp { nonce, sent_ms, signature },
+                is_request: false,
+            })
         }
-        MessageEnvelope::RegularMessage { encrypted_payload, sender_name, sender_fingerprint, sender_listening_port } => {
-            let _ = sender.send(NetworkEvent::MessageReceived { 
+        MessageEnvelope::RegularMessage { encrypted_payload, sender_name, sender_fingerprint, sender_listening_port, message_id } => {
+            Some(NetworkEvent::MessageReceived { 
                 encrypted_payload, 
                 sender_name, 
                 sender_fingerprint,
                 sender_address: format!("{}:{}", ip, sender_listening_port),
-            });
+                message_id,
+            })
+        }
+        MessageEnvelope::SessionEnd { sender_fingerprint } => {
+            Some(NetworkEvent::SessionEnded { sender_fingerprint })
         }
-        MessageEnvelope::TypingIndicator { is_typing, sender_fingerprint, sender_listening_port } => {
-            let _ = sender.send(NetworkEvent::TypingUpdate { 
+        MessageEnvelope::DeliveryAck { message_id, sender_fingerprint, group_id } => {
+            Some(NetworkEvent::DeliveryAckReceived { message_id, sender_fingerprint, group_id })
+        }
+        MessageEnvelope::TypingIndicator { is_typing, sender_fingerprint, sender_listening_port, group_id } => {
+            Some(NetworkEvent::TypingUpdate { 
                 is_typing, 
                 sender_fingerprint,
                 sender_address: format!("{}:{}", ip, sender_listening_port),
-            });
+                group_id,
+            })
         }
         MessageEnvelope::ReadReceipt { last_read_timestamp, sender_fingerprint, sender_listening_port } => {
-            let _ = sender.send(NetworkEvent::ReadReceiptReceived { 
+            Some(NetworkEvent::ReadReceiptReceived { 
                 last_read_timestamp, 
                 sender_fingerprint,
                 sender_address: format!("{}:{}", ip, sender_listening_port),
-            });
+            })
         }
-        MessageEnvelope::FileMessage { filename, encrypted_data, sender_name, sender_fingerprint, sender_listening_port } => {
-            let _ = sender.send(NetworkEvent::FileReceived { 
+        MessageEnvelope::FileMessage { filename, encrypted_data, sender_name, sender_fingerprint, sender_listening_port, message_id } => {
+            Some(NetworkEvent::FileReceived { 
                 filename, 
                 encrypted_data, 
                 sender_name, 
                 sender_fingerprint,
                 sender_address: format!("{}:{}", ip, sender_listening_port),
-            });
+                message_id,
+            })
+        }
+        MessageEnvelope::VoiceMessage { encrypted_audio, duration_ms, sender_name, sender_fingerprint, sender_listening_port, message_id } => {
+            Some(NetworkEvent::VoiceReceived {
+                encrypted_audio,
+                duration_ms,
+                sender_name,
+                sender_fingerprint,
+                sender_address: format!("{}:{}", ip, sender_listening_port),
+                message_id,
+            })
         }
         MessageEnvelope::ContactRemoved { fingerprint } => {
-            let _ = sender.send(NetworkEvent::ContactRemovalReceived { fingerprint });
+            Some(NetworkEvent::ContactRemovalReceived { fingerprint })
+        }
+        MessageEnvelope::KeyRevoked { fingerprint, revocation_certificate } => {
+            Some(NetworkEvent::KeyRevocationReceived { fingerprint, revocation_certificate })
+        }
+        MessageEnvelope::KeyRotation { old_fingerprint, new_public_key, signature } => {
+            Some(NetworkEvent::KeyRotationReceived { old_fingerprint, new_public_key, signature })
+        }
+        MessageEnvelope::DisappearingTimer { timer_secs, sender_fingerprint, sender_listening_port } => {
+            Some(NetworkEvent::DisappearingTimerChanged {
+                timer_secs,
+                sender_fingerprint,
+                sender_address: format!("{}:{}", ip, sender_listening_port),
+            })
+        }
+        MessageEnvelope::AddressUpdate { fingerprint, new_port, sent_ms, signature } => {
+            Some(NetworkEvent::AddressUpdateReceived {
+                fingerprint,
+                new_address: format!("{}:{}", ip, new_port),
+                notice: crate::request_store::AddressNotice { new_port, sent_ms, signature },
+            })
+        }
+        MessageEnvelope::SettingsSync { sealed, sender_fingerprint } => {
+            Some(NetworkEvent::SettingsSyncReceived { sealed, sender_fingerprint })
+        }
+        MessageEnvelope::ConversationRead { conversation_id, last_read_id, sender_fingerprint, sender_listening_port, .. } => {
+            Some(NetworkEvent::ConversationReadReceived {
+                conversation_id,
+                last_read_id,
+                sender_fingerprint,
+                sender_address: format!("{}:{}", ip, sender_listening_port),
+            })
+        }
+        MessageEnvelope::SettingsChanged { group_id, new_settings, sender_fingerprint } => {
+            Some(NetworkEvent::GroupSettingsReceived {
+                group_id,
+                new_settings,
+                sender_fingerprint,
+            })
         }
-        MessageEnvelope::Reaction { msg_timestamp, emoji, sender_name, sender_fingerprint, sender_listening_port } => {
-            let _ = sender.send(NetworkEvent::ReactionReceived {
+        MessageEnvelope::Reaction { msg_id, msg_timestamp, emoji, sender_name, sender_fingerprint, sender_listening_port, group_id } => {
+            Some(NetworkEvent::ReactionReceived {
+                msg_id,
                 msg_timestamp,
                 emoji,
                 sender_name,
                 sender_fingerprint,
                 sender_address: format!("{}:{}", ip, sender_listening_port),
-            });
+                group_id,
+            })
         }
 
         // Group Messages
-        MessageEnvelope::GroupInvite { group_id, group_name, creator_name, encrypted_symmetric_key, members, settings } => {
-            let _ = sender.send(NetworkEvent::GroupInviteReceived {
+        MessageEnvelope::GroupInvite { group_id, group_name, creator_name, encrypted_symmetric_key, members, settings, membership_epoch } => {
+            Some(NetworkEvent::GroupInviteReceived {
                 group_id,
                 group_name,
                 creator_name,
                 encrypted_symmetric_key,
                 members,
                 settings,
-            });
+                membership_epoch,
+            })
         }
         
-        MessageEnvelope::GroupMessage { group_id, sender_fingerprint, sender_name, encrypted_content, timestamp, expires_at } => {
-            let _ = sender.send(NetworkEvent::GroupMessageReceived {
+        MessageEnvelope::GroupMessage { group_id, sender_fingerprint, sender_name, encrypted_content, timestamp, expires_at, message_id } => {
+            Some(NetworkEvent::GroupMessageReceived {
                 group_id,
                 sender_fingerprint,
                 sender_name,
                 encrypted_content,
                 timestamp,
                 expires_at,
-            });
+                message_id,
+            })
         }
         
         MessageEnvelope::GroupJoinAnnouncement { group_id, new_member } => {
-            let ip = peer_addr.split(':').next().unwrap_or("127.0.0.1");
-            // Use member's address from the message, or construct from peer IP
-            let sender_address = new_member.address.clone();
-            let _ = sender.send(NetworkEvent::GroupJoinReceived {
-                group_id,
-                new_member,
-                sender_address,
-            });
+            Some(NetworkEvent::GroupJoinReceived { group_id, new_member })
         }
         
-        MessageEnvelope::GroupMemberSync { group_id, members } => {
-            let _ = sender.send(NetworkEvent::GroupMemberSyncReceived {
+        MessageEnvelope::GroupMemberSync { group_id, sender_fingerprint, members, membership_epoch, encrypted_key } => {
+            Some(NetworkEvent::GroupMemberSyncReceived {
+                membership_epoch,
+                encrypted_key,
                 group_id,
+                sender_fingerprint,
                 members,
-            });
+            })
+        }
+        
+        MessageEnvelope::GroupResyncRequest { group_id, fingerprint } => {
+            Some(NetworkEvent::GroupResyncRequested { group_id, fingerprint })
         }
         
-        MessageEnvelope::EmoteRequest { hash } => {
-            let _ = sender.send(NetworkEvent::EmoteRequestReceived {
+        MessageEnvelope::GroupMetadataUpdate { group_id, name, avatar_hash, updated_at_ms, sender_fingerprint, admins, owner, roles_version, signature } => {
+            Some(NetworkEvent::GroupMetadataReceived {
+                group_id,
+                name,
+                avatar_hash,
+                updated_at_ms,
+                sender_fingerprint,
+                admins,
+                owner,
+                roles_version,
+                signature,
+            })
+        }
+        
+        MessageEnvelope::GroupLeave { group_id, fingerprint, signature } => {
+            Some(NetworkEvent::GroupLeaveReceived { group_id, fingerprint, signature })
+        }
+        
+        MessageEnvelope::MemberRemoved { group_id, removed_fingerprint, new_encrypted_key, sender_fingerprint, signature } => {
+            Some(NetworkEvent::GroupKeyRotated {
+                group_id,
+                removed_fingerprint,
+                new_encrypted_key,
+                sender_fingerprint,
+                signature,
+            })
+        }
+        
+        MessageEnvelope::EmoteRequest { hash, sender_listening_port } => {
+            let sender_addr_raw = match sender_listening_port {
+                Some(port) => format!("{}:{}", ip, port),
+                None => peer_addr.to_string(),
+            };
+            Some(NetworkEvent::EmoteRequestReceived {
                 hash,
-                sender_addr_raw: peer_addr.to_string(),
-            });
+                sender_addr_raw,
+            })
         }
         
         MessageEnvelope::EmoteData { hash, data } => {
-            let _ = sender.send(NetworkEvent::EmoteDataReceived { hash, data });
+            Some(NetworkEvent::EmoteDataReceived { hash, data })
         }
         
 
         
-        _ => {}
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regular_message(payload_len: usize) -> MessageEnvelope {
+        MessageEnvelope::RegularMessage {
+            encrypted_payload: "x".repeat(payload_len),
+            sender_name: None,
+            sender_fingerprint: "fp".to_string(),
+            sender_listening_port: DEFAULT_PORT,
+            message_id: None,
+        }
+    }
+
+    #[test]
+    fn drain_is_bounded_and_keeps_receive_order() {
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        for i in 0..10 {
+            sender.send(i).unwrap();
+        }
+        assert_eq!(drain_events(&mut receiver, 4), vec![0, 1, 2, 3]);
+        assert_eq!(drain_events(&mut receiver, 100), (4..10).collect::<Vec<_>>());
+        assert!(drain_events(&mut receiver, 100).is_empty());
+    }
+
+    #[test]
+    fn connect_failures_map_to_specific_diagnoses() {
+        use std::io::{Error, ErrorKind};
+        assert_eq!(ConnectionDiagnosis::from_io(&Error::from(ErrorKind::ConnectionRefused)), ConnectionDiagnosis::Refused);
+        assert_eq!(ConnectionDiagnosis::from_io(&Error::from(ErrorKind::TimedOut)), ConnectionDiagnosis::TimedOut);
+        assert_eq!(ConnectionDiagnosis::from_io(&Error::from(ErrorKind::WouldBlock)), ConnectionDiagnosis::TimedOut);
+        assert_eq!(ConnectionDiagnosis::from_io(&Error::from(ErrorKind::NetworkUnreachable)), ConnectionDiagnosis::Unreachable);
+        assert!(matches!(ConnectionDiagnosis::from_io(&Error::from(ErrorKind::PermissionDenied)), ConnectionDiagnosis::Other(_)));
+
+        assert!(matches!(test_connection("not an address", CONNECT_TEST_TIMEOUT), ConnectionDiagnosis::InvalidAddress(_)));
+        assert_eq!(
+            test_connection("no-such-peer.invalid:62780", CONNECT_TEST_TIMEOUT),
+            ConnectionDiagnosis::DnsFailed("no-such-peer.invalid".to_string())
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = format!("127.0.0.1:{}", listener.local_addr().unwrap().port());
+        assert!(matches!(test_connection(&open, CONNECT_TEST_TIMEOUT), ConnectionDiagnosis::Reachable { .. }));
+        drop(listener);
+        assert_eq!(test_connection(&open, CONNECT_TEST_TIMEOUT), ConnectionDiagnosis::Refused);
+    }
+
+    #[test]
+    fn message_at_limit_passes_and_over_limit_fails() {
+        let limits = SizeLimits { max_message_bytes: 1000, max_file_bytes: 5000 };
+        let envelope = regular_message(10);
+        assert!(limits.check(&envelope, 1000).is_ok());
+        assert!(limits.check(&envelope, 1001).is_err());
+        assert_eq!(limits.max_frame_bytes(), 5000);
+    }
+
+    #[test]
+    fn files_use_their_own_larger_limit() {
+        let limits = SizeLimits { max_message_bytes: 1000, max_file_bytes: 5000 };
+        let file = MessageEnvelope::FileMessage {
+            filename: "photo.png".to_string(),
+            encrypted_data: String::new(),
+            sender_name: None,
+            sender_fingerprint: "fp".to_string(),
+            sender_listening_port: DEFAULT_PORT,
+            message_id: None,
+        };
+        assert!(limits.check(&file, 5000).is_ok());
+        assert!(limits.check(&file, 5001).is_err());
+        assert!(limits.check(&regular_message(10), 5000).is_err());
+    }
+
+    #[test]
+    fn voice_messages_round_trip_and_use_the_file_limit() {
+        let voice = MessageEnvelope::VoiceMessage {
+            encrypted_audio: "AAAA".to_string(),
+            duration_ms: 7_400,
+            sender_name: Some("Alice".to_string()),
+            sender_fingerprint: "fp".to_string(),
+            sender_listening_port: DEFAULT_PORT,
+            message_id: Some("m1".to_string()),
+        };
+        let json = serde_json::to_string(&voice).unwrap();
+        let MessageEnvelope::VoiceMessage { duration_ms, message_id, encrypted_audio, .. } =
+            serde_json::from_str(&json).unwrap()
+        else {
+            panic!("expected a voice message");
+        };
+        assert_eq!((duration_ms, encrypted_audio.as_str()), (7_400, "AAAA"));
+        assert_eq!(message_id.as_deref(), Some("m1"));
+
+        let limits = SizeLimits { max_message_bytes: 1000, max_file_bytes: 5000 };
+        assert!(limits.check(&voice, 5000).is_ok());
+        assert!(limits.check(&voice, 5001).is_err());
+    }
+
+    #[test]
+    fn group_invites_carry_the_membership_epoch() {
+        let invite = MessageEnvelope::GroupInvite {
+            group_id: "g1".to_string(),
+            group_name: "Friends".to_string(),
+            creator_name: "Alice".to_string(),
+            encrypted_symmetric_key: "AAAA".to_string(),
+            members: vec![("Alice".to_string(), "fp".to_string())],
+            settings: crate::group_store::GroupSettings {
+                invite_permission: crate::group_store::InvitePermission::AdminsOnly,
+                max_members: None,
+                disappearing_timer_secs: None,
+            },
+            membership_epoch: 7,
+        };
+        let received = serde_json::from_str(&serde_json::to_string(&invite).unwrap()).unwrap();
+        let Some(NetworkEvent::GroupInviteReceived { group_id, membership_epoch, .. }) =
+            envelope_event(received, "127.0.0.1:62780")
+        else {
+            panic!("expected a group invite");
+        };
+        assert_eq!((group_id.as_str(), membership_epoch), ("g1", 7));
+    }
+
+    #[test]
+    fn resent_key_carries_our_current_port() {
+        let keypair = cryptochat_crypto_core::pgp::PgpKeyPair::generate("alice").unwrap();
+        let envelope = MessageEnvelope::accepted_response(&keypair, 62791, "alice", 1_000).unwrap();
+        let MessageEnvelope::AcceptedResponse { sender_fingerprint, sender_public_key, sender_listening_port, sender_name, sent_ms, .. } =
+            serde_json::from_str(&serde_json::to_string(&envelope).unwrap()).unwrap()
+        else {
+            panic!("expected an accepted response");
+        };
+        assert_eq!(sender_fingerprint, keypair.fingerprint());
+        assert_eq!(sent_ms, 1_000);
+        assert!(sender_public_key.starts_with("-----BEGIN PGP"));
+        assert_eq!(sender_listening_port, 62791);
+        assert_eq!(sender_name.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn ports_are_chosen_within_the_configured_range() {
+        let config = ListenConfig { port_range: 40100..=40103, ..ListenConfig::default() };
+        assert_eq!(config.candidate_ports(None), vec![40100, 40101, 40102, 40103]);
+        // Last run's port goes first, but only if it's still allowed
+        assert_eq!(config.candidate_ports(Some(40102)), vec![40102, 40100, 40101, 40103]);
+        assert_eq!(config.candidate_ports(Some(DEFAULT_PORT)), vec![40100, 40101, 40102, 40103]);
+
+        // Take a port in a one-off range, then ask for that range again
+        let taken = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let busy = taken.local_addr().unwrap().port();
+        let only_busy = ListenConfig { port_range: busy..=busy, ..ListenConfig::default() };
+        let err = only_busy.bind(None).unwrap_err().to_string();
+        assert!(err.contains(&busy.to_string()), "{}", err);
+
+        let free = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap().local_addr().unwrap().port();
+        let config = ListenConfig { port_range: free.min(busy)..=free.max(busy), ..ListenConfig::default() };
+        let listener = config.bind(Some(busy)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert_ne!(port, busy);
+        assert!(config.port_range.contains(&port));
+    }
+
+    #[test]
+    fn port_range_and_advertised_address_from_settings() {
+        assert_eq!(parse_port_range("62780-62789"), Some(62780..=62789));
+        assert_eq!(parse_port_range(" 9000 "), Some(9000..=9000));
+        assert_eq!(parse_port_range("9000-8000"), None);
+        assert_eq!(parse_port_range("0-10"), None);
+        assert_eq!(parse_port_range("lots"), None);
+
+        assert_eq!(ListenConfig::default().advertised_address(62780), "127.0.0.1:62780");
+        let lan = ListenConfig { bind_address: "192.168.1.20".parse().unwrap(), ..ListenConfig::default() };
+        assert_eq!(lan.advertised_address(5000), "192.168.1.20:5000");
+        let named = ListenConfig { advertised_host: Some("chat.example.net".to_string()), ..lan };
+        assert_eq!(named.advertised_address(5000), "chat.example.net:5000");
+        let v6 = ListenConfig { bind_address: "::1".parse().unwrap(), ..ListenConfig::default() };
+        assert_eq!(v6.advertised_address(5000), "[::1]:5000");
+        // Every interface never advertises 0.0.0.0
+        let all = ListenConfig { bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED), ..ListenConfig::default() };
+        assert!(!all.advertised_address(5000).starts_with("0.0.0.0"));
     }
-    Ok(())
 }
@@ -0,0 +1,136 @@
+//! Desktop toast notifications and the notification sound
+//!
+//! Toasts go through `notify-rust`, which builds the toast XML itself, so
+//! message text is passed as data rather than spliced into a script. Both can
+//! be switched off in settings; the switches live in process-wide flags so
+//! the free functions can be called from anywhere in `update`.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Longest title/body shown in a toast (characters)
+const MAX_TITLE_CHARS: usize = 64;
+const MAX_BODY_CHARS: usize = 200;
+
+static ENABLED: AtomicBool = AtomicBool::new(true);
+static SOUND_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// User choices stored in notifications.json
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct NotificationSettings {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_true")]
+    pub sound: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self { enabled: true, sound: true }
+    }
+}
+
+fn get_settings_path() -> Result<PathBuf> {
+    Ok(crate::paths::data_dir()?.join("notifications.json"))
+}
+
+/// Load saved settings (defaults if missing or unreadable)
+pub fn load_settings() -> NotificationSettings {
+    get_settings_path()
+        .and_then(|path| crate::store_recovery::load_or_recover(&path, |json| Ok(serde_json::from_slice(json)?)))
+        .unwrap_or_default()
+}
+
+pub fn save_settings(settings: &NotificationSettings) -> Result<()> {
+    let json = serde_json::to_string_pretty(settings)?;
+    fs::write(get_settings_path()?, json).context("Failed to save notification settings")
+}
+
+/// Make `settings` take effect for subsequent notifications
+pub fn apply_settings(settings: &NotificationSettings) {
+    ENABLED.store(settings.enabled, Ordering::Relaxed);
+    SOUND_ENABLED.store(settings.sound, Ordering::Relaxed);
+}
+
+/// Flatten text for a single toast line: control characters (newlines, tabs)
+/// become spaces, whitespace runs collapse, and long text is cut with "…"
+pub fn sanitize(text: &str, max_chars: usize) -> String {
+    let flattened: String = text
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect();
+    let collapsed = flattened.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if collapsed.chars().count() <= max_chars {
+        return collapsed;
+    }
+    let mut cut: String = collapsed.chars().take(max_chars.saturating_sub(1)).collect();
+    cut.truncate(cut.trim_end().len());
+    cut.push('…');
+    cut
+}
+
+/// Show a toast, unless notifications are turned off
+pub fn show_notification(title: &str, message: &str) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let title = sanitize(title, MAX_TITLE_CHARS);
+    let body = sanitize(message, MAX_BODY_CHARS);
+    // Showing a toast can block briefly; keep it off the UI thread
+    std::thread::spawn(move || {
+        if let Err(e) = notify_rust::Notification::new()
+            .appname("CryptoChat")
+            .summary(&title)
+            .body(&body)
+            .show()
+        {
+            tracing::warn!(error = %e, "notification failed");
+        }
+    });
+}
+
+/// Play the system notification sound, unless notifications or sound are off
+pub fn play_notification_sound() {
+    if !ENABLED.load(Ordering::Relaxed) || !SOUND_ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    use windows::core::w;
+    use windows::Win32::Foundation::HMODULE;
+    use windows::Win32::Media::Audio::{PlaySoundW, SND_ALIAS, SND_ASYNC};
+
+    // SND_ASYNC returns immediately; the sound plays in the background
+    unsafe {
+        let _ = PlaySoundW(w!("SystemNotification"), HMODULE::default(), SND_ALIAS | SND_ASYNC);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitize_keeps_quotes_and_flattens_newlines() {
+        assert_eq!(
+            sanitize("He said \"hi\"\nand 'bye'\r\n\tok", 200),
+            "He said \"hi\" and 'bye' ok"
+        );
+        assert_eq!(sanitize("<b>&amp;</b>", 200), "<b>&amp;</b>");
+    }
+
+    #[test]
+    fn sanitize_truncates_long_text() {
+        let long = "word ".repeat(100);
+        let short = sanitize(&long, 20);
+        assert_eq!(short.chars().count(), 20);
+        assert!(short.ends_with('…'));
+        assert_eq!(sanitize("   ", 20), "");
+    }
+}
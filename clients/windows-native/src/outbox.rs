@@ -0,0 +1,157 @@
+//! Write-ahead log of outgoing direct messages
+//!
+//! A message is appended here before it is handed to the network and
+//! cleared once the send is confirmed. Whatever is still in the log at
+//! startup never got out, either because the app stopped mid-send or because
+//! the peer was offline, and the user is offered to resend it. Each line is
+//! one encrypted record, so a line torn by a crash is skipped on replay.
+
+use crate::encrypted_storage::{decrypt_data, derive_storage_key, encrypt_data, EncryptedStore};
+use crate::paths::data_dir;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// A direct message that hasn't been confirmed sent
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub message_id: String,
+    /// Conversation it belongs to: the recipient's fingerprint
+    pub conversation_id: String,
+    pub peer_address: String,
+    /// What goes over the wire before encryption (text or emote payload)
+    pub payload: String,
+    pub queued_ms: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+enum Record {
+    Append(OutboxEntry),
+    Clear(String),
+}
+
+fn get_outbox_path(fingerprint: &str) -> Result<PathBuf> {
+    Ok(data_dir()?.join(format!("outbox_{}.wal", fingerprint)))
+}
+
+fn write_record(path: &Path, record: &Record, key: &[u8; 32]) -> Result<()> {
+    // Start on a fresh line so a record torn by a crash can't swallow this one
+    let mut line = vec![b'\n'];
+    line.extend(serde_json::to_vec(&encrypt_data(record, key)?)?);
+    let mut file = OpenOptions::new().create(true).append(true).open(path).context("Failed to open outbox")?;
+    file.write_all(&line).context("Failed to write outbox")?;
+    file.sync_data().context("Failed to sync outbox")?;
+    Ok(())
+}
+
+fn replay(path: &Path, key: &[u8; 32]) -> Result<Vec<OutboxEntry>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).context("Failed to read outbox"),
+    };
+    let mut entries: Vec<OutboxEntry> = Vec::new();
+    for line in data.split(|&b| b == b'\n').filter(|l| !l.is_empty()) {
+        let record = serde_json::from_slice::<EncryptedStore>(line)
+            .map_err(anyhow::Error::from)
+            .and_then(|store| decrypt_data::<Record>(&store, key));
+        match record {
+            Ok(Record::Append(entry)) => {
+                entries.retain(|e| e.message_id != entry.message_id);
+                entries.push(entry);
+            }
+            Ok(Record::Clear(message_id)) => entries.retain(|e| e.message_id != message_id),
+            Err(e) => tracing::warn!(error = %e, "skipping unreadable outbox record"),
+        }
+    }
+    Ok(entries)
+}
+
+fn append_to(path: &Path, entry: &OutboxEntry, key: &[u8; 32]) -> Result<()> {
+    write_record(path, &Record::Append(entry.clone()), key)
+}
+
+/// Mark `message_id` sent. Once nothing is left the log is deleted, so it
+/// never grows past the messages actually in flight.
+fn clear_in(path: &Path, message_id: &str, key: &[u8; 32]) -> Result<()> {
+    write_record(path, &Record::Clear(message_id.to_string()), key)?;
+    if replay(path, key)?.is_empty() {
+        fs::remove_file(path).context("Failed to remove empty outbox")?;
+    }
+    Ok(())
+}
+
+/// Log a message before sending it
+pub fn append(entry: &OutboxEntry, fingerprint: &str) -> Result<()> {
+    append_to(&get_outbox_path(fingerprint)?, entry, &derive_storage_key(fingerprint))
+}
+
+/// Drop a message from the log once its send is confirmed (or abandoned)
+pub fn clear(message_id: &str, fingerprint: &str) -> Result<()> {
+    clear_in(&get_outbox_path(fingerprint)?, message_id, &derive_storage_key(fingerprint))
+}
+
+/// Messages logged but never confirmed sent, oldest first
+pub fn pending(fingerprint: &str) -> Result<Vec<OutboxEntry>> {
+    replay(&get_outbox_path(fingerprint)?, &derive_storage_key(fingerprint))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(id: &str) -> OutboxEntry {
+        OutboxEntry {
+            message_id: id.to_string(),
+            conversation_id: "FP_BOB".to_string(),
+            peer_address: "127.0.0.1:9000".to_string(),
+            payload: format!("message {}", id),
+            queued_ms: 1,
+        }
+    }
+
+    fn temp_outbox() -> PathBuf {
+        std::env::temp_dir().join(format!("cryptochat_outbox_{}.wal", uuid::Uuid::new_v4()))
+    }
+
+    #[test]
+    fn confirmed_sends_are_cleared_and_the_log_removed() {
+        let path = temp_outbox();
+        let key = derive_storage_key("fp-me");
+        append_to(&path, &entry("a"), &key).unwrap();
+        append_to(&path, &entry("b"), &key).unwrap();
+        assert_eq!(replay(&path, &key).unwrap(), vec![entry("a"), entry("b")]);
+
+        clear_in(&path, "a", &key).unwrap();
+        assert_eq!(replay(&path, &key).unwrap(), vec![entry("b")]);
+        assert!(!fs::read_to_string(&path).unwrap().contains("message b"));
+
+        clear_in(&path, "b", &key).unwrap();
+        assert!(!path.exists());
+        assert!(replay(&path, &key).unwrap().is_empty());
+    }
+
+    #[test]
+    fn startup_replay_survives_a_torn_write() {
+        let path = temp_outbox();
+        let key = derive_storage_key("fp-me");
+        append_to(&path, &entry("a"), &key).unwrap();
+        append_to(&path, &entry("b"), &key).unwrap();
+        clear_in(&path, "a", &key).unwrap();
+        // Retrying a message logs it again without duplicating it
+        append_to(&path, &entry("b"), &key).unwrap();
+
+        // The app died halfway through writing a record, then logged another
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(b"\n{\"iv\":[1,2,3],\"ciphe").unwrap();
+        drop(file);
+        append_to(&path, &entry("c"), &key).unwrap();
+
+        assert_eq!(replay(&path, &key).unwrap(), vec![entry("b"), entry("c")]);
+        // Another key can't read anything back
+        assert!(replay(&path, &derive_storage_key("someone-else")).unwrap().is_empty());
+        let _ = fs::remove_file(&path);
+    }
+}
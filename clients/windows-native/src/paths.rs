@@ -0,0 +1,103 @@
+//! Where the client keeps its files
+//!
+//! Everything the app stores lives under one data directory, resolved in
+//! this order:
+//! 1. `CRYPTOCHAT_DATA_DIR`, used as-is (tests, portable installs)
+//! 2. `~/.cryptochat` (or `~/.cryptochat_<instance>`) if it already exists,
+//!    so installs from before this change keep their data
+//! 3. The OS data directory, e.g. `%APPDATA%\CryptoChat` (or
+//!    `CryptoChat_<instance>`)
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const DATA_DIR_ENV: &str = "CRYPTOCHAT_DATA_DIR";
+
+fn resolve_data_dir(
+    override_dir: Option<PathBuf>,
+    home: Option<&Path>,
+    os_data: Option<&Path>,
+    instance: Option<u32>,
+) -> Option<PathBuf> {
+    if let Some(dir) = override_dir.filter(|d| !d.as_os_str().is_empty()) {
+        return Some(dir);
+    }
+    let suffix = instance.map(|id| format!("_{}", id)).unwrap_or_default();
+    let legacy = home.map(|h| h.join(format!(".cryptochat{}", suffix)));
+    if let Some(legacy) = legacy.as_ref().filter(|d| d.is_dir()) {
+        return Some(legacy.clone());
+    }
+    os_data.map(|d| d.join(format!("CryptoChat{}", suffix))).or(legacy)
+}
+
+/// The data directory, created if missing
+pub fn data_dir() -> Result<PathBuf> {
+    let dir = resolve_data_dir(
+        std::env::var_os(DATA_DIR_ENV).map(PathBuf::from),
+        dirs::home_dir().as_deref(),
+        dirs::data_dir().as_deref(),
+        crate::get_instance_id(),
+    )
+    .context("Could not find a data directory")?;
+    if !dir.exists() {
+        fs::create_dir_all(&dir).context("Failed to create data directory")?;
+    }
+    Ok(dir)
+}
+
+/// Where saved images and exported transcripts go
+pub fn downloads_dir() -> PathBuf {
+    dirs::download_dir()
+        .or_else(|| data_dir().ok().map(|d| d.join("downloads")))
+        .unwrap_or_else(std::env::temp_dir)
+}
+
+/// Scratch file for handing data to another process
+pub fn temp_file(name: &str) -> PathBuf {
+    std::env::temp_dir().join(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("cryptochat_paths_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn override_wins() {
+        let home = scratch();
+        fs::create_dir_all(home.join(".cryptochat")).unwrap();
+        let chosen = resolve_data_dir(Some(PathBuf::from("/portable/data")), Some(&home), Some(Path::new("/appdata")), Some(2));
+        assert_eq!(chosen, Some(PathBuf::from("/portable/data")));
+
+        // An empty override is ignored
+        let chosen = resolve_data_dir(Some(PathBuf::new()), Some(&home), None, None);
+        assert_eq!(chosen, Some(home.join(".cryptochat")));
+        let _ = fs::remove_dir_all(&home);
+    }
+
+    #[test]
+    fn existing_install_keeps_its_directory_otherwise_os_default() {
+        let home = scratch();
+        let appdata = Path::new("/appdata");
+
+        // Fresh install: the OS data directory, per instance
+        assert_eq!(resolve_data_dir(None, Some(&home), Some(appdata), None), Some(appdata.join("CryptoChat")));
+        assert_eq!(resolve_data_dir(None, Some(&home), Some(appdata), Some(2)), Some(appdata.join("CryptoChat_2")));
+
+        // Data from before: stay where it is
+        fs::create_dir_all(home.join(".cryptochat_2")).unwrap();
+        assert_eq!(resolve_data_dir(None, Some(&home), Some(appdata), Some(2)), Some(home.join(".cryptochat_2")));
+        assert_eq!(resolve_data_dir(None, Some(&home), Some(appdata), None), Some(appdata.join("CryptoChat")));
+
+        // No OS data directory known: fall back to the home directory
+        assert_eq!(resolve_data_dir(None, Some(&home), None, None), Some(home.join(".cryptochat")));
+        assert_eq!(resolve_data_dir(None, None, None, None), None);
+        let _ = fs::remove_dir_all(&home);
+    }
+}
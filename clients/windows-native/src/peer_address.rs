@@ -0,0 +1,168 @@
+//! Parsing for peer addresses: `IPv4:port`, `[IPv6]:port` and `hostname:port`.
+//!
+//! Addresses used to be assumed to look like `127.0.0.1:5000` and were split on
+//! the first/last colon, which breaks on IPv6 literals. Everything that reads a
+//! peer address should go through [`PeerAddress`] instead.
+
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+use std::str::FromStr;
+
+/// Host part of a peer address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Host {
+    Ip(IpAddr),
+    Name(String),
+}
+
+/// A validated `host:port` peer address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerAddress {
+    pub host: Host,
+    pub port: u16,
+}
+
+/// Why a peer address was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    MissingPort,
+    InvalidPort(String),
+    InvalidHost(String),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::MissingPort => write!(f, "Address needs a port (e.g. 192.168.1.5:62780)"),
+            AddressError::InvalidPort(p) => write!(f, "Invalid port {:?} (must be 1-65535)", p),
+            AddressError::InvalidHost(h) => write!(f, "Invalid host {:?}", h),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+impl PeerAddress {
+    /// Same host on a different port (e.g. the sender's listening port)
+    pub fn with_port(&self, port: u16) -> Self {
+        Self { host: self.host.clone(), port }
+    }
+
+    /// Host as it appears in an address string (IPv6 bracketed)
+    pub fn host_display(&self) -> String {
+        match &self.host {
+            Host::Ip(IpAddr::V6(ip)) => format!("[{}]", ip),
+            Host::Ip(IpAddr::V4(ip)) => ip.to_string(),
+            Host::Name(name) => name.clone(),
+        }
+    }
+}
+
+impl fmt::Display for PeerAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.host_display(), self.port)
+    }
+}
+
+impl FromStr for PeerAddress {
+    type Err = AddressError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        let input = input.trim();
+
+        let (host, port) = if let Some(rest) = input.strip_prefix('[') {
+            let (ip, after) = rest.split_once(']')
+                .ok_or_else(|| AddressError::InvalidHost(input.to_string()))?;
+            let ip: Ipv6Addr = ip.parse()
+                .map_err(|_| AddressError::InvalidHost(ip.to_string()))?;
+            let port = after.strip_prefix(':').ok_or(AddressError::MissingPort)?;
+            (Host::Ip(IpAddr::V6(ip)), port)
+        } else {
+            let (host, port) = input.rsplit_once(':').ok_or(AddressError::MissingPort)?;
+            if host.contains(':') {
+                // Bare IPv6 is ambiguous: is the last group the port?
+                return Err(AddressError::InvalidHost(format!("{} (put IPv6 addresses in brackets, e.g. [::1]:5000)", host)));
+            }
+            (parse_host(host)?, port)
+        };
+
+        let port = match port.parse::<u16>() {
+            Ok(p) if p != 0 => p,
+            _ => return Err(AddressError::InvalidPort(port.to_string())),
+        };
+        Ok(Self { host, port })
+    }
+}
+
+fn parse_host(host: &str) -> Result<Host, AddressError> {
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(Host::Ip(ip));
+    }
+    // Digits and dots that didn't parse are a malformed IPv4, not a hostname
+    let looks_numeric = host.chars().all(|c| c.is_ascii_digit() || c == '.');
+    let valid_name = !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= 63
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    if looks_numeric || !valid_name {
+        return Err(AddressError::InvalidHost(host.to_string()));
+    }
+    Ok(Host::Name(host.to_ascii_lowercase()))
+}
+
+/// Host of the socket a connection came from, formatted for building an
+/// address with a different port. Falls back to loopback if unparseable.
+pub fn connection_host(socket_addr: &str) -> String {
+    socket_addr.parse::<PeerAddress>()
+        .map(|addr| addr.host_display())
+        .unwrap_or_else(|_| "127.0.0.1".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_ipv4() {
+        let addr: PeerAddress = "192.168.1.5:62780".parse().unwrap();
+        assert_eq!(addr.host, Host::Ip("192.168.1.5".parse().unwrap()));
+        assert_eq!(addr.port, 62780);
+        assert_eq!(addr.to_string(), "192.168.1.5:62780");
+    }
+
+    #[test]
+    fn parses_bracketed_ipv6() {
+        let addr: PeerAddress = "[::1]:5000".parse().unwrap();
+        assert_eq!(addr.host, Host::Ip("::1".parse().unwrap()));
+        assert_eq!(addr.port, 5000);
+        assert_eq!(addr.to_string(), "[::1]:5000");
+        assert_eq!(addr.with_port(6000).to_string(), "[::1]:6000");
+        assert_eq!(connection_host("[fe80::1]:4444"), "[fe80::1]");
+    }
+
+    #[test]
+    fn parses_hostname() {
+        let addr: PeerAddress = "Alice-PC.local:62780".parse().unwrap();
+        assert_eq!(addr.host, Host::Name("alice-pc.local".to_string()));
+        assert_eq!(addr.to_string(), "alice-pc.local:62780");
+    }
+
+    #[test]
+    fn rejects_malformed_addresses() {
+        assert_eq!("127.0.0.1".parse::<PeerAddress>(), Err(AddressError::MissingPort));
+        assert_eq!("[::1]".parse::<PeerAddress>(), Err(AddressError::MissingPort));
+        assert!(matches!("127.0.0.1:".parse::<PeerAddress>(), Err(AddressError::InvalidPort(_))));
+        assert!(matches!("127.0.0.1:0".parse::<PeerAddress>(), Err(AddressError::InvalidPort(_))));
+        assert!(matches!("127.0.0.1:70000".parse::<PeerAddress>(), Err(AddressError::InvalidPort(_))));
+        assert!(matches!("::1:5000".parse::<PeerAddress>(), Err(AddressError::InvalidHost(_))));
+        assert!(matches!("[::1:5000".parse::<PeerAddress>(), Err(AddressError::InvalidHost(_))));
+        assert!(matches!("999.1.1.1:5000".parse::<PeerAddress>(), Err(AddressError::InvalidHost(_))));
+        assert!(matches!("bad_host!:5000".parse::<PeerAddress>(), Err(AddressError::InvalidHost(_))));
+        assert_eq!(connection_host("garbage"), "127.0.0.1");
+    }
+}
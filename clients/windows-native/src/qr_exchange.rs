@@ -41,7 +41,8 @@ pub struct QrPayload {
     ts: i64,
 
     /// Self-signature over {v, fp, pk, ts} using private key
-    /// This proves the QR creator had access to the private key
+    /// This proves the QR creator had access to the private key.
+    /// Either base64 of a binary signature or an armored PGP signature.
     sig: String,
 }
 
@@ -64,6 +65,16 @@ impl QrPayload {
 
     /// Create a new QR payload with automatic signature generation
     pub fn create_and_sign(keypair: &PgpKeyPair) -> Result<Self> {
+        Self::create_signed(keypair, false)
+    }
+
+    /// Like `create_and_sign`, but with an armored PGP signature that other
+    /// OpenPGP tools can check (larger, so the QR gets denser)
+    pub fn create_and_sign_armored(keypair: &PgpKeyPair) -> Result<Self> {
+        Self::create_signed(keypair, true)
+    }
+
+    fn create_signed(keypair: &PgpKeyPair, armored: bool) -> Result<Self> {
         let fingerprint = keypair.fingerprint();
         let public_key = keypair.export_public_key()
             .context("Failed to export public key")?;
@@ -75,9 +86,14 @@ impl QrPayload {
         let message = format!("{}{}{}{}", QR_VERSION, fingerprint, public_key, timestamp);
 
         // Sign the message
-        let signature_bytes = keypair.sign(message.as_bytes())
-            .context("Failed to sign QR payload")?;
-        let signature = BASE64.encode(&signature_bytes);
+        let signature = if armored {
+            keypair.sign_detached(message.as_bytes())
+                .context("Failed to sign QR payload")?
+        } else {
+            let signature_bytes = keypair.sign(message.as_bytes())
+                .context("Failed to sign QR payload")?;
+            BASE64.encode(&signature_bytes)
+        };
 
         Ok(Self {
             v: QR_VERSION,
@@ -141,12 +157,15 @@ impl QrPayload {
         // Recreate the canonical message that was signed
         let message = format!("{}{}{}{}", self.v, self.fp, self.pk, self.ts);
 
-        // Decode the signature from base64
-        let signature_bytes = BASE64.decode(&self.sig)
-            .context("Failed to decode signature")?;
-
-        // Verify the signature
-        PgpKeyPair::verify(keypair.cert(), message.as_bytes(), &signature_bytes)
+        // Verify the signature (armored or base64 binary)
+        let verified = if self.sig.starts_with("-----BEGIN PGP SIGNATURE-----") {
+            PgpKeyPair::verify_detached(keypair.cert(), message.as_bytes(), &self.sig)
+        } else {
+            let signature_bytes = BASE64.decode(&self.sig)
+                .context("Failed to decode signature")?;
+            PgpKeyPair::verify(keypair.cert(), message.as_bytes(), &signature_bytes)
+        };
+        verified
             .context("SECURITY WARNING: Signature verification failed! This QR code may have been forged or tampered with.")?;
 
         Ok(())
@@ -195,8 +214,12 @@ pub fn scan_qr_from_file(path: &str) -> Result<QrPayload> {
     let img = image::open(path)
         .context("Failed to open image file")?;
     
-    let img_luma = img.to_luma8();
-    let mut img_prepared = PreparedImage::prepare(img_luma);
+    scan_qr_from_image(img.to_luma8())
+}
+
+/// Decode and validate a QR payload from an in-memory image
+pub fn scan_qr_from_image(img: ImageBuffer<Luma<u8>, Vec<u8>>) -> Result<QrPayload> {
+    let mut img_prepared = PreparedImage::prepare(img);
     let grids = img_prepared.detect_grids();
     
     if grids.is_empty() {
@@ -235,3 +258,31 @@ pub fn format_fingerprint(fp: &str) -> String {
         .collect::<Vec<_>>()
         .join(" ")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_qr_image_scans_back_to_payload() {
+        let keypair = PgpKeyPair::generate("qr@example.com").unwrap();
+        let payload = QrPayload::create_and_sign(&keypair).unwrap();
+
+        let img = generate_qr_image(&payload).unwrap();
+        let scanned = scan_qr_from_image(img).unwrap();
+
+        assert_eq!(scanned.fp, keypair.fingerprint());
+        assert_eq!(scanned.public_key(), payload.public_key());
+    }
+
+    #[test]
+    fn armored_qr_still_fits_and_verifies() {
+        let keypair = PgpKeyPair::generate("qr@example.com").unwrap();
+        let payload = QrPayload::create_and_sign_armored(&keypair).unwrap();
+        assert!(payload.sig.starts_with("-----BEGIN PGP SIGNATURE-----"));
+
+        // Scanning validates the signature
+        let scanned = scan_qr_from_image(generate_qr_image(&payload).unwrap()).unwrap();
+        assert_eq!(scanned.fp, keypair.fingerprint());
+    }
+}
@@ -0,0 +1,136 @@
+//! Flood protection for the direct-connection listener
+//!
+//! Every inbound envelope arrives on its own connection, so the listener
+//! counts connections per source host in a fixed window and drops the excess
+//! before reading anything. Typing indicators get extra coalescing: a repeat of
+//! the state already forwarded is dropped unless enough time has passed, while
+//! a change (started/stopped typing) always goes through.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Envelopes accepted from one host per window
+pub const MAX_ENVELOPES_PER_WINDOW: u32 = 30;
+pub const WINDOW: Duration = Duration::from_secs(1);
+/// Shortest gap between two identical typing updates from one host
+pub const TYPING_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Sources tracked before idle ones are pruned
+const MAX_TRACKED_SOURCES: usize = 1024;
+
+/// What to do with an incoming connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    Accept,
+    /// Over the limit; drop silently
+    Drop,
+    /// Just went over the limit; drop and report it once
+    Throttle,
+}
+
+#[derive(Debug)]
+struct SourceState {
+    window_start: Instant,
+    count: u32,
+    /// Last typing state forwarded and when
+    last_typing: Option<(bool, Instant)>,
+}
+
+/// Per-source counters, shared by the listener's connection threads
+#[derive(Debug)]
+pub struct InboundLimiter {
+    max_per_window: u32,
+    window: Duration,
+    typing_interval: Duration,
+    sources: HashMap<String, SourceState>,
+}
+
+impl Default for InboundLimiter {
+    fn default() -> Self {
+        Self::new(MAX_ENVELOPES_PER_WINDOW, WINDOW, TYPING_INTERVAL)
+    }
+}
+
+impl InboundLimiter {
+    pub fn new(max_per_window: u32, window: Duration, typing_interval: Duration) -> Self {
+        Self { max_per_window, window, typing_interval, sources: HashMap::new() }
+    }
+
+    fn source(&mut self, host: &str, now: Instant) -> &mut SourceState {
+        if self.sources.len() >= MAX_TRACKED_SOURCES && !self.sources.contains_key(host) {
+            let window = self.window;
+            self.sources.retain(|_, s| now.duration_since(s.window_start) < window);
+        }
+        self.sources.entry(host.to_string()).or_insert(SourceState {
+            window_start: now,
+            count: 0,
+            last_typing: None,
+        })
+    }
+
+    /// Count a new connection from `host`
+    pub fn admit(&mut self, host: &str, now: Instant) -> Admission {
+        let (max, window) = (self.max_per_window, self.window);
+        let source = self.source(host, now);
+        if now.duration_since(source.window_start) >= window {
+            source.window_start = now;
+            source.count = 0;
+        }
+        source.count = source.count.saturating_add(1);
+
+        match source.count {
+            n if n <= max => Admission::Accept,
+            n if n == max + 1 => Admission::Throttle,
+            _ => Admission::Drop,
+        }
+    }
+
+    /// Whether a typing update from `host` should reach the UI
+    pub fn forward_typing(&mut self, host: &str, is_typing: bool, now: Instant) -> bool {
+        let interval = self.typing_interval;
+        let source = self.source(host, now);
+        let forward = match source.last_typing {
+            Some((last, at)) => last != is_typing || now.duration_since(at) >= interval,
+            None => true,
+        };
+        if forward {
+            source.last_typing = Some((is_typing, now));
+        }
+        forward
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_past_threshold_and_reports_once() {
+        let mut limiter = InboundLimiter::new(3, Duration::from_secs(1), TYPING_INTERVAL);
+        let start = Instant::now();
+
+        let results: Vec<_> = (0..6).map(|_| limiter.admit("10.0.0.5", start)).collect();
+        assert_eq!(
+            results,
+            [Admission::Accept, Admission::Accept, Admission::Accept, Admission::Throttle, Admission::Drop, Admission::Drop]
+        );
+        // Other hosts are unaffected, and the flooder recovers in the next window
+        assert_eq!(limiter.admit("10.0.0.6", start), Admission::Accept);
+        assert_eq!(limiter.admit("10.0.0.5", start + Duration::from_secs(1)), Admission::Accept);
+    }
+
+    #[test]
+    fn coalesces_rapid_typing_updates() {
+        let mut limiter = InboundLimiter::new(100, Duration::from_secs(1), Duration::from_millis(500));
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert!(limiter.forward_typing("10.0.0.5", true, at(0)));
+        assert!(!limiter.forward_typing("10.0.0.5", true, at(100)));
+        assert!(!limiter.forward_typing("10.0.0.5", true, at(400)));
+        // Stopping is never held back
+        assert!(limiter.forward_typing("10.0.0.5", false, at(450)));
+        assert!(limiter.forward_typing("10.0.0.5", true, at(460)));
+        assert!(limiter.forward_typing("10.0.0.5", true, at(960)));
+    }
+}
@@ -0,0 +1,180 @@
+//! Falling back to a node relay when a peer can't be reached directly
+//!
+//! Peers behind NAT often can't accept the direct TCP connection every send
+//! starts with. When that connection fails and a relay node is configured
+//! (`CRYPTOCHAT_RELAY_URL`, e.g. `http://relay.example:8080`), the envelope is
+//! posted to the node's `/relay` mailbox under the recipient's fingerprint
+//! instead. Each client polls its own mailbox, proving it holds the key by
+//! signing the node's challenge, handles what it finds exactly like a direct
+//! delivery and acknowledges it on the next poll. The node only ever sees the
+//! serialized envelope, whose message body is already encrypted for the
+//! recipient.
+
+use crate::network::{MessageEnvelope, NetworkHandle};
+use anyhow::{Context, Result};
+use cryptochat_crypto_core::pgp::PgpKeyPair;
+use cryptochat_node::relay::{fetch_statement, RelayChallenge, RelayFetch, RelaySubmit, RelayedMessage};
+use std::io::ErrorKind;
+use std::time::Duration;
+
+/// How often the mailbox is polled while a relay is configured
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How a message reached the network
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Route {
+    Direct,
+    Relay,
+}
+
+/// What to do after a direct send attempt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NextStep {
+    Done,
+    TryRelay,
+    GiveUp,
+}
+
+/// The relay node's base URL, if one is configured
+pub fn relay_url() -> Option<String> {
+    std::env::var("CRYPTOCHAT_RELAY_URL")
+        .ok()
+        .map(|url| url.trim().trim_end_matches('/').to_string())
+        .filter(|url| !url.is_empty())
+}
+
+/// Whether `err` means the peer couldn't be reached, as opposed to the
+/// message itself being unsendable (bad address, over the size limit)
+fn is_unreachable(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<std::io::Error>().is_some_and(|e| {
+        matches!(
+            e.kind(),
+            ErrorKind::ConnectionRefused
+                | ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::TimedOut
+                | ErrorKind::HostUnreachable
+                | ErrorKind::NetworkUnreachable
+                | ErrorKind::AddrNotAvailable
+        )
+    })
+}
+
+/// Decide whether a failed direct send should go through the relay
+pub fn next_step(direct: &Result<()>, relay_configured: bool) -> NextStep {
+    match direct {
+        Ok(()) => NextStep::Done,
+        Err(e) if relay_configured && is_unreachable(e) => NextStep::TryRelay,
+        Err(_) => NextStep::GiveUp,
+    }
+}
+
+fn agent() -> ureq::Agent {
+    ureq::AgentBuilder::new().timeout(Duration::from_secs(10)).build()
+}
+
+/// Post `envelope` to the relay's mailbox for `recipient_fingerprint`
+pub fn submit(
+    relay_url: &str,
+    recipient_fingerprint: &str,
+    envelope: &MessageEnvelope,
+    sender_fingerprint: &str,
+    sender_address: &str,
+) -> Result<()> {
+    let body = RelaySubmit {
+        recipient_fingerprint: recipient_fingerprint.to_string(),
+        sender_fingerprint: sender_fingerprint.to_string(),
+        sender_address: sender_address.to_string(),
+        payload: serde_json::to_string(envelope)?,
+    };
+    agent()
+        .post(&format!("{}/relay", relay_url))
+        .send_json(&body)
+        .context("Relay rejected the message")?;
+    Ok(())
+}
+
+/// Everything waiting in our mailbox on the relay, after deleting the
+/// messages in `ack` (ids from the previous fetch that we've handled)
+pub fn fetch(relay_url: &str, keypair: &PgpKeyPair, ack: &[u64]) -> Result<Vec<RelayedMessage>> {
+    let fingerprint = keypair.fingerprint();
+    let RelayChallenge { challenge } = agent()
+        .get(&format!("{}/relay/{}/challenge", relay_url, fingerprint))
+        .call()
+        .context("Could not reach relay")?
+        .into_json()
+        .context("Relay sent an unreadable challenge")?;
+    let body = RelayFetch {
+        public_key: keypair.export_public_key()?,
+        signature: keypair.sign_detached(&fetch_statement(&fingerprint, &challenge))?,
+        challenge,
+        ack: ack.to_vec(),
+    };
+    agent()
+        .post(&format!("{}/relay/{}/fetch", relay_url, fingerprint))
+        .send_json(&body)
+        .context("Relay refused the mailbox fetch")?
+        .into_json()
+        .context("Relay sent an unreadable mailbox")
+}
+
+/// Send directly, falling back to the relay if the peer is unreachable
+pub fn send_with_fallback(
+    peer_address: &str,
+    recipient_fingerprint: &str,
+    envelope: MessageEnvelope,
+    sender_fingerprint: &str,
+    sender_address: &str,
+) -> Result<Route> {
+    let relay = relay_url();
+    let direct = NetworkHandle::send_message(peer_address, envelope.clone());
+    match next_step(&direct, relay.is_some()) {
+        NextStep::Done => Ok(Route::Direct),
+        NextStep::GiveUp => direct.map(|()| Route::Direct),
+        NextStep::TryRelay => {
+            let relay = relay.unwrap_or_default();
+            tracing::info!(peer = %peer_address, "peer unreachable, sending through relay");
+            submit(&relay, recipient_fingerprint, &envelope, sender_fingerprint, sender_address)
+                .map(|()| Route::Relay)
+                .with_context(|| format!("Direct send failed ({})", direct.unwrap_err()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn io_err(kind: ErrorKind) -> Result<()> {
+        Err(io::Error::from(kind).into())
+    }
+
+    #[test]
+    fn only_unreachable_peers_fall_back_to_the_relay() {
+        assert_eq!(next_step(&Ok(()), true), NextStep::Done);
+        assert_eq!(next_step(&io_err(ErrorKind::ConnectionRefused), true), NextStep::TryRelay);
+        assert_eq!(next_step(&io_err(ErrorKind::TimedOut), true), NextStep::TryRelay);
+        assert_eq!(next_step(&io_err(ErrorKind::HostUnreachable), true), NextStep::TryRelay);
+        // Nowhere to fall back to
+        assert_eq!(next_step(&io_err(ErrorKind::ConnectionRefused), false), NextStep::GiveUp);
+        // The relay wouldn't help with a message that can't be sent at all
+        assert_eq!(next_step(&Err(anyhow::anyhow!("Message too large")), true), NextStep::GiveUp);
+        assert_eq!(next_step(&io_err(ErrorKind::InvalidInput), true), NextStep::GiveUp);
+    }
+
+    #[test]
+    fn refused_connection_is_unreachable() {
+        // Grab a free port, then close it so nothing is listening there
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let envelope = MessageEnvelope::RegularMessage {
+            encrypted_payload: "ciphertext".to_string(),
+            sender_name: None,
+            sender_fingerprint: "FP".to_string(),
+            sender_listening_port: 9000,
+            message_id: None,
+        };
+        let direct = NetworkHandle::send_message(&format!("127.0.0.1:{}", port), envelope);
+        assert_eq!(next_step(&direct, true), NextStep::TryRelay);
+    }
+}
@@ -0,0 +1,271 @@
+//! Replay protection for incoming connection requests
+//!
+//! Each `Request` envelope carries a random nonce and the time it was sent.
+//! A request is only handled if it is recent and its nonce hasn't been seen
+//! before, so a declined request can't simply be delivered again and a
+//! captured envelope stops working once it falls out of the window. Nonces
+//! are only remembered for as long as the window lasts, since anything older
+//! is refused on its timestamp anyway.
+//!
+//! The stamp is signed with the sender's key, so a relay or eavesdropper
+//! can't refresh an old request with a new nonce and time. Clients that
+//! predate replay protection send no stamp at all; their requests are still
+//! taken, just without these checks.
+//!
+//! The `AcceptedResponse` a peer sends back carries the same kind of stamp
+//! over a separate statement, so a request's stamp can't be passed off as an
+//! answer or the other way round.
+
+use cryptochat_crypto_core::pgp::PgpKeyPair;
+use cryptochat_messaging::requests::RequestKind;
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+
+/// Oldest request accepted, by the sender's clock
+pub const MAX_REQUEST_AGE_MS: i64 = 5 * 60 * 1000;
+/// How far ahead of our clock a sender's may be
+pub const MAX_CLOCK_SKEW_MS: i64 = 60 * 1000;
+/// Nonces remembered at most; the oldest are forgotten first
+pub const MAX_SEEN_NONCES: usize = 1024;
+
+/// Why a request was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayRejection {
+    /// No nonce, from a client that predates replay protection
+    MissingNonce,
+    /// Sent longer ago than the window allows
+    Expired { age_ms: i64 },
+    /// Timestamped further in the future than clock skew explains
+    FromFuture,
+    /// Nonce already handled
+    Replayed,
+    /// Stamp not signed by the sender's key
+    BadSignature,
+}
+
+/// Nonce, send time and signature of a `Request` envelope
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestStamp {
+    pub nonce: String,
+    pub sent_ms: i64,
+    /// Armored detached signature over [`statement`]
+    pub signature: String,
+}
+
+impl RequestStamp {
+    /// A fresh stamp for a request from `keypair`
+    pub fn sign(keypair: &PgpKeyPair, kind: RequestKind, sent_ms: i64) -> anyhow::Result<Self> {
+        let nonce = uuid::Uuid::new_v4().to_string();
+        let signature = keypair.sign_detached(&statement(&keypair.fingerprint(), kind, &nonce, sent_ms))?;
+        Ok(Self { nonce, sent_ms, signature })
+    }
+
+    /// A fresh stamp for an accepted response from `keypair`
+    pub fn sign_response(keypair: &PgpKeyPair, sent_ms: i64) -> anyhow::Result<Self> {
+        let nonce = uuid::Uuid::new_v4().to_string();
+        let signature = keypair.sign_detached(&response_statement(&keypair.fingerprint(), &nonce, sent_ms))?;
+        Ok(Self { nonce, sent_ms, signature })
+    }
+
+    /// No stamp at all: sent by a client that predates replay protection
+    pub fn is_legacy(&self) -> bool {
+        self.nonce.is_empty() && self.sent_ms == 0 && self.signature.is_empty()
+    }
+}
+
+/// What a request stamp signs
+pub fn statement(fingerprint: &str, kind: RequestKind, nonce: &str, sent_ms: i64) -> Vec<u8> {
+    format!("cryptochat-request-v1:{}:{:?}:{}:{}", fingerprint, kind, nonce, sent_ms).into_bytes()
+}
+
+/// What an accepted response's stamp signs
+pub fn response_statement(fingerprint: &str, nonce: &str, sent_ms: i64) -> Vec<u8> {
+    format!("cryptochat-accepted-v1:{}:{}:{}", fingerprint, nonce, sent_ms).into_bytes()
+}
+
+impl fmt::Display for ReplayRejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayRejection::MissingNonce => write!(f, "Request has no nonce"),
+            ReplayRejection::Expired { age_ms } => write!(f, "Request was sent {}s ago", age_ms / 1000),
+            ReplayRejection::FromFuture => write!(f, "Request is timestamped in the future"),
+            ReplayRejection::Replayed => write!(f, "Request was already received"),
+            ReplayRejection::BadSignature => write!(f, "Request stamp is not signed by the sender"),
+        }
+    }
+}
+
+/// Recently handled request nonces
+#[derive(Debug, Default)]
+pub struct ReplayGuard {
+    seen: HashSet<String>,
+    /// Nonces with their send time, oldest first
+    order: VecDeque<(String, i64)>,
+}
+
+impl ReplayGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn forget_expired(&mut self, now_ms: i64) {
+        while let Some((nonce, sent_ms)) = self.order.front() {
+            if now_ms - sent_ms <= MAX_REQUEST_AGE_MS && self.order.len() < MAX_SEEN_NONCES {
+                break;
+            }
+            self.seen.remove(nonce);
+            self.order.pop_front();
+        }
+    }
+
+    /// Check a request sent at `sent_ms` with `nonce`, and remember the nonce
+    /// if it is accepted
+    pub fn check(&mut self, nonce: &str, sent_ms: i64, now_ms: i64) -> Result<(), ReplayRejection> {
+        if nonce.is_empty() {
+            return Err(ReplayRejection::MissingNonce);
+        }
+        let age_ms = now_ms - sent_ms;
+        if age_ms > MAX_REQUEST_AGE_MS {
+            return Err(ReplayRejection::Expired { age_ms });
+        }
+        if age_ms < -MAX_CLOCK_SKEW_MS {
+            return Err(ReplayRejection::FromFuture);
+        }
+        self.forget_expired(now_ms);
+        if !self.seen.insert(nonce.to_string()) {
+            return Err(ReplayRejection::Replayed);
+        }
+        self.order.push_back((nonce.to_string(), sent_ms));
+        Ok(())
+    }
+
+    /// Check a request's stamp against the key it came with. Legacy requests
+    /// pass; stamped ones need the sender's signature as well as a fresh time
+    /// and an unseen nonce.
+    pub fn check_request(
+        &mut self,
+        stamp: &RequestStamp,
+        sender_fingerprint: &str,
+        sender_public_key: &str,
+        kind: RequestKind,
+        now_ms: i64,
+    ) -> Result<(), ReplayRejection> {
+        let statement = statement(sender_fingerprint, kind, &stamp.nonce, stamp.sent_ms);
+        self.check_signed(stamp, sender_fingerprint, sender_public_key, &statement, now_ms)
+    }
+
+    /// Check an accepted response's stamp the same way as a request's
+    pub fn check_response(
+        &mut self,
+        stamp: &RequestStamp,
+        sender_fingerprint: &str,
+        sender_public_key: &str,
+        now_ms: i64,
+    ) -> Result<(), ReplayRejection> {
+        let statement = response_statement(sender_fingerprint, &stamp.nonce, stamp.sent_ms);
+        self.check_signed(stamp, sender_fingerprint, sender_public_key, &statement, now_ms)
+    }
+
+    fn check_signed(
+        &mut self,
+        stamp: &RequestStamp,
+        sender_fingerprint: &str,
+        sender_public_key: &str,
+        statement: &[u8],
+        now_ms: i64,
+    ) -> Result<(), ReplayRejection> {
+        if stamp.is_legacy() {
+            return Ok(());
+        }
+        let signed = PgpKeyPair::parse_public_key(sender_public_key)
+            .ok()
+            .filter(|key| key.fingerprint() == sender_fingerprint)
+            .is_some_and(|key| PgpKeyPair::verify_detached(key.cert(), statement, &stamp.signature).is_ok());
+        if !signed {
+            return Err(ReplayRejection::BadSignature);
+        }
+        self.check(&stamp.nonce, stamp.sent_ms, now_ms)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replayed_nonce_is_rejected() {
+        let mut guard = ReplayGuard::new();
+        assert_eq!(guard.check("n1", 1_000, 2_000), Ok(()));
+        assert_eq!(guard.check("n1", 1_000, 3_000), Err(ReplayRejection::Replayed));
+        assert_eq!(guard.check("n2", 1_000, 3_000), Ok(()));
+        assert_eq!(guard.check("", 1_000, 3_000), Err(ReplayRejection::MissingNonce));
+
+        // The record stays bounded, and a forgotten nonce is still too old to use
+        for i in 0..MAX_SEEN_NONCES * 2 {
+            guard.check(&format!("fill{}", i), 1_000, 3_000).unwrap();
+        }
+        assert!(guard.order.len() <= MAX_SEEN_NONCES);
+        assert_eq!(guard.seen.len(), guard.order.len());
+    }
+
+    #[test]
+    fn stamps_must_be_signed_by_the_sender() {
+        let alice = PgpKeyPair::generate("alice").unwrap();
+        let mallory = PgpKeyPair::generate("mallory").unwrap();
+        let (fp, key) = (alice.fingerprint(), alice.export_public_key().unwrap());
+        let now = 1_000_000;
+        let mut guard = ReplayGuard::new();
+
+        let stamp = RequestStamp::sign(&alice, RequestKind::Chat, now).unwrap();
+        assert_eq!(guard.check_request(&stamp, &fp, &key, RequestKind::Chat, now), Ok(()));
+        assert_eq!(guard.check_request(&stamp, &fp, &key, RequestKind::Chat, now), Err(ReplayRejection::Replayed));
+
+        // Refreshed nonce or time, a different kind, or someone else's key
+        let fresh = RequestStamp { nonce: "new".to_string(), ..stamp.clone() };
+        assert_eq!(guard.check_request(&fresh, &fp, &key, RequestKind::Chat, now), Err(ReplayRejection::BadSignature));
+        let stamp = RequestStamp::sign(&alice, RequestKind::Chat, now).unwrap();
+        assert_eq!(guard.check_request(&stamp, &fp, &key, RequestKind::KeyReexchange, now), Err(ReplayRejection::BadSignature));
+        let forged = RequestStamp::sign(&mallory, RequestKind::Chat, now).unwrap();
+        assert_eq!(guard.check_request(&forged, &fp, &key, RequestKind::Chat, now), Err(ReplayRejection::BadSignature));
+
+        // Older clients don't stamp requests at all
+        assert!(RequestStamp::default().is_legacy());
+        assert_eq!(guard.check_request(&RequestStamp::default(), &fp, &key, RequestKind::Chat, now), Ok(()));
+    }
+
+    #[test]
+    fn response_stamps_are_not_interchangeable_with_request_stamps() {
+        let alice = PgpKeyPair::generate("alice").unwrap();
+        let (fp, key) = (alice.fingerprint(), alice.export_public_key().unwrap());
+        let now = 1_000_000;
+        let mut guard = ReplayGuard::new();
+
+        let response = RequestStamp::sign_response(&alice, now).unwrap();
+        let request = RequestStamp::sign(&alice, RequestKind::Chat, now).unwrap();
+        assert_eq!(guard.check_request(&response, &fp, &key, RequestKind::Chat, now), Err(ReplayRejection::BadSignature));
+        assert_eq!(guard.check_response(&request, &fp, &key, now), Err(ReplayRejection::BadSignature));
+        assert_eq!(guard.check_response(&response, &fp, &key, now), Ok(()));
+        assert_eq!(guard.check_response(&response, &fp, &key, now), Err(ReplayRejection::Replayed));
+    }
+
+    #[test]
+    fn expired_request_is_rejected() {
+        let mut guard = ReplayGuard::new();
+        let now = 10 * MAX_REQUEST_AGE_MS;
+        assert_eq!(
+            guard.check("old", now - MAX_REQUEST_AGE_MS - 1, now),
+            Err(ReplayRejection::Expired { age_ms: MAX_REQUEST_AGE_MS + 1 })
+        );
+        assert_eq!(guard.check("future", now + MAX_CLOCK_SKEW_MS + 1, now), Err(ReplayRejection::FromFuture));
+        assert_eq!(guard.check("fresh", now - MAX_REQUEST_AGE_MS, now), Ok(()));
+
+        // Once the window has passed its nonce is forgotten, and the timestamp
+        // alone keeps it out
+        assert_eq!(guard.check("later", now + MAX_REQUEST_AGE_MS, now + MAX_REQUEST_AGE_MS), Ok(()));
+        assert!(!guard.seen.contains("fresh"));
+        assert!(matches!(
+            guard.check("fresh", now - MAX_REQUEST_AGE_MS, now + MAX_REQUEST_AGE_MS),
+            Err(ReplayRejection::Expired { .. })
+        ));
+    }
+}
@@ -4,11 +4,11 @@
 //! to the local filesystem. In the future, this could be migrated to SQLite.
 
 use anyhow::{Context, Result};
-use cryptochat_messaging::requests::MessageRequest;
+use cryptochat_messaging::requests::{MessageRequest, RequestKind};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 // Re-export types for use in other modules
 pub use cryptochat_messaging::requests::Contact;
@@ -27,41 +27,99 @@ struct ContactStore {
     contacts: HashMap<String, Contact>,
 }
 
-/// Get the data directory for CryptoChat
-pub fn get_data_dir() -> Result<PathBuf> {
-    let home = std::env::var("USERPROFILE")
-        .or_else(|_| std::env::var("HOME"))
-        .context("Could not find home directory")?;
-
-    // Include instance suffix for multi-instance testing
-    let dir_name = match crate::get_instance_id() {
-        Some(id) => format!(".cryptochat_{}", id),
-        None => ".cryptochat".to_string(),
-    };
-    let data_dir = PathBuf::from(home).join(dir_name);
-
-    // Create directory if it doesn't exist
-    if !data_dir.exists() {
-        fs::create_dir_all(&data_dir)
-            .context("Failed to create data directory")?;
-    }
-
-    Ok(data_dir)
-}
-
 /// Get path to requests.json
 fn get_requests_path() -> Result<PathBuf> {
-    Ok(get_data_dir()?.join("requests.json"))
+    Ok(crate::paths::data_dir()?.join("requests.json"))
 }
 
 /// Get path to contacts.json
 fn get_contacts_path() -> Result<PathBuf> {
-    Ok(get_data_dir()?.join("contacts.json"))
+    Ok(crate::paths::data_dir()?.join("contacts.json"))
 }
 
 /// Get path to username.txt
 fn get_username_path() -> Result<PathBuf> {
-    Ok(get_data_dir()?.join("username.txt"))
+    Ok(crate::paths::data_dir()?.join("username.txt"))
+}
+
+/// Get path to listening_port.txt
+fn get_listening_port_path() -> Result<PathBuf> {
+    Ok(crate::paths::data_dir()?.join("listening_port.txt"))
+}
+
+/// Remember the port we last listened on so restarts keep the same address
+pub fn save_listening_port(port: u16) -> Result<()> {
+    save_listening_port_to(&get_listening_port_path()?, port)
+}
+
+/// Port we last listened on, if any
+pub fn load_listening_port() -> Result<Option<u16>> {
+    load_listening_port_from(&get_listening_port_path()?)
+}
+
+fn save_listening_port_to(path: &Path, port: u16) -> Result<()> {
+    fs::write(path, port.to_string()).context("Failed to save listening port")
+}
+
+fn load_listening_port_from(path: &Path) -> Result<Option<u16>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+    let contents = fs::read_to_string(path).context("Failed to read listening port")?;
+    // A corrupt file just means we fall back to the default port
+    Ok(contents.trim().parse::<u16>().ok().filter(|p| *p != 0))
+}
+
+/// How incoming connection requests are handled, stored in request_settings.json
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RequestSettings {
+    /// Follow saved contacts who resend the key we trust without prompting
+    #[serde(default = "default_true")]
+    pub auto_accept_contacts: bool,
+    /// Senders whose requests are dropped without a prompt
+    #[serde(default)]
+    pub blocked_fingerprints: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for RequestSettings {
+    fn default() -> Self {
+        Self { auto_accept_contacts: true, blocked_fingerprints: Vec::new() }
+    }
+}
+
+impl RequestSettings {
+    pub fn is_blocked(&self, fingerprint: &str) -> bool {
+        self.blocked_fingerprints.iter().any(|fp| fp == fingerprint)
+    }
+
+    /// Block `fingerprint`; false if it already was
+    pub fn block(&mut self, fingerprint: &str) -> bool {
+        if self.is_blocked(fingerprint) {
+            return false;
+        }
+        self.blocked_fingerprints.push(fingerprint.to_string());
+        true
+    }
+}
+
+fn get_request_settings_path() -> Result<PathBuf> {
+    Ok(crate::paths::data_dir()?.join("request_settings.json"))
+}
+
+/// Load saved request settings (defaults if missing or unreadable)
+pub fn load_request_settings() -> RequestSettings {
+    get_request_settings_path()
+        .and_then(|path| crate::store_recovery::load_or_recover(&path, |json| Ok(serde_json::from_slice(json)?)))
+        .unwrap_or_default()
+}
+
+pub fn save_request_settings(settings: &RequestSettings) -> Result<()> {
+    let json = serde_json::to_string_pretty(settings)?;
+    fs::write(get_request_settings_path()?, json).context("Failed to save request settings")
 }
 
 /// Save username to file
@@ -88,19 +146,11 @@ pub fn load_username() -> Result<Option<String>> {
 
 /// Load all message requests from disk
 pub fn load_requests() -> Result<Vec<MessageRequest>> {
-    let path = get_requests_path()?;
-
-    if !path.exists() {
-        return Ok(Vec::new());
-    }
-
-    let json = fs::read_to_string(&path)
-        .context("Failed to read requests file")?;
-
-    let store: RequestStore = serde_json::from_str(&json)
-        .context("Failed to parse requests JSON")?;
-
-    Ok(store.requests.into_values().collect())
+    crate::store_recovery::load_or_recover(&get_requests_path()?, |json| {
+        let store: RequestStore = serde_json::from_slice(json)
+            .context("Failed to parse requests JSON")?;
+        Ok(store.requests.into_values().collect())
+    })
 }
 
 /// Save a message request to disk
@@ -128,6 +178,45 @@ pub fn save_request(request: &MessageRequest) -> Result<()> {
     Ok(())
 }
 
+/// Drop pending requests that expired before `now_ms`, returning
+/// (live, expired)
+fn split_expired(requests: Vec<MessageRequest>, now_ms: i64) -> (Vec<MessageRequest>, Vec<MessageRequest>) {
+    requests.into_iter().partition(|r| !r.is_expired_at(now_ms))
+}
+
+/// Remove expired pending requests from disk. Returns how many were removed.
+pub fn purge_expired_requests() -> Result<usize> {
+    let path = get_requests_path()?;
+
+    if !path.exists() {
+        return Ok(0);
+    }
+
+    let now_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64;
+    let (live, expired) = split_expired(load_requests()?, now_ms);
+    if expired.is_empty() {
+        return Ok(0);
+    }
+
+    let requests = live
+        .into_iter()
+        .map(|r| (r.request_id.to_string(), r))
+        .collect::<HashMap<_, _>>();
+    let store = RequestStore { requests };
+
+    // Write to disk
+    let json = serde_json::to_string_pretty(&store)
+        .context("Failed to serialize requests")?;
+
+    fs::write(&path, json)
+        .context("Failed to write requests file")?;
+
+    Ok(expired.len())
+}
+
 /// Load all pending message requests
 pub fn load_pending_requests() -> Result<Vec<MessageRequest>> {
     Ok(load_requests()?
@@ -167,19 +256,11 @@ pub fn delete_request(request_id: &str) -> Result<()> {
 
 /// Load all contacts from disk
 pub fn load_contacts() -> Result<Vec<Contact>> {
-    let path = get_contacts_path()?;
-
-    if !path.exists() {
-        return Ok(Vec::new());
-    }
-
-    let json = fs::read_to_string(&path)
-        .context("Failed to read contacts file")?;
-
-    let store: ContactStore = serde_json::from_str(&json)
-        .context("Failed to parse contacts JSON")?;
-
-    Ok(store.contacts.into_values().collect())
+    crate::store_recovery::load_or_recover(&get_contacts_path()?, |json| {
+        let store: ContactStore = serde_json::from_slice(json)
+            .context("Failed to parse contacts JSON")?;
+        Ok(store.contacts.into_values().collect())
+    })
 }
 
 /// Save a contact to disk
@@ -239,72 +320,91 @@ pub struct StoredMessage {
 
 /// Get path to chat_history.json (legacy unencrypted)
 fn get_history_path() -> Result<PathBuf> {
-    Ok(get_data_dir()?.join("chat_history.json"))
+    Ok(crate::paths::data_dir()?.join("chat_history.json"))
+}
+
+/// What `migrate_chat_history` did
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryMigration {
+    /// No history, or already in the current format
+    UpToDate,
+    /// Upgraded from `from_version`; the original file was kept at `backup`
+    Migrated { from_version: u32, backup: PathBuf },
+}
+
+/// Upgrade chat history on disk to the current encrypted, versioned format.
+///
+/// Handles plaintext `chat_history.json` (v0) and encrypted history written
+/// before the schema had a version. The old file is backed up first; a
+/// plaintext backup is itself stored encrypted so migrating never leaves
+/// readable history behind.
+pub fn migrate_chat_history(fingerprint: &str) -> Result<HistoryMigration> {
+    let key = crate::encrypted_storage::derive_storage_key(fingerprint);
+    migrate_history_in(&crate::paths::data_dir()?, &key)
+}
+
+fn migrate_history_in(dir: &Path, key: &[u8; 32]) -> Result<HistoryMigration> {
+    use crate::encrypted_storage::{encrypt_data, read_history_file, write_history_file, HISTORY_VERSION};
+
+    let enc_path = dir.join("chat_history.enc");
+    if let Some((version, messages)) = read_history_file(&enc_path, key)? {
+        if version == HISTORY_VERSION {
+            return Ok(HistoryMigration::UpToDate);
+        }
+        let backup = dir.join(format!("chat_history.v{}.bak.enc", version));
+        fs::copy(&enc_path, &backup).context("Failed to back up chat history")?;
+        write_history_file(&enc_path, &messages, key)?;
+        return Ok(HistoryMigration::Migrated { from_version: version, backup });
+    }
+
+    let plain_path = dir.join("chat_history.json");
+    if !plain_path.exists() {
+        return Ok(HistoryMigration::UpToDate);
+    }
+    let raw = fs::read(&plain_path).context("Failed to read chat history")?;
+    // Refuse to migrate (and delete) a file we can't make sense of
+    let messages: Vec<StoredMessage> = serde_json::from_slice(&raw).context("Plaintext chat history is malformed")?;
+
+    let backup = dir.join("chat_history.v0.bak.enc");
+    fs::write(&backup, serde_json::to_vec(&encrypt_data(&raw, key)?)?).context("Failed to back up chat history")?;
+    let messages: Vec<_> = messages.iter().map(to_encrypted_message).collect();
+    write_history_file(&enc_path, &messages, key)?;
+    fs::remove_file(&plain_path).context("Failed to remove plaintext chat history")?;
+
+    Ok(HistoryMigration::Migrated { from_version: 0, backup })
 }
 
-/// Get path to encrypted chat history
-fn get_encrypted_history_path() -> Result<PathBuf> {
-    Ok(get_data_dir()?.join("chat_history.enc"))
+fn to_encrypted_message(m: &StoredMessage) -> crate::encrypted_storage::StoredMessage {
+    crate::encrypted_storage::StoredMessage {
+        sender_name: m.sender_name.clone(),
+        content: m.content.clone(),
+        is_mine: m.is_mine,
+        timestamp: m.timestamp.clone(),
+        expires_at: m.expires_at.clone(),
+        image_data: None,
+        image_filename: None,
+        emotes: m.emotes.clone(),
+    }
 }
 
-/// Load chat history - tries encrypted first, falls back to unencrypted for migration
+/// Load chat history, migrating older formats first
 pub fn load_chat_history_encrypted(fingerprint: &str) -> Result<Vec<StoredMessage>> {
-    use crate::encrypted_storage;
-    
-    let enc_path = get_encrypted_history_path()?;
-    
-    // If encrypted file exists, use it
-    if enc_path.exists() {
-        let stored = encrypted_storage::load_encrypted_history(fingerprint)?;
-        // Convert from encrypted_storage::StoredMessage to our StoredMessage
-        return Ok(stored.into_iter().map(|m| StoredMessage {
-            sender_name: m.sender_name,
-            content: m.content,
-            is_mine: m.is_mine,
-            timestamp: m.timestamp,
-            expires_at: m.expires_at,
-            emotes: m.emotes,
-        }).collect());
-    }
-    
-    // Check for unencrypted file to migrate
-    let plain_path = get_history_path()?;
-    if plain_path.exists() {
-        // Load old unencrypted history
-        let json = fs::read_to_string(&plain_path).context("Failed to read chat history")?;
-        let messages: Vec<StoredMessage> = serde_json::from_str(&json).unwrap_or_default();
-        
-        // Migrate to encrypted format
-        let _ = save_chat_history_encrypted(&messages, fingerprint);
-        
-        // Delete old unencrypted file for security
-        let _ = fs::remove_file(&plain_path);
-        
-        return Ok(messages);
-    }
-    
-    Ok(Vec::new())
+    migrate_chat_history(fingerprint)?;
+    let stored = crate::encrypted_storage::load_encrypted_history(fingerprint)?;
+    Ok(stored.into_iter().map(|m| StoredMessage {
+        sender_name: m.sender_name,
+        content: m.content,
+        is_mine: m.is_mine,
+        timestamp: m.timestamp,
+        expires_at: m.expires_at,
+        emotes: m.emotes,
+    }).collect())
 }
 
 /// Save chat history with encryption
 pub fn save_chat_history_encrypted(messages: &[StoredMessage], fingerprint: &str) -> Result<()> {
-    use crate::encrypted_storage;
-    
-    // Convert to encrypted_storage format
-    let stored: Vec<encrypted_storage::StoredMessage> = messages.iter().map(|m| {
-        encrypted_storage::StoredMessage {
-            sender_name: m.sender_name.clone(),
-            content: m.content.clone(),
-            is_mine: m.is_mine,
-            timestamp: m.timestamp.clone(),
-            expires_at: m.expires_at.clone(),
-            image_data: None,
-            image_filename: None,
-            emotes: m.emotes.clone(),
-        }
-    }).collect();
-    
-    encrypted_storage::save_encrypted_history(&stored, fingerprint)
+    let stored: Vec<_> = messages.iter().map(to_encrypted_message).collect();
+    crate::encrypted_storage::save_encrypted_history(&stored, fingerprint)
 }
 
 /// Append a message to encrypted chat history
@@ -349,21 +449,32 @@ pub struct SimpleContact {
     pub fingerprint: String,
     pub public_key: String,
     pub address: String,
+    /// The contact published a revocation for this key
+    #[serde(default)]
+    pub revoked: bool,
+    /// Name we chose locally; shown instead of the synced `name`
+    #[serde(default)]
+    pub alias: Option<String>,
+    /// Send time of the last signed address update we followed; older ones
+    /// are ignored
+    #[serde(default)]
+    pub address_updated_ms: i64,
+}
+
+impl SimpleContact {
+    /// Alias if we set one, otherwise the name the contact goes by
+    pub fn display_name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
 }
 
 fn get_simple_contacts_path() -> Result<PathBuf> {
-    Ok(get_data_dir()?.join("simple_contacts.json"))
+    Ok(crate::paths::data_dir()?.join("simple_contacts.json"))
 }
 
 /// Load simple contacts
 pub fn load_simple_contacts() -> Result<Vec<SimpleContact>> {
-    let path = get_simple_contacts_path()?;
-    if !path.exists() {
-        return Ok(Vec::new());
-    }
-    let json = fs::read_to_string(&path)?;
-    let contacts: Vec<SimpleContact> = serde_json::from_str(&json).unwrap_or_default();
-    Ok(contacts)
+    crate::store_recovery::load_or_recover(&get_simple_contacts_path()?, |json| Ok(serde_json::from_slice(json)?))
 }
 
 /// Save all simple contacts
@@ -386,16 +497,673 @@ pub fn upsert_simple_contact(contact: &SimpleContact) -> Result<()> {
 /// Update contact name by fingerprint (returns true if updated)
 pub fn update_contact_name(fingerprint: &str, new_name: &str) -> Result<bool> {
     let mut contacts = load_simple_contacts().unwrap_or_default();
-    let mut updated = false;
-    for contact in contacts.iter_mut() {
-        if contact.fingerprint == fingerprint && contact.name != new_name {
-            contact.name = new_name.to_string();
-            updated = true;
-            break;
-        }
-    }
+    let updated = apply_synced_name(&mut contacts, fingerprint, new_name);
     if updated {
         save_simple_contacts(&contacts)?;
     }
     Ok(updated)
 }
+
+/// Record the name a contact reports for themselves, leaving any alias alone
+/// (returns true if changed)
+pub fn apply_synced_name(contacts: &mut [SimpleContact], fingerprint: &str, new_name: &str) -> bool {
+    match contacts.iter_mut().find(|c| c.fingerprint == fingerprint) {
+        Some(contact) if contact.name != new_name => {
+            contact.name = new_name.to_string();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Set or clear (with a blank alias) our local name for a contact
+/// (returns true if changed)
+pub fn set_contact_alias(contacts: &mut [SimpleContact], fingerprint: &str, alias: &str) -> bool {
+    let alias = Some(alias.trim()).filter(|a| !a.is_empty()).map(str::to_string);
+    match contacts.iter_mut().find(|c| c.fingerprint == fingerprint) {
+        Some(contact) if contact.alias != alias => {
+            contact.alias = alias;
+            true
+        }
+        _ => false,
+    }
+}
+
+const CONTACT_FILE_VERSION: u32 = 1;
+
+/// Shareable list of contacts
+#[derive(Serialize, Deserialize)]
+struct ContactFile {
+    version: u32,
+    contacts: Vec<SimpleContact>,
+}
+
+/// A contact whose imported address differs from the one we have
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressConflict {
+    pub fingerprint: String,
+    pub name: String,
+    /// Address we kept
+    pub current: String,
+    /// Address the file had
+    pub imported: String,
+}
+
+/// What `import_contacts` did
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContactImport {
+    pub added: usize,
+    /// Already known with the same details
+    pub unchanged: usize,
+    /// Already known at a different address; ours is kept
+    pub conflicts: Vec<AddressConflict>,
+    /// Entries whose public key is unreadable or doesn't match the fingerprint
+    pub rejected: usize,
+    /// Names of imported contacts whose key has expired or been revoked.
+    /// They are still imported, like a saved contact whose key lapses.
+    pub invalid_keys: Vec<String>,
+}
+
+/// Serialize contacts for sharing or backup. Aliases are our own labels and
+/// stay out of the file, as does when an address was last updated.
+pub fn export_contacts(contacts: &[SimpleContact]) -> Result<Vec<u8>> {
+    let contacts = contacts
+        .iter()
+        .map(|c| SimpleContact { alias: None, address_updated_ms: 0, ..c.clone() })
+        .collect();
+    let file = ContactFile { version: CONTACT_FILE_VERSION, contacts };
+    Ok(serde_json::to_vec_pretty(&file)?)
+}
+
+/// Merge an exported contact file into `contacts`, de-duplicating by
+/// fingerprint. Known contacts keep their name, alias and address; a differing
+/// address is reported as a conflict rather than applied, except that an
+/// empty address is filled in. A known contact's revoked flag is never
+/// cleared, and a new one arrives revoked if the file or its key says so.
+pub fn import_contacts(bytes: &[u8], contacts: &mut Vec<SimpleContact>) -> Result<ContactImport> {
+    let file: ContactFile = serde_json::from_slice(bytes).context("Not a contact file")?;
+    if file.version > CONTACT_FILE_VERSION {
+        anyhow::bail!("Unsupported contact file version {}", file.version);
+    }
+
+    let mut summary = ContactImport::default();
+    for imported in file.contacts {
+        // The fingerprint must be the key's own, or a file could pair a
+        // trusted name with someone else's key
+        let key = match crate::app::stored_public_key(&imported.public_key) {
+            Ok((key, warning)) if key.fingerprint() == imported.fingerprint => {
+                if let Some(warning) = warning {
+                    tracing::warn!(peer = crate::logging::short_fp(&imported.fingerprint), %warning, "imported contact key is no longer valid");
+                    summary.invalid_keys.push(imported.display_name().to_string());
+                }
+                key
+            }
+            _ => {
+                summary.rejected += 1;
+                continue;
+            }
+        };
+
+        match contacts.iter_mut().find(|c| c.fingerprint == imported.fingerprint) {
+            None => {
+                let revoked = imported.revoked || key.is_revoked();
+                contacts.push(SimpleContact { alias: None, revoked, address_updated_ms: 0, ..imported });
+                summary.added += 1;
+            }
+            Some(existing) if existing.address.is_empty() && !imported.address.is_empty() => {
+                existing.address = imported.address;
+                summary.added += 1;
+            }
+            Some(existing) if imported.address.is_empty() || existing.address == imported.address => {
+                summary.unchanged += 1;
+            }
+            Some(existing) => summary.conflicts.push(AddressConflict {
+                fingerprint: existing.fingerprint.clone(),
+                name: existing.display_name().to_string(),
+                current: existing.address.clone(),
+                imported: imported.address,
+            }),
+        }
+    }
+    Ok(summary)
+}
+
+/// Sidebar label for a conversation: the contact's display name for direct
+/// chats, otherwise the conversation's own name
+pub fn conversation_label<'a>(contacts: &'a [SimpleContact], conversation_id: &str, fallback: &'a str) -> &'a str {
+    contacts
+        .iter()
+        .find(|c| c.fingerprint == conversation_id)
+        .map(SimpleContact::display_name)
+        .unwrap_or(fallback)
+}
+
+/// A contact's signed notice that it now listens on `new_port`. The host
+/// comes from the connection it arrived on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddressNotice {
+    pub new_port: u16,
+    pub sent_ms: i64,
+    /// Armored detached signature over [`address_statement`]
+    pub signature: String,
+}
+
+/// What an address update signs
+pub fn address_statement(fingerprint: &str, new_port: u16, sent_ms: i64) -> Vec<u8> {
+    format!("cryptochat-address-v1:{}:{}:{}", fingerprint, new_port, sent_ms).into_bytes()
+}
+
+impl AddressNotice {
+    /// Announce `new_port` as `keypair`
+    pub fn sign(keypair: &cryptochat_crypto_core::pgp::PgpKeyPair, new_port: u16, sent_ms: i64) -> Result<Self> {
+        let signature = keypair.sign_detached(&address_statement(&keypair.fingerprint(), new_port, sent_ms))?;
+        Ok(Self { new_port, sent_ms, signature })
+    }
+}
+
+/// Check an address update from `fingerprint`: it must be signed with the
+/// key we hold for that contact, recent, and newer than the last one we
+/// followed, so neither a stranger nor a replayed update can redirect a
+/// contact. Records the update's time on the contact when it passes.
+pub fn accept_address_notice(contacts: &mut [SimpleContact], fingerprint: &str, notice: &AddressNotice, now_ms: i64) -> bool {
+    use crate::request_replay::{MAX_CLOCK_SKEW_MS, MAX_REQUEST_AGE_MS};
+
+    let Some(contact) = contacts.iter_mut().find(|c| c.fingerprint == fingerprint && !c.revoked) else {
+        return false;
+    };
+    let age_ms = now_ms - notice.sent_ms;
+    if notice.sent_ms <= contact.address_updated_ms || age_ms > MAX_REQUEST_AGE_MS || age_ms < -MAX_CLOCK_SKEW_MS {
+        return false;
+    }
+    let signed = cryptochat_crypto_core::pgp::PgpKeyPair::parse_public_key(&contact.public_key)
+        .ok()
+        .filter(|key| key.fingerprint() == fingerprint)
+        .is_some_and(|key| {
+            let statement = address_statement(fingerprint, notice.new_port, notice.sent_ms);
+            cryptochat_crypto_core::pgp::PgpKeyPair::verify_detached(key.cert(), &statement, &notice.signature).is_ok()
+        });
+    if signed {
+        contact.address_updated_ms = notice.sent_ms;
+    }
+    signed
+}
+
+/// Point a contact at its new address after an AddressUpdate (returns true if changed)
+pub fn apply_address_update(contacts: &mut [SimpleContact], fingerprint: &str, new_address: &str) -> bool {
+    match contacts.iter_mut().find(|c| c.fingerprint == fingerprint) {
+        Some(contact) if contact.address != new_address => {
+            contact.address = new_address.to_string();
+            true
+        }
+        _ => false,
+    }
+}
+
+/// What the UI does with an incoming connection request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestRoute {
+    /// A saved contact resending the key we already trust: just follow them
+    Reconnect,
+    /// Ask whether to start a chat
+    AcceptChat,
+    /// Ask whether to connect so they can invite us to a group
+    AcceptGroup,
+    /// A saved contact offering new material for their key: ask whether to re-link
+    RelinkKey,
+}
+
+impl RequestRoute {
+    /// Pending-request line and accept button label
+    pub fn prompt(self) -> (&'static str, &'static str) {
+        match self {
+            RequestRoute::Reconnect | RequestRoute::AcceptChat => ("wants to chat", "Accept"),
+            RequestRoute::AcceptGroup => ("wants to invite you to a group", "Connect"),
+            RequestRoute::RelinkKey => ("sent an updated key", "Re-link key"),
+        }
+    }
+
+    /// Whether the request is accepted without asking. Only a saved contact
+    /// offering the key we already trust qualifies; strangers, new keys and
+    /// revoked contacts are always prompted.
+    pub fn accepts_automatically(self, settings: &RequestSettings) -> bool {
+        settings.auto_accept_contacts && self == RequestRoute::Reconnect
+    }
+}
+
+/// What the UI does with a request or answer once its stamp has been checked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestHandling {
+    /// From a blocked sender: dropped without a prompt
+    Drop,
+    /// A saved contact at a new address: follow them without asking
+    Follow,
+    /// Shown in pending requests
+    Prompt,
+}
+
+/// Decide what to do with a request, or an answer to one, routed to `route`.
+/// Following a contact moves where we send their
+/// messages, so it needs a stamp signed by their key (`signed`) and either
+/// auto-accept or an answer to a request we sent (`asked`).
+pub fn request_handling(
+    route: RequestRoute,
+    settings: &RequestSettings,
+    fingerprint: &str,
+    signed: bool,
+    asked: bool,
+) -> RequestHandling {
+    if settings.is_blocked(fingerprint) {
+        RequestHandling::Drop
+    } else if route == RequestRoute::Reconnect && signed && (asked || route.accepts_automatically(settings)) {
+        RequestHandling::Follow
+    } else {
+        RequestHandling::Prompt
+    }
+}
+
+/// Requests we sent that the peer hasn't answered yet, so an answer we asked
+/// for can be told apart from one nobody asked for
+#[derive(Debug, Default)]
+pub struct AwaitedAnswers {
+    /// Fingerprint, or address when that's all we had, with when we asked
+    asked: HashMap<String, i64>,
+}
+
+impl AwaitedAnswers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note a request sent to `peer` (a fingerprint or address)
+    pub fn record(&mut self, peer: &str, now_ms: i64) {
+        self.asked.retain(|_, asked_ms| now_ms - *asked_ms <= cryptochat_messaging::requests::DEFAULT_REQUEST_TTL_MS);
+        self.asked.insert(peer.to_string(), now_ms);
+    }
+
+    /// Whether an answer from `fingerprint` at `address` was asked for. Each
+    /// request is answered once.
+    pub fn take(&mut self, fingerprint: &str, address: &str, now_ms: i64) -> bool {
+        let by_fingerprint = self.asked.remove(fingerprint);
+        let by_address = self.asked.remove(address);
+        by_fingerprint
+            .into_iter()
+            .chain(by_address)
+            .any(|asked_ms| now_ms - asked_ms <= cryptochat_messaging::requests::DEFAULT_REQUEST_TTL_MS)
+    }
+}
+
+/// Whether `public_key` is the key with `fingerprint`. A re-link replaces a
+/// contact's saved key, so it's only offered for the same primary key with
+/// updated subkeys or signatures, never a different key under their name.
+pub fn key_belongs_to(fingerprint: &str, public_key: &str) -> bool {
+    cryptochat_crypto_core::pgp::PgpKeyPair::parse_public_key(public_key)
+        .is_ok_and(|key| key.fingerprint() == fingerprint)
+}
+
+/// Decide how to present a request of `kind` from `fingerprint`
+pub fn route_request(contacts: &[SimpleContact], kind: RequestKind, fingerprint: &str, public_key: &str) -> RequestRoute {
+    let saved = contacts.iter().find(|c| c.fingerprint == fingerprint && !c.revoked);
+    match (kind, saved) {
+        (_, Some(contact)) if contact.public_key == public_key => RequestRoute::Reconnect,
+        (RequestKind::KeyReexchange, Some(_)) => RequestRoute::RelinkKey,
+        // Nothing to re-link for someone we never saved: a first contact
+        (RequestKind::KeyReexchange, None) | (RequestKind::Chat, _) => RequestRoute::AcceptChat,
+        (RequestKind::GroupInvite, _) => RequestRoute::AcceptGroup,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptochat_messaging::{ConversationId, DeviceId};
+
+    fn request(fingerprint: &str) -> MessageRequest {
+        MessageRequest::new(
+            ConversationId::new(),
+            fingerprint.to_string(),
+            DeviceId::new(),
+            "-----BEGIN PGP PUBLIC KEY BLOCK-----".to_string(),
+            None,
+        )
+    }
+
+    #[test]
+    fn purge_filters_expired_pending_requests() {
+        let live = request("LIVE");
+        let mut stale = request("STALE");
+        stale.expires_at_ms = Some(stale.created_ms - 1);
+        let mut legacy = request("LEGACY");
+        legacy.expires_at_ms = None;
+        legacy.created_ms -= cryptochat_messaging::requests::DEFAULT_REQUEST_TTL_MS + 1;
+        let mut accepted = request("ACCEPTED");
+        accepted.expires_at_ms = Some(0);
+        accepted.accept();
+
+        let now = live.created_ms;
+        let (kept, expired) = split_expired(vec![live, stale, legacy, accepted], now);
+
+        let kept: Vec<&str> = kept.iter().map(|r| r.sender_fingerprint.as_str()).collect();
+        let mut expired: Vec<&str> = expired.iter().map(|r| r.sender_fingerprint.as_str()).collect();
+        expired.sort();
+        assert_eq!(kept, vec!["LIVE", "ACCEPTED"]);
+        assert_eq!(expired, vec!["LEGACY", "STALE"]);
+    }
+
+    #[test]
+    fn listening_port_roundtrip() {
+        let path = std::env::temp_dir().join(format!("cryptochat_port_{}.txt", uuid::Uuid::new_v4()));
+        assert_eq!(load_listening_port_from(&path).unwrap(), None);
+
+        save_listening_port_to(&path, 62781).unwrap();
+        assert_eq!(load_listening_port_from(&path).unwrap(), Some(62781));
+
+        fs::write(&path, "not a port").unwrap();
+        assert_eq!(load_listening_port_from(&path).unwrap(), None);
+        let _ = fs::remove_file(&path);
+    }
+
+    fn contact(fingerprint: &str, address: &str) -> SimpleContact {
+        SimpleContact {
+            name: fingerprint.to_string(),
+            fingerprint: fingerprint.to_string(),
+            public_key: String::new(),
+            address: address.to_string(),
+            revoked: false,
+            alias: None,
+            address_updated_ms: 0,
+        }
+    }
+
+    #[test]
+    fn request_kinds_route_to_their_prompt() {
+        let mut alice = contact("ALICE", "10.0.0.2:62780");
+        alice.public_key = "KEY_A".to_string();
+        let contacts = vec![alice];
+
+        assert_eq!(route_request(&contacts, RequestKind::Chat, "BOB", "KEY_B"), RequestRoute::AcceptChat);
+        assert_eq!(route_request(&contacts, RequestKind::GroupInvite, "BOB", "KEY_B"), RequestRoute::AcceptGroup);
+        assert_eq!(route_request(&contacts, RequestKind::KeyReexchange, "BOB", "KEY_B"), RequestRoute::AcceptChat);
+        assert_eq!(route_request(&contacts, RequestKind::KeyReexchange, "ALICE", "KEY_A2"), RequestRoute::RelinkKey);
+        // The key we already trust is a reconnect, whatever the kind
+        for kind in [RequestKind::Chat, RequestKind::GroupInvite, RequestKind::KeyReexchange] {
+            assert_eq!(route_request(&contacts, kind, "ALICE", "KEY_A"), RequestRoute::Reconnect);
+        }
+        assert_eq!(RequestRoute::RelinkKey.prompt().1, "Re-link key");
+    }
+
+    #[test]
+    fn keys_belong_only_to_their_own_fingerprint() {
+        let alice = cryptochat_crypto_core::pgp::PgpKeyPair::generate("alice").unwrap();
+        let mallory = cryptochat_crypto_core::pgp::PgpKeyPair::generate("mallory").unwrap();
+        let alice_key = alice.export_public_key().unwrap();
+
+        assert!(key_belongs_to(&alice.fingerprint(), &alice_key));
+        assert!(!key_belongs_to(&mallory.fingerprint(), &alice_key));
+        assert!(!key_belongs_to(&alice.fingerprint(), "KEY_A"));
+    }
+
+    #[test]
+    fn only_trusted_contacts_are_auto_accepted() {
+        let mut alice = contact("ALICE", "10.0.0.2:62780");
+        alice.public_key = "KEY_A".to_string();
+        let mut mallory = contact("MALLORY", "10.0.0.3:62780");
+        mallory.public_key = "KEY_M".to_string();
+        mallory.revoked = true;
+        let contacts = vec![alice, mallory];
+
+        let on = RequestSettings::default();
+        let off = RequestSettings { auto_accept_contacts: false, ..RequestSettings::default() };
+        let auto = |fingerprint: &str, key: &str, settings: &RequestSettings| {
+            route_request(&contacts, RequestKind::Chat, fingerprint, key).accepts_automatically(settings)
+        };
+
+        assert!(auto("ALICE", "KEY_A", &on));
+        assert!(!auto("ALICE", "KEY_A", &off));
+        // A stranger, a known fingerprint with a different key, and a revoked contact all prompt
+        assert!(!auto("BOB", "KEY_B", &on));
+        assert!(!auto("ALICE", "KEY_A2", &on));
+        assert!(!auto("MALLORY", "KEY_M", &on));
+    }
+
+    #[test]
+    fn blocked_senders_are_dropped_and_unasked_answers_need_auto_accept() {
+        let mut alice = contact("ALICE", "10.0.0.2:62780");
+        alice.public_key = "KEY_A".to_string();
+        let contacts = vec![alice];
+        let mut on = RequestSettings::default();
+        let off = RequestSettings { auto_accept_contacts: false, ..RequestSettings::default() };
+        let handling = |fingerprint: &str, key: &str, settings: &RequestSettings, signed: bool, asked: bool| {
+            let route = route_request(&contacts, RequestKind::Chat, fingerprint, key);
+            request_handling(route, settings, fingerprint, signed, asked)
+        };
+
+        assert_eq!(handling("ALICE", "KEY_A", &on, true, false), RequestHandling::Follow);
+        // An answer nobody asked for waits for the user when auto-accept is off
+        assert_eq!(handling("ALICE", "KEY_A", &off, true, false), RequestHandling::Prompt);
+        assert_eq!(handling("ALICE", "KEY_A", &off, true, true), RequestHandling::Follow);
+        // Unsigned, from a stranger, or with another key: the user decides
+        assert_eq!(handling("ALICE", "KEY_A", &on, false, true), RequestHandling::Prompt);
+        assert_eq!(handling("BOB", "KEY_B", &on, true, true), RequestHandling::Prompt);
+        assert_eq!(handling("ALICE", "KEY_A2", &on, true, true), RequestHandling::Prompt);
+
+        // Blocking wins over being a saved contact
+        assert!(on.block("ALICE"));
+        assert!(!on.block("ALICE"));
+        assert!(on.block("BOB"));
+        assert_eq!(handling("ALICE", "KEY_A", &on, true, true), RequestHandling::Drop);
+        assert_eq!(handling("BOB", "KEY_B", &on, true, false), RequestHandling::Drop);
+    }
+
+    #[test]
+    fn only_requests_we_sent_are_answered_once() {
+        let mut awaited = AwaitedAnswers::new();
+        awaited.record("ALICE", 1_000);
+        awaited.record("10.0.0.3:62780", 1_000);
+
+        assert!(!awaited.take("MALLORY", "10.0.0.9:62780", 2_000));
+        assert!(awaited.take("ALICE", "10.0.0.2:62780", 2_000));
+        assert!(!awaited.take("ALICE", "10.0.0.2:62780", 2_000));
+        // Asked by address only, before we knew their key
+        assert!(awaited.take("BOB", "10.0.0.3:62780", 2_000));
+
+        awaited.record("CAROL", 1_000);
+        let late = 1_000 + cryptochat_messaging::requests::DEFAULT_REQUEST_TTL_MS + 1;
+        assert!(!awaited.take("CAROL", "", late));
+    }
+
+    #[test]
+    fn synced_name_does_not_override_alias() {
+        let mut contacts = vec![contact("ALICE", "10.0.0.2:62780")];
+        assert!(set_contact_alias(&mut contacts, "ALICE", "  Mum  "));
+        assert_eq!(contacts[0].alias.as_deref(), Some("Mum"));
+
+        // The peer renames themselves: tracked, but the alias still shows
+        assert!(apply_synced_name(&mut contacts, "ALICE", "alice_2024"));
+        assert_eq!(contacts[0].name, "alice_2024");
+        assert_eq!(contacts[0].display_name(), "Mum");
+
+        // Clearing the alias falls back to the synced name
+        assert!(set_contact_alias(&mut contacts, "ALICE", ""));
+        assert_eq!(contacts[0].display_name(), "alice_2024");
+        assert!(!set_contact_alias(&mut contacts, "MALLORY", "x"));
+    }
+
+    fn keyed_contact(name: &str, address: &str) -> SimpleContact {
+        let key = cryptochat_crypto_core::pgp::PgpKeyPair::generate(&format!("{}@example.com", name)).unwrap();
+        SimpleContact {
+            name: name.to_string(),
+            fingerprint: key.fingerprint(),
+            public_key: key.export_public_key().unwrap(),
+            address: address.to_string(),
+            revoked: false,
+            alias: None,
+            address_updated_ms: 0,
+        }
+    }
+
+    #[test]
+    fn contacts_export_and_import_round_trip() {
+        let mut alice = keyed_contact("alice", "10.0.0.2:62780");
+        alice.alias = Some("Mum".to_string());
+        let bob = keyed_contact("bob", "10.0.0.3:62780");
+        let bytes = export_contacts(&[alice.clone(), bob.clone()]).unwrap();
+        assert!(!String::from_utf8_lossy(&bytes).contains("Mum"));
+
+        let mut imported = Vec::new();
+        let summary = import_contacts(&bytes, &mut imported).unwrap();
+        assert_eq!(summary.added, 2);
+        assert_eq!(imported[0].fingerprint, alice.fingerprint);
+        assert_eq!(imported[0].public_key, alice.public_key);
+        assert_eq!(imported[0].address, "10.0.0.2:62780");
+        assert_eq!(imported[0].alias, None);
+        assert_eq!(imported[1].name, "bob");
+    }
+
+    #[test]
+    fn reimport_dedups_and_reports_address_conflicts() {
+        let alice = keyed_contact("alice", "10.0.0.2:62780");
+        let bytes = export_contacts(&[alice.clone()]).unwrap();
+
+        let mut contacts = vec![alice.clone()];
+        let summary = import_contacts(&bytes, &mut contacts).unwrap();
+        assert_eq!((summary.added, summary.unchanged), (0, 1));
+        assert_eq!(contacts.len(), 1);
+
+        // Alice moved since the file was made: keep ours, report the difference
+        contacts[0].address = "10.0.0.9:62781".to_string();
+        let summary = import_contacts(&bytes, &mut contacts).unwrap();
+        assert_eq!(contacts.len(), 1);
+        assert_eq!(contacts[0].address, "10.0.0.9:62781");
+        assert_eq!(summary.conflicts, vec![AddressConflict {
+            fingerprint: alice.fingerprint.clone(),
+            name: "alice".to_string(),
+            current: "10.0.0.9:62781".to_string(),
+            imported: "10.0.0.2:62780".to_string(),
+        }]);
+
+        // A fingerprint that isn't the key's own is refused
+        let forged = SimpleContact { fingerprint: "FORGED".to_string(), ..alice.clone() };
+        let summary = import_contacts(&export_contacts(&[forged]).unwrap(), &mut contacts).unwrap();
+        assert_eq!((summary.added, summary.rejected), (0, 1));
+
+        // Importing doesn't un-revoke a contact, and a revoked entry stays revoked
+        contacts[0].revoked = true;
+        import_contacts(&bytes, &mut contacts).unwrap();
+        assert!(contacts[0].revoked);
+        let mut fresh = Vec::new();
+        import_contacts(&export_contacts(&contacts).unwrap(), &mut fresh).unwrap();
+        assert!(fresh[0].revoked);
+    }
+
+    #[test]
+    fn lapsed_keys_are_imported_with_a_warning() {
+        let mut key = cryptochat_crypto_core::pgp::PgpKeyPair::generate("old@example.com").unwrap();
+        let revocation = key.generate_revocation().unwrap();
+        key.apply_revocation(&revocation).unwrap();
+        let old = SimpleContact {
+            public_key: key.export_public_key().unwrap(),
+            fingerprint: key.fingerprint(),
+            ..keyed_contact("old", "10.0.0.4:62780")
+        };
+
+        let mut contacts = Vec::new();
+        let summary = import_contacts(&export_contacts(&[old]).unwrap(), &mut contacts).unwrap();
+        assert_eq!((summary.added, summary.rejected), (1, 0));
+        assert_eq!(summary.invalid_keys, vec!["old".to_string()]);
+        assert!(contacts[0].revoked);
+    }
+
+    #[test]
+    fn alias_is_the_sidebar_label() {
+        let mut contacts = vec![contact("ALICE", "10.0.0.2:62780")];
+        set_contact_alias(&mut contacts, "ALICE", "Mum");
+        assert_eq!(conversation_label(&contacts, "ALICE", "alice"), "Mum");
+        assert_eq!(conversation_label(&contacts, "group-1", "Book club"), "Book club");
+
+        // Contacts saved before aliases existed load without one
+        let legacy: SimpleContact =
+            serde_json::from_str(r#"{"name":"Bob","fingerprint":"BOB","public_key":"","address":""}"#).unwrap();
+        assert_eq!(legacy.display_name(), "Bob");
+    }
+
+    #[test]
+    fn address_update_changes_only_matching_contact() {
+        let mut contacts = vec![contact("ALICE", "10.0.0.2:62780"), contact("BOB", "10.0.0.3:62780")];
+
+        assert!(apply_address_update(&mut contacts, "ALICE", "10.0.0.2:62781"));
+        assert_eq!(contacts[0].address, "10.0.0.2:62781");
+        assert_eq!(contacts[1].address, "10.0.0.3:62780");
+
+        assert!(!apply_address_update(&mut contacts, "ALICE", "10.0.0.2:62781"));
+        assert!(!apply_address_update(&mut contacts, "MALLORY", "10.0.0.9:1"));
+    }
+
+    #[test]
+    fn address_updates_must_be_signed_fresh_and_newer() {
+        let alice_key = cryptochat_crypto_core::pgp::PgpKeyPair::generate("alice@example.com").unwrap();
+        let mallory_key = cryptochat_crypto_core::pgp::PgpKeyPair::generate("mallory@example.com").unwrap();
+        let alice_fp = alice_key.fingerprint();
+        let mut alice = contact(&alice_fp, "10.0.0.2:62780");
+        alice.public_key = alice_key.export_public_key().unwrap();
+        let mut contacts = vec![alice];
+        let now = 1_700_000_000_000;
+
+        // Someone else's key, or Alice's notice with another port
+        let forged = AddressNotice::sign(&mallory_key, 4444, now).unwrap();
+        assert!(!accept_address_notice(&mut contacts, &alice_fp, &forged, now));
+        let mut moved = AddressNotice::sign(&alice_key, 62781, now).unwrap();
+        moved.new_port = 4444;
+        assert!(!accept_address_notice(&mut contacts, &alice_fp, &moved, now));
+
+        // Stale or from the future
+        let stale = AddressNotice::sign(&alice_key, 62781, now - 10 * 60 * 1000).unwrap();
+        assert!(!accept_address_notice(&mut contacts, &alice_fp, &stale, now));
+        let future = AddressNotice::sign(&alice_key, 62781, now + 10 * 60 * 1000).unwrap();
+        assert!(!accept_address_notice(&mut contacts, &alice_fp, &future, now));
+
+        let notice = AddressNotice::sign(&alice_key, 62781, now - 1000).unwrap();
+        assert!(accept_address_notice(&mut contacts, &alice_fp, &notice, now));
+        assert_eq!(contacts[0].address_updated_ms, now - 1000);
+        // Replayed, or an older one arriving late
+        assert!(!accept_address_notice(&mut contacts, &alice_fp, &notice, now));
+        let older = AddressNotice::sign(&alice_key, 62779, now - 2000).unwrap();
+        assert!(!accept_address_notice(&mut contacts, &alice_fp, &older, now));
+
+        // Revoked contacts can't move
+        contacts[0].revoked = true;
+        let later = AddressNotice::sign(&alice_key, 62782, now).unwrap();
+        assert!(!accept_address_notice(&mut contacts, &alice_fp, &later, now));
+    }
+
+    #[test]
+    fn migrates_v0_plaintext_history_to_current_format() {
+        use crate::encrypted_storage::{decrypt_data, derive_storage_key, read_history_file, EncryptedStore, HISTORY_VERSION};
+
+        let dir = std::env::temp_dir().join(format!("cryptochat_history_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let key = derive_storage_key("FP_ME");
+
+        // v0: plaintext list written before emotes/expiry existed
+        let v0 = r#"[{"sender_name":"Alice","content":"hi","is_mine":false,"timestamp":"09:15"}]"#;
+        fs::write(dir.join("chat_history.json"), v0).unwrap();
+
+        let outcome = migrate_history_in(&dir, &key).unwrap();
+        let backup = dir.join("chat_history.v0.bak.enc");
+        assert_eq!(outcome, HistoryMigration::Migrated { from_version: 0, backup: backup.clone() });
+        assert!(!dir.join("chat_history.json").exists());
+
+        let (version, messages) = read_history_file(&dir.join("chat_history.enc"), &key).unwrap().unwrap();
+        assert_eq!(version, HISTORY_VERSION);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].content, "hi");
+        assert_eq!(messages[0].expires_at, None);
+
+        // The backup holds the original bytes, encrypted
+        let store: EncryptedStore = serde_json::from_slice(&fs::read(&backup).unwrap()).unwrap();
+        let original: Vec<u8> = decrypt_data(&store, &key).unwrap();
+        assert_eq!(original, v0.as_bytes());
+
+        assert_eq!(migrate_history_in(&dir, &key).unwrap(), HistoryMigration::UpToDate);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
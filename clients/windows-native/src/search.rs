@@ -0,0 +1,372 @@
+//! Searching message history across every conversation
+//!
+//! Matching is a case-insensitive substring test over what the user can read:
+//! message text and attachment filenames. Image bytes are never scanned.
+//!
+//! [`SearchIndex`] maps every word in the history to the messages containing
+//! it, so a query only looks at messages holding all of its words instead of
+//! lowercasing and scanning every one. It is saved next to the history,
+//! encrypted with the same storage key, and brought up to date against the
+//! loaded conversations rather than trusted blindly.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::conversation::{ChatMessage, Conversation};
+use crate::encrypted_storage::{decrypt_data, derive_storage_key, encrypt_data, EncryptedStore};
+use crate::paths::data_dir;
+
+/// Most hits the results view lists
+pub const MAX_HITS: usize = 200;
+
+/// Whether `msg` contains `needle`, which must already be lowercase
+fn matches(msg: &ChatMessage, needle: &str) -> bool {
+    msg.content.to_lowercase().contains(needle)
+        || msg.image_filename.as_deref().is_some_and(|f| f.to_lowercase().contains(needle))
+}
+
+/// Indices of the messages in `conv` that match `query`, oldest first
+pub fn search_conversation(conv: &Conversation, query: &str) -> Vec<usize> {
+    let needle = query.trim().to_lowercase();
+    if needle.is_empty() {
+        return Vec::new();
+    }
+    conv.messages.iter().enumerate().filter(|(_, msg)| matches(msg, &needle)).map(|(idx, _)| idx).collect()
+}
+
+/// `(conversation_id, message_index)` of every message matching `query`,
+/// newest first. Archived chats are skipped unless `include_archived` is set.
+/// Messages from before send times were recorded rank by their
+/// conversation's last activity, then by position.
+pub fn search_all(
+    conversations: &HashMap<String, Conversation>,
+    query: &str,
+    include_archived: bool,
+) -> Vec<(String, usize)> {
+    let hits = conversations
+        .values()
+        .filter(|conv| include_archived || !conv.settings.archived.value)
+        .flat_map(|conv| {
+            search_conversation(conv, query)
+                .into_iter()
+                .map(move |idx| (conv.messages[idx].sent_at_ms, conv.last_activity, conv.id.as_str(), idx))
+        })
+        .collect();
+    rank(hits)
+}
+
+/// Order `(sent_at_ms, last_activity, conversation_id, index)` hits newest
+/// first and keep the first [`MAX_HITS`]
+fn rank(mut hits: Vec<(i64, u64, &str, usize)>) -> Vec<(String, usize)> {
+    hits.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)).then(b.3.cmp(&a.3)).then(a.2.cmp(b.2)));
+    hits.into_iter().take(MAX_HITS).map(|(_, _, id, idx)| (id.to_string(), idx)).collect()
+}
+
+/// Words of `text`, which must already be lowercase: its runs of letters and digits
+fn words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric()).filter(|word| !word.is_empty())
+}
+
+/// Word-to-message index over the whole history
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchIndex {
+    /// Each word to the ids of the messages containing it
+    postings: HashMap<String, HashSet<Uuid>>,
+    /// Ids of each conversation's messages, in order, as they were indexed
+    indexed: HashMap<String, Vec<Uuid>>,
+}
+
+impl SearchIndex {
+    pub fn build(conversations: &HashMap<String, Conversation>) -> Self {
+        let mut index = Self::default();
+        index.sync(conversations);
+        index
+    }
+
+    fn add(&mut self, msg: &ChatMessage) {
+        let filename = msg.image_filename.as_deref().map(str::to_lowercase).unwrap_or_default();
+        let content = msg.content.to_lowercase();
+        for word in words(&content).chain(words(&filename)) {
+            self.postings.entry(word.to_string()).or_default().insert(msg.id);
+        }
+    }
+
+    fn remove_conversation(&mut self, conversation_id: &str) {
+        let Some(ids) = self.indexed.remove(conversation_id) else {
+            return;
+        };
+        let ids: HashSet<Uuid> = ids.into_iter().collect();
+        self.postings.retain(|_, postings| {
+            postings.retain(|id| !ids.contains(id));
+            !postings.is_empty()
+        });
+    }
+
+    /// Bring the index in line with `conversations`: messages appended since
+    /// the last sync are added, and a conversation whose history changed any
+    /// other way (expired or deleted messages) is indexed again. Returns
+    /// whether anything changed.
+    pub fn sync(&mut self, conversations: &HashMap<String, Conversation>) -> bool {
+        let gone: Vec<String> = self.indexed.keys().filter(|id| !conversations.contains_key(*id)).cloned().collect();
+        let mut changed = !gone.is_empty();
+        for id in gone {
+            self.remove_conversation(&id);
+        }
+
+        for (id, conv) in conversations {
+            let indexed = self.indexed.get(id).map(Vec::as_slice).unwrap_or_default();
+            let appended_only = indexed.len() <= conv.messages.len()
+                && indexed.iter().zip(&conv.messages).all(|(indexed, msg)| *indexed == msg.id);
+            if appended_only && indexed.len() == conv.messages.len() && self.indexed.contains_key(id) {
+                continue;
+            }
+            let start = if appended_only {
+                indexed.len()
+            } else {
+                self.remove_conversation(id);
+                0
+            };
+            for msg in &conv.messages[start..] {
+                self.add(msg);
+            }
+            self.indexed.insert(id.clone(), conv.messages.iter().map(|msg| msg.id).collect());
+            changed = true;
+        }
+        changed
+    }
+
+    /// Same results as [`search_all`], for an index that is in sync with
+    /// `conversations`. Only messages containing every word of the query
+    /// are compared against it.
+    pub fn search(
+        &self,
+        conversations: &HashMap<String, Conversation>,
+        query: &str,
+        include_archived: bool,
+    ) -> Vec<(String, usize)> {
+        let needle = query.trim().to_lowercase();
+        let mut candidates: Option<HashSet<Uuid>> = None;
+        for word in words(&needle) {
+            // A word of the query can sit inside a longer word of the message
+            let containing: HashSet<Uuid> = self
+                .postings
+                .iter()
+                .filter(|(indexed, _)| indexed.contains(word))
+                .flat_map(|(_, ids)| ids.iter().copied())
+                .collect();
+            candidates = Some(match candidates {
+                Some(candidates) => candidates.intersection(&containing).copied().collect(),
+                None => containing,
+            });
+        }
+        // Nothing to look up for punctuation-only queries
+        let Some(candidates) = candidates else {
+            return search_all(conversations, query, include_archived);
+        };
+
+        let hits = self
+            .indexed
+            .iter()
+            .filter_map(|(id, ids)| Some((conversations.get(id)?, ids)))
+            .filter(|(conv, _)| include_archived || !conv.settings.archived.value)
+            .flat_map(|(conv, ids)| {
+                let needle = &needle;
+                let candidates = &candidates;
+                ids.iter().enumerate().filter(move |(_, id)| candidates.contains(*id)).filter_map(move |(idx, _)| {
+                    let msg = conv.messages.get(idx).filter(|msg| matches(msg, needle))?;
+                    Some((msg.sent_at_ms, conv.last_activity, conv.id.as_str(), idx))
+                })
+            })
+            .collect();
+        rank(hits)
+    }
+}
+
+fn get_index_path(fingerprint: &str) -> Result<PathBuf> {
+    Ok(data_dir()?.join(format!("search_index_{}.enc", fingerprint)))
+}
+
+fn save_index_to(path: &Path, index: &SearchIndex, key: &[u8; 32]) -> Result<()> {
+    let encrypted = encrypt_data(index, key)?;
+    fs::write(path, serde_json::to_vec(&encrypted)?).context("Failed to write search index")?;
+    Ok(())
+}
+
+fn load_index_from(path: &Path, key: &[u8; 32]) -> Result<SearchIndex> {
+    let json = fs::read(path).context("Failed to read search index")?;
+    let encrypted: EncryptedStore = serde_json::from_slice(&json).context("Failed to parse encrypted store")?;
+    decrypt_data(&encrypted, key)
+}
+
+/// Save the search index, encrypted like the history it covers
+pub fn save_index(index: &SearchIndex, fingerprint: &str) -> Result<()> {
+    save_index_to(&get_index_path(fingerprint)?, index, &derive_storage_key(fingerprint))
+}
+
+/// The saved search index, synced against `conversations`. A missing or
+/// unreadable index is rebuilt from scratch; the flag says whether the
+/// result differs from what was on disk and should be saved.
+pub fn load_index(fingerprint: &str, conversations: &HashMap<String, Conversation>) -> (SearchIndex, bool) {
+    let loaded = get_index_path(fingerprint).and_then(|path| load_index_from(&path, &derive_storage_key(fingerprint)));
+    match loaded {
+        Ok(mut index) => {
+            let changed = index.sync(conversations);
+            (index, changed)
+        }
+        Err(_) => (SearchIndex::build(conversations), true),
+    }
+}
+
+/// The part of a matching message to show in the results list
+pub fn snippet(msg: &ChatMessage) -> String {
+    let text = match &msg.image_filename {
+        Some(filename) => format!("[Attachment: {}]", filename),
+        None => msg.content.replace('\n', " "),
+    };
+    match text.char_indices().nth(80) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conversation::DeliveryStatus;
+
+    fn msg(content: &str, sent_at_ms: i64) -> ChatMessage {
+        ChatMessage {
+            sender_name: "Alice".to_string(),
+            content: content.to_string(),
+            is_mine: false,
+            timestamp: "12:00".to_string(),
+            sent_at_ms,
+            image_data: None,
+            image_filename: None,
+            reactions: Vec::new(),
+            emotes: HashMap::new(),
+            expires_at: None,
+            message_id: None,
+            status: DeliveryStatus::Sent,
+            delivered_to: Vec::new(),
+            forwarded: false,
+            voice: None,
+            id: uuid::Uuid::new_v4(),
+        }
+    }
+
+    fn conv(id: &str, last_activity: u64, messages: Vec<ChatMessage>) -> Conversation {
+        let mut conv = Conversation::new(id.to_string(), id.to_string(), None);
+        conv.messages = messages;
+        conv.last_activity = last_activity;
+        conv
+    }
+
+    #[test]
+    fn hits_across_conversations_are_newest_first() {
+        let mut photo = msg("[Image: Picnic.png]", 250);
+        photo.image_filename = Some("Picnic.png".to_string());
+        // The bytes happen to spell the query; they must not match
+        photo.image_data = Some(b"picnic".to_vec());
+        let mut raw = msg("[Image: beach.png]", 260);
+        raw.image_filename = Some("beach.png".to_string());
+        raw.image_data = Some(b"picnic".to_vec());
+
+        let conversations = HashMap::from([
+            ("alice".to_string(), conv("alice", 10, vec![msg("picnic on Sunday?", 100), msg("unrelated", 150), msg("PICNIC it is", 300)])),
+            ("bob".to_string(), conv("bob", 20, vec![msg("bring the picnic blanket", 200), photo, raw])),
+            ("legacy".to_string(), conv("legacy", 5, vec![msg("old picnic", 0), msg("older picnic news", 0)])),
+        ]);
+
+        let hits = search_all(&conversations, "  Picnic ", false);
+        let expected: Vec<(String, usize)> = [("alice", 2), ("bob", 1), ("bob", 0), ("alice", 0), ("legacy", 1), ("legacy", 0)]
+            .into_iter()
+            .map(|(id, idx)| (id.to_string(), idx))
+            .collect();
+        assert_eq!(hits, expected);
+
+        assert!(search_all(&conversations, "   ", false).is_empty());
+        assert_eq!(search_conversation(&conversations["alice"], "picnic"), vec![0, 2]);
+    }
+
+    #[test]
+    fn archived_chats_only_when_asked() {
+        let mut archived = conv("archived", 1, vec![msg("secret plans", 500)]);
+        archived.settings.archived.set(true, 1);
+        let conversations = HashMap::from([
+            ("archived".to_string(), archived),
+            ("open".to_string(), conv("open", 2, vec![msg("plans for later", 100)])),
+        ]);
+
+        assert_eq!(search_all(&conversations, "plans", false), vec![("open".to_string(), 0)]);
+        assert_eq!(
+            search_all(&conversations, "plans", true),
+            vec![("archived".to_string(), 0), ("open".to_string(), 0)]
+        );
+    }
+
+    #[test]
+    fn index_follows_appends() {
+        let mut conversations = HashMap::from([("alice".to_string(), conv("alice", 1, vec![msg("see you at lunch", 100)]))]);
+        let mut index = SearchIndex::build(&conversations);
+        assert!(!index.sync(&conversations));
+        assert_eq!(index.search(&conversations, "lunch", false), vec![("alice".to_string(), 0)]);
+        assert!(index.search(&conversations, "dinner", false).is_empty());
+
+        // Appended messages are picked up without touching the rest
+        conversations.get_mut("alice").unwrap().messages.push(msg("dinner instead?", 200));
+        conversations.insert("bob".to_string(), conv("bob", 2, vec![msg("Dinner at 8", 150)]));
+        assert!(index.sync(&conversations));
+        assert_eq!(
+            index.search(&conversations, "dinner", false),
+            vec![("alice".to_string(), 1), ("bob".to_string(), 0)]
+        );
+
+        // An expired message shifts the rest, so that chat is indexed again
+        conversations.get_mut("alice").unwrap().messages.remove(0);
+        conversations.remove("bob");
+        assert!(index.sync(&conversations));
+        assert!(index.search(&conversations, "lunch", false).is_empty());
+        assert_eq!(index.search(&conversations, "dinner", false), vec![("alice".to_string(), 0)]);
+        assert_eq!(index, SearchIndex::build(&conversations));
+
+        // At rest it is encrypted, and comes back unchanged
+        let key = [7u8; 32];
+        let path = std::env::temp_dir().join(format!("cryptochat-search-{}.enc", Uuid::new_v4()));
+        save_index_to(&path, &index, &key).unwrap();
+        assert!(!String::from_utf8_lossy(&fs::read(&path).unwrap()).contains("dinner"));
+        assert_eq!(load_index_from(&path, &key).unwrap(), index);
+        assert!(load_index_from(&path, &[8u8; 32]).is_err());
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn indexed_results_match_a_linear_scan() {
+        let mut photo = msg("[Image: Picnic.png]", 250);
+        photo.image_filename = Some("Picnic.png".to_string());
+        let mut archived = conv("archived", 3, vec![msg("picnic plans, archived", 400)]);
+        archived.settings.archived.set(true, 1);
+        let conversations = HashMap::from([
+            ("alice".to_string(), conv("alice", 10, vec![msg("picnic on Sunday?", 100), msg("unrelated", 150), msg("PICNIC it is", 300)])),
+            ("bob".to_string(), conv("bob", 20, vec![msg("bring the picnic blanket", 200), photo, msg("Über-picnic!", 0)])),
+            ("archived".to_string(), archived),
+        ]);
+        let index = SearchIndex::build(&conversations);
+
+        for query in ["picnic", "  Picnic ", "nic on", "ic it is", "PNG", "über", "the", "sunday?", "?", "   ", "nothing"] {
+            for include_archived in [false, true] {
+                assert_eq!(
+                    index.search(&conversations, query, include_archived),
+                    search_all(&conversations, query, include_archived),
+                    "query {:?}",
+                    query
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Which public keys to try when verifying a direct message
+//!
+//! The same fingerprint can be stored with different key material: the saved
+//! contact may hold an older copy of a key whose subkeys were since updated,
+//! while the live session or a shared group has the current one. Every copy
+//! filed under the sender's fingerprint is a candidate, most trusted first.
+//! A copy only counts if the key really has that fingerprint; a contact or
+//! group entry filed under the wrong one is skipped.
+
+use std::fmt;
+
+use cryptochat_crypto_core::pgp::PgpKeyPair;
+
+use crate::group_store::Group;
+use crate::request_store::SimpleContact;
+
+/// Where a candidate key came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeySource {
+    /// Saved contact (display name)
+    Contact(String),
+    /// The peer of the current session
+    ActivePeer,
+    /// Member entry in a group (group name)
+    GroupMember(String),
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySource::Contact(name) => write!(f, "saved key for {}", name),
+            KeySource::ActivePeer => write!(f, "current session key"),
+            KeySource::GroupMember(group) => write!(f, "key from group '{}'", group),
+        }
+    }
+}
+
+/// A public key to try, and where it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CandidateKey {
+    pub source: KeySource,
+    pub public_key: String,
+}
+
+/// Keys filed under `sender_fingerprint` whose own fingerprint matches it:
+/// saved contacts, then the active session's peer, then group members. Each
+/// distinct key appears once.
+pub fn candidate_keys(
+    sender_fingerprint: &str,
+    contacts: &[SimpleContact],
+    active_peer: Option<(&str, &str)>,
+    groups: &[Group],
+) -> Vec<CandidateKey> {
+    let contact_keys = contacts
+        .iter()
+        .filter(|c| c.fingerprint == sender_fingerprint)
+        .map(|c| (KeySource::Contact(c.display_name().to_string()), c.public_key.as_str()));
+    let peer_key = active_peer
+        .filter(|(fingerprint, _)| *fingerprint == sender_fingerprint)
+        .map(|(_, key)| (KeySource::ActivePeer, key));
+    let member_keys = groups.iter().flat_map(|g| {
+        g.members
+            .iter()
+            .filter(|m| m.fingerprint == sender_fingerprint)
+            .map(|m| (KeySource::GroupMember(g.name.clone()), m.public_key.as_str()))
+    });
+
+    let mut candidates: Vec<CandidateKey> = Vec::new();
+    for (source, key) in contact_keys.chain(peer_key).chain(member_keys) {
+        if key.is_empty() || candidates.iter().any(|c| c.public_key == key) {
+            continue;
+        }
+        let matches = PgpKeyPair::parse_public_key(key)
+            .is_ok_and(|parsed| parsed.fingerprint() == sender_fingerprint);
+        if matches {
+            candidates.push(CandidateKey { source, public_key: key.to_string() });
+        }
+    }
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::group_store::{GroupMember, GroupSettings, InvitePermission};
+
+    fn contact(name: &str, fingerprint: &str, key: &str) -> SimpleContact {
+        SimpleContact {
+            name: name.to_string(),
+            fingerprint: fingerprint.to_string(),
+            public_key: key.to_string(),
+            address: String::new(),
+            revoked: false,
+            alias: None,
+            address_updated_ms: 0,
+        }
+    }
+
+    fn group_with(name: &str, fingerprint: &str, key: &str) -> Group {
+        Group {
+            id: "g1".to_string(),
+            name: name.to_string(),
+            created_at: String::new(),
+            creator_fingerprint: "ME".to_string(),
+            members: vec![GroupMember {
+                fingerprint: fingerprint.to_string(),
+                username: "alice".to_string(),
+                public_key: key.to_string(),
+                address: String::new(),
+                joined_at: String::new(),
+            }],
+            admins: vec!["ME".to_string()],
+            settings: GroupSettings {
+                invite_permission: InvitePermission::AdminsOnly,
+                max_members: None,
+                disappearing_timer_secs: None,
+            },
+            symmetric_key: vec![1; 32],
+            avatar_hash: None,
+            metadata_updated_ms: 0,
+            metadata_updated_by: String::new(),
+            membership_epoch: 0,
+            join_requests: Vec::new(),
+            roles_version: 0,
+            roles_updated_by: String::new(),
+        }
+    }
+
+    /// Fingerprint and armored public key of a fresh key
+    fn key(name: &str) -> (String, String) {
+        let keypair = PgpKeyPair::generate(name).unwrap();
+        (keypair.fingerprint(), keypair.export_public_key().unwrap())
+    }
+
+    #[test]
+    fn only_keys_for_the_sender_in_trust_order() {
+        let (alice, alice_key) = key("alice");
+        let (bob, bob_key) = key("bob");
+        // Differently formatted copies of the same key stand in for old and updated ones
+        let (old, session, new) = (alice_key.clone(), format!("{}\n", alice_key), format!("{}\n\n", alice_key));
+        let contacts = vec![contact("Alice", &alice, &old), contact("Bob", &bob, &bob_key)];
+        let groups = vec![group_with("Book club", &alice, &new)];
+
+        let keys = candidate_keys(&alice, &contacts, Some((&alice, &session)), &groups);
+        let sources: Vec<(&KeySource, &str)> = keys.iter().map(|k| (&k.source, k.public_key.as_str())).collect();
+        assert_eq!(sources, vec![
+            (&KeySource::Contact("Alice".to_string()), old.as_str()),
+            (&KeySource::ActivePeer, session.as_str()),
+            (&KeySource::GroupMember("Book club".to_string()), new.as_str()),
+        ]);
+
+        // Another peer's session key is never tried for Alice
+        let keys = candidate_keys(&alice, &contacts, Some((&bob, &bob_key)), &[]);
+        assert_eq!(keys.len(), 1);
+        assert!(candidate_keys("MALLORY", &contacts, Some((&bob, &bob_key)), &groups).is_empty());
+    }
+
+    #[test]
+    fn identical_copies_are_tried_once() {
+        let (alice, alice_key) = key("alice");
+        let contacts = vec![contact("Alice", &alice, &alice_key)];
+        let groups = vec![group_with("Book club", &alice, &alice_key)];
+        let keys = candidate_keys(&alice, &contacts, Some((&alice, &alice_key)), &groups);
+        assert_eq!(keys, vec![CandidateKey { source: KeySource::Contact("Alice".to_string()), public_key: alice_key }]);
+    }
+
+    #[test]
+    fn keys_filed_under_the_wrong_fingerprint_are_skipped() {
+        let (alice, alice_key) = key("alice");
+        let (_, mallory_key) = key("mallory");
+        // Mallory's key saved under Alice's fingerprint, and junk that isn't a key
+        let contacts = vec![contact("Alice", &alice, &mallory_key)];
+        let groups = vec![group_with("Book club", &alice, "not a key")];
+
+        let keys = candidate_keys(&alice, &contacts, Some((&alice, &alice_key)), &groups);
+        assert_eq!(keys, vec![CandidateKey { source: KeySource::ActivePeer, public_key: alice_key }]);
+    }
+}
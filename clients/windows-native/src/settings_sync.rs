@@ -0,0 +1,114 @@
+//! Syncing mute, pin and archive between the account's devices
+//!
+//! Whenever one of those settings changes, and once after logging in, a
+//! [`SettingsSync`] snapshot of every chat's settings is sealed and posted to
+//! the account's own mailbox on the relay. The other devices pick it up when
+//! they poll and merge it setting by setting, so it doesn't matter in which
+//! order snapshots arrive. Snapshots are sealed with a key derived from the
+//! account's secret key, which only the account's devices hold.
+//!
+//! The mailbox is shared, so whichever device polls first takes a snapshot;
+//! posting again on every login lets a device that missed one catch up.
+
+use crate::conversation::Conversation;
+use anyhow::{Context, Result};
+use cryptochat_crypto_core::pgp::PgpKeyPair;
+use cryptochat_crypto_core::KeyPair;
+use cryptochat_messaging::settings_sync::SettingsSync;
+use cryptochat_messaging::{DeviceId, EncryptedEnvelope};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
+use zeroize::Zeroize;
+
+/// Key snapshots are sealed with, the same on every device of the account
+fn sync_key(keypair: &PgpKeyPair) -> Result<KeyPair> {
+    let mut secret = keypair.export_secret_key()?;
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret.as_bytes()).expect("HMAC accepts any key length");
+    secret.zeroize();
+    mac.update(b"cryptochat-settings-sync-v1");
+    Ok(KeyPair::from_seed(&mac.finalize().into_bytes())?)
+}
+
+/// This install's id in snapshots, from its id in the device registry
+pub fn device_id(local_device_id: &str) -> Result<DeviceId> {
+    let id = uuid::Uuid::parse_str(local_device_id).context("Device id is not a UUID")?;
+    Ok(DeviceId(id))
+}
+
+/// Seal the settings of every conversation for the account's other devices
+pub fn seal(
+    conversations: &HashMap<String, Conversation>,
+    device_id: DeviceId,
+    keypair: &PgpKeyPair,
+) -> Result<EncryptedEnvelope> {
+    let snapshot = SettingsSync {
+        device_id,
+        conversations: conversations.iter().map(|(id, conv)| (id.clone(), conv.settings.clone())).collect(),
+    };
+    Ok(snapshot.seal(&sync_key(keypair)?)?)
+}
+
+/// Open another device's snapshot and merge it into `conversations`,
+/// returning the ids whose settings changed. Our own snapshots, and settings
+/// for chats this device doesn't have, change nothing.
+pub fn open_and_merge(
+    sealed: EncryptedEnvelope,
+    this_device: &DeviceId,
+    keypair: &PgpKeyPair,
+    conversations: &mut HashMap<String, Conversation>,
+) -> Result<Vec<String>> {
+    let mut snapshot = SettingsSync::open(sealed, &sync_key(keypair)?)?;
+    if &snapshot.device_id == this_device {
+        return Ok(Vec::new());
+    }
+    snapshot.conversations.retain(|id, _| conversations.contains_key(id));
+    let mut local: BTreeMap<String, _> =
+        conversations.iter().map(|(id, conv)| (id.clone(), conv.settings.clone())).collect();
+    let changed = snapshot.merge_into(&mut local);
+    for id in &changed {
+        if let (Some(conv), Some(settings)) = (conversations.get_mut(id), local.remove(id)) {
+            conv.settings = settings;
+        }
+    }
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chats() -> HashMap<String, Conversation> {
+        ["alice", "bob"]
+            .into_iter()
+            .map(|id| (id.to_string(), Conversation::new(id.to_string(), id.to_string(), None)))
+            .collect()
+    }
+
+    #[test]
+    fn another_devices_snapshot_merges_and_our_own_is_ignored() {
+        let account = PgpKeyPair::generate("me").unwrap();
+        let (laptop, desktop) = (DeviceId::new(), DeviceId::new());
+
+        let mut on_laptop = chats();
+        on_laptop.get_mut("alice").unwrap().settings.muted.set(true, 10);
+        on_laptop.insert("carol".to_string(), Conversation::new("carol".to_string(), "Carol".to_string(), None));
+        on_laptop.get_mut("carol").unwrap().settings.pinned.set(true, 10);
+        let sealed = seal(&on_laptop, laptop.clone(), &account).unwrap();
+
+        let mut on_desktop = chats();
+        on_desktop.get_mut("bob").unwrap().settings.archived.set(true, 20);
+        let changed = open_and_merge(sealed.clone(), &desktop, &account, &mut on_desktop).unwrap();
+        assert_eq!(changed, vec!["alice".to_string()]);
+        assert!(on_desktop["alice"].settings.muted.value);
+        assert!(on_desktop["bob"].settings.archived.value);
+        assert!(!on_desktop.contains_key("carol"));
+
+        // The laptop fetching its own snapshot back
+        assert!(open_and_merge(sealed.clone(), &laptop, &account, &mut chats()).unwrap().is_empty());
+
+        // Only the account's devices can open it
+        let stranger = PgpKeyPair::generate("stranger").unwrap();
+        assert!(open_and_merge(sealed, &desktop, &stranger, &mut chats()).is_err());
+    }
+}
@@ -0,0 +1,58 @@
+//! Keyboard shortcuts
+//!
+//! Keys reach [`shortcut_for`] only when no widget handled them; a focused
+//! text field keeps every key press to itself, so typing never triggers a
+//! shortcut. Everything but Escape also needs Ctrl (Cmd on macOS).
+
+use iced::keyboard::{key::Named, Key, Modifiers};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shortcut {
+    /// Ctrl+K: quick-jump to a chat or contact
+    Switcher,
+    /// Ctrl+,: settings
+    Settings,
+    /// Ctrl+Shift+L: light/dark theme
+    ToggleTheme,
+    /// Esc: close whatever panel or picker is open
+    Close,
+}
+
+/// The shortcut a key press stands for, if any
+pub fn shortcut_for(key: Key, modifiers: Modifiers) -> Option<Shortcut> {
+    match key.as_ref() {
+        Key::Named(Named::Escape) if modifiers.is_empty() => Some(Shortcut::Close),
+        Key::Character(c) if modifiers.command() => match (c.to_lowercase().as_str(), modifiers.shift()) {
+            ("k", false) => Some(Shortcut::Switcher),
+            (",", false) => Some(Shortcut::Settings),
+            ("l", true) => Some(Shortcut::ToggleTheme),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctrl(c: &str) -> Option<Shortcut> {
+        shortcut_for(Key::Character(c.into()), Modifiers::COMMAND)
+    }
+
+    #[test]
+    fn keys_map_to_shortcuts() {
+        assert_eq!(ctrl("k"), Some(Shortcut::Switcher));
+        assert_eq!(ctrl(","), Some(Shortcut::Settings));
+        assert_eq!(shortcut_for(Key::Character("L".into()), Modifiers::COMMAND | Modifiers::SHIFT), Some(Shortcut::ToggleTheme));
+        assert_eq!(shortcut_for(Key::Named(Named::Escape), Modifiers::empty()), Some(Shortcut::Close));
+
+        // Plain typing and near misses do nothing
+        assert_eq!(shortcut_for(Key::Character("k".into()), Modifiers::empty()), None);
+        assert_eq!(shortcut_for(Key::Character("l".into()), Modifiers::SHIFT), None);
+        assert_eq!(ctrl("l"), None);
+        assert_eq!(shortcut_for(Key::Character("K".into()), Modifiers::COMMAND | Modifiers::SHIFT), None);
+        assert_eq!(shortcut_for(Key::Named(Named::Escape), Modifiers::SHIFT), None);
+        assert_eq!(shortcut_for(Key::Named(Named::Enter), Modifiers::COMMAND), None);
+    }
+}
@@ -0,0 +1,66 @@
+//! Loading store files without silently losing them
+//!
+//! A store file that exists but can't be decrypted or parsed is renamed to
+//! `<name>.corrupt-<timestamp>` and the app starts from an empty store. The
+//! next save then can't overwrite it, so the data stays recoverable by hand.
+
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Read `path` and hand its bytes to `parse`. A missing file is an empty
+/// store; a file `parse` rejects is set aside and an empty store returned.
+/// Errors only if the file can't be read or moved.
+pub fn load_or_recover<T: Default>(path: &Path, parse: impl FnOnce(&[u8]) -> Result<T>) -> Result<T> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(T::default()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    match parse(&bytes) {
+        Ok(value) => Ok(value),
+        Err(e) => {
+            let backup = set_aside(path)?;
+            tracing::warn!(file = %path.display(), backup = %backup.display(), error = %e, "store file was corrupt; starting fresh");
+            Ok(T::default())
+        }
+    }
+}
+
+/// Move an unreadable store file out of the way, returning the backup path
+pub fn set_aside(path: &Path) -> Result<PathBuf> {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let backup = path.with_file_name(format!("{}.corrupt-{}", name, chrono::Local::now().format("%Y%m%d-%H%M%S")));
+    fs::rename(path, &backup).context("Failed to move corrupt store file aside")?;
+    Ok(backup)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn corrupt_file_is_backed_up_and_store_starts_empty() {
+        let dir = std::env::temp_dir().join(format!("cryptochat_recovery_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("simple_contacts.json");
+        let parse = |bytes: &[u8]| Ok(serde_json::from_slice::<Vec<String>>(bytes)?);
+
+        // Missing: empty, nothing created
+        assert!(load_or_recover(&path, parse).unwrap().is_empty());
+
+        fs::write(&path, br#"["alice","bob"]"#).unwrap();
+        assert_eq!(load_or_recover(&path, parse).unwrap(), vec!["alice", "bob"]);
+
+        fs::write(&path, br#"["alice","bo"#).unwrap();
+        assert!(load_or_recover(&path, parse).unwrap().is_empty());
+        assert!(!path.exists());
+        let backups: Vec<PathBuf> = fs::read_dir(&dir).unwrap().flatten().map(|e| e.path()).collect();
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].file_name().unwrap().to_string_lossy().starts_with("simple_contacts.json.corrupt-"));
+        assert_eq!(fs::read(&backups[0]).unwrap(), br#"["alice","bo"#);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
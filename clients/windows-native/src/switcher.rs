@@ -0,0 +1,110 @@
+//! Quick-jump palette: fuzzy matching over conversation and contact names
+//!
+//! Typing a few letters of a name ranks every chat, group and saved contact
+//! and Enter opens the best match, so a long sidebar never needs scrolling.
+
+/// Most matches listed at once
+pub const MAX_RESULTS: usize = 8;
+
+/// What choosing a match opens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// An existing chat (direct or group), by conversation id
+    Conversation(String),
+    /// A group nobody has written in yet, by group id
+    Group(String),
+    /// A saved contact without a chat yet, by index into the contact list
+    Contact(usize),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Candidate {
+    pub label: String,
+    pub target: Target,
+}
+
+/// How well `query` matches `name`; lower is better, None if it doesn't.
+/// The exact name beats a prefix, a prefix beats any other substring, and
+/// those beat letters scattered through the name, which rank by how far
+/// apart they are. Case is ignored.
+pub fn match_rank(query: &str, name: &str) -> Option<(u8, usize)> {
+    let query = query.trim().to_lowercase();
+    let name = name.to_lowercase();
+    if name == query {
+        return Some((0, 0));
+    }
+    if name.starts_with(&query) {
+        return Some((1, name.chars().count() - query.chars().count()));
+    }
+    if let Some(at) = name.find(&query) {
+        return Some((2, name[..at].chars().count()));
+    }
+
+    // Every query letter, in order, somewhere in the name
+    let mut wanted = query.chars().peekable();
+    let (mut first, mut last) = (None, 0);
+    for (i, c) in name.chars().enumerate() {
+        if wanted.peek() == Some(&c) {
+            wanted.next();
+            first.get_or_insert(i);
+            last = i;
+        }
+    }
+    if wanted.peek().is_some() {
+        return None;
+    }
+    let spread = last - first.unwrap_or(0) + 1 - query.chars().count();
+    Some((3, spread))
+}
+
+/// The best matches for `query`, best first. An empty query lists the
+/// candidates in the order given.
+pub fn rank<'a>(query: &str, candidates: &'a [Candidate]) -> Vec<&'a Candidate> {
+    if query.trim().is_empty() {
+        return candidates.iter().take(MAX_RESULTS).collect();
+    }
+    let mut matches: Vec<((u8, usize), &Candidate)> = candidates
+        .iter()
+        .filter_map(|c| match_rank(query, &c.label).map(|rank| (rank, c)))
+        .collect();
+    // Stable, so equally good matches keep their order
+    matches.sort_by_key(|(rank, _)| *rank);
+    matches.into_iter().take(MAX_RESULTS).map(|(_, c)| c).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidates(labels: &[&str]) -> Vec<Candidate> {
+        labels
+            .iter()
+            .enumerate()
+            .map(|(i, label)| Candidate { label: label.to_string(), target: Target::Contact(i) })
+            .collect()
+    }
+
+    fn labels<'a>(ranked: &[&'a Candidate]) -> Vec<&'a str> {
+        ranked.iter().map(|c| c.label.as_str()).collect()
+    }
+
+    #[test]
+    fn exact_then_prefix_then_substring_then_scattered() {
+        let all = candidates(&["Sam Alberts", "Alba", "Al", "Book Club", "alice", "Paul"]);
+        assert_eq!(labels(&rank("al", &all)), ["Al", "Alba", "alice", "Sam Alberts", "Paul"]);
+
+        assert_eq!(match_rank("AL", "Al"), Some((0, 0)));
+        assert_eq!(match_rank("bc", "Book Club"), Some((3, 4)));
+        // Letters closer together rank higher
+        assert!(match_rank("bc", "Bc Fans") < match_rank("bc", "Book Club"));
+    }
+
+    #[test]
+    fn no_match_and_empty_query() {
+        let all = candidates(&["alice", "bob"]);
+        assert!(rank("zed", &all).is_empty());
+        assert_eq!(match_rank("ba", "bob"), None);
+        assert_eq!(labels(&rank("  ", &all)), ["alice", "bob"]);
+        assert_eq!(rank(" Bob ", &all)[0].target, Target::Contact(1));
+    }
+}
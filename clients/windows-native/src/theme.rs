@@ -401,7 +401,11 @@ pub fn modern_bubble_mine() -> iced::widget::container::Appearance {
     let r = ((packed >> 16) & 0xFF) as f32 / 255.0;
     let g = ((packed >> 8) & 0xFF) as f32 / 255.0;
     let b = (packed & 0xFF) as f32 / 255.0;
-    
+    modern_bubble_color(r, g, b)
+}
+
+/// Modern "my" bubble in an explicit color (per-conversation override)
+pub fn modern_bubble_color(r: f32, g: f32, b: f32) -> iced::widget::container::Appearance {
     iced::widget::container::Appearance {
         background: Some(iced::Background::Color(Color::from_rgb(r, g, b))),
         text_color: Some(text_color_for_bg(r, g, b)),
@@ -0,0 +1,216 @@
+//! Voice messages: microphone capture, WAV encoding and playback
+//!
+//! Clips are recorded as 16 kHz mono 16-bit PCM through the waveIn API into a
+//! single buffer sized for `MAX_DURATION_MS`, wrapped in a WAV header, and
+//! played back with `PlaySoundW` straight from memory.
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+use windows::Win32::Media::Audio::{
+    waveInAddBuffer, waveInClose, waveInOpen, waveInPrepareHeader, waveInReset, waveInStart,
+    waveInUnprepareHeader, CALLBACK_NULL, HWAVEIN, WAVEFORMATEX, WAVEHDR, WAVE_FORMAT_PCM, WAVE_MAPPER,
+};
+
+pub const SAMPLE_RATE: u32 = 16_000;
+const CHANNELS: u16 = 1;
+const BITS_PER_SAMPLE: u16 = 16;
+const BYTES_PER_SECOND: u32 = SAMPLE_RATE * CHANNELS as u32 * BITS_PER_SAMPLE as u32 / 8;
+/// Longest clip we record or accept
+pub const MAX_DURATION_MS: u64 = 120_000;
+
+/// A voice clip in a conversation: the audio lives in the blob store
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VoiceClip {
+    /// Blob store id of the WAV data
+    pub blob: String,
+    pub duration_ms: u64,
+}
+
+/// Wrap 16-bit mono PCM bytes in a WAV header
+pub fn encode_wav(pcm: &[u8]) -> Vec<u8> {
+    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+    let mut wav = Vec::with_capacity(44 + pcm.len());
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + pcm.len() as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&CHANNELS.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&BYTES_PER_SECOND.to_le_bytes());
+    wav.extend_from_slice(&block_align.to_le_bytes());
+    wav.extend_from_slice(&BITS_PER_SAMPLE.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(pcm.len() as u32).to_le_bytes());
+    wav.extend_from_slice(pcm);
+    wav
+}
+
+/// Play length of a WAV file, read from its header (None if it isn't one)
+pub fn wav_duration_ms(wav: &[u8]) -> Option<u64> {
+    let u32_at = |at: usize| wav.get(at..at + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+    if wav.get(0..4)? != b"RIFF" || wav.get(8..12)? != b"WAVE" {
+        return None;
+    }
+    let mut byte_rate = None;
+    let mut pos = 12;
+    while pos + 8 <= wav.len() {
+        let id = &wav[pos..pos + 4];
+        let size = u32_at(pos + 4)? as usize;
+        match id {
+            b"fmt " => byte_rate = u32_at(pos + 16),
+            b"data" => {
+                let rate = byte_rate.filter(|&r| r > 0)? as u64;
+                // Trust what's actually there over a header claiming more
+                let available = wav.len().saturating_sub(pos + 8).min(size) as u64;
+                return Some(available * 1000 / rate);
+            }
+            _ => {}
+        }
+        pos = pos.checked_add(8 + size + (size & 1))?;
+    }
+    None
+}
+
+/// Duration to show for a received clip. The sender's claimed duration is
+/// only metadata; the audio itself decides, and clips that aren't valid WAV
+/// or run over `MAX_DURATION_MS` are refused.
+pub fn checked_duration_ms(wav: &[u8], claimed_ms: u64) -> Result<u64> {
+    let Some(actual) = wav_duration_ms(wav) else {
+        bail!("Voice message is not valid audio");
+    };
+    if actual > MAX_DURATION_MS {
+        bail!("Voice message is longer than {} seconds", MAX_DURATION_MS / 1000);
+    }
+    if actual.abs_diff(claimed_ms) > 1000 {
+        tracing::debug!(claimed_ms, actual_ms = actual, "voice message duration mismatch");
+    }
+    Ok(actual)
+}
+
+/// "m:ss"
+pub fn format_duration(ms: u64) -> String {
+    let secs = ms / 1000;
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// An open microphone capture; `stop` returns the clip as WAV
+pub struct Recorder {
+    handle: HWAVEIN,
+    header: Box<WAVEHDR>,
+    buffer: Box<[u8]>,
+    started: Instant,
+}
+
+// The handle and buffer are only touched from whichever thread owns the Recorder
+unsafe impl Send for Recorder {}
+
+impl std::fmt::Debug for Recorder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Recorder").field("started", &self.started).finish()
+    }
+}
+
+impl Recorder {
+    /// Start recording from the default microphone
+    pub fn start() -> Result<Self> {
+        let format = WAVEFORMATEX {
+            wFormatTag: WAVE_FORMAT_PCM as u16,
+            nChannels: CHANNELS,
+            nSamplesPerSec: SAMPLE_RATE,
+            nAvgBytesPerSec: BYTES_PER_SECOND,
+            nBlockAlign: CHANNELS * BITS_PER_SAMPLE / 8,
+            wBitsPerSample: BITS_PER_SAMPLE,
+            cbSize: 0,
+        };
+        let mut handle = HWAVEIN::default();
+        let buffer = vec![0u8; (BYTES_PER_SECOND as u64 * MAX_DURATION_MS / 1000) as usize].into_boxed_slice();
+        let mut recorder = unsafe {
+            if waveInOpen(Some(&mut handle), WAVE_MAPPER, &format, 0, 0, CALLBACK_NULL) != 0 {
+                bail!("No microphone available");
+            }
+            Self { handle, header: Box::new(WAVEHDR::default()), buffer, started: Instant::now() }
+        };
+        recorder.header.lpData = windows::core::PSTR(recorder.buffer.as_mut_ptr());
+        recorder.header.dwBufferLength = recorder.buffer.len() as u32;
+
+        let header_size = std::mem::size_of::<WAVEHDR>() as u32;
+        unsafe {
+            if waveInPrepareHeader(recorder.handle, &mut *recorder.header, header_size) != 0
+                || waveInAddBuffer(recorder.handle, &mut *recorder.header, header_size) != 0
+                || waveInStart(recorder.handle) != 0
+            {
+                waveInClose(recorder.handle);
+                bail!("Couldn't start recording");
+            }
+        }
+        recorder.started = Instant::now();
+        Ok(recorder)
+    }
+
+    pub fn elapsed_ms(&self) -> u64 {
+        (self.started.elapsed().as_millis() as u64).min(MAX_DURATION_MS)
+    }
+
+    /// Stop and return what was captured as a WAV file
+    pub fn stop(mut self) -> Result<Vec<u8>> {
+        let header_size = std::mem::size_of::<WAVEHDR>() as u32;
+        let recorded = unsafe {
+            // Reset hands the partly filled buffer back with its byte count
+            waveInReset(self.handle);
+            let recorded = (self.header.dwBytesRecorded as usize).min(self.buffer.len());
+            waveInUnprepareHeader(self.handle, &mut *self.header, header_size);
+            waveInClose(self.handle);
+            recorded
+        };
+        if recorded == 0 {
+            bail!("Nothing was recorded");
+        }
+        Ok(encode_wav(&self.buffer[..recorded]))
+    }
+}
+
+/// Play a WAV clip on a background thread
+pub fn play(wav: Vec<u8>) {
+    use windows::core::PCWSTR;
+    use windows::Win32::Foundation::HMODULE;
+    use windows::Win32::Media::Audio::{PlaySoundW, SND_MEMORY, SND_NODEFAULT, SND_SYNC};
+
+    // SND_SYNC keeps `wav` alive until playback ends
+    std::thread::spawn(move || unsafe {
+        let _ = PlaySoundW(PCWSTR(wav.as_ptr() as *const u16), HMODULE::default(), SND_MEMORY | SND_SYNC | SND_NODEFAULT);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wav_header_reports_duration() {
+        // 2.5 seconds of silence
+        let wav = encode_wav(&vec![0u8; BYTES_PER_SECOND as usize * 5 / 2]);
+        assert_eq!(wav_duration_ms(&wav), Some(2500));
+        assert_eq!(wav_duration_ms(b"not audio"), None);
+
+        // Cut short in transit: only what arrived counts
+        assert_eq!(wav_duration_ms(&wav[..44 + BYTES_PER_SECOND as usize]), Some(1000));
+    }
+
+    #[test]
+    fn received_duration_comes_from_the_audio() {
+        let wav = encode_wav(&vec![0u8; BYTES_PER_SECOND as usize * 3]);
+        assert_eq!(checked_duration_ms(&wav, 3000).unwrap(), 3000);
+        // A sender claiming a different length doesn't change what we show
+        assert_eq!(checked_duration_ms(&wav, 60_000).unwrap(), 3000);
+        assert!(checked_duration_ms(b"RIFF....WAVE", 1000).is_err());
+
+        let too_long = encode_wav(&vec![0u8; (BYTES_PER_SECOND as u64 * (MAX_DURATION_MS / 1000 + 1)) as usize]);
+        assert!(checked_duration_ms(&too_long, 1000).is_err());
+
+        assert_eq!(format_duration(7_400), "0:07");
+        assert_eq!(format_duration(83_000), "1:23");
+    }
+}
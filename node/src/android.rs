@@ -1,4 +1,5 @@
 use crate::overlay::{OverlayConfig, OverlayHandle, ReplicationEvent};
+use crate::routes::envelopes::PostEnvelope;
 use cryptochat_messaging::EncryptedEnvelope;
 use once_cell::sync::Lazy;
 use std::path::PathBuf;
@@ -11,6 +12,7 @@ use jni::sys::{jboolean, JNI_FALSE, JNI_TRUE};
 use jni::JNIEnv;
 use jni::JavaVM;
 
+use serde::Deserialize;
 use serde_json::json;
 use tracing::{error, info, warn};
 
@@ -193,19 +195,55 @@ fn event_to_json(event: &ReplicationEvent) -> serde_json::Value {
             "messageId": message_id,
             "peer": peer.to_string(),
         }),
+        ReplicationEvent::Delivered { message_id, peer } => json!({
+            "type": "delivered",
+            "messageId": message_id,
+            "peer": peer.to_string(),
+        }),
+        ReplicationEvent::UnderReplicated {
+            message_id,
+            acked,
+            target,
+        } => json!({
+            "type": "underReplicated",
+            "messageId": message_id,
+            "acked": acked,
+            "target": target,
+        }),
     }
 }
 
+/// What `publishEnvelope` accepts: the same `{ envelope, addressing }` body as
+/// `POST /envelopes`, or a bare envelope as older apps send it.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PublishRequest {
+    Addressed(PostEnvelope),
+    Bare(EncryptedEnvelope),
+}
+
 fn publish_envelope(env: &mut JNIEnv, envelope_json: JString) -> anyhow::Result<()> {
     let raw: String = env.get_string(&envelope_json)?.into();
-    let envelope: EncryptedEnvelope = serde_json::from_str(&raw)
+    let request: PublishRequest = serde_json::from_str(&raw)
         .map_err(|err| anyhow::anyhow!("invalid envelope JSON: {err}"))?;
+    let (envelope, addressing) = match request {
+        PublishRequest::Addressed(PostEnvelope {
+            envelope,
+            addressing,
+        }) => {
+            if !addressing.is_valid_for(&envelope) {
+                anyhow::bail!("addressing is not signed by the envelope's sender");
+            }
+            (envelope, Some(addressing))
+        }
+        PublishRequest::Bare(envelope) => (envelope, None),
+    };
 
     with_node(|node| {
         let replication = node.handle.replication().clone();
         let publish_result = node
             .runtime
-            .block_on(async move { replication.publish(envelope).await });
+            .block_on(async move { replication.publish_to(envelope, addressing).await });
         publish_result?;
         Ok(())
     })
@@ -1,4 +1,6 @@
+use crate::overlay::OverlayConfig;
 use std::env;
+use std::path::PathBuf;
 use uuid::Uuid;
 
 /// Runtime configuration for the node service.
@@ -7,6 +9,14 @@ pub struct AppConfig {
     pub host: String,
     pub port: u16,
     pub build_id: String,
+    /// Root directory for persisted node data.
+    pub storage_path: PathBuf,
+    /// Optional name isolating this node's data from other nodes sharing
+    /// `storage_path` on the same host.
+    pub namespace: Option<String>,
+    /// Whether to join the libp2p overlay. Without it the node serves its
+    /// HTTP routes (relay, subscriptions) only.
+    pub overlay_enabled: bool,
 }
 
 impl AppConfig {
@@ -18,10 +28,104 @@ impl AppConfig {
             .unwrap_or(8080);
         let build_id =
             env::var("CRYPTOCHAT_BUILD_ID").unwrap_or_else(|_| Uuid::new_v4().to_string());
+        let storage_path = env::var("CRYPTOCHAT_STORAGE_PATH")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from("data/node"));
+        let namespace = env::var("CRYPTOCHAT_NAMESPACE")
+            .ok()
+            .filter(|ns| !ns.is_empty());
+        if let Some(ns) = &namespace {
+            validate_namespace(ns)?;
+        }
+        let overlay_enabled = env::var("CRYPTOCHAT_OVERLAY")
+            .map(|v| !matches!(v.to_ascii_lowercase().as_str(), "0" | "false" | "off"))
+            .unwrap_or(true);
         Ok(Self {
             host,
             port,
             build_id,
+            storage_path,
+            namespace,
+            overlay_enabled,
         })
     }
+
+    /// Directory holding this node's sled database: `storage_path`, or
+    /// `storage_path/<namespace>` when a namespace is set.
+    pub fn data_dir(&self) -> PathBuf {
+        match &self.namespace {
+            Some(ns) => self.storage_path.join(ns),
+            None => self.storage_path.clone(),
+        }
+    }
+
+    /// Overlay configuration whose storage lives under this node's data directory.
+    pub fn overlay_config(&self) -> OverlayConfig {
+        OverlayConfig::default().with_storage_path(self.data_dir())
+    }
+}
+
+/// Namespaces become directory names, so keep them to a safe character set.
+fn validate_namespace(namespace: &str) -> anyhow::Result<()> {
+    let valid = namespace
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if !valid {
+        anyhow::bail!(
+            "invalid CRYPTOCHAT_NAMESPACE {namespace:?}: use letters, digits, '-' or '_'"
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::NodeStorage;
+    use cryptochat_crypto_core::KeyPair;
+    use cryptochat_messaging::{ConversationId, DeviceId, EncryptedEnvelope, PlaintextMessage};
+    use libp2p::PeerId;
+
+    fn config(root: &std::path::Path, namespace: Option<&str>) -> AppConfig {
+        AppConfig {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+            build_id: "test".to_string(),
+            storage_path: root.to_path_buf(),
+            namespace: namespace.map(str::to_string),
+            overlay_enabled: false,
+        }
+    }
+
+    #[test]
+    fn namespaces_use_isolated_databases() {
+        let root = std::env::temp_dir().join(format!("cryptochat-ns-{}", Uuid::new_v4()));
+        let alpha = config(&root, Some("alpha"));
+        let beta = config(&root, Some("beta"));
+        assert_ne!(alpha.data_dir(), beta.data_dir());
+        assert_eq!(alpha.overlay_config().storage_path, alpha.data_dir());
+
+        let alpha_storage = NodeStorage::open(alpha.data_dir()).unwrap();
+        let beta_storage = NodeStorage::open(beta.data_dir()).unwrap();
+
+        let keypair = KeyPair::from_seed(b"sender").unwrap();
+        let message = PlaintextMessage::new(ConversationId::new(), DeviceId::new(), b"hi".to_vec());
+        let envelope = EncryptedEnvelope::from_plaintext(message, &keypair).unwrap();
+        alpha_storage
+            .insert_outbound(&envelope.message_id.to_string(), &envelope, &[PeerId::random()])
+            .unwrap();
+
+        assert_eq!(alpha_storage.load_pending().unwrap().len(), 1);
+        assert!(beta_storage.load_pending().unwrap().is_empty());
+
+        drop((alpha_storage, beta_storage));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn namespace_must_be_a_plain_directory_name() {
+        assert!(validate_namespace("node-2_test").is_ok());
+        assert!(validate_namespace("../escape").is_err());
+        assert!(validate_namespace("a/b").is_err());
+    }
 }
@@ -1,9 +1,13 @@
 pub mod config;
 pub mod overlay;
 pub mod messaging;
+pub mod pending;
+pub mod relay;
 pub mod routes;
+pub mod self_test;
 pub mod state;
 pub mod storage;
+pub mod transfers;
 
 pub use config::AppConfig;
 pub use routes::router;
@@ -1,23 +1,96 @@
+use anyhow::Context;
 use axum::serve;
-use cryptochat_node::{init_tracing, router, AppConfig, AppState};
+use cryptochat_node::storage::NodeStorage;
+use cryptochat_node::{init_tracing, pending, router, self_test, AppConfig, AppState};
 use std::{net::SocketAddr, sync::Arc};
 use tokio::net::TcpListener;
-use tracing::info;
+use tracing::{info, warn};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
+    if std::env::args().skip(1).any(|arg| arg == "--self-test") {
+        let report = self_test::run_self_test();
+        println!("{report}");
+        std::process::exit(if report.passed() { 0 } else { 1 });
+    }
+
+    if std::env::args().skip(1).any(|arg| arg == "--dump-pending") {
+        let config = AppConfig::from_env()?;
+        // sled locks its data directory, so this only works with the node stopped
+        let storage = NodeStorage::open(config.data_dir()).with_context(|| {
+            format!(
+                "could not open {}; stop the node first, it holds the storage lock while running",
+                config.data_dir().display()
+            )
+        })?;
+        println!("{}", pending::dump_pending(&storage)?);
+        return Ok(());
+    }
+
+    if std::env::args().skip(1).any(|arg| arg == "--replay-pending") {
+        init_tracing();
+        let config = AppConfig::from_env()?;
+        let outcome = pending::replay_pending(config.overlay_config(), pending::REPLAY_WAIT).await?;
+        println!("{outcome}");
+        return Ok(());
+    }
+
     init_tracing();
 
     let config = AppConfig::from_env()?;
-    let state = AppState::new(config.clone());
+    let state = AppState::start(config.clone()).await?;
 
     let app = router(Arc::clone(&state));
 
     let addr: SocketAddr = format!("{}:{}", config.host, config.port).parse()?;
     let listener = TcpListener::bind(addr).await?;
     let local_addr = listener.local_addr()?;
-    info!(%local_addr, build_id = %config.build_id, "starting CryptoChat node service");
+    info!(
+        %local_addr,
+        build_id = %config.build_id,
+        data_dir = %config.data_dir().display(),
+        overlay = config.overlay_enabled,
+        "starting CryptoChat node service"
+    );
+
+    // Peer addresses let the relay pin a sender's host to where it connected from
+    serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(shutdown_signal())
+    .await?;
+
+    info!("shutting down; flushing storage");
+    state.shutdown().await
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix (what service managers send).
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!(error = %e, "failed to listen for Ctrl-C");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                warn!(error = %e, "failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
 
-    serve(listener, app).await?;
-    Ok(())
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
 }
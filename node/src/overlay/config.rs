@@ -17,6 +17,8 @@ pub struct OverlayConfig {
     pub storage_path: PathBuf,
     /// How often to retry pending envelopes.
     pub retry_interval: Duration,
+    /// How often buffered storage writes are flushed to disk.
+    pub flush_interval: Duration,
 }
 
 impl OverlayConfig {
@@ -29,6 +31,11 @@ impl OverlayConfig {
         self.retry_interval = interval;
         self
     }
+
+    pub fn with_flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
 }
 
 impl Default for OverlayConfig {
@@ -40,6 +47,7 @@ impl Default for OverlayConfig {
             max_connections: 128,
             storage_path: PathBuf::from("data/node"),
             retry_interval: Duration::from_secs(30),
+            flush_interval: Duration::from_millis(500),
         }
     }
 }
@@ -0,0 +1,135 @@
+//! Which overlay peers host which client fingerprints.
+//!
+//! Clients are known by their crypto fingerprint, the overlay by libp2p
+//! [`PeerId`]. A node announces every fingerprint with a live subscription as
+//! a Kademlia provider record; the runtime records its own announcements and
+//! the providers it finds for others here, so replication can prefer the
+//! peers that actually serve an envelope's recipient.
+
+use libp2p::kad::RecordKey;
+use libp2p::PeerId;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::{Arc, Mutex, PoisonError};
+
+const PROVIDER_KEY_PREFIX: &str = "cryptochat/fingerprint/";
+
+/// Kademlia key under which the hosts of `fingerprint` are published.
+pub fn provider_key(fingerprint: &str) -> RecordKey {
+    RecordKey::new(&format!("{PROVIDER_KEY_PREFIX}{fingerprint}"))
+}
+
+/// The fingerprint a [`provider_key`] was made for.
+pub fn fingerprint_for_key(key: &RecordKey) -> Option<&str> {
+    std::str::from_utf8(key.as_ref()).ok()?.strip_prefix(PROVIDER_KEY_PREFIX)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PeerDirectory {
+    hosts: Arc<Mutex<HashMap<String, BTreeSet<PeerId>>>>,
+}
+
+impl PeerDirectory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `peer` hosts `fingerprint`. Returns whether it was new.
+    pub fn announce(&self, fingerprint: &str, peer: PeerId) -> bool {
+        let mut hosts = self.hosts.lock().unwrap_or_else(PoisonError::into_inner);
+        hosts.entry(fingerprint.to_string()).or_default().insert(peer)
+    }
+
+    /// `peer` no longer hosts `fingerprint`.
+    pub fn withdraw(&self, fingerprint: &str, peer: &PeerId) {
+        let mut hosts = self.hosts.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(peers) = hosts.get_mut(fingerprint) {
+            peers.remove(peer);
+            if peers.is_empty() {
+                hosts.remove(fingerprint);
+            }
+        }
+    }
+
+    /// Drop every announcement from a peer that left the overlay.
+    pub fn forget_peer(&self, peer: &PeerId) {
+        let mut hosts = self.hosts.lock().unwrap_or_else(PoisonError::into_inner);
+        hosts.retain(|_, peers| {
+            peers.remove(peer);
+            !peers.is_empty()
+        });
+    }
+
+    /// Peers known to host `fingerprint`, in a stable order.
+    pub fn lookup(&self, fingerprint: &str) -> Vec<PeerId> {
+        let hosts = self.hosts.lock().unwrap_or_else(PoisonError::into_inner);
+        hosts
+            .get(fingerprint)
+            .map(|peers| peers.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Up to `factor` replication targets: peers hosting the recipient first,
+/// then the other connected peers. Hosts we aren't connected to are skipped.
+pub fn select_targets(hosting: &[PeerId], connected: &[PeerId], factor: usize) -> Vec<PeerId> {
+    let factor = factor.max(1);
+    let mut targets: Vec<PeerId> = hosting
+        .iter()
+        .filter(|peer| connected.contains(peer))
+        .copied()
+        .collect();
+    for peer in connected {
+        if !targets.contains(peer) {
+            targets.push(*peer);
+        }
+    }
+    targets.truncate(factor);
+    targets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn register_and_lookup() {
+        let directory = PeerDirectory::new();
+        let peer = PeerId::random();
+        assert!(directory.lookup("alice").is_empty());
+
+        assert!(directory.announce("alice", peer));
+        assert!(!directory.announce("alice", peer));
+        assert_eq!(directory.lookup("alice"), vec![peer]);
+        assert!(directory.lookup("bob").is_empty());
+
+        directory.withdraw("alice", &peer);
+        assert!(directory.lookup("alice").is_empty());
+
+        assert_eq!(fingerprint_for_key(&provider_key("alice")), Some("alice"));
+        assert_eq!(fingerprint_for_key(&RecordKey::new(b"other")), None);
+    }
+
+    #[test]
+    fn fingerprint_hosted_by_several_peers() {
+        let directory = PeerDirectory::new();
+        let (first, second, other) = (PeerId::random(), PeerId::random(), PeerId::random());
+        directory.announce("alice", first);
+        directory.announce("alice", second);
+        directory.announce("bob", first);
+
+        let mut expected = vec![first, second];
+        expected.sort();
+        assert_eq!(directory.lookup("alice"), expected);
+
+        // Hosts go first; the rest of the connected peers fill up to the factor
+        let hosting = directory.lookup("alice");
+        let targets = select_targets(&hosting, &[other, second, first], 3);
+        assert_eq!(&targets[..2], &hosting[..]);
+        assert_eq!(targets[2], other);
+        assert_eq!(select_targets(&hosting, &[other, second], 1), vec![second]);
+
+        directory.forget_peer(&first);
+        assert_eq!(directory.lookup("alice"), vec![second]);
+        assert!(directory.lookup("bob").is_empty());
+    }
+}
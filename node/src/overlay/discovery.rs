@@ -1,15 +1,126 @@
-use super::{OverlayConfig, OverlayError, OverlayResult, TransportHandle};
+use super::directory::fingerprint_for_key;
+use super::{OverlayConfig, OverlayError, OverlayResult, PeerDirectory, TransportHandle};
 use libp2p::kad::{store::MemoryStore, Event as KademliaEvent, Mode, QueryId};
 use libp2p::{kad, Multiaddr, PeerId};
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 use std::{str::FromStr, sync::Arc};
 use tokio::sync::{mpsc, Mutex};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
+
+/// Score a peer starts with, and is reset to after its cooldown.
+const INITIAL_SCORE: i32 = 0;
+/// Ceiling so a long good history can't shield a peer that turns bad.
+const MAX_SCORE: i32 = 10;
+/// Peers scoring below this are evicted from the active set.
+pub const EVICTION_THRESHOLD: i32 = -5;
+/// How long an evicted peer is ignored before it may be re-added.
+pub const EVICTION_COOLDOWN: Duration = Duration::from_secs(5 * 60);
+/// Scores drift one point back toward [`INITIAL_SCORE`] per interval, so
+/// old faults (and old good behaviour) stop counting.
+pub const SCORE_DECAY_INTERVAL: Duration = Duration::from_secs(60);
 
 #[derive(Debug, Clone)]
 pub enum DiscoveryEvent {
     PeerAdded(PeerId),
     PeerRemoved(PeerId),
+    /// The peer's score fell below [`EVICTION_THRESHOLD`].
+    PeerEvicted { peer: PeerId, score: i32 },
+}
+
+/// Something a peer did wrong.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PeerFault {
+    /// Rejected or failed a replication request.
+    Failure,
+    Timeout,
+    /// Sent data we couldn't decode.
+    InvalidMessage,
+}
+
+impl PeerFault {
+    fn penalty(self) -> i32 {
+        match self {
+            PeerFault::Failure | PeerFault::Timeout => 2,
+            PeerFault::InvalidMessage => 5,
+        }
+    }
+}
+
+/// A score as of when it last changed.
+#[derive(Debug, Clone, Copy)]
+struct Score {
+    value: i32,
+    updated: Instant,
+}
+
+impl Score {
+    /// The value after decaying from `updated` to `now`.
+    fn at(self, now: Instant) -> i32 {
+        let elapsed = now.saturating_duration_since(self.updated);
+        let steps =
+            (elapsed.as_secs() / SCORE_DECAY_INTERVAL.as_secs()).min(i32::MAX as u64) as i32;
+        if self.value > INITIAL_SCORE {
+            self.value.saturating_sub(steps).max(INITIAL_SCORE)
+        } else {
+            self.value.saturating_add(steps).min(INITIAL_SCORE)
+        }
+    }
+}
+
+/// Per-peer reputation and eviction cooldowns.
+#[derive(Debug, Default)]
+pub struct PeerScores {
+    scores: HashMap<PeerId, Score>,
+    evicted_until: HashMap<PeerId, Instant>,
+}
+
+impl PeerScores {
+    pub fn score(&self, peer: &PeerId, now: Instant) -> i32 {
+        self.scores
+            .get(peer)
+            .map_or(INITIAL_SCORE, |score| score.at(now))
+    }
+
+    /// Apply `change` to `peer`'s decayed score, returning the new value.
+    fn adjust(&mut self, peer: PeerId, now: Instant, change: impl FnOnce(i32) -> i32) -> i32 {
+        let value = change(self.score(&peer, now));
+        self.scores.insert(
+            peer,
+            Score {
+                value,
+                updated: now,
+            },
+        );
+        value
+    }
+
+    pub fn record_success(&mut self, peer: PeerId, now: Instant) {
+        self.adjust(peer, now, |score| (score + 1).min(MAX_SCORE));
+    }
+
+    /// Penalize `peer`, returning its score if this evicted it.
+    pub fn record_fault(&mut self, peer: PeerId, fault: PeerFault, now: Instant) -> Option<i32> {
+        let score = self.adjust(peer, now, |score| score - fault.penalty());
+        if score >= EVICTION_THRESHOLD {
+            return None;
+        }
+        self.scores.remove(&peer);
+        self.evicted_until.insert(peer, now + EVICTION_COOLDOWN);
+        Some(score)
+    }
+
+    /// Whether `peer` may join the active set, clearing an expired cooldown.
+    pub fn admit(&mut self, peer: &PeerId, now: Instant) -> bool {
+        match self.evicted_until.get(peer) {
+            Some(until) if now < *until => false,
+            Some(_) => {
+                self.evicted_until.remove(peer);
+                true
+            }
+            None => true,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -17,6 +128,8 @@ pub struct DiscoveryService {
     config: OverlayConfig,
     transport: TransportHandle,
     peers: Arc<Mutex<HashSet<PeerId>>>,
+    scores: Arc<Mutex<PeerScores>>,
+    directory: PeerDirectory,
     event_tx: mpsc::Sender<DiscoveryEvent>,
 }
 
@@ -27,6 +140,8 @@ impl DiscoveryService {
             config,
             transport,
             peers: Arc::new(Mutex::new(HashSet::new())),
+            scores: Arc::new(Mutex::new(PeerScores::default())),
+            directory: PeerDirectory::new(),
             event_tx,
         }
     }
@@ -56,6 +171,20 @@ impl DiscoveryService {
             KademliaEvent::ModeChanged { new_mode, .. } => {
                 debug!(?new_mode, "kademlia mode changed");
             }
+            KademliaEvent::OutboundQueryProgressed {
+                result:
+                    kad::QueryResult::GetProviders(Ok(kad::GetProvidersOk::FoundProviders {
+                        key,
+                        providers,
+                    })),
+                ..
+            } => {
+                if let Some(fingerprint) = fingerprint_for_key(key) {
+                    for peer in providers {
+                        self.directory.announce(fingerprint, *peer);
+                    }
+                }
+            }
             _ => {}
         }
         Ok(())
@@ -65,6 +194,28 @@ impl DiscoveryService {
         self.peers.lock().await.iter().cloned().collect()
     }
 
+    /// Which peers host which fingerprints.
+    pub fn directory(&self) -> &PeerDirectory {
+        &self.directory
+    }
+
+    /// Credit `peer` for a request it handled.
+    pub async fn record_success(&self, peer: PeerId) {
+        self.scores.lock().await.record_success(peer, Instant::now());
+    }
+
+    /// Penalize `peer`, evicting it from the active set once its score
+    /// falls below [`EVICTION_THRESHOLD`].
+    pub async fn record_fault(&self, peer: PeerId, fault: PeerFault) {
+        let evicted = self.scores.lock().await.record_fault(peer, fault, Instant::now());
+        if let Some(score) = evicted {
+            warn!(%peer, score, ?fault, "evicting misbehaving peer");
+            self.peers.lock().await.remove(&peer);
+            self.directory.forget_peer(&peer);
+            let _ = self.event_tx.send(DiscoveryEvent::PeerEvicted { peer, score }).await;
+        }
+    }
+
     pub fn start_bootstrap_queries(
         &self,
         behaviour: &mut kad::Behaviour<MemoryStore>,
@@ -76,6 +227,10 @@ impl DiscoveryService {
     }
 
     async fn insert_peer(&self, peer: PeerId) {
+        if !self.scores.lock().await.admit(&peer, Instant::now()) {
+            debug!(%peer, "ignoring peer during eviction cooldown");
+            return;
+        }
         let mut peers = self.peers.lock().await;
         if peers.insert(peer) {
             let _ = self.event_tx.send(DiscoveryEvent::PeerAdded(peer)).await;
@@ -83,6 +238,7 @@ impl DiscoveryService {
     }
 
     async fn remove_peer(&self, peer: PeerId) {
+        self.directory.forget_peer(&peer);
         let mut peers = self.peers.lock().await;
         if peers.remove(&peer) {
             let _ = self.event_tx.send(DiscoveryEvent::PeerRemoved(peer)).await;
@@ -109,3 +265,66 @@ fn parse_bootstrap(config: &OverlayConfig) -> OverlayResult<Vec<(PeerId, Multiad
     }
     Ok(peers)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_faults_evict_after_good_history_decays() {
+        let mut scores = PeerScores::default();
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        scores.record_success(peer, now);
+        scores.record_success(peer, now);
+        assert_eq!(scores.score(&peer, now), 2);
+
+        // 2 -> 0 -> -2 -> -4 stays in; the next timeout drops below the threshold
+        for _ in 0..3 {
+            assert_eq!(scores.record_fault(peer, PeerFault::Timeout, now), None);
+        }
+        assert_eq!(scores.score(&peer, now), -4);
+        assert_eq!(scores.record_fault(peer, PeerFault::Timeout, now), Some(-6));
+        assert!(!scores.admit(&peer, now));
+    }
+
+    #[test]
+    fn evicted_peer_is_readmitted_after_cooldown_with_fresh_score() {
+        let mut scores = PeerScores::default();
+        let peer = PeerId::random();
+        let now = Instant::now();
+
+        assert_eq!(scores.record_fault(peer, PeerFault::InvalidMessage, now), None);
+        assert!(scores.record_fault(peer, PeerFault::InvalidMessage, now).is_some());
+
+        assert!(!scores.admit(&peer, now + EVICTION_COOLDOWN / 2));
+        assert!(scores.admit(&peer, now + EVICTION_COOLDOWN));
+        assert_eq!(scores.score(&peer, now + EVICTION_COOLDOWN), INITIAL_SCORE);
+        assert!(scores.admit(&PeerId::random(), now));
+    }
+
+    #[test]
+    fn scores_decay_back_to_the_initial_score() {
+        let mut scores = PeerScores::default();
+        let (bad, good) = (PeerId::random(), PeerId::random());
+        let now = Instant::now();
+
+        scores.record_fault(bad, PeerFault::Timeout, now);
+        scores.record_fault(bad, PeerFault::Timeout, now);
+        for _ in 0..3 {
+            scores.record_success(good, now);
+        }
+        let later = now + 3 * SCORE_DECAY_INTERVAL;
+        assert_eq!(scores.score(&bad, later), -1);
+        assert_eq!(scores.score(&good, later), INITIAL_SCORE);
+        let much_later = now + 10 * SCORE_DECAY_INTERVAL;
+        assert_eq!(scores.score(&bad, much_later), INITIAL_SCORE);
+
+        // Old faults no longer count toward an eviction
+        for _ in 0..2 {
+            assert_eq!(scores.record_fault(bad, PeerFault::Timeout, later), None);
+        }
+        assert_eq!(scores.score(&bad, later), -5);
+    }
+}
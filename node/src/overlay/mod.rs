@@ -1,6 +1,7 @@
 //! Overlay coordination layer backed by libp2p.
 
 mod config;
+mod directory;
 mod discovery;
 mod replication;
 mod runtime;
@@ -8,9 +9,10 @@ mod subscriptions;
 mod transport;
 
 pub use config::OverlayConfig;
-pub use discovery::{DiscoveryEvent, DiscoveryService};
-pub use replication::{ReplicationEvent, ReplicationService};
-pub use subscriptions::{OverlayNotification, SubscriptionManager};
+pub use directory::{select_targets, PeerDirectory};
+pub use discovery::{DiscoveryEvent, DiscoveryService, PeerFault};
+pub use replication::{FanOut, ReplicationEvent, ReplicationService};
+pub use subscriptions::{OverlayNotification, Subscription, SubscriptionManager};
 pub use transport::{OverlayNetwork, TransportHandle};
 
 use crate::storage::NodeStorage;
@@ -41,14 +43,17 @@ pub struct OverlayHandle {
     discovery: DiscoveryService,
     replication: ReplicationService,
     subscriptions: SubscriptionManager,
-    _storage: NodeStorage,
+    storage: NodeStorage,
     runtime_task: tokio::task::JoinHandle<()>,
+    flush_task: tokio::task::JoinHandle<()>,
 }
 
 impl OverlayHandle {
     pub async fn start(config: OverlayConfig) -> OverlayResult<Self> {
         let storage = NodeStorage::open(&config.storage_path)
-            .map_err(|e| OverlayError::Replication(format!("failed to initialize storage: {e}")))?;
+            .map_err(|e| OverlayError::Replication(format!("failed to initialize storage: {e}")))?
+            .with_flush_async(true);
+        let flush_task = storage.spawn_periodic_flush(config.flush_interval);
 
         let (transport, runtime_components) = OverlayNetwork::initialize(&config).await?;
         let discovery = DiscoveryService::new(config.clone(), transport.clone());
@@ -56,12 +61,11 @@ impl OverlayHandle {
         let subscriptions = SubscriptionManager::new();
 
         let runtime = OverlayRuntime::new(
-            runtime_components.swarm,
-            runtime_components.command_rx,
-            runtime_components.replication_factor,
+            runtime_components,
             config.retry_interval,
             discovery.clone(),
             replication.clone(),
+            subscriptions.clone(),
             storage.clone(),
         );
         let runtime_handle = tokio::spawn(async move { runtime.run().await });
@@ -73,8 +77,9 @@ impl OverlayHandle {
             discovery,
             replication,
             subscriptions,
-            _storage: storage,
+            storage,
             runtime_task: runtime_handle,
+            flush_task,
         })
     }
 
@@ -84,8 +89,9 @@ impl OverlayHandle {
             discovery: _,
             replication,
             subscriptions: _,
-            _storage: _,
+            storage,
             runtime_task,
+            flush_task,
         } = self;
 
         let shutdown_rx = transport.request_shutdown().await?;
@@ -97,6 +103,12 @@ impl OverlayHandle {
             .await
             .map_err(|e| OverlayError::Transport(format!("runtime join error: {e}")))?;
 
+        // The runtime has stopped writing; make sure everything it wrote is on disk.
+        flush_task.abort();
+        storage
+            .flush()
+            .map_err(|e| OverlayError::Replication(format!("final storage flush failed: {e}")))?;
+
         drop(replication);
         Ok(())
     }
@@ -119,4 +131,8 @@ impl OverlayHandle {
     pub fn transport(&self) -> &TransportHandle {
         &self.transport
     }
+
+    pub fn storage(&self) -> &NodeStorage {
+        &self.storage
+    }
 }
@@ -1,7 +1,9 @@
 use super::{OverlayConfig, OverlayError, OverlayResult, TransportHandle};
-use cryptochat_messaging::EncryptedEnvelope;
+use crate::storage::NodeStorage;
+use cryptochat_messaging::{Addressing, EncryptedEnvelope};
 use libp2p::PeerId;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use tokio::sync::{broadcast, oneshot};
 use tracing::debug;
 
@@ -11,12 +13,32 @@ pub enum ReplicationEvent {
     PublishAck { message_id: String, peer: PeerId },
     PublishFailed { message_id: String, reason: String },
     PublishRetry { message_id: String, peer: PeerId },
+    /// A peer holds the envelope and a delivery receipt was recorded.
+    Delivered { message_id: String, peer: PeerId },
+    /// Fewer peers hold the envelope than the replication factor asks for.
+    UnderReplicated { message_id: String, acked: usize, target: usize },
+}
+
+/// How many peers have acknowledged a message against the replication target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FanOut {
+    pub message_id: String,
+    pub acked: usize,
+    pub target: usize,
+}
+
+impl FanOut {
+    pub fn is_under_replicated(&self) -> bool {
+        self.acked < self.target
+    }
 }
 
 struct ReplicationInner {
     event_tx: broadcast::Sender<ReplicationEvent>,
     transport: TransportHandle,
-    _config: OverlayConfig,
+    config: OverlayConfig,
+    /// Ack count last reported for each under-replicated message.
+    reported: Mutex<HashMap<String, usize>>,
 }
 
 #[derive(Clone)]
@@ -31,7 +53,8 @@ impl ReplicationService {
             inner: Arc::new(ReplicationInner {
                 event_tx,
                 transport,
-                _config: config,
+                config,
+                reported: Mutex::new(HashMap::new()),
             }),
         }
     }
@@ -41,11 +64,22 @@ impl ReplicationService {
     }
 
     pub async fn publish(&self, envelope: EncryptedEnvelope) -> OverlayResult<()> {
+        self.publish_to(envelope, None).await
+    }
+
+    /// Like [`publish`](Self::publish), but replicating to the peers known to
+    /// host the addressed recipient first, which push it to the recipient's
+    /// subscribers.
+    pub async fn publish_to(
+        &self,
+        envelope: EncryptedEnvelope,
+        addressing: Option<Addressing>,
+    ) -> OverlayResult<()> {
         let message_id = envelope.message_id.to_string();
         let (tx, rx) = oneshot::channel();
         self.inner
             .transport
-            .publish(envelope, tx)
+            .publish(envelope, addressing, tx)
             .await
             .map_err(|e| OverlayError::Replication(format!("send command failed: {e}")))?;
 
@@ -64,6 +98,71 @@ impl ReplicationService {
         }
     }
 
+    /// Number of peers each message should reach.
+    pub fn target_factor(&self) -> usize {
+        self.inner.config.replication_factor.max(1)
+    }
+
+    /// Fan-out of every pending message, emitting
+    /// [`ReplicationEvent::UnderReplicated`] for each one short of the target
+    /// whose ack count changed since the last report.
+    pub async fn fan_out_report(&self, storage: &NodeStorage) -> OverlayResult<Vec<FanOut>> {
+        let pending = storage.load_pending().map_err(|e| {
+            OverlayError::Replication(format!("failed to load pending envelopes: {e}"))
+        })?;
+        let target = self.target_factor();
+        let report: Vec<FanOut> = pending
+            .into_iter()
+            .map(|record| FanOut {
+                message_id: record.message_id,
+                acked: record.acked_peers.len(),
+                target,
+            })
+            .collect();
+
+        let under: HashMap<String, usize> = report
+            .iter()
+            .filter(|f| f.is_under_replicated())
+            .map(|f| (f.message_id.clone(), f.acked))
+            .collect();
+        let changed: Vec<(String, usize)> = {
+            let mut reported = self
+                .inner
+                .reported
+                .lock()
+                .map_err(|_| OverlayError::Replication("lock poisoned".into()))?;
+            let changed = under
+                .iter()
+                .filter(|(id, acked)| reported.get(*id) != Some(acked))
+                .map(|(id, acked)| (id.clone(), *acked))
+                .collect();
+            *reported = under;
+            changed
+        };
+        if !changed.is_empty() {
+            debug!(
+                pending = report.len(),
+                changed = changed.len(),
+                target,
+                "replication fan-out"
+            );
+        }
+        for (message_id, acked) in changed {
+            self.notify_under_replicated(&message_id, acked).await;
+        }
+        Ok(report)
+    }
+
+    pub async fn notify_under_replicated(&self, message_id: &str, acked: usize) {
+        let target = self.target_factor();
+        debug!(message_id, acked, target, "message under-replicated");
+        let _ = self.inner.event_tx.send(ReplicationEvent::UnderReplicated {
+            message_id: message_id.to_string(),
+            acked,
+            target,
+        });
+    }
+
     pub async fn notify_enqueued(&self, message_id: &str) {
         debug!(message_id, "replication queued");
         let _ = self.inner.event_tx.send(ReplicationEvent::PublishQueued {
@@ -79,6 +178,14 @@ impl ReplicationService {
         });
     }
 
+    pub async fn notify_delivered(&self, message_id: &str, peer: &PeerId) {
+        debug!(message_id, ?peer, "replication delivered");
+        let _ = self.inner.event_tx.send(ReplicationEvent::Delivered {
+            message_id: message_id.to_string(),
+            peer: *peer,
+        });
+    }
+
     pub async fn notify_failure(&self, message_id: &str, reason: String) {
         debug!(message_id, reason = reason.as_str(), "replication failure");
         let _ = self.inner.event_tx.send(ReplicationEvent::PublishFailed {
@@ -95,3 +202,48 @@ impl ReplicationService {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overlay::OverlayNetwork;
+    use cryptochat_crypto_core::KeyPair;
+    use cryptochat_messaging::{ConversationId, DeviceId, PlaintextMessage};
+
+    #[tokio::test]
+    async fn two_acks_of_three_is_reported_under_replicated() {
+        let dir = std::env::temp_dir().join(format!("cryptochat-node-{}", uuid::Uuid::new_v4()));
+        let mut config = OverlayConfig::default().with_storage_path(&dir);
+        config.replication_factor = 3;
+        let storage = NodeStorage::open(&config.storage_path).unwrap();
+        let (transport, _components) = OverlayNetwork::initialize(&config).await.unwrap();
+        let replication = ReplicationService::new(config, transport);
+        let mut events = replication.subscribe();
+
+        let keypair = KeyPair::from_seed(b"sender").unwrap();
+        let message = PlaintextMessage::new(ConversationId::new(), DeviceId::new(), b"hi".to_vec());
+        let envelope = EncryptedEnvelope::from_plaintext(message, &keypair).unwrap();
+        let message_id = envelope.message_id.to_string();
+        let peers = [PeerId::random(), PeerId::random(), PeerId::random()];
+        storage.insert_outbound(&message_id, &envelope, &peers).unwrap();
+        storage.mark_peer_success(&message_id, &peers[0]).unwrap();
+        storage.mark_peer_success(&message_id, &peers[1]).unwrap();
+
+        let report = replication.fan_out_report(&storage).await.unwrap();
+        let expected = FanOut { message_id: message_id.clone(), acked: 2, target: 3 };
+        assert_eq!(report, vec![expected]);
+
+        match events.recv().await.unwrap() {
+            ReplicationEvent::UnderReplicated { message_id: id, acked, target } => {
+                assert_eq!((id, acked, target), (message_id, 2, 3));
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+
+        // Nothing changed, so the next check stays quiet
+        assert_eq!(replication.fan_out_report(&storage).await.unwrap().len(), 1);
+        assert!(events.try_recv().is_err());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,53 +1,75 @@
+use super::directory::{provider_key, select_targets};
 use super::transport::{
-    EnvelopeRequest, EnvelopeResponse, NodeBehaviour, NodeEvent, OverlayCommand,
+    verify_replicas, EnvelopeRequest, EnvelopeResponse, NodeBehaviour, NodeEvent, OverlayCommand,
+    RuntimeComponents,
+};
+use super::{
+    DiscoveryService, OverlayError, OverlayNotification, OverlayResult, PeerFault,
+    ReplicationService, SubscriptionManager,
 };
-use super::{DiscoveryService, OverlayError, OverlayResult, ReplicationService};
 use crate::storage::{NodeStorage, PendingEnvelope};
+use cryptochat_messaging::EncryptedEnvelope;
 use futures::StreamExt;
 use libp2p::kad::QueryId;
 use libp2p::request_response::{
-    Event as RequestResponseEvent, Message as RequestResponseMessage, OutboundRequestId,
+    Event as RequestResponseEvent, InboundFailure, Message as RequestResponseMessage,
+    OutboundFailure, OutboundRequestId, ResponseChannel,
 };
 use libp2p::swarm::{Swarm, SwarmEvent};
-use libp2p::PeerId;
-use std::collections::HashMap;
+use libp2p::{identity, PeerId};
+use std::collections::{HashMap, HashSet};
 use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::mpsc;
 use tokio::time::{interval, MissedTickBehavior};
 use tracing::{debug, warn};
 
+/// Inbound replicas verified together once this many are queued.
+const INBOUND_BATCH: usize = 64;
+/// How long an inbound replica waits for others to batch with.
+const INBOUND_FLUSH_INTERVAL: Duration = Duration::from_millis(20);
+
 pub struct OverlayRuntime {
     swarm: Swarm<NodeBehaviour>,
     command_rx: mpsc::Receiver<OverlayCommand>,
+    node_key: identity::ed25519::Keypair,
     discovery: DiscoveryService,
     replication: ReplicationService,
+    subscriptions: SubscriptionManager,
     storage: NodeStorage,
     replication_factor: usize,
     retry_interval: Duration,
     pending_replications: HashMap<OutboundRequestId, (String, PeerId)>,
     bootstrap_query: Option<QueryId>,
+    /// Fingerprints we currently announce as a provider for.
+    announced: HashSet<String>,
+    /// Inbound replicas waiting for their signatures to be checked.
+    inbound: Vec<(PeerId, EnvelopeRequest, ResponseChannel<EnvelopeResponse>)>,
 }
 
 impl OverlayRuntime {
     pub fn new(
-        swarm: Swarm<NodeBehaviour>,
-        command_rx: mpsc::Receiver<OverlayCommand>,
-        replication_factor: usize,
+        components: RuntimeComponents,
         retry_interval: Duration,
         discovery: DiscoveryService,
         replication: ReplicationService,
+        subscriptions: SubscriptionManager,
         storage: NodeStorage,
     ) -> Self {
         Self {
-            swarm,
-            command_rx,
+            swarm: components.swarm,
+            command_rx: components.command_rx,
+            node_key: components.node_key,
             discovery,
             replication,
+            subscriptions,
             storage,
-            replication_factor,
+            replication_factor: components.replication_factor,
             retry_interval,
             pending_replications: HashMap::new(),
             bootstrap_query: None,
+            announced: HashSet::new(),
+            inbound: Vec::new(),
         }
     }
 
@@ -65,10 +87,15 @@ impl OverlayRuntime {
             warn!(?err, "failed to replay pending envelopes");
         }
 
+        let mut hosting_rx = self.subscriptions.subscribe();
+        self.sync_hosting();
+
         let mut retry_timer = interval(self.retry_interval);
         retry_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
         // Skip the immediate first tick since we just replayed pending items.
         retry_timer.tick().await;
+        let mut inbound_timer = interval(INBOUND_FLUSH_INTERVAL);
+        inbound_timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
 
         loop {
             tokio::select! {
@@ -79,7 +106,7 @@ impl OverlayRuntime {
                                 warn!(%err, %addr, "dial failure");
                             }
                         }
-                        Some(OverlayCommand::Publish { envelope, responder }) => {
+                        Some(OverlayCommand::Publish { envelope, addressing, responder }) => {
                             let message_id = envelope.message_id.to_string();
                             let peers = self.discovery.peers().await;
                             if peers.is_empty() {
@@ -91,13 +118,35 @@ impl OverlayRuntime {
                                 continue;
                             }
 
-                            let max_targets = self.replication_factor.max(1);
-                            let target_peers: Vec<PeerId> = peers.into_iter().take(max_targets).collect();
+                            let recipient = addressing.as_ref().map(|a| &a.recipient_fingerprint);
+                            let hosting = match recipient {
+                                Some(fingerprint) => {
+                                    // Refresh the hosts for next time; use what we know now
+                                    self.swarm
+                                        .behaviour_mut()
+                                        .kademlia
+                                        .get_providers(provider_key(fingerprint));
+                                    self.discovery.directory().lookup(fingerprint)
+                                }
+                                None => Vec::new(),
+                            };
+                            let target_peers =
+                                select_targets(&hosting, &peers, self.replication_factor);
 
-                            if let Err(err) = self
+                            let stored = self
                                 .storage
                                 .insert_outbound(&message_id, &envelope, &target_peers)
-                            {
+                                .and_then(|()| match &addressing {
+                                    Some(addressing) => {
+                                        self.storage.store_addressing(&message_id, addressing)
+                                    }
+                                    None => Ok(()),
+                                });
+                            let stored = match stored {
+                                Ok(()) => self.storage.flush_async().await,
+                                Err(err) => Err(err),
+                            };
+                            if let Err(err) = stored {
                                 let reason = format!("failed to persist envelope: {err}");
                                 let _ = responder
                                     .send(Err(OverlayError::Replication(reason.clone())));
@@ -119,7 +168,11 @@ impl OverlayRuntime {
                                     .request_response
                                     .send_request(
                                         peer,
-                                        EnvelopeRequest { envelope: envelope.clone() },
+                                        EnvelopeRequest::signed(
+                                            envelope.clone(),
+                                            addressing.clone(),
+                                            &self.node_key,
+                                        ),
                                     );
                                 self.pending_replications
                                     .insert(request_id, (message_id.clone(), peer.clone()));
@@ -141,15 +194,47 @@ impl OverlayRuntime {
                 event = self.swarm.select_next_some() => {
                     self.handle_swarm_event(event).await;
                 }
+                notification = hosting_rx.recv() => {
+                    match notification {
+                        Ok(OverlayNotification::HostingChanged { .. })
+                        | Err(RecvError::Lagged(_)) => self.sync_hosting(),
+                        Ok(_) | Err(RecvError::Closed) => {}
+                    }
+                }
+                _ = inbound_timer.tick(), if !self.inbound.is_empty() => {
+                    self.flush_inbound().await;
+                }
                 _ = retry_timer.tick() => {
                     if let Err(err) = self.retry_pending().await {
                         warn!(?err, "failed to retry pending envelopes");
                     }
+                    if let Err(err) = self.replication.fan_out_report(&self.storage).await {
+                        warn!(?err, "failed to check replication fan-out");
+                    }
                 }
             }
         }
     }
 
+    /// Bring our provider records in line with the live subscriptions.
+    fn sync_hosting(&mut self) {
+        let hosted = self.subscriptions.hosted_fingerprints();
+        let local_peer = *self.swarm.local_peer_id();
+        let directory = self.discovery.directory().clone();
+        let kademlia = &mut self.swarm.behaviour_mut().kademlia;
+        for fingerprint in hosted.difference(&self.announced) {
+            if let Err(err) = kademlia.start_providing(provider_key(fingerprint)) {
+                warn!(?err, %fingerprint, "failed to announce fingerprint");
+            }
+            directory.announce(fingerprint, local_peer);
+        }
+        for fingerprint in self.announced.difference(&hosted) {
+            kademlia.stop_providing(&provider_key(fingerprint));
+            directory.withdraw(fingerprint, &local_peer);
+        }
+        self.announced = hosted;
+    }
+
     fn dial_addr(&mut self, addr: libp2p::Multiaddr) -> OverlayResult<()> {
         let dial_opts = libp2p::swarm::dial_opts::DialOpts::unknown_peer_id()
             .address(addr.clone())
@@ -212,9 +297,11 @@ impl OverlayRuntime {
 
                 let request_id = self.swarm.behaviour_mut().request_response.send_request(
                     peer,
-                    EnvelopeRequest {
-                        envelope: record.envelope.clone(),
-                    },
+                    EnvelopeRequest::signed(
+                        record.envelope.clone(),
+                        record.addressing.clone(),
+                        &self.node_key,
+                    ),
                 );
 
                 self.pending_replications
@@ -225,6 +312,54 @@ impl OverlayRuntime {
         Ok(())
     }
 
+    /// Persist a replica and get it onto disk before we acknowledge it.
+    async fn store_inbound(&self, envelope: &EncryptedEnvelope) -> anyhow::Result<()> {
+        self.storage.store_inbound(envelope)?;
+        self.storage.flush_async().await
+    }
+
+    /// Check the queued inbound replicas' signatures as one batch, then
+    /// store and answer each of them.
+    async fn flush_inbound(&mut self) {
+        let batch = std::mem::take(&mut self.inbound);
+        let signed: Vec<_> = batch
+            .iter()
+            .map(|(peer, request, _)| (*peer, request))
+            .collect();
+        let verified = verify_replicas(&signed);
+
+        for ((peer, request, channel), verified) in batch.into_iter().zip(verified) {
+            let accepted = if !verified {
+                warn!(
+                    %peer,
+                    message_id = %request.envelope.message_id,
+                    "replica not signed by the node that sent it"
+                );
+                self.discovery.record_fault(peer, PeerFault::InvalidMessage).await;
+                false
+            } else {
+                match self.store_inbound(&request.envelope).await {
+                    Ok(()) => {
+                        notify_recipient(&self.subscriptions, request);
+                        true
+                    }
+                    Err(err) => {
+                        warn!(?err, %peer, "failed to persist inbound envelope");
+                        false
+                    }
+                }
+            };
+            if let Err(err) = self
+                .swarm
+                .behaviour_mut()
+                .request_response
+                .send_response(channel, EnvelopeResponse { accepted })
+            {
+                warn!(?err, %peer, "failed to send replication response");
+            }
+        }
+    }
+
     fn in_flight(&self, message_id: &str, peer: &PeerId) -> bool {
         self.pending_replications
             .values()
@@ -239,26 +374,12 @@ impl OverlayRuntime {
             RequestResponseEvent::Message { peer, message } => match message {
                 RequestResponseMessage::Request {
                     request, channel, ..
-                } => match self.storage.store_inbound(&request.envelope) {
-                    Ok(_) => {
-                        if let Err(err) = self
-                            .swarm
-                            .behaviour_mut()
-                            .request_response
-                            .send_response(channel, EnvelopeResponse { accepted: true })
-                        {
-                            warn!(?err, %peer, "failed to send replication response");
-                        }
-                    }
-                    Err(err) => {
-                        warn!(?err, %peer, "failed to persist inbound envelope");
-                        let _ = self
-                            .swarm
-                            .behaviour_mut()
-                            .request_response
-                            .send_response(channel, EnvelopeResponse { accepted: false });
+                } => {
+                    self.inbound.push((peer, request, channel));
+                    if self.inbound.len() >= INBOUND_BATCH {
+                        self.flush_inbound().await;
                     }
-                },
+                }
                 RequestResponseMessage::Response {
                     request_id,
                     response,
@@ -267,17 +388,20 @@ impl OverlayRuntime {
                         self.pending_replications.remove(&request_id)
                     {
                         if response.accepted {
-                            match self.storage.mark_peer_success(&message_id, &expected_peer) {
-                                Ok(_) => {
-                                    self.replication
-                                        .notify_ack(&message_id, &expected_peer)
-                                        .await;
-                                }
-                                Err(err) => {
-                                    warn!(?err, %expected_peer, "failed to update storage after ack");
-                                }
+                            self.discovery.record_success(expected_peer).await;
+                            if let Err(err) = record_delivery(
+                                &self.storage,
+                                &self.replication,
+                                &self.subscriptions,
+                                &message_id,
+                                &expected_peer,
+                            )
+                            .await
+                            {
+                                warn!(?err, %expected_peer, "failed to update storage after ack");
                             }
                         } else {
+                            self.discovery.record_fault(expected_peer, PeerFault::Failure).await;
                             let reason = "replication rejected".to_string();
                             self.replication.notify_failure(&message_id, reason).await;
                         }
@@ -291,9 +415,14 @@ impl OverlayRuntime {
                 error,
                 request_id,
             } => {
-                if let Some((message_id, _expected_peer)) =
+                if let Some((message_id, expected_peer)) =
                     self.pending_replications.remove(&request_id)
                 {
+                    let fault = match error {
+                        OutboundFailure::Timeout => PeerFault::Timeout,
+                        _ => PeerFault::Failure,
+                    };
+                    self.discovery.record_fault(expected_peer, fault).await;
                     let reason = format!("outbound failure: {error:?}");
                     self.replication.notify_failure(&message_id, reason).await;
                 } else {
@@ -306,6 +435,10 @@ impl OverlayRuntime {
                 request_id,
             } => {
                 debug!(%peer, ?request_id, ?error, "replication inbound failure");
+                // I/O errors on inbound requests are how undecodable payloads surface
+                if matches!(error, InboundFailure::Io(_)) {
+                    self.discovery.record_fault(peer, PeerFault::InvalidMessage).await;
+                }
             }
             RequestResponseEvent::ResponseSent { peer, .. } => {
                 debug!(%peer, "replication response sent");
@@ -313,3 +446,141 @@ impl OverlayRuntime {
         }
     }
 }
+
+/// Handle a peer accepting its replica: persist the receipt, emit the
+/// replication events, and route the receipt to the sender's subscription.
+async fn record_delivery(
+    storage: &NodeStorage,
+    replication: &ReplicationService,
+    subscriptions: &SubscriptionManager,
+    message_id: &str,
+    peer: &PeerId,
+) -> anyhow::Result<()> {
+    let ack = storage.mark_peer_success(message_id, peer)?;
+    storage.flush_async().await?;
+    replication.notify_ack(message_id, peer).await;
+    replication.notify_delivered(message_id, peer).await;
+    // Every targeted peer answered, but there weren't enough of them
+    if ack.complete && ack.acked < replication.target_factor() {
+        replication.notify_under_replicated(message_id, ack.acked).await;
+    }
+
+    if let Some(sender_fingerprint) = ack.sender_fingerprint {
+        subscriptions.notify(OverlayNotification::ReceiptAcknowledged {
+            receipt: ack.receipt,
+            sender_fingerprint,
+        })?;
+    }
+    Ok(())
+}
+
+/// Push a stored replica to its recipient's subscribers, if its sender
+/// signed who it is for. Unaddressed replicas are only stored.
+fn notify_recipient(subscriptions: &SubscriptionManager, request: EnvelopeRequest) {
+    let Some(addressing) = request.addressing else {
+        return;
+    };
+    if !addressing.is_valid_for(&request.envelope) {
+        debug!(
+            message_id = %request.envelope.message_id,
+            "replica addressing not signed by its sender"
+        );
+        return;
+    }
+    let _ = subscriptions.notify(OverlayNotification::EnvelopeReceived {
+        recipient_fingerprint: addressing.recipient_fingerprint,
+        envelope: request.envelope,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::overlay::{OverlayConfig, OverlayNetwork, ReplicationEvent};
+    use cryptochat_crypto_core::pgp::PgpKeyPair;
+    use cryptochat_crypto_core::KeyPair;
+    use cryptochat_messaging::{
+        Addressing, ConversationId, DeviceId, EncryptedEnvelope, PlaintextMessage,
+    };
+
+    #[tokio::test]
+    async fn successful_replication_produces_delivery_event_and_receipt() {
+        let dir = std::env::temp_dir().join(format!("cryptochat-node-{}", uuid::Uuid::new_v4()));
+        let config = OverlayConfig::default().with_storage_path(&dir);
+        let storage = NodeStorage::open(&config.storage_path).unwrap();
+        let (transport, _components) = OverlayNetwork::initialize(&config).await.unwrap();
+        let replication = ReplicationService::new(config, transport);
+        let subscriptions = SubscriptionManager::new();
+        let mut replication_rx = replication.subscribe();
+        let mut notification_rx = subscriptions.subscribe();
+
+        let keypair = KeyPair::from_seed(b"sender").unwrap();
+        let message = PlaintextMessage::new(ConversationId::new(), DeviceId::new(), b"hi".to_vec());
+        let envelope = EncryptedEnvelope::from_plaintext(message, &keypair).unwrap();
+        let message_id = envelope.message_id.to_string();
+        let peer = PeerId::random();
+        storage.insert_outbound(&message_id, &envelope, &[peer]).unwrap();
+
+        record_delivery(&storage, &replication, &subscriptions, &message_id, &peer)
+            .await
+            .unwrap();
+
+        assert!(matches!(replication_rx.recv().await.unwrap(), ReplicationEvent::PublishAck { .. }));
+        match replication_rx.recv().await.unwrap() {
+            ReplicationEvent::Delivered { message_id: id, peer: p } => {
+                assert_eq!(id, message_id);
+                assert_eq!(p, peer);
+            }
+            other => panic!("unexpected event {other:?}"),
+        }
+
+        match notification_rx.recv().await.unwrap() {
+            OverlayNotification::ReceiptAcknowledged { receipt, sender_fingerprint } => {
+                assert_eq!(receipt.message_id, envelope.message_id);
+                assert_eq!(sender_fingerprint, keypair.fingerprint().as_str());
+            }
+            other => panic!("unexpected notification {other:?}"),
+        }
+
+        let receipts = storage.load_receipts(&message_id).unwrap();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].1, peer);
+        assert!(storage.load_pending().unwrap().is_empty());
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn addressed_replicas_reach_the_recipients_subscribers() {
+        let subscriptions = SubscriptionManager::new();
+        let mut notification_rx = subscriptions.subscribe();
+        let alice = PgpKeyPair::generate("alice").unwrap();
+        let mallory = PgpKeyPair::generate("mallory").unwrap();
+
+        let keypair = KeyPair::from_seed(b"sender").unwrap();
+        let message = PlaintextMessage::new(ConversationId::new(), DeviceId::new(), b"hi".to_vec());
+        let mut envelope = EncryptedEnvelope::from_plaintext(message, &keypair).unwrap();
+        envelope.sender_fingerprint = alice.fingerprint();
+        let node_key = identity::ed25519::Keypair::generate();
+        let request = |addressing| EnvelopeRequest::signed(envelope.clone(), addressing, &node_key);
+
+        // Unaddressed, or addressed by someone other than the sender
+        notify_recipient(&subscriptions, request(None));
+        let forged = Addressing::sign(&envelope, "BOB", &mallory).unwrap();
+        notify_recipient(&subscriptions, request(Some(forged)));
+        let addressing = Addressing::sign(&envelope, "BOB", &alice).unwrap();
+        notify_recipient(&subscriptions, request(Some(addressing)));
+
+        match notification_rx.try_recv().unwrap() {
+            OverlayNotification::EnvelopeReceived {
+                recipient_fingerprint,
+                envelope: received,
+            } => {
+                assert_eq!(recipient_fingerprint, "BOB");
+                assert_eq!(received.message_id, envelope.message_id);
+            }
+            other => panic!("unexpected notification {other:?}"),
+        }
+        assert!(notification_rx.try_recv().is_err());
+    }
+}
@@ -1,40 +1,186 @@
 use super::{OverlayError, OverlayResult};
-use cryptochat_messaging::EncryptedEnvelope;
+use cryptochat_messaging::{DeliveryReceipt, EncryptedEnvelope};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::warn;
 
-#[derive(Debug, Clone)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "snake_case")]
 pub enum OverlayNotification {
-    EnvelopeReceived(EncryptedEnvelope),
-    ReceiptAcknowledged(String),
+    /// An envelope addressed to `recipient_fingerprint` arrived.
+    EnvelopeReceived {
+        recipient_fingerprint: String,
+        envelope: EncryptedEnvelope,
+    },
+    /// A replica of one of `sender_fingerprint`'s envelopes was delivered.
+    ReceiptAcknowledged {
+        receipt: DeliveryReceipt,
+        sender_fingerprint: String,
+    },
+    /// Sent straight to a subscriber once it has proven its fingerprint and
+    /// is registered; nothing addressed to it from then on is missed.
+    Subscribed { fingerprint: String },
+    /// The first subscriber for `fingerprint` registered (`hosted`), or the
+    /// last one went away. Meant for the overlay runtime, not clients.
+    HostingChanged { fingerprint: String, hosted: bool },
+    #[allow(dead_code)]
     DiscoveryUpdate(String),
 }
 
-#[derive(Clone, Default)]
-#[allow(dead_code)]
+impl OverlayNotification {
+    /// Fingerprint of the client this notification is meant for, if any.
+    pub fn fingerprint(&self) -> Option<&str> {
+        match self {
+            OverlayNotification::EnvelopeReceived {
+                recipient_fingerprint,
+                ..
+            } => Some(recipient_fingerprint),
+            OverlayNotification::ReceiptAcknowledged {
+                sender_fingerprint, ..
+            } => Some(sender_fingerprint),
+            OverlayNotification::Subscribed { fingerprint } => Some(fingerprint),
+            OverlayNotification::HostingChanged { .. } | OverlayNotification::DiscoveryUpdate(_) => {
+                None
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    next_id: usize,
+    active: HashMap<usize, String>,
+}
+
+impl Registry {
+    fn hosts(&self, fingerprint: &str) -> bool {
+        self.active.values().any(|active| active == fingerprint)
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct SubscriptionManager {
-    subscribers: Arc<Mutex<Vec<usize>>>,
+    subscribers: Arc<Mutex<Registry>>,
+    notifications: broadcast::Sender<OverlayNotification>,
+}
+
+impl Default for SubscriptionManager {
+    fn default() -> Self {
+        let (notifications, _rx) = broadcast::channel(128);
+        Self {
+            subscribers: Arc::new(Mutex::new(Registry::default())),
+            notifications,
+        }
+    }
 }
 
 impl SubscriptionManager {
-    #[allow(dead_code)]
     pub fn new() -> Self {
         Self::default()
     }
 
-    #[allow(dead_code)]
-    pub fn register(&self) -> OverlayResult<usize> {
-        let mut subs = self
+    /// Register a subscriber for notifications addressed to `fingerprint`.
+    ///
+    /// The registration is removed when the returned [`Subscription`] is dropped.
+    pub fn register(&self, fingerprint: &str) -> OverlayResult<Subscription> {
+        let mut registry = self
             .subscribers
             .lock()
             .map_err(|_| OverlayError::Subscription("lock poisoned".into()))?;
-        let id = subs.len();
-        subs.push(id);
-        Ok(id)
+        let id = registry.next_id;
+        registry.next_id += 1;
+        let newly_hosted = !registry.hosts(fingerprint);
+        registry.active.insert(id, fingerprint.to_string());
+        drop(registry);
+        if newly_hosted {
+            self.notify(OverlayNotification::HostingChanged {
+                fingerprint: fingerprint.to_string(),
+                hosted: true,
+            })?;
+        }
+        Ok(Subscription {
+            id,
+            fingerprint: fingerprint.to_string(),
+            receiver: self.notifications.subscribe(),
+            manager: self.clone(),
+        })
     }
 
-    #[allow(dead_code)]
-    pub fn notify(&self, _event: OverlayNotification) -> OverlayResult<()> {
-        Err(OverlayError::NotImplemented)
+    /// Number of registered subscribers.
+    pub fn active_count(&self) -> usize {
+        self.subscribers
+            .lock()
+            .map(|registry| registry.active.len())
+            .unwrap_or(0)
+    }
+
+    /// Fingerprints with at least one registered subscriber.
+    pub fn hosted_fingerprints(&self) -> HashSet<String> {
+        self.subscribers
+            .lock()
+            .map(|registry| registry.active.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Unfiltered stream of notifications published after this call.
+    pub fn subscribe(&self) -> broadcast::Receiver<OverlayNotification> {
+        self.notifications.subscribe()
+    }
+
+    /// Publish a notification to every current subscriber. Having no
+    /// subscribers is not an error.
+    pub fn notify(&self, event: OverlayNotification) -> OverlayResult<()> {
+        let _ = self.notifications.send(event);
+        Ok(())
+    }
+
+    fn unregister(&self, id: usize) {
+        let Ok(mut registry) = self.subscribers.lock() else {
+            return;
+        };
+        let Some(fingerprint) = registry.active.remove(&id) else {
+            return;
+        };
+        if !registry.hosts(&fingerprint) {
+            drop(registry);
+            let _ = self.notify(OverlayNotification::HostingChanged {
+                fingerprint,
+                hosted: false,
+            });
+        }
+    }
+}
+
+/// A registered subscriber, yielding only notifications for its fingerprint.
+pub struct Subscription {
+    id: usize,
+    fingerprint: String,
+    receiver: broadcast::Receiver<OverlayNotification>,
+    manager: SubscriptionManager,
+}
+
+impl Subscription {
+    /// Wait for the next notification for this subscriber.
+    pub async fn next(&mut self) -> Option<OverlayNotification> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(notification) if notification.fingerprint() == Some(&self.fingerprint) => {
+                    return Some(notification)
+                }
+                Ok(_) => {}
+                Err(RecvError::Lagged(skipped)) => {
+                    warn!(skipped, fingerprint = %self.fingerprint, "subscriber lagged; notifications dropped");
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.manager.unregister(self.id);
     }
 }
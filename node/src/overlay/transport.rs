@@ -1,7 +1,9 @@
 use super::{OverlayConfig, OverlayError, OverlayResult};
 use async_trait::async_trait;
 use bincode;
-use cryptochat_messaging::EncryptedEnvelope;
+use cryptochat_crypto_core::batch::{verify_batch, BatchItem};
+use cryptochat_crypto_core::CryptoError;
+use cryptochat_messaging::{Addressing, EncryptedEnvelope};
 use futures::prelude::*;
 use libp2p::core::{muxing::StreamMuxerBox, transport::Transport as CoreTransport};
 use libp2p::{
@@ -10,7 +12,7 @@ use libp2p::{
         store::MemoryStore, Behaviour as KademliaBehaviour, Config as KademliaConfig,
         Event as KademliaEvent,
     },
-    ping, quic,
+    ping, quic, relay,
     request_response::{
         self, Behaviour as RequestResponse, Config as RequestResponseConfig,
         Event as RequestResponseEvent, ProtocolSupport,
@@ -25,7 +27,9 @@ use tokio::sync::{mpsc, oneshot};
 const IDENTIFY_PROTOCOL: &str = "/cryptochat/overlay/1.0.0";
 const AGENT_VERSION: &str = concat!("cryptochat-node/", env!("CARGO_PKG_VERSION"));
 const KAD_PROTOCOL: &str = "/cryptochat/kad/1.0.0";
-const ENVELOPE_PROTOCOL: &str = "/cryptochat/envelope/1.0.0";
+/// 2.0.0 added the addressing to [`EnvelopeRequest`], 3.0.0 the sending
+/// node's signature.
+const ENVELOPE_PROTOCOL: &str = "/cryptochat/envelope/3.0.0";
 
 /// Commands sent to the overlay runtime.
 #[derive(Debug)]
@@ -33,6 +37,9 @@ pub enum OverlayCommand {
     Dial(Multiaddr),
     Publish {
         envelope: EncryptedEnvelope,
+        /// Who the envelope is for, so the peers hosting them are preferred
+        /// and can push it to the recipient's subscribers.
+        addressing: Option<Addressing>,
         responder: oneshot::Sender<OverlayResult<()>>,
     },
     Shutdown(oneshot::Sender<()>),
@@ -41,6 +48,85 @@ pub enum OverlayCommand {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EnvelopeRequest {
     pub envelope: EncryptedEnvelope,
+    /// The sender's signed addressing, when the publisher had one. The
+    /// receiving node checks it again before notifying anyone.
+    pub addressing: Option<Addressing>,
+    /// Raw Ed25519 key of the node sending the replica; it must be the key
+    /// behind that node's peer id.
+    pub node_key: Vec<u8>,
+    /// The sending node's signature over [`replica_statement`].
+    pub signature: Vec<u8>,
+}
+
+impl EnvelopeRequest {
+    /// A replica request signed with our node key.
+    pub(crate) fn signed(
+        envelope: EncryptedEnvelope,
+        addressing: Option<Addressing>,
+        node_key: &identity::ed25519::Keypair,
+    ) -> Self {
+        let signature = node_key.sign(&replica_statement(&envelope, &addressing));
+        Self {
+            envelope,
+            addressing,
+            node_key: node_key.public().to_bytes().to_vec(),
+            signature,
+        }
+    }
+}
+
+/// What a node signs when it sends a replica: the envelope together with its
+/// addressing, so neither can be swapped on the way.
+fn replica_statement(envelope: &EncryptedEnvelope, addressing: &Option<Addressing>) -> Vec<u8> {
+    // Same types the codec serializes for every request
+    bincode::serialize(&(b"cryptochat-replica-v1", envelope, addressing))
+        .expect("envelope requests serialize")
+}
+
+/// Check the signatures on a burst of inbound replicas in one batch,
+/// returning whether each one verified. A replica only counts when it was
+/// signed with the key of the peer that sent it.
+pub(crate) fn verify_replicas(requests: &[(PeerId, &EnvelopeRequest)]) -> Vec<bool> {
+    let statements: Vec<Vec<u8>> = requests
+        .iter()
+        .map(|(_, request)| replica_statement(&request.envelope, &request.addressing))
+        .collect();
+    let mut pending: Vec<usize> = (0..requests.len())
+        .filter(|&index| {
+            let (peer, request) = &requests[index];
+            identity::ed25519::PublicKey::try_from_bytes(&request.node_key)
+                .is_ok_and(|key| identity::PublicKey::from(key).to_peer_id() == *peer)
+        })
+        .collect();
+
+    let mut verified = vec![false; requests.len()];
+    while !pending.is_empty() {
+        let items: Vec<BatchItem<'_>> = pending
+            .iter()
+            .map(|&index| {
+                let request = requests[index].1;
+                (
+                    request.node_key.as_slice(),
+                    statements[index].as_slice(),
+                    request.signature.as_slice(),
+                )
+            })
+            .collect();
+        match verify_batch(&items) {
+            Ok(()) => {
+                for &index in &pending {
+                    verified[index] = true;
+                }
+                break;
+            }
+            // Drop the culprit and check the rest again
+            Err(CryptoError::BatchVerificationFailed(culprit)) => {
+                pending.remove(culprit);
+            }
+            Err(_) => break,
+        }
+    }
+    verified
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -123,6 +209,8 @@ pub(crate) struct NodeBehaviour {
     pub ping: ping::Behaviour,
     pub kademlia: KademliaBehaviour<MemoryStore>,
     pub(crate) request_response: RequestResponse<EnvelopeCodec>,
+    /// Circuit relay for peers that can't accept direct connections.
+    pub relay: relay::Behaviour,
 }
 
 #[derive(Debug)]
@@ -132,6 +220,7 @@ pub enum NodeEvent {
     Ping(ping::Event),
     Kademlia(KademliaEvent),
     RequestResponse(RequestResponseEvent<EnvelopeRequest, EnvelopeResponse>),
+    Relay(relay::Event),
 }
 
 impl From<identify::Event> for NodeEvent {
@@ -158,6 +247,12 @@ impl From<RequestResponseEvent<EnvelopeRequest, EnvelopeResponse>> for NodeEvent
     }
 }
 
+impl From<relay::Event> for NodeEvent {
+    fn from(event: relay::Event) -> Self {
+        Self::Relay(event)
+    }
+}
+
 struct TransportState {
     peer_id: PeerId,
     command_tx: mpsc::Sender<OverlayCommand>,
@@ -176,13 +271,16 @@ pub(crate) struct RuntimeComponents {
     pub(crate) swarm: Swarm<NodeBehaviour>,
     pub(crate) command_rx: mpsc::Receiver<OverlayCommand>,
     pub(crate) replication_factor: usize,
+    /// Signs the replicas we send; the same key as our peer id.
+    pub(crate) node_key: identity::ed25519::Keypair,
 }
 
 impl OverlayNetwork {
     pub(crate) async fn initialize(
         config: &OverlayConfig,
     ) -> OverlayResult<(TransportHandle, RuntimeComponents)> {
-        let local_key = identity::Keypair::generate_ed25519();
+        let node_key = identity::ed25519::Keypair::generate();
+        let local_key = identity::Keypair::from(node_key.clone());
         let local_peer_id = PeerId::from(local_key.public());
 
         let identify_cfg = identify::Config::new(IDENTIFY_PROTOCOL.into(), local_key.public())
@@ -210,6 +308,7 @@ impl OverlayNetwork {
             ping: ping::Behaviour::new(ping_cfg),
             kademlia,
             request_response,
+            relay: relay::Behaviour::new(local_peer_id, relay::Config::default()),
         };
 
         let transport = quic::tokio::Transport::new(quic::Config::new(&local_key))
@@ -244,6 +343,7 @@ impl OverlayNetwork {
                 swarm,
                 command_rx,
                 replication_factor: config.replication_factor.max(1),
+                node_key,
             },
         ))
     }
@@ -265,12 +365,14 @@ impl TransportHandle {
     pub async fn publish(
         &self,
         envelope: EncryptedEnvelope,
+        addressing: Option<Addressing>,
         responder: oneshot::Sender<OverlayResult<()>>,
     ) -> OverlayResult<()> {
         self.inner
             .command_tx
             .send(OverlayCommand::Publish {
                 envelope,
+                addressing,
                 responder,
             })
             .await
@@ -287,3 +389,47 @@ impl TransportHandle {
         Ok(rx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptochat_crypto_core::KeyPair;
+    use cryptochat_messaging::{ConversationId, DeviceId, PlaintextMessage};
+
+    fn envelope(body: &[u8]) -> EncryptedEnvelope {
+        let keypair = KeyPair::from_seed(b"sender").unwrap();
+        let message = PlaintextMessage::new(ConversationId::new(), DeviceId::new(), body.to_vec());
+        EncryptedEnvelope::from_plaintext(message, &keypair).unwrap()
+    }
+
+    fn peer_of(key: &identity::ed25519::Keypair) -> PeerId {
+        identity::PublicKey::from(key.public()).to_peer_id()
+    }
+
+    #[test]
+    fn replicas_verify_in_a_batch_and_bad_ones_are_singled_out() {
+        let (node, other) = (
+            identity::ed25519::Keypair::generate(),
+            identity::ed25519::Keypair::generate(),
+        );
+        let good = EnvelopeRequest::signed(envelope(b"one"), None, &node);
+        let mut tampered = EnvelopeRequest::signed(envelope(b"two"), None, &node);
+        tampered.envelope.created_ms += 1;
+        let signed_by_other = EnvelopeRequest::signed(envelope(b"three"), None, &other);
+        let also_good = EnvelopeRequest::signed(envelope(b"four"), None, &node);
+
+        let batch = [
+            (peer_of(&node), &good),
+            (peer_of(&node), &tampered),
+            // Validly signed, but not by the peer that sent it
+            (peer_of(&node), &signed_by_other),
+            (peer_of(&node), &also_good),
+        ];
+        assert_eq!(verify_replicas(&batch), vec![true, false, false, true]);
+        assert_eq!(
+            verify_replicas(&[(peer_of(&other), &signed_by_other)]),
+            vec![true]
+        );
+        assert!(verify_replicas(&[]).is_empty());
+    }
+}
@@ -0,0 +1,189 @@
+//! `--dump-pending` / `--replay-pending`: the replication queue for operators.
+//!
+//! Both read the node's data directory, so run them while the node itself is
+//! stopped. Only envelope metadata (id, sender, creation time and size) is
+//! ever printed; payloads stay sealed.
+
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{timeout_at, Instant};
+
+use crate::overlay::{OverlayConfig, OverlayHandle, ReplicationEvent};
+use crate::storage::{NodeStorage, PendingEnvelope};
+
+/// How long `--replay-pending` waits for peers to acknowledge.
+pub const REPLAY_WAIT: Duration = Duration::from_secs(15);
+
+/// Metadata of one queued envelope.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingSummary {
+    pub message_id: String,
+    pub sender_fingerprint: String,
+    pub created_ms: i64,
+    pub ciphertext_bytes: usize,
+    pub acked: usize,
+    pub pending_peers: Vec<String>,
+}
+
+impl From<&PendingEnvelope> for PendingSummary {
+    fn from(record: &PendingEnvelope) -> Self {
+        Self {
+            message_id: record.message_id.clone(),
+            sender_fingerprint: record.envelope.sender_fingerprint.clone(),
+            created_ms: record.envelope.created_ms,
+            ciphertext_bytes: record.envelope.payload.ciphertext.len(),
+            acked: record.acked_peers.len(),
+            pending_peers: record.pending_peers.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+}
+
+/// Everything still waiting on at least one peer.
+#[derive(Debug, Clone, Default)]
+pub struct PendingReport {
+    pub entries: Vec<PendingSummary>,
+}
+
+impl fmt::Display for PendingReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.entries.is_empty() {
+            return write!(f, "replication queue is empty");
+        }
+        for entry in &self.entries {
+            writeln!(
+                f,
+                "{} from {} created_ms={} ciphertext={}B acked={} pending={}",
+                entry.message_id,
+                entry.sender_fingerprint,
+                entry.created_ms,
+                entry.ciphertext_bytes,
+                entry.acked,
+                entry.pending_peers.len(),
+            )?;
+            for peer in &entry.pending_peers {
+                writeln!(f, "    waiting on {peer}")?;
+            }
+        }
+        write!(f, "{} message(s) pending", self.entries.len())
+    }
+}
+
+/// Read the replication queue.
+pub fn dump_pending(storage: &NodeStorage) -> Result<PendingReport> {
+    let pending = storage.load_pending().context("loading the replication queue")?;
+    let mut entries: Vec<PendingSummary> = pending.iter().map(PendingSummary::from).collect();
+    entries.sort_by(|a, b| (a.created_ms, &a.message_id).cmp(&(b.created_ms, &b.message_id)));
+    Ok(PendingReport { entries })
+}
+
+/// Result of a `--replay-pending` run.
+#[derive(Debug, Clone, Default)]
+pub struct ReplayOutcome {
+    /// Message id to how many peers still hadn't acknowledged after the wait.
+    pub still_pending: BTreeMap<String, usize>,
+    pub delivered: Vec<String>,
+}
+
+impl fmt::Display for ReplayOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for message_id in &self.delivered {
+            writeln!(f, "{message_id} delivered")?;
+        }
+        for (message_id, peers) in &self.still_pending {
+            writeln!(f, "{message_id} still waiting on {peers} peer(s)")?;
+        }
+        write!(
+            f,
+            "redelivery attempted for {} message(s): {} delivered, {} still pending",
+            self.delivered.len() + self.still_pending.len(),
+            self.delivered.len(),
+            self.still_pending.len(),
+        )
+    }
+}
+
+/// Start the overlay (which resends everything pending as it comes up), wait
+/// up to `wait` for acknowledgements, then shut it down again.
+pub async fn replay_pending(config: OverlayConfig, wait: Duration) -> Result<ReplayOutcome> {
+    let before = {
+        let storage = NodeStorage::open(&config.storage_path)?;
+        dump_pending(&storage)?
+    };
+    let mut waiting: BTreeMap<String, HashSet<String>> = before
+        .entries
+        .into_iter()
+        .map(|entry| (entry.message_id, entry.pending_peers.into_iter().collect()))
+        .collect();
+    if waiting.is_empty() {
+        return Ok(ReplayOutcome::default());
+    }
+
+    let overlay = OverlayHandle::start(config).await?;
+    let mut events = overlay.subscribe_replication();
+    let deadline = Instant::now() + wait;
+    while waiting.values().any(|peers| !peers.is_empty()) {
+        match timeout_at(deadline, events.recv()).await {
+            Ok(Ok(ReplicationEvent::PublishAck { message_id, peer })) => {
+                if let Some(peers) = waiting.get_mut(&message_id) {
+                    peers.remove(&peer.to_string());
+                }
+            }
+            Ok(Ok(_)) | Ok(Err(RecvError::Lagged(_))) => {}
+            Ok(Err(RecvError::Closed)) | Err(_) => break,
+        }
+    }
+    overlay.shutdown().await?;
+
+    let mut outcome = ReplayOutcome::default();
+    for (message_id, peers) in waiting {
+        if peers.is_empty() {
+            outcome.delivered.push(message_id);
+        } else {
+            outcome.still_pending.insert(message_id, peers.len());
+        }
+    }
+    Ok(outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptochat_crypto_core::KeyPair;
+    use cryptochat_messaging::{ConversationId, DeviceId, EncryptedEnvelope, PlaintextMessage};
+    use libp2p::PeerId;
+
+    #[test]
+    fn dump_lists_metadata_without_contents() {
+        let dir = std::env::temp_dir().join(format!("cryptochat-pending-{}", uuid::Uuid::new_v4()));
+        let storage = NodeStorage::open(&dir).unwrap();
+        assert_eq!(dump_pending(&storage).unwrap().to_string(), "replication queue is empty");
+
+        let key_pair = KeyPair::from_seed(b"pending").unwrap();
+        let message = PlaintextMessage::new(ConversationId::new(), DeviceId::new(), b"top secret".to_vec());
+        let envelope = EncryptedEnvelope::from_plaintext(message, &key_pair).unwrap();
+        let message_id = envelope.message_id.to_string();
+        let (acked, pending) = (PeerId::random(), PeerId::random());
+        storage.insert_outbound(&message_id, &envelope, &[acked, pending]).unwrap();
+        storage.mark_peer_success(&message_id, &acked).unwrap();
+
+        let report = dump_pending(&storage).unwrap();
+        assert_eq!(report.entries.len(), 1);
+        assert_eq!(report.entries[0].acked, 1);
+        assert_eq!(report.entries[0].pending_peers, vec![pending.to_string()]);
+
+        let text = report.to_string();
+        assert!(text.starts_with(&format!("{message_id} from {} ", key_pair.fingerprint())), "{text}");
+        assert!(text.contains("acked=1 pending=1"), "{text}");
+        assert!(text.contains(&format!("    waiting on {pending}")), "{text}");
+        assert!(text.ends_with("1 message(s) pending"), "{text}");
+        assert!(!text.contains("top secret"));
+        assert!(!text.contains(&envelope.payload.ciphertext));
+
+        drop(storage);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
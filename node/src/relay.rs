@@ -0,0 +1,680 @@
+//! Store-and-forward mailbox for clients that can't reach each other directly.
+//!
+//! A client whose direct connection fails submits its envelope here for the
+//! recipient's fingerprint. The recipient collects its mailbox by signing a
+//! one-time challenge from the node with the key behind that fingerprint, and
+//! messages stay queued until a later fetch acknowledges them by id, so a
+//! client that crashes mid-poll gets them again. Payloads are the clients'
+//! own end-to-end encrypted envelopes and are never inspected. Mailboxes live
+//! in memory, are capped per recipient, in number and in bytes (in total and
+//! per submitting address), and drop anything older than [`RELAY_TTL_MS`].
+//!
+//! Anyone may ask for a challenge, so a fingerprint can have several
+//! outstanding at once and answering one leaves the others alone. Asking
+//! for more than [`MAX_CHALLENGES_PER_CALLER`] a minute is refused. Otherwise
+//! a stranger asking over and over could keep the owner from ever getting in.
+//!
+//! Nodes running the overlay also act as libp2p circuit relays, so peers
+//! behind NAT can be reached over the overlay itself; this mailbox covers
+//! clients that only speak HTTP.
+
+use cryptochat_crypto_core::pgp::PgpKeyPair;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Longest a relayed message waits for its recipient.
+pub const RELAY_TTL_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+/// Messages kept per recipient; the oldest go first.
+pub const MAX_MAILBOX_LEN: usize = 256;
+/// Recipients with a mailbox at once; also caps the fingerprints and
+/// callers a challenge store keeps track of.
+pub const MAX_MAILBOXES: usize = 10_000;
+/// Largest payload accepted for relaying.
+pub const MAX_PAYLOAD_BYTES: usize = 1024 * 1024;
+/// Most payload bytes held across every mailbox.
+pub const MAX_RELAY_BYTES: usize = 256 * 1024 * 1024;
+/// Most payload bytes held from any one submitting address.
+pub const MAX_SENDER_BYTES: usize = 16 * 1024 * 1024;
+/// How long a challenge stays valid.
+pub const CHALLENGE_TTL_MS: i64 = 60 * 1000;
+/// Challenges outstanding per fingerprint; another one drops the oldest.
+/// More than one caller may hold in a [`CHALLENGE_TTL_MS`], so no single
+/// address can push out everyone else's.
+pub const MAX_CHALLENGES_PER_KEY: usize = 32;
+/// Challenges one address may ask for per [`CHALLENGE_RATE_WINDOW_MS`],
+/// comfortably above a client polling its mailbox every few seconds.
+pub const MAX_CHALLENGES_PER_CALLER: usize = 30;
+pub const CHALLENGE_RATE_WINDOW_MS: i64 = 60 * 1000;
+
+/// Body of `POST /relay`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelaySubmit {
+    pub recipient_fingerprint: String,
+    pub sender_fingerprint: String,
+    /// Where the sender says it can be reached directly (`host:port`). The
+    /// node replaces the host with the address the submission came from.
+    pub sender_address: String,
+    /// The client envelope, opaque to the node.
+    pub payload: String,
+}
+
+/// Response of `GET /relay/{fingerprint}/challenge`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayChallenge {
+    pub challenge: String,
+}
+
+/// Body of `POST /relay/{fingerprint}/fetch`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RelayFetch {
+    /// Armored public key; its fingerprint must be the mailbox's.
+    pub public_key: String,
+    /// The challenge the node handed out for this mailbox.
+    pub challenge: String,
+    /// Armored detached signature over [`fetch_statement`].
+    pub signature: String,
+    /// Ids of messages handled since the last fetch, to delete.
+    #[serde(default)]
+    pub ack: Vec<u64>,
+}
+
+/// One entry of the fetch response.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RelayedMessage {
+    /// Acknowledge this to have the message deleted.
+    pub id: u64,
+    pub sender_fingerprint: String,
+    pub sender_address: String,
+    pub payload: String,
+    pub received_ms: i64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RelayError {
+    #[error("recipient fingerprint is empty")]
+    NoRecipient,
+    #[error("payload is {0} bytes; the relay accepts at most {MAX_PAYLOAD_BYTES}")]
+    TooLarge(usize),
+    #[error("relay is holding the maximum of {MAX_MAILBOXES} mailboxes")]
+    Full,
+    #[error("relay has no room for another {0} bytes; try again later")]
+    OutOfSpace(usize),
+    #[error("too many bytes queued from this address; try again later")]
+    SenderQuota,
+    #[error("too many challenges requested; try again later")]
+    RateLimited,
+    #[error("mailbox access denied: {0}")]
+    Unauthorized(&'static str),
+}
+
+/// What a fetch signs: the mailbox and the node's challenge.
+pub fn fetch_statement(fingerprint: &str, challenge: &str) -> Vec<u8> {
+    format!("cryptochat-relay-fetch:{fingerprint}:{challenge}").into_bytes()
+}
+
+/// What a subscriber signs to open `GET /subscribe`: its fingerprint and a
+/// challenge from `GET /subscribe/{fingerprint}/challenge`.
+pub fn subscribe_statement(fingerprint: &str, challenge: &str) -> Vec<u8> {
+    format!("cryptochat-subscribe:{fingerprint}:{challenge}").into_bytes()
+}
+
+/// `claimed` (`host:port`) with the host replaced by the address the
+/// request actually came from, so a sender can't point replies elsewhere.
+pub fn observed_sender_address(claimed: &str, peer: IpAddr) -> String {
+    let port = claimed
+        .rsplit_once(':')
+        .and_then(|(_, port)| port.parse().ok())
+        .unwrap_or(0);
+    SocketAddr::new(peer, port).to_string()
+}
+
+/// Outstanding challenges, with a cap on how fast any caller asks for them.
+#[derive(Debug, Clone, Default)]
+pub struct Challenges {
+    inner: Arc<Mutex<ChallengeState>>,
+}
+
+#[derive(Debug, Default)]
+struct ChallengeState {
+    /// Challenges (and when each was issued) per fingerprint, oldest first.
+    issued: HashMap<String, VecDeque<(String, i64)>>,
+    /// When each caller asked for its recent challenges, oldest first.
+    requests: HashMap<IpAddr, VecDeque<i64>>,
+}
+
+impl ChallengeState {
+    fn prune(&mut self, now_ms: i64) {
+        self.issued.retain(|_, challenges| {
+            challenges.retain(|(_, issued_ms)| now_ms - *issued_ms < CHALLENGE_TTL_MS);
+            !challenges.is_empty()
+        });
+        self.requests.retain(|_, asked| {
+            asked.retain(|at_ms| now_ms - *at_ms < CHALLENGE_RATE_WINDOW_MS);
+            !asked.is_empty()
+        });
+    }
+}
+
+impl Challenges {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hand `caller` a one-time challenge for proving it holds the key
+    /// behind `fingerprint`. Earlier challenges stay valid, up to
+    /// [`MAX_CHALLENGES_PER_KEY`].
+    pub fn issue(
+        &self,
+        fingerprint: &str,
+        caller: IpAddr,
+        now_ms: i64,
+    ) -> Result<String, RelayError> {
+        let mut state = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        if !state.issued.contains_key(fingerprint) || !state.requests.contains_key(&caller) {
+            state.prune(now_ms);
+        }
+        let full = (!state.issued.contains_key(fingerprint) && state.issued.len() >= MAX_MAILBOXES)
+            || (!state.requests.contains_key(&caller) && state.requests.len() >= MAX_MAILBOXES);
+        if full {
+            return Err(RelayError::Full);
+        }
+
+        let asked = state.requests.entry(caller).or_default();
+        asked.retain(|at_ms| now_ms - *at_ms < CHALLENGE_RATE_WINDOW_MS);
+        if asked.len() >= MAX_CHALLENGES_PER_CALLER {
+            return Err(RelayError::RateLimited);
+        }
+        asked.push_back(now_ms);
+
+        let challenge = uuid::Uuid::new_v4().to_string();
+        let outstanding = state.issued.entry(fingerprint.to_string()).or_default();
+        outstanding.retain(|(_, issued_ms)| now_ms - *issued_ms < CHALLENGE_TTL_MS);
+        if outstanding.len() >= MAX_CHALLENGES_PER_KEY {
+            outstanding.pop_front();
+        }
+        outstanding.push_back((challenge.clone(), now_ms));
+        Ok(challenge)
+    }
+
+    /// Check that whoever signed `statement` holds the key behind
+    /// `fingerprint`, using up `challenge` whether or not the signature
+    /// checks out. Other challenges for the fingerprint are left alone.
+    pub fn authenticate(
+        &self,
+        fingerprint: &str,
+        public_key: &str,
+        challenge: &str,
+        signature: &str,
+        statement: &[u8],
+        now_ms: i64,
+    ) -> Result<(), RelayError> {
+        let issued = {
+            let mut state = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+            state.issued.get_mut(fingerprint).and_then(|outstanding| {
+                let at = outstanding
+                    .iter()
+                    .position(|(issued, _)| issued == challenge)?;
+                outstanding.remove(at)
+            })
+        };
+        match issued {
+            Some((_, issued_ms)) if now_ms - issued_ms < CHALLENGE_TTL_MS => {}
+            _ => return Err(RelayError::Unauthorized("unknown or expired challenge")),
+        }
+        let key = PgpKeyPair::from_public_key(public_key)
+            .map_err(|_| RelayError::Unauthorized("unusable public key"))?;
+        if !key.fingerprint().eq_ignore_ascii_case(fingerprint) {
+            return Err(RelayError::Unauthorized("key does not match the mailbox"));
+        }
+        PgpKeyPair::verify_detached(key.cert(), statement, signature)
+            .map_err(|_| RelayError::Unauthorized("bad signature"))?;
+        Ok(())
+    }
+}
+
+/// A queued message and the address it was submitted from.
+#[derive(Debug)]
+struct Queued {
+    message: RelayedMessage,
+    from: IpAddr,
+}
+
+/// Payload bytes queued, in total and per submitting address.
+#[derive(Debug, Default)]
+struct Usage {
+    total: usize,
+    per_sender: HashMap<IpAddr, usize>,
+}
+
+impl Usage {
+    fn sender(&self, from: &IpAddr) -> usize {
+        self.per_sender.get(from).copied().unwrap_or(0)
+    }
+
+    fn add(&mut self, from: IpAddr, bytes: usize) {
+        self.total += bytes;
+        *self.per_sender.entry(from).or_default() += bytes;
+    }
+
+    fn release(&mut self, queued: &Queued) {
+        let bytes = queued.message.payload.len();
+        self.total -= bytes;
+        if let Some(held) = self.per_sender.get_mut(&queued.from) {
+            *held -= bytes;
+            if *held == 0 {
+                self.per_sender.remove(&queued.from);
+            }
+        }
+    }
+
+    /// Keep the messages of `queue` that `keep` accepts, releasing the rest.
+    fn retain(
+        &mut self,
+        queue: &mut VecDeque<Queued>,
+        mut keep: impl FnMut(&RelayedMessage) -> bool,
+    ) {
+        queue.retain(|queued| {
+            let kept = keep(&queued.message);
+            if !kept {
+                self.release(queued);
+            }
+            kept
+        });
+    }
+}
+
+#[derive(Debug, Default)]
+struct Mailboxes {
+    queues: HashMap<String, VecDeque<Queued>>,
+    usage: Usage,
+    next_id: u64,
+}
+
+impl Mailboxes {
+    fn prune(&mut self, now_ms: i64) {
+        let usage = &mut self.usage;
+        self.queues.retain(|_, queue| {
+            usage.retain(queue, |m| now_ms - m.received_ms < RELAY_TTL_MS);
+            !queue.is_empty()
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RelayMailbox {
+    inner: Arc<Mutex<Mailboxes>>,
+    challenges: Challenges,
+    max_total_bytes: usize,
+    max_sender_bytes: usize,
+}
+
+impl Default for RelayMailbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RelayMailbox {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::default(),
+            challenges: Challenges::new(),
+            max_total_bytes: MAX_RELAY_BYTES,
+            max_sender_bytes: MAX_SENDER_BYTES,
+        }
+    }
+
+    /// Override the byte budgets for the whole relay and per sender.
+    pub fn with_capacity(mut self, max_total_bytes: usize, max_sender_bytes: usize) -> Self {
+        self.max_total_bytes = max_total_bytes;
+        self.max_sender_bytes = max_sender_bytes;
+        self
+    }
+
+    /// Queue a message for its recipient, counting it against `from`'s quota.
+    pub fn submit(&self, submit: RelaySubmit, from: IpAddr, now_ms: i64) -> Result<(), RelayError> {
+        if submit.recipient_fingerprint.is_empty() {
+            return Err(RelayError::NoRecipient);
+        }
+        let bytes = submit.payload.len();
+        if bytes > MAX_PAYLOAD_BYTES {
+            return Err(RelayError::TooLarge(bytes));
+        }
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let new_mailbox = !inner.queues.contains_key(&submit.recipient_fingerprint);
+        if new_mailbox || inner.usage.total + bytes > self.max_total_bytes {
+            inner.prune(now_ms);
+        }
+        if new_mailbox && inner.queues.len() >= MAX_MAILBOXES {
+            return Err(RelayError::Full);
+        }
+        if inner.usage.sender(&from) + bytes > self.max_sender_bytes {
+            return Err(RelayError::SenderQuota);
+        }
+        if inner.usage.total + bytes > self.max_total_bytes {
+            return Err(RelayError::OutOfSpace(bytes));
+        }
+
+        inner.next_id += 1;
+        let id = inner.next_id;
+        let Mailboxes { queues, usage, .. } = &mut *inner;
+        let mailbox = queues.entry(submit.recipient_fingerprint).or_default();
+        usage.retain(mailbox, |m| now_ms - m.received_ms < RELAY_TTL_MS);
+        if mailbox.len() >= MAX_MAILBOX_LEN {
+            if let Some(oldest) = mailbox.pop_front() {
+                usage.release(&oldest);
+            }
+        }
+        usage.add(from, bytes);
+        mailbox.push_back(Queued {
+            message: RelayedMessage {
+                id,
+                sender_fingerprint: submit.sender_fingerprint,
+                sender_address: submit.sender_address,
+                payload: submit.payload,
+                received_ms: now_ms,
+            },
+            from,
+        });
+        Ok(())
+    }
+
+    /// Hand `caller` a one-time challenge for fetching `fingerprint`'s
+    /// mailbox.
+    pub fn challenge(
+        &self,
+        fingerprint: &str,
+        caller: IpAddr,
+        now_ms: i64,
+    ) -> Result<String, RelayError> {
+        self.challenges.issue(fingerprint, caller, now_ms)
+    }
+
+    /// Check a signature over `statement` against one of the challenges
+    /// handed out for `fingerprint`; see [`Challenges::authenticate`].
+    pub fn authenticate(
+        &self,
+        fingerprint: &str,
+        public_key: &str,
+        challenge: &str,
+        signature: &str,
+        statement: &[u8],
+        now_ms: i64,
+    ) -> Result<(), RelayError> {
+        self.challenges.authenticate(
+            fingerprint,
+            public_key,
+            challenge,
+            signature,
+            statement,
+            now_ms,
+        )
+    }
+
+    /// Delete the acknowledged messages and return everything else waiting
+    /// for `fingerprint`, oldest first. The caller must prove it holds the
+    /// mailbox's key by signing one of its challenges.
+    pub fn fetch(
+        &self,
+        fingerprint: &str,
+        auth: &RelayFetch,
+        now_ms: i64,
+    ) -> Result<Vec<RelayedMessage>, RelayError> {
+        self.authenticate(
+            fingerprint,
+            &auth.public_key,
+            &auth.challenge,
+            &auth.signature,
+            &fetch_statement(fingerprint, &auth.challenge),
+            now_ms,
+        )?;
+
+        let mut inner = self.inner.lock().unwrap_or_else(PoisonError::into_inner);
+        let Mailboxes { queues, usage, .. } = &mut *inner;
+        let Some(mailbox) = queues.get_mut(fingerprint) else {
+            return Ok(Vec::new());
+        };
+        usage.retain(mailbox, |m| {
+            !auth.ack.contains(&m.id) && now_ms - m.received_ms < RELAY_TTL_MS
+        });
+        let waiting: Vec<RelayedMessage> = mailbox.iter().map(|q| q.message.clone()).collect();
+        if waiting.is_empty() {
+            queues.remove(fingerprint);
+        }
+        Ok(waiting)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALICE_IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(203, 0, 113, 5));
+    const BOB_IP: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(198, 51, 100, 7));
+
+    fn submit(recipient: &str, payload: &str) -> RelaySubmit {
+        RelaySubmit {
+            recipient_fingerprint: recipient.to_string(),
+            sender_fingerprint: "ALICE".to_string(),
+            sender_address: "203.0.113.5:62780".to_string(),
+            payload: payload.to_string(),
+        }
+    }
+
+    fn signed_fetch(
+        relay: &RelayMailbox,
+        key: &PgpKeyPair,
+        ack: Vec<u64>,
+        now_ms: i64,
+    ) -> RelayFetch {
+        let fingerprint = key.fingerprint();
+        let challenge = relay.challenge(&fingerprint, BOB_IP, now_ms).unwrap();
+        RelayFetch {
+            public_key: key.export_public_key().unwrap(),
+            signature: key
+                .sign_detached(&fetch_statement(&fingerprint, &challenge))
+                .unwrap(),
+            challenge,
+            ack,
+        }
+    }
+
+    #[test]
+    fn mailboxes_are_per_recipient_capped_and_expire() {
+        let bob = PgpKeyPair::generate("bob").unwrap();
+        let bob_fp = bob.fingerprint();
+        let relay = RelayMailbox::new();
+        relay.submit(submit(&bob_fp, "old"), ALICE_IP, 0).unwrap();
+        for i in 0..MAX_MAILBOX_LEN {
+            relay
+                .submit(submit(&bob_fp, &i.to_string()), ALICE_IP, 1)
+                .unwrap();
+        }
+        relay.submit(submit("CAROL", "hi"), ALICE_IP, 1).unwrap();
+
+        let auth = signed_fetch(&relay, &bob, Vec::new(), 2);
+        let waiting = relay.fetch(&bob_fp, &auth, 2).unwrap();
+        assert_eq!(waiting.len(), MAX_MAILBOX_LEN);
+        assert_eq!(waiting[0].payload, "0");
+
+        // Nothing is deleted until it's acknowledged
+        let auth = signed_fetch(&relay, &bob, Vec::new(), 3);
+        assert_eq!(
+            relay.fetch(&bob_fp, &auth, 3).unwrap().len(),
+            MAX_MAILBOX_LEN
+        );
+        let ack = waiting[..10].iter().map(|m| m.id).collect();
+        let auth = signed_fetch(&relay, &bob, ack, 4);
+        let rest = relay.fetch(&bob_fp, &auth, 4).unwrap();
+        assert_eq!(rest.len(), MAX_MAILBOX_LEN - 10);
+        assert_eq!(rest[0].payload, "10");
+
+        // Bob's messages expire too
+        let auth = signed_fetch(&relay, &bob, Vec::new(), 1 + RELAY_TTL_MS);
+        assert!(relay
+            .fetch(&bob_fp, &auth, 1 + RELAY_TTL_MS)
+            .unwrap()
+            .is_empty());
+
+        assert!(matches!(
+            relay.submit(submit("", "x"), ALICE_IP, 0),
+            Err(RelayError::NoRecipient)
+        ));
+        let huge = "x".repeat(MAX_PAYLOAD_BYTES + 1);
+        assert!(matches!(
+            relay.submit(submit(&bob_fp, &huge), ALICE_IP, 0),
+            Err(RelayError::TooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn only_the_mailbox_key_can_fetch() {
+        let bob = PgpKeyPair::generate("bob").unwrap();
+        let mallory = PgpKeyPair::generate("mallory").unwrap();
+        let bob_fp = bob.fingerprint();
+        let relay = RelayMailbox::new();
+        relay
+            .submit(submit(&bob_fp, "secret"), ALICE_IP, 0)
+            .unwrap();
+
+        // No challenge issued
+        let mut auth = signed_fetch(&relay, &bob, Vec::new(), 0);
+        auth.challenge = "made-up".to_string();
+        assert!(matches!(
+            relay.fetch(&bob_fp, &auth, 0),
+            Err(RelayError::Unauthorized(_))
+        ));
+
+        // Mallory's own key, signed correctly, doesn't open Bob's mailbox
+        let challenge = relay.challenge(&bob_fp, BOB_IP, 0).unwrap();
+        let auth = RelayFetch {
+            public_key: mallory.export_public_key().unwrap(),
+            signature: mallory
+                .sign_detached(&fetch_statement(&bob_fp, &challenge))
+                .unwrap(),
+            challenge,
+            ack: Vec::new(),
+        };
+        assert!(matches!(
+            relay.fetch(&bob_fp, &auth, 0),
+            Err(RelayError::Unauthorized(_))
+        ));
+
+        // Bob's public key with Mallory's signature
+        let challenge = relay.challenge(&bob_fp, BOB_IP, 0).unwrap();
+        let auth = RelayFetch {
+            public_key: bob.export_public_key().unwrap(),
+            signature: mallory
+                .sign_detached(&fetch_statement(&bob_fp, &challenge))
+                .unwrap(),
+            challenge,
+            ack: Vec::new(),
+        };
+        assert!(matches!(
+            relay.fetch(&bob_fp, &auth, 0),
+            Err(RelayError::Unauthorized(_))
+        ));
+
+        // A challenge is good once, and not after it expires
+        let auth = signed_fetch(&relay, &bob, Vec::new(), 0);
+        assert_eq!(relay.fetch(&bob_fp, &auth, 0).unwrap().len(), 1);
+        assert!(matches!(
+            relay.fetch(&bob_fp, &auth, 0),
+            Err(RelayError::Unauthorized(_))
+        ));
+        let auth = signed_fetch(&relay, &bob, Vec::new(), 0);
+        assert!(matches!(
+            relay.fetch(&bob_fp, &auth, CHALLENGE_TTL_MS),
+            Err(RelayError::Unauthorized(_))
+        ));
+    }
+
+    #[test]
+    fn mailbox_count_is_bounded() {
+        let relay = RelayMailbox::new();
+        for i in 0..MAX_MAILBOXES {
+            relay
+                .submit(submit(&format!("FP{i}"), "x"), ALICE_IP, 0)
+                .unwrap();
+        }
+        assert!(matches!(
+            relay.submit(submit("ONE_TOO_MANY", "x"), ALICE_IP, 0),
+            Err(RelayError::Full)
+        ));
+        // Existing mailboxes still take messages
+        relay.submit(submit("FP0", "y"), ALICE_IP, 0).unwrap();
+        // Room again once the old ones expire
+        relay
+            .submit(submit("ONE_TOO_MANY", "x"), ALICE_IP, RELAY_TTL_MS)
+            .unwrap();
+
+        assert_eq!(
+            observed_sender_address("10.0.0.1:62780", "203.0.113.9".parse().unwrap()),
+            "203.0.113.9:62780"
+        );
+    }
+    #[test]
+    fn a_stranger_asking_for_challenges_cant_lock_the_owner_out() {
+        let bob = PgpKeyPair::generate("bob").unwrap();
+        let bob_fp = bob.fingerprint();
+        let relay = RelayMailbox::new();
+        relay.submit(submit(&bob_fp, "hi"), ALICE_IP, 0).unwrap();
+
+        // Bob's challenge outlives as many as Mallory may ask for
+        let auth = signed_fetch(&relay, &bob, Vec::new(), 0);
+        let mallory_ip: IpAddr = "192.0.2.66".parse().unwrap();
+        for _ in 0..MAX_CHALLENGES_PER_CALLER {
+            relay.challenge(&bob_fp, mallory_ip, 0).unwrap();
+        }
+        assert!(matches!(
+            relay.challenge(&bob_fp, mallory_ip, 0),
+            Err(RelayError::RateLimited)
+        ));
+        assert_eq!(relay.fetch(&bob_fp, &auth, 0).unwrap().len(), 1);
+
+        // Bob can still ask, and Mallory again once the window has passed
+        let auth = signed_fetch(&relay, &bob, Vec::new(), 1);
+        assert_eq!(relay.fetch(&bob_fp, &auth, 1).unwrap().len(), 1);
+        relay
+            .challenge(&bob_fp, mallory_ip, CHALLENGE_RATE_WINDOW_MS)
+            .unwrap();
+    }
+
+    #[test]
+    fn queued_bytes_are_capped_per_sender_and_in_total() {
+        let bob = PgpKeyPair::generate("bob").unwrap();
+        let bob_fp = bob.fingerprint();
+        let carol_ip: IpAddr = "192.0.2.10".parse().unwrap();
+        let relay = RelayMailbox::new().with_capacity(10, 6);
+
+        relay.submit(submit(&bob_fp, "1234"), ALICE_IP, 0).unwrap();
+        relay.submit(submit("CAROL", "56"), ALICE_IP, 0).unwrap();
+        assert!(matches!(
+            relay.submit(submit("CAROL", "7"), ALICE_IP, 0),
+            Err(RelayError::SenderQuota)
+        ));
+        relay.submit(submit("CAROL", "abcd"), BOB_IP, 0).unwrap();
+        assert!(matches!(
+            relay.submit(submit("CAROL", "x"), carol_ip, 0),
+            Err(RelayError::OutOfSpace(1))
+        ));
+
+        // Acknowledged messages free their bytes, and so does expiry
+        let auth = signed_fetch(&relay, &bob, Vec::new(), 1);
+        let ack = relay
+            .fetch(&bob_fp, &auth, 1)
+            .unwrap()
+            .iter()
+            .map(|m| m.id)
+            .collect();
+        let auth = signed_fetch(&relay, &bob, ack, 1);
+        assert!(relay.fetch(&bob_fp, &auth, 1).unwrap().is_empty());
+        relay.submit(submit("CAROL", "x"), carol_ip, 1).unwrap();
+        relay.submit(submit("CAROL", "7"), ALICE_IP, 1).unwrap();
+        relay
+            .submit(submit("CAROL", "123456"), ALICE_IP, RELAY_TTL_MS + 1)
+            .unwrap();
+    }
+}
@@ -1,3 +1,4 @@
+use crate::routes::error::ApiJson;
 use crate::state::AppState;
 use axum::{extract::State, response::IntoResponse, routing::post, Json, Router};
 use serde::{Deserialize, Serialize};
@@ -21,7 +22,7 @@ pub fn routes() -> Router<Arc<AppState>> {
 
 async fn echo(
     State(state): State<Arc<AppState>>,
-    Json(payload): Json<EchoPayload>,
+    ApiJson(payload): ApiJson<EchoPayload>,
 ) -> impl IntoResponse {
     debug!(message = %payload.message, "echo request");
     Json(EchoResponse {
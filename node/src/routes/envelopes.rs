@@ -0,0 +1,56 @@
+use crate::overlay::OverlayNotification;
+use crate::state::AppState;
+use crate::routes::error::{ApiError, ApiJson};
+use axum::{extract::State, http::StatusCode, routing::post, Router};
+use cryptochat_messaging::{Addressing, EncryptedEnvelope};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PostEnvelope {
+    pub envelope: EncryptedEnvelope,
+    /// Who the envelope is for, signed by its sender. The node takes no
+    /// one's word for the recipient otherwise.
+    pub addressing: Addressing,
+}
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().route("/envelopes", post(post_envelope))
+}
+
+async fn post_envelope(
+    State(state): State<Arc<AppState>>,
+    ApiJson(payload): ApiJson<PostEnvelope>,
+) -> Result<StatusCode, ApiError> {
+    if !payload.addressing.is_valid_for(&payload.envelope) {
+        return Err(ApiError::Unauthorized(
+            "addressing is not signed by the envelope's sender".to_string(),
+        ));
+    }
+    debug!(
+        message_id = %payload.envelope.message_id,
+        recipient = %payload.addressing.recipient_fingerprint,
+        "envelope posted"
+    );
+    // Replicate it too, so hosts of the recipient elsewhere on the overlay
+    // push it to their subscribers
+    if let Some(overlay) = state.overlay() {
+        let published = overlay
+            .replication()
+            .publish_to(payload.envelope.clone(), Some(payload.addressing.clone()))
+            .await;
+        if let Err(err) = published {
+            warn!(
+                %err,
+                message_id = %payload.envelope.message_id,
+                "failed to replicate posted envelope"
+            );
+        }
+    }
+    state.subscriptions().notify(OverlayNotification::EnvelopeReceived {
+        recipient_fingerprint: payload.addressing.recipient_fingerprint,
+        envelope: payload.envelope,
+    })?;
+    Ok(StatusCode::ACCEPTED)
+}
@@ -0,0 +1,184 @@
+//! JSON error responses shared by the node's routes.
+//!
+//! Every failure is returned as `{ "error": "...", "code": "..." }` so clients
+//! can tell a rejected request (4xx) apart from a node-side failure (5xx).
+
+use crate::overlay::OverlayError;
+use crate::relay::RelayError;
+use crate::transfers::TransferError;
+use axum::{
+    extract::{rejection::JsonRejection, rejection::QueryRejection, FromRequest, FromRequestParts},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+/// Error body returned by every route.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ErrorBody {
+    pub error: String,
+    pub code: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    /// The request body or query could not be parsed or failed validation.
+    #[error("{0}")]
+    BadRequest(String),
+    /// The caller couldn't prove it may do this.
+    #[error("{0}")]
+    Unauthorized(String),
+    /// The node is at a capacity limit; try again later.
+    #[error("{0}")]
+    Busy(String),
+    /// This node doesn't offer the route's feature.
+    #[error("{0}")]
+    Unavailable(String),
+    /// Something failed on the node's side.
+    #[error("{0}")]
+    Internal(String),
+    #[error(transparent)]
+    Overlay(#[from] OverlayError),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Busy(_) => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::Internal(_) | ApiError::Overlay(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Busy(_) => "busy",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Internal(_) | ApiError::Overlay(_) => "internal",
+        }
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+impl From<RelayError> for ApiError {
+    fn from(err: RelayError) -> Self {
+        match err {
+            RelayError::Unauthorized(_) => ApiError::Unauthorized(err.to_string()),
+            RelayError::Full
+            | RelayError::OutOfSpace(_)
+            | RelayError::SenderQuota
+            | RelayError::RateLimited => ApiError::Busy(err.to_string()),
+            RelayError::NoRecipient | RelayError::TooLarge(_) => {
+                ApiError::BadRequest(err.to_string())
+            }
+        }
+    }
+}
+
+impl From<TransferError> for ApiError {
+    fn from(err: TransferError) -> Self {
+        match err {
+            TransferError::Full => ApiError::Busy(err.to_string()),
+            TransferError::Storage(_) => ApiError::Internal(err.to_string()),
+            TransferError::NoTransferId
+            | TransferError::BadChunk { .. }
+            | TransferError::TooLarge(_)
+            | TransferError::TooManyChunks(_) => ApiError::BadRequest(err.to_string()),
+        }
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        // Internal details go to the log, not to the client
+        let error = if status.is_server_error() {
+            warn!(err = %self, "request failed");
+            "internal server error".to_string()
+        } else {
+            self.to_string()
+        };
+        let body = ErrorBody {
+            error,
+            code: self.code().to_string(),
+        };
+        (status, Json(body)).into_response()
+    }
+}
+
+/// `Json` extractor whose rejection is an [`ApiError`].
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct ApiJson<T>(pub T);
+
+/// `Query` extractor whose rejection is an [`ApiError`].
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct ApiQuery<T>(pub T);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::state::AppState;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn test_config() -> AppConfig {
+        AppConfig {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+            build_id: "test".to_string(),
+            storage_path: std::env::temp_dir().join("cryptochat-node-test"),
+            namespace: None,
+            overlay_enabled: false,
+        }
+    }
+
+    async fn error_body(response: Response) -> ErrorBody {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn malformed_envelope_post_returns_structured_400() {
+        let app = crate::router(AppState::new(test_config()));
+        let request = Request::post("/envelopes")
+            .header("content-type", "application/json")
+            .body(Body::from(r#"{"addressing":{"recipient_fingerprint":"bob"},"envelope":{"message_id":"nope"}}"#))
+            .unwrap();
+
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = error_body(response).await;
+        assert_eq!(body.code, "bad_request");
+        assert!(!body.error.is_empty());
+    }
+
+    #[tokio::test]
+    async fn internal_failure_returns_structured_500() {
+        let response = ApiError::from(OverlayError::Subscription("channel closed".to_string())).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = error_body(response).await;
+        assert_eq!(body.code, "internal");
+        assert_eq!(body.error, "internal server error");
+    }
+}
@@ -1,6 +1,6 @@
 use crate::state::AppState;
 use axum::{extract::State, routing::get, Json, Router};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
 #[derive(Debug, Serialize)]
@@ -9,8 +9,30 @@ pub struct HealthResponse {
     build_id: String,
 }
 
+/// Body of `GET /ready`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReadyResponse {
+    pub status: String,
+    /// `"full"` with the overlay running, `"http_only"` without it.
+    pub mode: String,
+    /// Connected overlay peers; absent in HTTP-only mode.
+    pub peers: Option<usize>,
+}
+
+/// Body of `GET /metrics`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MetricsResponse {
+    /// File transfers waiting on chunks; absent without node storage.
+    pub transfers_in_progress: Option<usize>,
+    /// Incomplete file transfers discarded since the node started.
+    pub transfers_dropped: Option<u64>,
+}
+
 pub fn routes() -> Router<Arc<AppState>> {
-    Router::new().route("/health", get(health_check))
+    Router::new()
+        .route("/health", get(health_check))
+        .route("/ready", get(ready_check))
+        .route("/metrics", get(metrics))
 }
 
 async fn health_check(State(state): State<Arc<AppState>>) -> Json<HealthResponse> {
@@ -19,3 +41,64 @@ async fn health_check(State(state): State<Arc<AppState>>) -> Json<HealthResponse
         build_id: state.build_id().to_string(),
     })
 }
+
+async fn ready_check(State(state): State<Arc<AppState>>) -> Json<ReadyResponse> {
+    let (mode, peers) = match state.overlay() {
+        Some(overlay) => ("full", Some(overlay.discovery().peers().await.len())),
+        None => ("http_only", None),
+    };
+    Json(ReadyResponse {
+        status: "ready".to_string(),
+        mode: mode.to_string(),
+        peers,
+    })
+}
+
+async fn metrics(State(state): State<Arc<AppState>>) -> Json<MetricsResponse> {
+    let transfers = state.transfers();
+    Json(MetricsResponse {
+        transfers_in_progress: transfers.map(|t| t.in_progress()),
+        transfers_dropped: transfers.map(|t| t.dropped_transfers()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn serves_routes_with_the_overlay_disabled() {
+        let config = AppConfig {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+            build_id: "test".to_string(),
+            storage_path: std::env::temp_dir().join("cryptochat-node-test"),
+            namespace: None,
+            overlay_enabled: false,
+        };
+        let state = AppState::start(config).await.unwrap();
+        assert!(state.overlay().is_none());
+        let app = crate::router(state);
+
+        let response = app
+            .clone()
+            .oneshot(Request::get("/ready").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let ready: ReadyResponse = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!((ready.status.as_str(), ready.mode.as_str()), ("ready", "http_only"));
+        assert_eq!(ready.peers, None);
+
+        let response = app
+            .oneshot(Request::get("/health").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
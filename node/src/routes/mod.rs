@@ -1,5 +1,10 @@
 pub mod echo;
+pub mod envelopes;
+pub mod error;
 pub mod health;
+pub mod relay;
+pub mod subscribe;
+pub mod transfers;
 
 use crate::state::AppState;
 use axum::Router;
@@ -9,5 +14,9 @@ pub fn router(state: Arc<AppState>) -> Router {
     Router::new()
         .merge(health::routes())
         .merge(echo::routes())
+        .merge(envelopes::routes())
+        .merge(subscribe::routes())
+        .merge(relay::routes())
+        .merge(transfers::routes())
         .with_state(state)
 }
@@ -0,0 +1,179 @@
+use crate::relay::{
+    observed_sender_address, RelayChallenge, RelayFetch, RelaySubmit, RelayedMessage,
+};
+use crate::routes::error::{ApiError, ApiJson};
+use crate::state::AppState;
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::StatusCode,
+    routing::{get, post},
+    Json, Router,
+};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::Arc;
+use tracing::debug;
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/relay", post(submit))
+        .route("/relay/:fingerprint/challenge", get(challenge))
+        .route("/relay/:fingerprint/fetch", post(fetch))
+}
+
+async fn submit(
+    State(state): State<Arc<AppState>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    ApiJson(mut payload): ApiJson<RelaySubmit>,
+) -> Result<StatusCode, ApiError> {
+    let from = caller(peer);
+    if let Some(ConnectInfo(peer)) = peer {
+        payload.sender_address = observed_sender_address(&payload.sender_address, peer.ip());
+    }
+    debug!(recipient = %payload.recipient_fingerprint, bytes = payload.payload.len(), "relay submit");
+    state.relay().submit(payload, from, now_ms())?;
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn challenge(
+    State(state): State<Arc<AppState>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    Path(fingerprint): Path<String>,
+) -> Result<Json<RelayChallenge>, ApiError> {
+    let challenge = state
+        .relay()
+        .challenge(&fingerprint, caller(peer), now_ms())?;
+    Ok(Json(RelayChallenge { challenge }))
+}
+
+async fn fetch(
+    State(state): State<Arc<AppState>>,
+    Path(fingerprint): Path<String>,
+    ApiJson(auth): ApiJson<RelayFetch>,
+) -> Result<Json<Vec<RelayedMessage>>, ApiError> {
+    Ok(Json(state.relay().fetch(&fingerprint, &auth, now_ms())?))
+}
+
+/// The address quotas and rate limits are counted against. Requests served
+/// without connection info (in-process, as in tests) share one.
+pub(crate) fn caller(peer: Option<ConnectInfo<SocketAddr>>) -> IpAddr {
+    peer.map_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED), |ConnectInfo(peer)| {
+        peer.ip()
+    })
+}
+
+fn now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::relay::fetch_statement;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use cryptochat_crypto_core::pgp::PgpKeyPair;
+    use tower::ServiceExt;
+
+    fn test_config() -> AppConfig {
+        AppConfig {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+            build_id: "test".to_string(),
+            storage_path: std::env::temp_dir().join("cryptochat-node-test"),
+            namespace: None,
+            overlay_enabled: false,
+        }
+    }
+
+    fn post_json<T: serde::Serialize>(uri: &str, body: &T) -> Request<Body> {
+        Request::post(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(body).unwrap()))
+            .unwrap()
+    }
+
+    async fn signed_fetch(
+        app: &Router,
+        key: &PgpKeyPair,
+        ack: Vec<u64>,
+    ) -> axum::response::Response {
+        let fingerprint = key.fingerprint();
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get(format!("/relay/{fingerprint}/challenge"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let RelayChallenge { challenge } = serde_json::from_slice(&bytes).unwrap();
+        let auth = RelayFetch {
+            public_key: key.export_public_key().unwrap(),
+            signature: key
+                .sign_detached(&fetch_statement(&fingerprint, &challenge))
+                .unwrap(),
+            challenge,
+            ack,
+        };
+        app.clone()
+            .oneshot(post_json(&format!("/relay/{fingerprint}/fetch"), &auth))
+            .await
+            .unwrap()
+    }
+
+    async fn messages(response: axum::response::Response) -> Vec<RelayedMessage> {
+        assert_eq!(response.status(), StatusCode::OK);
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    #[tokio::test]
+    async fn submitted_messages_are_kept_until_acknowledged() {
+        let app = crate::router(AppState::new(test_config()));
+        let bob = PgpKeyPair::generate("bob").unwrap();
+        let submit = RelaySubmit {
+            recipient_fingerprint: bob.fingerprint(),
+            sender_fingerprint: "ALICE".to_string(),
+            sender_address: "203.0.113.5:62780".to_string(),
+            payload: "{\"opaque\":true}".to_string(),
+        };
+        let response = app
+            .clone()
+            .oneshot(post_json("/relay", &submit))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let fetched = messages(signed_fetch(&app, &bob, Vec::new()).await).await;
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].payload, submit.payload);
+        assert_eq!(fetched[0].sender_address, submit.sender_address);
+
+        // Still there until acknowledged
+        let again = messages(signed_fetch(&app, &bob, Vec::new()).await).await;
+        assert_eq!(again, fetched);
+        let acked = messages(signed_fetch(&app, &bob, vec![fetched[0].id]).await).await;
+        assert!(acked.is_empty());
+    }
+
+    #[tokio::test]
+    async fn fetching_without_the_key_is_refused() {
+        let app = crate::router(AppState::new(test_config()));
+        let bob = PgpKeyPair::generate("bob").unwrap();
+        let auth = RelayFetch {
+            public_key: bob.export_public_key().unwrap(),
+            challenge: "never-issued".to_string(),
+            signature: String::new(),
+            ack: Vec::new(),
+        };
+        let uri = format!("/relay/{}/fetch", bob.fingerprint());
+        let response = app.oneshot(post_json(&uri, &auth)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,414 @@
+use crate::overlay::{OverlayNotification, Subscription};
+use crate::relay::{subscribe_statement, RelayChallenge};
+use crate::routes::error::{ApiError, ApiQuery};
+use crate::state::AppState;
+use axum::{
+    extract::{
+        ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        ConnectInfo, Path, State,
+    },
+    response::Response,
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+/// How long a new socket has to send its [`SubscribeAuth`].
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Close code for a subscriber that failed to prove its fingerprint
+/// (policy violation).
+const CLOSE_UNAUTHORIZED: u16 = 1008;
+
+#[derive(Debug, Deserialize)]
+pub struct SubscribeParams {
+    pub fingerprint: String,
+}
+
+/// First frame a subscriber sends: proof that it holds the key behind the
+/// fingerprint it subscribes to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubscribeAuth {
+    /// Armored public key; its fingerprint must be the subscribed one.
+    pub public_key: String,
+    /// From `GET /subscribe/{fingerprint}/challenge`.
+    pub challenge: String,
+    /// Armored detached signature over [`subscribe_statement`].
+    pub signature: String,
+}
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new()
+        .route("/subscribe", get(subscribe))
+        .route("/subscribe/:fingerprint/challenge", get(challenge))
+}
+
+/// One-time challenge for subscribing as `fingerprint`.
+async fn challenge(
+    State(state): State<Arc<AppState>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    Path(fingerprint): Path<String>,
+) -> Result<Json<RelayChallenge>, ApiError> {
+    let caller = super::relay::caller(peer);
+    let challenge = state
+        .subscribe_challenges()
+        .issue(&fingerprint, caller, now_ms())?;
+    Ok(Json(RelayChallenge { challenge }))
+}
+
+/// Upgrade to a WebSocket that pushes every notification addressed to
+/// `fingerprint` as a JSON text frame. The first frame from the client must
+/// be a [`SubscribeAuth`]; the node answers with
+/// [`OverlayNotification::Subscribed`] once the subscription is in place, or
+/// closes the socket.
+async fn subscribe(
+    ws: WebSocketUpgrade,
+    ApiQuery(params): ApiQuery<SubscribeParams>,
+    State(state): State<Arc<AppState>>,
+) -> Response {
+    ws.on_upgrade(move |socket| async move {
+        let Some((socket, subscription)) = authenticate(socket, &params.fingerprint, &state).await
+        else {
+            return;
+        };
+        stream_notifications(socket, subscription).await;
+    })
+}
+
+/// Wait for the client's [`SubscribeAuth`] and register it if it checks
+/// out. Sockets that fail are closed.
+async fn authenticate(
+    mut socket: WebSocket,
+    fingerprint: &str,
+    state: &AppState,
+) -> Option<(WebSocket, Subscription)> {
+    let frame = tokio::time::timeout(AUTH_TIMEOUT, socket.recv()).await;
+    let auth = match frame {
+        Ok(Some(Ok(Message::Text(json)))) => serde_json::from_str::<SubscribeAuth>(&json).ok(),
+        _ => None,
+    };
+    let verified = auth
+        .ok_or("expected a subscribe auth frame")
+        .and_then(|auth| {
+            state
+                .subscribe_challenges()
+                .authenticate(
+                    fingerprint,
+                    &auth.public_key,
+                    &auth.challenge,
+                    &auth.signature,
+                    &subscribe_statement(fingerprint, &auth.challenge),
+                    now_ms(),
+                )
+                .map_err(|_| "fingerprint not proven")
+        });
+    if let Err(reason) = verified {
+        debug!(%fingerprint, reason, "subscriber refused");
+        let _ = socket
+            .send(Message::Close(Some(CloseFrame {
+                code: CLOSE_UNAUTHORIZED,
+                reason: reason.into(),
+            })))
+            .await;
+        return None;
+    }
+
+    let subscription = match state.subscriptions().register(fingerprint) {
+        Ok(subscription) => subscription,
+        Err(err) => {
+            warn!(%err, "failed to register subscriber");
+            return None;
+        }
+    };
+    let subscribed = OverlayNotification::Subscribed {
+        fingerprint: fingerprint.to_string(),
+    };
+    let json = serde_json::to_string(&subscribed).ok()?;
+    socket.send(Message::Text(json)).await.ok()?;
+    Some((socket, subscription))
+}
+
+async fn stream_notifications(mut socket: WebSocket, mut subscription: Subscription) {
+    loop {
+        tokio::select! {
+            notification = subscription.next() => {
+                let Some(notification) = notification else { break };
+                let json = match serde_json::to_string(&notification) {
+                    Ok(json) => json,
+                    Err(err) => {
+                        warn!(%err, "failed to serialize notification");
+                        continue;
+                    }
+                };
+                if socket.send(Message::Text(json)).await.is_err() {
+                    break;
+                }
+            }
+            incoming = socket.recv() => match incoming {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // Clients don't send anything meaningful; pings are answered by axum
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+    // Dropping the subscription unregisters it
+    debug!("subscriber disconnected");
+}
+
+fn now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscribeAuth;
+    use crate::config::AppConfig;
+    use crate::relay::{fetch_statement, subscribe_statement, RelayChallenge, RelayFetch};
+    use crate::routes::envelopes::PostEnvelope;
+    use crate::state::AppState;
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use cryptochat_crypto_core::pgp::PgpKeyPair;
+    use cryptochat_crypto_core::KeyPair;
+    use cryptochat_messaging::{
+        Addressing, ConversationId, DeviceId, EncryptedEnvelope, PlaintextMessage,
+    };
+    use futures::{SinkExt, StreamExt};
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+    use tower::ServiceExt;
+
+    type Socket = tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >;
+
+    fn test_config() -> AppConfig {
+        AppConfig {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+            build_id: "test".to_string(),
+            storage_path: std::env::temp_dir().join("cryptochat-node-test"),
+            namespace: None,
+            overlay_enabled: false,
+        }
+    }
+
+    fn envelope_from(sender: &PgpKeyPair) -> EncryptedEnvelope {
+        let keypair = KeyPair::from_seed(b"sender").unwrap();
+        let message = PlaintextMessage::new(ConversationId::new(), DeviceId::new(), b"hi".to_vec());
+        let mut envelope = EncryptedEnvelope::from_plaintext(message, &keypair).unwrap();
+        envelope.sender_fingerprint = sender.fingerprint();
+        envelope
+    }
+
+    async fn post(
+        app: axum::Router,
+        addressing: Addressing,
+        envelope: &EncryptedEnvelope,
+    ) -> StatusCode {
+        let body = serde_json::to_vec(&PostEnvelope {
+            envelope: envelope.clone(),
+            addressing,
+        })
+        .unwrap();
+        let request = Request::post("/envelopes")
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    async fn serve(app: axum::Router) -> SocketAddr {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        addr
+    }
+
+    async fn challenge(app: &axum::Router, uri: String) -> String {
+        let response = app
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let RelayChallenge { challenge } = serde_json::from_slice(&bytes).unwrap();
+        challenge
+    }
+
+    async fn connect(addr: SocketAddr, fingerprint: &str) -> Socket {
+        let (socket, _) = tokio_tungstenite::connect_async(format!(
+            "ws://{addr}/subscribe?fingerprint={fingerprint}"
+        ))
+        .await
+        .unwrap();
+        socket
+    }
+
+    /// Send an auth frame for `fingerprint` over `challenge`, signed by `key`.
+    async fn send_auth(
+        socket: &mut Socket,
+        fingerprint: &str,
+        key: &PgpKeyPair,
+        challenge: String,
+    ) {
+        let auth = SubscribeAuth {
+            public_key: key.export_public_key().unwrap(),
+            signature: key
+                .sign_detached(&subscribe_statement(fingerprint, &challenge))
+                .unwrap(),
+            challenge,
+        };
+        socket
+            .send(WsMessage::Text(serde_json::to_string(&auth).unwrap()))
+            .await
+            .unwrap();
+    }
+
+    /// Open `/subscribe` for `fingerprint` and send an auth frame signed by `key`.
+    async fn subscribe_as(
+        app: &axum::Router,
+        addr: SocketAddr,
+        fingerprint: &str,
+        key: &PgpKeyPair,
+    ) -> Socket {
+        let mut socket = connect(addr, fingerprint).await;
+        let challenge = challenge(app, format!("/subscribe/{fingerprint}/challenge")).await;
+        send_auth(&mut socket, fingerprint, key, challenge).await;
+        socket
+    }
+
+    async fn next_frame(socket: &mut Socket) -> WsMessage {
+        tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap()
+    }
+
+    fn json(frame: WsMessage) -> serde_json::Value {
+        let WsMessage::Text(json) = frame else {
+            panic!("expected a text frame, got {frame:?}");
+        };
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[tokio::test]
+    async fn posted_envelope_is_pushed_to_subscriber() {
+        let state = AppState::new(test_config());
+        let app = crate::router(state.clone());
+        let addr = serve(app.clone()).await;
+        let alice = PgpKeyPair::generate("alice").unwrap();
+        let bob = PgpKeyPair::generate("bob").unwrap();
+        let bob_fp = bob.fingerprint();
+
+        let mut socket = subscribe_as(&app, addr, &bob_fp, &bob).await;
+        let subscribed = json(next_frame(&mut socket).await);
+        assert_eq!(subscribed["subscribed"]["fingerprint"], bob_fp.as_str());
+        assert_eq!(state.subscriptions().active_count(), 1);
+
+        // Someone else's envelope is filtered out, and an addressing the
+        // sender didn't sign is refused; ours is pushed
+        let envelope = envelope_from(&alice);
+        let for_carol = Addressing::sign(&envelope, "CAROL", &alice).unwrap();
+        assert_eq!(
+            post(app.clone(), for_carol, &envelope).await,
+            StatusCode::ACCEPTED
+        );
+        let mallory = PgpKeyPair::generate("mallory").unwrap();
+        let forged = Addressing::sign(&envelope, &bob_fp, &mallory).unwrap();
+        assert_eq!(
+            post(app.clone(), forged, &envelope).await,
+            StatusCode::UNAUTHORIZED
+        );
+        let for_bob = Addressing::sign(&envelope, &bob_fp, &alice).unwrap();
+        assert_eq!(
+            post(app.clone(), for_bob, &envelope).await,
+            StatusCode::ACCEPTED
+        );
+
+        let pushed = json(next_frame(&mut socket).await);
+        let received = &pushed["envelope_received"];
+        assert_eq!(received["recipient_fingerprint"], bob_fp.as_str());
+        assert_eq!(
+            received["envelope"]["message_id"],
+            envelope.message_id.to_string()
+        );
+
+        // Disconnecting cleans up the subscription
+        socket.send(WsMessage::Close(None)).await.unwrap();
+        for _ in 0..50 {
+            if state.subscriptions().active_count() == 0 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(state.subscriptions().active_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn subscribing_to_someone_elses_fingerprint_is_refused() {
+        let state = AppState::new(test_config());
+        let app = crate::router(state.clone());
+        let addr = serve(app.clone()).await;
+        let bob = PgpKeyPair::generate("bob").unwrap();
+        let mallory = PgpKeyPair::generate("mallory").unwrap();
+
+        let mut socket = subscribe_as(&app, addr, &bob.fingerprint(), &mallory).await;
+        let WsMessage::Close(Some(frame)) = next_frame(&mut socket).await else {
+            panic!("expected the node to close the socket");
+        };
+        assert_eq!(u16::from(frame.code), super::CLOSE_UNAUTHORIZED);
+        assert_eq!(state.subscriptions().active_count(), 0);
+    }
+    #[tokio::test]
+    async fn fetching_the_relay_mailbox_leaves_a_subscribe_challenge_alone() {
+        let app = crate::router(AppState::new(test_config()));
+        let addr = serve(app.clone()).await;
+        let bob = PgpKeyPair::generate("bob").unwrap();
+        let bob_fp = bob.fingerprint();
+        let subscribe_challenge = challenge(&app, format!("/subscribe/{bob_fp}/challenge")).await;
+
+        // Bob fetches his relay mailbox in the meantime
+        let fetch_challenge = challenge(&app, format!("/relay/{bob_fp}/challenge")).await;
+        let fetch = RelayFetch {
+            public_key: bob.export_public_key().unwrap(),
+            signature: bob
+                .sign_detached(&fetch_statement(&bob_fp, &fetch_challenge))
+                .unwrap(),
+            challenge: fetch_challenge,
+            ack: Vec::new(),
+        };
+        let request = Request::post(format!("/relay/{bob_fp}/fetch"))
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(&fetch).unwrap()))
+            .unwrap();
+        assert_eq!(
+            app.clone().oneshot(request).await.unwrap().status(),
+            StatusCode::OK
+        );
+
+        // A relay challenge doesn't open a subscription...
+        let relay_only = challenge(&app, format!("/relay/{bob_fp}/challenge")).await;
+        let mut socket = connect(addr, &bob_fp).await;
+        send_auth(&mut socket, &bob_fp, &bob, relay_only).await;
+        assert!(matches!(
+            next_frame(&mut socket).await,
+            WsMessage::Close(Some(_))
+        ));
+
+        // ...and the subscribe challenge from before the fetch still does
+        let mut socket = connect(addr, &bob_fp).await;
+        send_auth(&mut socket, &bob_fp, &bob, subscribe_challenge).await;
+        let subscribed = json(next_frame(&mut socket).await);
+        assert_eq!(subscribed["subscribed"]["fingerprint"], bob_fp.as_str());
+    }
+}
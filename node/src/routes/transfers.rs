@@ -0,0 +1,120 @@
+use crate::routes::error::{ApiError, ApiJson};
+use crate::state::AppState;
+use crate::transfers::FileChunk;
+use axum::{extract::State, http::StatusCode, routing::post, Router};
+use std::sync::Arc;
+use tracing::debug;
+
+pub fn routes() -> Router<Arc<AppState>> {
+    Router::new().route("/transfers/chunks", post(post_chunk))
+}
+
+/// Buffer one chunk of a relayed file: `202 Accepted` while chunks are
+/// missing, `201 Created` once the whole file is stored.
+async fn post_chunk(
+    State(state): State<Arc<AppState>>,
+    ApiJson(chunk): ApiJson<FileChunk>,
+) -> Result<StatusCode, ApiError> {
+    let Some(transfers) = state.transfers() else {
+        return Err(ApiError::Unavailable(
+            "this node does not relay files".to_string(),
+        ));
+    };
+    debug!(
+        transfer_id = %chunk.transfer_id,
+        index = chunk.index,
+        total = chunk.total_chunks,
+        "file chunk posted"
+    );
+    match transfers.accept(chunk, now_ms())? {
+        Some(_) => Ok(StatusCode::CREATED),
+        None => Ok(StatusCode::ACCEPTED),
+    }
+}
+
+fn now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::routes::health::MetricsResponse;
+    use axum::body::{to_bytes, Body};
+    use axum::http::Request;
+    use tower::ServiceExt;
+
+    fn test_config(storage_path: std::path::PathBuf) -> AppConfig {
+        AppConfig {
+            host: "127.0.0.1".to_string(),
+            port: 0,
+            build_id: "test".to_string(),
+            storage_path,
+            namespace: None,
+            overlay_enabled: false,
+        }
+    }
+
+    fn post_chunk(chunk: &FileChunk) -> Request<Body> {
+        Request::post("/transfers/chunks")
+            .header("content-type", "application/json")
+            .body(Body::from(serde_json::to_vec(chunk).unwrap()))
+            .unwrap()
+    }
+
+    fn chunk(index: u32, total_chunks: u32, data: &[u8]) -> FileChunk {
+        FileChunk {
+            transfer_id: "photo".to_string(),
+            index,
+            total_chunks,
+            data: data.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn chunks_are_reassembled_and_bad_ones_rejected() {
+        let dir = std::env::temp_dir().join(format!("cryptochat-node-{}", uuid::Uuid::new_v4()));
+        let app = crate::router(AppState::start(test_config(dir.clone())).await.unwrap());
+
+        let response = app
+            .clone()
+            .oneshot(post_chunk(&chunk(0, 2, b"ab")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let response = app
+            .clone()
+            .oneshot(post_chunk(&chunk(1, 2, b"cd")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let response = app
+            .clone()
+            .oneshot(post_chunk(&chunk(2, 2, b"ef")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = app
+            .oneshot(Request::get("/metrics").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let metrics: MetricsResponse = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(metrics.transfers_in_progress, Some(0));
+        assert_eq!(metrics.transfers_dropped, Some(0));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn nodes_without_storage_refuse_chunks() {
+        let app = crate::router(AppState::new(test_config(std::env::temp_dir())));
+        let response = app.oneshot(post_chunk(&chunk(0, 1, b"x"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
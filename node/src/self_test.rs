@@ -0,0 +1,131 @@
+//! `--self-test`: quick health check of storage and crypto for operators.
+
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+use cryptochat_crypto_core::{sign_message, verify_signature, KeyPair};
+use cryptochat_messaging::{ConversationId, DeviceId, EncryptedEnvelope, PlaintextMessage};
+use libp2p::PeerId;
+use uuid::Uuid;
+
+use crate::storage::NodeStorage;
+
+/// Outcome of a single self-test check.
+#[derive(Debug)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub outcome: Result<(), String>,
+}
+
+/// Pass/fail report for every check that ran.
+#[derive(Debug, Default)]
+pub struct SelfTestReport {
+    pub checks: Vec<CheckResult>,
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.outcome.is_ok())
+    }
+
+    fn record<T>(&mut self, name: &'static str, outcome: &Result<T>) {
+        self.checks.push(CheckResult {
+            name,
+            outcome: outcome.as_ref().map(|_| ()).map_err(|e| format!("{e:#}")),
+        });
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            match &check.outcome {
+                Ok(()) => writeln!(f, "[PASS] {}", check.name)?,
+                Err(e) => writeln!(f, "[FAIL] {}: {e}", check.name)?,
+            }
+        }
+        let failed = self.checks.iter().filter(|c| c.outcome.is_err()).count();
+        if failed == 0 {
+            write!(f, "self-test passed ({} checks)", self.checks.len())
+        } else {
+            write!(f, "self-test FAILED ({failed} of {} checks)", self.checks.len())
+        }
+    }
+}
+
+/// Run every check against a throwaway storage directory under the system
+/// temp dir, removing it afterwards.
+pub fn run_self_test() -> SelfTestReport {
+    let dir = std::env::temp_dir().join(format!("cryptochat-self-test-{}", Uuid::new_v4()));
+    let report = run_self_test_in(&dir);
+    let _ = std::fs::remove_dir_all(&dir);
+    report
+}
+
+fn run_self_test_in(dir: &Path) -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+
+    report.record("sign/verify", &check_signatures());
+
+    let envelope = envelope_round_trip();
+    report.record("envelope encrypt/decrypt", &envelope);
+
+    let storage = NodeStorage::open(dir).with_context(|| format!("opening {}", dir.display()));
+    report.record("storage open", &storage);
+
+    let round_trip = match (&storage, &envelope) {
+        (Ok(storage), Ok(envelope)) => storage_round_trip(storage, envelope),
+        _ => Err(anyhow::anyhow!("skipped: an earlier check failed")),
+    };
+    report.record("storage round-trip", &round_trip);
+
+    report
+}
+
+fn check_signatures() -> Result<()> {
+    let key_pair = KeyPair::generate().context("generating key pair")?;
+    let signature = sign_message(&key_pair, b"self-test").context("signing")?;
+    verify_signature(&key_pair, b"self-test", &signature).context("verifying")?;
+    if verify_signature(&key_pair, b"tampered", &signature).is_ok() {
+        bail!("signature verified over the wrong message");
+    }
+    Ok(())
+}
+
+fn envelope_round_trip() -> Result<EncryptedEnvelope> {
+    let key_pair = KeyPair::generate().context("generating key pair")?;
+    let body = b"cryptochat self-test".to_vec();
+    let message = PlaintextMessage::new(ConversationId::new(), DeviceId::new(), body.clone());
+    let envelope = EncryptedEnvelope::from_plaintext(message, &key_pair).context("encrypting")?;
+    let decrypted = envelope.clone().into_plaintext(&key_pair).context("decrypting")?;
+    ensure!(decrypted.body == body, "decrypted body does not match");
+    Ok(envelope)
+}
+
+fn storage_round_trip(storage: &NodeStorage, envelope: &EncryptedEnvelope) -> Result<()> {
+    storage.store_inbound(envelope).context("store_inbound")?;
+
+    let message_id = envelope.message_id.to_string();
+    storage
+        .insert_outbound(&message_id, envelope, &[PeerId::random()])
+        .context("insert_outbound")?;
+    let pending = storage.load_pending().context("load_pending")?;
+    ensure!(
+        pending.iter().any(|p| p.message_id == message_id),
+        "stored envelope missing from load_pending"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn self_test_passes_on_healthy_node() {
+        let report = run_self_test();
+        assert!(report.passed(), "{report}");
+        assert_eq!(report.checks.len(), 4);
+    }
+}
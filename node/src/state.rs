@@ -1,14 +1,87 @@
 use crate::config::AppConfig;
+use crate::overlay::{OverlayHandle, SubscriptionManager};
+use crate::relay::{Challenges, RelayMailbox};
+use crate::storage::NodeStorage;
+use crate::transfers::{ReassemblyBuffer, TRANSFER_EXPIRY_INTERVAL};
+use std::fmt;
 use std::sync::Arc;
+use tracing::info;
 
-#[derive(Debug)]
 pub struct AppState {
     config: AppConfig,
+    subscriptions: SubscriptionManager,
+    relay: RelayMailbox,
+    /// Challenges for `/subscribe`, kept apart from the relay's so
+    /// subscribing and fetching don't compete for them.
+    subscribe_challenges: Challenges,
+    /// The running overlay, unless the node was started HTTP-only.
+    overlay: Option<OverlayHandle>,
+    /// Chunked file transfers; needs the node's storage, which only
+    /// [`start`](Self::start) opens.
+    transfers: Option<ReassemblyBuffer>,
 }
 
 impl AppState {
     pub fn new(config: AppConfig) -> Arc<Self> {
-        Arc::new(Self { config })
+        Self::with_subscriptions(config, SubscriptionManager::new())
+    }
+
+    /// Share an existing subscription manager (e.g. the overlay's) with the HTTP routes.
+    pub fn with_subscriptions(config: AppConfig, subscriptions: SubscriptionManager) -> Arc<Self> {
+        Arc::new(Self {
+            config,
+            subscriptions,
+            relay: RelayMailbox::new(),
+            subscribe_challenges: Challenges::new(),
+            overlay: None,
+            transfers: None,
+        })
+    }
+
+    /// Start the overlay when `config.overlay_enabled` is set and share its
+    /// subscriptions with the HTTP routes; otherwise serve HTTP only.
+    pub async fn start(config: AppConfig) -> anyhow::Result<Arc<Self>> {
+        let (subscriptions, storage, overlay) = if config.overlay_enabled {
+            let overlay = OverlayHandle::start(config.overlay_config()).await?;
+            let storage = overlay.storage().clone();
+            (overlay.subscriptions().clone(), storage, Some(overlay))
+        } else {
+            info!("overlay disabled; serving HTTP routes only");
+            let storage = NodeStorage::open(config.data_dir())?;
+            (SubscriptionManager::new(), storage, None)
+        };
+        let transfers = ReassemblyBuffer::new(storage);
+        transfers.spawn_periodic_expiry(TRANSFER_EXPIRY_INTERVAL);
+        Ok(Arc::new(Self {
+            subscriptions,
+            relay: RelayMailbox::new(),
+            subscribe_challenges: Challenges::new(),
+            overlay,
+            transfers: Some(transfers),
+            config,
+        }))
+    }
+
+    pub fn subscriptions(&self) -> &SubscriptionManager {
+        &self.subscriptions
+    }
+
+    /// Mailboxes for clients that can't reach their peers directly.
+    pub fn relay(&self) -> &RelayMailbox {
+        &self.relay
+    }
+
+    /// Challenges subscribers sign to prove their fingerprint.
+    pub fn subscribe_challenges(&self) -> &Challenges {
+        &self.subscribe_challenges
+    }
+
+    pub fn overlay(&self) -> Option<&OverlayHandle> {
+        self.overlay.as_ref()
+    }
+
+    pub fn transfers(&self) -> Option<&ReassemblyBuffer> {
+        self.transfers.as_ref()
     }
 
     pub fn config(&self) -> &AppConfig {
@@ -18,4 +91,34 @@ impl AppState {
     pub fn build_id(&self) -> &str {
         &self.config.build_id
     }
+
+    /// Stop the overlay and flush its storage. Call once the HTTP server has
+    /// stopped; if a route still holds the state, only the flush happens.
+    pub async fn shutdown(self: Arc<Self>) -> anyhow::Result<()> {
+        match Arc::try_unwrap(self) {
+            Ok(state) => {
+                if let Some(overlay) = state.overlay {
+                    overlay.shutdown().await?;
+                }
+            }
+            Err(state) => {
+                if let Some(overlay) = state.overlay() {
+                    overlay.storage().flush()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for AppState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AppState")
+            .field("config", &self.config)
+            .field("subscriptions", &self.subscriptions)
+            .field("relay", &self.relay)
+            .field("overlay", &self.overlay.is_some())
+            .field("transfers", &self.transfers.is_some())
+            .finish()
+    }
 }
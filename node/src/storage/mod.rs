@@ -1,14 +1,23 @@
 use std::path::Path;
 use std::str::FromStr;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
-use cryptochat_messaging::EncryptedEnvelope;
+use anyhow::{bail, Context, Result};
+use cryptochat_crypto_core::pgp::PgpKeyPair;
+use cryptochat_messaging::{Addressing, DeliveryReceipt, EncryptedEnvelope};
 use libp2p::PeerId;
 use serde::{Deserialize, Serialize};
+use tokio::time::MissedTickBehavior;
+use tracing::warn;
+use uuid::Uuid;
 
 #[derive(Clone)]
 pub struct NodeStorage {
     db: sled::Db,
+    /// Whether writes return before reaching disk. The caller then owns
+    /// durability: [`NodeStorage::flush_async`] after writes that must
+    /// survive a crash, plus [`NodeStorage::spawn_periodic_flush`].
+    flush_async: bool,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
@@ -24,16 +33,62 @@ struct StoredInbound {
     stored_ms: i64,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredReceipt {
+    receipt: DeliveryReceipt,
+    peer: String,
+}
+
+/// Outcome of [`NodeStorage::mark_peer_success`].
+#[derive(Debug, Clone)]
+pub struct PeerAck {
+    /// Receipt recorded for the acknowledging peer.
+    pub receipt: DeliveryReceipt,
+    /// Original sender of the envelope, if we still held the record.
+    pub sender_fingerprint: Option<String>,
+    /// Whether every target peer has now acknowledged.
+    pub complete: bool,
+    /// Number of peers that have acknowledged so far, this one included.
+    pub acked: usize,
+}
+
+/// A device registered under an identity fingerprint.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeviceRecord {
+    pub device_id: String,
+    pub fingerprint: String,
+    /// Armored public key of the device itself; its envelopes are signed
+    /// with the matching secret key.
+    pub public_key: String,
+    pub first_seen_ms: i64,
+    pub last_seen_ms: i64,
+    /// When the device was revoked; envelopes it sends are rejected from then on.
+    pub revoked_ms: Option<i64>,
+}
+
+impl DeviceRecord {
+    pub fn is_revoked(&self) -> bool {
+        self.revoked_ms.is_some()
+    }
+}
+
 #[derive(Clone)]
 pub struct PendingEnvelope {
     pub message_id: String,
     pub envelope: EncryptedEnvelope,
+    /// Sent along with every retry; see [`NodeStorage::store_addressing`].
+    pub addressing: Option<Addressing>,
     pub pending_peers: Vec<PeerId>,
+    pub acked_peers: Vec<PeerId>,
 }
 
 impl NodeStorage {
     const TREE: &'static str = "replication";
     const INBOUND_TREE: &'static str = "inbound";
+    const RECEIPT_TREE: &'static str = "receipts";
+    const DEVICE_TREE: &'static str = "devices";
+    const BLOB_TREE: &'static str = "blobs";
+    const ADDRESSING_TREE: &'static str = "addressing";
 
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
         let path = path.as_ref();
@@ -41,17 +96,83 @@ impl NodeStorage {
             .with_context(|| format!("failed to create storage directory {:?}", path))?;
         let db = sled::open(path)
             .with_context(|| format!("failed to open sled database at {:?}", path))?;
-        Ok(Self { db })
+        Ok(Self {
+            db,
+            flush_async: false,
+        })
+    }
+
+    /// With `true`, writes no longer flush synchronously before returning.
+    pub fn with_flush_async(mut self, flush_async: bool) -> Self {
+        self.flush_async = flush_async;
+        self
+    }
+
+    /// Write everything buffered to disk, blocking until it is there.
+    pub fn flush(&self) -> Result<()> {
+        self.db.flush().context("failed to flush storage")?;
+        Ok(())
+    }
+
+    /// Write everything buffered to disk without blocking the executor.
+    pub async fn flush_async(&self) -> Result<()> {
+        self.db
+            .flush_async()
+            .await
+            .context("failed to flush storage")?;
+        Ok(())
+    }
+
+    /// Flush every `interval` until the returned task is aborted.
+    pub fn spawn_periodic_flush(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let storage = self.clone();
+        tokio::spawn(async move {
+            let mut timer = tokio::time::interval(interval);
+            timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                timer.tick().await;
+                if let Err(err) = storage.flush_async().await {
+                    warn!(?err, "periodic storage flush failed");
+                }
+            }
+        })
+    }
+
+    fn flush_after_write(&self, tree: &sled::Tree) -> Result<()> {
+        if !self.flush_async {
+            tree.flush()?;
+        }
+        Ok(())
     }
 
     fn tree(&self) -> sled::Result<sled::Tree> {
         self.db.open_tree(Self::TREE)
     }
 
+    fn addressing_tree(&self) -> sled::Result<sled::Tree> {
+        self.db.open_tree(Self::ADDRESSING_TREE)
+    }
+
     fn inbound_tree(&self) -> sled::Result<sled::Tree> {
         self.db.open_tree(Self::INBOUND_TREE)
     }
 
+    fn receipt_tree(&self) -> sled::Result<sled::Tree> {
+        self.db.open_tree(Self::RECEIPT_TREE)
+    }
+
+    fn device_tree(&self) -> sled::Result<sled::Tree> {
+        self.db.open_tree(Self::DEVICE_TREE)
+    }
+
+    fn blob_tree(&self) -> sled::Result<sled::Tree> {
+        self.db.open_tree(Self::BLOB_TREE)
+    }
+
+    fn device_key(fingerprint: &str, device_id: &str) -> String {
+        format!("{fingerprint}/{device_id}")
+    }
+
     pub fn insert_outbound(
         &self,
         message_id: &str,
@@ -78,15 +199,40 @@ impl NodeStorage {
 
         let encoded = bincode::serialize(&record)?;
         tree.insert(key, encoded)?;
-        tree.flush()?;
+        self.flush_after_write(&tree)?;
+        Ok(())
+    }
+
+    /// Keep the sender's addressing for an outbound envelope until every
+    /// target peer has it, so retries can still name the recipient. Kept
+    /// apart from the replication records, whose format predates it.
+    pub fn store_addressing(&self, message_id: &str, addressing: &Addressing) -> Result<()> {
+        let tree = self.addressing_tree()?;
+        tree.insert(message_id.as_bytes(), bincode::serialize(addressing)?)?;
+        self.flush_after_write(&tree)?;
         Ok(())
     }
 
-    pub fn mark_peer_success(&self, message_id: &str, peer: &PeerId) -> Result<bool> {
+    fn load_addressing(&self, message_id: &str) -> Result<Option<Addressing>> {
+        match self.addressing_tree()?.get(message_id.as_bytes())? {
+            Some(bytes) => Ok(Some(bincode::deserialize(&bytes)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Record that `peer` accepted its replica, storing a delivery receipt.
+    pub fn mark_peer_success(&self, message_id: &str, peer: &PeerId) -> Result<PeerAck> {
+        let receipt = self.store_receipt(message_id, peer)?;
+
         let tree = self.tree()?;
         let key = message_id.as_bytes();
         let Some(existing) = tree.get(key)? else {
-            return Ok(true);
+            return Ok(PeerAck {
+                receipt,
+                sender_fingerprint: None,
+                complete: true,
+                acked: 1,
+            });
         };
 
         let mut record: StoredEnvelope = bincode::deserialize(&existing)?;
@@ -98,12 +244,49 @@ impl NodeStorage {
 
         if record.pending_peers.is_empty() {
             tree.remove(key)?;
+            self.addressing_tree()?.remove(key)?;
         } else {
             let encoded = bincode::serialize(&record)?;
             tree.insert(key, encoded)?;
         }
-        tree.flush()?;
-        Ok(record.pending_peers.is_empty())
+        self.flush_after_write(&tree)?;
+        Ok(PeerAck {
+            receipt,
+            sender_fingerprint: Some(record.envelope.sender_fingerprint.clone()),
+            complete: record.pending_peers.is_empty(),
+            acked: record.acked_peers.len(),
+        })
+    }
+
+    fn store_receipt(&self, message_id: &str, peer: &PeerId) -> Result<DeliveryReceipt> {
+        let message_uuid = Uuid::parse_str(message_id)
+            .with_context(|| format!("message id {message_id} is not a UUID"))?;
+        // Receipts identify the recipient by UUID; derive a stable one from the peer id.
+        let delivered_to = Uuid::new_v5(&Uuid::NAMESPACE_OID, &peer.to_bytes());
+        let receipt = DeliveryReceipt::new(message_uuid, delivered_to);
+
+        let record = StoredReceipt {
+            receipt: receipt.clone(),
+            peer: peer.to_string(),
+        };
+        let tree = self.receipt_tree()?;
+        tree.insert(format!("{message_id}/{peer}").as_bytes(), bincode::serialize(&record)?)?;
+        self.flush_after_write(&tree)?;
+        Ok(receipt)
+    }
+
+    /// Delivery receipts recorded for an outbound message, with the peer each came from.
+    pub fn load_receipts(&self, message_id: &str) -> Result<Vec<(DeliveryReceipt, PeerId)>> {
+        let tree = self.receipt_tree()?;
+        let mut receipts = Vec::new();
+        for entry in tree.scan_prefix(format!("{message_id}/").as_bytes()) {
+            let (_, value) = entry?;
+            let record: StoredReceipt = bincode::deserialize(&value)?;
+            if let Ok(peer) = PeerId::from_str(&record.peer) {
+                receipts.push((record.receipt, peer));
+            }
+        }
+        Ok(receipts)
     }
 
     pub fn load_pending(&self) -> Result<Vec<PendingEnvelope>> {
@@ -114,41 +297,314 @@ impl NodeStorage {
             let message_id =
                 String::from_utf8(key.to_vec()).context("stored key was not valid UTF-8")?;
             let record: StoredEnvelope = bincode::deserialize(&value)?;
-            let mut peers = Vec::new();
-            for peer_str in &record.pending_peers {
-                if let Ok(peer_id) = PeerId::from_str(peer_str) {
-                    peers.push(peer_id);
-                }
-            }
+            let parse_peers = |peers: &[String]| -> Vec<PeerId> {
+                peers.iter().filter_map(|p| PeerId::from_str(p).ok()).collect()
+            };
+            let peers = parse_peers(&record.pending_peers);
             if peers.is_empty() {
                 continue;
             }
             pending.push(PendingEnvelope {
+                addressing: self.load_addressing(&message_id)?,
                 message_id,
                 envelope: record.envelope.clone(),
                 pending_peers: peers,
+                acked_peers: parse_peers(&record.acked_peers),
             });
         }
         Ok(pending)
     }
 
+    /// Persist an envelope received from a peer. Envelopes sent by a revoked
+    /// device are refused.
+    ///
+    /// The device id in an envelope is only a claim, so a registered device
+    /// must also have signed the envelope with the key it registered with
+    /// before its record counts. Once an identity has registered devices,
+    /// envelopes from devices it never registered are refused too, or a
+    /// revoked device could carry on under a fresh id.
     pub fn store_inbound(&self, envelope: &EncryptedEnvelope) -> Result<()> {
+        let device_id = envelope.sender_device.0.to_string();
+        let devices = self.list_devices(&envelope.sender_fingerprint)?;
+        match devices.iter().find(|d| d.device_id == device_id) {
+            Some(device) => {
+                let signed = PgpKeyPair::parse_public_key(&device.public_key)
+                    .is_ok_and(|key| envelope.is_signed_by_device(&key));
+                if !signed {
+                    bail!(
+                        "envelope {} is not signed by device {device_id}",
+                        envelope.message_id
+                    );
+                }
+                if device.is_revoked() {
+                    bail!(
+                        "envelope {} was sent by revoked device {device_id}",
+                        envelope.message_id
+                    );
+                }
+            }
+            None if !devices.is_empty() => bail!(
+                "envelope {} was sent by unregistered device {device_id}",
+                envelope.message_id
+            ),
+            None => {}
+        }
+
         let tree = self.inbound_tree()?;
         let key = envelope.message_id.to_string();
 
-        let stored_ms = std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_millis() as i64;
-
         let record = StoredInbound {
             envelope: envelope.clone(),
-            stored_ms,
+            stored_ms: now_ms(),
         };
 
         let encoded = bincode::serialize(&record)?;
         tree.insert(key.as_bytes(), encoded)?;
-        tree.flush()?;
+        self.flush_after_write(&tree)?;
+        Ok(())
+    }
+
+    /// Persist a reassembled file under `blob_id`, replacing any earlier one.
+    pub fn store_blob(&self, blob_id: &str, bytes: &[u8]) -> Result<()> {
+        let tree = self.blob_tree()?;
+        tree.insert(blob_id.as_bytes(), bytes)?;
+        self.flush_after_write(&tree)?;
         Ok(())
     }
+
+    pub fn load_blob(&self, blob_id: &str) -> Result<Option<Vec<u8>>> {
+        let tree = self.blob_tree()?;
+        Ok(tree.get(blob_id.as_bytes())?.map(|bytes| bytes.to_vec()))
+    }
+
+    fn load_device(&self, fingerprint: &str, device_id: &str) -> Result<Option<DeviceRecord>> {
+        let tree = self.device_tree()?;
+        match tree.get(Self::device_key(fingerprint, device_id).as_bytes())? {
+            Some(value) => Ok(Some(bincode::deserialize(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn save_device(&self, record: &DeviceRecord) -> Result<()> {
+        let tree = self.device_tree()?;
+        let key = Self::device_key(&record.fingerprint, &record.device_id);
+        tree.insert(key.as_bytes(), bincode::serialize(record)?)?;
+        self.flush_after_write(&tree)?;
+        Ok(())
+    }
+
+    /// Register `device_id` under `fingerprint`, or refresh its last-seen
+    /// time if it is already known. A revoked device cannot re-register.
+    pub fn register_device(
+        &self,
+        fingerprint: &str,
+        device_id: &str,
+        public_key: &str,
+    ) -> Result<DeviceRecord> {
+        let now = now_ms();
+        let record = match self.load_device(fingerprint, device_id)? {
+            Some(existing) if existing.is_revoked() => {
+                bail!("device {device_id} has been revoked")
+            }
+            Some(existing) => DeviceRecord {
+                public_key: public_key.to_string(),
+                last_seen_ms: now,
+                ..existing
+            },
+            None => DeviceRecord {
+                device_id: device_id.to_string(),
+                fingerprint: fingerprint.to_string(),
+                public_key: public_key.to_string(),
+                first_seen_ms: now,
+                last_seen_ms: now,
+                revoked_ms: None,
+            },
+        };
+        self.save_device(&record)?;
+        Ok(record)
+    }
+
+    /// Every device registered under `fingerprint`, revoked ones included,
+    /// oldest first.
+    pub fn list_devices(&self, fingerprint: &str) -> Result<Vec<DeviceRecord>> {
+        let tree = self.device_tree()?;
+        let mut devices = Vec::new();
+        for entry in tree.scan_prefix(format!("{fingerprint}/").as_bytes()) {
+            let (_, value) = entry?;
+            devices.push(bincode::deserialize::<DeviceRecord>(&value)?);
+        }
+        devices.sort_by_key(|d| d.first_seen_ms);
+        Ok(devices)
+    }
+
+    /// Mark a device as revoked. Revoking twice keeps the original time.
+    pub fn revoke_device(&self, fingerprint: &str, device_id: &str) -> Result<DeviceRecord> {
+        let Some(mut record) = self.load_device(fingerprint, device_id)? else {
+            bail!("no device {device_id} registered for {fingerprint}");
+        };
+        if record.revoked_ms.is_none() {
+            record.revoked_ms = Some(now_ms());
+            self.save_device(&record)?;
+        }
+        Ok(record)
+    }
+}
+
+fn now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cryptochat_crypto_core::KeyPair;
+    use cryptochat_messaging::{ConversationId, DeviceId, PlaintextMessage};
+
+    fn temp_storage() -> (NodeStorage, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("cryptochat-storage-{}", Uuid::new_v4()));
+        (NodeStorage::open(&dir).unwrap(), dir)
+    }
+
+    fn envelope_from(device: &DeviceId) -> EncryptedEnvelope {
+        let keypair = KeyPair::from_seed(b"sender").unwrap();
+        let message = PlaintextMessage::new(ConversationId::new(), device.clone(), b"hi".to_vec());
+        EncryptedEnvelope::from_plaintext(message, &keypair).unwrap()
+    }
+
+    #[tokio::test]
+    async fn deferred_writes_survive_reopen_after_flush() {
+        let dir = std::env::temp_dir().join(format!("cryptochat-storage-{}", Uuid::new_v4()));
+        let storage = NodeStorage::open(&dir).unwrap().with_flush_async(true);
+        let flusher = storage.spawn_periodic_flush(Duration::from_millis(10));
+
+        let envelope = envelope_from(&DeviceId::new());
+        let message_id = envelope.message_id.to_string();
+        let (acked, pending) = (PeerId::random(), PeerId::random());
+        storage
+            .insert_outbound(&message_id, &envelope, &[acked, pending])
+            .unwrap();
+        storage.flush_async().await.unwrap();
+        storage.mark_peer_success(&message_id, &acked).unwrap();
+        storage.register_device("FP", "laptop", "KEY").unwrap();
+
+        // Shutdown: stop the periodic task, then the guaranteed final flush
+        flusher.abort();
+        let _ = flusher.await;
+        storage.flush().unwrap();
+        drop(storage);
+
+        let reopened = NodeStorage::open(&dir).unwrap();
+        let records = reopened.load_pending().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].pending_peers, vec![pending]);
+        assert_eq!(records[0].acked_peers, vec![acked]);
+        assert_eq!(reopened.load_receipts(&message_id).unwrap().len(), 1);
+        assert_eq!(reopened.list_devices("FP").unwrap().len(), 1);
+        drop(reopened);
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn registered_devices_are_listed_per_identity() {
+        let (storage, dir) = temp_storage();
+        let first = storage.register_device("FP", "laptop", "KEY1").unwrap();
+        storage.register_device("FP", "phone", "KEY2").unwrap();
+        storage.register_device("OTHER", "tablet", "KEY3").unwrap();
+
+        let again = storage.register_device("FP", "laptop", "KEY1").unwrap();
+        assert_eq!(again.first_seen_ms, first.first_seen_ms);
+        assert!(again.last_seen_ms >= first.last_seen_ms);
+
+        let ids: Vec<_> = storage
+            .list_devices("FP")
+            .unwrap()
+            .into_iter()
+            .map(|d| d.device_id)
+            .collect();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains(&"laptop".to_string()) && ids.contains(&"phone".to_string()));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn revoked_device_is_rejected() {
+        let (storage, dir) = temp_storage();
+        let (device, device_key) = (DeviceId::new(), PgpKeyPair::generate("laptop").unwrap());
+        let signed = |device: &DeviceId, key: &PgpKeyPair| {
+            let mut envelope = envelope_from(device);
+            envelope.sign_device(key).unwrap();
+            envelope
+        };
+        let envelope = signed(&device, &device_key);
+        let device_id = device.0.to_string();
+        storage
+            .register_device(
+                &envelope.sender_fingerprint,
+                &device_id,
+                &device_key.export_public_key().unwrap(),
+            )
+            .unwrap();
+        storage.store_inbound(&envelope).unwrap();
+
+        let revoked = storage
+            .revoke_device(&envelope.sender_fingerprint, &device_id)
+            .unwrap();
+        assert!(revoked.is_revoked());
+        assert!(storage
+            .store_inbound(&signed(&device, &device_key))
+            .is_err());
+        assert!(storage
+            .register_device(&envelope.sender_fingerprint, &device_id, "KEY")
+            .is_err());
+        // Other registered devices on the same identity still get through
+        let (phone, phone_key) = (DeviceId::new(), PgpKeyPair::generate("phone").unwrap());
+        storage
+            .register_device(
+                &envelope.sender_fingerprint,
+                &phone.0.to_string(),
+                &phone_key.export_public_key().unwrap(),
+            )
+            .unwrap();
+        storage.store_inbound(&signed(&phone, &phone_key)).unwrap();
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn devices_must_prove_who_they_are() {
+        let (storage, dir) = temp_storage();
+        let (device, device_key) = (DeviceId::new(), PgpKeyPair::generate("laptop").unwrap());
+        let fingerprint = envelope_from(&device).sender_fingerprint;
+
+        // Nothing registered yet: envelopes pass as before
+        storage
+            .store_inbound(&envelope_from(&DeviceId::new()))
+            .unwrap();
+
+        storage
+            .register_device(
+                &fingerprint,
+                &device.0.to_string(),
+                &device_key.export_public_key().unwrap(),
+            )
+            .unwrap();
+        // Unsigned, or signed by some other key, the device id is just a claim
+        assert!(storage.store_inbound(&envelope_from(&device)).is_err());
+        let mut forged = envelope_from(&device);
+        forged
+            .sign_device(&PgpKeyPair::generate("mallory").unwrap())
+            .unwrap();
+        assert!(storage.store_inbound(&forged).is_err());
+        // A revoked device can't dodge the check under a fresh id
+        let mut fresh = envelope_from(&DeviceId::new());
+        fresh.sign_device(&device_key).unwrap();
+        assert!(storage.store_inbound(&fresh).is_err());
+
+        let mut envelope = envelope_from(&device);
+        envelope.sign_device(&device_key).unwrap();
+        storage.store_inbound(&envelope).unwrap();
+        let _ = std::fs::remove_dir_all(dir);
+    }
 }
@@ -0,0 +1,331 @@
+//! Reassembly of chunked file transfers relayed through the node.
+//!
+//! Chunks are buffered per transfer until every one has arrived, then joined
+//! and written to the storage blob area; nothing complete stays in memory.
+//! A transfer that grows past [`MAX_TRANSFER_BYTES`] or sees no chunk for
+//! [`TRANSFER_IDLE_TIMEOUT_MS`] is discarded, so a sender that stops halfway
+//! can't pin memory. Discards are counted in [`ReassemblyBuffer::dropped_transfers`],
+//! which `GET /metrics` reports.
+//!
+//! Across all transfers the buffer holds at most [`MAX_BUFFERED_BYTES`] in at
+//! most [`MAX_TRANSFERS`] transfers; chunks beyond that are refused until
+//! room frees up, rather than evicting someone else's transfer. Idle
+//! transfers are also swept by [`ReassemblyBuffer::spawn_periodic_expiry`],
+//! so a stalled sender's chunks go even when no other chunk arrives.
+
+use crate::storage::NodeStorage;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::time::MissedTickBehavior;
+use tracing::warn;
+
+/// Largest file accepted for reassembly.
+pub const MAX_TRANSFER_BYTES: usize = 64 * 1024 * 1024;
+/// How long a transfer may go without a new chunk before it is discarded.
+pub const TRANSFER_IDLE_TIMEOUT_MS: i64 = 2 * 60 * 1000;
+/// Most chunks a single transfer may be split into.
+pub const MAX_TOTAL_CHUNKS: u32 = 16 * 1024;
+/// Most bytes buffered across every transfer in progress.
+pub const MAX_BUFFERED_BYTES: usize = 256 * 1024 * 1024;
+/// Most transfers in progress at once.
+pub const MAX_TRANSFERS: usize = 64;
+/// How often idle transfers are swept.
+pub const TRANSFER_EXPIRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// One piece of a file, as the sender split it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileChunk {
+    pub transfer_id: String,
+    /// Zero-based position of this chunk.
+    pub index: u32,
+    pub total_chunks: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TransferError {
+    #[error("transfer id is empty")]
+    NoTransferId,
+    #[error("chunk {index} of {total} is out of range, or disagrees with the transfer's chunk count")]
+    BadChunk { index: u32, total: u32 },
+    #[error("transfer {0} exceeds {MAX_TRANSFER_BYTES} bytes and was discarded")]
+    TooLarge(String),
+    #[error("transfer is split into {0} chunks, more than {MAX_TOTAL_CHUNKS}")]
+    TooManyChunks(u32),
+    #[error("reassembly buffer is full; try again later")]
+    Full,
+    #[error("failed to store reassembled file: {0}")]
+    Storage(String),
+}
+
+#[derive(Debug)]
+struct PartialTransfer {
+    total_chunks: u32,
+    chunks: BTreeMap<u32, Vec<u8>>,
+    buffered_bytes: usize,
+    last_chunk_ms: i64,
+}
+
+#[derive(Clone)]
+pub struct ReassemblyBuffer {
+    transfers: Arc<Mutex<HashMap<String, PartialTransfer>>>,
+    dropped: Arc<AtomicU64>,
+    storage: NodeStorage,
+    max_bytes: usize,
+    idle_timeout_ms: i64,
+    max_buffered_bytes: usize,
+    max_transfers: usize,
+}
+
+impl ReassemblyBuffer {
+    pub fn new(storage: NodeStorage) -> Self {
+        Self {
+            transfers: Arc::default(),
+            dropped: Arc::default(),
+            storage,
+            max_bytes: MAX_TRANSFER_BYTES,
+            idle_timeout_ms: TRANSFER_IDLE_TIMEOUT_MS,
+            max_buffered_bytes: MAX_BUFFERED_BYTES,
+            max_transfers: MAX_TRANSFERS,
+        }
+    }
+
+    /// Override the size cap and inactivity timeout.
+    pub fn with_limits(mut self, max_bytes: usize, idle_timeout_ms: i64) -> Self {
+        self.max_bytes = max_bytes;
+        self.idle_timeout_ms = idle_timeout_ms;
+        self
+    }
+
+    /// Override the caps shared by every transfer.
+    pub fn with_capacity(mut self, max_buffered_bytes: usize, max_transfers: usize) -> Self {
+        self.max_buffered_bytes = max_buffered_bytes;
+        self.max_transfers = max_transfers;
+        self
+    }
+
+    /// Buffer a chunk. Once its transfer is complete the file is written to
+    /// the blob area under the transfer id, which is returned.
+    pub fn accept(&self, chunk: FileChunk, now_ms: i64) -> Result<Option<String>, TransferError> {
+        if chunk.transfer_id.is_empty() {
+            return Err(TransferError::NoTransferId);
+        }
+        if chunk.index >= chunk.total_chunks {
+            return Err(TransferError::BadChunk {
+                index: chunk.index,
+                total: chunk.total_chunks,
+            });
+        }
+        if chunk.total_chunks > MAX_TOTAL_CHUNKS {
+            return Err(TransferError::TooManyChunks(chunk.total_chunks));
+        }
+
+        let mut transfers = self.transfers.lock().unwrap_or_else(PoisonError::into_inner);
+        self.expire_locked(&mut transfers, now_ms);
+
+        let buffered: usize = transfers.values().map(|t| t.buffered_bytes).sum();
+        let new_transfer = !transfers.contains_key(&chunk.transfer_id);
+        if (new_transfer && transfers.len() >= self.max_transfers)
+            || buffered + chunk.data.len() > self.max_buffered_bytes
+        {
+            return Err(TransferError::Full);
+        }
+
+        let transfer = transfers
+            .entry(chunk.transfer_id.clone())
+            .or_insert_with(|| PartialTransfer {
+                total_chunks: chunk.total_chunks,
+                chunks: BTreeMap::new(),
+                buffered_bytes: 0,
+                last_chunk_ms: now_ms,
+            });
+        if transfer.total_chunks != chunk.total_chunks {
+            return Err(TransferError::BadChunk {
+                index: chunk.index,
+                total: chunk.total_chunks,
+            });
+        }
+        transfer.last_chunk_ms = now_ms;
+        transfer.buffered_bytes += chunk.data.len();
+        if let Some(previous) = transfer.chunks.insert(chunk.index, chunk.data) {
+            // A resent chunk replaces the earlier copy
+            transfer.buffered_bytes -= previous.len();
+        }
+
+        if transfer.buffered_bytes > self.max_bytes {
+            transfers.remove(&chunk.transfer_id);
+            self.record_drop(&chunk.transfer_id, "size cap exceeded");
+            return Err(TransferError::TooLarge(chunk.transfer_id));
+        }
+        if transfer.chunks.len() < transfer.total_chunks as usize {
+            return Ok(None);
+        }
+
+        let Some(complete) = transfers.remove(&chunk.transfer_id) else {
+            return Ok(None);
+        };
+        drop(transfers);
+        let mut file = Vec::with_capacity(complete.buffered_bytes);
+        for data in complete.chunks.into_values() {
+            file.extend_from_slice(&data);
+        }
+        self.storage
+            .store_blob(&chunk.transfer_id, &file)
+            .map_err(|e| TransferError::Storage(format!("{e:#}")))?;
+        Ok(Some(chunk.transfer_id))
+    }
+
+    /// Discard every transfer idle for longer than the timeout, returning
+    /// how many went.
+    pub fn expire_idle(&self, now_ms: i64) -> usize {
+        let mut transfers = self.transfers.lock().unwrap_or_else(PoisonError::into_inner);
+        self.expire_locked(&mut transfers, now_ms)
+    }
+
+    /// Run [`expire_idle`](Self::expire_idle) every `interval` until the
+    /// returned task is aborted.
+    pub fn spawn_periodic_expiry(&self, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let buffer = self.clone();
+        tokio::spawn(async move {
+            let mut timer = tokio::time::interval(interval);
+            timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            loop {
+                timer.tick().await;
+                buffer.expire_idle(now_ms());
+            }
+        })
+    }
+
+    fn expire_locked(&self, transfers: &mut HashMap<String, PartialTransfer>, now_ms: i64) -> usize {
+        let expired: Vec<String> = transfers
+            .iter()
+            .filter(|(_, transfer)| now_ms - transfer.last_chunk_ms > self.idle_timeout_ms)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &expired {
+            transfers.remove(id);
+            self.record_drop(id, "inactivity timeout");
+        }
+        expired.len()
+    }
+
+    fn record_drop(&self, transfer_id: &str, reason: &str) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        warn!(transfer_id, reason, "discarded incomplete file transfer");
+    }
+
+    /// Transfers discarded so far, for either limit.
+    pub fn dropped_transfers(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Transfers still waiting on chunks.
+    pub fn in_progress(&self) -> usize {
+        self.transfers.lock().unwrap_or_else(PoisonError::into_inner).len()
+    }
+}
+
+fn now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(transfer_id: &str, index: u32, total_chunks: u32, data: &[u8]) -> FileChunk {
+        FileChunk {
+            transfer_id: transfer_id.to_string(),
+            index,
+            total_chunks,
+            data: data.to_vec(),
+        }
+    }
+
+    fn temp_buffer() -> (ReassemblyBuffer, NodeStorage, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("cryptochat-transfers-{}", uuid::Uuid::new_v4()));
+        let storage = NodeStorage::open(&dir).unwrap();
+        (ReassemblyBuffer::new(storage.clone()), storage, dir)
+    }
+
+    #[test]
+    fn idle_transfers_are_discarded() {
+        let (buffer, storage, dir) = temp_buffer();
+        buffer.accept(chunk("stalled", 0, 2, b"half"), 0).unwrap();
+        buffer.accept(chunk("active", 0, 2, b"one "), TRANSFER_IDLE_TIMEOUT_MS).unwrap();
+
+        assert_eq!(buffer.expire_idle(TRANSFER_IDLE_TIMEOUT_MS + 1), 1);
+        assert_eq!(buffer.in_progress(), 1);
+        assert_eq!(buffer.dropped_transfers(), 1);
+
+        // The stalled transfer starts over; the active one completes into the blob area
+        let late = TRANSFER_IDLE_TIMEOUT_MS + 2;
+        assert_eq!(buffer.accept(chunk("stalled", 1, 2, b"rest"), late).unwrap(), None);
+        assert_eq!(
+            buffer.accept(chunk("active", 1, 2, b"two"), late).unwrap(),
+            Some("active".to_string())
+        );
+        assert_eq!(storage.load_blob("active").unwrap(), Some(b"one two".to_vec()));
+        assert_eq!(storage.load_blob("stalled").unwrap(), None);
+        assert_eq!(buffer.in_progress(), 1);
+
+        drop((buffer, storage));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn oversized_transfers_are_rejected() {
+        let (buffer, storage, dir) = temp_buffer();
+        let buffer = buffer.with_limits(8, TRANSFER_IDLE_TIMEOUT_MS);
+
+        buffer.accept(chunk("big", 0, 3, b"12345"), 0).unwrap();
+        // Resending a chunk doesn't count twice
+        buffer.accept(chunk("big", 0, 3, b"12345"), 0).unwrap();
+        assert!(matches!(
+            buffer.accept(chunk("big", 1, 3, b"6789"), 0),
+            Err(TransferError::TooLarge(id)) if id == "big"
+        ));
+        assert_eq!(buffer.in_progress(), 0);
+        assert_eq!(buffer.dropped_transfers(), 1);
+        assert_eq!(storage.load_blob("big").unwrap(), None);
+
+        assert!(matches!(
+            buffer.accept(chunk("bad", 3, 3, b"x"), 0),
+            Err(TransferError::BadChunk { .. })
+        ));
+        assert!(matches!(
+            buffer.accept(chunk("split", 0, MAX_TOTAL_CHUNKS + 1, b"x"), 0),
+            Err(TransferError::TooManyChunks(_))
+        ));
+
+        drop((buffer, storage));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn shared_caps_refuse_chunks_without_evicting_other_transfers() {
+        let (buffer, storage, dir) = temp_buffer();
+        let buffer = buffer.with_capacity(10, 2);
+
+        buffer.accept(chunk("a", 0, 2, b"1234"), 0).unwrap();
+        buffer.accept(chunk("b", 0, 2, b"1234"), 0).unwrap();
+        // A third transfer, then more bytes than are left
+        assert!(matches!(buffer.accept(chunk("c", 0, 1, b"x"), 0), Err(TransferError::Full)));
+        assert!(matches!(buffer.accept(chunk("a", 1, 2, b"123"), 0), Err(TransferError::Full)));
+        assert_eq!(buffer.in_progress(), 2);
+        assert_eq!(buffer.dropped_transfers(), 0);
+
+        // Finishing a transfer makes room again
+        assert_eq!(buffer.accept(chunk("b", 1, 2, b"56"), 0).unwrap(), Some("b".to_string()));
+        assert_eq!(buffer.accept(chunk("c", 0, 1, b"x"), 0).unwrap(), Some("c".to_string()));
+
+        drop((buffer, storage));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -5,12 +5,21 @@
 //! required behaviors (key generation, signing, verification, and envelope
 //! encryption) so higher layers can be developed in parallel.
 
+pub mod batch;
 pub mod pgp;
+pub mod ratchet;
+pub mod session;
 use base64::{engine::general_purpose, Engine as _};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Nonce,
+};
+use hkdf::Hkdf;
 use rand::{rngs::OsRng, RngCore, SeedableRng};
 use rand_chacha::ChaCha20Rng;
 use sha2::{Digest, Sha256};
 use std::fmt::{self, Display};
+use subtle::ConstantTimeEq;
 use uuid::Uuid;
 
 /// Result type exposed by crypto-core APIs.
@@ -25,12 +34,50 @@ pub enum CryptoError {
     InvalidCiphertext,
     #[error("internal error: {0}")]
     Internal(String),
+    #[error("invalid key encoding: {0}")]
+    InvalidKeyEncoding(String),
+    #[error("wrong passphrase")]
+    WrongPassphrase,
+    #[error("key has expired")]
+    KeyExpired,
+    #[error("key has been revoked")]
+    KeyRevoked,
+    #[error("key has no usable encryption subkey")]
+    NoEncryptionSubkey,
+    #[error("key creation time is too far in the future")]
+    KeyCreatedInFuture,
+    #[error("signature {0} in batch failed verification")]
+    BatchVerificationFailed(usize),
+    #[error("ciphertext failed authentication")]
+    AuthenticationFailed,
+}
+
+/// Version byte for [`KeyPair::to_bytes`] / [`KeyPair::export_secret`].
+const KEY_ENCODING_VERSION: u8 = 1;
+
+/// Flag set when the encoded key carries its private half.
+const KEY_FLAG_SECRET: u8 = 0x01;
+
+/// Compare two byte strings without short-circuiting on the first mismatch.
+///
+/// Only the lengths can leak through timing, never how many leading bytes
+/// matched. Use this for anything secret-derived that gates access.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.ct_eq(b).into()
 }
 
 /// Represents a PGP-style key fingerprint.
-#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+///
+/// Equality is constant-time (see [`constant_time_eq`]).
+#[derive(Debug, Clone, Eq, Hash, serde::Serialize, serde::Deserialize)]
 pub struct Fingerprint(String);
 
+impl PartialEq for Fingerprint {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(self.0.as_bytes(), other.0.as_bytes())
+    }
+}
+
 impl Fingerprint {
     /// Creates a fingerprint from a public key byte slice.
     pub fn from_public_key(public_key: &[u8]) -> Self {
@@ -108,12 +155,127 @@ impl KeyPair {
     pub fn private_key(&self) -> &[u8] {
         &self.private_key
     }
+
+    /// Public-only view, safe to serialize and share.
+    pub fn public_view(&self) -> PublicKeyView {
+        PublicKeyView {
+            fingerprint: self.fingerprint.clone(),
+            public_key: self.public_key.clone(),
+        }
+    }
+
+    /// Encode the public half.
+    ///
+    /// Layout: `version | flags | u32 len | public | u32 len | private`
+    /// (lengths little-endian). The private section is always empty here;
+    /// use [`KeyPair::export_secret`] to include it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        encode_key(&self.public_key, None)
+    }
+
+    /// Encode the full key pair, including the private key.
+    pub fn export_secret(&self) -> Vec<u8> {
+        encode_key(&self.public_key, Some(&self.private_key))
+    }
+
+    /// Decode a key pair produced by [`KeyPair::export_secret`].
+    ///
+    /// Public-only encodings are rejected since they can't sign or decrypt.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (public_key, private_key) = decode_key(bytes)?;
+        let private_key = private_key
+            .ok_or_else(|| CryptoError::InvalidKeyEncoding("no private key present".to_string()))?;
+
+        // The public key is derived from the private key; refuse mismatches
+        let mut hasher = Sha256::new();
+        hasher.update(&private_key);
+        if !constant_time_eq(hasher.finalize().as_slice(), public_key.as_slice()) {
+            return Err(CryptoError::InvalidKeyEncoding("public key does not match private key".to_string()));
+        }
+
+        Ok(Self {
+            fingerprint: Fingerprint::from_public_key(&public_key),
+            public_key,
+            private_key,
+        })
+    }
 }
 
-/// Represents a detached signature.
+/// Public half of a [`KeyPair`].
 #[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PublicKeyView {
+    pub fingerprint: Fingerprint,
+    pub public_key: Vec<u8>,
+}
+
+impl PublicKeyView {
+    /// Decode from either [`KeyPair::to_bytes`] or [`KeyPair::export_secret`]
+    /// output; any private section is ignored.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (public_key, _) = decode_key(bytes)?;
+        Ok(Self {
+            fingerprint: Fingerprint::from_public_key(&public_key),
+            public_key,
+        })
+    }
+}
+
+fn encode_key(public_key: &[u8], private_key: Option<&[u8]>) -> Vec<u8> {
+    let private = private_key.unwrap_or_default();
+    let mut out = Vec::with_capacity(10 + public_key.len() + private.len());
+    out.push(KEY_ENCODING_VERSION);
+    out.push(if private_key.is_some() { KEY_FLAG_SECRET } else { 0 });
+    out.extend_from_slice(&(public_key.len() as u32).to_le_bytes());
+    out.extend_from_slice(public_key);
+    out.extend_from_slice(&(private.len() as u32).to_le_bytes());
+    out.extend_from_slice(private);
+    out
+}
+
+fn decode_key(bytes: &[u8]) -> Result<(Vec<u8>, Option<Vec<u8>>)> {
+    fn take<'a>(bytes: &mut &'a [u8], len: usize, what: &str) -> Result<&'a [u8]> {
+        if bytes.len() < len {
+            return Err(CryptoError::InvalidKeyEncoding(format!("truncated {what}")));
+        }
+        let (head, rest) = bytes.split_at(len);
+        *bytes = rest;
+        Ok(head)
+    }
+    fn take_section(bytes: &mut &[u8], what: &str) -> Result<Vec<u8>> {
+        let len = take(bytes, 4, what)?;
+        let len = u32::from_le_bytes([len[0], len[1], len[2], len[3]]) as usize;
+        Ok(take(bytes, len, what)?.to_vec())
+    }
+
+    let mut rest = bytes;
+    let header = take(&mut rest, 2, "header")?;
+    if header[0] != KEY_ENCODING_VERSION {
+        return Err(CryptoError::InvalidKeyEncoding(format!("unsupported version {}", header[0])));
+    }
+    let has_secret = header[1] & KEY_FLAG_SECRET != 0;
+
+    let public_key = take_section(&mut rest, "public key")?;
+    let private_key = take_section(&mut rest, "private key")?;
+    if !rest.is_empty() {
+        return Err(CryptoError::InvalidKeyEncoding("trailing bytes".to_string()));
+    }
+
+    Ok((public_key, has_secret.then_some(private_key)))
+}
+
+/// Represents a detached signature.
+///
+/// Equality is constant-time so comparing against an expected signature
+/// doesn't reveal how much of a forgery was correct.
+#[derive(Debug, Clone, Eq, serde::Serialize, serde::Deserialize)]
 pub struct Signature(String);
 
+impl PartialEq for Signature {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(self.0.as_bytes(), other.0.as_bytes())
+    }
+}
+
 impl Signature {
     pub fn as_str(&self) -> &str {
         &self.0
@@ -132,6 +294,7 @@ pub fn sign_message(key_pair: &KeyPair, message: &[u8]) -> Result<Signature> {
 /// Verify a deterministic signature created by [`sign_message`].
 pub fn verify_signature(key_pair: &KeyPair, message: &[u8], signature: &Signature) -> Result<()> {
     let expected = sign_message(key_pair, message)?;
+    // Constant-time: see `impl PartialEq for Signature`
     if expected == *signature {
         Ok(())
     } else {
@@ -139,16 +302,50 @@ pub fn verify_signature(key_pair: &KeyPair, message: &[u8], signature: &Signatur
     }
 }
 
+/// Algorithm that produced an [`EncryptedPayload`].
+///
+/// Recorded in every payload so the decoder never has to guess, and so new
+/// suites can be added without breaking what is already stored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CipherSuite {
+    /// The original prototype scheme: an unauthenticated ChaCha20 keystream
+    /// XORed over the plaintext. Payloads without a suite tag are this.
+    ChaCha20Stream,
+    /// ChaCha20-Poly1305 AEAD with a random nonce.
+    ChaCha20Poly1305,
+}
+
+impl CipherSuite {
+    /// Suite used for newly encrypted messages.
+    pub const DEFAULT: CipherSuite = CipherSuite::ChaCha20Poly1305;
+
+    /// Suite of payloads stored before suites were recorded.
+    fn legacy() -> Self {
+        CipherSuite::ChaCha20Stream
+    }
+
+    fn nonce_len(self) -> usize {
+        match self {
+            CipherSuite::ChaCha20Stream => 24,
+            CipherSuite::ChaCha20Poly1305 => 12,
+        }
+    }
+}
+
 /// Symmetric envelope encrypted payload.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct EncryptedPayload {
+    #[serde(default = "CipherSuite::legacy")]
+    pub suite: CipherSuite,
     pub nonce: String,
     pub ciphertext: String,
 }
 
 impl EncryptedPayload {
-    pub fn new(nonce: &[u8], ciphertext: &[u8]) -> Self {
+    pub fn new(suite: CipherSuite, nonce: &[u8], ciphertext: &[u8]) -> Self {
         Self {
+            suite,
             nonce: general_purpose::STANDARD_NO_PAD.encode(nonce),
             ciphertext: general_purpose::STANDARD_NO_PAD.encode(ciphertext),
         }
@@ -165,10 +362,63 @@ impl EncryptedPayload {
     }
 }
 
-/// Encrypts a message deterministically for prototyping purposes.
+/// Encrypts a message with [`CipherSuite::DEFAULT`].
 ///
 /// **Important:** replace this with real OpenPGP session key handling before launch.
 pub fn encrypt_message(key_pair: &KeyPair, plaintext: &[u8]) -> Result<EncryptedPayload> {
+    encrypt_message_with(key_pair, plaintext, CipherSuite::DEFAULT)
+}
+
+/// Encrypts a message with a specific [`CipherSuite`].
+pub fn encrypt_message_with(
+    key_pair: &KeyPair,
+    plaintext: &[u8],
+    suite: CipherSuite,
+) -> Result<EncryptedPayload> {
+    match suite {
+        CipherSuite::ChaCha20Stream => Ok(stream_encrypt(key_pair, plaintext)),
+        CipherSuite::ChaCha20Poly1305 => aead_encrypt(key_pair, plaintext),
+    }
+}
+
+/// Decrypts an [`EncryptedPayload`] with whichever suite produced it.
+pub fn decrypt_message(key_pair: &KeyPair, payload: &EncryptedPayload) -> Result<Vec<u8>> {
+    let (nonce, ciphertext) = payload.decode()?;
+    if nonce.len() != payload.suite.nonce_len() {
+        return Err(CryptoError::InvalidCiphertext);
+    }
+    match payload.suite {
+        CipherSuite::ChaCha20Stream => Ok(stream_decrypt(key_pair, ciphertext)),
+        CipherSuite::ChaCha20Poly1305 => aead_decrypt(key_pair, &nonce, &ciphertext),
+    }
+}
+
+const PAYLOAD_KEY_INFO: &[u8] = b"CryptoChat payload key v1";
+
+fn aead_cipher(key_pair: &KeyPair) -> Result<ChaCha20Poly1305> {
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::new(None, key_pair.private_key())
+        .expand(PAYLOAD_KEY_INFO, &mut key)
+        .map_err(|e| CryptoError::Internal(format!("payload key derivation failed: {e}")))?;
+    Ok(ChaCha20Poly1305::new(&key.into()))
+}
+
+fn aead_encrypt(key_pair: &KeyPair, plaintext: &[u8]) -> Result<EncryptedPayload> {
+    let mut nonce = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext = aead_cipher(key_pair)?
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|e| CryptoError::Internal(format!("encryption failed: {e}")))?;
+    Ok(EncryptedPayload::new(CipherSuite::ChaCha20Poly1305, &nonce, &ciphertext))
+}
+
+fn aead_decrypt(key_pair: &KeyPair, nonce: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+    aead_cipher(key_pair)?
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| CryptoError::AuthenticationFailed)
+}
+
+fn stream_encrypt(key_pair: &KeyPair, plaintext: &[u8]) -> EncryptedPayload {
     let mut hasher = Sha256::new();
     hasher.update(key_pair.fingerprint().as_str().as_bytes());
     hasher.update(plaintext.len().to_le_bytes());
@@ -183,16 +433,10 @@ pub fn encrypt_message(key_pair: &KeyPair, plaintext: &[u8]) -> Result<Encrypted
         *byte ^= (keystream.next_u32() & 0xFF) as u8;
     }
 
-    Ok(EncryptedPayload::new(&nonce, &ciphertext))
+    EncryptedPayload::new(CipherSuite::ChaCha20Stream, &nonce, &ciphertext)
 }
 
-/// Decrypts an [`EncryptedPayload`] created by [`encrypt_message`].
-pub fn decrypt_message(key_pair: &KeyPair, payload: &EncryptedPayload) -> Result<Vec<u8>> {
-    let (nonce, mut ciphertext) = payload.decode()?;
-    if nonce.len() != 24 {
-        return Err(CryptoError::InvalidCiphertext);
-    }
-
+fn stream_decrypt(key_pair: &KeyPair, mut ciphertext: Vec<u8>) -> Vec<u8> {
     let mut hasher = Sha256::new();
     hasher.update(key_pair.fingerprint().as_str().as_bytes());
     hasher.update(ciphertext.len().to_le_bytes());
@@ -203,14 +447,29 @@ pub fn decrypt_message(key_pair: &KeyPair, payload: &EncryptedPayload) -> Result
         *byte ^= (keystream.next_u32() & 0xFF) as u8;
     }
 
-    Ok(ciphertext)
+    ciphertext
 }
 
+/// Namespace for [`device_id_from_seed`], so device ids can't collide with
+/// v5 UUIDs other software derives from the same bytes.
+const DEVICE_ID_NAMESPACE: Uuid = Uuid::from_u128(0x6c1e_2f0a_8d4b_4c3e_9a57_c0de_c4a7_0001);
+
 /// Generates an opaque identifier for device registrations.
+///
+/// Random on every call; use it for first-time setup, then
+/// [`device_id_from_seed`] once the device has a persisted seed.
 pub fn generate_device_id() -> String {
     Uuid::new_v4().to_string()
 }
 
+/// Derives a stable device identifier from a per-device seed.
+///
+/// The same seed always yields the same id, so a reinstalled device that
+/// restores its seed is recognized by the node as the same device.
+pub fn device_id_from_seed(seed: &[u8]) -> String {
+    Uuid::new_v5(&DEVICE_ID_NAMESPACE, seed).to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -225,6 +484,45 @@ mod tests {
         assert_eq!(first.fingerprint(), second.fingerprint());
     }
 
+    #[test]
+    fn secret_export_roundtrip() {
+        let keypair = KeyPair::from_seed(b"export").unwrap();
+        let restored = KeyPair::from_bytes(&keypair.export_secret()).unwrap();
+        assert_eq!(restored.public_key(), keypair.public_key());
+        assert_eq!(restored.private_key(), keypair.private_key());
+        assert_eq!(restored.fingerprint(), keypair.fingerprint());
+    }
+
+    #[test]
+    fn public_bytes_omit_private_key() {
+        let keypair = KeyPair::from_seed(b"export").unwrap();
+        let public = keypair.to_bytes();
+        assert!(public.len() < keypair.export_secret().len());
+        assert!(KeyPair::from_bytes(&public).is_err());
+
+        let view = PublicKeyView::from_bytes(&public).unwrap();
+        assert_eq!(view, keypair.public_view());
+    }
+
+    #[test]
+    fn truncated_key_bytes_are_rejected() {
+        let encoded = KeyPair::from_seed(b"export").unwrap().export_secret();
+        for len in [0, 1, 5, encoded.len() - 1] {
+            assert!(matches!(
+                KeyPair::from_bytes(&encoded[..len]),
+                Err(CryptoError::InvalidKeyEncoding(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn tampered_private_key_is_rejected() {
+        let mut encoded = KeyPair::from_seed(b"export").unwrap().export_secret();
+        let last = encoded.len() - 1;
+        encoded[last] ^= 0xFF;
+        assert!(KeyPair::from_bytes(&encoded).is_err());
+    }
+
     #[test]
     fn signatures_roundtrip() {
         let keypair = KeyPair::from_seed(b"signatures").unwrap();
@@ -233,6 +531,24 @@ mod tests {
         verify_signature(&keypair, message, &signature).unwrap();
     }
 
+    #[test]
+    fn signature_comparison_is_exact() {
+        let keypair = KeyPair::from_seed(b"signatures").unwrap();
+        let signature = sign_message(&keypair, b"original").unwrap();
+
+        // Same length, differs only in the last character
+        let mut forged = signature.as_str().to_owned();
+        let last = forged.pop().unwrap();
+        forged.push(if last == 'A' { 'B' } else { 'A' });
+        let forged = Signature(forged);
+
+        assert_ne!(signature, forged);
+        assert!(verify_signature(&keypair, b"original", &forged).is_err());
+        assert!(verify_signature(&keypair, b"tampered", &signature).is_err());
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+    }
+
     #[test]
     fn encryption_roundtrip() {
         let keypair = KeyPair::from_seed(b"encryption").unwrap();
@@ -241,9 +557,55 @@ mod tests {
         assert_eq!(decrypted, b"secret message");
     }
 
+    #[test]
+    fn each_suite_roundtrips() {
+        let keypair = KeyPair::from_seed(b"suites").unwrap();
+        for suite in [CipherSuite::ChaCha20Stream, CipherSuite::ChaCha20Poly1305] {
+            let payload = encrypt_message_with(&keypair, b"secret message", suite).unwrap();
+            assert_eq!(payload.suite, suite);
+            let json = serde_json::to_string(&payload).unwrap();
+            let restored: EncryptedPayload = serde_json::from_str(&json).unwrap();
+            assert_eq!(decrypt_message(&keypair, &restored).unwrap(), b"secret message");
+        }
+        assert_eq!(encrypt_message(&keypair, b"x").unwrap().suite, CipherSuite::ChaCha20Poly1305);
+
+        // Only the AEAD suite notices tampering
+        let mut payload = encrypt_message(&keypair, b"secret message").unwrap();
+        let (nonce, mut ciphertext) = payload.decode().unwrap();
+        ciphertext[0] ^= 1;
+        payload = EncryptedPayload::new(payload.suite, &nonce, &ciphertext);
+        assert!(matches!(decrypt_message(&keypair, &payload), Err(CryptoError::AuthenticationFailed)));
+    }
+
+    #[test]
+    fn untagged_payloads_are_legacy_and_unknown_suites_rejected() {
+        let keypair = KeyPair::from_seed(b"suites").unwrap();
+        let legacy = encrypt_message_with(&keypair, b"stored before suites", CipherSuite::ChaCha20Stream).unwrap();
+        let untagged = format!(r#"{{"nonce":"{}","ciphertext":"{}"}}"#, legacy.nonce, legacy.ciphertext);
+        let restored: EncryptedPayload = serde_json::from_str(&untagged).unwrap();
+        assert_eq!(restored.suite, CipherSuite::ChaCha20Stream);
+        assert_eq!(decrypt_message(&keypair, &restored).unwrap(), b"stored before suites");
+
+        let unknown = format!(r#"{{"suite":"rot13","nonce":"{}","ciphertext":"{}"}}"#, legacy.nonce, legacy.ciphertext);
+        assert!(serde_json::from_str::<EncryptedPayload>(&unknown).is_err());
+    }
+
     #[test]
     fn generate_device_id_is_uuid() {
         let id = generate_device_id();
         assert!(Uuid::parse_str(&id).is_ok());
     }
+
+    #[test]
+    fn device_id_from_seed_is_deterministic() {
+        let first = device_id_from_seed(b"device-seed");
+        let second = device_id_from_seed(b"device-seed");
+        assert_eq!(first, second);
+        assert!(Uuid::parse_str(&first).is_ok());
+    }
+
+    #[test]
+    fn device_id_differs_across_seeds() {
+        assert_ne!(device_id_from_seed(b"laptop"), device_id_from_seed(b"desktop"));
+    }
 }
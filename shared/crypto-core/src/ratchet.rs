@@ -0,0 +1,279 @@
+//! Symmetric key ratchet for per-message forward secrecy.
+//!
+//! A [`RatchetState`] is seeded from a [`SessionState`] and keeps one chain
+//! for sending and one for receiving. Every message advances its chain and
+//! uses a fresh message key, so old keys cannot be recomputed from the
+//! current state. Running a new handshake and calling [`RatchetState::rekey`]
+//! mixes fresh ephemeral secret into the root, healing the session after a
+//! state compromise.
+//!
+//! Incoming messages only move the receiving chain once they decrypt, so a
+//! corrupted or forged message can't use up a key or knock the two sides
+//! out of step.
+
+use crate::pgp::PgpKeyPair;
+use crate::session::SessionState;
+use crate::{CryptoError, Result};
+use hkdf::Hkdf;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+
+/// Most message keys we will derive ahead for (and cache) when messages are
+/// skipped or arrive out of order.
+pub const MAX_SKIPPED_KEYS: usize = 256;
+
+const CHAIN_A_INFO: &[u8] = b"CryptoChat ratchet chain A";
+const CHAIN_B_INFO: &[u8] = b"CryptoChat ratchet chain B";
+const MESSAGE_KEY_INFO: &[u8] = b"CryptoChat ratchet message key";
+const NEXT_CHAIN_INFO: &[u8] = b"CryptoChat ratchet next chain";
+const REKEY_INFO: &[u8] = b"CryptoChat ratchet rekey";
+
+fn expand(prk: &[u8; 32], info: &[u8]) -> Result<[u8; 32]> {
+    let mut out = [0u8; 32];
+    Hkdf::<Sha256>::from_prk(prk)
+        .map_err(|e| CryptoError::Internal(format!("invalid ratchet key: {e}")))?
+        .expand(info, &mut out)
+        .map_err(|e| CryptoError::Internal(format!("ratchet derivation failed: {e}")))?;
+    Ok(out)
+}
+
+/// One direction of the ratchet.
+#[derive(Clone, Serialize, Deserialize)]
+struct Chain {
+    key: [u8; 32],
+    /// Index of the next message key this chain will produce
+    index: u32,
+}
+
+impl Chain {
+    /// Derive the current message key and step the chain forward.
+    fn step(&mut self) -> Result<(u32, [u8; 32])> {
+        let message_key = expand(&self.key, MESSAGE_KEY_INFO)?;
+        self.key = expand(&self.key, NEXT_CHAIN_INFO)?;
+        let index = self.index;
+        self.index += 1;
+        Ok((index, message_key))
+    }
+}
+
+/// Per-conversation ratchet state.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RatchetState {
+    root: [u8; 32],
+    sending: Chain,
+    receiving: Chain,
+    /// Keys for receiving-chain messages we skipped over, by index
+    skipped: BTreeMap<u32, [u8; 32]>,
+    is_first: bool,
+}
+
+impl RatchetState {
+    /// Seed a ratchet from a completed session handshake.
+    ///
+    /// Both peers must pass opposite values for `is_first` (e.g. whoever has
+    /// the lower identity fingerprint passes `true`) so that one side's
+    /// sending chain is the other side's receiving chain.
+    pub fn from_session(session: &SessionState, is_first: bool) -> Result<Self> {
+        Self::from_root(*session.key(), is_first)
+    }
+
+    fn from_root(root: [u8; 32], is_first: bool) -> Result<Self> {
+        let chain_a = Chain { key: expand(&root, CHAIN_A_INFO)?, index: 0 };
+        let chain_b = Chain { key: expand(&root, CHAIN_B_INFO)?, index: 0 };
+        let (sending, receiving) = if is_first { (chain_a, chain_b) } else { (chain_b, chain_a) };
+        Ok(Self {
+            root,
+            sending,
+            receiving,
+            skipped: BTreeMap::new(),
+            is_first,
+        })
+    }
+
+    /// Encrypt an outgoing message under the next sending key. Returns the
+    /// index to send alongside it.
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Result<(u32, Vec<u8>)> {
+        let (index, key) = self.next_sending_key()?;
+        let ciphertext = PgpKeyPair::encrypt_with_session_key(&key, plaintext)?;
+        Ok((index, ciphertext))
+    }
+
+    /// Decrypt the incoming message with the given index.
+    ///
+    /// Skipped messages have their keys cached (up to [`MAX_SKIPPED_KEYS`]) so
+    /// they can still be decrypted when they arrive late. Each key opens one
+    /// message; the ratchet is only advanced once decryption succeeds.
+    pub fn decrypt(&mut self, index: u32, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let mut next = self.clone();
+        let key = next.receiving_key(index)?;
+        let plaintext = PgpKeyPair::decrypt_with_session_key(&key, ciphertext)?;
+        *self = next;
+        Ok(plaintext)
+    }
+
+    /// Key for the next outgoing message, with its index.
+    fn next_sending_key(&mut self) -> Result<(u32, [u8; 32])> {
+        self.sending.step()
+    }
+
+    /// Key for an incoming message with the given index, stepping the
+    /// receiving chain. Asking again for a used index is an error.
+    fn receiving_key(&mut self, index: u32) -> Result<[u8; 32]> {
+        if let Some(key) = self.skipped.remove(&index) {
+            return Ok(key);
+        }
+        if index < self.receiving.index {
+            return Err(CryptoError::Internal(format!("message key {index} already used")));
+        }
+        let gap = (index - self.receiving.index) as usize;
+        if gap > MAX_SKIPPED_KEYS {
+            return Err(CryptoError::Internal(format!("too many skipped messages ({gap})")));
+        }
+
+        while self.receiving.index < index {
+            let (skipped_index, key) = self.receiving.step()?;
+            self.skipped.insert(skipped_index, key);
+        }
+        // Keep the cache bounded by dropping the oldest skipped keys
+        while self.skipped.len() > MAX_SKIPPED_KEYS {
+            self.skipped.pop_first();
+        }
+
+        let (_, key) = self.receiving.step()?;
+        Ok(key)
+    }
+
+    /// Mix a fresh session handshake into the root and restart both chains.
+    pub fn rekey(&mut self, session: &SessionState) -> Result<()> {
+        let mut ikm = self.root.to_vec();
+        ikm.extend_from_slice(session.key());
+        let mut root = [0u8; 32];
+        Hkdf::<Sha256>::new(None, &ikm)
+            .expand(REKEY_INFO, &mut root)
+            .map_err(|e| CryptoError::Internal(format!("ratchet rekey failed: {e}")))?;
+        *self = Self::from_root(root, self.is_first)?;
+        Ok(())
+    }
+
+    /// Number of cached keys for skipped messages.
+    pub fn skipped_count(&self) -> usize {
+        self.skipped.len()
+    }
+}
+
+impl std::fmt::Debug for RatchetState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RatchetState")
+            .field("sending_index", &self.sending.index)
+            .field("receiving_index", &self.receiving.index)
+            .field("skipped", &self.skipped.len())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::begin_session;
+
+    fn pair() -> (RatchetState, RatchetState) {
+        let alice = begin_session();
+        let bob = begin_session();
+        let (alice_pub, bob_pub) = (alice.public_key(), bob.public_key());
+        let alice_session = alice.complete(&bob_pub, b"ctx").unwrap();
+        let bob_session = bob.complete(&alice_pub, b"ctx").unwrap();
+        (
+            RatchetState::from_session(&alice_session, true).unwrap(),
+            RatchetState::from_session(&bob_session, false).unwrap(),
+        )
+    }
+
+    #[test]
+    fn in_order_keys_match() {
+        let (mut alice, mut bob) = pair();
+        for expected in 0..5 {
+            let (index, key) = alice.next_sending_key().unwrap();
+            assert_eq!(index, expected);
+            assert_eq!(bob.receiving_key(index).unwrap(), key);
+        }
+
+        // And the other direction uses its own chain
+        let (index, key) = bob.next_sending_key().unwrap();
+        assert_eq!(alice.receiving_key(index).unwrap(), key);
+    }
+
+    #[test]
+    fn skipped_message_can_be_delivered_later() {
+        let (mut alice, mut bob) = pair();
+        let first = alice.next_sending_key().unwrap();
+        let second = alice.next_sending_key().unwrap();
+        let third = alice.next_sending_key().unwrap();
+
+        assert_eq!(bob.receiving_key(third.0).unwrap(), third.1);
+        assert_eq!(bob.skipped_count(), 2);
+        assert_eq!(bob.receiving_key(first.0).unwrap(), first.1);
+        assert_eq!(bob.receiving_key(second.0).unwrap(), second.1);
+        assert_eq!(bob.skipped_count(), 0);
+    }
+
+    #[test]
+    fn keys_are_never_reused() {
+        let (mut alice, mut bob) = pair();
+        let mut seen = std::collections::HashSet::new();
+        for _ in 0..50 {
+            let (_, key) = alice.next_sending_key().unwrap();
+            assert!(seen.insert(key));
+        }
+        let (_, bob_key) = bob.next_sending_key().unwrap();
+        assert!(!seen.contains(&bob_key));
+
+        // A delivered message's key can't be fetched a second time
+        let (mut alice, mut bob) = pair();
+        let (index, _) = alice.next_sending_key().unwrap();
+        bob.receiving_key(index).unwrap();
+        assert!(bob.receiving_key(index).is_err());
+    }
+
+    #[test]
+    fn rejects_gap_beyond_skip_limit() {
+        let (_, mut bob) = pair();
+        assert!(bob.receiving_key(MAX_SKIPPED_KEYS as u32 + 1).is_err());
+        assert!(bob.receiving_key(MAX_SKIPPED_KEYS as u32).is_ok());
+    }
+
+    #[test]
+    fn tampered_message_leaves_the_ratchet_in_step() {
+        let (mut alice, mut bob) = pair();
+        let (index, sealed) = alice.encrypt(b"one").unwrap();
+        let mut tampered = sealed.clone();
+        let last = tampered.len() - 1;
+        tampered[last] ^= 0x01;
+        assert!(bob.decrypt(index, &tampered).is_err());
+        // Nor does a message claiming a later index skip any keys
+        assert!(bob.decrypt(index + 5, &sealed).is_err());
+        assert_eq!(bob.skipped_count(), 0);
+
+        assert_eq!(bob.decrypt(index, &sealed).unwrap(), b"one");
+        let (index, sealed) = alice.encrypt(b"two").unwrap();
+        assert_eq!(bob.decrypt(index, &sealed).unwrap(), b"two");
+        assert!(bob.decrypt(index, &sealed).is_err());
+    }
+
+    #[test]
+    fn rekey_heals_both_sides() {
+        let (mut alice, mut bob) = pair();
+        let before = alice.next_sending_key().unwrap().1;
+
+        let a = begin_session();
+        let b = begin_session();
+        let (a_pub, b_pub) = (a.public_key(), b.public_key());
+        alice.rekey(&a.complete(&b_pub, b"ctx").unwrap()).unwrap();
+        bob.rekey(&b.complete(&a_pub, b"ctx").unwrap()).unwrap();
+
+        let (index, key) = alice.next_sending_key().unwrap();
+        assert_eq!(index, 0);
+        assert_ne!(key, before);
+        assert_eq!(bob.receiving_key(index).unwrap(), key);
+    }
+}
@@ -0,0 +1,164 @@
+//! Ephemeral session handshakes for forward secrecy.
+//!
+//! Each side generates a one-time X25519 key with [`begin_session`] and sends
+//! the public half to its peer (authenticated by the identity key at the
+//! messaging layer). The shared secret seeds a [`SessionState`] that is stored
+//! apart from the identity key and ratcheted forward with [`advance_session`].
+//! Because the ephemeral secrets are consumed by the handshake, leaking the
+//! long-term identity key later does not reveal past session keys.
+
+use crate::{CryptoError, Result};
+use hkdf::Hkdf;
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+/// Domain separation for the handshake key derivation.
+const HANDSHAKE_INFO: &[u8] = b"CryptoChat session v1";
+
+/// Domain separation for ratcheting a session key forward.
+const ADVANCE_INFO: &[u8] = b"CryptoChat session advance v1";
+
+/// Our half of a handshake that has not yet seen the peer's ephemeral key.
+pub struct PendingSession {
+    secret: EphemeralSecret,
+    public: PublicKey,
+}
+
+impl PendingSession {
+    /// Ephemeral public key to send to the peer.
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public.to_bytes()
+    }
+
+    /// Finish the handshake with the peer's ephemeral public key.
+    ///
+    /// `context` binds the session to the conversation (e.g. both identity
+    /// fingerprints) and must be the same on both sides. The ephemeral secret
+    /// is consumed here.
+    pub fn complete(self, peer_public: &[u8], context: &[u8]) -> Result<SessionState> {
+        let peer: [u8; 32] = peer_public
+            .try_into()
+            .map_err(|_| CryptoError::Internal("ephemeral public key must be 32 bytes".to_string()))?;
+        let ours = self.public.to_bytes();
+        let shared = self.secret.diffie_hellman(&PublicKey::from(peer));
+        if !shared.was_contributory() {
+            return Err(CryptoError::Internal("peer sent a low-order ephemeral key".to_string()));
+        }
+
+        // Order the public keys so both sides use the same salt
+        let (first, second) = if ours <= peer { (ours, peer) } else { (peer, ours) };
+        let mut salt = Vec::with_capacity(64);
+        salt.extend_from_slice(&first);
+        salt.extend_from_slice(&second);
+
+        let mut info = HANDSHAKE_INFO.to_vec();
+        info.extend_from_slice(context);
+
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(&salt), shared.as_bytes())
+            .expand(&info, &mut key)
+            .map_err(|e| CryptoError::Internal(format!("session key derivation failed: {e}")))?;
+
+        Ok(SessionState { key, epoch: 0 })
+    }
+}
+
+/// Per-conversation session key, stored separately from the identity key.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SessionState {
+    key: [u8; 32],
+    epoch: u64,
+}
+
+impl SessionState {
+    /// Key for the current epoch.
+    pub fn key(&self) -> &[u8; 32] {
+        &self.key
+    }
+
+    /// Number of times the session has been advanced.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+}
+
+impl std::fmt::Debug for SessionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SessionState")
+            .field("epoch", &self.epoch)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Start a handshake by generating a fresh ephemeral key.
+pub fn begin_session() -> PendingSession {
+    let secret = EphemeralSecret::random_from_rng(OsRng);
+    let public = PublicKey::from(&secret);
+    PendingSession { secret, public }
+}
+
+/// Ratchet the session key forward one epoch, returning the new key.
+///
+/// The previous key is overwritten and cannot be recomputed from the new one.
+pub fn advance_session(state: &mut SessionState) -> Result<[u8; 32]> {
+    let mut next = [0u8; 32];
+    Hkdf::<Sha256>::from_prk(&state.key)
+        .map_err(|e| CryptoError::Internal(format!("invalid session key: {e}")))?
+        .expand(ADVANCE_INFO, &mut next)
+        .map_err(|e| CryptoError::Internal(format!("session advance failed: {e}")))?;
+    state.key = next;
+    state.epoch += 1;
+    Ok(next)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handshake(context: &[u8]) -> (SessionState, SessionState) {
+        let alice = begin_session();
+        let bob = begin_session();
+        let (alice_pub, bob_pub) = (alice.public_key(), bob.public_key());
+        (
+            alice.complete(&bob_pub, context).unwrap(),
+            bob.complete(&alice_pub, context).unwrap(),
+        )
+    }
+
+    #[test]
+    fn both_sides_derive_same_key() {
+        let (alice, bob) = handshake(b"alice|bob");
+        assert_eq!(alice.key(), bob.key());
+    }
+
+    #[test]
+    fn each_handshake_gets_a_fresh_key() {
+        let (first, _) = handshake(b"alice|bob");
+        let (second, _) = handshake(b"alice|bob");
+        assert_ne!(first.key(), second.key());
+    }
+
+    #[test]
+    fn advancing_stays_in_sync_and_forgets_old_key() {
+        let (mut alice, mut bob) = handshake(b"alice|bob");
+        let old = *alice.key();
+
+        let a1 = advance_session(&mut alice).unwrap();
+        let b1 = advance_session(&mut bob).unwrap();
+        assert_eq!(a1, b1);
+        assert_ne!(a1, old);
+        assert_eq!(alice.epoch(), 1);
+
+        let stored = serde_json::to_string(&alice).unwrap();
+        let restored: SessionState = serde_json::from_str(&stored).unwrap();
+        assert_eq!(restored.key(), &a1);
+    }
+
+    #[test]
+    fn rejects_malformed_peer_key() {
+        let pending = begin_session();
+        assert!(pending.complete(&[1u8; 16], b"ctx").is_err());
+    }
+}
@@ -3,11 +3,14 @@
 pub mod onboarding;
 pub mod pgp_envelope;
 pub mod requests;
+pub mod settings_sync;
+use cryptochat_crypto_core::pgp::PgpKeyPair;
 use cryptochat_crypto_core::{
     decrypt_message, encrypt_message, sign_message, verify_signature, EncryptedPayload, KeyPair,
     Signature,
 };
 use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
 use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 
@@ -67,12 +70,60 @@ pub struct EncryptedEnvelope {
     pub created_ms: i64,
     pub payload: EncryptedPayload,
     pub signature: Signature,
+    /// Whether the body was deflate-compressed before encryption.
+    #[serde(default)]
+    pub compressed: bool,
+    /// Armored detached signature over [`device_statement`] by the key
+    /// `sender_device` registered with. Older senders omit it.
+    #[serde(default)]
+    pub device_signature: Option<String>,
+}
+
+/// What a device signs to prove it sent an envelope.
+pub fn device_statement(sender_fingerprint: &str, device: &DeviceId, message_id: &Uuid) -> Vec<u8> {
+    format!(
+        "cryptochat-device-v1:{sender_fingerprint}:{}:{message_id}",
+        device.0
+    )
+    .into_bytes()
+}
+
+/// Deflate `body`, returning `None` when that would not make it smaller.
+fn compress_if_smaller(body: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(body).ok()?;
+    let compressed = encoder.finish().ok()?;
+    (compressed.len() < body.len()).then_some(compressed)
+}
+
+/// Largest body a compressed envelope may inflate to.
+pub const MAX_DECOMPRESSED_BYTES: usize = 16 * 1024 * 1024;
+
+/// Inflate `body`, refusing output past [`MAX_DECOMPRESSED_BYTES`] so a
+/// small envelope can't expand into an arbitrarily large allocation.
+fn decompress(body: &[u8]) -> crate::Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    flate2::read::DeflateDecoder::new(body)
+        .take(MAX_DECOMPRESSED_BYTES as u64 + 1)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| MessagingError::Crypto(format!("decompression failed: {e}")))?;
+    if decompressed.len() > MAX_DECOMPRESSED_BYTES {
+        return Err(MessagingError::Crypto(format!(
+            "decompressed body exceeds {MAX_DECOMPRESSED_BYTES} bytes"
+        )));
+    }
+    Ok(decompressed)
 }
 
 impl EncryptedEnvelope {
     /// Encrypt and sign a plaintext message using the provided key pair.
+    ///
+    /// The body is compressed before encryption when that shrinks it (the
+    /// ciphertext itself would not compress).
     pub fn from_plaintext(message: PlaintextMessage, key_pair: &KeyPair) -> crate::Result<Self> {
-        let payload = encrypt_message(key_pair, &message.body)
+        let compressed_body = compress_if_smaller(&message.body);
+        let to_encrypt = compressed_body.as_deref().unwrap_or(&message.body);
+        let payload = encrypt_message(key_pair, to_encrypt)
             .map_err(|e| MessagingError::Crypto(format!("{e:?}")))?;
         let signature = sign_message(key_pair, &message.body)
             .map_err(|e| MessagingError::Crypto(format!("{e:?}")))?;
@@ -85,13 +136,46 @@ impl EncryptedEnvelope {
             created_ms: message.created_ms,
             payload,
             signature,
+            compressed: compressed_body.is_some(),
+            device_signature: None,
         })
     }
 
+    /// Sign the envelope as its sender device, with the key that device
+    /// registered under.
+    pub fn sign_device(&mut self, device_key: &PgpKeyPair) -> crate::Result<()> {
+        let statement = device_statement(
+            &self.sender_fingerprint,
+            &self.sender_device,
+            &self.message_id,
+        );
+        let signature = device_key
+            .sign_detached(&statement)
+            .map_err(|e| MessagingError::Crypto(format!("{e:?}")))?;
+        self.device_signature = Some(signature);
+        Ok(())
+    }
+
+    /// Whether the envelope carries a valid signature by `device_key`.
+    pub fn is_signed_by_device(&self, device_key: &PgpKeyPair) -> bool {
+        let Some(signature) = &self.device_signature else {
+            return false;
+        };
+        let statement = device_statement(
+            &self.sender_fingerprint,
+            &self.sender_device,
+            &self.message_id,
+        );
+        PgpKeyPair::verify_detached(device_key.cert(), &statement, signature).is_ok()
+    }
+
     /// Decrypts the payload and verifies the signature using the provided key pair.
     pub fn into_plaintext(self, key_pair: &KeyPair) -> crate::Result<PlaintextMessage> {
-        let ciphertext = decrypt_message(key_pair, &self.payload)
+        let mut ciphertext = decrypt_message(key_pair, &self.payload)
             .map_err(|e| MessagingError::Crypto(format!("{e:?}")))?;
+        if self.compressed {
+            ciphertext = decompress(&ciphertext)?;
+        }
 
         verify_signature(key_pair, &ciphertext, &self.signature)
             .map_err(|e| MessagingError::Crypto(format!("{e:?}")))?;
@@ -106,6 +190,75 @@ impl EncryptedEnvelope {
     }
 }
 
+/// The sender's signed word that an envelope is meant for
+/// `recipient_fingerprint`. Nodes only push an envelope to a recipient's
+/// subscribers when it comes with one; the envelope itself doesn't name
+/// its recipient.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Addressing {
+    pub recipient_fingerprint: String,
+    /// Armored public key behind the envelope's `sender_fingerprint`.
+    pub sender_public_key: String,
+    /// Armored detached signature over [`addressing_statement`].
+    pub signature: String,
+}
+
+/// What a sender signs to address an envelope.
+pub fn addressing_statement(
+    sender_fingerprint: &str,
+    recipient_fingerprint: &str,
+    message_id: &Uuid,
+) -> Vec<u8> {
+    format!("cryptochat-addressing-v1:{sender_fingerprint}:{recipient_fingerprint}:{message_id}")
+        .into_bytes()
+}
+
+impl Addressing {
+    /// Address `envelope` to `recipient_fingerprint`, signed with the
+    /// sender's identity key.
+    pub fn sign(
+        envelope: &EncryptedEnvelope,
+        recipient_fingerprint: &str,
+        sender_key: &PgpKeyPair,
+    ) -> crate::Result<Self> {
+        let statement = addressing_statement(
+            &envelope.sender_fingerprint,
+            recipient_fingerprint,
+            &envelope.message_id,
+        );
+        let signature = sender_key
+            .sign_detached(&statement)
+            .map_err(|e| MessagingError::Crypto(format!("{e:?}")))?;
+        let sender_public_key = sender_key
+            .export_public_key()
+            .map_err(|e| MessagingError::Crypto(format!("{e:?}")))?;
+        Ok(Self {
+            recipient_fingerprint: recipient_fingerprint.to_string(),
+            sender_public_key,
+            signature,
+        })
+    }
+
+    /// Whether the key behind `envelope`'s sender signed this addressing.
+    pub fn is_valid_for(&self, envelope: &EncryptedEnvelope) -> bool {
+        let Ok(key) = PgpKeyPair::parse_public_key(&self.sender_public_key) else {
+            return false;
+        };
+        if !key
+            .fingerprint()
+            .eq_ignore_ascii_case(&envelope.sender_fingerprint)
+        {
+            return false;
+        }
+        let statement = addressing_statement(
+            &envelope.sender_fingerprint,
+            &self.recipient_fingerprint,
+            &envelope.message_id,
+        );
+        PgpKeyPair::verify_detached(key.cert(), &statement, &self.signature).is_ok()
+    }
+}
+
 /// Basic delivery receipt model.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DeliveryReceipt {
@@ -133,6 +286,12 @@ impl DeliveryReceipt {
 pub enum MessagingError {
     #[error("cryptographic failure: {0}")]
     Crypto(String),
+    #[error("invalid recovery phrase: {0}")]
+    InvalidRecoveryPhrase(String),
+    #[error("key {0} is not a recipient of this envelope")]
+    NotARecipient(String),
+    #[error("malformed settings sync: {0}")]
+    MalformedSettings(String),
 }
 
 pub type Result<T> = std::result::Result<T, MessagingError>;
@@ -160,4 +319,67 @@ mod tests {
         assert_eq!(message.conversation_id, decrypted.conversation_id);
         assert_eq!(message.sender_device, decrypted.sender_device);
     }
+
+    #[test]
+    fn compressible_payload_is_compressed() {
+        let keypair = KeyPair::from_seed(b"test-compression").unwrap();
+        let body = "the same emote payload again and again ".repeat(50).into_bytes();
+        let message = PlaintextMessage::new(ConversationId::new(), DeviceId::new(), body.clone());
+
+        let envelope = EncryptedEnvelope::from_plaintext(message, &keypair).unwrap();
+        assert!(envelope.compressed);
+        let (_, ciphertext) = envelope.payload.decode().unwrap();
+        assert!(ciphertext.len() < body.len());
+
+        let decrypted = envelope.into_plaintext(&keypair).unwrap();
+        assert_eq!(decrypted.body, body);
+    }
+
+    #[test]
+    fn decompression_stops_at_the_limit() {
+        let at_limit = compress_if_smaller(&vec![0u8; MAX_DECOMPRESSED_BYTES]).unwrap();
+        assert_eq!(decompress(&at_limit).unwrap().len(), MAX_DECOMPRESSED_BYTES);
+
+        let bomb = compress_if_smaller(&vec![0u8; MAX_DECOMPRESSED_BYTES + 1]).unwrap();
+        assert!(matches!(decompress(&bomb), Err(MessagingError::Crypto(_))));
+    }
+
+    #[test]
+    fn incompressible_payload_is_sent_as_is() {
+        let keypair = KeyPair::from_seed(b"test-compression").unwrap();
+        // Pseudo-random bytes from a fixed seed don't deflate
+        let body = KeyPair::from_seed(b"noise").unwrap().private_key().to_vec();
+        let message = PlaintextMessage::new(ConversationId::new(), DeviceId::new(), body.clone());
+
+        let envelope = EncryptedEnvelope::from_plaintext(message, &keypair).unwrap();
+        assert!(!envelope.compressed);
+
+        let decrypted = envelope.into_plaintext(&keypair).unwrap();
+        assert_eq!(decrypted.body, body);
+    }
+
+    #[test]
+    fn only_the_senders_key_addresses_an_envelope() {
+        let keypair = KeyPair::from_seed(b"test-addressing").unwrap();
+        let alice = PgpKeyPair::generate("alice").unwrap();
+        let mallory = PgpKeyPair::generate("mallory").unwrap();
+        let message = PlaintextMessage::new(ConversationId::new(), DeviceId::new(), b"hi".to_vec());
+        let mut envelope = EncryptedEnvelope::from_plaintext(message, &keypair).unwrap();
+        envelope.sender_fingerprint = alice.fingerprint();
+
+        let addressing = Addressing::sign(&envelope, "BOB", &alice).unwrap();
+        assert!(addressing.is_valid_for(&envelope));
+
+        // Redirected to someone else, or reused for another envelope
+        let mut redirected = addressing.clone();
+        redirected.recipient_fingerprint = "CAROL".to_string();
+        assert!(!redirected.is_valid_for(&envelope));
+        let mut other = envelope.clone();
+        other.message_id = Uuid::new_v4();
+        assert!(!addressing.is_valid_for(&other));
+
+        // Signed by a key that isn't the sender's
+        let forged = Addressing::sign(&envelope, "BOB", &mallory).unwrap();
+        assert!(!forged.is_valid_for(&envelope));
+    }
 }
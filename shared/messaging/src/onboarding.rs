@@ -2,6 +2,7 @@
 
 use crate::{DeviceId, MessagingError, Result};
 use cryptochat_crypto_core::pgp::PgpKeyPair;
+use cryptochat_crypto_core::KeyPair;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
@@ -117,7 +118,10 @@ impl KeyBundle {
     /// Verify that the fingerprint matches the public key.
     pub fn verify_fingerprint(&self) -> Result<bool> {
         let keypair = self.import_public_key()?;
-        Ok(keypair.fingerprint() == self.fingerprint)
+        Ok(cryptochat_crypto_core::constant_time_eq(
+            keypair.fingerprint().as_bytes(),
+            self.fingerprint.as_bytes(),
+        ))
     }
 }
 
@@ -207,6 +211,29 @@ impl SasVerification {
     }
 }
 
+/// Encode a key seed as a BIP39 mnemonic the user can write down.
+///
+/// Seeds of 16, 20, 24, 28 or 32 bytes map to 12, 15, 18, 21 or 24 words.
+pub fn recovery_phrase_from_seed(seed: &[u8]) -> Result<String> {
+    let mnemonic = bip39::Mnemonic::from_entropy(seed)
+        .map_err(|e| MessagingError::InvalidRecoveryPhrase(format!("unsupported seed: {}", e)))?;
+    Ok(mnemonic.to_string())
+}
+
+/// Decode a recovery phrase back into the key seed, validating its checksum.
+pub fn seed_from_recovery_phrase(phrase: &str) -> Result<Vec<u8>> {
+    let normalized = phrase.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+    let mnemonic = bip39::Mnemonic::parse_normalized(&normalized)
+        .map_err(|e| MessagingError::InvalidRecoveryPhrase(e.to_string()))?;
+    Ok(mnemonic.to_entropy())
+}
+
+/// Rebuild the identity key pair from a recovery phrase.
+pub fn keypair_from_recovery_phrase(phrase: &str) -> Result<KeyPair> {
+    let seed = seed_from_recovery_phrase(phrase)?;
+    KeyPair::from_seed(&seed).map_err(|e| MessagingError::Crypto(format!("{e:?}")))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -281,4 +308,43 @@ mod tests {
         assert!(sas.verify(&sas.sas_words));
         assert!(!sas.verify(&vec!["wrong".to_string()]));
     }
+
+    #[test]
+    fn test_recovery_phrase_roundtrip() {
+        let seed: Vec<u8> = (0u8..32).collect();
+        let phrase = recovery_phrase_from_seed(&seed).unwrap();
+        assert_eq!(phrase.split_whitespace().count(), 24);
+
+        let restored = seed_from_recovery_phrase(&phrase).unwrap();
+        assert_eq!(restored, seed);
+
+        let original = KeyPair::from_seed(&seed).unwrap();
+        let recovered = keypair_from_recovery_phrase(&format!("  {}  ", phrase.to_uppercase())).unwrap();
+        assert_eq!(recovered.fingerprint(), original.fingerprint());
+    }
+
+    #[test]
+    fn test_twelve_word_phrase_from_short_seed() {
+        let seed = [7u8; 16];
+        let phrase = recovery_phrase_from_seed(&seed).unwrap();
+        assert_eq!(phrase.split_whitespace().count(), 12);
+        assert_eq!(seed_from_recovery_phrase(&phrase).unwrap(), seed.to_vec());
+    }
+
+    #[test]
+    fn test_recovery_phrase_bad_checksum_rejected() {
+        let phrase = recovery_phrase_from_seed(&[0u8; 16]).unwrap();
+        // All-zero entropy ends in "about"; swapping the last word breaks the checksum
+        let mut words: Vec<&str> = phrase.split_whitespace().collect();
+        assert_eq!(words[11], "about");
+        words[11] = "abandon";
+
+        let err = seed_from_recovery_phrase(&words.join(" ")).unwrap_err();
+        assert!(matches!(err, MessagingError::InvalidRecoveryPhrase(_)));
+    }
+
+    #[test]
+    fn test_recovery_phrase_rejects_unsupported_seed() {
+        assert!(recovery_phrase_from_seed(&[1u8; 10]).is_err());
+    }
 }
@@ -2,10 +2,25 @@
 
 use crate::{ConversationId, DeviceId, MessagingError, PlaintextMessage, Result};
 use cryptochat_crypto_core::pgp::PgpKeyPair;
+use cryptochat_crypto_core::ratchet::RatchetState;
+use cryptochat_crypto_core::session::{self, PendingSession, SessionState};
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
 use sequoia_openpgp::Cert;
 use uuid::Uuid;
 
+/// Length in bytes of the random session key used by [`MultiRecipientEnvelope`].
+const SESSION_KEY_LEN: usize = 32;
+
+fn encode_b64(bytes: &[u8]) -> String {
+    base64::Engine::encode(&base64::engine::general_purpose::STANDARD, bytes)
+}
+
+fn decode_b64(encoded: &str) -> Result<Vec<u8>> {
+    base64::Engine::decode(&base64::engine::general_purpose::STANDARD, encoded)
+        .map_err(|e| MessagingError::Crypto(format!("base64 decode failed: {}", e)))
+}
+
 /// Encrypted envelope using OpenPGP for end-to-end encryption.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PgpEnvelope {
@@ -70,6 +85,236 @@ impl PgpEnvelope {
     }
 }
 
+/// Ephemeral handshake key, signed by the sender's identity key so the peer
+/// knows who it is establishing a session with.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionOffer {
+    pub conversation_id: ConversationId,
+    pub sender_fingerprint: String,
+    /// X25519 ephemeral public key (base64 encoded).
+    pub ephemeral_public: String,
+    /// Detached signature over conversation id and ephemeral key (base64 encoded).
+    pub signature: String,
+}
+
+impl SessionOffer {
+    fn signed_bytes(conversation_id: &ConversationId, ephemeral_public: &[u8]) -> Vec<u8> {
+        let mut bytes = conversation_id.0.as_bytes().to_vec();
+        bytes.extend_from_slice(ephemeral_public);
+        bytes
+    }
+
+    /// Begin a forward-secret session for a conversation.
+    ///
+    /// Send the returned offer to the peer and keep the pending half until
+    /// their offer arrives, then call [`SessionOffer::accept`].
+    pub fn begin(
+        conversation_id: ConversationId,
+        identity: &PgpKeyPair,
+    ) -> Result<(PendingSession, Self)> {
+        let pending = session::begin_session();
+        let public = pending.public_key();
+        let signature = identity
+            .sign(&Self::signed_bytes(&conversation_id, &public))
+            .map_err(|e| MessagingError::Crypto(format!("sign failed: {}", e)))?;
+
+        let offer = Self {
+            conversation_id,
+            sender_fingerprint: identity.fingerprint(),
+            ephemeral_public: encode_b64(&public),
+            signature: encode_b64(&signature),
+        };
+        Ok((pending, offer))
+    }
+
+    /// Verify the peer's offer against their cert and finish the handshake.
+    ///
+    /// `local_fingerprint` is our own identity fingerprint; both fingerprints
+    /// are bound into the derived key.
+    pub fn accept(
+        &self,
+        pending: PendingSession,
+        local_fingerprint: &str,
+        sender_cert: &Cert,
+    ) -> Result<SessionState> {
+        if !sender_cert.fingerprint().to_hex().eq_ignore_ascii_case(&self.sender_fingerprint) {
+            return Err(MessagingError::Crypto("offer fingerprint does not match sender cert".to_string()));
+        }
+        let public = decode_b64(&self.ephemeral_public)?;
+        PgpKeyPair::verify(
+            sender_cert,
+            &Self::signed_bytes(&self.conversation_id, &public),
+            &decode_b64(&self.signature)?,
+        )
+        .map_err(|e| MessagingError::Crypto(format!("offer signature invalid: {}", e)))?;
+
+        let mut fingerprints = [local_fingerprint.to_uppercase(), self.sender_fingerprint.to_uppercase()];
+        fingerprints.sort();
+        let context = format!("{}|{}|{}", self.conversation_id.0, fingerprints[0], fingerprints[1]);
+
+        pending
+            .complete(&public, context.as_bytes())
+            .map_err(|e| MessagingError::Crypto(format!("handshake failed: {}", e)))
+    }
+}
+
+/// Ratchet a conversation's session key forward, returning the new key.
+pub fn advance_session(state: &mut SessionState) -> Result<[u8; 32]> {
+    session::advance_session(state).map_err(|e| MessagingError::Crypto(format!("{e:?}")))
+}
+
+/// Message encrypted with a per-message key from the conversation's ratchet.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RatchetEnvelope {
+    pub conversation_id: ConversationId,
+    pub sender_fingerprint: String,
+    /// Position in the sender's chain, used to derive the matching key.
+    pub index: u32,
+    /// Body encrypted under the message key (base64 encoded).
+    pub encrypted_payload: String,
+}
+
+impl RatchetEnvelope {
+    /// Encrypt `body` with the next sending key, advancing the ratchet.
+    pub fn seal(
+        conversation_id: ConversationId,
+        sender_fingerprint: String,
+        ratchet: &mut RatchetState,
+        body: &[u8],
+    ) -> Result<Self> {
+        let (index, encrypted) = ratchet
+            .encrypt(body)
+            .map_err(|e| MessagingError::Crypto(format!("encrypt failed: {}", e)))?;
+        Ok(Self {
+            conversation_id,
+            sender_fingerprint,
+            index,
+            encrypted_payload: encode_b64(&encrypted),
+        })
+    }
+
+    /// Decrypt with the receiving key for this envelope's index. The ratchet
+    /// is left untouched if decryption fails.
+    pub fn open(&self, ratchet: &mut RatchetState) -> Result<Vec<u8>> {
+        ratchet
+            .decrypt(self.index, &decode_b64(&self.encrypted_payload)?)
+            .map_err(|e| MessagingError::Crypto(format!("decrypt failed: {}", e)))
+    }
+}
+
+/// Session key wrapped (PGP-encrypted) for one recipient.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WrappedSessionKey {
+    pub recipient_fingerprint: String,
+    /// PGP-encrypted session key (base64 encoded).
+    pub wrapped_key: String,
+}
+
+/// Envelope encrypted once for several recipients.
+///
+/// The body is encrypted under a random session key, which is then wrapped
+/// for each recipient's public key. Useful for small groups that have no
+/// shared symmetric key.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MultiRecipientEnvelope {
+    pub message_id: Uuid,
+    pub conversation_id: ConversationId,
+    pub sender_fingerprint: String,
+    pub sender_device: DeviceId,
+    pub created_ms: i64,
+    /// Body encrypted under the session key (base64 encoded).
+    pub encrypted_payload: String,
+    /// Detached signature by the sender over the plaintext body (base64 encoded).
+    pub signature: String,
+    pub recipients: Vec<WrappedSessionKey>,
+}
+
+impl MultiRecipientEnvelope {
+    /// Sign and encrypt a plaintext message for every recipient cert.
+    pub fn from_plaintext(
+        message: PlaintextMessage,
+        sender_keypair: &PgpKeyPair,
+        recipient_certs: &[Cert],
+    ) -> Result<Self> {
+        if recipient_certs.is_empty() {
+            return Err(MessagingError::Crypto("no recipients given".to_string()));
+        }
+
+        let mut session_key = [0u8; SESSION_KEY_LEN];
+        rand::thread_rng().fill_bytes(&mut session_key);
+
+        let recipients = recipient_certs
+            .iter()
+            .map(|cert| {
+                let wrapped = PgpKeyPair::encrypt(cert, &session_key).map_err(|e| {
+                    MessagingError::Crypto(format!("wrapping session key failed: {}", e))
+                })?;
+                Ok(WrappedSessionKey {
+                    recipient_fingerprint: cert.fingerprint().to_hex(),
+                    wrapped_key: encode_b64(&wrapped),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let encrypted_payload = PgpKeyPair::encrypt_with_session_key(&session_key, &message.body)
+            .map_err(|e| MessagingError::Crypto(format!("encrypt failed: {}", e)))?;
+        let signature = sender_keypair
+            .sign(&message.body)
+            .map_err(|e| MessagingError::Crypto(format!("sign failed: {}", e)))?;
+
+        Ok(Self {
+            message_id: message.message_id,
+            conversation_id: message.conversation_id,
+            sender_fingerprint: sender_keypair.fingerprint(),
+            sender_device: message.sender_device,
+            created_ms: message.created_ms,
+            encrypted_payload: encode_b64(&encrypted_payload),
+            signature: encode_b64(&signature),
+            recipients,
+        })
+    }
+
+    /// Fingerprints of everyone this envelope was encrypted for.
+    pub fn recipient_fingerprints(&self) -> impl Iterator<Item = &str> {
+        self.recipients.iter().map(|r| r.recipient_fingerprint.as_str())
+    }
+
+    /// Unwrap our session key, decrypt the body and verify the sender's signature.
+    ///
+    /// Returns [`MessagingError::NotARecipient`] if the envelope holds no
+    /// wrapped key for `recipient_keypair`.
+    pub fn into_plaintext(
+        self,
+        recipient_keypair: &PgpKeyPair,
+        sender_cert: &Cert,
+    ) -> Result<PlaintextMessage> {
+        let my_fingerprint = recipient_keypair.fingerprint();
+        let wrapped = self
+            .recipients
+            .iter()
+            .find(|r| r.recipient_fingerprint.eq_ignore_ascii_case(&my_fingerprint))
+            .ok_or_else(|| MessagingError::NotARecipient(my_fingerprint.clone()))?;
+
+        let session_key = recipient_keypair
+            .decrypt(&decode_b64(&wrapped.wrapped_key)?)
+            .map_err(|e| MessagingError::Crypto(format!("unwrapping session key failed: {}", e)))?;
+
+        let body = PgpKeyPair::decrypt_with_session_key(&session_key, &decode_b64(&self.encrypted_payload)?)
+            .map_err(|e| MessagingError::Crypto(format!("decrypt failed: {}", e)))?;
+
+        PgpKeyPair::verify(sender_cert, &body, &decode_b64(&self.signature)?)
+            .map_err(|e| MessagingError::Crypto(format!("signature verification failed: {}", e)))?;
+
+        Ok(PlaintextMessage {
+            message_id: self.message_id,
+            conversation_id: self.conversation_id,
+            sender_device: self.sender_device,
+            created_ms: self.created_ms,
+            body,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -124,4 +369,123 @@ mod tests {
         let result = envelope.into_plaintext(&bob, eve.cert());
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_multi_recipient_envelope_decrypts_for_each_recipient() {
+        let alice = PgpKeyPair::generate("alice@example.com").unwrap();
+        let bob = PgpKeyPair::generate("bob@example.com").unwrap();
+        let carol = PgpKeyPair::generate("carol@example.com").unwrap();
+        let dave = PgpKeyPair::generate("dave@example.com").unwrap();
+
+        let message = PlaintextMessage::new(
+            ConversationId::new(),
+            DeviceId::new(),
+            b"Hello, small group!".to_vec(),
+        );
+
+        let envelope = MultiRecipientEnvelope::from_plaintext(
+            message.clone(),
+            &alice,
+            &[bob.cert().clone(), carol.cert().clone(), dave.cert().clone()],
+        )
+        .unwrap();
+        assert_eq!(envelope.recipients.len(), 3);
+
+        for recipient in [&bob, &carol, &dave] {
+            let decrypted = envelope
+                .clone()
+                .into_plaintext(recipient, alice.cert())
+                .unwrap();
+            assert_eq!(decrypted.body, message.body);
+            assert_eq!(decrypted.message_id, message.message_id);
+        }
+    }
+
+    #[test]
+    fn test_multi_recipient_envelope_rejects_non_recipient() {
+        let alice = PgpKeyPair::generate("alice@example.com").unwrap();
+        let bob = PgpKeyPair::generate("bob@example.com").unwrap();
+        let eve = PgpKeyPair::generate("eve@example.com").unwrap();
+
+        let message = PlaintextMessage::new(ConversationId::new(), DeviceId::new(), b"not for eve".to_vec());
+        let envelope =
+            MultiRecipientEnvelope::from_plaintext(message, &alice, &[bob.cert().clone()]).unwrap();
+
+        let err = envelope.into_plaintext(&eve, alice.cert()).unwrap_err();
+        assert!(matches!(err, MessagingError::NotARecipient(fp) if fp == eve.fingerprint()));
+    }
+
+    #[test]
+    fn test_session_handshake_agrees_and_advances() {
+        let alice = PgpKeyPair::generate("alice@example.com").unwrap();
+        let bob = PgpKeyPair::generate("bob@example.com").unwrap();
+        let conversation = ConversationId::new();
+
+        let (alice_pending, alice_offer) = SessionOffer::begin(conversation.clone(), &alice).unwrap();
+        let (bob_pending, bob_offer) = SessionOffer::begin(conversation, &bob).unwrap();
+
+        let mut alice_session = bob_offer.accept(alice_pending, &alice.fingerprint(), bob.cert()).unwrap();
+        let mut bob_session = alice_offer.accept(bob_pending, &bob.fingerprint(), alice.cert()).unwrap();
+        assert_eq!(alice_session.key(), bob_session.key());
+
+        assert_eq!(
+            advance_session(&mut alice_session).unwrap(),
+            advance_session(&mut bob_session).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_leaked_identity_key_does_not_reveal_session_key() {
+        let alice = PgpKeyPair::generate("alice@example.com").unwrap();
+        let bob = PgpKeyPair::generate("bob@example.com").unwrap();
+        let conversation = ConversationId::new();
+
+        let (alice_pending, alice_offer) = SessionOffer::begin(conversation.clone(), &alice).unwrap();
+        let (bob_pending, bob_offer) = SessionOffer::begin(conversation.clone(), &bob).unwrap();
+        let session = bob_offer.accept(alice_pending, &alice.fingerprint(), bob.cert()).unwrap();
+        let _ = alice_offer.accept(bob_pending, &bob.fingerprint(), alice.cert()).unwrap();
+
+        // An attacker later steals Alice's identity key and has the recorded
+        // offers. The ephemeral secrets are gone, so the best they can do is
+        // run a new handshake against Bob's recorded offer.
+        let stolen = PgpKeyPair::from_secret_key(&alice.export_secret_key().unwrap()).unwrap();
+        let (attacker_pending, _) = SessionOffer::begin(conversation, &stolen).unwrap();
+        let attacker_session = bob_offer.accept(attacker_pending, &stolen.fingerprint(), bob.cert()).unwrap();
+
+        assert_ne!(attacker_session.key(), session.key());
+    }
+
+    #[test]
+    fn test_session_offer_rejects_wrong_signer() {
+        let alice = PgpKeyPair::generate("alice@example.com").unwrap();
+        let bob = PgpKeyPair::generate("bob@example.com").unwrap();
+        let eve = PgpKeyPair::generate("eve@example.com").unwrap();
+        let conversation = ConversationId::new();
+
+        let (bob_pending, _) = SessionOffer::begin(conversation.clone(), &bob).unwrap();
+        let (_, mut eve_offer) = SessionOffer::begin(conversation, &eve).unwrap();
+        eve_offer.sender_fingerprint = alice.fingerprint();
+
+        assert!(eve_offer.accept(bob_pending, &bob.fingerprint(), alice.cert()).is_err());
+    }
+
+    #[test]
+    fn test_ratchet_envelope_out_of_order() {
+        let alice = session::begin_session();
+        let bob = session::begin_session();
+        let (alice_pub, bob_pub) = (alice.public_key(), bob.public_key());
+        let mut alice_ratchet =
+            RatchetState::from_session(&alice.complete(&bob_pub, b"ctx").unwrap(), true).unwrap();
+        let mut bob_ratchet =
+            RatchetState::from_session(&bob.complete(&alice_pub, b"ctx").unwrap(), false).unwrap();
+
+        let conversation = ConversationId::new();
+        let first = RatchetEnvelope::seal(conversation.clone(), "A".into(), &mut alice_ratchet, b"one").unwrap();
+        let second = RatchetEnvelope::seal(conversation, "A".into(), &mut alice_ratchet, b"two").unwrap();
+        assert_ne!(first.encrypted_payload, second.encrypted_payload);
+
+        assert_eq!(second.open(&mut bob_ratchet).unwrap(), b"two");
+        assert_eq!(first.open(&mut bob_ratchet).unwrap(), b"one");
+        assert!(first.open(&mut bob_ratchet).is_err());
+    }
 }
@@ -9,6 +9,9 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::{ConversationId, DeviceId};
 
+/// How long a pending request stays valid before it is purged (7 days)
+pub const DEFAULT_REQUEST_TTL_MS: i64 = 7 * 24 * 60 * 60 * 1000;
+
 /// Status of a message request
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum RequestStatus {
@@ -20,6 +23,18 @@ pub enum RequestStatus {
     Rejected,
 }
 
+/// What a request asks for, so the recipient can present the right choice
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RequestKind {
+    /// Start a conversation
+    #[default]
+    Chat,
+    /// Connect so the requester can invite the recipient to a group
+    GroupInvite,
+    /// A known contact re-linking under a new key (rotation or new device)
+    KeyReexchange,
+}
+
 /// A message request from an unknown contact
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MessageRequest {
@@ -49,6 +64,15 @@ pub struct MessageRequest {
 
     /// Preview of the first message (encrypted)
     pub first_message_preview: Option<String>,
+
+    /// When a still-pending request expires (missing on requests saved
+    /// before expiry existed; those use `created_ms` + the default TTL)
+    #[serde(default)]
+    pub expires_at_ms: Option<i64>,
+
+    /// What the requester wants (requests saved before kinds existed are chats)
+    #[serde(default)]
+    pub kind: RequestKind,
 }
 
 impl MessageRequest {
@@ -75,9 +99,17 @@ impl MessageRequest {
             status: RequestStatus::Pending,
             status_updated_ms: now,
             first_message_preview,
+            expires_at_ms: Some(now + DEFAULT_REQUEST_TTL_MS),
+            kind: RequestKind::Chat,
         }
     }
 
+    /// Set what the request asks for
+    pub fn with_kind(mut self, kind: RequestKind) -> Self {
+        self.kind = kind;
+        self
+    }
+
     /// Accept the message request
     pub fn accept(&mut self) {
         self.status = RequestStatus::Accepted;
@@ -100,6 +132,17 @@ impl MessageRequest {
     pub fn is_pending(&self) -> bool {
         self.status == RequestStatus::Pending
     }
+
+    /// When this request expires
+    pub fn expiry_ms(&self) -> i64 {
+        self.expires_at_ms
+            .unwrap_or(self.created_ms + DEFAULT_REQUEST_TTL_MS)
+    }
+
+    /// Check if this request went unanswered past its expiry
+    pub fn is_expired_at(&self, now_ms: i64) -> bool {
+        self.is_pending() && now_ms >= self.expiry_ms()
+    }
 }
 
 /// Contact entry created after accepting a message request
@@ -111,9 +154,13 @@ pub struct Contact {
     /// Public key (ASCII-armored)
     pub public_key: String,
 
-    /// Optional display name
+    /// Name the contact reports for themselves
     pub display_name: Option<String>,
 
+    /// Name we chose for the contact locally; shown instead of `display_name`
+    #[serde(default)]
+    pub alias: Option<String>,
+
     /// When this contact was added
     pub added_ms: i64,
 
@@ -128,6 +175,7 @@ impl Contact {
             fingerprint: request.sender_fingerprint.clone(),
             public_key: request.sender_public_key.clone(),
             display_name: None,
+            alias: None,
             added_ms: SystemTime::now()
                 .duration_since(UNIX_EPOCH)
                 .unwrap_or_default()
@@ -135,6 +183,11 @@ impl Contact {
             last_conversation_id: Some(request.conversation_id.clone()),
         }
     }
+
+    /// Name to show: our alias if set, otherwise the contact's own name
+    pub fn shown_name(&self) -> Option<&str> {
+        self.alias.as_deref().or(self.display_name.as_deref())
+    }
 }
 
 #[cfg(test)]
@@ -161,6 +214,55 @@ mod tests {
         assert!(!request.is_pending());
     }
 
+    #[test]
+    fn test_request_expiry() {
+        let mut request = MessageRequest::new(
+            ConversationId::new(),
+            "ABC123".to_string(),
+            DeviceId::new(),
+            "-----BEGIN PGP PUBLIC KEY BLOCK-----".to_string(),
+            None,
+        );
+        let expiry = request.expiry_ms();
+        assert_eq!(expiry, request.created_ms + DEFAULT_REQUEST_TTL_MS);
+        assert!(!request.is_expired_at(expiry - 1));
+        assert!(request.is_expired_at(expiry));
+
+        // Answered requests never expire
+        request.accept();
+        assert!(!request.is_expired_at(expiry + 1));
+    }
+
+    #[test]
+    fn test_request_kind_serialization() {
+        for kind in [RequestKind::Chat, RequestKind::GroupInvite, RequestKind::KeyReexchange] {
+            let request = MessageRequest::new(
+                ConversationId::new(),
+                "ABC123".to_string(),
+                DeviceId::new(),
+                "-----BEGIN PGP PUBLIC KEY BLOCK-----".to_string(),
+                None,
+            )
+            .with_kind(kind);
+            let json = serde_json::to_string(&request).unwrap();
+            let restored: MessageRequest = serde_json::from_str(&json).unwrap();
+            assert_eq!(restored.kind, kind);
+        }
+
+        // Requests stored before kinds existed load as chat requests
+        let mut legacy = serde_json::to_value(MessageRequest::new(
+            ConversationId::new(),
+            "ABC123".to_string(),
+            DeviceId::new(),
+            String::new(),
+            None,
+        ))
+        .unwrap();
+        legacy.as_object_mut().unwrap().remove("kind");
+        let restored: MessageRequest = serde_json::from_value(legacy).unwrap();
+        assert_eq!(restored.kind, RequestKind::Chat);
+    }
+
     #[test]
     fn test_contact_from_request() {
         let conv_id = ConversationId::new();
@@ -177,5 +279,24 @@ mod tests {
         let contact = Contact::from_request(&request);
         assert_eq!(contact.fingerprint, "ABC123");
         assert_eq!(contact.last_conversation_id, Some(conv_id));
+        assert_eq!(contact.alias, None);
+    }
+
+    #[test]
+    fn test_contact_alias_shown_over_display_name() {
+        let request = MessageRequest::new(
+            ConversationId::new(),
+            "ABC123".to_string(),
+            DeviceId::new(),
+            "-----BEGIN PGP PUBLIC KEY BLOCK-----".to_string(),
+            None,
+        );
+        let mut contact = Contact::from_request(&request);
+        contact.display_name = Some("alice99".to_string());
+        assert_eq!(contact.shown_name(), Some("alice99"));
+
+        contact.alias = Some("Alice (work)".to_string());
+        contact.display_name = Some("alice_renamed".to_string());
+        assert_eq!(contact.shown_name(), Some("Alice (work)"));
     }
 }
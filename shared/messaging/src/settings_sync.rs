@@ -0,0 +1,185 @@
+//! Per-conversation settings synced between a user's devices.
+//!
+//! Mute, pin and archive each carry their own timestamp and merge
+//! last-writer-wins, so muting a chat on one device and pinning it on another
+//! both survive. A [`SettingsSync`] snapshot is sealed to the user's own key
+//! and replicated through the node like any other envelope.
+
+use crate::{ConversationId, DeviceId, EncryptedEnvelope, MessagingError, PlaintextMessage, Result};
+use cryptochat_crypto_core::KeyPair;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// A value with the time (ms since epoch) it was last set.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "StampedRepr<T>")]
+pub struct Stamped<T> {
+    pub value: T,
+    pub updated_ms: i64,
+}
+
+/// Also accepts a bare value, as stored before settings were timestamped.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StampedRepr<T> {
+    Stamped { value: T, updated_ms: i64 },
+    Bare(T),
+}
+
+impl<T> From<StampedRepr<T>> for Stamped<T> {
+    fn from(repr: StampedRepr<T>) -> Self {
+        match repr {
+            StampedRepr::Stamped { value, updated_ms } => Self { value, updated_ms },
+            StampedRepr::Bare(value) => Self { value, updated_ms: 0 },
+        }
+    }
+}
+
+impl<T: Clone + Ord> Stamped<T> {
+    pub fn set(&mut self, value: T, now_ms: i64) {
+        self.value = value;
+        self.updated_ms = now_ms;
+    }
+
+    /// Take `other` if it was written later. Equal timestamps fall back to
+    /// comparing values so every device settles on the same result.
+    /// Returns whether the value changed.
+    pub fn merge(&mut self, other: &Self) -> bool {
+        let newer = (other.updated_ms, &other.value) > (self.updated_ms, &self.value);
+        if newer {
+            let changed = other.value != self.value;
+            *self = other.clone();
+            return changed;
+        }
+        false
+    }
+}
+
+/// Synced settings for one conversation.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConversationSettings {
+    /// No notifications except mentions.
+    #[serde(default)]
+    pub muted: Stamped<bool>,
+    /// Listed above other conversations.
+    #[serde(default)]
+    pub pinned: Stamped<bool>,
+    /// Hidden from the main conversation list.
+    #[serde(default)]
+    pub archived: Stamped<bool>,
+}
+
+impl ConversationSettings {
+    /// Merge every setting; returns whether anything changed.
+    pub fn merge(&mut self, other: &Self) -> bool {
+        let muted = self.muted.merge(&other.muted);
+        let pinned = self.pinned.merge(&other.pinned);
+        let archived = self.archived.merge(&other.archived);
+        muted || pinned || archived
+    }
+}
+
+/// Snapshot of one device's conversation settings, keyed by conversation id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SettingsSync {
+    pub device_id: DeviceId,
+    pub conversations: BTreeMap<String, ConversationSettings>,
+}
+
+impl SettingsSync {
+    /// Conversation id every settings envelope is filed under.
+    pub fn conversation_id() -> ConversationId {
+        ConversationId(Uuid::new_v5(&Uuid::NAMESPACE_OID, b"cryptochat-settings-sync"))
+    }
+
+    /// Fold this snapshot into `local`, returning the ids whose settings changed.
+    pub fn merge_into(&self, local: &mut BTreeMap<String, ConversationSettings>) -> Vec<String> {
+        let mut changed = Vec::new();
+        for (id, remote) in &self.conversations {
+            if local.entry(id.clone()).or_default().merge(remote) {
+                changed.push(id.clone());
+            }
+        }
+        changed
+    }
+
+    /// Encrypt and sign the snapshot with the user's own key, for replication
+    /// to their other devices.
+    pub fn seal(&self, key_pair: &KeyPair) -> Result<EncryptedEnvelope> {
+        let body = serde_json::to_vec(self)
+            .map_err(|e| MessagingError::MalformedSettings(e.to_string()))?;
+        let message = PlaintextMessage::new(Self::conversation_id(), self.device_id.clone(), body);
+        EncryptedEnvelope::from_plaintext(message, key_pair)
+    }
+
+    /// Decrypt a snapshot sealed by another of the user's devices.
+    pub fn open(envelope: EncryptedEnvelope, key_pair: &KeyPair) -> Result<Self> {
+        if envelope.conversation_id != Self::conversation_id() {
+            return Err(MessagingError::MalformedSettings("not a settings envelope".into()));
+        }
+        let message = envelope.into_plaintext(key_pair)?;
+        serde_json::from_slice(&message.body).map_err(|e| MessagingError::MalformedSettings(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamped(value: bool, updated_ms: i64) -> Stamped<bool> {
+        Stamped { value, updated_ms }
+    }
+
+    #[test]
+    fn later_write_wins_per_setting() {
+        // Device A muted at t=10; device B pinned at t=20 and unmuted earlier at t=5
+        let mut a = ConversationSettings { muted: stamped(true, 10), ..Default::default() };
+        let b = ConversationSettings {
+            muted: stamped(false, 5),
+            pinned: stamped(true, 20),
+            ..Default::default()
+        };
+
+        assert!(a.merge(&b));
+        assert_eq!(a.muted, stamped(true, 10));
+        assert_eq!(a.pinned, stamped(true, 20));
+        assert_eq!(a.archived, Stamped::default());
+        assert!(!a.merge(&b));
+    }
+
+    #[test]
+    fn ties_resolve_the_same_way_on_both_devices() {
+        let mut a = stamped(false, 7);
+        let mut b = stamped(true, 7);
+        let (a_before, b_before) = (a.clone(), b.clone());
+        a.merge(&b_before);
+        b.merge(&a_before);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn legacy_bare_values_load_with_zero_timestamp() {
+        let settings: ConversationSettings = serde_json::from_str(r#"{"muted":true}"#).unwrap();
+        assert_eq!(settings.muted, stamped(true, 0));
+        assert_eq!(settings.pinned, Stamped::default());
+    }
+
+    #[test]
+    fn sealed_snapshot_merges_into_other_device() {
+        let key = KeyPair::from_seed(b"me").unwrap();
+        let snapshot = SettingsSync {
+            device_id: DeviceId::new(),
+            conversations: BTreeMap::from([(
+                "alice".to_string(),
+                ConversationSettings { archived: stamped(true, 30), ..Default::default() },
+            )]),
+        };
+
+        let received = SettingsSync::open(snapshot.seal(&key).unwrap(), &key).unwrap();
+        let mut local = BTreeMap::from([("bob".to_string(), ConversationSettings::default())]);
+        assert_eq!(received.merge_into(&mut local), vec!["alice".to_string()]);
+        assert!(local["alice"].archived.value);
+        assert!(received.merge_into(&mut local).is_empty());
+    }
+}